@@ -0,0 +1,63 @@
+/*
+ * C API of libswww_client, for controlling swww-daemon from other programs.
+ *
+ * Link with -lswww_client. Every function talks to the daemon through its
+ * socket, so it must be running (see swww-init(1)).
+ *
+ * This mirrors the `extern "C"` functions in client/src/lib.rs and the codes
+ * of swww_protocol::error::Error, which client/tests/header.rs checks.
+ */
+#ifndef SWWW_CLIENT_H
+#define SWWW_CLIENT_H
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+/*
+ * Returns one line per output, like `swww query` prints, or NULL on failure.
+ * The result must be freed with swww_free_string.
+ */
+char *swww_query(void);
+
+/*
+ * Displays the image at `path` on `outputs`, a comma separated list of output
+ * or group names. NULL or an empty string means every output. The image is
+ * resized to fill the outputs, with the default transition, and animated images
+ * only show their first frame.
+ *
+ * Returns 0 on success, and -1 on failure.
+ */
+int swww_set_image(const char *path, const char *outputs);
+
+/*
+ * Returns why the last call that failed in this thread did, or NULL if none
+ * did. The result is owned by the library, and is valid until the next failure
+ * in this thread.
+ */
+const char *swww_last_error(void);
+
+/*
+ * Returns the code of the last error in this thread, or 0 if there was none.
+ * These are the same codes swww(1) exits with.
+ */
+int swww_last_error_code(void);
+
+#define SWWW_ERROR_OTHER 1
+#define SWWW_ERROR_IPC 3             /* talking through the socket failed */
+#define SWWW_ERROR_DAEMON 4          /* swww-daemon isn't running */
+#define SWWW_ERROR_INVALID_REQUEST 5 /* bad arguments */
+#define SWWW_ERROR_OUTPUT 6          /* some output doesn't exist */
+#define SWWW_ERROR_CONFLICT 7        /* clashes with the daemon's state */
+#define SWWW_ERROR_CANCELLED 8       /* cancelled with `swww queue clear` */
+#define SWWW_ERROR_IMAGE 9           /* the image couldn't be read */
+#define SWWW_ERROR_UNSUPPORTED 10    /* swww-daemon lacks a feature */
+
+/* Frees a string returned by this library. */
+void swww_free_string(char *s);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif /* SWWW_CLIENT_H */
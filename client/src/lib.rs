@@ -0,0 +1,394 @@
+//! A small library for controlling `swww-daemon` from other programs, without going through the
+//! `swww` binary. Besides the Rust API, it exposes a C API (declared in
+//! `include/swww_client.h`), so that wallpaper pickers and scripts in other languages can use it
+//! through their FFI.
+//!
+//! Connecting goes through the same steps as `swww`: the daemon's socket is looked for in
+//! `$XDG_RUNTIME_DIR` (or `/tmp/swww`), or, with `SWWW_REMOTE` set, the daemon listening at that
+//! address is connected to, authenticating with `SWWW_TOKEN`. For requests the functions below
+//! don't cover, build a [`Request`] and use [`send`] or [`send_within`].
+//!
+//! Only the basics are covered: images are displayed with the default transition, and animated
+//! images only show their first frame. Use `swww img` for everything else.
+use std::{
+    cell::RefCell,
+    ffi::{c_char, c_int, CStr, CString},
+    os::unix::net::UnixStream,
+    path::Path,
+    time::Duration,
+};
+
+use image::RgbaImage;
+use swww_render::{
+    color,
+    resize::{resize, FilterType},
+};
+
+pub use swww_protocol::{self, error::Error, Answer, Request};
+use swww_protocol::{
+    BgInfo, Clear, Colors, Coord, DaemonVersion, Img, OutputColors, Playback, Position, Transition,
+    TransitionSync, TransitionType,
+};
+use utils::{
+    communication::{self, get_remote, get_socket_path},
+    variant,
+};
+
+mod remote;
+
+thread_local! {
+    /// Why the last call to the C API failed, along with the error's code
+    static LAST_ERROR: RefCell<Option<(u8, CString)>> = const { RefCell::new(None) };
+}
+
+/// Connects to the daemon, trying a few times over half a second, in case it is still starting
+pub fn connect() -> Result<UnixStream, Error> {
+    connect_retrying(5, Duration::from_millis(100))
+}
+
+/// Connects to the daemon, trying `tries` times (at least once), `interval` apart. With
+/// `SWWW_REMOTE` set, this connects to the remote daemon instead, only trying once
+pub fn connect_retrying(tries: u8, interval: Duration) -> Result<UnixStream, Error> {
+    if let Some(remote) = get_remote() {
+        return remote::connect(&remote);
+    }
+    let path = get_socket_path();
+    let mut error = None;
+    for i in 0..tries.max(1) {
+        if i > 0 {
+            std::thread::sleep(interval);
+        }
+        match UnixStream::connect(&path) {
+            Ok(socket) => {
+                if let Err(e) = socket.set_nonblocking(false) {
+                    return Err(Error::Ipc(format!(
+                        "Failed to set blocking connection: {e}"
+                    )));
+                }
+                return Ok(socket);
+            }
+            Err(e) => error = Some(e),
+        }
+    }
+    // we always try at least once
+    let error = error.unwrap();
+    if error.kind() == std::io::ErrorKind::NotFound {
+        return Err(Error::Daemon(
+            "Socket file not found. Are you sure swww-daemon is running?".to_string(),
+        ));
+    }
+    Err(Error::Daemon(format!(
+        "Failed to connect to socket: {error}"
+    )))
+}
+
+/// Sends `request` to the daemon, for what the functions below don't cover. Error answers are
+/// returned as errors
+pub fn send(request: Request) -> Result<Answer, Error> {
+    let socket = connect()?;
+    communication::send(&request, &socket)?;
+    answer(Answer::receive(socket)?)
+}
+
+/// Like [`send`], but waits up to `timeout` for the answer, for the requests the daemon takes a
+/// while to answer, like `Request::Init` while it waits for the session
+pub fn send_within(request: Request, timeout: Duration) -> Result<Answer, Error> {
+    let socket = connect()?;
+    communication::send(&request, &socket)?;
+    answer(Answer::receive_within(socket, timeout)?)
+}
+
+fn answer(answer: Answer) -> Result<Answer, Error> {
+    match answer {
+        Answer::Err(e) => Err(e),
+        answer => Ok(answer),
+    }
+}
+
+/// Returns the information of every output the daemon is drawing to
+pub fn query() -> Result<Vec<BgInfo>, Error> {
+    match send(Request::Query)? {
+        Answer::Info(infos) => Ok(infos),
+        _ => Err(Error::Ipc(
+            "swww-daemon answered the query with something else".to_string(),
+        )),
+    }
+}
+
+/// Returns where the animation playing on each output is, for the outputs playing one
+pub fn playback() -> Result<Vec<Playback>, Error> {
+    match send(Request::Playback)? {
+        Answer::Playback(playback) => Ok(playback),
+        _ => Err(Error::Ipc(
+            "swww-daemon answered the playback query with something else".to_string(),
+        )),
+    }
+}
+
+/// Returns the running daemon's version, and the optional features it was built with
+pub fn version() -> Result<DaemonVersion, Error> {
+    match send(Request::Version)? {
+        Answer::Version(version) => Ok(version),
+        _ => Err(Error::Ipc(
+            "swww-daemon answered the version request with something else".to_string(),
+        )),
+    }
+}
+
+/// Returns the average colors of what `outputs` (or all outputs that aren't pinned, if empty)
+/// display, split into a `grid` of columns and rows
+pub fn colors(outputs: &[String], grid: (u32, u32)) -> Result<Vec<OutputColors>, Error> {
+    let request = Request::Colors(Colors {
+        outputs: outputs.to_vec(),
+        grid,
+    });
+    match send(request)? {
+        Answer::Colors(colors) => Ok(colors),
+        _ => Err(Error::Ipc(
+            "swww-daemon answered the colors request with something else".to_string(),
+        )),
+    }
+}
+
+/// Displays the image at `path` on `outputs` (or all outputs that aren't pinned, if empty), resized
+/// to fill them
+pub fn set_image(path: &Path, outputs: &[String]) -> Result<(), Error> {
+    send(image_request(path, outputs, default_transition())?).map(|_| ())
+}
+
+/// The request [`set_image`] sends, with `transition` instead of the default one. Outputs with a
+/// pre-rendered variant of the image (like `wall.1920x1080.png`, see `swww-img(1)`) get the variant
+/// instead. Decoding and resizing take a while, so this lets the request be prepared ahead of time
+pub fn image_request(
+    path: &Path,
+    outputs: &[String],
+    transition: Transition,
+) -> Result<Request, Error> {
+    let path = match path.canonicalize() {
+        Ok(path) => path,
+        Err(e) => {
+            return Err(Error::Image(format!(
+                "failed to canonicalize image path: {e}"
+            )))
+        }
+    };
+
+    // outputs with the same dimensions get the same image
+    let mut requests: Vec<(Img, Vec<String>)> = Vec::new();
+    let mut dims: Vec<(u32, u32)> = Vec::new();
+    let mut decoded: Option<RgbaImage> = None;
+    for info in query()?
+        .into_iter()
+        .filter(|info| info.is_requested(outputs))
+    {
+        let dim = info.real_dim();
+        if let Some(i) = dims.iter().position(|d| *d == dim) {
+            requests[i].1.push(info.name);
+            continue;
+        }
+        let img = match variant::find(&path, dim) {
+            Some(variant) => decode(&variant)?,
+            None => match &decoded {
+                Some(img) => img.clone(),
+                None => decoded.insert(decode(&path)?).clone(),
+            },
+        };
+        let img = Img {
+            path: path.clone(),
+            // the same filter `swww img` uses by default, so we display the same thing
+            img: resize(img, dim, FilterType::Lanczos3)?,
+        };
+        requests.push((img, vec![info.name]));
+        dims.push(dim);
+    }
+    if requests.is_empty() {
+        return Err(Error::Output(
+            "none of the requested outputs are valid".to_string(),
+        ));
+    }
+    Ok(Request::Img((transition, requests)))
+}
+
+fn decode(path: &Path) -> Result<RgbaImage, Error> {
+    let bytes = match std::fs::read(path) {
+        Ok(bytes) => bytes,
+        Err(e) => return Err(Error::Image(format!("failed to open image: {e}"))),
+    };
+    let format = match image::guess_format(&bytes) {
+        Ok(format) => format,
+        Err(e) => return Err(Error::Image(format!("failed to detect image format: {e}"))),
+    };
+    let mut img = match image::load_from_memory_with_format(&bytes, format) {
+        Ok(img) => img.into_rgba8(),
+        Err(e) => return Err(Error::Image(format!("failed to decode image: {e}"))),
+    };
+    if let Some(icc) = color::icc_profile(&bytes, format) {
+        // like `swww img`, we would rather show slightly off colors than nothing
+        let _ = color::to_srgb(&mut img, &icc);
+    }
+    Ok(img)
+}
+
+/// Fills `outputs` (or all outputs that aren't pinned, if empty) with `color`, in rgb
+pub fn clear(color: [u8; 3], outputs: &[String]) -> Result<(), Error> {
+    send(Request::Clear(Clear {
+        color,
+        outputs: outputs.to_vec(),
+        transition: default_transition(),
+    }))
+    .map(|_| ())
+}
+
+/// The transition everything here is displayed with
+fn default_transition() -> Transition {
+    Transition {
+        transition_type: TransitionType::Simple,
+        duration: 3.0,
+        step: 2,
+        fps: 30,
+        angle: 45.0,
+        pos: Position::new(Coord::Percent(0.5), Coord::Percent(0.5)),
+        bezier: (0.54, 0.0, 0.34, 0.99),
+        wave: (20.0, 20.0),
+        sync: TransitionSync::None,
+        skip_threshold: 0.0,
+    }
+}
+
+fn set_last_error(e: Error) {
+    // error messages never have nul bytes, but better safe than sorry
+    let msg = CString::new(e.message().replace('\0', " ")).unwrap_or_default();
+    LAST_ERROR.with(|last| *last.borrow_mut() = Some((e.code(), msg)));
+}
+
+/// Reads a C string argument. `NULL` is read as an empty string
+///
+/// # Safety
+///
+/// `s` must be `NULL` or point to a nul terminated string
+unsafe fn read_c_str(s: *const c_char) -> Result<String, Error> {
+    if s.is_null() {
+        return Ok(String::new());
+    }
+    match CStr::from_ptr(s).to_str() {
+        Ok(s) => Ok(s.to_string()),
+        Err(e) => Err(Error::InvalidRequest(format!(
+            "argument isn't valid utf-8: {e}"
+        ))),
+    }
+}
+
+/// Returns one line per output, like `swww query` prints, or `NULL` on failure. The result must
+/// be freed with `swww_free_string`
+#[no_mangle]
+pub extern "C" fn swww_query() -> *mut c_char {
+    match query() {
+        Ok(infos) => {
+            let lines: Vec<String> = infos.iter().map(BgInfo::to_string).collect();
+            match CString::new(lines.join("\n")) {
+                Ok(s) => s.into_raw(),
+                Err(e) => {
+                    set_last_error(Error::Other(e.to_string()));
+                    std::ptr::null_mut()
+                }
+            }
+        }
+        Err(e) => {
+            set_last_error(e);
+            std::ptr::null_mut()
+        }
+    }
+}
+
+/// Displays the image at `path` on `outputs`, a comma separated list of output or group names.
+/// `NULL` or an empty string means every output. Returns 0 on success, and -1 on failure
+///
+/// # Safety
+///
+/// `path` must point to a nul terminated string, and `outputs` must be `NULL` or point to one
+#[no_mangle]
+pub unsafe extern "C" fn swww_set_image(path: *const c_char, outputs: *const c_char) -> c_int {
+    let result = (|| {
+        if path.is_null() {
+            return Err(Error::InvalidRequest("no image path was given".to_string()));
+        }
+        let path = read_c_str(path)?;
+        let mut outputs: Vec<String> = read_c_str(outputs)?
+            .split(',')
+            .filter(|s| !s.is_empty())
+            .map(str::to_string)
+            .collect();
+        if !outputs.is_empty() {
+            if let Answer::Groups(groups) = send(Request::Group(swww_protocol::GroupCommand::List))?
+            {
+                swww_protocol::expand_groups(&mut outputs, &groups);
+            }
+        }
+        set_image(Path::new(&path), &outputs)
+    })();
+    match result {
+        Ok(()) => 0,
+        Err(e) => {
+            set_last_error(e);
+            -1
+        }
+    }
+}
+
+/// Returns why the last call that failed in this thread did, or `NULL` if none did. The result
+/// is owned by the library, and is valid until the next failure in this thread
+#[no_mangle]
+pub extern "C" fn swww_last_error() -> *const c_char {
+    LAST_ERROR.with(|last| match &*last.borrow() {
+        Some((_, e)) => e.as_ptr(),
+        None => std::ptr::null(),
+    })
+}
+
+/// Returns the code of the last error in this thread, or 0 if there was none. The codes are the
+/// same `swww` exits with, and are listed in `include/swww_client.h`
+#[no_mangle]
+pub extern "C" fn swww_last_error_code() -> c_int {
+    LAST_ERROR.with(|last| match &*last.borrow() {
+        Some((code, _)) => c_int::from(*code),
+        None => 0,
+    })
+}
+
+/// Frees a string returned by this library
+///
+/// # Safety
+///
+/// `s` must be `NULL` or have been returned by this library, and not freed yet
+#[no_mangle]
+pub unsafe extern "C" fn swww_free_string(s: *mut c_char) {
+    if !s.is_null() {
+        drop(CString::from_raw(s));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn images_should_be_sent_in_bgra() {
+        let img = image::RgbaImage::from_pixel(3, 3, image::Rgba([10, 20, 30, 255]));
+        let resized = resize(img, (3, 3), FilterType::Lanczos3).unwrap();
+        assert_eq!(resized.len(), 3 * 3 * 4);
+        for pixel in resized.chunks_exact(4) {
+            assert_eq!(pixel, [30, 20, 10, 255]);
+        }
+    }
+
+    #[test]
+    fn null_arguments_should_be_rejected() {
+        assert_eq!(
+            unsafe { swww_set_image(std::ptr::null(), std::ptr::null()) },
+            -1
+        );
+        let e = unsafe { CStr::from_ptr(swww_last_error()) };
+        assert_eq!(e.to_str().unwrap(), "no image path was given");
+        assert_eq!(swww_last_error_code(), 5);
+    }
+}
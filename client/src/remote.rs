@@ -0,0 +1,53 @@
+//! `SWWW_REMOTE`: talking to a daemon on another machine, started with `swww-daemon --listen`.
+//! Everything else expects a unix socket, so we hand it one end of a socket pair, and copy
+//! what goes through it to and from the daemon's TCP connection in the background
+use std::{
+    io::{self, Write},
+    net::{Shutdown, TcpStream},
+    os::unix::net::UnixStream,
+    thread,
+};
+
+use swww_protocol::error::Error;
+use utils::communication::{get_token, parse_tcp_addr};
+
+/// Connects to the daemon listening at `remote`, like `tcp://10.0.0.5:9500`, and authenticates
+/// with the token in `SWWW_TOKEN`
+pub fn connect(remote: &str) -> Result<UnixStream, Error> {
+    let addr = parse_tcp_addr(remote).map_err(Error::InvalidRequest)?;
+    let Some(token) = get_token() else {
+        return Err(Error::InvalidRequest(
+            "SWWW_REMOTE requires the remote daemon's token in SWWW_TOKEN".to_string(),
+        ));
+    };
+    let tcp = match TcpStream::connect(addr) {
+        Ok(tcp) => tcp,
+        Err(e) => {
+            return Err(Error::Daemon(format!(
+                "failed to connect to swww-daemon at {addr}: {e}"
+            )))
+        }
+    };
+    let bridged = (&tcp)
+        .write_all(format!("{token}\n").as_bytes())
+        .and_then(|()| UnixStream::pair())
+        .and_then(|(ours, theirs)| {
+            let (mut tcp_read, mut theirs_read) = (tcp.try_clone()?, theirs.try_clone()?);
+            // whichever side is done closes its half, so errors don't need reporting
+            thread::spawn(move || {
+                let _ = io::copy(&mut theirs_read, &mut &tcp);
+                let _ = tcp.shutdown(Shutdown::Write);
+            });
+            thread::spawn(move || {
+                let _ = io::copy(&mut tcp_read, &mut &theirs);
+                let _ = theirs.shutdown(Shutdown::Write);
+            });
+            Ok(ours)
+        });
+    match bridged {
+        Ok(ours) => Ok(ours),
+        Err(e) => Err(Error::Ipc(format!(
+            "failed to set up connection to {addr}: {e}"
+        ))),
+    }
+}
@@ -0,0 +1,136 @@
+//! Checks that `include/swww_client.h` declares exactly the functions `src/lib.rs` exports, with the
+//! same signatures, and the same error codes `Error::code` returns
+use std::collections::BTreeSet;
+
+use swww_client::Error;
+
+fn read(file: &str) -> String {
+    let path = format!("{}/{file}", env!("CARGO_MANIFEST_DIR"));
+    std::fs::read_to_string(&path).unwrap_or_else(|e| panic!("failed to read {path}: {e}"))
+}
+
+fn c_type(rust: &str) -> String {
+    match rust.trim() {
+        "" => "void".to_string(),
+        "c_int" => "int".to_string(),
+        "c_char" => "char".to_string(),
+        rust => match (rust.strip_prefix("*mut "), rust.strip_prefix("*const ")) {
+            (Some(pointee), _) => format!("{} *", c_type(pointee)),
+            (_, Some(pointee)) => format!("const {} *", c_type(pointee)),
+            _ => panic!("don't know the C type of {rust}"),
+        },
+    }
+}
+
+/// The prototype each `#[no_mangle]` function of lib.rs should have in the header, like
+/// `int swww_set_image(const char *path, const char *outputs)`
+fn exported() -> BTreeSet<String> {
+    let lib = read("src/lib.rs");
+    lib.split("#[no_mangle]")
+        .skip(1)
+        .map(|item| {
+            let signature = item[..item.find('{').unwrap()].split_whitespace();
+            let signature = signature.collect::<Vec<_>>().join(" ");
+            let signature = signature.trim_start_matches("pub ");
+            let signature = signature.trim_start_matches("unsafe ");
+            let signature = signature.strip_prefix("extern \"C\" fn ").unwrap();
+            let (name, rest) = signature.split_once('(').unwrap();
+            let (args, ret) = rest.split_once(')').unwrap();
+            let ret = c_type(ret.trim().trim_start_matches("->"));
+            let args: Vec<String> = args
+                .split(',')
+                .filter(|arg| !arg.trim().is_empty())
+                .map(|arg| {
+                    let (name, ty) = arg.split_once(':').unwrap();
+                    format!("{} {}", c_type(ty), name.trim())
+                })
+                .collect();
+            let args = if args.is_empty() {
+                "void".to_string()
+            } else {
+                args.join(", ")
+            };
+            normalize(&format!("{ret} {name}({args})"))
+        })
+        .collect()
+}
+
+/// The header, without comments and with one declaration per item
+fn header_items() -> Vec<String> {
+    let mut header = read("include/swww_client.h");
+    while let Some(start) = header.find("/*") {
+        let end = start + header[start..].find("*/").unwrap() + 2;
+        header.replace_range(start..end, " ");
+    }
+    let mut items = Vec::new();
+    let mut declaration = String::new();
+    for line in header
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty())
+    {
+        if line.starts_with('#') {
+            items.push(line.to_string());
+        } else if !line.starts_with("extern \"C\"") && line != "}" {
+            declaration.push_str(line);
+            declaration.push(' ');
+            if line.ends_with(';') {
+                items.push(normalize(declaration.trim_end_matches([' ', ';'])));
+                declaration.clear();
+            }
+        }
+    }
+    items
+}
+
+/// Spacing doesn't matter in C, as long as the pointers stick to the names
+fn normalize(prototype: &str) -> String {
+    let prototype = prototype.split_whitespace().collect::<Vec<_>>().join(" ");
+    prototype.replace("* ", "*").replace(" (", "(")
+}
+
+#[test]
+fn header_should_declare_every_exported_function() {
+    let declared: BTreeSet<String> = header_items()
+        .into_iter()
+        .filter(|item| !item.starts_with('#'))
+        .collect();
+    assert_eq!(declared, exported());
+}
+
+#[test]
+fn header_should_have_every_error_code() {
+    // exhaustive, so that new errors can't be forgotten here
+    let name = |e: &Error| match e {
+        Error::Other(_) => "OTHER",
+        Error::Ipc(_) => "IPC",
+        Error::Daemon(_) => "DAEMON",
+        Error::InvalidRequest(_) => "INVALID_REQUEST",
+        Error::Output(_) => "OUTPUT",
+        Error::Conflict(_) => "CONFLICT",
+        Error::Cancelled(_) => "CANCELLED",
+        Error::Image(_) => "IMAGE",
+        Error::Unsupported(_) => "UNSUPPORTED",
+    };
+    let errors = [
+        Error::Other(String::new()),
+        Error::Ipc(String::new()),
+        Error::Daemon(String::new()),
+        Error::InvalidRequest(String::new()),
+        Error::Output(String::new()),
+        Error::Conflict(String::new()),
+        Error::Cancelled(String::new()),
+        Error::Image(String::new()),
+        Error::Unsupported(String::new()),
+    ];
+    let expected: BTreeSet<String> = errors
+        .iter()
+        .map(|e| format!("#define SWWW_ERROR_{} {}", name(e), e.code()))
+        .collect();
+    let declared: BTreeSet<String> = header_items()
+        .into_iter()
+        .filter(|item| item.starts_with("#define SWWW_ERROR_"))
+        .map(|item| item.split_whitespace().collect::<Vec<_>>().join(" "))
+        .collect();
+    assert_eq!(declared, expected);
+}
@@ -0,0 +1,683 @@
+//! Exercises every request of swww's protocol against a running daemon, and checks that the
+//! answers are the ones documented in `doc/protocol.md`. This is meant for verifying third-party
+//! clients' assumptions, and the daemon itself, after changes to the protocol.
+//!
+//! Note this changes what is displayed on the output being tested.
+use std::{
+    io::Write,
+    os::unix::net::UnixStream,
+    path::PathBuf,
+    thread,
+    time::{Duration, Instant},
+};
+
+use swww_protocol::{
+    error::Error, Adjust, AdjustSource, Adjustment, AnimationRequest, Answer, BgImg, BgInfo, Busy,
+    Clear, Colors, Coord, Follow, Generate, Generator, GroupCommand, Img, Patch, Pin, Position,
+    QueueCommand, Record, Refresh, Region, Request, SafeArea, SetSafeArea, TestPattern,
+    TransactionCommand, Transition, TransitionSync, TransitionType, PROTOCOL_VERSION,
+};
+use utils::{
+    communication::{self, get_socket_path},
+    comp_decomp::{BitPack, Pack},
+};
+
+/// How long we wait for the daemon to apply a request that it answers before it is done with it
+const SETTLE_TIMEOUT: Duration = Duration::from_secs(5);
+
+struct Checker {
+    passed: u32,
+    failed: u32,
+}
+
+impl Checker {
+    fn check(&mut self, name: &str, result: Result<(), String>) {
+        match result {
+            Ok(()) => {
+                println!("PASS {name}");
+                self.passed += 1;
+            }
+            Err(e) => {
+                println!("FAIL {name}: {e}");
+                self.failed += 1;
+            }
+        }
+    }
+}
+
+fn main() {
+    let mut output = None;
+    let mut kill = false;
+    for arg in std::env::args().skip(1) {
+        match arg.as_str() {
+            "--kill" => kill = true,
+            "-h" | "--help" => {
+                println!("Usage: conformance [--kill] [OUTPUT]");
+                println!("Runs every request against the running swww-daemon, on OUTPUT (or the");
+                println!("first output). With --kill, the daemon is killed at the end.");
+                return;
+            }
+            _ => output = Some(arg),
+        }
+    }
+
+    let infos = match query() {
+        Ok(infos) => infos,
+        Err(e) => {
+            eprintln!("failed to query the daemon, is it running? {e}");
+            std::process::exit(1);
+        }
+    };
+    let info = match infos
+        .into_iter()
+        .find(|info| output.as_ref().is_none_or(|output| *output == info.name))
+    {
+        Some(info) => info,
+        None => {
+            eprintln!("the daemon has no output {}", output.unwrap_or_default());
+            std::process::exit(1);
+        }
+    };
+    println!("Testing output {info}");
+
+    let mut checker = Checker {
+        passed: 0,
+        failed: 0,
+    };
+    let output = info.name.clone();
+    let dim = info.real_dim();
+    checker.check("init", expect_ok(Request::Init));
+    checker.check("query", check_query(&output));
+    checker.check("malformed request", check_malformed());
+    checker.check("other protocol version", check_other_version());
+    checker.check(
+        "queue list",
+        match send(Request::Queue(QueueCommand::List)) {
+            Ok(Answer::Queue(_)) => Ok(()),
+            other => unexpected(other),
+        },
+    );
+    checker.check(
+        "trace",
+        match send(Request::Trace(None)) {
+            Ok(Answer::Trace(_)) => Ok(()),
+            other => unexpected(other),
+        },
+    );
+    checker.check(
+        "playback",
+        match send(Request::Playback) {
+            Ok(Answer::Playback(_)) => Ok(()),
+            other => unexpected(other),
+        },
+    );
+    checker.check(
+        "version",
+        match send(Request::Version) {
+            Ok(Answer::Version(_)) => Ok(()),
+            other => unexpected(other),
+        },
+    );
+    checker.check("clear", check_clear(&output));
+    checker.check("colors", check_colors(&output));
+    checker.check("adjust", check_adjust(&output));
+    checker.check("img", check_img(&output, dim));
+    checker.check("animation", check_animation(&output, dim));
+    checker.check("generate", check_generate(&output));
+    checker.check("patch", check_patch(&output));
+    checker.check("region", check_region(&output, dim));
+    checker.check("busy", check_busy(&output));
+    checker.check("pin", check_pin(&output));
+    checker.check("safe area", check_safe_area(&output));
+    checker.check("group", check_group(&output));
+    checker.check("follow", check_follow(&output));
+    checker.check("record", check_record(&output));
+    checker.check("refresh", check_refresh(&output));
+    checker.check("disable and enable", check_disable(&output));
+    checker.check("transaction", check_transaction(&output));
+    checker.check(
+        "queue clear",
+        expect_ok(Request::Queue(QueueCommand::Clear)),
+    );
+    if kill {
+        checker.check("kill", check_kill());
+    }
+
+    println!("{} passed, {} failed", checker.passed, checker.failed);
+    if checker.failed > 0 {
+        std::process::exit(1);
+    }
+}
+
+fn send(request: Request) -> Result<Answer, String> {
+    let socket = match UnixStream::connect(get_socket_path()) {
+        Ok(socket) => socket,
+        Err(e) => return Err(format!("failed to connect to the socket: {e}")),
+    };
+    communication::send(&request, &socket).map_err(|e| e.to_string())?;
+    Answer::receive(socket).map_err(|e| e.to_string())
+}
+
+fn unexpected(answer: Result<Answer, String>) -> Result<(), String> {
+    match answer {
+        Ok(Answer::Err(e)) => Err(format!("daemon answered with an error: {e}")),
+        Ok(_) => Err("daemon answered with the wrong kind of answer".to_string()),
+        Err(e) => Err(e),
+    }
+}
+
+fn expect_ok(request: Request) -> Result<(), String> {
+    match send(request) {
+        Ok(Answer::Ok) => Ok(()),
+        other => unexpected(other),
+    }
+}
+
+/// `Img` and `Clear` answer with `Transition`, unless there was nothing to transition
+fn expect_transition(request: Request) -> Result<(), String> {
+    match send(request) {
+        Ok(Answer::Ok | Answer::Transition { .. }) => Ok(()),
+        other => unexpected(other),
+    }
+}
+
+/// The error must also be of the given kind, eg. `Error::Output`
+fn expect_err(request: Request, kind: fn(String) -> Error) -> Result<(), String> {
+    let expected = kind(String::new());
+    match send(request) {
+        Ok(Answer::Err(e)) if e.code() == expected.code() => Ok(()),
+        Ok(Answer::Err(e)) => Err(format!(
+            "daemon answered with an error of the wrong kind ({} instead of {}): {e}",
+            e.code(),
+            expected.code()
+        )),
+        Ok(_) => Err("daemon accepted an invalid request".to_string()),
+        Err(e) => Err(e),
+    }
+}
+
+fn query() -> Result<Vec<BgInfo>, String> {
+    match send(Request::Query)? {
+        Answer::Info(infos) => Ok(infos),
+        Answer::Err(e) => Err(format!("daemon answered with an error: {e}")),
+        _ => Err("daemon answered with the wrong kind of answer".to_string()),
+    }
+}
+
+/// Waits until the output's info matches `f`
+fn wait_for_info(output: &str, what: &str, f: impl Fn(&BgInfo) -> bool) -> Result<(), String> {
+    let start = Instant::now();
+    loop {
+        if query()?.iter().any(|info| info.name == output && f(info)) {
+            return Ok(());
+        }
+        if start.elapsed() > SETTLE_TIMEOUT {
+            return Err(format!("{output} never {what}"));
+        }
+        thread::sleep(Duration::from_millis(50));
+    }
+}
+
+fn transition() -> Transition {
+    Transition {
+        transition_type: TransitionType::Simple,
+        duration: 0.0,
+        step: 255,
+        fps: 30,
+        angle: 0.0,
+        pos: center(),
+        bezier: (0.0, 0.0, 1.0, 1.0),
+        wave: (20.0, 20.0),
+        sync: TransitionSync::None,
+        skip_threshold: 0.0,
+    }
+}
+
+fn center() -> Position {
+    Position::new(Coord::Percent(0.5), Coord::Percent(0.5))
+}
+
+fn check_query(output: &str) -> Result<(), String> {
+    let infos = query()?;
+    if infos.iter().all(|info| info.name != output) {
+        return Err(format!("{output} disappeared from the query"));
+    }
+    if infos.iter().any(|info| info.dim.0 == 0 || info.dim.1 == 0) {
+        return Err("some outputs have no dimensions".to_string());
+    }
+    Ok(())
+}
+
+/// Garbage must be answered with an error, instead of being ignored or crashing the daemon
+fn check_malformed() -> Result<(), String> {
+    let mut socket = match UnixStream::connect(get_socket_path()) {
+        Ok(socket) => socket,
+        Err(e) => return Err(format!("failed to connect to the socket: {e}")),
+    };
+    if let Err(e) = socket.write_all(&[0xff; 16]) {
+        return Err(format!("failed to write to the socket: {e}"));
+    }
+    match Answer::receive(socket) {
+        Ok(Answer::Err(_)) => (),
+        Ok(_) => return Err("daemon accepted a malformed request".to_string()),
+        Err(e) => return Err(e.to_string()),
+    }
+    match query() {
+        Ok(_) => Ok(()),
+        Err(_) => Err("daemon stopped answering after a malformed request".to_string()),
+    }
+}
+
+/// Requests of another version of the protocol must be refused with `Unsupported`, without being
+/// read any further
+fn check_other_version() -> Result<(), String> {
+    let mut socket = match UnixStream::connect(get_socket_path()) {
+        Ok(socket) => socket,
+        Err(e) => return Err(format!("failed to connect to the socket: {e}")),
+    };
+    let mut request = b"swww".to_vec();
+    request.extend((PROTOCOL_VERSION + 1).to_le_bytes());
+    // `Query`'s tag
+    request.extend(11u32.to_le_bytes());
+    if let Err(e) = socket.write_all(&request) {
+        return Err(format!("failed to write to the socket: {e}"));
+    }
+    match Answer::receive(socket) {
+        Ok(Answer::Err(Error::Unsupported(_))) => Ok(()),
+        other => unexpected(other.map_err(|e| e.to_string())),
+    }
+}
+
+fn check_clear(output: &str) -> Result<(), String> {
+    let color = [1, 2, 3];
+    expect_transition(Request::Clear(Clear {
+        color,
+        outputs: vec![output.to_string()],
+        transition: transition(),
+    }))?;
+    wait_for_info(output, "was cleared", |info| {
+        info.img == BgImg::Color(color)
+    })
+}
+
+fn check_colors(output: &str) -> Result<(), String> {
+    let colors = |outputs: Vec<String>, grid| Request::Colors(Colors { outputs, grid });
+    match send(colors(vec![output.to_string()], (3, 2)))? {
+        Answer::Colors(answer) if answer.len() == 1 && answer[0].colors.len() == 6 => (),
+        Answer::Colors(_) => return Err("expected 6 colors of a single output".to_string()),
+        other => return unexpected(Ok(other)),
+    }
+    expect_err(
+        colors(vec![output.to_string()], (0, 1)),
+        Error::InvalidRequest,
+    )?;
+    expect_err(
+        colors(vec!["swww-conformance-nonexistent".to_string()], (1, 1)),
+        Error::Output,
+    )
+}
+
+fn check_adjust(output: &str) -> Result<(), String> {
+    // the output was just cleared, so there is always something to adjust
+    expect_transition(Request::Adjust(Adjust {
+        adjustment: Adjustment {
+            brightness: -20,
+            contrast: 10,
+            gamma: 1.2,
+        },
+        source: AdjustSource::Base,
+        match_brightness: true,
+        effect: None,
+        outputs: vec![output.to_string()],
+        transition: transition(),
+    }))?;
+    // clearing stopped any regions the output had
+    expect_err(
+        Request::Adjust(Adjust {
+            adjustment: Adjustment::default(),
+            source: AdjustSource::Regions,
+            match_brightness: false,
+            effect: None,
+            outputs: vec![output.to_string()],
+            transition: transition(),
+        }),
+        Error::Conflict,
+    )?;
+    expect_err(
+        Request::Adjust(Adjust {
+            adjustment: Adjustment::default(),
+            source: AdjustSource::Base,
+            match_brightness: false,
+            effect: None,
+            outputs: vec!["swww-conformance-nonexistent".to_string()],
+            transition: transition(),
+        }),
+        Error::Output,
+    )
+}
+
+fn check_img(output: &str, dim: (u32, u32)) -> Result<(), String> {
+    // nothing is stored at this path, so the daemon's cache eviction will clean up after us
+    let path = PathBuf::from("/swww-conformance/image.png");
+    let size = dim.0 as usize * dim.1 as usize;
+    let img = Img {
+        path: path.clone(),
+        img: [40, 80, 120, 255].repeat(size),
+    };
+    expect_transition(Request::Img((
+        transition(),
+        vec![(img, vec![output.to_string()])],
+    )))?;
+    wait_for_info(output, "displayed the image", |info| {
+        info.img == BgImg::Img(path.clone())
+    })
+}
+
+fn check_animation(output: &str, dim: (u32, u32)) -> Result<(), String> {
+    let size = dim.0 as usize * dim.1 as usize * 4;
+    let mut canvas = vec![0; size];
+    let first = vec![40; size];
+    let second = vec![80; size];
+    let mut animation = Vec::new();
+    for frame in [&second, &first] {
+        animation.push((
+            BitPack::pack(&mut canvas, frame)?,
+            Duration::from_millis(100),
+        ));
+    }
+    let request: AnimationRequest = vec![(
+        swww_protocol::Animation {
+            animation: animation.into_boxed_slice(),
+            keyframes: Box::new([]),
+            sync: false,
+        },
+        vec![output.to_string()],
+    )];
+    expect_ok(Request::Animation(request))?;
+    expect_err(
+        Request::Animation(vec![(
+            swww_protocol::Animation {
+                animation: Box::new([]),
+                keyframes: Box::new([]),
+                sync: false,
+            },
+            vec!["swww-conformance-nonexistent".to_string()],
+        )]),
+        Error::Output,
+    )
+}
+
+fn check_generate(output: &str) -> Result<(), String> {
+    expect_ok(Request::Generate(Generate {
+        generator: Generator::Gradient {
+            colors: vec![[0, 0, 0], [255, 255, 255]],
+            angle: 0.0,
+            period: None,
+            fps: 1,
+        },
+        outputs: vec![output.to_string()],
+    }))?;
+    wait_for_info(output, "displayed the gradient", |info| {
+        info.img == BgImg::Generated("gradient".to_string())
+    })?;
+    expect_err(
+        Request::Generate(Generate {
+            generator: Generator::Gradient {
+                colors: Vec::new(),
+                angle: 0.0,
+                period: None,
+                fps: 1,
+            },
+            outputs: vec![output.to_string()],
+        }),
+        Error::InvalidRequest,
+    )?;
+    expect_err(
+        Request::Generate(Generate {
+            generator: Generator::TestPattern {
+                pattern: TestPattern::Grid,
+            },
+            outputs: vec![
+                output.to_string(),
+                "swww-conformance-nonexistent".to_string(),
+            ],
+        }),
+        Error::Output,
+    )
+}
+
+fn check_patch(output: &str) -> Result<(), String> {
+    expect_ok(Request::Patch(Patch {
+        img: [255, 255, 255, 128].repeat(4),
+        dim: (2, 2),
+        pos: center(),
+        outputs: vec![output.to_string()],
+    }))?;
+    expect_err(
+        Request::Patch(Patch {
+            img: vec![0; 3],
+            dim: (2, 2),
+            pos: center(),
+            outputs: vec![output.to_string()],
+        }),
+        Error::InvalidRequest,
+    )
+}
+
+fn check_region(output: &str, dim: (u32, u32)) -> Result<(), String> {
+    let region = |keyframes: bool, dim: (u32, u32)| -> Result<Request, String> {
+        let size = dim.0 as usize * dim.1 as usize * 4;
+        let (first, second) = (vec![40; size], vec![80; size]);
+        let mut canvas = first.clone();
+        let mut animation = Vec::new();
+        for frame in [&second, &first] {
+            animation.push((
+                BitPack::pack(&mut canvas, frame)?,
+                Duration::from_millis(100),
+            ));
+        }
+        let keyframes = if keyframes {
+            vec![(1, BitPack::keyframe(&first)?)]
+        } else {
+            Vec::new()
+        };
+        Ok(Request::Region(Region {
+            animation: swww_protocol::Animation {
+                animation: animation.into_boxed_slice(),
+                keyframes: keyframes.into_boxed_slice(),
+                sync: false,
+            },
+            dim,
+            pos: center(),
+            outputs: vec![output.to_string()],
+        }))
+    };
+    expect_ok(region(true, (2, 2))?)?;
+    expect_err(region(false, (2, 2))?, Error::InvalidRequest)?;
+    expect_err(region(true, (dim.0 + 1, 1))?, Error::InvalidRequest)
+}
+
+fn check_busy(output: &str) -> Result<(), String> {
+    for progress in [Some(0.5), None] {
+        expect_ok(Request::Busy(Busy {
+            outputs: vec![output.to_string()],
+            progress,
+        }))?;
+    }
+    Ok(())
+}
+
+fn check_pin(output: &str) -> Result<(), String> {
+    for pinned in [true, false] {
+        expect_ok(Request::Pin(Pin {
+            output: output.to_string(),
+            pinned,
+        }))?;
+        wait_for_info(output, "was (un)pinned", |info| info.pinned == pinned)?;
+    }
+    expect_err(
+        Request::Pin(Pin {
+            output: "swww-conformance-nonexistent".to_string(),
+            pinned: true,
+        }),
+        Error::Output,
+    )
+}
+
+fn check_safe_area(output: &str) -> Result<(), String> {
+    for area in [
+        SafeArea {
+            top: 40,
+            ..SafeArea::default()
+        },
+        SafeArea::default(),
+    ] {
+        expect_ok(Request::SafeArea(SetSafeArea {
+            output: output.to_string(),
+            area,
+        }))?;
+        wait_for_info(output, "had its safe area set", |info| {
+            info.safe_area == area
+        })?;
+    }
+    expect_err(
+        Request::SafeArea(SetSafeArea {
+            output: "swww-conformance-nonexistent".to_string(),
+            area: SafeArea::default(),
+        }),
+        Error::Output,
+    )
+}
+
+fn check_transaction(output: &str) -> Result<(), String> {
+    let clear = |color| {
+        Request::Clear(Clear {
+            color,
+            outputs: vec![output.to_string()],
+            transition: transition(),
+        })
+    };
+    expect_err(
+        Request::Transaction(TransactionCommand::Commit),
+        Error::Conflict,
+    )?;
+    expect_ok(Request::Transaction(TransactionCommand::Begin))?;
+    expect_err(
+        Request::Transaction(TransactionCommand::Begin),
+        Error::Conflict,
+    )?;
+    expect_ok(clear([4, 5, 6]))?;
+    if query()?
+        .iter()
+        .any(|info| info.img == BgImg::Color([4, 5, 6]))
+    {
+        return Err("a staged request was applied before the commit".to_string());
+    }
+    expect_ok(Request::Transaction(TransactionCommand::Commit))?;
+    wait_for_info(output, "applied the transaction", |info| {
+        info.img == BgImg::Color([4, 5, 6])
+    })?;
+    expect_ok(Request::Transaction(TransactionCommand::Begin))?;
+    expect_ok(clear([7, 8, 9]))?;
+    expect_ok(Request::Transaction(TransactionCommand::Rollback))?;
+    if query()?
+        .iter()
+        .any(|info| info.img == BgImg::Color([7, 8, 9]))
+    {
+        return Err("a rolled back request was applied".to_string());
+    }
+    Ok(())
+}
+
+fn check_group(output: &str) -> Result<(), String> {
+    let name = "swww-conformance".to_string();
+    expect_ok(Request::Group(GroupCommand::Create {
+        name: name.clone(),
+        outputs: vec![output.to_string()],
+    }))?;
+    match send(Request::Group(GroupCommand::List)) {
+        Ok(Answer::Groups(groups)) if groups.contains(&(name.clone(), vec![output.into()])) => (),
+        Ok(Answer::Groups(_)) => return Err("the group wasn't listed".to_string()),
+        other => return unexpected(other),
+    }
+    expect_ok(Request::Group(GroupCommand::Delete(name.clone())))?;
+    expect_err(
+        Request::Group(GroupCommand::Delete(name)),
+        Error::InvalidRequest,
+    )?;
+    expect_err(
+        Request::Group(GroupCommand::Create {
+            name: output.to_string(),
+            outputs: vec![output.to_string()],
+        }),
+        Error::Conflict,
+    )
+}
+
+fn check_follow(output: &str) -> Result<(), String> {
+    expect_ok(Request::Follow(Follow {
+        output: output.to_string(),
+        source: None,
+    }))?;
+    // outputs can't follow themselves
+    expect_err(
+        Request::Follow(Follow {
+            output: output.to_string(),
+            source: Some(output.to_string()),
+        }),
+        Error::Conflict,
+    )
+}
+
+/// Recording needs the daemon's `ffmpeg` feature, so we only check that the daemon answers
+fn check_record(output: &str) -> Result<(), String> {
+    let path = std::env::temp_dir().join("swww-conformance.mkv");
+    let answer = send(Request::Record(Record {
+        output: output.to_string(),
+        duration: Duration::from_millis(1),
+        fps: 1,
+        path,
+    }));
+    match answer {
+        Ok(Answer::Ok | Answer::Err(_)) => Ok(()),
+        other => unexpected(other),
+    }
+}
+
+fn check_refresh(output: &str) -> Result<(), String> {
+    match send(Request::Refresh(Refresh {
+        outputs: vec![output.to_string()],
+    })) {
+        Ok(Answer::Info(infos)) if infos.iter().any(|info| info.name == output) => (),
+        other => return unexpected(other),
+    }
+    expect_err(
+        Request::Refresh(Refresh {
+            outputs: vec!["swww-conformance-nonexistent".to_string()],
+        }),
+        Error::Output,
+    )
+}
+
+fn check_disable(output: &str) -> Result<(), String> {
+    expect_ok(Request::Disable(output.to_string()))?;
+    if query()?.iter().any(|info| info.name == output) {
+        return Err(format!("{output} is still being drawn to"));
+    }
+    expect_err(Request::Disable(output.to_string()), Error::Output)?;
+    expect_ok(Request::Enable(output.to_string()))?;
+    wait_for_info(output, "came back", |_| true)?;
+    expect_err(Request::Enable(output.to_string()), Error::Conflict)
+}
+
+fn check_kill() -> Result<(), String> {
+    expect_ok(Request::Kill)?;
+    let start = Instant::now();
+    while get_socket_path().exists() {
+        if start.elapsed() > SETTLE_TIMEOUT {
+            return Err("the socket was never removed".to_string());
+        }
+        thread::sleep(Duration::from_millis(50));
+    }
+    Ok(())
+}
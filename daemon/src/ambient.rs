@@ -0,0 +1,82 @@
+//! The average colors of what outputs display, for ambient lighting (`swww subscribe
+//! --avg-color`). LED controllers ask for them many times a second, so we only average some of
+//! the pixels
+use swww_protocol::{error::Error, OutputColors};
+
+/// Only one in this many pixels of every row, and one in this many rows, are averaged
+const STRIDE: usize = 4;
+
+/// Splits `canvas` (in Xrgb8888, with dimensions `dim`) into a `grid` of columns and rows, and
+/// averages every cell
+pub fn colors(
+    output: &str,
+    canvas: &[u8],
+    dim: (u32, u32),
+    grid: (u32, u32),
+) -> Result<OutputColors, Error> {
+    if grid.0 == 0 || grid.1 == 0 {
+        return Err(Error::InvalidRequest(
+            "the grid must have at least one column and one row".to_string(),
+        ));
+    }
+    let (width, height) = (dim.0 as usize, dim.1 as usize);
+    let (columns, rows) = (grid.0 as usize, grid.1 as usize);
+    // cells are at least one pixel wide, even with more columns than pixels
+    let range = |i: usize, cells: usize, len: usize| {
+        let start = (i * len / cells).min(len.saturating_sub(1));
+        start..((i + 1) * len / cells).max(start + 1).min(len)
+    };
+    let mut colors = Vec::with_capacity(columns * rows);
+    for row in 0..rows {
+        for column in 0..columns {
+            let mut sum = [0u64; 3];
+            let mut count = 0;
+            for y in range(row, rows, height).step_by(STRIDE) {
+                for x in range(column, columns, width).step_by(STRIDE) {
+                    let Some(pixel) = canvas.get((y * width + x) * 4..(y * width + x) * 4 + 3)
+                    else {
+                        continue;
+                    };
+                    // Xrgb8888 is little endian, so the bytes are in bgr order
+                    sum[0] += pixel[2] as u64;
+                    sum[1] += pixel[1] as u64;
+                    sum[2] += pixel[0] as u64;
+                    count += 1;
+                }
+            }
+            let count = count.max(1);
+            colors.push(sum.map(|channel| (channel / count) as u8));
+        }
+    }
+    Ok(OutputColors {
+        output: output.to_string(),
+        colors,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cells_should_have_their_own_average() {
+        // the left half is red and the right half blue, in bgr
+        let canvas: Vec<u8> = (0..16 * 8)
+            .flat_map(|i| {
+                if i % 16 < 8 {
+                    [0, 0, 255, 0]
+                } else {
+                    [255, 0, 0, 0]
+                }
+            })
+            .collect();
+        let whole = colors("DP-1", &canvas, (16, 8), (1, 1)).unwrap();
+        assert_eq!(whole.colors, [[127, 0, 127]]);
+        let halves = colors("DP-1", &canvas, (16, 8), (2, 1)).unwrap();
+        assert_eq!(halves.colors, [[255, 0, 0], [0, 0, 255]]);
+        // more cells than pixels still gives every cell a color
+        let tiny = colors("DP-1", &canvas, (16, 8), (32, 1)).unwrap();
+        assert_eq!(tiny.colors.len(), 32);
+        assert!(colors("DP-1", &canvas, (16, 8), (0, 1)).is_err());
+    }
+}
@@ -0,0 +1,251 @@
+//! The shared memory we draw the outputs into
+//!
+//! Usually, that is a single buffer, attached to the output's surface. Outputs too big for a single
+//! buffer are split into tiles instead, each with its own buffer: the first one is attached to the
+//! output's surface, and the others to subsurfaces placed next to it. We then draw into a canvas in
+//! memory, and copy whatever changed into the tiles when committing.
+use log::{debug, warn};
+use smithay_client_toolkit::{
+    environment::Environment,
+    reexports::client::protocol::{
+        wl_compositor, wl_shm, wl_subcompositor, wl_subsurface, wl_surface,
+    },
+    shm::MemPool,
+};
+
+use crate::wayland;
+
+/// Buffers wider or taller than this are tiled. It is the largest texture many GPUs accept, and
+/// compositors usually upload our buffers into textures
+const MAX_BUFFER_SIDE: u32 = 8192;
+
+/// The largest side of a tile. Tiles this small are cheap to allocate and to copy into
+const TILE_SIDE: u32 = 2048;
+
+/// A rectangle of the output, in buffer pixels
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Rect {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+impl Rect {
+    pub fn new(x: u32, y: u32, width: u32, height: u32) -> Self {
+        Self {
+            x,
+            y,
+            width,
+            height,
+        }
+    }
+
+    fn intersection(&self, other: &Rect) -> Option<Rect> {
+        let (x0, y0) = (self.x.max(other.x), self.y.max(other.y));
+        let x1 = (self.x + self.width).min(other.x + other.width);
+        let y1 = (self.y + self.height).min(other.y + other.height);
+        (x0 < x1 && y0 < y1).then(|| Rect::new(x0, y0, x1 - x0, y1 - y0))
+    }
+}
+
+struct Tile {
+    rect: Rect,
+    pool: MemPool,
+    /// The subsurface this tile is drawn to. The first tile has none, since it is drawn to the
+    /// output's own surface
+    sub: Option<(wl_surface::WlSurface, wl_subsurface::WlSubsurface)>,
+}
+
+impl Drop for Tile {
+    fn drop(&mut self) {
+        if let Some((surface, subsurface)) = &self.sub {
+            subsurface.destroy();
+            surface.destroy();
+        }
+    }
+}
+
+enum Kind {
+    Single(MemPool),
+    Tiled { canvas: Vec<u8>, tiles: Vec<Tile> },
+}
+
+pub struct Buffer {
+    env: Environment<wayland::Env>,
+    dim: (u32, u32),
+    kind: Kind,
+}
+
+impl Buffer {
+    pub fn new(env: Environment<wayland::Env>) -> Self {
+        let pool = new_pool(&env).expect("Failed to create a memory pool!");
+        Self {
+            env,
+            dim: (0, 0),
+            kind: Kind::Single(pool),
+        }
+    }
+
+    /// Makes sure we can draw a buffer of `dim` to `surface`, with `scale` as its buffer scale.
+    /// Outputs that are too big, or for which we can't allocate a single buffer, are tiled
+    pub fn allocate(
+        &mut self,
+        surface: &wl_surface::WlSurface,
+        dim: (u32, u32),
+        scale: i32,
+    ) -> Result<(), String> {
+        let size = dim.0 as usize * dim.1 as usize * 4;
+        self.dim = dim;
+        if dim.0 <= MAX_BUFFER_SIDE && dim.1 <= MAX_BUFFER_SIDE {
+            if let Kind::Tiled { .. } = self.kind {
+                self.kind = Kind::Single(new_pool(&self.env)?);
+            }
+            if let Kind::Single(pool) = &mut self.kind {
+                match pool.resize(size) {
+                    Ok(()) => return Ok(()),
+                    Err(e) => warn!("failed to allocate {size} bytes for a buffer: {e}. Tiling it"),
+                }
+            }
+        }
+
+        // the tiles must be placed in whole logical pixels
+        let side = TILE_SIDE - TILE_SIDE % scale as u32;
+        let compositor = self.env.require_global::<wl_compositor::WlCompositor>();
+        let Some(subcompositor) = self.env.get_global::<wl_subcompositor::WlSubcompositor>() else {
+            return Err(format!(
+                "can't tile a buffer of {}x{}, since the compositor doesn't support subsurfaces",
+                dim.0, dim.1
+            ));
+        };
+        let mut tiles = Vec::new();
+        for y in (0..dim.1).step_by(side as usize) {
+            for x in (0..dim.0).step_by(side as usize) {
+                let rect = Rect::new(x, y, side.min(dim.0 - x), side.min(dim.1 - y));
+                let mut pool = new_pool(&self.env)?;
+                let tile_size = rect.width as usize * rect.height as usize * 4;
+                if let Err(e) = pool.resize(tile_size) {
+                    return Err(format!(
+                        "failed to allocate {tile_size} bytes for a tile: {e}"
+                    ));
+                }
+                let sub = (!tiles.is_empty()).then(|| {
+                    let tile_surface = self.env.create_surface().detach();
+                    // like the output's surface, tiles shouldn't take the cursor's input
+                    let empty_region = compositor.create_region();
+                    tile_surface.set_input_region(Some(&empty_region));
+                    empty_region.destroy();
+                    tile_surface.set_buffer_scale(scale);
+                    let subsurface = subcompositor.get_subsurface(&tile_surface, surface);
+                    subsurface.set_position(x as i32 / scale, y as i32 / scale);
+                    (tile_surface, subsurface.detach())
+                });
+                tiles.push(Tile { rect, pool, sub });
+            }
+        }
+        debug!(
+            "Split a buffer of {}x{} into {} tiles",
+            dim.0,
+            dim.1,
+            tiles.len()
+        );
+        self.kind = Kind::Tiled {
+            canvas: vec![0; size],
+            tiles,
+        };
+        Ok(())
+    }
+
+    /// What we are drawing, 4 bytes per pixel, in rows without padding
+    pub fn canvas(&mut self) -> &mut [u8] {
+        let size = self.dim.0 as usize * self.dim.1 as usize * 4;
+        match &mut self.kind {
+            Kind::Single(pool) => &mut pool.mmap()[0..size],
+            Kind::Tiled { canvas, .. } => canvas,
+        }
+    }
+
+    /// Attaches the buffer to `surface`, damaging `damage`, and commits
+    pub fn commit(&mut self, surface: &wl_surface::WlSurface, damage: Rect) {
+        let (width, height) = (self.dim.0 as i32, self.dim.1 as i32);
+        match &mut self.kind {
+            Kind::Single(pool) => {
+                let buffer = pool.buffer(0, width, height, 4 * width, wl_shm::Format::Xrgb8888);
+                surface.attach(Some(&buffer), 0, 0);
+                surface.damage_buffer(
+                    damage.x as i32,
+                    damage.y as i32,
+                    damage.width as i32,
+                    damage.height as i32,
+                );
+            }
+            Kind::Tiled { canvas, tiles } => {
+                let stride = width as usize * 4;
+                for tile in tiles.iter_mut() {
+                    let Some(damaged) = tile.rect.intersection(&damage) else {
+                        continue;
+                    };
+                    let tile_stride = tile.rect.width as usize * 4;
+                    let mmap = tile.pool.mmap();
+                    for y in damaged.y..damaged.y + damaged.height {
+                        let src = y as usize * stride + damaged.x as usize * 4;
+                        let dst = (y - tile.rect.y) as usize * tile_stride
+                            + (damaged.x - tile.rect.x) as usize * 4;
+                        let len = damaged.width as usize * 4;
+                        mmap[dst..dst + len].copy_from_slice(&canvas[src..src + len]);
+                    }
+                    let buffer = tile.pool.buffer(
+                        0,
+                        tile.rect.width as i32,
+                        tile.rect.height as i32,
+                        tile_stride as i32,
+                        wl_shm::Format::Xrgb8888,
+                    );
+                    let tile_surface = tile.sub.as_ref().map_or(surface, |(s, _)| s);
+                    tile_surface.attach(Some(&buffer), 0, 0);
+                    tile_surface.damage_buffer(
+                        (damaged.x - tile.rect.x) as i32,
+                        (damaged.y - tile.rect.y) as i32,
+                        damaged.width as i32,
+                        damaged.height as i32,
+                    );
+                    // subsurfaces are synchronized, so this only shows up with the commit below
+                    if tile.sub.is_some() {
+                        tile_surface.commit();
+                    }
+                }
+            }
+        }
+        surface.commit();
+    }
+
+    /// How many tiles the buffer is split into, if it is tiled
+    pub fn tiles(&self) -> Option<usize> {
+        match &self.kind {
+            Kind::Single(_) => None,
+            Kind::Tiled { tiles, .. } => Some(tiles.len()),
+        }
+    }
+}
+
+fn new_pool(env: &Environment<wayland::Env>) -> Result<MemPool, String> {
+    env.create_simple_pool(|_dispatch_data| {
+        //do I need to do something here???
+    })
+    .map_err(|e| format!("failed to create a memory pool: {e}"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rects_should_intersect_only_if_they_share_pixels() {
+        let a = Rect::new(0, 0, 10, 10);
+        assert_eq!(
+            a.intersection(&Rect::new(5, 8, 10, 10)),
+            Some(Rect::new(5, 8, 5, 2))
+        );
+        assert_eq!(a.intersection(&Rect::new(10, 0, 10, 10)), None);
+    }
+}
@@ -0,0 +1,77 @@
+//! `--check`: reports what `swww doctor` needs to know about this daemon and the compositor,
+//! without starting. Every line is `key: value`:
+//!
+//! ```text
+//! version: 0.7.2
+//! features: ffmpeg, x11
+//! backend: wayland
+//! layer-shell: yes
+//! viewporter: no
+//! outputs: 2
+//! ```
+//!
+//! When we can't connect to the compositor, `backend` is `none`, followed by an `error` line
+use smithay_client_toolkit::reexports::client::{Display, GlobalManager};
+
+use swww_protocol::DaemonVersion;
+
+/// Our version, and the optional features we were built with
+pub fn version() -> DaemonVersion {
+    let features = [
+        ("ffmpeg", cfg!(feature = "ffmpeg")),
+        ("x11", cfg!(feature = "x11")),
+        ("plugins", cfg!(feature = "plugins")),
+    ];
+    DaemonVersion {
+        version: env!("CARGO_PKG_VERSION").to_string(),
+        features: features
+            .into_iter()
+            .filter(|(_, enabled)| *enabled)
+            .map(|(name, _)| name.to_string())
+            .collect(),
+    }
+}
+
+pub fn run() {
+    let version = version();
+    println!("version: {}", version.version);
+    println!("features: {}", version.features.join(", "));
+    #[cfg(feature = "x11")]
+    if crate::x11::is_session() {
+        println!("backend: x11");
+        return;
+    }
+    match wayland_globals() {
+        Ok(globals) => {
+            let has = |interface: &str| globals.iter().any(|global| global == interface);
+            let yes_no = |interface: &str| if has(interface) { "yes" } else { "no" };
+            println!("backend: wayland");
+            println!("layer-shell: {}", yes_no("zwlr_layer_shell_v1"));
+            println!("viewporter: {}", yes_no("wp_viewporter"));
+            let outputs = globals.iter().filter(|global| *global == "wl_output");
+            println!("outputs: {}", outputs.count());
+        }
+        Err(e) => {
+            println!("backend: none");
+            println!("error: {e}");
+        }
+    }
+}
+
+/// The interfaces of every global the compositor advertises
+fn wayland_globals() -> Result<Vec<String>, String> {
+    let display = match Display::connect_to_env() {
+        Ok(display) => display,
+        Err(e) => return Err(format!("failed to connect to the wayland compositor: {e}")),
+    };
+    let mut queue = display.create_event_queue();
+    let globals = GlobalManager::new(&display.attach(queue.token()));
+    if let Err(e) = queue.sync_roundtrip(&mut (), |_, _, _| {}) {
+        return Err(format!("failed to list the compositor's globals: {e}"));
+    }
+    Ok(globals
+        .list()
+        .into_iter()
+        .map(|(_, interface, _)| interface)
+        .collect())
+}
@@ -0,0 +1,288 @@
+//! `swww adjust --effect plugin:NAME`: effects applied to the image by WebAssembly modules in
+//! `$XDG_CONFIG_HOME/swww/effects` (or `~/.config/swww/effects`). Unlike transition plugins, effects
+//! are sandboxed, so that effects written by others are safe to use: they are validated and run by
+//! our own interpreter (see `wasm`), on a thread of their own, can't import anything, can't take
+//! more than 256MiB of memory, and are stopped after `MAX_TIME`. Effects export:
+//!
+//! ```wat
+//! (func (export "swww_effect_abi") (result i32)) ;; returns 1
+//! (func (export "swww_effect_alloc") (param $len i32) (result i32))
+//! (func (export "swww_effect_apply") (param $ptr i32) (param $width i32) (param $height i32)
+//!                                    (result i32))
+//! ```
+//!
+//! `swww_effect_alloc` returns where in the module's memory the frame, of `len` bytes, should go,
+//! and `swww_effect_apply` transforms the `width * height` pixels there, in rgba, in place.
+//! Returning anything other than 0 fails the request. Every image gets a fresh instance of the
+//! module, so nothing carries over between outputs. Backgrounds are opaque, so alpha is left as it
+//! was
+use std::{path::PathBuf, thread};
+
+use swww_protocol::error::Error;
+
+#[cfg(feature = "effects")]
+mod wasm;
+
+/// The version of the interface above. Effects built for another one are refused
+#[cfg(feature = "effects")]
+const ABI: u32 = 1;
+
+/// Requests wait for the effect before them, so it can't take too long
+#[cfg(feature = "effects")]
+const MAX_TIME: std::time::Duration = std::time::Duration::from_secs(5);
+
+pub fn dir() -> Result<PathBuf, Error> {
+    let config = match std::env::var_os("XDG_CONFIG_HOME") {
+        Some(dir) => PathBuf::from(dir),
+        None => match std::env::var_os("HOME") {
+            Some(home) => PathBuf::from(home).join(".config"),
+            None => {
+                return Err(Error::Other(
+                    "failed to read both XDG_CONFIG_HOME and HOME env vars".to_string(),
+                ))
+            }
+        },
+    };
+    Ok(config.join("swww/effects"))
+}
+
+/// Loads the effect called `name` and applies it to each of `imgs`, in bgra, of their dimensions,
+/// on a thread of its own, so that we keep drawing meanwhile. `done` gets the images back, or the
+/// first error
+pub fn spawn<F>(name: String, imgs: Vec<(Vec<u8>, (u32, u32))>, done: F) -> Result<(), Error>
+where
+    F: FnOnce(Result<Vec<Vec<u8>>, Error>) + Send + 'static,
+{
+    let apply = move || {
+        let effect = load(&name)?;
+        imgs.into_iter()
+            .map(|(mut img, dim)| effect.apply(&mut img, dim).map(|()| img))
+            .collect()
+    };
+    match thread::Builder::new()
+        .name("effect".to_string()) //Name our threads  for better log messages
+        .spawn(move || done(apply()))
+    {
+        Ok(_) => Ok(()),
+        Err(e) => Err(Error::Other(format!(
+            "failed to spawn 'effect' thread: {e}"
+        ))),
+    }
+}
+
+/// Loads the effect called `name`, checking that it speaks our interface
+fn load(name: &str) -> Result<Effect, Error> {
+    if !cfg!(feature = "effects") {
+        return Err(Error::Unsupported(
+            "swww-daemon was built without the 'effects' feature".to_string(),
+        ));
+    }
+    if name.is_empty() || name.contains('/') {
+        return Err(Error::InvalidRequest(format!(
+            "invalid effect name {name:?}"
+        )));
+    }
+    let path = dir()?.join(format!("{name}.wasm"));
+    let bytes = std::fs::read(&path)
+        .map_err(|e| Error::Other(format!("failed to read effect {}: {e}", path.display())))?;
+    Effect::new(name, &bytes)
+}
+
+#[cfg(feature = "effects")]
+pub struct Effect {
+    name: String,
+    module: wasm::Module,
+}
+
+#[cfg(not(feature = "effects"))]
+pub enum Effect {}
+
+impl Effect {
+    #[cfg(feature = "effects")]
+    fn new(name: &str, bytes: &[u8]) -> Result<Self, Error> {
+        use std::time::Instant;
+        use wasm::{FuncType, ValType::I32};
+
+        let invalid = |e: String| Error::InvalidRequest(format!("invalid effect {name}: {e}"));
+        let module = wasm::Module::parse(bytes).map_err(invalid)?;
+        for (export, params) in [
+            ("swww_effect_abi", 0),
+            ("swww_effect_alloc", 1),
+            ("swww_effect_apply", 3),
+        ] {
+            let ty = FuncType {
+                params: vec![I32; params],
+                results: vec![I32],
+            };
+            if module.export_type(export) != Some(&ty) {
+                return Err(invalid(format!(
+                    "it doesn't export {export}({params} i32) -> i32"
+                )));
+            }
+        }
+        let mut instance =
+            wasm::Instance::new(&module, Instant::now() + MAX_TIME).map_err(invalid)?;
+        let abi = call(&mut instance, "swww_effect_abi", &[]).map_err(invalid)?;
+        if abi != ABI {
+            return Err(Error::Unsupported(format!(
+                "effect {name} was built for version {abi} of the effect interface, but \
+                swww-daemon speaks version {ABI}"
+            )));
+        }
+        Ok(Self {
+            name: name.to_string(),
+            module,
+        })
+    }
+
+    #[cfg(not(feature = "effects"))]
+    fn new(_: &str, _: &[u8]) -> Result<Self, Error> {
+        unreachable!("we return before loading effects without the feature")
+    }
+
+    /// Applies the effect to `img`, of `dim`, in bgra
+    #[cfg(feature = "effects")]
+    pub fn apply(&self, img: &mut [u8], dim: (u32, u32)) -> Result<(), Error> {
+        self.run(img, dim)
+            .map_err(|e| Error::Other(format!("effect {} failed: {e}", self.name)))
+    }
+
+    #[cfg(not(feature = "effects"))]
+    pub fn apply(&self, _: &mut [u8], _: (u32, u32)) -> Result<(), Error> {
+        match *self {}
+    }
+
+    #[cfg(feature = "effects")]
+    fn run(&self, img: &mut [u8], (width, height): (u32, u32)) -> Result<(), String> {
+        use std::time::Instant;
+
+        let mut instance = wasm::Instance::new(&self.module, Instant::now() + MAX_TIME)?;
+        let len = i32::try_from(img.len()).map_err(|_| "the image is too big")? as u32;
+        let ptr = call(&mut instance, "swww_effect_alloc", &[len])? as usize;
+        let frame = instance
+            .memory
+            .get_mut(ptr..ptr + img.len())
+            .ok_or("swww_effect_alloc returned memory the effect doesn't have")?;
+        for (rgba, bgra) in frame.chunks_exact_mut(4).zip(img.chunks_exact(4)) {
+            rgba.copy_from_slice(&[bgra[2], bgra[1], bgra[0], bgra[3]]);
+        }
+        match call(
+            &mut instance,
+            "swww_effect_apply",
+            &[ptr as u32, width, height],
+        )? {
+            0 => (),
+            status => return Err(format!("it returned {}", status as i32)),
+        }
+        // memory can grow, but never shrink, so the frame is still there
+        let frame = &instance.memory[ptr..ptr + img.len()];
+        for (bgra, rgba) in img.chunks_exact_mut(4).zip(frame.chunks_exact(4)) {
+            bgra[..3].copy_from_slice(&[rgba[2], rgba[1], rgba[0]]);
+        }
+        Ok(())
+    }
+}
+
+/// Calls a function of the interface, all of which return a single i32
+#[cfg(feature = "effects")]
+fn call(instance: &mut wasm::Instance, name: &str, args: &[u32]) -> Result<u32, String> {
+    let args: Vec<u64> = args.iter().map(|arg| *arg as u64).collect();
+    match instance.call(name, &args)?[..] {
+        [result] => Ok(result as u32),
+        _ => Err(format!("{name} returned the wrong number of values")),
+    }
+}
+
+#[cfg(all(test, feature = "effects"))]
+mod tests {
+    use super::*;
+
+    fn section(wasm: &mut Vec<u8>, id: u8, contents: &[u8]) {
+        wasm.push(id);
+        wasm.push(contents.len() as u8);
+        wasm.extend(contents);
+    }
+
+    /// An effect of version `abi` of the interface inverting the colors, which puts the frame at the
+    /// address `alloc` returns
+    fn invert(abi: u8, alloc: &[u8]) -> Vec<u8> {
+        let mut wasm = b"\0asm\x01\0\0\0".to_vec();
+        #[rustfmt::skip]
+        section(&mut wasm, 1, &[
+            3,
+            0x60, 0, 1, 0x7f,
+            0x60, 1, 0x7f, 1, 0x7f,
+            0x60, 3, 0x7f, 0x7f, 0x7f, 1, 0x7f,
+        ]);
+        section(&mut wasm, 3, &[3, 0, 1, 2]);
+        // a page of memory, which is enough for the tests
+        section(&mut wasm, 5, &[1, 0, 1]);
+        let mut exports = vec![3];
+        for (i, name) in ["swww_effect_abi", "swww_effect_alloc", "swww_effect_apply"]
+            .into_iter()
+            .enumerate()
+        {
+            exports.push(name.len() as u8);
+            exports.extend(name.as_bytes());
+            exports.extend([0, i as u8]);
+        }
+        section(&mut wasm, 7, &exports);
+        let abi = [0, 0x41, abi, 0x0b];
+        #[rustfmt::skip]
+        let apply = [
+            0,
+            // the end of the frame, ptr + width * height * 4, goes in width
+            0x20, 0, 0x20, 1, 0x20, 2, 0x6c, 0x41, 4, 0x6c, 0x6a, 0x21, 1,
+            0x02, 0x40, 0x03, 0x40,
+            // until ptr reaches the end
+            0x20, 0, 0x20, 1, 0x4f, 0x0d, 1,
+            // *ptr = 255 - *ptr
+            0x20, 0, 0x41, 0xff, 0x01, 0x20, 0, 0x2d, 0, 0, 0x6b, 0x3a, 0, 0,
+            // ptr += 1
+            0x20, 0, 0x41, 1, 0x6a, 0x21, 0,
+            0x0c, 0, 0x0b, 0x0b,
+            0x41, 0, 0x0b,
+        ];
+        let mut code = vec![3];
+        for body in [&abi[..], alloc, &apply] {
+            code.push(body.len() as u8);
+            code.extend(body);
+        }
+        section(&mut wasm, 10, &code);
+        wasm
+    }
+
+    #[test]
+    fn should_apply_effects_in_rgba() {
+        let effect = Effect::new("invert", &invert(1, &[0, 0x41, 8, 0x0b])).unwrap();
+        let mut img = [10, 20, 30, 255, 0, 0, 255, 255];
+        effect.apply(&mut img, (2, 1)).unwrap();
+        assert_eq!(img, [245, 235, 225, 255, 255, 255, 0, 255]);
+    }
+
+    #[test]
+    fn should_refuse_effects_escaping_their_memory() {
+        // alloc returns the last byte of the memory, so the frame can't fit
+        let effect = Effect::new("escape", &invert(1, &[0, 0x41, 0xff, 0xff, 0x03, 0x0b])).unwrap();
+        let mut img = [0; 8];
+        assert!(effect.apply(&mut img, (2, 1)).is_err());
+        assert_eq!(img, [0; 8]);
+    }
+
+    #[test]
+    fn should_refuse_modules_without_the_interface() {
+        assert!(matches!(
+            Effect::new("future", &invert(2, &[0, 0x41, 0, 0x0b])),
+            Err(Error::Unsupported(_))
+        ));
+        assert!(matches!(
+            Effect::new("garbage", b"\0asm\x01\0\0\0\x01"),
+            Err(Error::InvalidRequest(_))
+        ));
+        // alloc returns an i64, which is caught before anything runs
+        assert!(matches!(
+            Effect::new("ill-typed", &invert(1, &[0, 0x42, 8, 0x0b])),
+            Err(Error::InvalidRequest(_))
+        ));
+    }
+}
@@ -0,0 +1,1800 @@
+//! Just enough of WebAssembly to run effects: a small interpreter, instead of pulling in a whole
+//! runtime. It implements the MVP instruction set, plus the sign extension, saturating float to int
+//! and bulk memory instructions compilers emit by default, but not SIMD, threads or reference
+//! types. Modules can't import anything, so all they can touch is their own memory. They are
+//! validated upfront, like the spec says: function bodies are type checked as they are decoded, so
+//! broken modules are refused before any of their code runs. The interpreter still checks what it
+//! does as it runs, so the worst a module can do is trap, or run out of time
+use std::{collections::HashMap, time::Instant};
+
+const PAGE: usize = 64 * 1024;
+/// 256MiB, enough for a few copies of an 8K frame
+const MAX_PAGES: u32 = 4096;
+const MAX_CALL_DEPTH: usize = 1024;
+/// In values, across every function being called
+const MAX_STACK: usize = 1024 * 1024;
+const MAX_LOCALS: usize = 64 * 1024;
+const MAX_TABLE: u32 = 64 * 1024;
+/// How many instructions run between looking at the clock
+const FUEL: u32 = 1024 * 1024;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum ValType {
+    I32,
+    I64,
+    F32,
+    F64,
+}
+
+#[derive(PartialEq, Eq, Debug)]
+pub struct FuncType {
+    pub params: Vec<ValType>,
+    pub results: Vec<ValType>,
+}
+
+struct Func {
+    ty: usize,
+    /// How many locals there are besides the parameters
+    locals: usize,
+    code: Vec<Op>,
+}
+
+struct Global {
+    ty: ValType,
+    mutable: bool,
+    /// The initial value
+    value: u64,
+}
+
+enum Export {
+    Func(usize),
+    Other,
+}
+
+/// Instructions, decoded, with the targets of the structured ones worked out. Values are untyped:
+/// every value is a `u64`, with i32s and f32s in the low 32 bits
+enum Op {
+    Unreachable,
+    Block {
+        params: usize,
+        results: usize,
+        end: usize,
+    },
+    Loop {
+        params: usize,
+    },
+    If {
+        params: usize,
+        results: usize,
+        /// Where to go when the condition is false: past the `Else`, or to the `End`
+        skip: usize,
+        end: usize,
+    },
+    /// Reached at the end of the `then` branch
+    Else {
+        end: usize,
+    },
+    End,
+    Br(u32),
+    BrIf(u32),
+    /// The last depth is the default
+    BrTable(Box<[u32]>),
+    Return,
+    Call(usize),
+    CallIndirect(usize),
+    Drop,
+    Select,
+    LocalGet(usize),
+    LocalSet(usize),
+    LocalTee(usize),
+    GlobalGet(usize),
+    GlobalSet(usize),
+    Load {
+        opcode: u8,
+        offset: u32,
+    },
+    Store {
+        opcode: u8,
+        offset: u32,
+    },
+    MemorySize,
+    MemoryGrow,
+    MemoryCopy,
+    MemoryFill,
+    Const(u64),
+    /// Every numeric instruction, from `i32.eqz` (0x45) to `i64.extend32_s` (0xc4)
+    Numeric(u8),
+    /// The saturating float to int conversions, 0xfc 0 to 7
+    TruncSat(u8),
+}
+
+pub struct Module {
+    types: Vec<FuncType>,
+    funcs: Vec<Func>,
+    table: Option<Vec<Option<usize>>>,
+    /// The minimum and maximum number of pages
+    memory: Option<(u32, u32)>,
+    globals: Vec<Global>,
+    exports: HashMap<String, Export>,
+    /// Active segments, with their offsets
+    data: Vec<(usize, Vec<u8>)>,
+    start: Option<usize>,
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn new(bytes: &'a [u8]) -> Self {
+        Self { bytes, pos: 0 }
+    }
+
+    fn done(&self) -> bool {
+        self.pos == self.bytes.len()
+    }
+
+    fn byte(&mut self) -> Result<u8, String> {
+        let byte = *self.bytes.get(self.pos).ok_or("unexpected end of module")?;
+        self.pos += 1;
+        Ok(byte)
+    }
+
+    fn bytes(&mut self, len: usize) -> Result<&'a [u8], String> {
+        let bytes = self
+            .pos
+            .checked_add(len)
+            .and_then(|end| self.bytes.get(self.pos..end))
+            .ok_or("unexpected end of module")?;
+        self.pos += len;
+        Ok(bytes)
+    }
+
+    /// LEB128, which linkers like to pad to its maximum length
+    fn leb(&mut self, bits: u32, signed: bool) -> Result<u64, String> {
+        let mut value = 0;
+        let mut shift = 0;
+        loop {
+            if shift >= bits.div_ceil(7) * 7 {
+                return Err("integer representation too long".to_string());
+            }
+            let byte = self.byte()?;
+            if shift < 64 {
+                value |= u64::from(byte & 0x7f) << shift;
+            }
+            shift += 7;
+            if byte & 0x80 == 0 {
+                if signed && shift < 64 && byte & 0x40 != 0 {
+                    value |= u64::MAX << shift;
+                }
+                return Ok(value);
+            }
+        }
+    }
+
+    fn u32(&mut self) -> Result<u32, String> {
+        Ok(self.leb(32, false)? as u32)
+    }
+
+    fn index(&mut self) -> Result<usize, String> {
+        Ok(self.u32()? as usize)
+    }
+
+    fn zero(&mut self) -> Result<(), String> {
+        match self.u32()? {
+            0 => Ok(()),
+            _ => Err("only one memory and one table are supported".to_string()),
+        }
+    }
+
+    fn name(&mut self) -> Result<String, String> {
+        let len = self.index()?;
+        String::from_utf8(self.bytes(len)?.to_vec()).map_err(|_| "invalid name".to_string())
+    }
+
+    fn val_type(&mut self) -> Result<ValType, String> {
+        match self.byte()? {
+            0x7f => Ok(ValType::I32),
+            0x7e => Ok(ValType::I64),
+            0x7d => Ok(ValType::F32),
+            0x7c => Ok(ValType::F64),
+            ty => Err(format!("unsupported value type 0x{ty:02x}")),
+        }
+    }
+
+    fn val_types(&mut self) -> Result<Vec<ValType>, String> {
+        (0..self.u32()?).map(|_| self.val_type()).collect()
+    }
+
+    /// The minimum and maximum of a table or memory
+    fn limits(&mut self) -> Result<(u32, Option<u32>), String> {
+        let (min, max) = match self.byte()? {
+            0 => (self.u32()?, None),
+            1 => (self.u32()?, Some(self.u32()?)),
+            _ => return Err("only 32 bit, unshared tables and memories are supported".to_string()),
+        };
+        if max.is_some_and(|max| max < min) {
+            return Err("size minimum must not be greater than maximum".to_string());
+        }
+        Ok((min, max))
+    }
+
+    /// A constant expression, as used for the initial values of globals and segment offsets
+    fn constant(&mut self, globals: &[Global]) -> Result<(ValType, u64), String> {
+        let constant = match self.byte()? {
+            0x41 => (ValType::I32, self.leb(32, true)? as u32 as u64),
+            0x42 => (ValType::I64, self.leb(64, true)?),
+            0x43 => (
+                ValType::F32,
+                u32::from_le_bytes(self.bytes(4)?.try_into().unwrap()) as u64,
+            ),
+            0x44 => (
+                ValType::F64,
+                u64::from_le_bytes(self.bytes(8)?.try_into().unwrap()),
+            ),
+            0x23 => match globals.get(self.index()?) {
+                Some(global) if !global.mutable => (global.ty, global.value),
+                Some(_) => return Err("constant expressions can't read mutable globals".into()),
+                None => return Err("unknown global".to_string()),
+            },
+            _ => return Err("unsupported constant expression".to_string()),
+        };
+        match self.byte()? {
+            0x0b => Ok(constant),
+            _ => Err("unsupported constant expression".to_string()),
+        }
+    }
+
+    /// Where a segment goes in the table or memory
+    fn offset(&mut self, globals: &[Global]) -> Result<usize, String> {
+        match self.constant(globals)? {
+            (ValType::I32, offset) => Ok(offset as u32 as usize),
+            _ => Err("type mismatch: offsets are i32s".to_string()),
+        }
+    }
+}
+
+impl Module {
+    pub fn parse(bytes: &[u8]) -> Result<Self, String> {
+        let mut r = Reader::new(bytes);
+        if r.bytes(8).ok() != Some(b"\0asm\x01\0\0\0") {
+            return Err("not a WebAssembly module".to_string());
+        }
+        let mut module = Self {
+            types: Vec::new(),
+            funcs: Vec::new(),
+            table: None,
+            memory: None,
+            globals: Vec::new(),
+            exports: HashMap::new(),
+            data: Vec::new(),
+            start: None,
+        };
+        let mut func_types = Vec::new();
+        // the sections must come in this order, each at most once, but for custom ones
+        let order = [1, 2, 3, 4, 5, 6, 7, 8, 9, 12, 10, 11];
+        let mut last = None;
+        while !r.done() {
+            let id = r.byte()?;
+            let len = r.index()?;
+            let mut s = Reader::new(r.bytes(len)?);
+            if id != 0 {
+                let position = order.iter().position(|&section| section == id);
+                if position.is_some() && position <= last {
+                    return Err(format!("section {id} is out of order, or repeated"));
+                }
+                last = position;
+            }
+            match id {
+                // custom sections, and the data count
+                0 | 12 => continue,
+                1 => {
+                    for _ in 0..s.u32()? {
+                        if s.byte()? != 0x60 {
+                            return Err("invalid function type".to_string());
+                        }
+                        let params = s.val_types()?;
+                        let results = s.val_types()?;
+                        module.types.push(FuncType { params, results });
+                    }
+                }
+                2 => {
+                    if s.u32()? != 0 {
+                        return Err("effects can't import anything".to_string());
+                    }
+                }
+                3 => {
+                    for _ in 0..s.u32()? {
+                        let ty = s.index()?;
+                        if ty >= module.types.len() {
+                            return Err("unknown function type".to_string());
+                        }
+                        func_types.push(ty);
+                    }
+                }
+                4 => {
+                    if s.u32()? > 1 || s.byte()? != 0x70 {
+                        return Err("only one table of functions is supported".to_string());
+                    }
+                    let (min, _) = s.limits()?;
+                    if min > MAX_TABLE {
+                        return Err("the table is too big".to_string());
+                    }
+                    module.table = Some(vec![None; min as usize]);
+                }
+                5 => {
+                    if s.u32()? > 1 {
+                        return Err("only one memory is supported".to_string());
+                    }
+                    let (min, max) = s.limits()?;
+                    if min > MAX_PAGES {
+                        return Err(format!(
+                            "the module needs {min} pages of memory, but can't have more than \
+                            {MAX_PAGES}"
+                        ));
+                    }
+                    module.memory = Some((min, max.unwrap_or(MAX_PAGES).min(MAX_PAGES)));
+                }
+                6 => {
+                    for _ in 0..s.u32()? {
+                        let ty = s.val_type()?;
+                        let mutable = match s.byte()? {
+                            0 => false,
+                            1 => true,
+                            _ => return Err("invalid global mutability".to_string()),
+                        };
+                        let (init, value) = s.constant(&module.globals)?;
+                        if init != ty {
+                            return Err(format!(
+                                "type mismatch: a global of {ty:?} set to a {init:?}"
+                            ));
+                        }
+                        module.globals.push(Global { ty, mutable, value });
+                    }
+                }
+                7 => {
+                    for _ in 0..s.u32()? {
+                        let name = s.name()?;
+                        let export = match s.byte()? {
+                            0 => Export::Func(s.index()?),
+                            1..=3 => {
+                                s.u32()?;
+                                Export::Other
+                            }
+                            _ => return Err("invalid export".to_string()),
+                        };
+                        if module.exports.insert(name, export).is_some() {
+                            return Err("duplicate export name".to_string());
+                        }
+                    }
+                }
+                8 => module.start = Some(s.index()?),
+                9 => {
+                    for _ in 0..s.u32()? {
+                        let offset = match s.u32()? {
+                            0 => s.offset(&module.globals)?,
+                            2 => {
+                                s.zero()?;
+                                let offset = s.offset(&module.globals)?;
+                                if s.byte()? != 0 {
+                                    return Err("unsupported element kind".to_string());
+                                }
+                                offset
+                            }
+                            _ => return Err("only active function elements are supported".into()),
+                        };
+                        let funcs = (0..s.u32()?)
+                            .map(|_| s.index())
+                            .collect::<Result<Vec<_>, _>>()?;
+                        let table = module.table.as_mut().ok_or("unknown table")?;
+                        let table = offset
+                            .checked_add(funcs.len())
+                            .and_then(|end| table.get_mut(offset..end))
+                            .ok_or("element segment out of bounds")?;
+                        for (slot, func) in table.iter_mut().zip(funcs) {
+                            *slot = Some(func);
+                        }
+                    }
+                }
+                10 => {
+                    let count = s.index()?;
+                    if count != func_types.len() {
+                        return Err("function and code sections don't match".to_string());
+                    }
+                    for &ty in &func_types {
+                        let len = s.index()?;
+                        let mut body = Reader::new(s.bytes(len)?);
+                        let func = parse_body(&mut body, ty, &module, &func_types)?;
+                        module.funcs.push(func);
+                    }
+                }
+                11 => {
+                    for _ in 0..s.u32()? {
+                        let offset = match s.u32()? {
+                            0 => Some(s.offset(&module.globals)?),
+                            1 => None,
+                            2 => {
+                                s.zero()?;
+                                Some(s.offset(&module.globals)?)
+                            }
+                            _ => return Err("invalid data segment".to_string()),
+                        };
+                        if offset.is_some() && module.memory.is_none() {
+                            return Err("unknown memory".to_string());
+                        }
+                        let len = s.index()?;
+                        let bytes = s.bytes(len)?.to_vec();
+                        // passive segments are only used by `memory.init`, which we don't support
+                        if let Some(offset) = offset {
+                            module.data.push((offset, bytes));
+                        }
+                    }
+                }
+                _ => return Err(format!("unknown section {id}")),
+            }
+            if !s.done() {
+                return Err(format!("section {id} is longer than its contents"));
+            }
+        }
+        if module.funcs.len() != func_types.len() {
+            return Err("function and code sections don't match".to_string());
+        }
+        let funcs = module.funcs.len();
+        let exported = module.exports.values().filter_map(|export| match export {
+            Export::Func(func) => Some(*func),
+            _ => None,
+        });
+        let tabled = module.table.iter().flatten().flatten().copied();
+        if exported
+            .chain(tabled)
+            .chain(module.start)
+            .any(|func| func >= funcs)
+        {
+            return Err("unknown function".to_string());
+        }
+        if module.start.is_some_and(|start| {
+            module.types[module.funcs[start].ty]
+                != FuncType {
+                    params: Vec::new(),
+                    results: Vec::new(),
+                }
+        }) {
+            return Err("the start function can't take or return anything".to_string());
+        }
+        Ok(module)
+    }
+
+    /// The type of the function exported as `name`
+    pub fn export_type(&self, name: &str) -> Option<&FuncType> {
+        match self.exports.get(name)? {
+            Export::Func(func) => Some(&self.types[self.funcs[*func].ty]),
+            _ => None,
+        }
+    }
+}
+
+/// `(params, results)` of a block
+fn block_type(r: &mut Reader, types: &[FuncType]) -> Result<(Vec<ValType>, Vec<ValType>), String> {
+    match r.leb(33, true)? as i64 {
+        // empty
+        -0x40 => Ok((Vec::new(), Vec::new())),
+        // a single value type, encoded like in `Reader::val_type`
+        ty @ -0x04..=-0x01 => {
+            let ty = Reader::new(&[(ty & 0x7f) as u8]).val_type()?;
+            Ok((Vec::new(), vec![ty]))
+        }
+        ty => match usize::try_from(ty).ok().and_then(|ty| types.get(ty)) {
+            Some(ty) => Ok((ty.params.clone(), ty.results.clone())),
+            None => Err("invalid block type".to_string()),
+        },
+    }
+}
+
+/// The type of the value loaded or stored by a memory instruction, and how many bytes it accesses
+fn access(opcode: u8) -> (ValType, usize) {
+    match opcode {
+        0x28 | 0x36 => (ValType::I32, 4),
+        0x29 | 0x37 => (ValType::I64, 8),
+        0x2a | 0x38 => (ValType::F32, 4),
+        0x2b | 0x39 => (ValType::F64, 8),
+        0x2c | 0x2d | 0x3a => (ValType::I32, 1),
+        0x2e | 0x2f | 0x3b => (ValType::I32, 2),
+        0x30 | 0x31 | 0x3c => (ValType::I64, 1),
+        0x32 | 0x33 | 0x3d => (ValType::I64, 2),
+        _ => (ValType::I64, 4),
+    }
+}
+
+/// `(params, result)` of the numeric instruction `opcode`, from 0x45 to 0xc4
+fn numeric_type(opcode: u8) -> (&'static [ValType], ValType) {
+    use ValType as T;
+
+    match opcode {
+        0x45 | 0x67..=0x69 | 0xc0 | 0xc1 => (&[T::I32], T::I32),
+        0x46..=0x4f => (&[T::I32, T::I32], T::I32),
+        0x50 | 0xa7 => (&[T::I64], T::I32),
+        0x51..=0x5a => (&[T::I64, T::I64], T::I32),
+        0x5b..=0x60 => (&[T::F32, T::F32], T::I32),
+        0x61..=0x66 => (&[T::F64, T::F64], T::I32),
+        0x6a..=0x78 => (&[T::I32, T::I32], T::I32),
+        0x79..=0x7b | 0xc2..=0xc4 => (&[T::I64], T::I64),
+        0x7c..=0x8a => (&[T::I64, T::I64], T::I64),
+        0x8b..=0x91 => (&[T::F32], T::F32),
+        0x92..=0x98 => (&[T::F32, T::F32], T::F32),
+        0x99..=0x9f => (&[T::F64], T::F64),
+        0xa0..=0xa6 => (&[T::F64, T::F64], T::F64),
+        0xa8 | 0xa9 | 0xbc => (&[T::F32], T::I32),
+        0xaa | 0xab => (&[T::F64], T::I32),
+        0xac | 0xad => (&[T::I32], T::I64),
+        0xae | 0xaf => (&[T::F32], T::I64),
+        0xb0 | 0xb1 | 0xbd => (&[T::F64], T::I64),
+        0xb2 | 0xb3 | 0xbe => (&[T::I32], T::F32),
+        0xb4 | 0xb5 => (&[T::I64], T::F32),
+        0xb6 => (&[T::F64], T::F32),
+        0xb7 | 0xb8 => (&[T::I32], T::F64),
+        0xb9 | 0xba | 0xbf => (&[T::I64], T::F64),
+        _ => (&[T::F32], T::F64),
+    }
+}
+
+/// A block being validated
+struct Ctrl {
+    /// Where the block starts in the code, unless it is the function itself
+    start: Option<usize>,
+    /// Where the `else` of an if is, once we get there
+    else_: Option<usize>,
+    params: Vec<ValType>,
+    results: Vec<ValType>,
+    /// The height of the operand stack when the block started, without its parameters
+    height: usize,
+    /// Set after instructions that never go on, like `br`. The stack is polymorphic past them
+    unreachable: bool,
+    is_loop: bool,
+}
+
+impl Ctrl {
+    /// The values branching to the block carries
+    fn label(&self) -> &[ValType] {
+        if self.is_loop {
+            &self.params
+        } else {
+            &self.results
+        }
+    }
+}
+
+/// Type checks a function body as it is decoded, with the algorithm from the appendix of the spec.
+/// Values of unknown type, which unreachable code can pop, are `None`
+struct Validator {
+    vals: Vec<Option<ValType>>,
+    ctrls: Vec<Ctrl>,
+}
+
+impl Validator {
+    fn push(&mut self, ty: ValType) {
+        self.vals.push(Some(ty));
+    }
+
+    fn push_all(&mut self, types: &[ValType]) {
+        self.vals.extend(types.iter().copied().map(Some));
+    }
+
+    fn pop(&mut self, expected: Option<ValType>) -> Result<Option<ValType>, String> {
+        let ctrl = self
+            .ctrls
+            .last()
+            .ok_or("code after the end of a function")?;
+        if self.vals.len() == ctrl.height {
+            return match ctrl.unreachable {
+                true => Ok(expected),
+                false => Err("type mismatch: not enough values on the stack".to_string()),
+            };
+        }
+        match (self.vals.pop().flatten(), expected) {
+            (Some(actual), Some(expected)) if actual != expected => Err(format!(
+                "type mismatch: expected {expected:?}, found {actual:?}"
+            )),
+            (actual, expected) => Ok(actual.or(expected)),
+        }
+    }
+
+    fn pop_all(&mut self, types: &[ValType]) -> Result<(), String> {
+        for ty in types.iter().rev() {
+            self.pop(Some(*ty))?;
+        }
+        Ok(())
+    }
+
+    /// Starts a block whose parameters were already popped
+    fn push_ctrl(
+        &mut self,
+        start: usize,
+        else_: Option<usize>,
+        (params, results): (Vec<ValType>, Vec<ValType>),
+        is_loop: bool,
+    ) {
+        self.push_all(&params);
+        self.ctrls.push(Ctrl {
+            start: Some(start),
+            else_,
+            params,
+            results,
+            height: self.vals.len(),
+            unreachable: false,
+            is_loop,
+        });
+        let ctrl = self.ctrls.last_mut().unwrap();
+        ctrl.height -= ctrl.params.len();
+    }
+
+    /// Ends the innermost block, which must leave exactly its results
+    fn pop_ctrl(&mut self) -> Result<Ctrl, String> {
+        let results = self.ctrls.last().ok_or("unexpected end")?.results.clone();
+        self.pop_all(&results)?;
+        let ctrl = self.ctrls.pop().unwrap();
+        if self.vals.len() != ctrl.height {
+            return Err("type mismatch: values left on the stack at the end of a block".into());
+        }
+        Ok(ctrl)
+    }
+
+    /// What branching `depth` blocks out carries
+    fn label(&self, depth: u32) -> Result<Vec<ValType>, String> {
+        match self.ctrls.len().checked_sub(depth as usize + 1) {
+            Some(i) => Ok(self.ctrls[i].label().to_vec()),
+            None => Err("unknown label".to_string()),
+        }
+    }
+
+    /// Marks the rest of the block as unreachable
+    fn unreachable(&mut self) {
+        let ctrl = self.ctrls.last_mut().unwrap();
+        self.vals.truncate(ctrl.height);
+        ctrl.unreachable = true;
+    }
+}
+
+fn parse_body(
+    r: &mut Reader,
+    ty: usize,
+    module: &Module,
+    func_types: &[usize],
+) -> Result<Func, String> {
+    let func_type = &module.types[ty];
+    let mut local_types = func_type.params.clone();
+    let mut locals: usize = 0;
+    for _ in 0..r.u32()? {
+        let count = r.index()?;
+        locals = locals
+            .checked_add(count)
+            .filter(|locals| *locals <= MAX_LOCALS)
+            .ok_or("too many locals")?;
+        let ty = r.val_type()?;
+        local_types.extend(std::iter::repeat_n(ty, count));
+    }
+    let local = |r: &mut Reader| {
+        let local = r.index()?;
+        match local_types.get(local) {
+            Some(ty) => Ok((local, *ty)),
+            None => Err("unknown local".to_string()),
+        }
+    };
+    let global = |r: &mut Reader| {
+        let global = r.index()?;
+        match module.globals.get(global) {
+            Some(ty) => Ok((global, ty)),
+            None => Err("unknown global".to_string()),
+        }
+    };
+    let memory = || match module.memory {
+        Some(_) => Ok(()),
+        None => Err("unknown memory".to_string()),
+    };
+
+    let mut code = Vec::new();
+    let mut v = Validator {
+        vals: Vec::new(),
+        ctrls: vec![Ctrl {
+            start: None,
+            else_: None,
+            params: Vec::new(),
+            results: func_type.results.clone(),
+            height: 0,
+            unreachable: false,
+            is_loop: false,
+        }],
+    };
+    loop {
+        let opcode = r.byte()?;
+        let op = match opcode {
+            0x00 => {
+                v.unreachable();
+                Op::Unreachable
+            }
+            0x01 => continue,
+            0x02..=0x04 => {
+                let (params, results) = block_type(r, &module.types)?;
+                if opcode == 0x04 {
+                    v.pop(Some(ValType::I32))?;
+                }
+                v.pop_all(&params)?;
+                let op = match opcode {
+                    0x02 => Op::Block {
+                        params: params.len(),
+                        results: results.len(),
+                        end: 0,
+                    },
+                    0x03 => Op::Loop {
+                        params: params.len(),
+                    },
+                    _ => Op::If {
+                        params: params.len(),
+                        results: results.len(),
+                        skip: 0,
+                        end: 0,
+                    },
+                };
+                v.push_ctrl(code.len(), None, (params, results), opcode == 0x03);
+                op
+            }
+            0x05 => {
+                let in_if = v.ctrls.last().is_some_and(|ctrl| {
+                    ctrl.else_.is_none()
+                        && ctrl
+                            .start
+                            .is_some_and(|start| matches!(code[start], Op::If { .. }))
+                });
+                if !in_if {
+                    return Err("else outside of an if".to_string());
+                }
+                let ctrl = v.pop_ctrl()?;
+                let start = ctrl.start.unwrap();
+                v.push_ctrl(start, Some(code.len()), (ctrl.params, ctrl.results), false);
+                Op::Else { end: 0 }
+            }
+            0x0b => {
+                let ctrl = v.pop_ctrl()?;
+                let Some(start) = ctrl.start else {
+                    code.push(Op::End);
+                    break;
+                };
+                let end = code.len();
+                match &mut code[start] {
+                    Op::Block { end: target, .. } => *target = end,
+                    Op::If {
+                        skip, end: target, ..
+                    } => {
+                        // without an else, the parameters are what the if leaves
+                        if ctrl.else_.is_none() && ctrl.params != ctrl.results {
+                            return Err("type mismatch: an if without else changes types".into());
+                        }
+                        *skip = ctrl.else_.map_or(end, |else_| else_ + 1);
+                        *target = end;
+                    }
+                    _ => (),
+                }
+                if let Some(else_) = ctrl.else_ {
+                    code[else_] = Op::Else { end };
+                }
+                v.push_all(&ctrl.results);
+                Op::End
+            }
+            0x0c => {
+                let depth = r.u32()?;
+                v.pop_all(&v.label(depth)?)?;
+                v.unreachable();
+                Op::Br(depth)
+            }
+            0x0d => {
+                let depth = r.u32()?;
+                v.pop(Some(ValType::I32))?;
+                let label = v.label(depth)?;
+                v.pop_all(&label)?;
+                v.push_all(&label);
+                Op::BrIf(depth)
+            }
+            0x0e => {
+                let count = r.u32()?;
+                let depths: Box<[u32]> = (0..=count).map(|_| r.u32()).collect::<Result<_, _>>()?;
+                v.pop(Some(ValType::I32))?;
+                // the last depth is the default
+                let arity = v.label(*depths.last().unwrap())?.len();
+                for depth in &depths {
+                    let label = v.label(*depth)?;
+                    if label.len() != arity {
+                        return Err("type mismatch: br_table targets of different arity".into());
+                    }
+                    v.pop_all(&label)?;
+                    v.push_all(&label);
+                }
+                v.unreachable();
+                Op::BrTable(depths)
+            }
+            0x0f => {
+                v.pop_all(&func_type.results)?;
+                v.unreachable();
+                Op::Return
+            }
+            0x10 => {
+                let func = r.index()?;
+                let ty = func_types.get(func).ok_or("unknown function")?;
+                v.pop_all(&module.types[*ty].params)?;
+                v.push_all(&module.types[*ty].results);
+                Op::Call(func)
+            }
+            0x11 => {
+                let ty = r.index()?;
+                r.zero()?;
+                let Some(func_type) = module.types.get(ty) else {
+                    return Err("unknown function type".to_string());
+                };
+                if module.table.is_none() {
+                    return Err("unknown table".to_string());
+                }
+                v.pop(Some(ValType::I32))?;
+                v.pop_all(&func_type.params)?;
+                v.push_all(&func_type.results);
+                Op::CallIndirect(ty)
+            }
+            0x1a => {
+                v.pop(None)?;
+                Op::Drop
+            }
+            0x1b | 0x1c => {
+                let expected = match opcode {
+                    0x1b => None,
+                    _ => match r.val_types()?[..] {
+                        [ty] => Some(ty),
+                        _ => return Err("invalid result arity of select".to_string()),
+                    },
+                };
+                v.pop(Some(ValType::I32))?;
+                let second = v.pop(expected)?;
+                let first = v.pop(second.or(expected))?;
+                v.vals.push(first.or(second));
+                Op::Select
+            }
+            0x20 => {
+                let (local, ty) = local(r)?;
+                v.push(ty);
+                Op::LocalGet(local)
+            }
+            0x21 => {
+                let (local, ty) = local(r)?;
+                v.pop(Some(ty))?;
+                Op::LocalSet(local)
+            }
+            0x22 => {
+                let (local, ty) = local(r)?;
+                v.pop(Some(ty))?;
+                v.push(ty);
+                Op::LocalTee(local)
+            }
+            0x23 => {
+                let (global, Global { ty, .. }) = global(r)?;
+                v.push(*ty);
+                Op::GlobalGet(global)
+            }
+            0x24 => {
+                let (global, Global { ty, mutable, .. }) = global(r)?;
+                if !mutable {
+                    return Err("global is immutable".to_string());
+                }
+                v.pop(Some(*ty))?;
+                Op::GlobalSet(global)
+            }
+            0x28..=0x3e => {
+                memory()?;
+                let (ty, size) = access(opcode);
+                // alignment is only a hint, but it can't be larger than the value
+                match r.u32()? {
+                    align if align < usize::BITS && 1 << align <= size => (),
+                    _ => return Err("alignment must not be larger than natural".to_string()),
+                }
+                let offset = r.u32()?;
+                match opcode {
+                    0x28..=0x35 => {
+                        v.pop(Some(ValType::I32))?;
+                        v.push(ty);
+                        Op::Load { opcode, offset }
+                    }
+                    _ => {
+                        v.pop(Some(ty))?;
+                        v.pop(Some(ValType::I32))?;
+                        Op::Store { opcode, offset }
+                    }
+                }
+            }
+            0x3f => {
+                r.zero()?;
+                memory()?;
+                v.push(ValType::I32);
+                Op::MemorySize
+            }
+            0x40 => {
+                r.zero()?;
+                memory()?;
+                v.pop(Some(ValType::I32))?;
+                v.push(ValType::I32);
+                Op::MemoryGrow
+            }
+            0x41 => {
+                v.push(ValType::I32);
+                Op::Const(r.leb(32, true)? as u32 as u64)
+            }
+            0x42 => {
+                v.push(ValType::I64);
+                Op::Const(r.leb(64, true)?)
+            }
+            0x43 => {
+                v.push(ValType::F32);
+                Op::Const(u32::from_le_bytes(r.bytes(4)?.try_into().unwrap()) as u64)
+            }
+            0x44 => {
+                v.push(ValType::F64);
+                Op::Const(u64::from_le_bytes(r.bytes(8)?.try_into().unwrap()))
+            }
+            0x45..=0xc4 => {
+                let (params, result) = numeric_type(opcode);
+                v.pop_all(params)?;
+                v.push(result);
+                Op::Numeric(opcode)
+            }
+            0xfc => match r.u32()? {
+                sub @ 0..=7 => {
+                    let from = [ValType::F32, ValType::F64][sub as usize / 2 % 2];
+                    let to = [ValType::I32, ValType::I64][sub as usize / 4];
+                    v.pop(Some(from))?;
+                    v.push(to);
+                    Op::TruncSat(sub as u8)
+                }
+                sub @ (10 | 11) => {
+                    r.zero()?;
+                    if sub == 10 {
+                        r.zero()?;
+                    }
+                    memory()?;
+                    v.pop_all(&[ValType::I32; 3])?;
+                    match sub {
+                        10 => Op::MemoryCopy,
+                        _ => Op::MemoryFill,
+                    }
+                }
+                sub => return Err(format!("unsupported instruction 0xfc {sub}")),
+            },
+            _ => return Err(format!("unsupported instruction 0x{opcode:02x}")),
+        };
+        code.push(op);
+    }
+    if !r.done() {
+        return Err("code after the end of a function".to_string());
+    }
+    Ok(Func { ty, locals, code })
+}
+
+struct Frame {
+    func: usize,
+    pc: usize,
+    /// Where the locals start in the stack
+    base: usize,
+    /// Where the labels of this call start
+    labels: usize,
+}
+
+struct Label {
+    /// The height of the stack when the block started, without its parameters
+    height: usize,
+    /// How many values branching to the label carries
+    arity: usize,
+    /// Where branching to the label goes
+    target: usize,
+    /// Branching to a loop starts it again, so it keeps its label
+    is_loop: bool,
+}
+
+pub struct Instance<'m> {
+    module: &'m Module,
+    pub memory: Vec<u8>,
+    max_pages: u32,
+    globals: Vec<u64>,
+    deadline: Instant,
+}
+
+impl<'m> Instance<'m> {
+    /// Functions of the instance fail once they run past `deadline`
+    pub fn new(module: &'m Module, deadline: Instant) -> Result<Self, String> {
+        let (pages, max_pages) = module.memory.unwrap_or((0, 0));
+        let mut instance = Self {
+            module,
+            memory: vec![0; pages as usize * PAGE],
+            max_pages,
+            globals: module.globals.iter().map(|global| global.value).collect(),
+            deadline,
+        };
+        for (offset, bytes) in &module.data {
+            instance
+                .memory
+                .get_mut(*offset..offset + bytes.len())
+                .ok_or("data segment out of bounds")?
+                .copy_from_slice(bytes);
+        }
+        if let Some(start) = module.start {
+            instance.run(start, Vec::new())?;
+        }
+        Ok(instance)
+    }
+
+    /// Calls the function exported as `name`
+    pub fn call(&mut self, name: &str, args: &[u64]) -> Result<Vec<u64>, String> {
+        let Some(Export::Func(func)) = self.module.exports.get(name) else {
+            return Err(format!(
+                "the module doesn't export a function called {name}"
+            ));
+        };
+        let ty = &self.module.types[self.module.funcs[*func].ty];
+        if ty.params.len() != args.len() {
+            return Err(format!("{name} takes {} arguments", ty.params.len()));
+        }
+        self.run(*func, args.to_vec())
+    }
+
+    fn run(&mut self, func: usize, mut stack: Vec<u64>) -> Result<Vec<u64>, String> {
+        let module = self.module;
+        let mut frames: Vec<Frame> = Vec::new();
+        let mut labels: Vec<Label> = Vec::new();
+        let mut frame = enter(module, func, &mut stack, 0)?;
+        let mut fuel = FUEL;
+        loop {
+            fuel -= 1;
+            if fuel == 0 {
+                if Instant::now() > self.deadline {
+                    return Err("ran out of time".to_string());
+                }
+                fuel = FUEL;
+            }
+            let op = &module.funcs[frame.func].code[frame.pc];
+            frame.pc += 1;
+            let branch = match op {
+                Op::Unreachable => return Err("reached unreachable code".to_string()),
+                Op::Block {
+                    params,
+                    results,
+                    end,
+                } => {
+                    labels.push(Label {
+                        height: below(&stack, *params)?,
+                        arity: *results,
+                        target: end + 1,
+                        is_loop: false,
+                    });
+                    None
+                }
+                Op::Loop { params } => {
+                    labels.push(Label {
+                        height: below(&stack, *params)?,
+                        arity: *params,
+                        target: frame.pc,
+                        is_loop: true,
+                    });
+                    None
+                }
+                Op::If {
+                    params,
+                    results,
+                    skip,
+                    end,
+                } => {
+                    let condition = pop(&mut stack)? as u32;
+                    labels.push(Label {
+                        height: below(&stack, *params)?,
+                        arity: *results,
+                        target: end + 1,
+                        is_loop: false,
+                    });
+                    if condition == 0 {
+                        frame.pc = *skip;
+                    }
+                    None
+                }
+                Op::Else { end } => {
+                    labels.pop();
+                    frame.pc = end + 1;
+                    None
+                }
+                // the end of the function is a return, like branching to its (implicit) label
+                Op::End if labels.len() == frame.labels => Some(0),
+                Op::End => {
+                    labels.pop();
+                    None
+                }
+                Op::Br(depth) => Some(*depth),
+                Op::BrIf(depth) => (pop(&mut stack)? as u32 != 0).then_some(*depth),
+                Op::BrTable(depths) => {
+                    let i = pop(&mut stack)? as u32 as usize;
+                    depths.get(i).or(depths.last()).copied()
+                }
+                Op::Return => Some(labels.len().saturating_sub(frame.labels) as u32),
+                Op::Call(func) => {
+                    let callee = enter(module, *func, &mut stack, labels.len())?;
+                    if frames.len() == MAX_CALL_DEPTH {
+                        return Err("call stack exhausted".to_string());
+                    }
+                    frames.push(std::mem::replace(&mut frame, callee));
+                    None
+                }
+                Op::CallIndirect(ty) => {
+                    let i = pop(&mut stack)? as u32 as usize;
+                    let Some(&Some(func)) = module.table.as_ref().and_then(|table| table.get(i))
+                    else {
+                        return Err("undefined table element".to_string());
+                    };
+                    if module.types[module.funcs[func].ty] != module.types[*ty] {
+                        return Err("indirect call type mismatch".to_string());
+                    }
+                    let callee = enter(module, func, &mut stack, labels.len())?;
+                    if frames.len() == MAX_CALL_DEPTH {
+                        return Err("call stack exhausted".to_string());
+                    }
+                    frames.push(std::mem::replace(&mut frame, callee));
+                    None
+                }
+                Op::Drop => {
+                    pop(&mut stack)?;
+                    None
+                }
+                Op::Select => {
+                    let condition = pop(&mut stack)? as u32;
+                    let second = pop(&mut stack)?;
+                    let first = pop(&mut stack)?;
+                    stack.push(if condition != 0 { first } else { second });
+                    None
+                }
+                Op::LocalGet(local) => {
+                    let value = *stack.get(frame.base + local).ok_or(UNDERFLOW)?;
+                    stack.push(value);
+                    None
+                }
+                Op::LocalSet(local) => {
+                    let value = pop(&mut stack)?;
+                    *stack.get_mut(frame.base + local).ok_or(UNDERFLOW)? = value;
+                    None
+                }
+                Op::LocalTee(local) => {
+                    let value = *stack.last().ok_or(UNDERFLOW)?;
+                    *stack.get_mut(frame.base + local).ok_or(UNDERFLOW)? = value;
+                    None
+                }
+                Op::GlobalGet(global) => {
+                    stack.push(self.globals[*global]);
+                    None
+                }
+                Op::GlobalSet(global) => {
+                    self.globals[*global] = pop(&mut stack)?;
+                    None
+                }
+                Op::Load { opcode, offset } => {
+                    let (_, size) = access(*opcode);
+                    let address = self.address(pop(&mut stack)?, *offset, size)?;
+                    let mut bytes = [0; 8];
+                    bytes[..size].copy_from_slice(&self.memory[address..address + size]);
+                    let raw = u64::from_le_bytes(bytes);
+                    stack.push(match opcode {
+                        0x2c => raw as i8 as i32 as u32 as u64,
+                        0x2e => raw as i16 as i32 as u32 as u64,
+                        0x30 => raw as i8 as u64,
+                        0x32 => raw as i16 as u64,
+                        0x34 => raw as i32 as u64,
+                        _ => raw,
+                    });
+                    None
+                }
+                Op::Store { opcode, offset } => {
+                    let (_, size) = access(*opcode);
+                    let value = pop(&mut stack)?;
+                    let address = self.address(pop(&mut stack)?, *offset, size)?;
+                    self.memory[address..address + size]
+                        .copy_from_slice(&value.to_le_bytes()[..size]);
+                    None
+                }
+                Op::MemorySize => {
+                    stack.push((self.memory.len() / PAGE) as u64);
+                    None
+                }
+                Op::MemoryGrow => {
+                    let pages = self.memory.len() / PAGE;
+                    let grow = pop(&mut stack)? as u32 as usize;
+                    if pages + grow > self.max_pages as usize {
+                        stack.push(u32::MAX as u64);
+                    } else {
+                        self.memory.resize((pages + grow) * PAGE, 0);
+                        stack.push(pages as u64);
+                    }
+                    None
+                }
+                Op::MemoryCopy => {
+                    let len = pop(&mut stack)? as u32 as usize;
+                    let src = self.address(pop(&mut stack)?, 0, len)?;
+                    let dst = self.address(pop(&mut stack)?, 0, len)?;
+                    self.memory.copy_within(src..src + len, dst);
+                    None
+                }
+                Op::MemoryFill => {
+                    let len = pop(&mut stack)? as u32 as usize;
+                    let value = pop(&mut stack)? as u8;
+                    let dst = self.address(pop(&mut stack)?, 0, len)?;
+                    self.memory[dst..dst + len].fill(value);
+                    None
+                }
+                Op::Const(value) => {
+                    stack.push(*value);
+                    None
+                }
+                Op::Numeric(opcode) => {
+                    numeric(*opcode, &mut stack)?;
+                    None
+                }
+                Op::TruncSat(sub) => {
+                    let value = pop(&mut stack)?;
+                    let (f, d) = (f32::from_bits(value as u32), f64::from_bits(value));
+                    // `as` saturates, and turns NaNs into 0, just like these instructions
+                    stack.push(match sub {
+                        0 => f as i32 as u32 as u64,
+                        1 => f as u32 as u64,
+                        2 => d as i32 as u32 as u64,
+                        3 => d as u32 as u64,
+                        4 => f as i64 as u64,
+                        5 => f as u64,
+                        6 => d as i64 as u64,
+                        _ => d as u64,
+                    });
+                    None
+                }
+            };
+
+            let Some(depth) = branch else {
+                continue;
+            };
+            let depth = depth as usize;
+            if depth < labels.len().saturating_sub(frame.labels) {
+                let i = labels.len() - 1 - depth;
+                let label = &labels[i];
+                keep(&mut stack, label.height, label.arity)?;
+                frame.pc = label.target;
+                labels.truncate(if label.is_loop { i + 1 } else { i });
+                continue;
+            }
+            // returning
+            let results = module.types[module.funcs[frame.func].ty].results.len();
+            keep(&mut stack, frame.base, results)?;
+            labels.truncate(frame.labels);
+            match frames.pop() {
+                Some(caller) => frame = caller,
+                None => return Ok(stack),
+            }
+        }
+    }
+
+    /// Where an access of `size` bytes at `base + offset` starts, if it is in bounds
+    fn address(&self, base: u64, offset: u32, size: usize) -> Result<usize, String> {
+        let start = base as u32 as usize + offset as usize;
+        match start.checked_add(size) {
+            Some(end) if end <= self.memory.len() => Ok(start),
+            _ => Err("out of bounds memory access".to_string()),
+        }
+    }
+}
+
+const UNDERFLOW: &str = "stack underflow";
+
+fn pop(stack: &mut Vec<u64>) -> Result<u64, String> {
+    stack.pop().ok_or_else(|| UNDERFLOW.to_string())
+}
+
+/// The height of `stack` without its last `count` values
+fn below(stack: &[u64], count: usize) -> Result<usize, String> {
+    stack
+        .len()
+        .checked_sub(count)
+        .ok_or_else(|| UNDERFLOW.to_string())
+}
+
+/// Drops everything above `height` but the last `count` values
+fn keep(stack: &mut Vec<u64>, height: usize, count: usize) -> Result<(), String> {
+    let kept = below(stack, count)?;
+    if kept < height {
+        return Err(UNDERFLOW.to_string());
+    }
+    stack.drain(height..kept);
+    Ok(())
+}
+
+/// Starts a call to `func`, whose arguments are on top of `stack`
+fn enter(
+    module: &Module,
+    func: usize,
+    stack: &mut Vec<u64>,
+    labels: usize,
+) -> Result<Frame, String> {
+    let Func { ty, locals, .. } = module.funcs[func];
+    let base = below(stack, module.types[ty].params.len())?;
+    if stack.len() + locals > MAX_STACK {
+        return Err("stack exhausted".to_string());
+    }
+    stack.resize(stack.len() + locals, 0);
+    Ok(Frame {
+        func,
+        pc: 0,
+        base,
+        labels,
+    })
+}
+
+/// Like `f32::min`, but with NaNs winning, and -0 being less than 0
+macro_rules! min {
+    ($a:expr, $b:expr) => {{
+        let (a, b) = ($a, $b);
+        if a.is_nan() || b.is_nan() {
+            a + b
+        } else if a == b {
+            if a.is_sign_negative() {
+                a
+            } else {
+                b
+            }
+        } else {
+            a.min(b)
+        }
+    }};
+}
+
+macro_rules! max {
+    ($a:expr, $b:expr) => {{
+        let (a, b) = ($a, $b);
+        if a.is_nan() || b.is_nan() {
+            a + b
+        } else if a == b {
+            if a.is_sign_positive() {
+                a
+            } else {
+                b
+            }
+        } else {
+            a.max(b)
+        }
+    }};
+}
+
+/// Truncates `value` towards zero, failing unless the result is in `(lower, upper)`
+fn trunc(value: f64, lower: f64, upper: f64) -> Result<f64, String> {
+    if value.is_nan() {
+        return Err("invalid conversion to integer".to_string());
+    }
+    let value = value.trunc();
+    if value <= lower || value >= upper {
+        return Err("integer overflow".to_string());
+    }
+    Ok(value)
+}
+
+const I32: (f64, f64) = (-2147483649.0, 2147483648.0);
+const U32: (f64, f64) = (-1.0, 4294967296.0);
+/// The lower bound is the float right below `i64::MIN`
+const I64: (f64, f64) = (-9223372036854777856.0, 9223372036854775808.0);
+const U64: (f64, f64) = (-1.0, 18446744073709551616.0);
+
+fn numeric(opcode: u8, stack: &mut Vec<u64>) -> Result<(), String> {
+    let unary = matches!(
+        opcode,
+        0x45 | 0x50 | 0x67..=0x69 | 0x79..=0x7b | 0x8b..=0x91 | 0x99..=0x9f | 0xa7..=0xc4
+    );
+    let b = pop(stack)?;
+    let value = if unary {
+        unary_op(opcode, b)?
+    } else {
+        binary_op(opcode, pop(stack)?, b)?
+    };
+    stack.push(value);
+    Ok(())
+}
+
+fn unary_op(opcode: u8, x: u64) -> Result<u64, String> {
+    let (x32, f, d) = (x as u32, f32::from_bits(x as u32), f64::from_bits(x));
+    let from_f32 = |f: f32| f.to_bits() as u64;
+    Ok(match opcode {
+        0x45 => (x32 == 0) as u64,
+        0x50 => (x == 0) as u64,
+        0x67 => x32.leading_zeros() as u64,
+        0x68 => x32.trailing_zeros() as u64,
+        0x69 => x32.count_ones() as u64,
+        0x79 => x.leading_zeros() as u64,
+        0x7a => x.trailing_zeros() as u64,
+        0x7b => x.count_ones() as u64,
+        0x8b => from_f32(f.abs()),
+        0x8c => from_f32(-f),
+        0x8d => from_f32(f.ceil()),
+        0x8e => from_f32(f.floor()),
+        0x8f => from_f32(f.trunc()),
+        0x90 => from_f32(f.round_ties_even()),
+        0x91 => from_f32(f.sqrt()),
+        0x99 => d.abs().to_bits(),
+        0x9a => (-d).to_bits(),
+        0x9b => d.ceil().to_bits(),
+        0x9c => d.floor().to_bits(),
+        0x9d => d.trunc().to_bits(),
+        0x9e => d.round_ties_even().to_bits(),
+        0x9f => d.sqrt().to_bits(),
+        0xa7 => x32 as u64,
+        0xa8 => trunc(f as f64, I32.0, I32.1)? as i32 as u32 as u64,
+        0xa9 => trunc(f as f64, U32.0, U32.1)? as u32 as u64,
+        0xaa => trunc(d, I32.0, I32.1)? as i32 as u32 as u64,
+        0xab => trunc(d, U32.0, U32.1)? as u32 as u64,
+        0xac => x32 as i32 as i64 as u64,
+        0xad => x32 as u64,
+        0xae => trunc(f as f64, I64.0, I64.1)? as i64 as u64,
+        0xaf => trunc(f as f64, U64.0, U64.1)? as u64,
+        0xb0 => trunc(d, I64.0, I64.1)? as i64 as u64,
+        0xb1 => trunc(d, U64.0, U64.1)? as u64,
+        0xb2 => from_f32(x32 as i32 as f32),
+        0xb3 => from_f32(x32 as f32),
+        0xb4 => from_f32(x as i64 as f32),
+        0xb5 => from_f32(x as f32),
+        0xb6 => from_f32(d as f32),
+        0xb7 => (x32 as i32 as f64).to_bits(),
+        0xb8 => (x32 as f64).to_bits(),
+        0xb9 => (x as i64 as f64).to_bits(),
+        0xba => (x as f64).to_bits(),
+        0xbb => (f as f64).to_bits(),
+        // reinterpretations don't change the bits
+        0xbc | 0xbe => x32 as u64,
+        0xbd | 0xbf => x,
+        0xc0 => x as i8 as i32 as u32 as u64,
+        0xc1 => x as i16 as i32 as u32 as u64,
+        0xc2 => x as i8 as u64,
+        0xc3 => x as i16 as u64,
+        _ => x as i32 as u64,
+    })
+}
+
+fn binary_op(opcode: u8, a: u64, b: u64) -> Result<u64, String> {
+    let (a32, b32) = (a as u32, b as u32);
+    let (fa, fb) = (f32::from_bits(a32), f32::from_bits(b32));
+    let (da, db) = (f64::from_bits(a), f64::from_bits(b));
+    let from_f32 = |f: f32| f.to_bits() as u64;
+    let divide_by_zero = || Err("integer divide by zero".to_string());
+    Ok(match opcode {
+        0x46 => (a32 == b32) as u64,
+        0x47 => (a32 != b32) as u64,
+        0x48 => ((a32 as i32) < b32 as i32) as u64,
+        0x49 => (a32 < b32) as u64,
+        0x4a => (a32 as i32 > b32 as i32) as u64,
+        0x4b => (a32 > b32) as u64,
+        0x4c => (a32 as i32 <= b32 as i32) as u64,
+        0x4d => (a32 <= b32) as u64,
+        0x4e => (a32 as i32 >= b32 as i32) as u64,
+        0x4f => (a32 >= b32) as u64,
+        0x51 => (a == b) as u64,
+        0x52 => (a != b) as u64,
+        0x53 => ((a as i64) < b as i64) as u64,
+        0x54 => (a < b) as u64,
+        0x55 => (a as i64 > b as i64) as u64,
+        0x56 => (a > b) as u64,
+        0x57 => (a as i64 <= b as i64) as u64,
+        0x58 => (a <= b) as u64,
+        0x59 => (a as i64 >= b as i64) as u64,
+        0x5a => (a >= b) as u64,
+        0x5b => (fa == fb) as u64,
+        0x5c => (fa != fb) as u64,
+        0x5d => (fa < fb) as u64,
+        0x5e => (fa > fb) as u64,
+        0x5f => (fa <= fb) as u64,
+        0x60 => (fa >= fb) as u64,
+        0x61 => (da == db) as u64,
+        0x62 => (da != db) as u64,
+        0x63 => (da < db) as u64,
+        0x64 => (da > db) as u64,
+        0x65 => (da <= db) as u64,
+        0x66 => (da >= db) as u64,
+        0x6a => a32.wrapping_add(b32) as u64,
+        0x6b => a32.wrapping_sub(b32) as u64,
+        0x6c => a32.wrapping_mul(b32) as u64,
+        0x6d | 0x6f if b32 == 0 => return divide_by_zero(),
+        0x6d => match (a32 as i32).checked_div(b32 as i32) {
+            Some(quotient) => quotient as u32 as u64,
+            None => return Err("integer overflow".to_string()),
+        },
+        0x6f => (a32 as i32).wrapping_rem(b32 as i32) as u32 as u64,
+        0x6e | 0x70 if b32 == 0 => return divide_by_zero(),
+        0x6e => (a32 / b32) as u64,
+        0x70 => (a32 % b32) as u64,
+        0x71 => (a32 & b32) as u64,
+        0x72 => (a32 | b32) as u64,
+        0x73 => (a32 ^ b32) as u64,
+        0x74 => a32.wrapping_shl(b32) as u64,
+        0x75 => (a32 as i32).wrapping_shr(b32) as u32 as u64,
+        0x76 => a32.wrapping_shr(b32) as u64,
+        0x77 => a32.rotate_left(b32 % 32) as u64,
+        0x78 => a32.rotate_right(b32 % 32) as u64,
+        0x7c => a.wrapping_add(b),
+        0x7d => a.wrapping_sub(b),
+        0x7e => a.wrapping_mul(b),
+        0x7f | 0x81 if b == 0 => return divide_by_zero(),
+        0x7f => match (a as i64).checked_div(b as i64) {
+            Some(quotient) => quotient as u64,
+            None => return Err("integer overflow".to_string()),
+        },
+        0x81 => (a as i64).wrapping_rem(b as i64) as u64,
+        0x80 | 0x82 if b == 0 => return divide_by_zero(),
+        0x80 => a / b,
+        0x82 => a % b,
+        0x83 => a & b,
+        0x84 => a | b,
+        0x85 => a ^ b,
+        0x86 => a.wrapping_shl(b as u32),
+        0x87 => (a as i64).wrapping_shr(b as u32) as u64,
+        0x88 => a.wrapping_shr(b as u32),
+        0x89 => a.rotate_left((b % 64) as u32),
+        0x8a => a.rotate_right((b % 64) as u32),
+        0x92 => from_f32(fa + fb),
+        0x93 => from_f32(fa - fb),
+        0x94 => from_f32(fa * fb),
+        0x95 => from_f32(fa / fb),
+        0x96 => from_f32(min!(fa, fb)),
+        0x97 => from_f32(max!(fa, fb)),
+        0x98 => from_f32(fa.copysign(fb)),
+        0xa0 => (da + db).to_bits(),
+        0xa1 => (da - db).to_bits(),
+        0xa2 => (da * db).to_bits(),
+        0xa3 => (da / db).to_bits(),
+        0xa4 => min!(da, db).to_bits(),
+        0xa5 => max!(da, db).to_bits(),
+        _ => da.copysign(db).to_bits(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+
+    /// A module with a single function type, `pages` of memory, and one function per body,
+    /// exported as "f0", "f1", ...
+    fn module(params: &[u8], results: &[u8], pages: Option<u8>, bodies: &[&[u8]]) -> Vec<u8> {
+        let mut wasm = b"\0asm\x01\0\0\0".to_vec();
+        let mut section = |id: u8, contents: Vec<u8>| {
+            wasm.push(id);
+            wasm.push(contents.len() as u8);
+            wasm.extend(contents);
+        };
+        let mut ty = vec![1, 0x60, params.len() as u8];
+        ty.extend(params);
+        ty.push(results.len() as u8);
+        ty.extend(results);
+        section(1, ty);
+        let mut funcs = vec![bodies.len() as u8];
+        funcs.extend(bodies.iter().map(|_| 0));
+        section(3, funcs);
+        if let Some(pages) = pages {
+            section(5, vec![1, 0, pages]);
+        }
+        let mut exports = vec![bodies.len() as u8];
+        for i in 0..bodies.len() {
+            exports.extend([2, b'f', b'0' + i as u8, 0, i as u8]);
+        }
+        section(7, exports);
+        let mut code = vec![bodies.len() as u8];
+        for body in bodies {
+            code.push(body.len() as u8 + 1);
+            // no locals
+            code.push(0);
+            code.extend(*body);
+        }
+        section(10, code);
+        wasm
+    }
+
+    fn call(wasm: &[u8], func: &str, args: &[u64]) -> Result<Vec<u64>, String> {
+        let module = Module::parse(wasm)?;
+        let deadline = Instant::now() + Duration::from_secs(1);
+        Instance::new(&module, deadline)?.call(func, args)
+    }
+
+    const I32: u8 = 0x7f;
+
+    #[test]
+    fn should_run_arithmetic() {
+        // (a - b) * 3, a / b, and the popcount of a
+        let wasm = module(
+            &[I32, I32],
+            &[I32],
+            None,
+            &[
+                &[0x20, 0, 0x20, 1, 0x6b, 0x41, 3, 0x6c, 0x0b],
+                &[0x20, 0, 0x20, 1, 0x6d, 0x0b],
+                &[0x20, 0, 0x69, 0x0b],
+            ],
+        );
+        assert_eq!(call(&wasm, "f0", &[10, 4]).unwrap(), [18]);
+        assert_eq!(
+            call(&wasm, "f0", &[4, 10]).unwrap(),
+            [(-18i32) as u32 as u64]
+        );
+        assert_eq!(
+            call(&wasm, "f1", &[(-9i32) as u32 as u64, 2]).unwrap(),
+            [(-4i32) as u32 as u64]
+        );
+        assert_eq!(call(&wasm, "f2", &[0xff, 0]).unwrap(), [8]);
+        assert_eq!(
+            call(&wasm, "f1", &[1, 0]).unwrap_err(),
+            "integer divide by zero"
+        );
+        assert_eq!(
+            call(&wasm, "f1", &[i32::MIN as u32 as u64, u32::MAX as u64]).unwrap_err(),
+            "integer overflow"
+        );
+    }
+
+    #[test]
+    fn should_run_structured_control_flow() {
+        // the sum of 1 to n, in a loop, with the sum as the second parameter
+        let sum = [
+            0x03, 0x40, // loop
+            0x20, 0, 0x45, 0x04, 0x40, 0x20, 1, 0x0f, 0x0b, // if n == 0, return the sum
+            0x20, 1, 0x20, 0, 0x6a, 0x21, 1, // sum += n
+            0x20, 0, 0x41, 1, 0x6b, 0x21, 0, // n -= 1
+            0x0c, 0,    // br 0
+            0x0b, // end
+            0x00, // unreachable
+            0x0b,
+        ];
+        // n > 5 ? 100 : 200
+        let choose = [
+            0x20, 0, 0x41, 5, 0x4a, 0x04, I32, 0x41, 0xe4, 0, 0x05, 0x41, 0xc8, 1, 0x0b, 0x0b,
+        ];
+        let wasm = module(&[I32, I32], &[I32], None, &[&sum, &choose]);
+        assert_eq!(call(&wasm, "f0", &[10, 0]).unwrap(), [55]);
+        assert_eq!(call(&wasm, "f0", &[0, 7]).unwrap(), [7]);
+        assert_eq!(call(&wasm, "f1", &[6, 0]).unwrap(), [100]);
+        assert_eq!(call(&wasm, "f1", &[5, 0]).unwrap(), [200]);
+    }
+
+    #[test]
+    fn should_keep_effects_in_their_memory() {
+        // stores a byte at the address, and loads it back
+        let store = [0x20, 0, 0x20, 1, 0x3a, 0, 0, 0x20, 0, 0x2d, 0, 0, 0x0b];
+        let wasm = module(&[I32, I32], &[I32], Some(1), &[&store]);
+        assert_eq!(call(&wasm, "f0", &[100, 0x1ff]).unwrap(), [0xff]);
+        assert_eq!(call(&wasm, "f0", &[PAGE as u64 - 1, 7]).unwrap(), [7]);
+        assert_eq!(
+            call(&wasm, "f0", &[PAGE as u64, 7]).unwrap_err(),
+            "out of bounds memory access"
+        );
+        // memory.grow past the limit fails, instead of allocating
+        let grow = [0x20, 0, 0x40, 0, 0x0b];
+        let wasm = module(&[I32], &[I32], Some(1), &[&grow]);
+        assert_eq!(call(&wasm, "f0", &[1]).unwrap(), [1]);
+        assert_eq!(
+            call(&wasm, "f0", &[MAX_PAGES as u64]).unwrap(),
+            [u32::MAX as u64]
+        );
+    }
+
+    #[test]
+    fn should_stop_runaway_effects() {
+        let forever = [0x03, 0x40, 0x0c, 0, 0x0b, 0x0b];
+        let wasm = module(&[], &[], None, &[&forever]);
+        let parsed = Module::parse(&wasm).unwrap();
+        let deadline = Instant::now() + Duration::from_millis(10);
+        let mut instance = Instance::new(&parsed, deadline).unwrap();
+        assert_eq!(instance.call("f0", &[]).unwrap_err(), "ran out of time");
+
+        let recurse = [0x10, 0, 0x0b];
+        let wasm = module(&[], &[], None, &[&recurse]);
+        assert_eq!(call(&wasm, "f0", &[]).unwrap_err(), "call stack exhausted");
+    }
+
+    #[test]
+    fn should_refuse_imports() {
+        let mut wasm = b"\0asm\x01\0\0\0".to_vec();
+        // a function type, and an import of env.f with it
+        wasm.extend([1, 4, 1, 0x60, 0, 0]);
+        wasm.extend([2, 9, 1, 3, b'e', b'n', b'v', 1, b'f', 0, 0]);
+        assert_eq!(
+            Module::parse(&wasm).err().unwrap(),
+            "effects can't import anything"
+        );
+        assert!(Module::parse(b"\0asm\x02\0\0\0").is_err());
+        assert!(Module::parse(&wasm[..12]).is_err());
+    }
+
+    fn refused(wasm: &[u8]) -> String {
+        Module::parse(wasm).err().unwrap()
+    }
+
+    #[test]
+    fn should_refuse_ill_typed_functions() {
+        // i32.add of an i64
+        let wasm = module(&[], &[I32], None, &[&[0x41, 1, 0x42, 1, 0x6a, 0x0b]]);
+        assert_eq!(refused(&wasm), "type mismatch: expected I32, found I64");
+        // a value too many, and one too few
+        let wasm = module(&[], &[I32], None, &[&[0x41, 1, 0x41, 2, 0x0b]]);
+        assert!(refused(&wasm).contains("values left on the stack"));
+        let wasm = module(&[], &[I32], None, &[&[0x0b]]);
+        assert!(refused(&wasm).contains("not enough values"));
+        // select between an i32 and an i64
+        let select = [0x41, 1, 0x42, 1, 0x41, 0, 0x1b, 0x1a, 0x0b];
+        let wasm = module(&[], &[], None, &[&select]);
+        assert!(refused(&wasm).starts_with("type mismatch"));
+        // an if without else, which would leave nothing when the condition is false
+        let if_ = [0x41, 1, 0x04, I32, 0x41, 2, 0x0b, 0x0b];
+        let wasm = module(&[], &[I32], None, &[&if_]);
+        assert!(refused(&wasm).starts_with("type mismatch"));
+        // anything goes after unreachable code, as long as the types can match
+        let wasm = module(&[], &[I32], None, &[&[0x00, 0x6a, 0x0b]]);
+        assert!(Module::parse(&wasm).is_ok());
+        let wasm = module(&[], &[I32], None, &[&[0x00, 0x42, 1, 0x0b]]);
+        assert!(refused(&wasm).starts_with("type mismatch"));
+    }
+
+    #[test]
+    fn should_refuse_broken_control_flow() {
+        let wasm = module(&[], &[], None, &[&[0x0c, 1, 0x0b]]);
+        assert_eq!(refused(&wasm), "unknown label");
+        let wasm = module(&[], &[], None, &[&[0x05, 0x0b]]);
+        assert_eq!(refused(&wasm), "else outside of an if");
+        // a block without its end
+        let wasm = module(&[], &[], None, &[&[0x02, 0x40, 0x0b]]);
+        assert!(Module::parse(&wasm).is_err());
+        // a br_table to a block with a result and to the function, without any
+        let table = [0x02, I32, 0x41, 0, 0x41, 0, 0x0e, 1, 0, 1, 0x0b, 0x1a, 0x0b];
+        let wasm = module(&[], &[], None, &[&table]);
+        assert!(refused(&wasm).contains("br_table"));
+        let wasm = module(&[], &[], None, &[&[0x10, 1, 0x0b]]);
+        assert_eq!(refused(&wasm), "unknown function");
+    }
+
+    #[test]
+    fn should_refuse_invalid_memory_and_globals() {
+        let load = [0x41, 0, 0x28, 2, 0, 0x1a, 0x0b];
+        assert_eq!(refused(&module(&[], &[], None, &[&load])), "unknown memory");
+        assert!(Module::parse(&module(&[], &[], Some(1), &[&load])).is_ok());
+        // 16 byte alignment for 4 bytes
+        let load = [0x41, 0, 0x28, 4, 0, 0x1a, 0x0b];
+        assert!(refused(&module(&[], &[], Some(1), &[&load])).contains("alignment"));
+
+        // a global, and a function setting it
+        let global = |mutable: u8| {
+            let mut wasm = b"\0asm\x01\0\0\0".to_vec();
+            wasm.extend([1, 4, 1, 0x60, 0, 0]);
+            wasm.extend([3, 2, 1, 0]);
+            wasm.extend([6, 6, 1, I32, mutable, 0x41, 0, 0x0b]);
+            wasm.extend([10, 8, 1, 6, 0, 0x41, 1, 0x24, 0, 0x0b]);
+            wasm
+        };
+        assert!(Module::parse(&global(1)).is_ok());
+        assert_eq!(refused(&global(0)), "global is immutable");
+        let mut wasm = global(1);
+        // initialized with an i64
+        wasm[23] = 0x42;
+        assert!(refused(&wasm).starts_with("type mismatch"));
+    }
+
+    #[test]
+    fn should_refuse_malformed_sections() {
+        let wasm = module(&[], &[], None, &[&[0x0b]]);
+        assert!(Module::parse(&wasm).is_ok());
+        // cut in the middle of the code section, and of a section header
+        assert!(Module::parse(&wasm[..wasm.len() - 1]).is_err());
+        assert!(Module::parse(&wasm[..9]).is_err());
+        let mut repeated = wasm.clone();
+        repeated.extend([1, 1, 0]);
+        assert_eq!(refused(&repeated), "section 1 is out of order, or repeated");
+        let mut long = b"\0asm\x01\0\0\0".to_vec();
+        long.extend([1, 2, 0, 0]);
+        assert_eq!(refused(&long), "section 1 is longer than its contents");
+        let mut unknown = b"\0asm\x01\0\0\0".to_vec();
+        unknown.extend([13, 0]);
+        assert_eq!(refused(&unknown), "unknown section 13");
+    }
+}
@@ -0,0 +1,246 @@
+//! `--fifo`: simple line commands read from a named pipe, for environments (like some sandboxes)
+//! that can write to a pipe, but can't connect to a socket. Commands are sent to our own socket,
+//! just like `swww` would send them, so they wait in the queue like any other request
+use log::{debug, error};
+
+use std::{
+    ffi::CString,
+    fs::{self, OpenOptions},
+    io::{self, BufRead, BufReader},
+    os::unix::ffi::OsStrExt,
+    path::{Path, PathBuf},
+    thread,
+};
+
+use swww_protocol::{error::Error, Request};
+
+/// What a line can ask for
+#[derive(Debug, PartialEq)]
+enum Command {
+    Img {
+        path: PathBuf,
+        outputs: Vec<String>,
+    },
+    Clear {
+        color: [u8; 3],
+        outputs: Vec<String>,
+    },
+    Enable(String),
+    Disable(String),
+    Kill,
+}
+
+/// Makes the fifo at `path`, replacing whatever was left there, and runs the commands written to
+/// it in the background
+pub fn spawn(path: PathBuf) -> Result<(), String> {
+    make_fifo(&path)?;
+    if let Err(e) = thread::Builder::new()
+        .name("fifo".to_string()) //Name our threads  for better log messages
+        .spawn(move || read(&path))
+    {
+        return Err(format!("failed to spawn 'fifo' thread: {e}"));
+    }
+    Ok(())
+}
+
+fn make_fifo(path: &Path) -> Result<(), String> {
+    match fs::remove_file(path) {
+        Ok(()) => debug!("Removed stale fifo at {path:?}"),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => (),
+        Err(e) => return Err(format!("failed to remove old fifo at {path:?}: {e}")),
+    }
+    let c_path = match CString::new(path.as_os_str().as_bytes()) {
+        Ok(c_path) => c_path,
+        Err(e) => return Err(format!("invalid fifo path {path:?}: {e}")),
+    };
+    // only the user running the daemon may write commands to it
+    if unsafe { libc::mkfifo(c_path.as_ptr(), 0o600) } != 0 {
+        let e = io::Error::last_os_error();
+        return Err(format!("failed to make fifo at {path:?}: {e}"));
+    }
+    Ok(())
+}
+
+fn read(path: &Path) {
+    // keeping it open for writing too means we never read the end of the file when a writer closes
+    // it, so we don't have to reopen it after every one of them
+    let fifo = match OpenOptions::new().read(true).write(true).open(path) {
+        Ok(fifo) => fifo,
+        Err(e) => {
+            error!("failed to open fifo at {path:?}: {e}");
+            return;
+        }
+    };
+    for line in BufReader::new(fifo).split(b'\n') {
+        let line = match line {
+            Ok(line) => line,
+            Err(e) => {
+                error!("failed to read from fifo: {e}");
+                return;
+            }
+        };
+        let line = String::from_utf8_lossy(&line);
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        debug!("Running {line:?} from the fifo");
+        let result = parse(line).and_then(|command| run(command).map_err(|e| e.to_string()));
+        if let Err(e) = result {
+            error!("{line:?} (from the fifo) failed: {e}");
+        }
+    }
+}
+
+/// Parses one of
+///
+/// * `img [-o <outputs>] <path>`
+/// * `clear [-o <outputs>] [<color>]`
+/// * `enable <output>`
+/// * `disable <output>`
+/// * `kill`
+///
+/// Paths may have spaces, since they go until the end of the line
+fn parse(line: &str) -> Result<Command, String> {
+    let (name, rest) = split_word(line);
+    match name {
+        "img" => {
+            let (outputs, path) = split_outputs(rest);
+            if path.is_empty() {
+                return Err("img requires the path to an image".to_string());
+            }
+            Ok(Command::Img {
+                path: PathBuf::from(path),
+                outputs,
+            })
+        }
+        "clear" => {
+            let (outputs, color) = split_outputs(rest);
+            let color = if color.is_empty() {
+                [0, 0, 0]
+            } else {
+                parse_color(color)?
+            };
+            Ok(Command::Clear { color, outputs })
+        }
+        "enable" | "disable" => {
+            let (output, rest) = split_word(rest);
+            if output.is_empty() || !rest.is_empty() {
+                return Err(format!("{name} requires exactly one output"));
+            }
+            if name == "enable" {
+                Ok(Command::Enable(output.to_string()))
+            } else {
+                Ok(Command::Disable(output.to_string()))
+            }
+        }
+        "kill" if rest.is_empty() => Ok(Command::Kill),
+        "kill" => Err("kill takes no arguments".to_string()),
+        _ => Err(format!(
+            "unknown command {name:?}, expected img, clear, enable, disable or kill"
+        )),
+    }
+}
+
+/// Splits the first word off `line`
+fn split_word(line: &str) -> (&str, &str) {
+    match line.trim_start().split_once(char::is_whitespace) {
+        Some((word, rest)) => (word, rest.trim_start()),
+        None => (line.trim_start(), ""),
+    }
+}
+
+/// Splits the optional `-o <outputs>` off the start of `line`
+fn split_outputs(line: &str) -> (Vec<String>, &str) {
+    match split_word(line) {
+        ("-o" | "--outputs", rest) => {
+            let (outputs, rest) = split_word(rest);
+            let outputs = outputs
+                .split(',')
+                .filter(|output| !output.is_empty())
+                .map(str::to_string)
+                .collect();
+            (outputs, rest)
+        }
+        _ => (Vec::new(), line),
+    }
+}
+
+/// Parses colors in `rrggbb` or `rgb` format, with or without a leading `#`
+fn parse_color(color: &str) -> Result<[u8; 3], String> {
+    let hex = color.strip_prefix('#').unwrap_or(color);
+    let invalid = || format!("invalid color {color:?}, expected rrggbb or rgb");
+    if !hex.bytes().all(|b| b.is_ascii_hexdigit()) {
+        return Err(invalid());
+    }
+    let channel = |digits: &str| u8::from_str_radix(digits, 16).map_err(|_| invalid());
+    match hex.len() {
+        6 => Ok([
+            channel(&hex[0..2])?,
+            channel(&hex[2..4])?,
+            channel(&hex[4..6])?,
+        ]),
+        // shorthand notation: 'f' means 'ff'
+        3 => Ok([
+            channel(&hex[0..1])? * 17,
+            channel(&hex[1..2])? * 17,
+            channel(&hex[2..3])? * 17,
+        ]),
+        _ => Err(invalid()),
+    }
+}
+
+fn run(command: Command) -> Result<(), Error> {
+    match command {
+        Command::Img { path, outputs } => swww_client::set_image(&path, &outputs),
+        Command::Clear { color, outputs } => swww_client::clear(color, &outputs),
+        Command::Enable(output) => swww_client::send(Request::Enable(output)).map(|_| ()),
+        Command::Disable(output) => swww_client::send(Request::Disable(output)).map(|_| ()),
+        Command::Kill => swww_client::send(Request::Kill).map(|_| ()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn should_parse_paths_with_spaces() {
+        assert_eq!(
+            parse("img -o DP-1,HDMI-A-1  /home/me/My Pictures/a.png").unwrap(),
+            Command::Img {
+                path: PathBuf::from("/home/me/My Pictures/a.png"),
+                outputs: vec!["DP-1".to_string(), "HDMI-A-1".to_string()],
+            }
+        );
+        assert!(parse("img -o DP-1").is_err());
+    }
+
+    #[test]
+    fn should_parse_colors() {
+        assert_eq!(
+            parse("clear #f80").unwrap(),
+            Command::Clear {
+                color: [255, 136, 0],
+                outputs: Vec::new(),
+            }
+        );
+        assert_eq!(
+            parse("clear").unwrap(),
+            Command::Clear {
+                color: [0, 0, 0],
+                outputs: Vec::new(),
+            }
+        );
+        assert!(parse("clear 12345").is_err());
+        assert!(parse("clear +1+2+3").is_err());
+    }
+
+    #[test]
+    fn should_reject_unknown_commands() {
+        assert_eq!(parse("kill").unwrap(), Command::Kill);
+        assert!(parse("kill now").is_err());
+        assert!(parse("disable").is_err());
+        assert!(parse("query").is_err());
+    }
+}
@@ -0,0 +1,55 @@
+//! Runs the command given with `--hook` when something happens, so that users can pair what we
+//! display with anything else, like playing a sound when a transition starts. The command is run
+//! by `sh`, and learns what happened through the `SWWW_EVENT` and `SWWW_OUTPUTS` variables
+use log::{debug, error};
+
+use std::{process::Command, sync::Arc, thread};
+
+#[derive(Clone, Copy, Debug)]
+pub enum Event {
+    TransitionStart,
+    TransitionEnd,
+}
+
+impl Event {
+    fn name(self) -> &'static str {
+        match self {
+            Event::TransitionStart => "transition-start",
+            Event::TransitionEnd => "transition-end",
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct Hook {
+    command: Arc<str>,
+}
+
+impl Hook {
+    pub fn new(command: String) -> Self {
+        Self {
+            command: command.into(),
+        }
+    }
+
+    /// Runs the hook in the background, so that it can take as long as it wants
+    pub fn run(&self, event: Event, outputs: &[String]) {
+        let mut command = Command::new("sh");
+        command
+            .arg("-c")
+            .arg(&*self.command)
+            .env("SWWW_EVENT", event.name())
+            .env("SWWW_OUTPUTS", outputs.join(","));
+        debug!("Running hook for {event:?} on {outputs:?}");
+        if let Err(e) = thread::Builder::new()
+            .name("hook".to_string()) //Name our threads  for better log messages
+            .spawn(move || match command.status() {
+                Ok(status) if !status.success() => error!("hook for {event:?} failed: {status}"),
+                Ok(_) => (),
+                Err(e) => error!("failed to run hook for {event:?}: {e}"),
+            })
+        {
+            error!("failed to spawn 'hook' thread: {e}");
+        }
+    }
+}
@@ -10,34 +10,72 @@ use smithay_client_toolkit::{
             self,
             channel::{self, Channel},
             signals::{self, Signal},
+            timer::{TimeoutAction, Timer},
             LoopHandle, LoopSignal,
         },
-        client::protocol::{wl_output, wl_shm, wl_surface},
+        client::protocol::{wl_output, wl_surface},
         client::{protocol::wl_compositor, Attached, Display, EventQueue, Main},
-        protocols::wlr::unstable::layer_shell::v1::client::{
-            zwlr_layer_shell_v1, zwlr_layer_surface_v1,
+        protocols::{
+            viewporter::client::{wp_viewport, wp_viewporter},
+            wlr::unstable::layer_shell::v1::client::{zwlr_layer_shell_v1, zwlr_layer_surface_v1},
         },
     },
-    shm::MemPool,
     WaylandSource,
 };
 
 use std::{
     cell::{Cell, RefCell, RefMut},
-    fs,
+    collections::HashMap,
+    fs, io,
     os::unix::net::{UnixListener, UnixStream},
+    path::PathBuf,
     rc::Rc,
+    sync::Arc,
+    time::{Duration, Instant},
 };
 
+use swww_protocol::{
+    add_followers, error::Error, Adjust, AdjustSource, Answer, BgImg, BgInfo, Busy, Clear, Colors,
+    Follow, Generate, GroupCommand, Groups, Img, ManualCommand, Patch, Pin, Position, QueueCommand,
+    Record, Refresh, Region, Request, SafeArea, SlideshowCommand, TransactionCommand,
+    TransitionSync,
+};
+use swww_render::adjust::{luminance, match_brightness};
 use utils::{
-    communication::{get_socket_path, Answer, BgImg, BgInfo, Clear, Img, Request},
-    comp_decomp::ReadiedPack,
+    cache::Cached,
+    communication::{get_fifo_path, get_remote, get_socket_path, get_token, parse_tcp_addr},
+    comp_decomp::{ReadiedPack, Window},
+    surface::{clamp_resolution, Layer, Placement},
 };
 
+mod ambient;
+mod buffer;
+mod check;
+mod effect;
+mod fifo;
+mod hook;
+mod manual;
+mod plugin;
 mod processor;
+mod recorder;
+mod remote;
+mod session;
+mod slideshow;
+mod takeover;
+mod trace;
 mod wayland;
+#[cfg(feature = "x11")]
+mod x11;
 
-use processor::{ImgWithDim, Processor};
+use buffer::{Buffer, Rect};
+use hook::Hook;
+use manual::Manual;
+use processor::{evict_cache, ImgWithDim, Layout, Presentation, Processor};
+use recorder::Recorder;
+use session::SessionWait;
+use slideshow::Slideshows;
+use takeover::Takeover;
+use trace::Traces;
 
 #[derive(PartialEq, Copy, Clone)]
 enum RenderEvent {
@@ -45,12 +83,153 @@ enum RenderEvent {
     Closed,
 }
 
+/// A request waiting to be handled, along with the connection its answer must be sent to
+struct QueuedRequest {
+    stream: UnixStream,
+    request: Request,
+    since: Instant,
+    /// Identifies the request in `swww trace`
+    id: u64,
+    span: tracing::Span,
+}
+
+/// An `Adjust` request whose effect is being applied on a thread of its own
+struct PendingEffect {
+    adjust: Adjust,
+    /// The path of each image, the output it goes to, and that output's dimensions when we started
+    outputs: Vec<(PathBuf, String, (u32, u32))>,
+    /// Who to answer once we are done, set as soon as the request is handled
+    client: Option<(UnixStream, tracing::Span)>,
+}
+
+impl QueuedRequest {
+    fn describe(&self) -> String {
+        format!(
+            "#{} {} (waiting for {:.1}s)",
+            self.id,
+            describe_request(&self.request),
+            self.since.elapsed().as_secs_f32()
+        )
+    }
+}
+
+/// What `request` does, in a few words, for `swww queue list` and `swww trace`
+fn describe_request(request: &Request) -> String {
+    let outputs = |outputs: &[String]| {
+        if outputs.is_empty() {
+            "all outputs".to_string()
+        } else {
+            outputs.join(", ")
+        }
+    };
+    match request {
+        Request::Animation(animations) => {
+            let outputs: Vec<_> = animations.iter().flat_map(|a| a.1.clone()).collect();
+            format!("animation on {}", outputs.join(", "))
+        }
+        Request::Clear(clear) => format!(
+            "clear to {:02x}{:02x}{:02x} on {}",
+            clear.color[0],
+            clear.color[1],
+            clear.color[2],
+            outputs(&clear.outputs)
+        ),
+        Request::Generate(generate) => format!(
+            "generate {} on {}",
+            generate.generator.name(),
+            outputs(&generate.outputs)
+        ),
+        Request::Img((_, imgs)) => {
+            let outputs: Vec<_> = imgs.iter().flat_map(|img| img.1.clone()).collect();
+            let path = imgs.first().map(|img| img.0.path.display().to_string());
+            format!("img {} on {}", path.unwrap_or_default(), outputs.join(", "))
+        }
+        Request::Patch(patch) => format!("patch on {}", outputs(&patch.outputs)),
+        Request::Region(region) => format!("region on {}", outputs(&region.outputs)),
+        Request::Refresh(refresh) => format!("refresh {}", outputs(&refresh.outputs)),
+        Request::Adjust(adjust) => format!(
+            "adjust {} to {} on {}",
+            match adjust.source {
+                AdjustSource::Base => "base",
+                AdjustSource::Regions => "regions",
+            },
+            adjust.adjustment,
+            outputs(&adjust.outputs)
+        ),
+        Request::Busy(busy) => format!("busy indicator on {}", outputs(&busy.outputs)),
+        Request::Disable(output) => format!("disable {output}"),
+        Request::Enable(output) => format!("enable {output}"),
+        Request::Follow(follow) => format!("follow {}", follow.output),
+        Request::Group(GroupCommand::Create { name, .. }) => format!("create group {name}"),
+        Request::Group(GroupCommand::Delete(name)) => format!("delete group {name}"),
+        Request::Group(GroupCommand::List) => "list groups".to_string(),
+        Request::Pin(pin) if pin.pinned => format!("pin {}", pin.output),
+        Request::Pin(pin) => format!("unpin {}", pin.output),
+        Request::SafeArea(set) => format!("set safe area of {} to {}", set.output, set.area),
+        Request::Init => "init".to_string(),
+        Request::Kill => "kill".to_string(),
+        Request::Query => "query".to_string(),
+        Request::Queue(_) => "queue".to_string(),
+        Request::Record(record) => format!("record {}", record.output),
+        Request::Transaction(TransactionCommand::Begin) => "begin transaction".to_string(),
+        Request::Transaction(TransactionCommand::Commit) => "commit transaction".to_string(),
+        Request::Transaction(TransactionCommand::Rollback) => "rollback transaction".to_string(),
+        Request::Trace(_) => "trace".to_string(),
+        Request::Playback => "query animations".to_string(),
+        Request::Version => "version".to_string(),
+        Request::Colors(colors) => format!("average colors of {}", outputs(&colors.outputs)),
+        Request::Manual(ManualCommand::Start((_, imgs))) => {
+            let outputs: Vec<_> = imgs.iter().flat_map(|img| img.1.clone()).collect();
+            let path = imgs.first().map(|img| img.0.path.display().to_string());
+            format!(
+                "manual transition to {} on {}",
+                path.unwrap_or_default(),
+                outputs.join(", ")
+            )
+        }
+        Request::Manual(ManualCommand::Set(progress)) => {
+            format!("set manual transition to {progress:.3}")
+        }
+        Request::Manual(ManualCommand::End) => "end manual transition".to_string(),
+        Request::Slideshow(SlideshowCommand::Start(slideshow)) => format!(
+            "slideshow of {} on {}",
+            slideshow.dir.display(),
+            outputs(&slideshow.outputs)
+        ),
+        Request::Slideshow(SlideshowCommand::Stop(stopped)) => {
+            format!("stop slideshow on {}", outputs(stopped))
+        }
+    }
+}
+
+/// Height of the busy indicator, in logical pixels
+const BUSY_BAR_HEIGHT: u32 = 4;
+
+/// A progress bar drawn at the bottom of an output while the client processes an animation for it
+struct BusyBar {
+    progress: f32,
+    /// What the bar is covering, so that we can put it back once we are done
+    under: Vec<u8>,
+}
+
+/// Outputs the user asked us to let go of with `swww disable`, by name
+type DisabledOutputs = Vec<(String, wl_output::WlOutput)>;
+
 struct Bg {
     info: BgInfo,
+    output: wl_output::WlOutput,
     surface: wl_surface::WlSurface,
     layer_surface: Main<zwlr_layer_surface_v1::ZwlrLayerSurfaceV1>,
     next_render_event: Rc<Cell<Option<RenderEvent>>>,
-    pool: MemPool,
+    buffer: Buffer,
+    /// Scales our buffer up to the output's size, along with the largest resolution we draw at, if
+    /// `--max-resolution` applies to this output
+    viewport: Option<(wp_viewport::WpViewport, (u32, u32))>,
+    recorder: Option<Recorder>,
+    busy: Option<BusyBar>,
+    /// Why we couldn't allocate this output's buffer, if we couldn't. We never draw to outputs
+    /// without a buffer, and refuse requests for them
+    buffer_error: Option<String>,
 }
 
 impl Bg {
@@ -59,17 +238,19 @@ impl Bg {
         output_name: String,
         surface: wl_surface::WlSurface,
         layer_shell: &Attached<zwlr_layer_shell_v1::ZwlrLayerShellV1>,
-        pool: MemPool,
+        buffer: Buffer,
+        viewport: Option<(wp_viewport::WpViewport, (u32, u32))>,
+        placement: &Placement,
     ) -> Self {
-        let layer_surface = layer_shell.get_layer_surface(
-            &surface,
-            Some(output),
-            zwlr_layer_shell_v1::Layer::Background,
-            "swww".to_owned(),
-        );
+        let layer = match placement.layer.get(&output_name) {
+            Layer::Background => zwlr_layer_shell_v1::Layer::Background,
+            Layer::Bottom => zwlr_layer_shell_v1::Layer::Bottom,
+        };
+        let layer_surface =
+            layer_shell.get_layer_surface(&surface, Some(output), layer, "swww".to_owned());
 
         layer_surface.set_anchor(zwlr_layer_surface_v1::Anchor::all());
-        layer_surface.set_exclusive_zone(-1);
+        layer_surface.set_exclusive_zone(placement.exclusive_zone.get(&output_name));
 
         let next_render_event = Rc::new(Cell::new(None::<RenderEvent>));
         let next_render_event_handle = Rc::clone(&next_render_event);
@@ -97,15 +278,25 @@ impl Bg {
         surface.commit();
 
         Self {
+            output: output.clone(),
             surface,
             layer_surface,
             next_render_event,
-            pool,
+            buffer,
+            viewport,
+            recorder: None,
+            busy: None,
+            buffer_error: None,
             info: BgInfo {
                 name: output_name,
                 dim: (0, 0),
                 scale_factor: 1,
                 img: BgImg::Color([0, 0, 0]),
+                following: None,
+                pinned: false,
+                safe_area: SafeArea::default(),
+                buffer_dim: None,
+                hash: 0,
             },
         }
     }
@@ -119,18 +310,34 @@ impl Bg {
             Some(RenderEvent::Configure { width, height }) => {
                 let scale_factor = get_surface_scale_factor(&self.surface);
                 if self.info.dim != (width, height) || self.info.scale_factor != scale_factor {
-                    self.surface.set_buffer_scale(scale_factor);
+                    self.busy = None;
+                    if self.recorder.take().is_some() {
+                        info!("Stopped recording {}, since it was resized", self.info.name);
+                    }
                     self.info.dim = (width, height);
-                    self.info.scale_factor = scale_factor;
-                    let width = width as usize * scale_factor as usize;
-                    let height = height as usize * scale_factor as usize;
-                    if let Err(e) = self.pool.resize(width * height * 4) {
-                        error!("failed to resize {} memory pool: {e}", &self.info.name);
+                    self.buffer_error = match self.allocate(scale_factor) {
+                        Ok(()) => None,
+                        // a buffer at scale 1 is smaller, and the compositor will scale it up
+                        Err(e) if scale_factor > 1 => {
+                            warn!("{e}. Falling back to a buffer scale of 1");
+                            self.allocate(1).err()
+                        }
+                        Err(e) => Some(e),
+                    };
+                    if let Some(e) = &self.buffer_error {
+                        error!("{e}. Nothing will be drawn to {}", self.info.name);
+                        return Some(false);
                     }
 
                     // We must clear the outputs so that animations work due to the new underlying
                     // buffer needing to be the exact size of the monitor's.
                     self.clear([0, 0, 0]);
+                    if let Some(tiles) = self.buffer.tiles() {
+                        info!(
+                            "{} is too big for a single buffer, using {tiles} tiles",
+                            self.info.name
+                        );
+                    }
                     debug!("Configured {}", self.info);
                     Some(false)
                 } else {
@@ -142,96 +349,321 @@ impl Bg {
         }
     }
 
-    ///'color' argument is in rbg. We copy it correctly to brgx inside the function
-    fn clear(&mut self, color: [u8; 3]) {
-        self.info.img = BgImg::Color(color);
+    /// Sets the buffer scale, and makes sure we have memory for a buffer of that scale. Outputs
+    /// bigger than `--max-resolution` get a smaller buffer, at a scale of 1, which the viewport
+    /// scales up
+    fn allocate(&mut self, scale_factor: i32) -> Result<(), String> {
+        self.info.scale_factor = scale_factor;
+        self.info.buffer_dim = None;
+        let real = self.info.real_dim();
+        let mut buffer_scale = scale_factor;
+        if let Some((viewport, max)) = &self.viewport {
+            self.info.buffer_dim = clamp_resolution(real, *max);
+            match self.info.buffer_dim {
+                Some(_) => {
+                    let (width, height) = self.info.dim;
+                    viewport.set_destination(width as i32, height as i32);
+                    buffer_scale = 1;
+                }
+                None => viewport.set_destination(-1, -1),
+            }
+        }
+        self.surface.set_buffer_scale(buffer_scale);
         let dim = self.info.real_dim();
-        let stride = 4 * dim.0 as i32;
-        let width = dim.0 as i32;
-        let height = dim.1 as i32;
+        if dim != real {
+            info!(
+                "Drawing {} at {}x{} instead of {}x{}",
+                self.info.name, dim.0, dim.1, real.0, real.1
+            );
+        }
+        self.buffer
+            .allocate(&self.surface, dim, buffer_scale)
+            .map_err(|e| format!("failed to allocate {}'s buffer: {e}", self.info.name))
+    }
 
-        let buffer = self
-            .pool
-            .buffer(0, width, height, stride, wl_shm::Format::Xrgb8888);
+    /// Commits what we drew, damaging only `damage`
+    fn commit(&mut self, damage: Rect) {
+        self.buffer.commit(&self.surface, damage);
+        self.record();
+    }
 
-        let canvas = self.pool.mmap();
+    /// The whole output, as a damage rectangle
+    fn full_damage(&self) -> Rect {
+        let dim = self.info.real_dim();
+        Rect::new(0, 0, dim.0, dim.1)
+    }
+
+    ///'color' argument is in rbg. We copy it correctly to brgx inside the function
+    fn clear(&mut self, color: [u8; 3]) {
+        if self.buffer_error.is_some() {
+            return;
+        }
+        self.info.img = BgImg::Color(color);
+        self.busy = None;
+        let canvas = self.buffer.canvas();
         for pixel in canvas.chunks_exact_mut(4) {
             pixel[0] = color[2];
             pixel[1] = color[1];
             pixel[2] = color[0];
         }
         debug!("Clearing output: {}", self.info.name);
-        self.surface.attach(Some(&buffer), 0, 0);
-        self.surface.damage_buffer(0, 0, width, height);
-        self.surface.commit();
+        self.commit(self.full_damage());
     }
 
     fn draw(&mut self, img: &ReadiedPack) {
-        let dim = self.info.real_dim();
-        let stride = 4 * dim.0 as i32;
-        let width = dim.0 as i32;
-        let height = dim.1 as i32;
-
-        let buffer = self
-            .pool
-            .buffer(0, width, height, stride, wl_shm::Format::Xrgb8888);
-        let canvas = self.pool.mmap();
-        if !img.unpack(canvas) {
+        if self.buffer_error.is_some() {
+            return;
+        }
+        // the image is unpacked over what it was packed against, which doesn't have the bar
+        self.restore_busy_strip();
+        if !img.unpack(self.buffer.canvas()) {
             error!("buf_len different from expected_buf_size");
         }
         debug!("Decompressed img.");
+        self.paint_busy_bar();
+
+        let damage = match img.window() {
+            // regions only change their own window, unless they are under the busy bar
+            Some(window) if self.busy.is_none() => {
+                Rect::new(window.offset.0, window.offset.1, window.dim.0, window.dim.1)
+            }
+            _ => self.full_damage(),
+        };
+        self.commit(damage);
+    }
+
+    /// Blends the patch's image with what is currently being displayed, only damaging the area it
+    /// covers
+    fn patch(&mut self, patch: &Patch) {
+        if self.buffer_error.is_some() {
+            return;
+        }
+        let dim = self.info.real_dim();
+        let (width, height) = (dim.0 as i64, dim.1 as i64);
+        let (patch_w, patch_h) = (patch.dim.0 as i64, patch.dim.1 as i64);
+        let (left, top) = self.place(patch.dim, &patch.pos);
 
-        self.surface.attach(Some(&buffer), 0, 0);
-        self.surface.damage_buffer(0, 0, width, height);
-        self.surface.commit();
+        let (x0, y0) = (left.max(0), top.max(0));
+        let (x1, y1) = ((left + patch_w).min(width), (top + patch_h).min(height));
+        if x0 >= x1 || y0 >= y1 {
+            debug!("Patch is outside of output {}", self.info.name);
+            return;
+        }
+
+        self.restore_busy_strip();
+        let canvas = self.buffer.canvas();
+        for y in y0..y1 {
+            for x in x0..x1 {
+                let src = (((y - top) * patch_w + x - left) * 4) as usize;
+                let dst = ((y * width + x) * 4) as usize;
+                let pixel = &patch.img[src..src + 4];
+                let alpha = pixel[3] as u16;
+                for (d, s) in canvas[dst..dst + 3].iter_mut().zip(&pixel[0..3]) {
+                    *d = ((*d as u16 * (255 - alpha) + *s as u16 * alpha) / 255) as u8;
+                }
+            }
+        }
+        self.paint_busy_bar();
+        debug!("Patching output: {}", self.info.name);
+        self.commit(Rect::new(
+            x0 as u32,
+            y0 as u32,
+            (x1 - x0) as u32,
+            (y1 - y0) as u32,
+        ));
+    }
+
+    /// Where the top left corner of something of `dim` goes, for its center to be at `pos`
+    fn place(&self, dim: (u32, u32), pos: &Position) -> (i64, i64) {
+        let output_dim = self.info.real_dim();
+        let (center_x, center_y) = pos.to_pixel(output_dim);
+        let left = (center_x - dim.0 as f32 / 2.0).round() as i64;
+        // our positions have the y axis growing upwards
+        let top = (output_dim.1 as f32 - center_y - dim.1 as f32 / 2.0).round() as i64;
+        (left, top)
+    }
+
+    /// Range of the buffer covered by the busy indicator
+    fn busy_strip(&self) -> std::ops::Range<usize> {
+        let dim = self.info.real_dim();
+        let height = (BUSY_BAR_HEIGHT * self.info.scale_factor as u32).min(dim.1);
+        let row = dim.0 as usize * 4;
+        (dim.1 - height) as usize * row..dim.1 as usize * row
+    }
+
+    /// Puts back what the busy indicator is covering, without committing
+    fn restore_busy_strip(&mut self) {
+        let strip = self.busy_strip();
+        if let Some(busy) = &self.busy {
+            self.buffer.canvas()[strip].copy_from_slice(&busy.under);
+        }
+    }
+
+    /// Saves what is under the busy indicator and draws it, without committing
+    fn paint_busy_bar(&mut self) {
+        let strip = self.busy_strip();
+        let width = self.info.real_dim().0 as usize;
+        let Some(busy) = &mut self.busy else {
+            return;
+        };
+        let canvas = &mut self.buffer.canvas()[strip];
+        busy.under.clear();
+        busy.under.extend_from_slice(canvas);
+        let done = (width as f32 * busy.progress.clamp(0.0, 1.0)) as usize;
+        for row in canvas.chunks_exact_mut(width * 4) {
+            for (x, pixel) in row.chunks_exact_mut(4).enumerate() {
+                // we dim the whole strip, and lighten the part that is done, so that the bar is
+                // visible over both dark and bright wallpapers
+                let lighten = if x < done { 128 } else { 0 };
+                for channel in &mut pixel[0..3] {
+                    *channel = *channel / 2 + lighten;
+                }
+            }
+        }
+    }
+
+    /// Damages and commits the strip covered by the busy indicator
+    fn commit_busy_strip(&mut self) {
+        let dim = self.info.real_dim();
+        let height = (BUSY_BAR_HEIGHT * self.info.scale_factor as u32).min(dim.1);
+        self.commit(Rect::new(0, dim.1 - height, dim.0, height));
+    }
+
+    /// Draws the busy indicator, or updates its progress
+    fn show_busy(&mut self, progress: f32) {
+        if self.buffer_error.is_some() {
+            return;
+        }
+        self.restore_busy_strip();
+        self.busy = Some(BusyBar {
+            progress,
+            under: Vec::new(),
+        });
+        self.paint_busy_bar();
+        self.commit_busy_strip();
+    }
+
+    /// Removes the busy indicator, if we are showing one
+    fn end_busy(&mut self) {
+        if self.busy.is_some() {
+            self.restore_busy_strip();
+            self.busy = None;
+            self.commit_busy_strip();
+        }
+    }
+
+    /// Sends what we just committed to the recording, if there is one
+    fn record(&mut self) {
+        if let Some(recorder) = self.recorder.take() {
+            if recorder.send(self.get_current_img()) {
+                self.recorder = Some(recorder);
+            }
+        }
+    }
+
+    /// Position of the output's top-left corner in the global layout, in logical coordinates
+    fn position(&self) -> (i32, i32) {
+        with_output_info(&self.output, |info| info.location).unwrap_or((0, 0))
     }
 
     ///This method is what makes necessary that we use the mempoll, instead of the "easier"
     ///automempoll
     fn get_current_img(&mut self) -> &[u8] {
-        let dim = self.info.real_dim();
-        let size = dim.0 as usize * dim.1 as usize * 4;
-        &self.pool.mmap()[0..size]
+        self.buffer.canvas()
+    }
+
+    /// Our info, along with the hash of what we are displaying right now
+    fn query(&mut self) -> BgInfo {
+        let hash = crc32fast::hash(self.get_current_img());
+        BgInfo {
+            hash,
+            ..self.info.clone()
+        }
     }
 
     fn get_current_img_mut(&mut self) -> &mut [u8] {
-        let dim = self.info.real_dim();
-        let size = dim.0 as usize * dim.1 as usize * 4;
-        &mut self.pool.mmap()[0..size]
+        self.buffer.canvas()
     }
 }
 
 impl Drop for Bg {
     fn drop(&mut self) {
+        if let Some((viewport, _)) = &self.viewport {
+            viewport.destroy();
+        }
         self.layer_surface.destroy();
         self.surface.destroy();
     }
 }
 
+/// What we were told in the command line
+struct Args {
+    /// The maximum size of the cache, if any
+    cache_size: Option<u64>,
+    hook: Option<Hook>,
+    /// The minimum time between changes to an output's wallpaper, if any
+    min_interval: Option<Duration>,
+    /// What to wait for before setting the first wallpaper
+    session: SessionWait,
+    /// Whether to also read commands from `$XDG_RUNTIME_DIR/swww.fifo`
+    fifo: bool,
+    /// The layer and exclusive zone of each output's surface
+    placement: Rc<Placement>,
+    /// Whether to take over from the daemon that is already running
+    replace: bool,
+    /// Where to listen for remote clients, if anywhere
+    listen: Option<remote::Listen>,
+    /// Whether to swap images without transitions, and not play animations
+    reduce_motion: bool,
+}
+
 fn main() -> Result<(), String> {
+    let args = parse_args()?;
     make_logger();
+    // we send ourselves the requests of `--fifo` and `--listen`, and with SWWW_REMOTE set they
+    // would go to another daemon instead, or come back to us through the network
+    if get_remote().is_some() {
+        warn!("Ignoring SWWW_REMOTE, which only clients use");
+        std::env::remove_var("SWWW_REMOTE");
+    }
+    if args.reduce_motion {
+        info!("Motion is reduced: images are swapped without transitions, and animations paused");
+    }
+    #[cfg(feature = "x11")]
+    if x11::is_session() {
+        return x11::main(args);
+    }
 
-    let listener = make_socket()?;
+    let listener = if args.replace {
+        make_socket(Takeover::socket_path())?
+    } else {
+        make_socket(get_socket_path())?
+    };
     debug!(
         "Made socket in {:?} and initialized logger. Starting daemon...",
         listener.local_addr().unwrap() //this should always work if the socket connected correctly
     );
+    let fifo = args.fifo;
+    if fifo && !args.replace {
+        fifo::spawn(get_fifo_path())?;
+    }
 
     let (env, display, queue) = wayland::make_wayland_environment();
 
     let bgs = Rc::new(RefCell::new(Vec::new()));
-
-    let layer_shell = env.require_global::<zwlr_layer_shell_v1::ZwlrLayerShellV1>();
+    let disabled: Rc<RefCell<DisabledOutputs>> = Rc::new(RefCell::new(Vec::new()));
 
     let env_handle = env.clone();
     let bgs_handle = Rc::clone(&bgs);
+    let disabled_handle = Rc::clone(&disabled);
+    let placement = Rc::clone(&args.placement);
     let output_handler = move |output: wl_output::WlOutput, info: &OutputInfo| {
         create_backgrounds(
             &output,
             info,
             &env_handle,
-            &bgs_handle,
-            &layer_shell.clone(),
+            (&bgs_handle, &disabled_handle),
+            &placement,
         );
     };
     // Process currently existing outputs
@@ -245,21 +677,175 @@ fn main() -> Result<(), String> {
         env.listen_for_outputs(move |output, info, _| output_handler(output, info));
 
     //NOTE: we can't move display into the function because it causes a segfault
-    main_loop(&bgs, queue, &display, listener)?;
+    let outputs = bgs.borrow().iter().map(|bg| bg.info.name.clone()).collect();
+    evict_cache(args.cache_size, outputs);
+
+    let (took_over, killed_by) = main_loop(&bgs, &disabled, &env, queue, &display, listener, args)?;
     info!("Finished running event loop.");
+    // whoever killed us only hears back once the compositor is done with our surfaces, so that
+    // whatever replaces us doesn't race them
+    bgs.borrow_mut().clear();
+    let mut queue = display.create_event_queue();
+    if let Err(e) = queue.sync_roundtrip(&mut (), |_, _, _| {}) {
+        warn!("Failed to wait for the compositor to destroy our surfaces: {e}");
+    }
+    let removed = remove_socket(took_over, fifo);
+    if let Some(stream) = killed_by {
+        let answer = match &removed {
+            Ok(()) => Answer::Ok,
+            Err(e) => Answer::Err(Error::Other(e.clone())),
+        };
+        if let Err(e) = answer.send(&stream) {
+            error!("Failed to answer the kill request: {e}");
+        }
+    }
+    removed
+}
 
-    let socket_addr = get_socket_path();
+/// Cleans up after ourselves, once we are done. If we never `took_over` from a previous daemon,
+/// the socket and fifo we'd remove are still its own
+fn remove_socket(took_over: bool, fifo: bool) -> Result<(), String> {
+    let socket_addr = if took_over {
+        get_socket_path()
+    } else {
+        Takeover::socket_path()
+    };
     if let Err(e) = fs::remove_file(&socket_addr) {
         return Err(format!(
             "Failed to remove socket at {socket_addr:?} after closing unexpectedly: {e}"
         ));
     }
     info!("Removed socket at {:?}", socket_addr);
+    if fifo && took_over {
+        let fifo_addr = get_fifo_path();
+        if let Err(e) = fs::remove_file(&fifo_addr) {
+            return Err(format!("Failed to remove fifo at {fifo_addr:?}: {e}"));
+        }
+        info!("Removed fifo at {:?}", fifo_addr);
+    }
 
     info!("Goodbye!");
     Ok(())
 }
 
+fn parse_args() -> Result<Args, String> {
+    let mut cache_size = None;
+    let mut hook = None;
+    let mut min_interval = None;
+    let mut session = SessionWait {
+        delay: None,
+        command: None,
+    };
+    let mut fifo = false;
+    let mut replace = false;
+    let mut reduce_motion = false;
+    let mut listen = None;
+    let (mut layer, mut exclusive_zone, mut max_resolution) = (None, None, None);
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--cache-size" => match args.next() {
+                Some(size) => cache_size = Some(utils::cache::parse_size(&size)?),
+                None => return Err("--cache-size requires a size, like 500M".to_string()),
+            },
+            "--hook" => match args.next() {
+                Some(command) => hook = Some(Hook::new(command)),
+                None => return Err("--hook requires a command".to_string()),
+            },
+            "--min-interval" => match args.next() {
+                Some(interval) => min_interval = Some(parse_interval(&interval)?),
+                None => return Err("--min-interval requires a duration, like 2s".to_string()),
+            },
+            "--delay" => match args.next() {
+                Some(delay) => session.delay = Some(parse_interval(&delay)?),
+                None => return Err("--delay requires a duration, like 2s".to_string()),
+            },
+            "--wait-for" => match args.next() {
+                Some(command) => session.command = Some(command),
+                None => return Err("--wait-for requires a command".to_string()),
+            },
+            "--fifo" => fifo = true,
+            "--replace" => replace = true,
+            "--reduce-motion" => reduce_motion = true,
+            "--listen" => match args.next() {
+                Some(raw) => {
+                    let addr = parse_tcp_addr(&raw)?;
+                    remote::check_local(addr)?;
+                    listen = Some(addr.to_string());
+                }
+                None => {
+                    return Err(
+                        "--listen requires an address, like tcp://127.0.0.1:9500".to_string()
+                    )
+                }
+            },
+            "--layer" => match args.next() {
+                Some(raw) => layer = Some(raw),
+                None => return Err("--layer requires a layer, like bottom".to_string()),
+            },
+            "--exclusive-zone" => match args.next() {
+                Some(raw) => exclusive_zone = Some(raw),
+                None => return Err("--exclusive-zone requires a number, like 0".to_string()),
+            },
+            "--max-resolution" => match args.next() {
+                Some(raw) => max_resolution = Some(raw),
+                None => {
+                    return Err("--max-resolution requires a resolution, like 3840x2160".to_string())
+                }
+            },
+            "--check" => {
+                check::run();
+                std::process::exit(0);
+            }
+            "-h" | "--help" => {
+                println!(
+                    "Usage: swww-daemon [--cache-size <SIZE>] [--hook <COMMAND>] \
+                    [--min-interval <DURATION>] [--delay <DURATION>] [--wait-for <COMMAND>] [--fifo] \
+                    [--layer <LAYER>] [--exclusive-zone <ZONE>] [--max-resolution <RESOLUTION>] \
+                    [--listen <ADDRESS>] [--reduce-motion] [--replace] [--check]"
+                );
+                std::process::exit(0);
+            }
+            _ => return Err(format!("unrecognized argument: {arg}")),
+        }
+    }
+    Ok(Args {
+        cache_size,
+        hook,
+        min_interval,
+        session,
+        fifo,
+        placement: Rc::new(Placement::parse(
+            layer.as_deref(),
+            exclusive_zone.as_deref(),
+            max_resolution.as_deref(),
+        )?),
+        replace,
+        listen: match listen {
+            Some(addr) => match get_token() {
+                Some(token) => Some(remote::Listen { addr, token }),
+                None => return Err("--listen requires a token in SWWW_TOKEN".to_string()),
+            },
+            None => None,
+        },
+        reduce_motion,
+    })
+}
+
+/// Parses durations in milliseconds (`500ms`) or seconds (`2s`, or just `2`)
+fn parse_interval(raw: &str) -> Result<Duration, String> {
+    let (number, millis) = match raw.strip_suffix("ms") {
+        Some(number) => (number, true),
+        None => (raw.strip_suffix('s').unwrap_or(raw), false),
+    };
+    match number.parse::<f64>() {
+        Ok(n) if n.is_finite() && n >= 0.0 => {
+            Ok(Duration::from_secs_f64(if millis { n / 1000.0 } else { n }))
+        }
+        _ => Err(format!("invalid duration: {raw}")),
+    }
+}
+
 fn make_logger() {
     let config = simplelog::ConfigBuilder::new()
         .set_thread_level(LevelFilter::Info) //let me see where the processing is happening
@@ -279,42 +865,71 @@ fn create_backgrounds(
     output: &wl_output::WlOutput,
     info: &OutputInfo,
     env: &Environment<wayland::Env>,
-    bgs: &Rc<RefCell<Vec<Bg>>>,
-    layer_shell: &Attached<zwlr_layer_shell_v1::ZwlrLayerShellV1>,
+    (bgs, disabled): (&Rc<RefCell<Vec<Bg>>>, &Rc<RefCell<DisabledOutputs>>),
+    placement: &Placement,
 ) {
     if info.obsolete {
         // an output has been removed, release it
         bgs.borrow_mut().retain(|bg| bg.info.name != info.name);
+        disabled.borrow_mut().retain(|(name, _)| *name != info.name);
         output.release();
     } else {
         // an output has been created, construct a surface for it
-        let surface = env.create_surface().detach();
-        let pool = env
-            .create_simple_pool(|_dispatch_data| {
-                //do I need to do something here???
-            })
-            .expect("Failed to create a memory pool!");
+        bgs.borrow_mut().push(make_bg(output, info, env, placement));
+    }
+}
+
+/// Creates a new layer surface for the output, so that we can draw its background
+fn make_bg(
+    output: &wl_output::WlOutput,
+    info: &OutputInfo,
+    env: &Environment<wayland::Env>,
+    placement: &Placement,
+) -> Bg {
+    let layer_shell = env.require_global::<zwlr_layer_shell_v1::ZwlrLayerShellV1>();
+    let surface = env.create_surface().detach();
+    let buffer = Buffer::new(env.clone());
 
-        // Wayland clients are expected to render the cursor on their input region. By setting the
-        // input region to an empty region, the compositor renders the default cursor. Without
-        // this, and empty desktop won't render a cursor.
-        let compositor = env.require_global::<wl_compositor::WlCompositor>();
-        let empty_region = compositor.create_region();
-        surface.set_input_region(Some(&empty_region));
+    // Wayland clients are expected to render the cursor on their input region. By setting the
+    // input region to an empty region, the compositor renders the default cursor. Without
+    // this, and empty desktop won't render a cursor.
+    let compositor = env.require_global::<wl_compositor::WlCompositor>();
+    let empty_region = compositor.create_region();
+    surface.set_input_region(Some(&empty_region));
 
-        // From `wl_surface::set_opaque_region`:
-        // > Setting the pending opaque region has copy semantics, and the
-        // > wl_region object can be destroyed immediately.
-        empty_region.destroy();
+    // From `wl_surface::set_opaque_region`:
+    // > Setting the pending opaque region has copy semantics, and the
+    // > wl_region object can be destroyed immediately.
+    empty_region.destroy();
 
-        debug!("New background with output: {:?}", info);
-        let bg = Bg::new(output, info.name.clone(), surface, layer_shell, pool);
-        bgs.borrow_mut().push(bg);
-    }
+    let viewport = match placement.max_resolution.get(&info.name) {
+        Some(max) => match env.get_global::<wp_viewporter::WpViewporter>() {
+            Some(viewporter) => Some((viewporter.get_viewport(&surface).detach(), max)),
+            None => {
+                warn!(
+                    "The compositor doesn't support the viewporter protocol, so {} is drawn at its \
+                    full resolution",
+                    info.name
+                );
+                None
+            }
+        },
+        None => None,
+    };
+
+    debug!("New background with output: {:?}", info);
+    Bg::new(
+        output,
+        info.name.clone(),
+        surface,
+        &layer_shell,
+        buffer,
+        viewport,
+        placement,
+    )
 }
 
-fn make_socket() -> Result<UnixListener, String> {
-    let socket_addr = get_socket_path();
+fn make_socket(socket_addr: PathBuf) -> Result<UnixListener, String> {
     let runtime_dir = match socket_addr.parent() {
         Some(path) => path,
         None => return Err("couldn't find a valid runtime directory".to_owned()),
@@ -335,10 +950,10 @@ fn make_socket() -> Result<UnixListener, String> {
     Ok(listener)
 }
 
-fn register_signals(handle: &LoopHandle<LoopSignal>) -> Result<(), String> {
+fn register_signals(handle: &LoopHandle<Daemon>) -> Result<(), String> {
     match signals::Signals::new(&[Signal::SIGINT, Signal::SIGQUIT, Signal::SIGTERM]) {
         Ok(signals) => {
-            if let Err(e) = handle.insert_source(signals, |_, _, loop_signal| loop_signal.stop()) {
+            if let Err(e) = handle.insert_source(signals, |_, _, daemon| daemon.signal.stop()) {
                 Err(format!("failed to insert signals source: {e}"))
             } else {
                 Ok(())
@@ -348,49 +963,56 @@ fn register_signals(handle: &LoopHandle<LoopSignal>) -> Result<(), String> {
     }
 }
 
-fn register_channel<'a>(
-    handle: &LoopHandle<'a, LoopSignal>,
-    bgs: &'a Rc<RefCell<Vec<Bg>>>,
-    fr_recv: Channel<(Vec<String>, ReadiedPack)>,
+fn register_channel(
+    handle: &LoopHandle<Daemon>,
+    fr_recv: Channel<Presentation>,
 ) -> Result<(), String> {
-    if let Err(e) = handle.insert_source(fr_recv, |evt, _, loop_signal| match evt {
-        channel::Event::Msg(msg) => handle_recv_img(&mut bgs.borrow_mut(), &msg),
-        channel::Event::Closed => loop_signal.stop(),
+    if let Err(e) = handle.insert_source(fr_recv, |evt, _, daemon| match evt {
+        channel::Event::Msg((output, img, done)) => {
+            handle_recv_img(&mut daemon.bgs.borrow_mut(), &output, &img);
+            // the worker may have exited in the meantime, in which case nobody is waiting for this
+            let _ = done.send(());
+        }
+        channel::Event::Closed => daemon.signal.stop(),
     }) {
         return Err(format! {"failed to register channel: {e}"});
     }
     Ok(())
 }
 
-fn register_socket<'a>(
-    handle: &LoopHandle<'a, LoopSignal>,
-    bgs: &'a Rc<RefCell<Vec<Bg>>>,
-    display: &'a Display,
-    processor: &'a Rc<RefCell<Processor>>,
-    listener: UnixListener,
+fn register_effects(
+    handle: &LoopHandle<Daemon>,
+    receiver: Channel<Result<Vec<Vec<u8>>, Error>>,
 ) -> Result<(), String> {
+    // the daemon holds a sender, so the channel is never closed
+    if let Err(e) = handle.insert_source(receiver, |evt, _, daemon| {
+        if let channel::Event::Msg(imgs) = evt {
+            daemon.finish_effect(imgs);
+        }
+    }) {
+        return Err(format!("failed to register effects channel: {e}"));
+    }
+    Ok(())
+}
+
+fn register_socket(handle: &LoopHandle<Daemon>, listener: UnixListener) -> Result<(), String> {
     if let Err(e) = listener.set_nonblocking(true) {
         return Err(format!("failed to set nonblocking mode for socket: {e}"));
     };
     if let Err(e) = handle.insert_source(
         calloop::generic::Generic::new(listener, calloop::Interest::READ, calloop::Mode::Level),
-        |_, listener, loop_signal| {
-            let mut processor = processor.borrow_mut();
-            match listener.accept() {
-                Ok((stream, _)) => {
-                    match recv_socket_msg(bgs.borrow_mut(), stream, loop_signal, &mut processor) {
-                        Err(e) => error!("Failed to receive socket message: {}", e),
-                        Ok(()) => {
-                            //We must flush here because if multiple requests are sent at once the loop
-                            //might never be idle, and so the callback in the run function below
-                            //wouldn't be called (afaik)
-                            if let Err(e) = display.flush() {
-                                error!("Couldn't flush display: {}", e);
-                            }
-                        }
+        |_, listener, daemon| {
+            loop {
+                match listener.accept() {
+                    Ok((stream, _)) => {
+                        enqueue_request(&mut daemon.requests, &daemon.traces, stream)
+                    }
+                    Err(e) if e.kind() == io::ErrorKind::WouldBlock => break,
+                    Err(e) => {
+                        error!("Failed to accept connection: {}", e);
+                        break;
                     }
                 }
-                Err(e) => error!("Failed to accept connection: {}", e),
             }
             Ok(calloop::PostAction::Continue)
         },
@@ -399,81 +1021,533 @@ fn register_socket<'a>(
     }
     Ok(())
 }
-///bgs and display can't be moved into here because it causes a segfault
+
+/// Queues the request in `stream`, after every other request with the same or higher priority.
+/// Requests inspecting or changing the queue itself, the traces and the version are answered right
+/// away
+fn enqueue_request(queue: &mut Vec<QueuedRequest>, traces: &Traces, stream: UnixStream) {
+    let answer = match Request::receive(&stream) {
+        Ok(Request::Queue(QueueCommand::List)) => {
+            Answer::Queue(queue.iter().map(QueuedRequest::describe).collect())
+        }
+        Ok(Request::Queue(QueueCommand::Clear)) => {
+            for queued in queue.drain(..) {
+                let answer = Answer::Err(Error::Cancelled(
+                    "request was cancelled with `swww queue clear`".to_string(),
+                ));
+                if let Err(e) = answer.send(&queued.stream) {
+                    error!("Failed to answer cancelled request: {e}");
+                }
+            }
+            Answer::Ok
+        }
+        Ok(Request::Trace(None)) => Answer::Trace(traces.list()),
+        Ok(Request::Trace(Some(id))) => match traces.get(id) {
+            Some(trace) => Answer::Trace(trace),
+            None => Answer::Err(Error::InvalidRequest(format!(
+                "there is no trace of request #{id}, only the last ones are kept"
+            ))),
+        },
+        Ok(Request::Version) => Answer::Version(check::version()),
+        Ok(request) => {
+            let priority = request.priority();
+            let i = queue
+                .iter()
+                .position(|queued| queued.request.priority() < priority)
+                .unwrap_or(queue.len());
+            let (id, span) = trace::request(&describe_request(&request));
+            span.in_scope(|| tracing::info!("queued behind {i} requests"));
+            queue.insert(
+                i,
+                QueuedRequest {
+                    stream,
+                    request,
+                    since: Instant::now(),
+                    id,
+                    span,
+                },
+            );
+            return;
+        }
+        Err(e) => Answer::Err(e),
+    };
+    if let Err(e) = answer.send(&stream) {
+        error!("Failed to answer request: {e}");
+    }
+}
+
+/// Everything the event loop's sources work on. calloop hands it to the callback of every source,
+/// so new sources (timers, file watchers, ...) can be registered without threading more state
+/// around. `bgs` and `disabled` are shared with the output listener, which sctk calls outside of
+/// the event loop's dispatching
+struct Daemon {
+    bgs: Rc<RefCell<Vec<Bg>>>,
+    disabled: Rc<RefCell<DisabledOutputs>>,
+    /// The layer and exclusive zone of each output's surface, for the outputs we enable again
+    placement: Rc<Placement>,
+    groups: Groups,
+    requests: Vec<QueuedRequest>,
+    /// What happened to the last requests, for `swww trace`
+    traces: Traces,
+    /// Requests waiting for the transaction they are part of to be committed, if there is one
+    staged: Option<Vec<Request>>,
+    /// The transition `swww transition set` moves, if there is one
+    manual: Option<Manual>,
+    slideshows: Slideshows,
+    /// The `Adjust` request whose effect is being applied, if there is one. Until it's done, we
+    /// only handle `Query` and `Kill` requests, so that nothing changes the outputs under it
+    effect: Option<PendingEffect>,
+    /// Where the threads applying effects send the images back
+    effects: channel::Sender<Result<Vec<Vec<u8>>, Error>>,
+    processor: Processor,
+    cache_size: Option<u64>,
+    /// The minimum time between changes to an output's wallpaper, if any. Requests arriving sooner
+    /// wait, and the ones replaced by newer requests while waiting are dropped
+    min_interval: Option<Duration>,
+    /// When we last changed each output's wallpaper
+    last_changes: HashMap<String, Instant>,
+    /// When the timer waking us up to handle waiting requests fires, if there is one
+    wakeup: Option<Instant>,
+    /// Set until we take over from the previous daemon, with `--replace`
+    takeover: Option<Takeover>,
+    /// Whether the session is ready for us to set wallpapers. Until it is, we don't restore the
+    /// cached images, and only handle `Query` and `Kill` requests
+    session_ready: bool,
+    /// Where the `Kill` request we are exiting for came from, if any. It is answered once our
+    /// surfaces and socket are gone, so that clients know we are really done
+    killed_by: Option<UnixStream>,
+    handle: LoopHandle<'static, Daemon>,
+    signal: LoopSignal,
+}
+
+impl Daemon {
+    /// Reconfigures the outputs that changed since the last iteration, and restores their images
+    /// from the cache
+    fn handle_output_events(&mut self) {
+        let mut bgs = self.bgs.borrow_mut();
+        let processor = &mut self.processor;
+        let mut i = 0;
+        while i != bgs.len() {
+            if let Some(should_remove) = bgs[i].handle_events() {
+                processor.set_output_count(bgs.len() as u8);
+                processor.stop_animations(&[bgs[i].info.name.clone()]);
+                if should_remove {
+                    bgs.remove(i);
+                } else {
+                    processor.add_output(&bgs[i].info.name);
+                    if self.session_ready {
+                        restore_cached_img(&mut bgs[i], processor);
+                    }
+                    i += 1;
+                }
+            } else {
+                i += 1;
+            }
+        }
+        // outputs may also be removed when they are disconnected or disabled
+        if processor.output_count() > bgs.len() {
+            processor.retain_outputs(|output| bgs.iter().any(|bg| bg.info.name == output));
+        }
+    }
+
+    /// Takes over from the previous daemon once our outputs are drawn, if we were started with
+    /// `--replace`
+    fn take_over(&mut self) {
+        let Some(takeover) = &self.takeover else {
+            return;
+        };
+        let drawn = self.bgs.borrow().iter().all(|bg| bg.info.dim != (0, 0));
+        if !takeover.is_due(drawn) {
+            return;
+        }
+        match takeover.run() {
+            Ok(()) => {
+                if takeover.fifo {
+                    if let Err(e) = fifo::spawn(get_fifo_path()) {
+                        error!("{e}");
+                    }
+                }
+                if let Some(listen) = takeover.listen.clone() {
+                    if let Err(e) = remote::register(&self.handle, listen) {
+                        error!("{e}");
+                    }
+                }
+                self.takeover = None;
+            }
+            Err(e) => {
+                error!("Failed to take over from the previous daemon: {e}");
+                self.signal.stop();
+            }
+        }
+    }
+
+    /// Sets the first wallpapers, now that the session is ready
+    fn start_session(&mut self) {
+        if self.session_ready {
+            return;
+        }
+        info!("Session is ready, restoring the cached images");
+        self.session_ready = true;
+        let mut bgs = self.bgs.borrow_mut();
+        for bg in bgs.iter_mut().filter(|bg| bg.info.dim != (0, 0)) {
+            restore_cached_img(bg, &mut self.processor);
+        }
+    }
+
+    /// Starts staging requests, applies the staged ones all at once, or throws them away
+    fn transaction(
+        &mut self,
+        command: TransactionCommand,
+        env: &Environment<wayland::Env>,
+    ) -> Answer {
+        match (command, self.staged.take()) {
+            (TransactionCommand::Begin, None) => {
+                self.staged = Some(Vec::new());
+                Answer::Ok
+            }
+            (TransactionCommand::Begin, Some(staged)) => {
+                self.staged = Some(staged);
+                Answer::Err(Error::Conflict(
+                    "a transaction is already in progress".to_string(),
+                ))
+            }
+            (TransactionCommand::Commit, Some(staged)) => {
+                info!("Committing {} staged requests", staged.len());
+                // everything is applied in this iteration, so it all shows up with the same flush
+                let mut errors: Vec<Error> = staged
+                    .into_iter()
+                    .filter_map(|request| match handle_request(self, env, request) {
+                        Answer::Err(e) => Some(e),
+                        _ => None,
+                    })
+                    .collect();
+                match errors.len() {
+                    0 => Answer::Ok,
+                    1 => Answer::Err(errors.remove(0)),
+                    _ => {
+                        let errors: Vec<&str> = errors.iter().map(Error::message).collect();
+                        Answer::Err(Error::Other(errors.join("\n")))
+                    }
+                }
+            }
+            (TransactionCommand::Rollback, Some(staged)) => {
+                info!("Discarding {} staged requests", staged.len());
+                Answer::Ok
+            }
+            (_, None) => Answer::Err(Error::Conflict("no transaction is in progress".to_string())),
+        }
+    }
+
+    /// Transitions to the images the effect of the pending `Adjust` request made, and answers it
+    fn finish_effect(&mut self, imgs: Result<Vec<Vec<u8>>, Error>) {
+        let Some(effect) = self.effect.take() else {
+            return;
+        };
+        let answer = match imgs {
+            Ok(imgs) => {
+                let mut bgs = self.bgs.borrow_mut();
+                let mut requests = Vec::new();
+                for ((path, output, dim), img) in effect.outputs.into_iter().zip(imgs) {
+                    // outputs can still change, or go away, while the effect runs
+                    if bgs.iter().any(|bg| {
+                        bg.info.name == output
+                            && bg.info.real_dim() == dim
+                            && bg.buffer_error.is_none()
+                    }) {
+                        requests.push((Img { path, img }, vec![output]));
+                    } else {
+                        warn!("{output} changed while applying the effect, skipping it");
+                    }
+                }
+                if requests.is_empty() {
+                    Answer::Err(Error::Output(
+                        "the requested outputs changed while applying the effect".to_string(),
+                    ))
+                } else {
+                    transition_adjusted(&mut bgs, &effect.adjust, requests, &mut self.processor)
+                }
+            }
+            Err(e) => Answer::Err(e),
+        };
+        if let Some((stream, span)) = effect.client {
+            let _entered = span.enter();
+            trace_answer(&answer);
+            if let Err(e) = answer.send(&stream) {
+                error!("Failed to answer request: {e}");
+            }
+        }
+    }
+
+    /// Finds the next request we can handle, answering the throttled ones that newer requests
+    /// replace with `Coalesced`. When all of them must wait, we set up a timer to wake us up
+    fn next_request(&mut self) -> Option<usize> {
+        if !self.session_ready || self.effect.is_some() {
+            return self
+                .requests
+                .iter()
+                .position(|queued| matches!(queued.request, Request::Query | Request::Kill));
+        }
+        let min_interval = match self.min_interval {
+            // staged requests aren't applied until the commit, so there is nothing to throttle
+            Some(interval) if self.staged.is_none() => interval,
+            _ => return (!self.requests.is_empty()).then_some(0),
+        };
+        let now = Instant::now();
+        let mut earliest: Option<Instant> = None;
+        let mut i = 0;
+        while i < self.requests.len() {
+            let bgs = self.bgs.borrow();
+            let outputs = match throttled_outputs(&bgs, &self.requests[i].request) {
+                Some(outputs) => outputs,
+                // nothing may jump ahead of waiting requests into a transaction
+                None if earliest.is_some()
+                    && matches!(self.requests[i].request, Request::Transaction(_)) =>
+                {
+                    break
+                }
+                None => return Some(i),
+            };
+            let superseded = !outputs.is_empty()
+                && self.requests[i + 1..]
+                    .iter()
+                    .take_while(|later| !matches!(later.request, Request::Transaction(_)))
+                    .filter_map(|later| throttled_outputs(&bgs, &later.request))
+                    .any(|later| outputs.iter().all(|output| later.contains(output)));
+            drop(bgs);
+            if superseded {
+                let queued = self.requests.remove(i);
+                debug!("Coalescing {}", queued.describe());
+                queued
+                    .span
+                    .in_scope(|| tracing::info!("coalesced with a newer request"));
+                if let Err(e) = Answer::Coalesced.send(&queued.stream) {
+                    error!("Failed to answer coalesced request: {e}");
+                }
+                continue;
+            }
+            let ready_at = outputs
+                .iter()
+                .filter_map(|output| self.last_changes.get(output))
+                .map(|changed| *changed + min_interval)
+                .max();
+            match ready_at {
+                Some(ready_at) if ready_at > now => {
+                    earliest = Some(earliest.map_or(ready_at, |e| e.min(ready_at)));
+                    i += 1;
+                }
+                _ => return Some(i),
+            }
+        }
+        if let Some(earliest) = earliest {
+            self.wake_at(earliest);
+        }
+        None
+    }
+
+    /// Makes sure the event loop wakes up at `deadline`, at the latest
+    fn wake_at(&mut self, deadline: Instant) {
+        if self.wakeup.is_some_and(|wakeup| wakeup <= deadline) {
+            return;
+        }
+        let timer = Timer::from_deadline(deadline);
+        match self.handle.insert_source(timer, |_, _, daemon| {
+            daemon.wakeup = None;
+            TimeoutAction::Drop
+        }) {
+            Ok(_) => self.wakeup = Some(deadline),
+            Err(e) => error!("failed to set up timer for throttled requests: {e}"),
+        }
+    }
+
+    /// We handle a single request per iteration, so that a burst of them can't keep us from
+    /// drawing, and so that users have the chance to inspect and cancel the rest
+    fn handle_next_request(&mut self, env: &Environment<wayland::Env>) {
+        let Some(i) = self.next_request() else {
+            return;
+        };
+        let queued = self.requests.remove(i);
+        // the threads drawing what the request asked for keep the span alive until they are done
+        let _entered = queued.span.enter();
+        tracing::info!(
+            "handling after waiting for {:.3}s",
+            queued.since.elapsed().as_secs_f32()
+        );
+        let changed = match self.min_interval {
+            Some(_) if self.staged.is_none() => {
+                throttled_outputs(&self.bgs.borrow(), &queued.request)
+            }
+            _ => None,
+        };
+        let kill = matches!(queued.request, Request::Kill);
+        let caches = match &queued.request {
+            Request::Img(_) | Request::Animation(_) => self.staged.is_none(),
+            Request::Transaction(TransactionCommand::Commit) => true,
+            _ => false,
+        };
+        let answer = match queued.request {
+            Request::Transaction(command) => self.transaction(command, env),
+            Request::Adjust(adjust) if self.staged.is_some() && adjust.effect.is_some() => {
+                Answer::Err(Error::InvalidRequest(
+                    "effects can't be part of a transaction".to_string(),
+                ))
+            }
+            request if self.staged.is_some() && is_staged(&request) => {
+                if let Some(staged) = &mut self.staged {
+                    staged.push(request);
+                }
+                Answer::Ok
+            }
+            request => handle_request(self, env, request),
+        };
+        if let (Some(outputs), false) = (changed, matches!(answer, Answer::Err(_))) {
+            let now = Instant::now();
+            for output in outputs {
+                self.last_changes.insert(output, now);
+            }
+        }
+        if let Some(effect) = self
+            .effect
+            .as_mut()
+            .filter(|effect| effect.client.is_none())
+        {
+            tracing::info!("applying the effect");
+            effect.client = Some((queued.stream, queued.span.clone()));
+        } else {
+            trace_answer(&answer);
+            if kill && matches!(answer, Answer::Ok) {
+                self.killed_by = Some(queued.stream);
+            } else if let Err(e) = answer.send(&queued.stream) {
+                error!("Failed to answer request: {e}");
+            }
+        }
+        if caches {
+            let outputs = self
+                .bgs
+                .borrow()
+                .iter()
+                .map(|bg| bg.info.name.clone())
+                .collect();
+            evict_cache(self.cache_size, outputs);
+        }
+        if !self.requests.is_empty() {
+            self.signal.wakeup();
+        }
+    }
+}
+
+/// Records how we answered the request being handled, for `swww trace`
+fn trace_answer(answer: &Answer) {
+    match answer {
+        Answer::Err(e) => tracing::info!("answered with an error: {}", e.message()),
+        _ => tracing::info!("answered"),
+    }
+}
+
+///bgs and display can't be moved into here because it causes a segfault. Returns whether we took
+///over from the previous daemon, and where the `Kill` request we exited for came from, if any
 fn main_loop(
     bgs: &Rc<RefCell<Vec<Bg>>>,
+    disabled: &Rc<RefCell<DisabledOutputs>>,
+    env: &Environment<wayland::Env>,
     queue: EventQueue,
     display: &Display,
     listener: UnixListener,
-) -> Result<(), String> {
-    //We use 1 because we can't send a new frame without being absolutely sure that all previous
-    //have already been displayed. Using 0 causes the animation to stop.
-    let (frame_sender, frame_receiver) = calloop::channel::sync_channel(1);
-    let processor = Rc::new(RefCell::new(Processor::new(frame_sender)));
-    let mut event_loop = match calloop::EventLoop::<calloop::LoopSignal>::try_new() {
+    args: Args,
+) -> Result<(bool, Option<UnixStream>), String> {
+    let traces = Traces::default();
+    if let Err(e) = tracing::subscriber::set_global_default(trace::Recorder::new(traces.clone())) {
+        return Err(format!("failed to set up request tracing: {e}"));
+    }
+    //Every output's worker sends at most one frame at a time, so this can't grow unbounded
+    let (presenter, frame_receiver) = calloop::channel::channel();
+    let (effects, effect_receiver) = calloop::channel::channel();
+    let mut event_loop = match calloop::EventLoop::<Daemon>::try_new() {
         Ok(el) => el,
         Err(e) => return Err(e.to_string()),
     };
     let event_handle = event_loop.handle();
 
     register_signals(&event_handle)?;
-    register_channel(&event_handle, bgs, frame_receiver)?;
-    register_socket(&event_handle, bgs, display, &processor, listener)?;
+    register_channel(&event_handle, frame_receiver)?;
+    register_effects(&event_handle, effect_receiver)?;
+    register_socket(&event_handle, listener)?;
+    if let Some(listen) = args.listen.clone().filter(|_| !args.replace) {
+        remote::register(&event_handle, listen)?;
+    }
+    let session_ready = !args.session.is_needed();
+    if !session_ready {
+        args.session
+            .register(&event_handle, Daemon::start_session)?;
+    }
 
     if let Err(e) = WaylandSource::new(queue).quick_insert(event_handle) {
         return Err(e.to_string());
     }
 
+    let mut daemon = Daemon {
+        bgs: Rc::clone(bgs),
+        disabled: Rc::clone(disabled),
+        placement: args.placement,
+        groups: Groups::new(),
+        requests: Vec::new(),
+        traces,
+        staged: None,
+        manual: None,
+        slideshows: Slideshows::default(),
+        effect: None,
+        effects,
+        processor: Processor::new(presenter, args.hook, args.reduce_motion),
+        cache_size: args.cache_size,
+        min_interval: args.min_interval,
+        last_changes: HashMap::new(),
+        wakeup: None,
+        takeover: args
+            .replace
+            .then(|| Takeover::new(args.fifo, args.listen.clone())),
+        session_ready,
+        killed_by: None,
+        handle: event_loop.handle(),
+        signal: event_loop.get_signal(),
+    };
+
     info!("Initialization succeeded! Starting main loop...");
-    let mut loop_signal = event_loop.get_signal();
-    if let Err(e) = event_loop.run(None, &mut loop_signal, |_| {
-        {
-            let mut bgs = bgs.borrow_mut();
-            let mut i = 0;
-            while i != bgs.len() {
-                if let Some(should_remove) = bgs[i].handle_events() {
-                    let mut processor = processor.borrow_mut();
-                    processor.set_output_count(bgs.len() as u8);
-                    processor.stop_animations(&[bgs[i].info.name.clone()]);
-                    if should_remove {
-                        bgs.remove(i);
-                    } else {
-                        let info = bgs[i].info.clone();
-                        let old_img = bgs[i].get_current_img_mut();
-                        if let Some(path) = processor.import_cached_img(info, old_img) {
-                            bgs[i].info.img = BgImg::Img(path);
-                        }
-                        i += 1;
-                    }
-                } else {
-                    i += 1;
-                }
-            }
-        }
+    if let Err(e) = event_loop.run(None, &mut daemon, |daemon| {
+        daemon.handle_output_events();
+        daemon.handle_next_request(env);
         if let Err(e) = display.flush() {
             error!("Couldn't flush display: {}", e);
         }
+        // the compositor has what we drew now, so the old daemon's surfaces can go
+        daemon.take_over();
     }) {
         return Err(format!("Event loop closed unexpectedly: {e}"));
     }
 
-    Ok(())
+    Ok((daemon.takeover.is_none(), daemon.killed_by))
 }
 
-fn recv_socket_msg(
-    mut bgs: RefMut<Vec<Bg>>,
-    stream: UnixStream,
-    loop_signal: &calloop::LoopSignal,
-    proc: &mut Processor,
-) -> Result<(), String> {
-    let request = Request::receive(&stream);
-    let answer = match request {
+fn handle_request(
+    daemon: &mut Daemon,
+    env: &Environment<wayland::Env>,
+    request: Request,
+) -> Answer {
+    let mut bgs = daemon.bgs.borrow_mut();
+    let mut disabled = daemon.disabled.borrow_mut();
+    let groups = &mut daemon.groups;
+    let proc = &mut daemon.processor;
+    let loop_signal = &daemon.signal;
+    if let (Some(manual), Some(outputs)) = (&mut daemon.manual, throttled_outputs(&bgs, &request)) {
+        manual.forget(&outputs);
+    }
+    let request = check_buffers(&bgs, &request).map(|()| request);
+    match request {
         Ok(Request::Animation(animations)) => {
             let mut result = Answer::Ok;
             for animation in &animations {
                 for output in &animation.1 {
                     if !bgs.iter().any(|bg| &bg.info.name == output) {
-                        result = Answer::Err(format!("Output {output} doesn't exit"));
+                        result =
+                            Answer::Err(Error::Output(format!("Output {output} doesn't exist")));
                         break;
                     }
                 }
@@ -481,42 +1555,218 @@ fn recv_socket_msg(
             if matches!(result, Answer::Err(_)) {
                 result
             } else {
+                end_busy(&mut bgs, animations.iter().flat_map(|a| &a.1));
                 for animation in animations {
                     let bg = bgs.iter().find(|bg| animation.1.contains(&bg.info.name));
                     if bg.is_none() {
                         continue;
                     }
                     //unwraping is fine because we test it above
-                    let dim = bg.unwrap().info.real_dim();
+                    let info = &bg.unwrap().info;
+                    let dim = info.real_dim();
                     let size = dim.0 as usize * dim.1 as usize * 4;
-                    if let Answer::Err(e) = proc.animate(animation.0, animation.1, size) {
+                    let path = match &info.img {
+                        BgImg::Img(path) => Some(path.clone()),
+                        _ => None,
+                    };
+                    if let Answer::Err(e) = proc.animate(animation.0, animation.1, size, path) {
                         result = Answer::Err(e);
                     }
                 }
                 result
             }
         }
-        Ok(Request::Clear(clear)) => clear_outputs(&mut bgs, &clear, proc),
+        Ok(Request::Clear(mut clear)) => {
+            add_followers(&mut clear.outputs, bgs.iter().map(|bg| &bg.info));
+            bgs.iter_mut()
+                .filter(|bg| bg.info.is_requested(&clear.outputs))
+                .for_each(Bg::end_busy);
+            clear_outputs(&mut bgs, clear, proc)
+        }
+        Ok(Request::Adjust(mut adjust)) => {
+            add_followers(&mut adjust.outputs, bgs.iter().map(|bg| &bg.info));
+            bgs.iter_mut()
+                .filter(|bg| bg.info.is_requested(&adjust.outputs))
+                .for_each(Bg::end_busy);
+            match adjusted_imgs(&mut bgs, &adjust, proc) {
+                Ok(requests) => match adjust.effect.clone() {
+                    Some(name) => start_effect(
+                        &bgs,
+                        adjust,
+                        name,
+                        requests,
+                        &mut daemon.effect,
+                        &daemon.effects,
+                    ),
+                    None => transition_adjusted(&mut bgs, &adjust, requests, proc),
+                },
+                Err(answer) => answer,
+            }
+        }
+        Ok(Request::Disable(output)) => match bgs.iter().position(|bg| bg.info.name == output) {
+            Some(i) => {
+                proc.stop_animations(std::slice::from_ref(&output));
+                // dropping the background destroys its surface
+                let bg = bgs.remove(i);
+                proc.set_output_count(bgs.len() as u8);
+                disabled.push((output, bg.output.clone()));
+                info!("Disabled output {}", bg.info.name);
+                Answer::Ok
+            }
+            None => Answer::Err(Error::Output(format!(
+                "Output {output} doesn't exist or is already disabled"
+            ))),
+        },
+        Ok(Request::Enable(output)) => {
+            match disabled.iter().position(|(name, _)| *name == output) {
+                Some(i) => {
+                    let (_, wl_output) = disabled.remove(i);
+                    match with_output_info(&wl_output, Clone::clone) {
+                        Some(info) => {
+                            bgs.push(make_bg(&wl_output, &info, env, &daemon.placement));
+                            info!("Enabled output {output}");
+                            Answer::Ok
+                        }
+                        None => Answer::Err(Error::Output(format!(
+                            "Failed to get info of output {output}"
+                        ))),
+                    }
+                }
+                None => Answer::Err(Error::Conflict(format!("Output {output} is not disabled"))),
+            }
+        }
+        Ok(Request::Follow(follow)) => follow_output(&mut bgs, follow),
+        Ok(Request::Pin(pin)) => pin_output(&mut bgs, pin),
+        Ok(Request::SafeArea(set)) => match bgs.iter_mut().find(|bg| bg.info.name == set.output) {
+            Some(bg) => {
+                bg.info.safe_area = set.area;
+                Answer::Ok
+            }
+            None => Answer::Err(Error::Output(format!(
+                "Output {} doesn't exist",
+                set.output
+            ))),
+        },
+        Ok(Request::Group(command)) => manage_groups(&bgs, groups, command),
+        Ok(Request::Generate(mut generate)) => {
+            add_followers(&mut generate.outputs, bgs.iter().map(|bg| &bg.info));
+            generate_bgs(&mut bgs, generate, proc)
+        }
         Ok(Request::Kill) => {
             loop_signal.stop();
             Answer::Ok
         }
+        Ok(Request::Patch(patch)) => patch_outputs(&mut bgs, &patch),
+        Ok(Request::Region(region)) => region_outputs(&bgs, region, proc),
+        Ok(Request::Busy(busy)) => show_busy(&mut bgs, busy),
+        Ok(Request::Refresh(mut refresh)) => {
+            add_followers(&mut refresh.outputs, bgs.iter().map(|bg| &bg.info));
+            refresh_outputs(&mut bgs, refresh)
+        }
         Ok(Request::Img(img)) => {
             let old_imgs = get_old_imgs(&mut bgs, &img.1);
             if old_imgs.len() != img.1.len() {
-                Answer::Err("Daemon received request for outputs that don't exist".to_string())
+                Answer::Err(Error::Output(
+                    "Daemon received request for outputs that don't exist".to_string(),
+                ))
             } else {
-                proc.transition(&img.0, img.1, old_imgs)
+                let layouts = match img.0.sync {
+                    TransitionSync::None => Vec::new(),
+                    TransitionSync::Layout => get_layouts(&bgs, &img.1),
+                };
+                proc.transition(&img.0, img.1, old_imgs, layouts)
             }
         }
-        Ok(Request::Init { .. }) => Answer::Ok,
-        Ok(Request::Query) => Answer::Info(bgs.iter().map(|bg| bg.info.clone()).collect()),
+        Ok(Request::Init) => Answer::Ok,
+        Ok(Request::Queue(_)) => Answer::Err(Error::InvalidRequest(
+            "queue commands can't be queued".to_string(),
+        )),
+        Ok(Request::Trace(_)) => Answer::Err(Error::InvalidRequest(
+            "trace requests can't be queued".to_string(),
+        )),
+        Ok(Request::Version) => Answer::Version(check::version()),
+        Ok(Request::Query) => Answer::Info(bgs.iter_mut().map(Bg::query).collect()),
+        Ok(Request::Playback) => Answer::Playback(proc.playback()),
+        Ok(Request::Colors(colors)) => average_colors(&mut bgs, colors),
+        Ok(Request::Record(record)) => record_output(&mut bgs, record),
+        Ok(Request::Manual(command)) => manual::handle(&mut bgs, &mut daemon.manual, proc, command),
+        Ok(Request::Slideshow(command)) => {
+            let infos: Vec<&BgInfo> = bgs.iter().map(|bg| &bg.info).collect();
+            daemon.slideshows.handle(&daemon.handle, &infos, command)
+        }
+        Ok(Request::Transaction(_)) => Answer::Err(Error::InvalidRequest(
+            "transactions can't be part of transactions".to_string(),
+        )),
         Err(e) => Answer::Err(e),
+    }
+}
+
+/// The outputs whose whole wallpaper `request` replaces, which `--min-interval` throttles. `None`
+/// for every other request
+fn throttled_outputs(bgs: &[Bg], request: &Request) -> Option<Vec<String>> {
+    let requested = match request {
+        Request::Img((_, imgs)) => {
+            return Some(imgs.iter().flat_map(|img| img.1.clone()).collect())
+        }
+        Request::Clear(clear) => &clear.outputs,
+        Request::Adjust(adjust) => &adjust.outputs,
+        Request::Generate(generate) => &generate.outputs,
+        _ => return None,
     };
-    answer.send(&stream)
+    Some(
+        bgs.iter()
+            .filter(|bg| bg.info.is_requested(requested))
+            .map(|bg| bg.info.name.clone())
+            .collect(),
+    )
+}
+
+/// Whether `request` changes what is displayed, and so must wait for the transaction in progress to
+/// be committed. Everything else, like queries, is handled right away
+fn is_staged(request: &Request) -> bool {
+    matches!(
+        request,
+        Request::Adjust(_)
+            | Request::Animation(_)
+            | Request::Clear(_)
+            | Request::Disable(_)
+            | Request::Enable(_)
+            | Request::Follow(_)
+            | Request::Generate(_)
+            | Request::Img(_)
+            | Request::Patch(_)
+            | Request::Pin(_)
+            | Request::Region(_)
+            | Request::SafeArea(_)
+    )
+}
+
+/// Refuses requests for outputs we couldn't allocate a buffer for
+fn check_buffers(bgs: &[Bg], request: &Request) -> Result<(), Error> {
+    let outputs: Vec<&String> = match request {
+        Request::Animation(animations) => animations.iter().flat_map(|a| &a.1).collect(),
+        Request::Img((_, imgs)) | Request::Manual(ManualCommand::Start((_, imgs))) => {
+            imgs.iter().flat_map(|img| &img.1).collect()
+        }
+        Request::Clear(clear) => clear.outputs.iter().collect(),
+        Request::Adjust(adjust) => adjust.outputs.iter().collect(),
+        Request::Generate(generate) => generate.outputs.iter().collect(),
+        Request::Patch(patch) => patch.outputs.iter().collect(),
+        Request::Region(region) => region.outputs.iter().collect(),
+        Request::Busy(busy) => busy.outputs.iter().collect(),
+        Request::Record(record) => vec![&record.output],
+        _ => Vec::new(),
+    };
+    for bg in bgs.iter().filter(|bg| outputs.contains(&&bg.info.name)) {
+        if let Some(e) = &bg.buffer_error {
+            return Err(Error::Output(e.clone()));
+        }
+    }
+    Ok(())
 }
 
 fn get_old_imgs(bgs: &mut RefMut<Vec<Bg>>, imgs: &[(Img, Vec<String>)]) -> Vec<ImgWithDim> {
+    end_busy(bgs, imgs.iter().flat_map(|img| &img.1));
     let mut v = Vec::with_capacity(imgs.len());
 
     for (img, outputs) in imgs {
@@ -531,25 +1781,663 @@ fn get_old_imgs(bgs: &mut RefMut<Vec<Bg>>, imgs: &[(Img, Vec<String>)]) -> Vec<I
     v
 }
 
-fn handle_recv_img(bgs: &mut RefMut<Vec<Bg>>, msg: &(Vec<String>, ReadiedPack)) {
-    let (outputs, img) = msg;
-    if outputs.is_empty() {
-        warn!("Received empty list of outputs from processor, which should be impossible");
+/// Computes where the first output of every request is in the area covered by all of them, so that
+/// transitions can travel across the outputs as if they were a single one
+fn get_layouts(bgs: &[Bg], imgs: &[(Img, Vec<String>)]) -> Vec<Layout> {
+    let geometries: Vec<_> = imgs
+        .iter()
+        .filter_map(|(_, outputs)| bgs.iter().find(|bg| bg.info.name == outputs[0]))
+        .map(|bg| {
+            let (real, dim) = (bg.info.real_dim(), bg.info.dim);
+            // outputs drawn at `--max-resolution` have fewer pixels than their scale tells
+            let scale = (
+                real.0 as f64 / dim.0.max(1) as f64,
+                real.1 as f64 / dim.1.max(1) as f64,
+            );
+            (bg.position(), dim, scale)
+        })
+        .collect();
+
+    let (mut min_x, mut min_y, mut max_x, mut max_y) = (i32::MAX, i32::MAX, i32::MIN, i32::MIN);
+    for ((x, y), (width, height), _) in &geometries {
+        min_x = min_x.min(*x);
+        min_y = min_y.min(*y);
+        max_x = max_x.max(x + *width as i32);
+        max_y = max_y.max(y + *height as i32);
     }
-    bgs.iter_mut()
-        .filter(|bg| outputs.contains(&bg.info.name))
-        .for_each(|bg| bg.draw(img));
+
+    geometries
+        .into_iter()
+        .map(|((x, y), (_, height), scale)| Layout {
+            // our transitions have the y axis growing upwards
+            offset: ((x - min_x) as u32, (max_y - y - height as i32) as u32),
+            canvas: ((max_x - min_x) as u32, (max_y - min_y) as u32),
+            scale,
+        })
+        .collect()
 }
 
-//TODO: error when no output was valid
-fn clear_outputs(bgs: &mut RefMut<Vec<Bg>>, clear: &Clear, proc: &mut Processor) -> Answer {
-    proc.stop_animations(&clear.outputs);
-    if clear.outputs.is_empty() {
-        bgs.iter_mut().for_each(|bg| bg.clear(clear.color));
-    } else {
+/// Displays what `bg` displayed last time, if it is cached: an image, a color or a generator
+fn restore_cached_img(bg: &mut Bg, processor: &mut Processor) {
+    if bg.buffer_error.is_some() {
+        return;
+    }
+    let cached = match utils::cache::load(&bg.info.name) {
+        Ok(Some(cached)) => cached,
+        Ok(None) => {
+            info!("failed to find cached image for monitor '{}'", bg.info.name);
+            return;
+        }
+        Err(e) => {
+            error!("failed to load cached image: {e}");
+            return;
+        }
+    };
+    match cached {
+        Cached::Img(img, animation) => {
+            let info = bg.info.clone();
+            let cached = (img, animation);
+            if let Some(path) = processor.import_cached_img(info, bg.get_current_img_mut(), cached)
+            {
+                bg.info.img = BgImg::Img(path);
+            }
+        }
+        Cached::Color(color) => bg.clear(color),
+        Cached::Generated(generator) => {
+            bg.info.img = BgImg::Generated(generator.name().to_string());
+            let canvas = (bg.get_current_img().into(), bg.info.real_dim());
+            if let Answer::Err(e) =
+                processor.generate(generator, vec![(canvas, vec![bg.info.name.clone()])])
+            {
+                error!("failed to restore the generator of {}: {e}", bg.info.name);
+            }
+        }
+    }
+}
+
+fn handle_recv_img(bgs: &mut RefMut<Vec<Bg>>, output: &str, img: &ReadiedPack) {
+    match bgs.iter_mut().find(|bg| bg.info.name == output) {
+        Some(bg) => bg.draw(img),
+        None => debug!("Received frame for output {output}, which no longer exists"),
+    }
+}
+
+fn average_colors(bgs: &mut RefMut<Vec<Bg>>, colors: Colors) -> Answer {
+    if let Some(output) = colors
+        .outputs
+        .iter()
+        .find(|output| !bgs.iter().any(|bg| bg.info.name == **output))
+    {
+        return Answer::Err(Error::Output(format!("Output {output} doesn't exist")));
+    }
+    let mut answer = Vec::new();
+    for bg in bgs
+        .iter_mut()
+        .filter(|bg| bg.info.is_requested(&colors.outputs))
+    {
+        let (name, dim) = (bg.info.name.clone(), bg.info.real_dim());
+        match ambient::colors(&name, bg.get_current_img(), dim, colors.grid) {
+            Ok(output) => answer.push(output),
+            Err(e) => return Answer::Err(e),
+        }
+    }
+    Answer::Colors(answer)
+}
+
+fn record_output(bgs: &mut RefMut<Vec<Bg>>, record: Record) -> Answer {
+    let bg = match bgs.iter_mut().find(|bg| bg.info.name == record.output) {
+        Some(bg) => bg,
+        None => {
+            return Answer::Err(Error::Output(format!(
+                "Output {} doesn't exist",
+                record.output
+            )))
+        }
+    };
+    if bg.recorder.as_ref().is_some_and(Recorder::is_recording) {
+        return Answer::Err(Error::Conflict(format!(
+            "Output {} is already being recorded",
+            record.output
+        )));
+    }
+    let dim = bg.info.real_dim();
+    let first = bg.get_current_img().to_vec();
+    info!("Recording {} to {:?}", record.output, record.path);
+    match Recorder::start(record, dim, first) {
+        Ok(recorder) => {
+            bg.recorder = Some(recorder);
+            Answer::Ok
+        }
+        Err(e) => Answer::Err(e),
+    }
+}
+
+fn generate_bgs(bgs: &mut RefMut<Vec<Bg>>, generate: Generate, proc: &mut Processor) -> Answer {
+    let unknown: Vec<&str> = generate
+        .outputs
+        .iter()
+        .filter(|output| !bgs.iter().any(|bg| bg.info.name == **output))
+        .map(String::as_str)
+        .collect();
+    if !unknown.is_empty() {
+        return Answer::Err(Error::Output(format!(
+            "Outputs {} don't exist",
+            unknown.join(", ")
+        )));
+    }
+    // outputs with the same dimensions that are displaying the same thing can share a generator
+    let mut requests: Vec<(ImgWithDim, Vec<String>)> = Vec::new();
+    let mut current_imgs: Vec<BgImg> = Vec::new();
+    for bg in bgs
+        .iter_mut()
+        .filter(|bg| bg.buffer_error.is_none())
+        .filter(|bg| bg.info.is_requested(&generate.outputs))
+    {
+        let dim = bg.info.real_dim();
+        let current_img = std::mem::replace(
+            &mut bg.info.img,
+            BgImg::Generated(generate.generator.name().to_string()),
+        );
+        match requests
+            .iter_mut()
+            .zip(&current_imgs)
+            .find(|(((_, d), _), img)| *d == dim && **img == current_img)
+        {
+            Some((request, _)) => request.1.push(bg.info.name.clone()),
+            None => {
+                requests.push((
+                    (bg.get_current_img().into(), dim),
+                    vec![bg.info.name.clone()],
+                ));
+                current_imgs.push(current_img);
+            }
+        }
+    }
+
+    if requests.is_empty() {
+        return Answer::Err(Error::Output(
+            "none of the requested outputs are valid".to_string(),
+        ));
+    }
+    let generated: Vec<String> = requests
+        .iter()
+        .flat_map(|(_, outputs)| outputs)
+        .cloned()
+        .collect();
+    let answer = proc.generate(generate.generator.clone(), requests);
+    if matches!(answer, Answer::Ok) {
+        // so that the next daemon starts the generator again
+        if let Err(e) = utils::cache::store_generator(&generate.generator, &generated) {
+            error!(
+                "failed to cache the generator of {}: {e}",
+                generated.join(", ")
+            );
+        }
+    }
+    answer
+}
+
+fn manage_groups(bgs: &[Bg], groups: &mut Groups, command: GroupCommand) -> Answer {
+    match command {
+        GroupCommand::Create { name, outputs } => {
+            if bgs.iter().any(|bg| bg.info.name == name) {
+                return Answer::Err(Error::Conflict(format!(
+                    "{name} is already the name of an output"
+                )));
+            }
+            if outputs.is_empty() {
+                return Answer::Err(Error::InvalidRequest(
+                    "groups need at least one output".to_string(),
+                ));
+            }
+            match groups.iter_mut().find(|(group, _)| *group == name) {
+                Some((_, members)) => *members = outputs,
+                None => groups.push((name, outputs)),
+            }
+            Answer::Ok
+        }
+        GroupCommand::Delete(name) => match groups.iter().position(|(group, _)| *group == name) {
+            Some(i) => {
+                groups.remove(i);
+                Answer::Ok
+            }
+            None => Answer::Err(Error::InvalidRequest(format!("Group {name} doesn't exist"))),
+        },
+        GroupCommand::List => Answer::Groups(groups.clone()),
+    }
+}
+
+fn pin_output(bgs: &mut RefMut<Vec<Bg>>, pin: Pin) -> Answer {
+    match bgs.iter_mut().find(|bg| bg.info.name == pin.output) {
+        Some(bg) => {
+            bg.info.pinned = pin.pinned;
+            Answer::Ok
+        }
+        None => Answer::Err(Error::Output(format!(
+            "Output {} doesn't exist",
+            pin.output
+        ))),
+    }
+}
+
+fn follow_output(bgs: &mut RefMut<Vec<Bg>>, follow: Follow) -> Answer {
+    if let Some(source) = &follow.source {
+        if !bgs.iter().any(|bg| &bg.info.name == source) {
+            return Answer::Err(Error::Output(format!("Output {source} doesn't exist")));
+        }
+        // make sure we won't end up with outputs following each other in circles
+        let mut current = Some(source);
+        while let Some(output) = current {
+            if *output == follow.output {
+                return Answer::Err(Error::Conflict(format!(
+                    "{} can't follow {source}, because {source} already follows it",
+                    follow.output
+                )));
+            }
+            current = bgs
+                .iter()
+                .find(|bg| &bg.info.name == output)
+                .and_then(|bg| bg.info.following.as_ref());
+        }
+    }
+
+    match bgs.iter_mut().find(|bg| bg.info.name == follow.output) {
+        Some(bg) => {
+            bg.info.following = follow.source;
+            Answer::Ok
+        }
+        None => Answer::Err(Error::Output(format!(
+            "Output {} doesn't exist",
+            follow.output
+        ))),
+    }
+}
+
+fn show_busy(bgs: &mut RefMut<Vec<Bg>>, busy: Busy) -> Answer {
+    for bg in bgs
+        .iter_mut()
+        .filter(|bg| bg.info.is_requested(&busy.outputs))
+    {
+        match busy.progress {
+            Some(progress) => bg.show_busy(progress),
+            None => bg.end_busy(),
+        }
+    }
+    Answer::Ok
+}
+
+/// Forgets the cached images of the requested outputs, and reconfigures those whose scale changed
+/// without us noticing. The client then sends their images again, read and resized anew
+fn refresh_outputs(bgs: &mut RefMut<Vec<Bg>>, refresh: Refresh) -> Answer {
+    let mut refreshed = Vec::new();
+    for bg in bgs
+        .iter_mut()
+        .filter(|bg| bg.info.is_requested(&refresh.outputs))
+    {
+        let (width, height) = bg.info.dim;
+        if (width, height) != (0, 0)
+            && get_surface_scale_factor(&bg.surface) != bg.info.scale_factor
+        {
+            // handled with the other output events, before the client's next request
+            bg.next_render_event
+                .set(Some(RenderEvent::Configure { width, height }));
+        }
+        refreshed.push(bg.info.name.clone());
+    }
+    if refreshed.is_empty() {
+        return Answer::Err(Error::Output(
+            "none of the requested outputs exist".to_string(),
+        ));
+    }
+    if let Err(e) = utils::cache::forget(&refreshed) {
+        return Answer::Err(Error::Other(e));
+    }
+    info!("Refreshing {}", refreshed.join(", "));
+    Answer::Info(
         bgs.iter_mut()
-            .filter(|bg| clear.outputs.contains(&bg.info.name))
-            .for_each(|bg| bg.clear(clear.color));
+            .filter(|bg| refreshed.contains(&bg.info.name))
+            .map(Bg::query)
+            .collect(),
+    )
+}
+
+/// Removes the busy indicator from `outputs`, so that what we draw next isn't covered by it
+fn end_busy<'a>(bgs: &mut RefMut<Vec<Bg>>, outputs: impl IntoIterator<Item = &'a String>) {
+    for output in outputs {
+        if let Some(bg) = bgs.iter_mut().find(|bg| &bg.info.name == output) {
+            bg.end_busy();
+        }
+    }
+}
+
+fn patch_outputs(bgs: &mut RefMut<Vec<Bg>>, patch: &Patch) -> Answer {
+    if patch.img.len() != patch.dim.0 as usize * patch.dim.1 as usize * 4 {
+        return Answer::Err(Error::InvalidRequest(
+            "patch's image doesn't match its dimensions".to_string(),
+        ));
+    }
+    let mut patched = false;
+    for bg in bgs
+        .iter_mut()
+        .filter(|bg| bg.buffer_error.is_none())
+        .filter(|bg| bg.info.is_requested(&patch.outputs))
+    {
+        bg.patch(patch);
+        patched = true;
+    }
+    if patched {
+        Answer::Ok
+    } else {
+        Answer::Err(Error::Output(
+            "none of the requested outputs are valid".to_string(),
+        ))
+    }
+}
+
+fn region_outputs(bgs: &[Bg], region: Region, proc: &mut Processor) -> Answer {
+    // outputs with the same dimensions share the region's thread
+    let mut requests: Vec<(Window, Vec<String>)> = Vec::new();
+    for bg in bgs
+        .iter()
+        .filter(|bg| bg.buffer_error.is_none())
+        .filter(|bg| bg.info.is_requested(&region.outputs))
+    {
+        let dim = bg.info.real_dim();
+        let (left, top) = bg.place(region.dim, &region.pos);
+        let window = Window {
+            offset: (left.max(0) as u32, top.max(0) as u32),
+            dim: region.dim,
+            buf_width: dim.0,
+        };
+        if left < 0 || top < 0 || !window.fits(dim) {
+            return Answer::Err(Error::InvalidRequest(format!(
+                "region doesn't fit in output {}",
+                bg.info.name
+            )));
+        }
+        match requests.iter_mut().find(|(w, _)| *w == window) {
+            Some((_, outputs)) => outputs.push(bg.info.name.clone()),
+            None => requests.push((window, vec![bg.info.name.clone()])),
+        }
+    }
+
+    if requests.is_empty() {
+        return Answer::Err(Error::Output(
+            "none of the requested outputs are valid".to_string(),
+        ));
+    }
+    // the processor checks the animation, so if it is invalid the first region fails, before any
+    // of them plays
+    let animation = Arc::new(region.animation);
+    for (window, outputs) in requests {
+        if let Answer::Err(e) = proc.region(Arc::clone(&animation), outputs, window) {
+            return Answer::Err(e);
+        }
+    }
+    Answer::Ok
+}
+
+/// Finds what was set on `bg`, before any adjustments. Images are read back from the cache
+fn unadjusted_img(bg: &Bg) -> Result<Vec<u8>, Error> {
+    let dim = bg.info.real_dim();
+    let size = dim.0 as usize * dim.1 as usize;
+    let path = match &bg.info.img {
+        BgImg::Color(color) => return Ok([color[2], color[1], color[0], 255].repeat(size)),
+        BgImg::Img(path) => path,
+        BgImg::Generated(_) => {
+            return Err(Error::Conflict(format!(
+                "{} is displaying a generated background",
+                bg.info.name
+            )))
+        }
+    };
+    match utils::cache::load(&bg.info.name)? {
+        Some(Cached::Img(_, Some(_))) => Err(Error::Conflict(format!(
+            "{} is displaying an animation",
+            bg.info.name
+        ))),
+        Some(Cached::Img(img, None)) if img.path == *path && img.img.len() == size * 4 => {
+            Ok(img.img)
+        }
+        _ => Err(Error::Other(format!(
+            "the image displayed on {} isn't cached",
+            bg.info.name
+        ))),
+    }
+}
+
+/// The adjusted image of each of the requested outputs, and the output it goes to, or the answer
+/// to the request when there is nothing to transition to. The effect isn't applied yet
+fn adjusted_imgs(
+    bgs: &mut RefMut<Vec<Bg>>,
+    adjust: &Adjust,
+    proc: &mut Processor,
+) -> Result<Vec<(Img, Vec<String>)>, Answer> {
+    let requested: Vec<String> = bgs
+        .iter()
+        .filter(|bg| bg.info.is_requested(&adjust.outputs))
+        .map(|bg| bg.info.name.clone())
+        .collect();
+    if adjust.source == AdjustSource::Regions {
+        if adjust.match_brightness {
+            return Err(Answer::Err(Error::InvalidRequest(
+                "brightness can only be matched for the base images".to_string(),
+            )));
+        }
+        if adjust.effect.is_some() {
+            return Err(Answer::Err(Error::InvalidRequest(
+                "effects can only be applied to the base images".to_string(),
+            )));
+        }
+        return Err(adjust_regions(bgs, adjust, &requested, proc));
+    }
+    let mut requests: Vec<(Img, Vec<String>)> = Vec::new();
+    let mut animated = false;
+    let mut error = None;
+    for bg in bgs
+        .iter_mut()
+        .filter(|bg| bg.buffer_error.is_none())
+        .filter(|bg| bg.info.is_requested(&adjust.outputs))
+    {
+        let name = bg.info.name.clone();
+        if adjust.effect.is_some() && proc.is_animated(&name) {
+            let e = Error::Conflict(format!(
+                "{name} is playing an animation, effects need stills"
+            ));
+            warn!("can't adjust {name}: {e}");
+            error = Some(e);
+            continue;
+        }
+        // animations are adjusted as they play, instead of transitioning
+        match proc.adjust_animation(&name, &requested, adjust.adjustment, bg.get_current_img()) {
+            Ok(true) => {
+                animated = true;
+                continue;
+            }
+            Ok(false) => (),
+            Err(e) => {
+                warn!("can't adjust {name}: {e}");
+                error = Some(e);
+                continue;
+            }
+        }
+        match unadjusted_img(bg) {
+            Ok(mut img) => {
+                adjust.adjustment.apply(&mut img);
+                let path = match &bg.info.img {
+                    BgImg::Img(path) => path.clone(),
+                    _ => std::path::PathBuf::new(),
+                };
+                requests.push((Img { path, img }, vec![bg.info.name.clone()]));
+            }
+            Err(e) => {
+                warn!("can't adjust {}: {e}", bg.info.name);
+                error = Some(e);
+            }
+        }
+    }
+
+    if requests.is_empty() {
+        return Err(match error {
+            _ if animated => Answer::Ok,
+            Some(e) => Answer::Err(e),
+            None => Answer::Err(Error::Output(
+                "none of the requested outputs are valid".to_string(),
+            )),
+        });
+    }
+    Ok(requests)
+}
+
+/// Transitions to the adjusted images, each of which goes to a single output
+fn transition_adjusted(
+    bgs: &mut RefMut<Vec<Bg>>,
+    adjust: &Adjust,
+    mut requests: Vec<(Img, Vec<String>)>,
+    proc: &mut Processor,
+) -> Answer {
+    let mut old_imgs: Vec<ImgWithDim> = Vec::new();
+    for (_, outputs) in &requests {
+        if let Some(bg) = bgs.iter_mut().find(|bg| bg.info.name == outputs[0]) {
+            old_imgs.push((bg.get_current_img().into(), bg.info.real_dim()));
+        }
+    }
+    if adjust.match_brightness {
+        let luminances: Vec<f32> = requests
+            .iter()
+            .map(|(img, _)| luminance(&img.img))
+            .collect();
+        for ((img, _), matched) in requests.iter_mut().zip(match_brightness(&luminances)) {
+            matched.apply(&mut img.img);
+        }
+    }
+    let layouts = match adjust.transition.sync {
+        TransitionSync::None => Vec::new(),
+        TransitionSync::Layout => get_layouts(bgs, &requests),
+    };
+    proc.transition(&adjust.transition, requests, old_imgs, layouts)
+}
+
+/// Applies the effect called `name` to the adjusted images on a thread of its own, and leaves
+/// `Daemon::finish_effect` to transition to them once it's done
+fn start_effect(
+    bgs: &[Bg],
+    adjust: Adjust,
+    name: String,
+    requests: Vec<(Img, Vec<String>)>,
+    daemon_effect: &mut Option<PendingEffect>,
+    effects: &channel::Sender<Result<Vec<Vec<u8>>, Error>>,
+) -> Answer {
+    let dims: Vec<(u32, u32)> = requests
+        .iter()
+        .filter_map(|(_, outputs)| bgs.iter().find(|bg| bg.info.name == outputs[0]))
+        .map(|bg| bg.info.real_dim())
+        .collect();
+    let (outputs, imgs) = requests
+        .into_iter()
+        .zip(dims)
+        .map(|((img, mut outputs), dim)| ((img.path, outputs.remove(0), dim), (img.img, dim)))
+        .unzip();
+    let done = effects.clone();
+    // we may be exiting by the time the effect is done, in which case nobody is waiting for it
+    match effect::spawn(name, imgs, move |imgs| drop(done.send(imgs))) {
+        Ok(()) => {
+            *daemon_effect = Some(PendingEffect {
+                adjust,
+                outputs,
+                client: None,
+            });
+            Answer::Ok
+        }
+        Err(e) => Answer::Err(e),
+    }
+}
+
+/// Adjusts the regions playing on the requested outputs, as they play
+fn adjust_regions(
+    bgs: &mut RefMut<Vec<Bg>>,
+    adjust: &Adjust,
+    requested: &[String],
+    proc: &mut Processor,
+) -> Answer {
+    let mut count = 0;
+    for bg in bgs
+        .iter_mut()
+        .filter(|bg| bg.buffer_error.is_none())
+        .filter(|bg| requested.contains(&bg.info.name))
+    {
+        let name = bg.info.name.clone();
+        let dim = bg.info.real_dim();
+        match proc.adjust_regions(
+            &name,
+            requested,
+            adjust.adjustment,
+            (bg.get_current_img(), dim),
+        ) {
+            Ok(n) => count += n,
+            Err(e) => return Answer::Err(e),
+        }
+    }
+    if count == 0 {
+        return Answer::Err(Error::Conflict(
+            "there are no regions playing on the requested outputs".to_string(),
+        ));
     }
     Answer::Ok
 }
+
+fn clear_outputs(bgs: &mut RefMut<Vec<Bg>>, clear: Clear, proc: &mut Processor) -> Answer {
+    // just like with images, outputs with the same dimensions that are displaying the same thing
+    // can share the new image, unless we must take their place in the layout into account
+    let merge_outputs = matches!(clear.transition.sync, TransitionSync::None);
+    let mut requests: Vec<(Img, Vec<String>)> = Vec::new();
+    let mut old_imgs: Vec<ImgWithDim> = Vec::new();
+    let mut current_imgs: Vec<BgImg> = Vec::new();
+    for bg in bgs
+        .iter_mut()
+        .filter(|bg| bg.buffer_error.is_none())
+        .filter(|bg| bg.info.is_requested(&clear.outputs))
+    {
+        let dim = bg.info.real_dim();
+        let current_img = std::mem::replace(&mut bg.info.img, BgImg::Color(clear.color));
+        match requests
+            .iter_mut()
+            .zip(&old_imgs)
+            .zip(&current_imgs)
+            .find(|((_, (_, d)), img)| merge_outputs && *d == dim && **img == current_img)
+        {
+            Some(((request, _), _)) => request.1.push(bg.info.name.clone()),
+            None => {
+                let size = dim.0 as usize * dim.1 as usize;
+                let color = [clear.color[2], clear.color[1], clear.color[0], 255];
+                let img = Img {
+                    path: std::path::PathBuf::new(),
+                    img: color.repeat(size),
+                };
+                requests.push((img, vec![bg.info.name.clone()]));
+                old_imgs.push((bg.get_current_img().into(), dim));
+                current_imgs.push(current_img);
+            }
+        }
+    }
+
+    if requests.is_empty() {
+        return Answer::Err(Error::Output(
+            "none of the requested outputs are valid".to_string(),
+        ));
+    }
+    // so that the next daemon brings back the color, instead of the image it replaced
+    let cleared: Vec<String> = requests
+        .iter()
+        .flat_map(|(_, outputs)| outputs)
+        .cloned()
+        .collect();
+    if let Err(e) = utils::cache::store_color(clear.color, &cleared) {
+        error!("failed to cache the color of {}: {e}", cleared.join(", "));
+    }
+    let layouts = match clear.transition.sync {
+        TransitionSync::None => Vec::new(),
+        TransitionSync::Layout => get_layouts(bgs, &requests),
+    };
+    proc.transition(&clear.transition, requests, old_imgs, layouts)
+}
@@ -0,0 +1,160 @@
+//! `swww transition manual`: transitions driven by another program, like a slider or a gesture,
+//! instead of by time. The transition stays where the last `swww transition set` put it, until
+//! `swww transition end` snaps it to whichever images it is closest to
+use log::{debug, error, info};
+
+use std::cell::RefMut;
+
+use swww_protocol::{
+    error::Error, Answer, BgImg, ImageRequest, Img, ManualCommand, TransitionSync,
+};
+use utils::{cache, comp_decomp::ReadiedPack};
+
+use swww_render::transition::{Runner, TransitionType};
+
+use crate::{processor::Processor, Bg};
+
+/// The transition in progress
+pub struct Manual {
+    /// One for every image of the request, along with the outputs it goes to
+    groups: Vec<(Runner, Img, Vec<String>)>,
+    /// Where the last `Set` put the transition, from 0 to 1
+    progress: f32,
+}
+
+impl Manual {
+    /// Takes `outputs` out of the transition, because something else was displayed on them
+    pub fn forget(&mut self, outputs: &[String]) {
+        for (_, _, group) in &mut self.groups {
+            group.retain(|output| !outputs.contains(output));
+        }
+        self.groups.retain(|(_, _, outputs)| !outputs.is_empty());
+    }
+}
+
+pub fn handle(
+    bgs: &mut RefMut<Vec<Bg>>,
+    manual: &mut Option<Manual>,
+    proc: &mut Processor,
+    command: ManualCommand,
+) -> Answer {
+    match command {
+        ManualCommand::Start(request) => {
+            if manual.is_some() {
+                return Answer::Err(Error::Conflict(
+                    "a manual transition is already in progress, end it with `swww transition end`"
+                        .to_string(),
+                ));
+            }
+            match start(bgs, proc, request) {
+                Ok(started) => {
+                    *manual = Some(started);
+                    Answer::Ok
+                }
+                Err(e) => Answer::Err(e),
+            }
+        }
+        ManualCommand::Set(progress) => match manual {
+            Some(manual) => {
+                manual.progress = progress.clamp(0.0, 1.0);
+                draw(bgs, manual, manual.progress);
+                Answer::Ok
+            }
+            None => not_started(),
+        },
+        ManualCommand::End => match manual.take() {
+            Some(manual) => {
+                end(bgs, manual);
+                Answer::Ok
+            }
+            None => not_started(),
+        },
+    }
+}
+
+fn not_started() -> Answer {
+    Answer::Err(Error::Conflict(
+        "there is no manual transition in progress, start one with `swww transition manual`"
+            .to_string(),
+    ))
+}
+
+fn start(
+    bgs: &mut RefMut<Vec<Bg>>,
+    proc: &mut Processor,
+    (transition, imgs): ImageRequest,
+) -> Result<Manual, Error> {
+    crate::end_busy(bgs, imgs.iter().flat_map(|img| &img.1));
+    let mut layouts = match transition.sync {
+        TransitionSync::None => Vec::new(),
+        TransitionSync::Layout => crate::get_layouts(bgs, &imgs),
+    }
+    .into_iter();
+    let plugin = match &transition.transition_type {
+        TransitionType::Plugin(name) => Some(crate::plugin::load(name)?),
+        _ => None,
+    };
+    let mut groups = Vec::with_capacity(imgs.len());
+    for (img, outputs) in imgs {
+        let Some(bg) = bgs
+            .iter_mut()
+            .find(|bg| Some(&bg.info.name) == outputs.first())
+        else {
+            return Err(Error::Output(
+                "Daemon received request for outputs that don't exist".to_string(),
+            ));
+        };
+        let dim = bg.info.real_dim();
+        let old_img: Box<[u8]> = bg.get_current_img().into();
+        if old_img.len() != img.img.len() {
+            return Err(Error::InvalidRequest(format!(
+                "Output and image have different sizes: {} vs {}",
+                old_img.len(),
+                img.img.len()
+            )));
+        }
+        let mut runner = Runner::new(old_img, dim, transition.clone(), layouts.next());
+        if let Some(plugin) = &plugin {
+            runner = runner.with_plugin(plugin.frame(dim, &transition));
+        }
+        groups.push((runner, img, outputs));
+    }
+    // whatever was playing would draw over the transition
+    for (_, _, outputs) in &groups {
+        proc.stop_animations(outputs);
+    }
+    info!("Started manual transition");
+    Ok(Manual {
+        groups,
+        progress: 0.0,
+    })
+}
+
+fn draw(bgs: &mut RefMut<Vec<Bg>>, manual: &Manual, progress: f32) {
+    for (runner, img, outputs) in &manual.groups {
+        let frame = ReadiedPack::keyframe(&runner.frame_at(&img.img, progress));
+        for bg in bgs.iter_mut().filter(|bg| outputs.contains(&bg.info.name)) {
+            bg.draw(&frame);
+        }
+    }
+    debug!("Drew manual transition at {progress:.3}");
+}
+
+fn end(bgs: &mut RefMut<Vec<Bg>>, manual: Manual) {
+    let finished = manual.progress >= 0.5;
+    draw(bgs, &manual, if finished { 1.0 } else { 0.0 });
+    if !finished {
+        info!("Cancelled manual transition");
+        return;
+    }
+    for (_, img, outputs) in &manual.groups {
+        for bg in bgs.iter_mut().filter(|bg| outputs.contains(&bg.info.name)) {
+            bg.info.img = BgImg::Img(img.path.clone());
+        }
+        // like the images of `swww img`, so that they come back when the daemon starts
+        if let Err(e) = cache::store_img(img, outputs) {
+            error!("failed to cache image: {e}");
+        }
+    }
+    info!("Finished manual transition");
+}
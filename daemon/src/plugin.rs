@@ -0,0 +1,170 @@
+//! `--transition-type plugin:NAME`: transitions drawn by shared libraries in
+//! `$XDG_CONFIG_HOME/swww/transitions` (or `~/.config/swww/transitions`). The library is loaded
+//! again every time a transition starts, so rebuilding a plugin takes effect on the next one,
+//! without restarting the daemon. Plugins export, with the C ABI:
+//!
+//! ```c
+//! uint32_t swww_transition_abi(void); // returns 1
+//! int swww_transition_frame(const uint8_t *old, const uint8_t *new, uint8_t *out,
+//!                           uint32_t width, uint32_t height, float progress,
+//!                           float angle, float x, float y);
+//! ```
+//!
+//! `old`, `new` and `out` are `width * height` pixels in bgra. `progress` goes from 0 to 1, after
+//! the bezier curve. `angle` is `--transition-angle`, in degrees, and `x` and `y` are
+//! `--transition-pos`, in pixels from the output's top left corner, so that plugins can be steered
+//! like the built-in transitions. Returning anything other than 0 leaves `out` as the plain fade
+//! would draw it
+use std::path::PathBuf;
+
+use swww_protocol::error::Error;
+use swww_render::transition::{PluginFrame, Transition};
+
+/// The version of the interface above. Plugins built for another one are refused
+#[cfg(feature = "plugins")]
+const ABI: u32 = 1;
+
+pub fn dir() -> Result<PathBuf, Error> {
+    let config = match std::env::var_os("XDG_CONFIG_HOME") {
+        Some(dir) => PathBuf::from(dir),
+        None => match std::env::var_os("HOME") {
+            Some(home) => PathBuf::from(home).join(".config"),
+            None => {
+                return Err(Error::Other(
+                    "failed to read both XDG_CONFIG_HOME and HOME env vars".to_string(),
+                ))
+            }
+        },
+    };
+    Ok(config.join("swww/transitions"))
+}
+
+/// Loads the plugin called `name`, checking that it speaks our interface. Since this can fail,
+/// transitions load their plugin before they change anything, and then draw every output with it
+pub fn load(name: &str) -> Result<Plugin, Error> {
+    if !cfg!(feature = "plugins") {
+        return Err(Error::Unsupported(
+            "swww-daemon was built without the 'plugins' feature".to_string(),
+        ));
+    }
+    if name.is_empty() || name.contains('/') {
+        return Err(Error::InvalidRequest(format!(
+            "invalid transition plugin name {name:?}"
+        )));
+    }
+    Plugin::open(dir()?.join(format!("{name}.so")))
+}
+
+/// A loaded plugin. Clones share the library, which stays loaded until the last of them is dropped
+#[cfg(feature = "plugins")]
+#[derive(Clone)]
+pub struct Plugin {
+    library: std::sync::Arc<libloading::Library>,
+    frame: Frame,
+}
+
+#[cfg(not(feature = "plugins"))]
+#[derive(Clone)]
+pub enum Plugin {}
+
+#[cfg(feature = "plugins")]
+type Frame =
+    unsafe extern "C" fn(*const u8, *const u8, *mut u8, u32, u32, f32, f32, f32, f32) -> i32;
+
+impl Plugin {
+    /// Draws the frames of `transition` on outputs of `dim`
+    pub fn frame(&self, dim: (u32, u32), transition: &Transition) -> Box<PluginFrame> {
+        let (x, y) = transition.pos.to_pixel(dim);
+        // our positions grow upwards, but the rows of the images go downwards
+        let geometry = (transition.angle as f32, x, dim.1 as f32 - y);
+        self.draw(dim, geometry)
+    }
+
+    #[cfg(feature = "plugins")]
+    fn open(path: PathBuf) -> Result<Self, Error> {
+        use libloading::{Library, Symbol};
+        use log::debug;
+
+        type Abi = unsafe extern "C" fn() -> u32;
+
+        let failed = |e: libloading::Error| {
+            Error::Other(format!(
+                "failed to load transition plugin {}: {e}",
+                path.display()
+            ))
+        };
+        // SAFETY: plugins are the user's own code, which they asked us to run
+        let library = unsafe { Library::new(&path) }.map_err(failed)?;
+        let abi = unsafe {
+            let abi: Symbol<Abi> = library.get(b"swww_transition_abi\0").map_err(failed)?;
+            abi()
+        };
+        if abi != ABI {
+            return Err(Error::Unsupported(format!(
+                "transition plugin {} was built for version {abi} of the plugin interface, \
+                but swww-daemon speaks version {ABI}",
+                path.display()
+            )));
+        }
+        let frame: Frame = unsafe {
+            *library
+                .get::<Frame>(b"swww_transition_frame\0")
+                .map_err(failed)?
+        };
+        debug!("loaded transition plugin {}", path.display());
+        Ok(Self {
+            library: std::sync::Arc::new(library),
+            frame,
+        })
+    }
+
+    #[cfg(not(feature = "plugins"))]
+    fn open(_: PathBuf) -> Result<Self, Error> {
+        unreachable!("we return before opening plugins without the feature")
+    }
+
+    /// `geometry` is the angle, and the position's x and y, as plugins get them
+    #[cfg(feature = "plugins")]
+    fn draw(
+        &self,
+        (width, height): (u32, u32),
+        (angle, x, y): (f32, f32, f32),
+    ) -> Box<PluginFrame> {
+        use log::warn;
+        use swww_render::transition::blend;
+
+        // the function pointer is only valid for as long as the library stays loaded
+        let Self { library, frame } = self.clone();
+        let len = width as usize * height as usize * 4;
+        Box::new(move |old, new, progress, out| {
+            let _library = &library;
+            if old.len() != len || new.len() != len || out.len() != len {
+                out.copy_from_slice(&blend(old, new, progress));
+                return;
+            }
+            // SAFETY: every buffer holds the `width * height` pixels the interface promises
+            let status = unsafe {
+                frame(
+                    old.as_ptr(),
+                    new.as_ptr(),
+                    out.as_mut_ptr(),
+                    width,
+                    height,
+                    progress,
+                    angle,
+                    x,
+                    y,
+                )
+            };
+            if status != 0 {
+                warn!("transition plugin failed with {status}, fading instead");
+                out.copy_from_slice(&blend(old, new, progress));
+            }
+        })
+    }
+
+    #[cfg(not(feature = "plugins"))]
+    fn draw(&self, _: (u32, u32), _: (f32, f32, f32)) -> Box<PluginFrame> {
+        match *self {}
+    }
+}
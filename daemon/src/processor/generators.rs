@@ -0,0 +1,933 @@
+//! Procedurally generated backgrounds.
+//!
+//! Every generator simply draws whole frames into a canvas. We then take care of figuring out what
+//! changed since the last frame and sending only that to the main thread, so generators that
+//! change slowly are very cheap to display.
+
+use std::{
+    io::{Read, Write},
+    process::{self, Stdio},
+    sync::mpsc,
+    thread,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+use log::{debug, error};
+use swww_protocol::{Position, TestPattern};
+use utils::comp_decomp::ReadiedPack;
+
+use super::{send_frame, should_stop, FrameSender};
+
+pub struct Generator {
+    canvas: Box<[u8]>,
+    dimensions: (u32, u32),
+    generator: swww_protocol::Generator,
+}
+
+impl Generator {
+    pub fn new(
+        canvas: Box<[u8]>,
+        dimensions: (u32, u32),
+        generator: swww_protocol::Generator,
+    ) -> Self {
+        Self {
+            canvas,
+            dimensions,
+            generator,
+        }
+    }
+
+    pub fn execute(
+        self,
+        outputs: &mut Vec<String>,
+        sender: &FrameSender,
+        stop_recv: &mpsc::Receiver<Vec<String>>,
+    ) {
+        debug!("Starting {} generator", self.generator.name());
+        match self.generator.clone() {
+            swww_protocol::Generator::Gradient {
+                colors,
+                angle,
+                period,
+                fps,
+            } => self.gradient(&colors, angle, period, fps, outputs, sender, stop_recv),
+            swww_protocol::Generator::Plasma {
+                colors,
+                scale,
+                speed,
+                fps,
+            } => self.plasma(&colors, scale, speed, fps, outputs, sender, stop_recv),
+            swww_protocol::Generator::Clock {
+                format,
+                font,
+                size,
+                color,
+                pos,
+            } => {
+                // we've already checked the font in `validate`
+                let font = fontdue::Font::from_bytes(font, fontdue::FontSettings::default())
+                    .expect("font should have been validated");
+                self.clock(
+                    &format, &font, size, color, &pos, outputs, sender, stop_recv,
+                )
+            }
+            swww_protocol::Generator::Life {
+                cell_size,
+                alive,
+                dead,
+                fps,
+            } => self.life(cell_size, alive, dead, fps, outputs, sender, stop_recv),
+            swww_protocol::Generator::External { command, fps } => {
+                self.external(&command, fps, outputs, sender, stop_recv)
+            }
+            swww_protocol::Generator::TestPattern { pattern } => {
+                let dimensions = self.dimensions;
+                self.run(outputs, sender, stop_recv, |canvas, _| {
+                    draw_test_pattern(canvas, dimensions, pattern);
+                    None
+                })
+            }
+        }
+    }
+
+    /// Checks whether the generator can actually be run, so that we can report errors back to the
+    /// client instead of failing in the generator thread
+    pub fn validate(generator: &swww_protocol::Generator) -> Result<(), String> {
+        match generator {
+            swww_protocol::Generator::Gradient { colors, .. }
+            | swww_protocol::Generator::Plasma { colors, .. } => {
+                if colors.is_empty() {
+                    return Err("at least one color is needed".to_string());
+                }
+            }
+            swww_protocol::Generator::Clock { format, font, .. } => {
+                if chrono::format::StrftimeItems::new(format)
+                    .any(|item| item == chrono::format::Item::Error)
+                {
+                    return Err(format!("invalid clock format: {format}"));
+                }
+                if let Err(e) =
+                    fontdue::Font::from_bytes(font.as_slice(), fontdue::FontSettings::default())
+                {
+                    return Err(format!("failed to load font: {e}"));
+                }
+            }
+            swww_protocol::Generator::Life { .. }
+            | swww_protocol::Generator::TestPattern { .. } => (),
+            swww_protocol::Generator::External { command, .. } => {
+                if command.is_empty() {
+                    return Err("no command was given to produce the frames".to_string());
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Keeps calling `draw` with the time elapsed since we started, and sends the resulting frames.
+    /// `draw` must return how long to wait until the next frame, or `None` if the image is done
+    fn run<F>(
+        mut self,
+        outputs: &mut Vec<String>,
+        sender: &FrameSender,
+        stop_recv: &mpsc::Receiver<Vec<String>>,
+        mut draw: F,
+    ) where
+        F: FnMut(&mut [u8], Duration) -> Option<Duration>,
+    {
+        let mut goal = self.canvas.to_vec();
+        let start = Instant::now();
+        let mut interval = Duration::ZERO;
+        loop {
+            let now = Instant::now();
+            let next = draw(&mut goal, start.elapsed());
+            let frame = ReadiedPack::new(&mut self.canvas, &goal, |cur, goal, _| *cur = *goal);
+            let timeout = interval.saturating_sub(now.elapsed());
+            let should_exit = if frame.is_empty() {
+                should_stop(outputs, timeout, stop_recv)
+            } else {
+                send_frame(frame, outputs, timeout, sender, stop_recv)
+            };
+            if should_exit {
+                debug!("Generator was interrupted!");
+                return;
+            }
+            match next {
+                Some(next) => interval = next,
+                None => {
+                    debug!("Generator has finished.");
+                    return;
+                }
+            }
+        }
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn gradient(
+        self,
+        colors: &[[u8; 3]],
+        angle: f64,
+        period: Option<Duration>,
+        fps: u8,
+        outputs: &mut Vec<String>,
+        sender: &FrameSender,
+        stop_recv: &mpsc::Receiver<Vec<String>>,
+    ) {
+        let (width, height) = (self.dimensions.0 as usize, self.dimensions.1 as usize);
+        let (sin, cos) = angle.to_radians().sin_cos();
+        // we project every pixel onto the gradient's direction. The projections go from `min` to
+        // `min + len`, so we can precompute the colors for every possible projection once per frame
+        let len = (width as f64 * cos.abs() + height as f64 * sin.abs()).max(1.0);
+        let min = (width as f64 * cos).min(0.0) + (height as f64 * sin).min(0.0);
+        let mut colors_lut = vec![[0u8; 4]; len as usize + 1];
+        let interval = Duration::from_nanos(1_000_000_000 / fps.max(1) as u64);
+
+        self.run(outputs, sender, stop_recv, |canvas, elapsed| {
+            let phase = match period {
+                Some(period) => elapsed.as_secs_f64() / period.as_secs_f64().max(f64::EPSILON),
+                None => 0.0,
+            };
+            for (i, color) in colors_lut.iter_mut().enumerate() {
+                *color = gradient_color(colors, i as f64 / len + phase, period.is_some());
+            }
+
+            for (i, row) in canvas.chunks_exact_mut(width * 4).enumerate() {
+                // our y axis grows upwards
+                let y = (height - 1 - i) as f64;
+                let row_start = y * sin - min;
+                for (x, pixel) in row.chunks_exact_mut(4).enumerate() {
+                    let projection = (row_start + x as f64 * cos) as usize;
+                    pixel.copy_from_slice(&colors_lut[projection.min(colors_lut.len() - 1)]);
+                }
+            }
+            period.map(|_| interval)
+        })
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn plasma(
+        self,
+        colors: &[[u8; 3]],
+        scale: f32,
+        speed: f32,
+        fps: u8,
+        outputs: &mut Vec<String>,
+        sender: &FrameSender,
+        stop_recv: &mpsc::Receiver<Vec<String>>,
+    ) {
+        // Noise is expensive, so we only sample it every CELL pixels and interpolate the rest.
+        // Since the plasma is very smooth, this is indistinguishable from sampling every pixel
+        const CELL: usize = 8;
+        let (width, height) = (self.dimensions.0 as usize, self.dimensions.1 as usize);
+        let (cols, rows) = (width / CELL + 2, height / CELL + 2);
+        let mut field = vec![0.0f32; cols * rows];
+        let palette: Vec<[u8; 4]> = (0..=255)
+            .map(|i| gradient_color(colors, i as f64 / 255.0, false))
+            .collect();
+        let scale = scale.max(1.0) as f64;
+        let interval = Duration::from_nanos(1_000_000_000 / fps.max(1) as u64);
+
+        self.run(outputs, sender, stop_recv, |canvas, elapsed| {
+            let z = elapsed.as_secs_f64() * speed as f64 * 0.25;
+            for (row, line) in field.chunks_exact_mut(cols).enumerate() {
+                for (col, value) in line.iter_mut().enumerate() {
+                    let (x, y) = ((col * CELL) as f64 / scale, (row * CELL) as f64 / scale);
+                    *value = fractal_noise(x, y, z) as f32;
+                }
+            }
+
+            for (y, line) in canvas.chunks_exact_mut(width * 4).enumerate() {
+                let (row, fy) = (y / CELL, (y % CELL) as f32 / CELL as f32);
+                for (x, pixel) in line.chunks_exact_mut(4).enumerate() {
+                    let (col, fx) = (x / CELL, (x % CELL) as f32 / CELL as f32);
+                    let i = row * cols + col;
+                    let top = field[i] + (field[i + 1] - field[i]) * fx;
+                    let bottom = field[i + cols] + (field[i + cols + 1] - field[i + cols]) * fx;
+                    let value = top + (bottom - top) * fy;
+                    let index = ((value + 1.0) * 127.5).clamp(0.0, 255.0) as usize;
+                    pixel.copy_from_slice(&palette[index]);
+                }
+            }
+
+            if speed == 0.0 {
+                None
+            } else {
+                Some(interval)
+            }
+        })
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn clock(
+        self,
+        format: &str,
+        font: &fontdue::Font,
+        size: f32,
+        color: [u8; 3],
+        pos: &Position,
+        outputs: &mut Vec<String>,
+        sender: &FrameSender,
+        stop_recv: &mpsc::Receiver<Vec<String>>,
+    ) {
+        let base = self.canvas.clone();
+        let dimensions = self.dimensions;
+        let (center_x, center_y) = pos.to_pixel(dimensions);
+        let mut last_text = String::new();
+
+        self.run(outputs, sender, stop_recv, |canvas, _| {
+            let now = chrono::Local::now();
+            let text = now.format(format).to_string();
+            if text != last_text {
+                canvas.copy_from_slice(&base);
+                // our positions have the y axis growing upwards, but images grow downwards
+                let center = (center_x, dimensions.1 as f32 - center_y);
+                draw_text(canvas, dimensions, &text, font, size, color, center);
+                last_text = text;
+            }
+            // check again when the next second starts
+            let subsec = Duration::from_nanos(chrono::Timelike::nanosecond(&now) as u64);
+            Some(Duration::from_secs(1).saturating_sub(subsec))
+        })
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn life(
+        self,
+        cell_size: u32,
+        alive: [u8; 3],
+        dead: [u8; 3],
+        fps: u8,
+        outputs: &mut Vec<String>,
+        sender: &FrameSender,
+        stop_recv: &mpsc::Receiver<Vec<String>>,
+    ) {
+        let cell_size = cell_size.max(1) as usize;
+        let width = self.dimensions.0 as usize;
+        let cols = width.div_ceil(cell_size);
+        let mut grid = seed_life(&self.canvas, width, cell_size);
+        let mut next = vec![false; grid.len()];
+        // canvas is in bgr, colors in rgb
+        let alive = [alive[2], alive[1], alive[0], 255];
+        let dead = [dead[2], dead[1], dead[0], 255];
+        let interval = Duration::from_nanos(1_000_000_000 / fps.max(1) as u64);
+        let mut first_frame = true;
+
+        self.run(outputs, sender, stop_recv, |canvas, _| {
+            if !first_frame {
+                step_life(&grid, &mut next, cols);
+                if next == grid {
+                    debug!("Life has settled down.");
+                    return None;
+                }
+                std::mem::swap(&mut grid, &mut next);
+            }
+            first_frame = false;
+
+            for (y, line) in canvas.chunks_exact_mut(width * 4).enumerate() {
+                let row = &grid[(y / cell_size) * cols..][..cols];
+                for (x, pixel) in line.chunks_exact_mut(4).enumerate() {
+                    pixel.copy_from_slice(if row[x / cell_size] { &alive } else { &dead });
+                }
+            }
+            Some(interval)
+        })
+    }
+
+    /// Unlike the other generators, we don't go through `run` here, because we have to wait for
+    /// the frames to arrive, and we want to tell the producer exactly when each one is due.
+    ///
+    /// We only ever have a single frame requested at a time. If it arrives after its deadline, we
+    /// drop it and skip requesting frames until it arrives, so that slow producers get to catch up
+    /// instead of falling further and further behind
+    fn external(
+        mut self,
+        command: &[String],
+        fps: u8,
+        outputs: &mut Vec<String>,
+        sender: &FrameSender,
+        stop_recv: &mpsc::Receiver<Vec<String>>,
+    ) {
+        let mut child = match process::Command::new(&command[0])
+            .args(&command[1..])
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()
+        {
+            Ok(child) => child,
+            Err(e) => {
+                error!(
+                    "failed to start external frame producer '{}': {e}",
+                    command[0]
+                );
+                return;
+            }
+        };
+        // unwrapping is fine because we asked for both pipes above
+        let mut stdin = child.stdin.take().unwrap();
+        let stdout = child.stdout.take().unwrap();
+
+        let (frame_sender, frames) = mpsc::sync_channel(1);
+        let frame_len = self.canvas.len();
+        let reader = match thread::Builder::new()
+            .name("external frames reader".to_string()) //Name our threads  for better log messages
+            .stack_size(super::TSTACK_SIZE) //the default of 2MB is way too overkill for this
+            .spawn(move || read_frames(stdout, frame_len, &frame_sender))
+        {
+            Ok(reader) => reader,
+            Err(e) => {
+                error!("failed to spawn 'external frames reader' thread: {e}");
+                let _ = child.kill();
+                let _ = child.wait();
+                return;
+            }
+        };
+
+        let (width, height) = self.dimensions;
+        let interval = Duration::from_nanos(1_000_000_000 / fps.max(1) as u64);
+        let mut deadline = Instant::now();
+        let mut waiting_late_frame = false;
+        for frame in 0u64.. {
+            deadline += interval;
+            if !waiting_late_frame {
+                let until_deadline = deadline.saturating_duration_since(Instant::now());
+                let deadline_us = (SystemTime::now() + until_deadline)
+                    .duration_since(UNIX_EPOCH)
+                    .unwrap_or_default()
+                    .as_micros();
+                let request = format!("{frame} {width} {height} {deadline_us}\n");
+                if let Err(e) = stdin.write_all(request.as_bytes()) {
+                    debug!("External frame producer stopped reading requests: {e}");
+                    break;
+                }
+            }
+
+            let should_exit =
+                match frames.recv_timeout(deadline.saturating_duration_since(Instant::now())) {
+                    Ok(_) if waiting_late_frame => {
+                        debug!("Dropping late frame from external producer");
+                        waiting_late_frame = false;
+                        should_stop(
+                            outputs,
+                            deadline.saturating_duration_since(Instant::now()),
+                            stop_recv,
+                        )
+                    }
+                    Ok(goal) => {
+                        let frame =
+                            ReadiedPack::new(&mut self.canvas, &goal, |cur, goal, _| *cur = *goal);
+                        let timeout = deadline.saturating_duration_since(Instant::now());
+                        if frame.is_empty() {
+                            should_stop(outputs, timeout, stop_recv)
+                        } else {
+                            send_frame(frame, outputs, timeout, sender, stop_recv)
+                        }
+                    }
+                    Err(mpsc::RecvTimeoutError::Timeout) => {
+                        debug!("Frame {frame} from external producer missed its deadline");
+                        waiting_late_frame = true;
+                        should_stop(outputs, Duration::ZERO, stop_recv)
+                    }
+                    Err(mpsc::RecvTimeoutError::Disconnected) => {
+                        debug!("External frame producer has finished.");
+                        break;
+                    }
+                };
+            if should_exit {
+                debug!("Generator was interrupted!");
+                break;
+            }
+        }
+
+        if let Err(e) = child.kill() {
+            debug!("failed to kill external frame producer: {e}");
+        }
+        let _ = child.wait();
+        drop(frames);
+        let _ = reader.join();
+    }
+}
+
+/// Reads frames of `len` bytes, in rgba, from `reader`, and sends them in bgrx until either side
+/// is closed
+fn read_frames(mut reader: impl Read, len: usize, sender: &mpsc::SyncSender<Box<[u8]>>) {
+    loop {
+        let mut frame = vec![0; len];
+        if let Err(e) = reader.read_exact(&mut frame) {
+            debug!("Stopped reading external frames: {e}");
+            return;
+        }
+        for pixel in frame.chunks_exact_mut(4) {
+            pixel.swap(0, 2);
+        }
+        if sender.send(frame.into_boxed_slice()).is_err() {
+            return;
+        }
+    }
+}
+
+/// Makes the initial Game of Life grid out of `canvas` (in bgrx, `width` pixels wide). A cell
+/// starts alive depending on how bright the pixels it covers are. We use ordered dithering to
+/// decide that, so that even images with little contrast still get an interesting pattern
+fn seed_life(canvas: &[u8], width: usize, cell_size: usize) -> Vec<bool> {
+    const BAYER: [[u8; 4]; 4] = [[0, 8, 2, 10], [12, 4, 14, 6], [3, 11, 1, 9], [15, 7, 13, 5]];
+    let height = canvas.len() / 4 / width;
+    let (cols, rows) = (width.div_ceil(cell_size), height.div_ceil(cell_size));
+    let mut luminance = vec![(0.0f32, 0u32); cols * rows];
+    for (y, line) in canvas.chunks_exact(width * 4).enumerate() {
+        for (x, pixel) in line.chunks_exact(4).enumerate() {
+            let (sum, count) = &mut luminance[(y / cell_size) * cols + x / cell_size];
+            *sum +=
+                (0.0722 * pixel[0] as f32 + 0.7152 * pixel[1] as f32 + 0.2126 * pixel[2] as f32)
+                    / 255.0;
+            *count += 1;
+        }
+    }
+
+    luminance
+        .into_iter()
+        .enumerate()
+        .map(|(i, (sum, count))| {
+            let threshold = (BAYER[(i / cols) % 4][(i % cols) % 4] as f32 + 0.5) / 16.0;
+            sum / count.max(1) as f32 > threshold
+        })
+        .collect()
+}
+
+/// Computes the next generation of `grid` (which is `cols` wide) into `next`. The grid wraps around
+/// at its edges
+fn step_life(grid: &[bool], next: &mut [bool], cols: usize) {
+    let rows = grid.len() / cols;
+    for row in 0..rows {
+        for col in 0..cols {
+            let mut neighbors = 0;
+            for (dy, dx) in [
+                (rows - 1, cols - 1),
+                (rows - 1, 0),
+                (rows - 1, 1),
+                (0, cols - 1),
+                (0, 1),
+                (1, cols - 1),
+                (1, 0),
+                (1, 1),
+            ] {
+                let (y, x) = ((row + dy) % rows, (col + dx) % cols);
+                neighbors += grid[y * cols + x] as u8;
+            }
+            let i = row * cols + col;
+            next[i] = matches!((grid[i], neighbors), (true, 2) | (_, 3));
+        }
+    }
+}
+
+/// Draws `text` (with `color` in rgb) into `canvas`, centered at `center`, blending it with what
+/// is already there
+fn draw_text(
+    canvas: &mut [u8],
+    dimensions: (u32, u32),
+    text: &str,
+    font: &fontdue::Font,
+    size: f32,
+    color: [u8; 3],
+    center: (f32, f32),
+) {
+    let (width, height) = (dimensions.0 as i64, dimensions.1 as i64);
+    let (ascent, descent) = match font.horizontal_line_metrics(size) {
+        Some(metrics) => (metrics.ascent, metrics.descent),
+        None => (size, 0.0),
+    };
+    let glyphs: Vec<_> = text.chars().map(|c| font.rasterize(c, size)).collect();
+    let text_width: f32 = glyphs
+        .iter()
+        .map(|(metrics, _)| metrics.advance_width)
+        .sum();
+
+    let mut pen_x = center.0 - text_width / 2.0;
+    let baseline = center.1 - (ascent - descent) / 2.0 + ascent;
+    for (metrics, coverage) in &glyphs {
+        let left = (pen_x + metrics.xmin as f32).round() as i64;
+        let top = (baseline - (metrics.height as i32 + metrics.ymin) as f32).round() as i64;
+        for (row, line) in coverage.chunks_exact(metrics.width.max(1)).enumerate() {
+            let y = top + row as i64;
+            if !(0..height).contains(&y) {
+                continue;
+            }
+            for (col, alpha) in line.iter().enumerate() {
+                let x = left + col as i64;
+                if !(0..width).contains(&x) || *alpha == 0 {
+                    continue;
+                }
+                let i = ((y * width + x) * 4) as usize;
+                let alpha = *alpha as u16;
+                // canvas is in bgr, color in rgb
+                for (byte, c) in canvas[i..i + 3].iter_mut().zip(color.iter().rev()) {
+                    *byte = ((*byte as u16 * (255 - alpha) + *c as u16 * alpha) / 255) as u8;
+                }
+            }
+        }
+        pen_x += metrics.advance_width;
+    }
+}
+
+/// Sums a few octaves of Perlin noise, for a more natural look. The result is roughly in [-1, 1]
+fn fractal_noise(x: f64, y: f64, z: f64) -> f64 {
+    let mut value = 0.0;
+    let mut amplitude = 1.0;
+    let mut frequency = 1.0;
+    for _ in 0..3 {
+        value += perlin(x * frequency, y * frequency, z * frequency) * amplitude;
+        amplitude *= 0.5;
+        frequency *= 2.0;
+    }
+    // perlin noise rarely gets close to its theoretical limits, so we stretch it a little
+    (value * 0.9).clamp(-1.0, 1.0)
+}
+
+/// Classic 3D Perlin noise, except we hash the lattice coordinates instead of using a permutation
+/// table, so that the noise doesn't repeat itself
+fn perlin(x: f64, y: f64, z: f64) -> f64 {
+    fn fade(t: f64) -> f64 {
+        t * t * t * (t * (t * 6.0 - 15.0) + 10.0)
+    }
+
+    fn lerp(t: f64, a: f64, b: f64) -> f64 {
+        a + t * (b - a)
+    }
+
+    fn grad(x: i64, y: i64, z: i64, dx: f64, dy: f64, dz: f64) -> f64 {
+        let mut h = (x as u64).wrapping_mul(0x9E37_79B9_7F4A_7C15)
+            ^ (y as u64).wrapping_mul(0xC2B2_AE3D_27D4_EB4F)
+            ^ (z as u64).wrapping_mul(0x1656_67B1_9E37_79F9);
+        h ^= h >> 29;
+        h = h.wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        h ^= h >> 32;
+        // these are the 12 directions from the improved noise reference implementation
+        match h & 15 {
+            0 | 12 => dx + dy,
+            1 | 13 => -dx + dy,
+            2 => dx - dy,
+            3 => -dx - dy,
+            4 => dx + dz,
+            5 => -dx + dz,
+            6 => dx - dz,
+            7 => -dx - dz,
+            8 => dy + dz,
+            9 | 14 => -dy + dz,
+            10 => dy - dz,
+            _ => -dy - dz,
+        }
+    }
+
+    let (xi, yi, zi) = (x.floor() as i64, y.floor() as i64, z.floor() as i64);
+    let (dx, dy, dz) = (x - x.floor(), y - y.floor(), z - z.floor());
+    let (u, v, w) = (fade(dx), fade(dy), fade(dz));
+
+    let corner = |i: i64, j: i64, k: i64| {
+        grad(
+            xi + i,
+            yi + j,
+            zi + k,
+            dx - i as f64,
+            dy - j as f64,
+            dz - k as f64,
+        )
+    };
+
+    lerp(
+        w,
+        lerp(
+            v,
+            lerp(u, corner(0, 0, 0), corner(1, 0, 0)),
+            lerp(u, corner(0, 1, 0), corner(1, 1, 0)),
+        ),
+        lerp(
+            v,
+            lerp(u, corner(0, 0, 1), corner(1, 0, 1)),
+            lerp(u, corner(0, 1, 1), corner(1, 1, 1)),
+        ),
+    )
+}
+
+/// Draws `pattern` into `canvas`, in bgrx
+fn draw_test_pattern(canvas: &mut [u8], dimensions: (u32, u32), pattern: TestPattern) {
+    let (width, height) = (dimensions.0 as usize, dimensions.1 as usize);
+    if width == 0 || height == 0 {
+        return;
+    }
+    for (y, row) in canvas.chunks_exact_mut(width * 4).enumerate() {
+        for (x, pixel) in row.chunks_exact_mut(4).enumerate() {
+            let color = match pattern {
+                TestPattern::Bars => smpte_bars(x, y, width, height),
+                TestPattern::Ramps => ramps(x, y, width, height),
+                TestPattern::Grid => grid(x, y, width, height),
+            };
+            pixel.copy_from_slice(&[color[2], color[1], color[0], 255]);
+        }
+    }
+}
+
+/// Color, in rgb, of the SMPTE color bars at (x, y)
+fn smpte_bars(x: usize, y: usize, width: usize, height: usize) -> [u8; 3] {
+    const TOP: [[u8; 3]; 7] = [
+        [192, 192, 192],
+        [192, 192, 0],
+        [0, 192, 192],
+        [0, 192, 0],
+        [192, 0, 192],
+        [192, 0, 0],
+        [0, 0, 192],
+    ];
+    const MIDDLE: [[u8; 3]; 7] = [
+        [0, 0, 192],
+        [19, 19, 19],
+        [192, 0, 192],
+        [19, 19, 19],
+        [0, 192, 192],
+        [19, 19, 19],
+        [192, 192, 192],
+    ];
+    // -I, white, +Q, black, and the PLUGE: blacker than black (as far as rgb allows), black and
+    // a bit lighter than black, so that the black level can be set between the last two
+    const BOTTOM: [[u8; 3]; 4] = [[0, 33, 76], [255, 255, 255], [50, 0, 106], [19, 19, 19]];
+    const PLUGE: [[u8; 3]; 3] = [[0, 0, 0], [19, 19, 19], [38, 38, 38]];
+
+    let bar = x * 7 / width;
+    if y < height * 2 / 3 {
+        TOP[bar]
+    } else if y < height * 3 / 4 {
+        MIDDLE[bar]
+    } else if bar < 5 {
+        // the first three colors take 5/4 of a bar each
+        BOTTOM[(x * 28 / width / 5).min(3)]
+    } else if bar == 5 {
+        PLUGE[(x * 21 / width - 15).min(2)]
+    } else {
+        [19, 19, 19]
+    }
+}
+
+/// Color, in rgb, of the ramps at (x, y): from top to bottom, smooth gray, gray in 16 steps, red,
+/// green and blue, all going from black on the left to full intensity on the right
+fn ramps(x: usize, y: usize, width: usize, height: usize) -> [u8; 3] {
+    let value = (x * 255 / (width - 1).max(1)) as u8;
+    match y * 5 / height {
+        0 => [value; 3],
+        1 => [(x * 16 / width * 17) as u8; 3],
+        2 => [value, 0, 0],
+        3 => [0, value, 0],
+        _ => [0, 0, value],
+    }
+}
+
+/// Color, in rgb, of the alignment grid at (x, y)
+fn grid(x: usize, y: usize, width: usize, height: usize) -> [u8; 3] {
+    const WHITE: [u8; 3] = [255, 255, 255];
+    const GRAY: [u8; 3] = [96, 96, 96];
+    const RED: [u8; 3] = [255, 0, 0];
+    const BLACK: [u8; 3] = [0, 0, 0];
+
+    // square cells, with lines going out from the center, so that the grid is symmetric
+    let cell = (width.min(height) / 8).max(2);
+    let (cx, cy) = (width / 2, height / 2);
+    let (dx, dy) = (x.abs_diff(cx), y.abs_diff(cy));
+
+    if x == 0 || y == 0 || x == width - 1 || y == height - 1 {
+        return WHITE;
+    }
+    // 1 pixel checkerboards at the corners average out to gray, unless the image is scaled
+    let corner = cell / 2;
+    let (ex, ey) = (x.min(width - 1 - x), y.min(height - 1 - y));
+    if ex <= corner && ey <= corner {
+        return if (x + y).is_multiple_of(2) {
+            WHITE
+        } else {
+            BLACK
+        };
+    }
+    if dx == 0 || dy == 0 {
+        return RED;
+    }
+    let radius = (width.min(height) / 2 - 1) as f64;
+    let distance = ((dx * dx + dy * dy) as f64).sqrt();
+    if (distance - radius).abs() < 1.0 {
+        return WHITE;
+    }
+    if dx % cell == 0 || dy % cell == 0 {
+        return GRAY;
+    }
+    BLACK
+}
+
+/// Returns the color, in bgrx, at position `t` of a gradient evenly spaced between `colors` (given
+/// in rgb). Cyclic gradients go back to the first color at their end, so that they can be shifted
+/// seamlessly
+fn gradient_color(colors: &[[u8; 3]], t: f64, cyclic: bool) -> [u8; 4] {
+    let segments = if cyclic {
+        colors.len()
+    } else {
+        colors.len().saturating_sub(1)
+    };
+    if segments == 0 || colors.len() == 1 {
+        let color = colors.first().unwrap_or(&[0, 0, 0]);
+        return [color[2], color[1], color[0], 255];
+    }
+
+    let t = if cyclic {
+        t.rem_euclid(1.0)
+    } else {
+        t.clamp(0.0, 1.0)
+    };
+    let pos = t * segments as f64;
+    let i = (pos as usize).min(segments - 1);
+    let frac = pos - i as f64;
+    let (a, b) = (colors[i], colors[(i + 1) % colors.len()]);
+    let lerp = |a: u8, b: u8| (a as f64 + (b as f64 - a as f64) * frac).round() as u8;
+    [lerp(a[2], b[2]), lerp(a[1], b[1]), lerp(a[0], b[0]), 255]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const COLORS: [[u8; 3]; 3] = [[255, 0, 0], [0, 255, 0], [0, 0, 255]];
+
+    #[test]
+    fn gradient_should_start_and_end_at_the_right_colors() {
+        assert_eq!(gradient_color(&COLORS, 0.0, false), [0, 0, 255, 255]);
+        assert_eq!(gradient_color(&COLORS, 0.5, false), [0, 255, 0, 255]);
+        assert_eq!(gradient_color(&COLORS, 1.0, false), [255, 0, 0, 255]);
+    }
+
+    #[test]
+    fn cyclic_gradient_should_wrap_around() {
+        assert_eq!(gradient_color(&COLORS, 0.0, true), [0, 0, 255, 255]);
+        assert_eq!(gradient_color(&COLORS, 1.0, true), [0, 0, 255, 255]);
+        assert_eq!(
+            gradient_color(&COLORS, 0.25, true),
+            gradient_color(&COLORS, 1.25, true)
+        );
+    }
+
+    #[test]
+    fn should_reject_invalid_clock_formats() {
+        let clock = |format: &str| swww_protocol::Generator::Clock {
+            format: format.to_string(),
+            font: Vec::new(),
+            size: 10.0,
+            color: [0, 0, 0],
+            pos: Position::new(
+                swww_protocol::Coord::Percent(0.5),
+                swww_protocol::Coord::Percent(0.5),
+            ),
+        };
+        let err = Generator::validate(&clock("%H:%Q")).unwrap_err();
+        assert!(err.contains("format"), "{err}");
+        // the format is fine, so now it should complain about the (empty) font
+        let err = Generator::validate(&clock("%H:%M")).unwrap_err();
+        assert!(err.contains("font"), "{err}");
+    }
+
+    #[test]
+    fn life_should_follow_conways_rules() {
+        // a blinker in the middle of a 5x5 grid
+        let mut grid = [false; 25];
+        for i in [11, 12, 13] {
+            grid[i] = true;
+        }
+        let mut next = [false; 25];
+        step_life(&grid, &mut next, 5);
+        let alive: Vec<_> = (0..25).filter(|&i| next[i]).collect();
+        assert_eq!(alive, [7, 12, 17]);
+
+        let mut back = [false; 25];
+        step_life(&next, &mut back, 5);
+        assert_eq!(back, grid);
+    }
+
+    #[test]
+    fn life_should_be_seeded_from_the_luminance() {
+        let (width, height) = (16, 8);
+        let white = vec![255; width * height * 4];
+        let black = vec![0; width * height * 4];
+        assert!(seed_life(&white, width, 4).into_iter().all(|alive| alive));
+        assert!(seed_life(&black, width, 4).into_iter().all(|alive| !alive));
+
+        // cells that are only partially covered by the image should still work
+        let grid = seed_life(&white, width, 5);
+        assert_eq!(grid.len(), 4 * 2);
+    }
+
+    #[test]
+    fn should_read_whole_external_frames_only() {
+        let mut stream = vec![1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15, 16];
+        // an incomplete frame at the end should be ignored
+        stream.extend_from_slice(&[17, 18, 19]);
+        let (sender, receiver) = mpsc::sync_channel(2);
+        read_frames(stream.as_slice(), 8, &sender);
+        drop(sender);
+
+        let frames: Vec<_> = receiver.iter().collect();
+        assert_eq!(frames.len(), 2);
+        // rgba should have been turned into bgra
+        assert_eq!(*frames[0], [3, 2, 1, 4, 7, 6, 5, 8]);
+        assert_eq!(*frames[1], [11, 10, 9, 12, 15, 14, 13, 16]);
+    }
+
+    #[test]
+    fn perlin_noise_should_be_zero_at_lattice_points() {
+        for (x, y, z) in [(0.0, 0.0, 0.0), (1.0, 5.0, -3.0), (-7.0, 2.0, 10.0)] {
+            assert_eq!(perlin(x, y, z), 0.0);
+        }
+    }
+
+    #[test]
+    fn fractal_noise_should_be_bounded_and_continuous() {
+        for i in 0..1000 {
+            let (x, y, z) = (i as f64 * 0.37, i as f64 * 0.11, i as f64 * 0.05);
+            let value = fractal_noise(x, y, z);
+            assert!(
+                (-1.0..=1.0).contains(&value),
+                "noise out of bounds: {value}"
+            );
+            let delta = (fractal_noise(x + 0.001, y, z) - value).abs();
+            assert!(delta < 0.05, "noise is not continuous at ({x}, {y}, {z})");
+        }
+    }
+
+    #[test]
+    fn test_patterns_should_cover_the_whole_canvas() {
+        let (width, height) = (97, 61);
+        for pattern in [TestPattern::Bars, TestPattern::Ramps, TestPattern::Grid] {
+            let mut canvas = vec![1u8; width * height * 4];
+            draw_test_pattern(&mut canvas, (width as u32, height as u32), pattern);
+            assert!(canvas.chunks_exact(4).all(|pixel| pixel[3] == 255));
+        }
+    }
+
+    #[test]
+    fn smpte_bars_should_be_in_order() {
+        let (width, height) = (700, 300);
+        assert_eq!(smpte_bars(0, 0, width, height), [192, 192, 192]);
+        assert_eq!(smpte_bars(150, 0, width, height), [192, 192, 0]);
+        assert_eq!(smpte_bars(699, 0, width, height), [0, 0, 192]);
+        assert_eq!(smpte_bars(0, 210, width, height), [0, 0, 192]);
+        assert_eq!(smpte_bars(0, 299, width, height), [0, 33, 76]);
+        assert_eq!(smpte_bars(150, 299, width, height), [255, 255, 255]);
+        assert_eq!(smpte_bars(699, 299, width, height), [19, 19, 19]);
+    }
+
+    #[test]
+    fn ramps_should_go_from_black_to_full_intensity() {
+        let (width, height) = (256, 100);
+        assert_eq!(ramps(0, 0, width, height), [0, 0, 0]);
+        assert_eq!(ramps(255, 0, width, height), [255, 255, 255]);
+        assert_eq!(ramps(255, 30, width, height), [255, 255, 255]);
+        assert_eq!(ramps(255, 50, width, height), [255, 0, 0]);
+        assert_eq!(ramps(255, 99, width, height), [0, 0, 255]);
+    }
+
+    #[test]
+    fn gradient_with_a_single_color_should_be_solid() {
+        for t in [0.0, 0.3, 1.0] {
+            assert_eq!(gradient_color(&COLORS[..1], t, true), [0, 0, 255, 255]);
+        }
+    }
+}
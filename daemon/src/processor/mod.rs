@@ -1,42 +1,188 @@
-use log::{debug, error, info};
+use log::{debug, error, info, warn};
 
-use smithay_client_toolkit::reexports::calloop::channel::SyncSender;
+use smithay_client_toolkit::reexports::calloop::channel;
 
 use std::{
-    path::PathBuf,
+    path::{Path, PathBuf},
     sync::mpsc,
-    sync::{Arc, RwLock},
+    sync::{Arc, Mutex, Once, RwLock, Weak},
     thread,
-    time::Duration,
+    time::{Duration, Instant},
 };
 
-use utils::{
-    communication::{Animation, Answer, BgInfo, Img},
-    comp_decomp::ReadiedPack,
+use swww_protocol::{
+    error::Error, Animation, Answer, BgInfo, Generator, Img, Playback, TransitionType,
 };
+use utils::comp_decomp::{BitPack, Pack, ReadiedPack, Window};
 
-mod animations;
+use swww_render::{adjust::Adjustment, resize::crop, transition::Runner};
+
+use crate::hook::{Event, Hook};
+
+mod generators;
 mod sync_barrier;
+mod workers;
+
+pub use swww_render::transition::Layout;
+pub use workers::{FrameSender, Presentation};
 
 ///The default thread stack size of 2MiB is way too overkill for our purposes
 const TSTACK_SIZE: usize = 1 << 17; //128KiB
 
+///If an animation falls this far behind, it tries to catch up by skipping to a keyframe
+const MAX_LAG: Duration = Duration::from_millis(100);
+
+///If we can't send a frame for this long, we assume the compositor stopped taking our buffers (for
+///example, because the output was disabled), and pause until it comes back
+const STALL_TIMEOUT: Duration = Duration::from_secs(3);
+
+///How often threads waiting for room in a full queue check whether they were asked to stop
+const STOP_POLL: Duration = Duration::from_millis(100);
+
 pub type ImgWithDim = (Box<[u8]>, (u32, u32));
 
+///Where an animation is, once it sent its first frame
+type Progress = Mutex<Option<Playhead>>;
+
+#[derive(Clone, Copy)]
+struct Playhead {
+    ///The last frame sent, and when it was due
+    frame: usize,
+    due: Instant,
+    frames: usize,
+    ///How many times it went back to its first frame
+    loops: u64,
+    since: Instant,
+}
+
+///Where the daemon leaves new adjustments for a running animation, for its thread to pick up
+///before its next frame
+type Adjuster = Mutex<Option<Readjust>>;
+
+///A new adjustment for a running animation. If it wasn't adjusted before, the animation can't know
+///what it is displaying by itself, so it comes along
+struct Readjust {
+    adjustment: Adjustment,
+    unadjusted: Option<Vec<u8>>,
+}
+
+///Where an animation's frames are drawn
+#[derive(Clone, Copy)]
+enum Target {
+    ///Whole outputs, whose buffers have this many bytes
+    Outputs(usize),
+    ///A window of the outputs
+    Window(Window),
+}
+
+impl Target {
+    fn size(&self) -> usize {
+        match self {
+            Self::Outputs(size) => *size,
+            Self::Window(window) => window.dim.0 as usize * window.dim.1 as usize * 4,
+        }
+    }
+
+    ///Readies `frame` for an image of the target's size. It still needs to be placed before being
+    ///sent
+    fn ready(&self, frame: &BitPack) -> ReadiedPack {
+        frame.ready(self.size())
+    }
+
+    fn place(&self, pack: ReadiedPack) -> ReadiedPack {
+        match self {
+            Self::Outputs(_) => pack,
+            Self::Window(window) => pack.in_window(*window),
+        }
+    }
+}
+
+///An animation playing on some outputs. Outputs displaying the same image, at the same size, can
+///join it
+struct RunningAnimation {
+    path: Option<PathBuf>,
+    output_size: usize,
+    outputs: Vec<String>,
+    adjustment: Adjustment,
+    adjuster: Arc<Adjuster>,
+    ///Only the animation's thread holds a strong reference, so this dies with it
+    progress: Weak<Progress>,
+    ///The frames being played, so that outputs playing the same ones can share them
+    animation: Weak<Animation>,
+}
+
+///An animation playing in a window of some outputs, which new regions overlapping it replace
+struct RunningRegion {
+    outputs: Vec<String>,
+    window: Window,
+    stopper: mpsc::Sender<Vec<String>>,
+    adjustment: Adjustment,
+    adjuster: Arc<Adjuster>,
+    ///Only the region's thread holds a strong reference, so this dies with it
+    progress: Weak<Progress>,
+}
+
+/// What `--hook` hears of the transitions of a request, which run in a thread per output: they
+/// start when the first of them does, and end once the last of them is dropped, so that the hook
+/// runs once per request, however many outputs it has
+struct HookEvents {
+    hook: Hook,
+    outputs: Vec<String>,
+    started: Once,
+    /// The outputs that weren't interrupted
+    finished: Mutex<Vec<String>>,
+}
+
+impl HookEvents {
+    fn start(&self) {
+        self.started
+            .call_once(|| self.hook.run(Event::TransitionStart, &self.outputs));
+    }
+
+    fn finish(&self, outputs: &[String]) {
+        self.finished.lock().unwrap().extend_from_slice(outputs);
+    }
+}
+
+impl Drop for HookEvents {
+    fn drop(&mut self) {
+        let finished = self.finished.get_mut().unwrap();
+        if !finished.is_empty() {
+            self.hook.run(Event::TransitionEnd, finished);
+        }
+    }
+}
+
 pub struct Processor {
-    frame_sender: SyncSender<(Vec<String>, ReadiedPack)>,
+    frame_sender: FrameSender,
+    presenter: channel::Sender<Presentation>,
     anim_stoppers: Vec<mpsc::Sender<Vec<String>>>,
     on_going_transitions: Arc<RwLock<Vec<String>>>,
     sync_barrier: Arc<sync_barrier::SyncBarrier>,
+    running_animations: Vec<RunningAnimation>,
+    regions: Vec<RunningRegion>,
+    hook: Option<Hook>,
+    /// Whether to swap images without transitions, and only display the first frame of
+    /// animations. See `swww-daemon --reduce-motion`
+    reduce_motion: bool,
 }
 
 impl Processor {
-    pub fn new(frame_sender: SyncSender<(Vec<String>, ReadiedPack)>) -> Self {
+    pub fn new(
+        presenter: channel::Sender<Presentation>,
+        hook: Option<Hook>,
+        reduce_motion: bool,
+    ) -> Self {
         Self {
-            frame_sender,
+            frame_sender: FrameSender::default(),
+            presenter,
             anim_stoppers: Vec::new(),
             on_going_transitions: Arc::new(RwLock::new(Vec::new())),
             sync_barrier: Arc::new(sync_barrier::SyncBarrier::new(0)),
+            running_animations: Vec::new(),
+            regions: Vec::new(),
+            hook,
+            reduce_motion,
         }
     }
 
@@ -44,63 +190,160 @@ impl Processor {
         self.sync_barrier.set_goal(outputs_count);
     }
 
+    /// Starts a new worker for `output`, replacing the old one, if any
+    pub fn add_output(&mut self, output: &str) {
+        let frames = self.frame_sender.add_queue(output);
+        workers::spawn(output.to_string(), frames, self.presenter.clone());
+    }
+
+    /// Stops the workers of all outputs for which `f` returns false
+    pub fn retain_outputs(&mut self, f: impl FnMut(&str) -> bool) {
+        self.frame_sender.retain_queues(f);
+    }
+
+    pub fn output_count(&self) -> usize {
+        self.frame_sender.len()
+    }
+
     pub fn transition(
         &mut self,
-        transition: &utils::communication::Transition,
+        transition: &swww_protocol::Transition,
         requests: Vec<(Img, Vec<String>)>,
         old_imgs: Vec<ImgWithDim>,
+        layouts: Vec<Layout>,
     ) -> Answer {
-        let mut answer = Answer::Ok;
-        for ((old_img, dim), (new_img, mut outputs)) in old_imgs.into_iter().zip(requests) {
-            if old_img.len() != new_img.img.len() {
-                return Answer::Err(format!(
-                    "Output and image have different sizes: {} vs {}.\
-                            This should be impossible.\
-                            Please get in the contact with the developers",
-                    old_img.len(),
-                    new_img.img.len()
-                ));
-            }
+        let mut answer = Answer::Transition {
+            frames: 0,
+            duration: Duration::ZERO,
+        };
+        let mut layouts = layouts.into_iter();
+        let mut transition = transition.clone();
+        if self.reduce_motion {
+            // the same as `--transition-type simple --transition-step 255`, which swaps right away
+            transition.transition_type = TransitionType::Simple;
+            transition.step = 255;
+        }
+        // nothing may change before we know the request can be carried out in full
+        let mut sizes = old_imgs.iter().zip(&requests);
+        if let Some(((old_img, _), (new_img, _))) =
+            sizes.find(|((old, _), (new, _))| old.len() != new.img.len())
+        {
+            return Answer::Err(Error::InvalidRequest(format!(
+                "Output and image have different sizes: {} vs {}.\
+                        This should be impossible.\
+                        Please get in the contact with the developers",
+                old_img.len(),
+                new_img.img.len()
+            )));
+        }
+        let plugin = match &transition.transition_type {
+            TransitionType::Plugin(name) => match crate::plugin::load(name) {
+                Ok(plugin) => Some(plugin),
+                Err(e) => return Answer::Err(e),
+            },
+            _ => None,
+        };
+        let hook = self.hook.clone().map(|hook| {
+            Arc::new(HookEvents {
+                hook,
+                outputs: requests
+                    .iter()
+                    .flat_map(|(_, outputs)| outputs)
+                    .cloned()
+                    .collect(),
+                started: Once::new(),
+                finished: Mutex::new(Vec::new()),
+            })
+        });
+        for ((old_img, dim), (new_img, outputs)) in old_imgs.into_iter().zip(requests) {
+            let layout = layouts.next();
             self.stop_animations(&outputs);
-            let transition = transition.clone();
-            let sender = self.frame_sender.clone();
-            let (stopper, stop_recv) = mpsc::channel();
-            self.anim_stoppers.push(stopper);
-            let on_going_transitions = Arc::clone(&self.on_going_transitions);
-            if let Err(e) = thread::Builder::new()
-                .name("transition".to_string()) //Name our threads  for better log messages
-                .stack_size(TSTACK_SIZE) //the default of 2MB is way too overkill for this
-                .spawn(move || {
-                    on_going_transitions
-                        .write()
-                        .unwrap()
-                        .extend_from_slice(&outputs);
-                    animations::Transition::new(old_img, dim, transition).execute(
-                        &new_img.img,
-                        &mut outputs,
-                        &sender,
-                        &stop_recv,
-                    );
-                    on_going_transitions
-                        .write()
-                        .unwrap()
-                        .retain(|output| !outputs.contains(output));
-                })
-            {
-                answer = Answer::Err(format!("failed to spawn transition thread: {e}"));
-                error!("failed to spawn 'transition' thread: {}", e);
-            };
+            // outputs of the same size share the new image, but every one of them runs its own
+            // transition, so that a slow output doesn't hold back the others
+            let new_img = Arc::new(new_img);
+            for output in outputs {
+                let mut runner = Runner::new(old_img.clone(), dim, transition.clone(), layout);
+                if let Some(plugin) = &plugin {
+                    runner = runner.with_plugin(plugin.frame(dim, &transition));
+                }
+                if let Answer::Transition { frames, duration } = &mut answer {
+                    let estimate = runner.estimate(&new_img.img);
+                    *frames = estimate.0.max(*frames);
+                    *duration = estimate.1.max(*duration);
+                }
+                let new_img = Arc::clone(&new_img);
+                if let Err(e) = self.spawn_transition(runner, new_img, output, hook.clone()) {
+                    answer = Answer::Err(Error::Other(format!(
+                        "failed to spawn transition thread: {e}"
+                    )));
+                    error!("failed to spawn 'transition' thread: {}", e);
+                }
+            }
         }
         answer
     }
 
+    /// Plays `runner` into `new_img` on `output`, in a thread of its own
+    fn spawn_transition(
+        &mut self,
+        runner: Runner,
+        new_img: Arc<Img>,
+        output: String,
+        hook: Option<Arc<HookEvents>>,
+    ) -> std::io::Result<()> {
+        let mut outputs = vec![output];
+        let sender = self.frame_sender.clone();
+        let (stopper, stop_recv) = mpsc::channel();
+        self.anim_stoppers.push(stopper);
+        let on_going_transitions = Arc::clone(&self.on_going_transitions);
+        let span = tracing::info_span!("transition", ?outputs);
+        thread::Builder::new()
+            .name("transition".to_string()) //Name our threads  for better log messages
+            .stack_size(TSTACK_SIZE) //the default of 2MB is way too overkill for this
+            .spawn(move || {
+                let _entered = span.enter();
+                on_going_transitions
+                    .write()
+                    .unwrap()
+                    .extend_from_slice(&outputs);
+                if let Some(hook) = &hook {
+                    hook.start();
+                }
+                runner.execute(&new_img.img, &mut |frame, timeout| {
+                    send_frame(frame, &mut outputs, timeout, &sender, &stop_recv)
+                });
+                on_going_transitions
+                    .write()
+                    .unwrap()
+                    .retain(|output| !outputs.contains(output));
+                if let Some(hook) = &hook {
+                    hook.finish(&outputs);
+                }
+            })
+            .map(|_| ())
+    }
+
+    ///`path` is the image the animation came from, if any. Outputs that are added later
+    ///displaying it can join the animation
     pub fn animate(
         &mut self,
-        animation: utils::communication::Animation,
+        animation: swww_protocol::Animation,
         mut outputs: Vec<String>,
         output_size: usize,
+        path: Option<PathBuf>,
     ) -> Answer {
+        if self.reduce_motion {
+            // the first frame was already sent as an image
+            debug!(
+                "Not playing animation on {}: motion is reduced",
+                outputs.join(", ")
+            );
+            return Answer::Ok;
+        }
         let mut answer = Answer::Ok;
+        let animation = self.share(path.as_deref(), output_size, animation);
+        let (progress, adjuster) =
+            self.register_animation(path, &outputs, output_size, Some(&animation));
 
         let sender = self.frame_sender.clone();
         let (stopper, stop_recv) = mpsc::channel();
@@ -108,10 +351,12 @@ impl Processor {
 
         let barrier = Arc::clone(&self.sync_barrier);
         self.anim_stoppers.push(stopper);
+        let span = tracing::info_span!("animation", ?outputs);
         if let Err(e) = thread::Builder::new()
             .name("animation".to_string()) //Name our threads  for better log messages
             .stack_size(TSTACK_SIZE) //the default of 2MB is way too overkill for this
             .spawn(move || {
+                let _entered = span.enter();
                 while on_going_transitions
                     .read()
                     .unwrap()
@@ -120,41 +365,156 @@ impl Processor {
                 {
                     std::thread::yield_now();
                 }
-                let mut now = std::time::Instant::now();
-                /* We only need to animate if we have > 1 frame */
-                if animation.animation.len() == 1 {
-                    return;
-                }
-                for (frame, duration) in animation.animation.iter().cycle() {
-                    let frame = frame.ready(output_size);
-
-                    if animation.sync {
-                        barrier.inc_and_wait_while(*duration, || match stop_recv.try_recv() {
-                            Ok(to_remove) => {
-                                outputs.retain(|o| !to_remove.contains(o));
-                                outputs.is_empty() || to_remove.is_empty()
-                            }
-                            Err(mpsc::TryRecvError::Empty) => false,
-                            Err(mpsc::TryRecvError::Disconnected) => true,
-                        });
-                        if outputs.is_empty() {
-                            return;
-                        }
-                    }
+                let barrier = animation.sync.then_some(&*barrier);
+                play_animation(
+                    &animation,
+                    &mut outputs,
+                    Target::Outputs(output_size),
+                    &sender,
+                    &stop_recv,
+                    barrier,
+                    (&progress, None),
+                    &adjuster,
+                );
+            })
+        {
+            answer = Answer::Err(Error::Other(format!(
+                "failed to spawn animation thread: {e}"
+            )));
+            error!("failed to spawn 'animation' thread: {e}");
+        };
 
-                    let timeout = duration.saturating_sub(now.elapsed());
-                    if send_frame(frame, &mut outputs, timeout, &sender, &stop_recv) {
-                        debug!("STOPPING");
-                        return;
+        answer
+    }
+
+    ///Plays `animation` in `window` of `outputs`, replacing the regions of those outputs it
+    ///overlaps. The animation must have a keyframe for its last frame, which is drawn first
+    pub fn region(
+        &mut self,
+        animation: Arc<Animation>,
+        mut outputs: Vec<String>,
+        window: Window,
+    ) -> Answer {
+        if let Err(e) = check_region(&animation) {
+            return Answer::Err(e);
+        }
+        self.regions
+            .retain(|region| region.progress.strong_count() > 0);
+        for region in self
+            .regions
+            .iter_mut()
+            .filter(|r| overlaps(&r.window, &window))
+        {
+            let replaced: Vec<String> = region
+                .outputs
+                .iter()
+                .filter(|output| outputs.contains(output))
+                .cloned()
+                .collect();
+            if !replaced.is_empty() {
+                region.outputs.retain(|output| !replaced.contains(output));
+                // if the thread is already gone, there is nothing to stop anyway
+                let _ = region.stopper.send(replaced);
+            }
+        }
+
+        let progress = Arc::new(Mutex::new(None));
+        let adjuster = Arc::new(Mutex::new(None));
+        let sender = self.frame_sender.clone();
+        let (stopper, stop_recv) = mpsc::channel();
+        self.anim_stoppers.push(stopper.clone());
+        self.regions.push(RunningRegion {
+            outputs: outputs.clone(),
+            window,
+            stopper,
+            adjustment: Adjustment::default(),
+            adjuster: Arc::clone(&adjuster),
+            progress: Arc::downgrade(&progress),
+        });
+        let on_going_transitions = Arc::clone(&self.on_going_transitions);
+        let still = self.reduce_motion;
+        let span = tracing::info_span!("region", ?outputs);
+        if let Err(e) = thread::Builder::new()
+            .name("region".to_string()) //Name our threads  for better log messages
+            .stack_size(TSTACK_SIZE) //the default of 2MB is way too overkill for this
+            .spawn(move || {
+                let _entered = span.enter();
+                while on_going_transitions
+                    .read()
+                    .unwrap()
+                    .iter()
+                    .any(|output| outputs.contains(output))
+                {
+                    std::thread::yield_now();
+                }
+                // joining at the last frame draws its keyframe, which is the first image
+                let last = animation.animation.len() - 1;
+                if still {
+                    let target = Target::Window(window);
+                    if let Some(frame) = animation.keyframe(last) {
+                        let frame = target.place(target.ready(frame));
+                        send_frame(frame, &mut outputs, Duration::ZERO, &sender, &stop_recv);
                     }
-                    now = std::time::Instant::now();
+                    return;
                 }
+                let start = (last, Instant::now());
+                play_animation(
+                    &animation,
+                    &mut outputs,
+                    Target::Window(window),
+                    &sender,
+                    &stop_recv,
+                    None,
+                    (&progress, Some(start)),
+                    &adjuster,
+                );
             })
         {
-            answer = Answer::Err(format!("failed to spawn animation thread: {e}"));
-            error!("failed to spawn 'animation' thread: {e}");
-        };
+            error!("failed to spawn 'region' thread: {e}");
+            return Answer::Err(Error::Other(format!("failed to spawn region thread: {e}")));
+        }
+        Answer::Ok
+    }
 
+    pub fn generate(
+        &mut self,
+        generator: Generator,
+        requests: Vec<(ImgWithDim, Vec<String>)>,
+    ) -> Answer {
+        if let Err(e) = generators::Generator::validate(&generator) {
+            return Answer::Err(Error::InvalidRequest(e));
+        }
+        let generator = if self.reduce_motion {
+            still(generator)
+        } else {
+            generator
+        };
+        let mut answer = Answer::Ok;
+        for ((canvas, dim), mut outputs) in requests {
+            self.stop_animations(&outputs);
+            let generator = generator.clone();
+            let sender = self.frame_sender.clone();
+            let (stopper, stop_recv) = mpsc::channel();
+            self.anim_stoppers.push(stopper);
+            let span = tracing::info_span!("generator", ?outputs);
+            if let Err(e) = thread::Builder::new()
+                .name("generator".to_string()) //Name our threads  for better log messages
+                .stack_size(TSTACK_SIZE) //the default of 2MB is way too overkill for this
+                .spawn(move || {
+                    let _entered = span.enter();
+                    generators::Generator::new(canvas, dim, generator).execute(
+                        &mut outputs,
+                        &sender,
+                        &stop_recv,
+                    );
+                })
+            {
+                answer = Answer::Err(Error::Other(format!(
+                    "failed to spawn generator thread: {e}"
+                )));
+                error!("failed to spawn 'generator' thread: {}", e);
+            };
+        }
         answer
     }
 
@@ -165,57 +525,264 @@ impl Processor {
             .retain(|output| !to_stop.contains(output));
         self.anim_stoppers
             .retain(|a| a.send(to_stop.to_vec()).is_ok());
+        for animation in &mut self.running_animations {
+            animation.outputs.retain(|output| !to_stop.contains(output));
+        }
+        for region in &mut self.regions {
+            region.outputs.retain(|output| !to_stop.contains(output));
+        }
     }
 
-    #[must_use]
-    pub fn import_cached_img(&mut self, info: BgInfo, old_img: &mut [u8]) -> Option<PathBuf> {
-        if let Some((Img { img, path }, anim)) = get_cached_bg(&info.name) {
-            let output_size = old_img.len();
-            if output_size < img.len() {
-                info!(
-                    "{} monitor's buffer size ({output_size}) is smaller than cache's image ({})",
-                    info.name,
-                    img.len()
-                );
-                return None;
-            }
-            let pack = ReadiedPack::new(old_img, &img, |cur, goal, _| {
-                *cur = *goal;
+    ///Adjusts the colors of the animation playing on `output`, if any, without restarting it.
+    ///`requested` are all the outputs being adjusted, which must include every output the
+    ///animation plays on, and `displayed` is what `output` is displaying right now. Returns whether
+    ///there was an animation to adjust
+    pub fn adjust_animation(
+        &mut self,
+        output: &str,
+        requested: &[String],
+        adjustment: Adjustment,
+        displayed: &[u8],
+    ) -> Result<bool, Error> {
+        self.running_animations
+            .retain(|animation| animation.progress.strong_count() > 0);
+        let Some(animation) = self
+            .running_animations
+            .iter_mut()
+            .find(|animation| animation.outputs.iter().any(|o| o == output))
+        else {
+            return Ok(false);
+        };
+        if let Some(other) = animation.outputs.iter().find(|o| !requested.contains(o)) {
+            return Err(Error::Conflict(format!(
+                "{output} shares its animation with {other}, so they must be adjusted together"
+            )));
+        }
+        if animation.adjustment != adjustment {
+            let unadjusted = animation
+                .adjustment
+                .is_identity()
+                .then(|| displayed.to_vec());
+            *animation.adjuster.lock().unwrap() = Some(Readjust {
+                adjustment,
+                unadjusted,
             });
+            animation.adjustment = adjustment;
+        }
+        Ok(true)
+    }
 
-            let sender = self.frame_sender.clone();
-            let (stopper, stop_recv) = mpsc::channel();
-            self.anim_stoppers.push(stopper);
-            if let Err(e) = thread::Builder::new()
-                .name("cache importing".to_string()) //Name our threads  for better log messages
-                .stack_size(TSTACK_SIZE) //the default of 2MB is way too overkill for this
-                .spawn(move || {
-                    let mut outputs = vec![info.name];
-                    send_frame(pack, &mut outputs, Duration::new(0, 0), &sender, &stop_recv);
-                    if let Some(anim) = anim {
-                        let mut now = std::time::Instant::now();
-                        if anim.animation.len() == 1 {
-                            return;
-                        }
-                        for (frame, duration) in anim.animation.iter().cycle() {
-                            let frame = frame.ready(output_size);
-                            let timeout = duration.saturating_sub(now.elapsed());
-                            if send_frame(frame, &mut outputs, timeout, &sender, &stop_recv) {
-                                return;
-                            }
-                            now = std::time::Instant::now();
-                        }
-                    }
-                })
-            {
-                error!("failed to spawn 'cache importing' thread: {}", e);
-                return None;
+    ///Whether `output` is playing an animation
+    pub fn is_animated(&mut self, output: &str) -> bool {
+        self.running_animations
+            .retain(|animation| animation.progress.strong_count() > 0);
+        self.running_animations
+            .iter()
+            .any(|animation| animation.outputs.iter().any(|o| o == output))
+    }
+
+    ///Like `adjust_animation`, but for the regions playing on `output`. `displayed` has dimensions
+    ///`dim`. Returns how many regions there were
+    pub fn adjust_regions(
+        &mut self,
+        output: &str,
+        requested: &[String],
+        adjustment: Adjustment,
+        (displayed, dim): (&[u8], (u32, u32)),
+    ) -> Result<usize, Error> {
+        self.regions
+            .retain(|region| region.progress.strong_count() > 0);
+        let mut count = 0;
+        for region in self
+            .regions
+            .iter_mut()
+            .filter(|region| region.outputs.iter().any(|o| o == output))
+        {
+            if let Some(other) = region.outputs.iter().find(|o| !requested.contains(o)) {
+                return Err(Error::Conflict(format!(
+                    "{output} shares a region with {other}, so they must be adjusted together"
+                )));
             }
+            count += 1;
+            if region.adjustment != adjustment && region.window.fits(dim) {
+                let window = &region.window;
+                let unadjusted = region
+                    .adjustment
+                    .is_identity()
+                    .then(|| crop(displayed, dim, window.offset, window.dim));
+                *region.adjuster.lock().unwrap() = Some(Readjust {
+                    adjustment,
+                    unadjusted,
+                });
+                region.adjustment = adjustment;
+            }
+        }
+        Ok(count)
+    }
 
-            return Some(path);
+    ///Displays `cached`, the image (and animation) `info`'s output displayed last time
+    #[must_use]
+    pub fn import_cached_img(
+        &mut self,
+        info: BgInfo,
+        old_img: &mut [u8],
+        cached: (Img, Option<Animation>),
+    ) -> Option<PathBuf> {
+        let (Img { img, path }, anim) = cached;
+        let output_size = old_img.len();
+        if output_size < img.len() {
+            info!(
+                "{} monitor's buffer size ({output_size}) is smaller than cache's image ({})",
+                info.name,
+                img.len()
+            );
+            return None;
+        }
+        let pack = ReadiedPack::new(old_img, &img, |cur, goal, _| {
+            *cur = *goal;
+        });
+        let anim = anim
+            .filter(|_| !self.reduce_motion)
+            .map(|anim| self.share(Some(&path), output_size, anim));
+        // if this image is already animating elsewhere, we join it instead of starting over
+        let join_at = anim
+            .as_ref()
+            .and_then(|anim| self.join_point(&path, output_size, anim));
+        if join_at.is_some() {
+            info!("{} is joining the animation of {path:?}", info.name);
         }
-        info!("failed to find cached image for monitor '{}'", info.name);
-        None
+        let (progress, adjuster) = self.register_animation(
+            Some(path.clone()),
+            std::slice::from_ref(&info.name),
+            output_size,
+            anim.as_ref(),
+        );
+
+        let sender = self.frame_sender.clone();
+        let (stopper, stop_recv) = mpsc::channel();
+        self.anim_stoppers.push(stopper);
+        if let Err(e) = thread::Builder::new()
+            .name("cache importing".to_string()) //Name our threads  for better log messages
+            .stack_size(TSTACK_SIZE) //the default of 2MB is way too overkill for this
+            .spawn(move || {
+                let mut outputs = vec![info.name];
+                send_frame(pack, &mut outputs, Duration::new(0, 0), &sender, &stop_recv);
+                if let Some(anim) = anim {
+                    play_animation(
+                        &anim,
+                        &mut outputs,
+                        Target::Outputs(output_size),
+                        &sender,
+                        &stop_recv,
+                        None,
+                        (&progress, join_at),
+                        &adjuster,
+                    );
+                }
+            })
+        {
+            error!("failed to spawn 'cache importing' thread: {}", e);
+            return None;
+        }
+
+        Some(path)
+    }
+
+    fn register_animation(
+        &mut self,
+        path: Option<PathBuf>,
+        outputs: &[String],
+        output_size: usize,
+        animation: Option<&Arc<Animation>>,
+    ) -> (Arc<Progress>, Arc<Adjuster>) {
+        let progress = Arc::new(Mutex::new(None));
+        let adjuster = Arc::new(Mutex::new(None));
+        self.running_animations
+            .retain(|animation| animation.progress.strong_count() > 0);
+        self.running_animations.push(RunningAnimation {
+            path,
+            output_size,
+            outputs: outputs.to_vec(),
+            adjustment: Adjustment::default(),
+            adjuster: Arc::clone(&adjuster),
+            progress: Arc::downgrade(&progress),
+            animation: animation.map_or_else(Weak::new, Arc::downgrade),
+        });
+        (progress, adjuster)
+    }
+
+    ///`animation`, or the same frames if they are already playing on other outputs, so that
+    ///outputs displaying the same image at the same size don't each keep a copy of them
+    fn share(
+        &self,
+        path: Option<&Path>,
+        output_size: usize,
+        animation: Animation,
+    ) -> Arc<Animation> {
+        let playing = self
+            .running_animations
+            .iter()
+            .filter(|running| {
+                path.is_some()
+                    && running.path.as_deref() == path
+                    && running.output_size == output_size
+            })
+            .filter_map(|running| running.animation.upgrade())
+            // the same image at the same size may still have been prepared with other options
+            .find(|playing| **playing == animation);
+        match playing {
+            Some(playing) => {
+                debug!("Sharing the frames of an animation that is already playing");
+                playing
+            }
+            None => Arc::new(animation),
+        }
+    }
+
+    ///Where an output displaying the first frame of `animation` should start playing it, to join
+    ///an animation of the same image that is already running, if any
+    fn join_point(
+        &self,
+        path: &Path,
+        output_size: usize,
+        animation: &Animation,
+    ) -> Option<(usize, Instant)> {
+        if animation.animation.len() <= 1 {
+            return None;
+        }
+        let progress = self
+            .running_animations
+            .iter()
+            .filter(|running| {
+                running.path.as_deref() == Some(path) && running.output_size == output_size
+            })
+            .find_map(|running| running.progress.upgrade())?;
+        let head = (*progress.lock().unwrap())?;
+        Some(next_join_point(animation, head.frame, head.due))
+    }
+
+    ///Where the animations playing on whole outputs are, for every output playing one
+    pub fn playback(&mut self) -> Vec<Playback> {
+        self.running_animations
+            .retain(|animation| animation.progress.strong_count() > 0);
+        let mut playback = Vec::new();
+        for animation in &self.running_animations {
+            let Some(head) = animation
+                .progress
+                .upgrade()
+                .and_then(|progress| *progress.lock().unwrap())
+            else {
+                continue;
+            };
+            playback.extend(animation.outputs.iter().map(|output| Playback {
+                output: output.clone(),
+                frame: head.frame,
+                frames: head.frames,
+                loops: head.loops,
+                elapsed: head.since.elapsed(),
+            }));
+        }
+        playback
     }
 }
 
@@ -228,61 +795,472 @@ impl Drop for Processor {
     }
 }
 
+///Plays `animation` in a loop, until it is stopped, drawing its frames to `target` of the outputs.
+///If `barrier` is given, every frame waits for the other outputs using it before being sent
+///
+///Every frame sent is recorded in the first element of `progress`. If the second is given, we start
+///from that frame, when it is due, instead of from the beginning. It must be a keyframe, or the
+///first frame (in which case the outputs must be displaying the animation's first image)
+///
+///When we fall behind (because the compositor stalled, for example), we jump straight to the latest
+///keyframe that is already due, instead of replaying every frame before it. Synced animations
+///never skip frames, since that would make them go out of sync
+///
+///New adjustments left in `adjuster` are picked up before the next frame. While adjusted, we keep
+///what the animation would be displaying unadjusted, so that changing the adjustment can redraw it
+///at once, instead of waiting for every pixel to change
+#[allow(clippy::too_many_arguments)]
+fn play_animation(
+    animation: &Animation,
+    outputs: &mut Vec<String>,
+    target: Target,
+    sender: &FrameSender,
+    stop_recv: &mpsc::Receiver<Vec<String>>,
+    barrier: Option<&sync_barrier::SyncBarrier>,
+    progress: (&Progress, Option<(usize, Instant)>),
+    adjuster: &Adjuster,
+) {
+    let frames = &animation.animation;
+    /* We only need to animate if we have > 1 frame */
+    if frames.len() <= 1 {
+        return;
+    }
+    let (progress, start) = progress;
+    let (mut i, mut due) = start.unwrap_or_else(|| (0, Instant::now() + frames[0].1));
+    let (mut loops, since) = (0, Instant::now());
+    let mut joining = start.is_some();
+    let ready = |frame| target.ready(frame);
+    // the lookup table of the current adjustment, and what we'd display without it
+    let mut adjusted: Option<([u8; 256], Vec<u8>)> = None;
+    loop {
+        let mut keyframe = None;
+        if joining {
+            keyframe = animation.keyframe(i).map(ready);
+            joining = false;
+        }
+        let now = Instant::now();
+        if barrier.is_none() && now > due + MAX_LAG {
+            match catch_up(animation, i, due, now) {
+                Some((k, k_due)) => {
+                    debug!("Animation fell behind, skipping to keyframe {k}");
+                    keyframe = animation.keyframe(k).map(ready);
+                    if k < i {
+                        loops += 1;
+                    }
+                    (i, due) = (k, k_due);
+                }
+                // with no keyframe to jump to, we just carry on from here
+                None => due = now,
+            }
+        }
+        let mut frame = keyframe.unwrap_or_else(|| ready(&frames[i].0));
+        let readjust = adjuster.lock().unwrap().take();
+        if let Some(Readjust {
+            adjustment,
+            unadjusted,
+        }) = readjust
+        {
+            let canvas = unadjusted.or_else(|| adjusted.take().map(|(_, canvas)| canvas));
+            match canvas.filter(|canvas| canvas.len() == target.size()) {
+                Some(mut canvas) => {
+                    frame.unpack(&mut canvas);
+                    let mut img = canvas.clone();
+                    adjustment.apply(&mut img);
+                    frame = ReadiedPack::keyframe(&img);
+                    adjusted = (!adjustment.is_identity()).then(|| (adjustment.lut(), canvas));
+                }
+                None => warn!("can't adjust an animation without knowing what it is displaying"),
+            }
+        } else if let Some((lut, canvas)) = &mut adjusted {
+            frame.unpack(canvas);
+            frame.map_colors(lut);
+        }
+        let frame = target.place(frame);
+
+        if let Some(barrier) = barrier {
+            barrier.inc_and_wait_while(frames[i].1, || match stop_recv.try_recv() {
+                Ok(to_remove) => {
+                    outputs.retain(|o| !to_remove.contains(o));
+                    outputs.is_empty() || to_remove.is_empty()
+                }
+                Err(mpsc::TryRecvError::Empty) => false,
+                Err(mpsc::TryRecvError::Disconnected) => true,
+            });
+            if outputs.is_empty() {
+                return;
+            }
+        }
+
+        let timeout = due.saturating_duration_since(Instant::now());
+        if send_frame(frame, outputs, timeout, sender, stop_recv) {
+            debug!("STOPPING");
+            return;
+        }
+        *progress.lock().unwrap() = Some(Playhead {
+            frame: i,
+            due,
+            frames: frames.len(),
+            loops,
+            since,
+        });
+        i = (i + 1) % frames.len();
+        due += frames[i].1;
+        if i == 0 {
+            loops += 1;
+        }
+    }
+}
+
+fn overlaps(a: &Window, b: &Window) -> bool {
+    a.offset.0 < b.offset.0 + b.dim.0
+        && b.offset.0 < a.offset.0 + a.dim.0
+        && a.offset.1 < b.offset.1 + b.dim.1
+        && b.offset.1 < a.offset.1 + a.dim.1
+}
+
+///Finds the latest keyframe that is already due at `now`, within a loop of the animation from
+///frame `i`, which was due at `due`. Returns its index and when it was due
+fn catch_up(
+    animation: &Animation,
+    i: usize,
+    due: Instant,
+    now: Instant,
+) -> Option<(usize, Instant)> {
+    let frames = &animation.animation;
+    let mut latest = None;
+    let (mut j, mut j_due) = (i, due);
+    for _ in 1..frames.len() {
+        j = (j + 1) % frames.len();
+        j_due += frames[j].1;
+        if j_due > now {
+            break;
+        }
+        if animation.keyframe(j).is_some() {
+            latest = Some((j, j_due));
+        }
+    }
+    latest
+}
+
+///Region animations come straight from clients, so we make sure they have frames, and that their
+///keyframes are sorted, in range, and include the last frame, which is the first one we draw
+fn check_region(animation: &Animation) -> Result<(), Error> {
+    let frames = animation.animation.len();
+    if frames == 0 {
+        return Err(Error::InvalidRequest(
+            "region's animation has no frames".to_string(),
+        ));
+    }
+    let indices = animation.keyframes.iter().map(|(i, _)| *i);
+    if indices.clone().any(|i| i >= frames) || !indices.is_sorted_by(|a, b| a < b) {
+        return Err(Error::InvalidRequest(
+            "region's animation has keyframes out of range or out of order".to_string(),
+        ));
+    }
+    if animation.keyframe(frames - 1).is_none() {
+        return Err(Error::InvalidRequest(
+            "region's animation doesn't start with a keyframe".to_string(),
+        ));
+    }
+    Ok(())
+}
+
+///The first frame after `i` (which was due at `due`) that can be joined from the animation's first
+///image: either a keyframe or the start of the next loop. Returns it and when it is due
+fn next_join_point(animation: &Animation, i: usize, due: Instant) -> (usize, Instant) {
+    let frames = &animation.animation;
+    let (mut j, mut due) = (i, due);
+    loop {
+        j = (j + 1) % frames.len();
+        due += frames[j].1;
+        if j == 0 || animation.keyframe(j).is_some() {
+            return (j, due);
+        }
+    }
+}
+
 ///Returns whether the calling function should exit or not
+///
+///Rather than blocking on the queues forever, we sleep until a worker makes room in its queue, but
+///for `STOP_POLL` at most, so that stalled animations can still be stopped
 fn send_frame(
     frame: ReadiedPack,
     outputs: &mut Vec<String>,
     timeout: Duration,
-    sender: &SyncSender<(Vec<String>, ReadiedPack)>,
+    sender: &FrameSender,
+    stop_recv: &mpsc::Receiver<Vec<String>>,
+) -> bool {
+    if should_stop(outputs, timeout, stop_recv) {
+        return true;
+    }
+    let frame = Arc::new(frame);
+    let mut pending = outputs.clone();
+    let start = Instant::now();
+    let mut stalled = false;
+    loop {
+        let taken = sender.taken();
+        let mut gone = Vec::new();
+        pending.retain(|output| match sender.try_send(output, Arc::clone(&frame)) {
+            Ok(()) => false,
+            Err(mpsc::TrySendError::Full(_)) => true,
+            Err(mpsc::TrySendError::Disconnected(_)) => {
+                gone.push(output.clone());
+                false
+            }
+        });
+        if !gone.is_empty() {
+            debug!("outputs {gone:?} no longer have workers, stopping drawing to them");
+            outputs.retain(|o| !gone.contains(o));
+            if outputs.is_empty() {
+                return true;
+            }
+        }
+        if pending.is_empty() {
+            if stalled {
+                info!("frames are being accepted again, resuming {outputs:?}");
+            }
+            tracing::debug!(
+                "frame queued after waiting for {:.3}s",
+                start.elapsed().as_secs_f32()
+            );
+            return false;
+        }
+        if !stalled && start.elapsed() >= STALL_TIMEOUT {
+            stalled = true;
+            tracing::info!("stalled, the compositor isn't taking frames for {pending:?}");
+            warn!(
+                "could not send a frame for {}s, pausing {pending:?} until the compositor catches up",
+                STALL_TIMEOUT.as_secs()
+            );
+        }
+        sender.wait_for_room(taken, STOP_POLL);
+        if should_stop(outputs, Duration::ZERO, stop_recv) {
+            return true;
+        }
+        pending.retain(|o| outputs.contains(o));
+    }
+}
+
+///Waits up to `timeout` for a request to stop some of the outputs. Returns whether the calling
+///function should exit or not
+fn should_stop(
+    outputs: &mut Vec<String>,
+    timeout: Duration,
     stop_recv: &mpsc::Receiver<Vec<String>>,
 ) -> bool {
     match stop_recv.recv_timeout(timeout) {
         Ok(to_remove) => {
             outputs.retain(|o| !to_remove.contains(o));
-            if outputs.is_empty() || to_remove.is_empty() {
-                return true;
-            }
+            outputs.is_empty() || to_remove.is_empty()
         }
-        Err(mpsc::RecvTimeoutError::Timeout) => (),
-        Err(mpsc::RecvTimeoutError::Disconnected) => return true,
+        Err(mpsc::RecvTimeoutError::Timeout) => false,
+        Err(mpsc::RecvTimeoutError::Disconnected) => true,
     }
-    match sender.send((outputs.clone(), frame)) {
-        Ok(()) => false,
-        Err(_) => true,
+}
+
+/// `generator`, without what moves in it, for `--reduce-motion`. Clocks still tick
+fn still(generator: Generator) -> Generator {
+    match generator {
+        Generator::Gradient {
+            colors, angle, fps, ..
+        } => Generator::Gradient {
+            colors,
+            angle,
+            period: None,
+            fps,
+        },
+        Generator::Plasma {
+            colors, scale, fps, ..
+        } => Generator::Plasma {
+            colors,
+            scale,
+            speed: 0.0,
+            fps,
+        },
+        generator => generator,
     }
 }
 
-fn get_cached_bg(output: &str) -> Option<(Img, Option<Animation>)> {
-    let cache_path = match utils::communication::get_cache_path() {
-        Ok(mut path) => {
-            path.push(output);
-            path
-        }
-        Err(e) => {
-            error!("failed to get bgs cache's path: {e}");
-            return None;
-        }
-    };
+///Evicts cache files in the background, never evicting the ones of the outputs in `keep` just to
+///fit in `limit`
+pub fn evict_cache(limit: Option<u64>, keep: Vec<String>) {
+    if let Err(e) = thread::Builder::new()
+        .name("cache eviction".to_string()) //Name our threads  for better log messages
+        .stack_size(TSTACK_SIZE) //the default of 2MB is way too overkill for this
+        .spawn(move || match utils::cache::evict(limit, &keep) {
+            Ok(0) => (),
+            Ok(freed) => info!("Evicted {freed} bytes from the cache"),
+            Err(e) => error!("failed to evict cache files: {e}"),
+        })
+    {
+        error!("failed to spawn 'cache eviction' thread: {}", e);
+    }
+}
 
-    let cache_file = match std::fs::File::open(cache_path) {
-        Ok(file) => file,
-        Err(e) => {
-            if e.kind() != std::io::ErrorKind::NotFound {
-                error!("failed to open bgs cache's file: {e}");
-            }
-            return None;
-        }
-    };
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use utils::comp_decomp::{BitPack, Pack};
 
-    let mut reader = std::io::BufReader::new(cache_file);
-    match Img::try_from(&mut reader) {
-        Ok(img) => match Animation::try_from(&mut reader) {
-            Ok(anim) => Some((img, Some(anim))),
-            Err(_) => Some((img, None)),
-        },
-        Err(e) => {
-            error!("failed to read bgs cache's file: {e}");
-            None
+    fn test_animation(frames: usize, keyframes: &[usize]) -> Animation {
+        let frame = || BitPack::pack(&mut [0; 4], &[1; 4]).unwrap();
+        Animation {
+            animation: (0..frames)
+                .map(|_| (frame(), Duration::from_millis(10)))
+                .collect(),
+            keyframes: keyframes.iter().map(|i| (*i, frame())).collect(),
+            sync: false,
         }
     }
+
+    #[test]
+    fn should_catch_up_to_the_latest_due_keyframe() {
+        let animation = test_animation(10, &[2, 4, 8]);
+        let due = Instant::now();
+        let now = due + Duration::from_millis(55);
+        assert_eq!(catch_up(&animation, 0, due, now).unwrap().0, 4);
+        // only frames 1 and 2 are due
+        let now = due + Duration::from_millis(25);
+        assert_eq!(catch_up(&animation, 0, due, now).unwrap().0, 2);
+        // and it wraps around
+        let now = due + Duration::from_millis(65);
+        assert_eq!(catch_up(&animation, 6, due, now).unwrap().0, 2);
+    }
+
+    fn frame() -> ReadiedPack {
+        ReadiedPack::new(&mut [0; 4], &[1; 4], |cur, goal, _| *cur = *goal)
+    }
+
+    #[test]
+    fn stalled_frames_can_be_stopped() {
+        let sender = FrameSender::default();
+        let _queue = sender.add_queue("dummy");
+        let (stopper, stop_recv) = mpsc::channel();
+        let mut outputs = vec!["dummy".to_string()];
+
+        assert!(!send_frame(
+            frame(),
+            &mut outputs,
+            Duration::ZERO,
+            &sender,
+            &stop_recv
+        ));
+        // nobody is reading, so the queue is now full
+        let handle = thread::spawn(move || {
+            send_frame(frame(), &mut outputs, Duration::ZERO, &sender, &stop_recv)
+        });
+        thread::sleep(Duration::from_millis(20));
+        stopper.send(Vec::new()).unwrap();
+        assert!(handle.join().unwrap(), "stalled send did not stop");
+    }
+
+    #[test]
+    fn waiting_senders_wake_up_once_there_is_room() {
+        let sender = FrameSender::default();
+        let queue = sender.add_queue("dummy");
+        let (_stopper, stop_recv) = mpsc::channel();
+        let mut outputs = vec!["dummy".to_string()];
+
+        assert!(!send_frame(
+            frame(),
+            &mut outputs,
+            Duration::ZERO,
+            &sender,
+            &stop_recv
+        ));
+        let handle = thread::spawn(move || {
+            let start = Instant::now();
+            let stopped = send_frame(frame(), &mut outputs, Duration::ZERO, &sender, &stop_recv);
+            (stopped, start.elapsed())
+        });
+        thread::sleep(Duration::from_millis(20));
+        assert!(queue.recv().is_ok());
+        let (stopped, waited) = handle.join().unwrap();
+        assert!(!stopped);
+        assert!(
+            waited < STOP_POLL,
+            "the sender slept through the room being made"
+        );
+        assert!(queue.recv().is_ok());
+    }
+
+    #[test]
+    fn outputs_without_workers_are_dropped() {
+        let sender = FrameSender::default();
+        let queue = sender.add_queue("alive");
+        let (_stopper, stop_recv) = mpsc::channel();
+        let mut outputs = vec!["alive".to_string(), "gone".to_string()];
+
+        assert!(!send_frame(
+            frame(),
+            &mut outputs,
+            Duration::ZERO,
+            &sender,
+            &stop_recv
+        ));
+        assert_eq!(outputs, vec!["alive".to_string()]);
+        assert!(queue.recv().is_ok());
+    }
+
+    #[test]
+    fn should_join_at_the_next_keyframe_or_loop() {
+        let due = Instant::now();
+        let animation = test_animation(10, &[4]);
+        let (i, i_due) = next_join_point(&animation, 1, due);
+        assert_eq!((i, i_due - due), (4, Duration::from_millis(30)));
+        assert_eq!(next_join_point(&animation, 4, due).0, 0);
+        assert_eq!(next_join_point(&test_animation(10, &[]), 9, due).0, 0);
+    }
+
+    #[test]
+    fn regions_should_overlap_only_if_they_share_pixels() {
+        let window = |offset, dim| Window {
+            offset,
+            dim,
+            buf_width: 100,
+        };
+        let a = window((10, 10), (20, 20));
+        assert!(overlaps(&a, &window((29, 29), (5, 5))));
+        assert!(overlaps(&a, &window((0, 0), (100, 100))));
+        assert!(!overlaps(&a, &window((30, 10), (5, 5))));
+        assert!(!overlaps(&a, &window((10, 0), (20, 10))));
+    }
+
+    #[test]
+    fn regions_should_be_checked_before_playing() {
+        assert!(check_region(&test_animation(3, &[2])).is_ok());
+        assert!(check_region(&test_animation(3, &[0, 2])).is_ok());
+        assert!(check_region(&test_animation(0, &[])).is_err());
+        assert!(check_region(&test_animation(3, &[0])).is_err());
+        assert!(check_region(&test_animation(3, &[2, 5])).is_err());
+        assert!(check_region(&test_animation(3, &[2, 0])).is_err());
+    }
+
+    #[test]
+    fn should_not_catch_up_without_keyframes() {
+        let animation = test_animation(10, &[]);
+        let due = Instant::now();
+        assert!(catch_up(&animation, 0, due, due + Duration::from_secs(1)).is_none());
+    }
+
+    #[test]
+    fn still_generators_should_not_move() {
+        let plasma = Generator::Plasma {
+            colors: vec![[0, 0, 0]],
+            scale: 1.0,
+            speed: 2.0,
+            fps: 30,
+        };
+        assert!(matches!(still(plasma), Generator::Plasma { speed, .. } if speed == 0.0));
+        let gradient = Generator::Gradient {
+            colors: vec![[0, 0, 0], [255, 255, 255]],
+            angle: 0.0,
+            period: Some(Duration::from_secs(10)),
+            fps: 30,
+        };
+        assert!(matches!(
+            still(gradient),
+            Generator::Gradient { period: None, .. }
+        ));
+    }
 }
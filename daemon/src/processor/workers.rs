@@ -0,0 +1,156 @@
+//! Every output is serviced by its own worker, with its own queue of frames. This way, an output
+//! that takes long to draw (like a 4K monitor playing a big gif) can only delay the animations sent
+//! to it, instead of every animation in the daemon.
+use log::{debug, error};
+
+use smithay_client_toolkit::reexports::calloop::channel;
+
+use std::{
+    collections::HashMap,
+    sync::{mpsc, Arc, Condvar, Mutex, RwLock},
+    thread,
+    time::{Duration, Instant},
+};
+
+use tracing::Span;
+
+use utils::comp_decomp::ReadiedPack;
+
+use super::TSTACK_SIZE;
+
+/// A frame a worker wants the main thread to draw to its output. The main thread must signal
+/// through the last field once it is done
+pub type Presentation = (String, Arc<ReadiedPack>, mpsc::SyncSender<()>);
+
+/// A frame waiting in an output's queue, along with the span of the request it is drawn for
+type Frame = (Arc<ReadiedPack>, Span);
+
+/// Counts the frames the workers took out of their queues, so that the threads waiting for room in
+/// a full queue can sleep until there is some, instead of polling
+#[derive(Default)]
+struct Room {
+    taken: Mutex<u64>,
+    changed: Condvar,
+}
+
+impl Room {
+    fn signal(&self) {
+        *self.taken.lock().unwrap() += 1;
+        self.changed.notify_all();
+    }
+}
+
+/// Sends frames to the queue of each output
+#[derive(Clone, Default)]
+pub struct FrameSender {
+    queues: Arc<RwLock<HashMap<String, mpsc::SyncSender<Frame>>>>,
+    room: Arc<Room>,
+}
+
+/// The receiving end of an output's queue
+pub struct Queue {
+    frames: mpsc::Receiver<Frame>,
+    room: Arc<Room>,
+}
+
+impl Queue {
+    /// Waits for the next frame, and wakes up whoever is waiting for room to send theirs
+    pub fn recv(&self) -> Result<Frame, mpsc::RecvError> {
+        let frame = self.frames.recv()?;
+        self.room.signal();
+        Ok(frame)
+    }
+}
+
+impl FrameSender {
+    /// Creates a new queue for `output`, replacing the old one, if any
+    pub fn add_queue(&self, output: &str) -> Queue {
+        // Queues hold a single frame because we can't send a new frame without being absolutely
+        // sure that all previous have already been displayed
+        let (sender, frames) = mpsc::sync_channel(1);
+        self.queues
+            .write()
+            .unwrap()
+            .insert(output.to_string(), sender);
+        Queue {
+            frames,
+            room: Arc::clone(&self.room),
+        }
+    }
+
+    /// Removes the queues whose outputs don't match the predicate, which makes their workers exit
+    pub fn retain_queues(&self, mut f: impl FnMut(&str) -> bool) {
+        self.queues.write().unwrap().retain(|output, _| f(output));
+        // whoever is waiting to send to the removed queues can give up on them now
+        self.room.signal();
+    }
+
+    /// How many frames the workers took out of their queues so far, for `wait_for_room`
+    pub fn taken(&self) -> u64 {
+        *self.room.taken.lock().unwrap()
+    }
+
+    /// Sleeps until a worker takes a frame out of its queue, if none did since `taken` was
+    /// returned by `taken`, or for `timeout` at most
+    pub fn wait_for_room(&self, taken: u64, timeout: Duration) {
+        let guard = self.room.taken.lock().unwrap();
+        let _guard = self
+            .room
+            .changed
+            .wait_timeout_while(guard, timeout, |now| *now == taken)
+            .unwrap();
+    }
+
+    pub fn len(&self) -> usize {
+        self.queues.read().unwrap().len()
+    }
+
+    /// Fails with `Disconnected` if `output` has no queue. The frame is traced as part of the
+    /// current span
+    pub fn try_send(
+        &self,
+        output: &str,
+        frame: Arc<ReadiedPack>,
+    ) -> Result<(), mpsc::TrySendError<Arc<ReadiedPack>>> {
+        match self.queues.read().unwrap().get(output) {
+            Some(queue) => match queue.try_send((frame, Span::current())) {
+                Ok(()) => Ok(()),
+                Err(mpsc::TrySendError::Full((frame, _))) => Err(mpsc::TrySendError::Full(frame)),
+                Err(mpsc::TrySendError::Disconnected((frame, _))) => {
+                    Err(mpsc::TrySendError::Disconnected(frame))
+                }
+            },
+            None => Err(mpsc::TrySendError::Disconnected(frame)),
+        }
+    }
+}
+
+/// Spawns the worker for `output`. It exits once its queue is removed from the `FrameSender`
+pub fn spawn(output: String, frames: Queue, presenter: channel::Sender<Presentation>) {
+    if let Err(e) = thread::Builder::new()
+        .name(format!("{output} worker")) //Name our threads  for better log messages
+        .stack_size(TSTACK_SIZE) //the default of 2MB is way too overkill for this
+        .spawn(move || {
+            // A worker is always either waiting for a frame, or for the main thread to draw it
+            while let Ok((frame, span)) = frames.recv() {
+                let _entered = span.enter();
+                let (done, drawn) = mpsc::sync_channel(1);
+                let start = Instant::now();
+                if presenter.send((output.clone(), frame, done)).is_err() {
+                    break;
+                }
+                // this only fails if the main thread dropped the frame without drawing it, in
+                // which case there is nothing to wait for
+                if drawn.recv().is_ok() {
+                    tracing::debug!(
+                        "frame drawn on {output} after {:.3}s",
+                        start.elapsed().as_secs_f32()
+                    );
+                }
+            }
+            debug!("Worker for {output} exited");
+        })
+    {
+        error!("failed to spawn 'worker' thread: {e}");
+    }
+}
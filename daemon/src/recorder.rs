@@ -0,0 +1,159 @@
+//! Records what we commit to an output into a video, by piping the frames to ffmpeg. Frames are
+//! written at a constant rate: whatever was committed last when a frame is due is what ends up in
+//! the video, so static images are recorded just as well as animations.
+use log::{debug, error, info};
+
+use std::{
+    io::Write,
+    process::{Child, ChildStdin, Command, Stdio},
+    sync::mpsc,
+    thread,
+    time::{Duration, Instant},
+};
+
+use swww_protocol::{error::Error, Record};
+
+/// A committed frame, along with when it was committed
+type Frame = (Instant, Vec<u8>);
+
+/// How many committed frames may wait for the encoder before we start dropping them
+const QUEUE_SIZE: usize = 8;
+
+/// Sends the frames committed to an output to the thread feeding them to ffmpeg
+pub struct Recorder {
+    frames: mpsc::SyncSender<Frame>,
+    end: Instant,
+}
+
+impl Recorder {
+    /// Starts ffmpeg, and the thread feeding it. `first` is what the output is displaying right
+    /// now, in the Xrgb8888 format of our buffers
+    pub fn start(record: Record, dim: (u32, u32), first: Vec<u8>) -> Result<Self, Error> {
+        if !cfg!(feature = "ffmpeg") {
+            return Err(Error::Unsupported(
+                "swww-daemon was built without the 'ffmpeg' feature".to_string(),
+            ));
+        }
+
+        let mut ffmpeg = match Command::new("ffmpeg")
+            .args(["-y", "-loglevel", "error"])
+            .args(["-f", "rawvideo", "-pixel_format", "bgr0"])
+            .args(["-video_size", &format!("{}x{}", dim.0, dim.1)])
+            .args(["-framerate", &record.fps.to_string()])
+            .args(["-i", "-"])
+            // most encoders can't handle odd dimensions with yuv420p
+            .args([
+                "-vf",
+                "pad=ceil(iw/2)*2:ceil(ih/2)*2",
+                "-pix_fmt",
+                "yuv420p",
+            ])
+            .arg(&record.path)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::null())
+            .stderr(Stdio::piped())
+            .spawn()
+        {
+            Ok(child) => child,
+            Err(e) => return Err(Error::Unsupported(format!("failed to spawn ffmpeg: {e}"))),
+        };
+        let stdin = match ffmpeg.stdin.take() {
+            Some(stdin) => stdin,
+            None => return Err(Error::Other("failed to open ffmpeg's stdin".to_string())),
+        };
+
+        let (sender, receiver) = mpsc::sync_channel(QUEUE_SIZE);
+        let end = Instant::now() + record.duration;
+        if let Err(e) = thread::Builder::new()
+            .name(format!("{} recorder", record.output)) //Name our threads  for better log messages
+            .spawn(move || encode(ffmpeg, stdin, receiver, first, record))
+        {
+            return Err(Error::Other(format!(
+                "failed to spawn 'recorder' thread: {e}"
+            )));
+        }
+        Ok(Self {
+            frames: sender,
+            end,
+        })
+    }
+
+    pub fn is_recording(&self) -> bool {
+        Instant::now() < self.end
+    }
+
+    /// Returns false once the recording is over
+    pub fn send(&self, frame: &[u8]) -> bool {
+        match self.frames.try_send((Instant::now(), frame.to_vec())) {
+            Ok(()) => true,
+            Err(mpsc::TrySendError::Full(_)) => {
+                debug!("Encoder is lagging behind, dropping committed frame");
+                true
+            }
+            Err(mpsc::TrySendError::Disconnected(_)) => false,
+        }
+    }
+}
+
+fn encode(
+    ffmpeg: Child,
+    mut stdin: ChildStdin,
+    frames: mpsc::Receiver<Frame>,
+    first: Vec<u8>,
+    record: Record,
+) {
+    let start = Instant::now();
+    let interval = Duration::from_secs_f64(1.0 / record.fps as f64);
+    let total = (record.duration.as_secs_f64() * record.fps as f64).ceil() as u32;
+    let mut current = first;
+    // a frame committed after the one we are about to write was due
+    let mut pending: Option<Frame> = None;
+    'frames: for i in 0..total {
+        let due = start + interval * i;
+        loop {
+            match pending.take() {
+                Some((committed, frame)) if committed <= due => current = frame,
+                Some(frame) => {
+                    pending = Some(frame);
+                    break;
+                }
+                None => {
+                    let received = match due.checked_duration_since(Instant::now()) {
+                        Some(timeout) => frames.recv_timeout(timeout),
+                        None => frames.try_recv().map_err(|e| match e {
+                            mpsc::TryRecvError::Empty => mpsc::RecvTimeoutError::Timeout,
+                            mpsc::TryRecvError::Disconnected => {
+                                mpsc::RecvTimeoutError::Disconnected
+                            }
+                        }),
+                    };
+                    match received {
+                        Ok(frame) => pending = Some(frame),
+                        Err(mpsc::RecvTimeoutError::Timeout) => break,
+                        Err(mpsc::RecvTimeoutError::Disconnected) => {
+                            info!("Recording of {} was interrupted", record.output);
+                            break 'frames;
+                        }
+                    }
+                }
+            }
+        }
+        if let Err(e) = stdin.write_all(&current) {
+            error!("failed to send frame to ffmpeg: {e}");
+            break;
+        }
+    }
+    // closing stdin tells ffmpeg there are no more frames
+    drop(stdin);
+    match ffmpeg.wait_with_output() {
+        Ok(output) if output.status.success() => {
+            info!("Finished recording {} to {:?}", record.output, record.path)
+        }
+        Ok(output) => error!(
+            "ffmpeg failed to record {}: {}",
+            record.output,
+            String::from_utf8_lossy(&output.stderr).trim()
+        ),
+        Err(e) => error!("failed to wait for ffmpeg: {e}"),
+    }
+}
@@ -0,0 +1,335 @@
+//! `--listen`: accepting requests over TCP, so that a central machine can control the wallpapers of
+//! many others, like the displays of a digital signage wall. Clients first send the token given in
+//! `SWWW_TOKEN` on its own line, and then a request, exactly like they would send it to our socket.
+//! We forward it to our socket, so that it waits in the queue like any other request, and send the
+//! answer back.
+//!
+//! Only the requests that change or query what is displayed are forwarded (see [`allowed`]). The
+//! ones that run commands, write files, forget the cache or stop the daemon are refused, since a
+//! stolen token must not be enough to take over the machine.
+//!
+//! Nothing is encrypted, since there is no TLS, so we only listen on loopback addresses (see
+//! [`check_local`]). Other machines reach us through a tunnel that encrypts the connection, like
+//! ssh's port forwarding or stunnel, which also keeps the token from being read by anyone in between
+//!
+//! Connections that haven't authenticated yet are limited to [`MAX_PENDING`] at a time, and have
+//! [`TOKEN_TIMEOUT`] to send their token, so that clients without one can't tie up our threads
+use log::{debug, error, info, warn};
+use smithay_client_toolkit::reexports::calloop::{
+    generic::Generic, Interest, LoopHandle, Mode, PostAction,
+};
+
+use std::{
+    io::{self, BufRead, BufReader, Read},
+    net::{TcpListener, TcpStream, ToSocketAddrs},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+use swww_protocol::{error::Error, Answer, Generator, QueueCommand, Request, SlideshowCommand};
+
+/// Tokens longer than this are rejected without reading the rest of them
+const MAX_TOKEN_LEN: u64 = 1024;
+/// How long a client has to send its token, once connected
+const TOKEN_TIMEOUT: Duration = Duration::from_secs(5);
+/// How long an authenticated client has to send its request. Images can be big, and networks slow
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(60);
+/// How many connections may be waiting to authenticate at once. Any more are closed right away
+const MAX_PENDING: usize = 16;
+/// How long we wait for the address to be free, which it may not be yet right after the daemon
+/// we took over from exited
+const MAX_BIND_WAIT: Duration = Duration::from_secs(2);
+
+/// Where to listen, and the token clients must send
+#[derive(Clone)]
+pub struct Listen {
+    pub addr: String,
+    pub token: String,
+}
+
+/// Listens on `listen.addr`, accepting connections in the event loop of `handle`. Each of them is
+/// then handled in its own thread, since reading the request can take as long as the network
+/// does, and the answer only comes once the request went through the queue
+pub fn register<D>(handle: &LoopHandle<D>, listen: Listen) -> Result<(), String> {
+    let listener = bind(&listen.addr)?;
+    if let Err(e) = listener.set_nonblocking(true) {
+        return Err(format!(
+            "failed to set nonblocking mode for {}: {e}",
+            listen.addr
+        ));
+    }
+    let pending = Arc::new(AtomicUsize::new(0));
+    if let Err(e) = handle.insert_source(
+        Generic::new(listener, Interest::READ, Mode::Level),
+        move |_, listener, _| {
+            accept(listener, &listen.token, &pending);
+            Ok(PostAction::Continue)
+        },
+    ) {
+        return Err(format!("failed to register {}: {e}", listen.addr));
+    }
+    info!("Listening for remote clients on {}", listen.addr);
+    Ok(())
+}
+
+/// Refuses addresses other machines could connect to directly, since nothing we send or receive is
+/// encrypted. They must go through a tunnel to one of ours instead
+pub fn check_local(addr: &str) -> Result<(), String> {
+    let resolved = match addr.to_socket_addrs() {
+        Ok(resolved) => resolved.collect::<Vec<_>>(),
+        Err(e) => return Err(format!("failed to resolve {addr}: {e}")),
+    };
+    if resolved.is_empty() || resolved.iter().any(|addr| !addr.ip().is_loopback()) {
+        return Err(format!(
+            "--listen only accepts loopback addresses, like tcp://127.0.0.1:9500, since it \
+            doesn't encrypt anything. Reach it from other machines through a tunnel (like ssh's \
+            port forwarding, or stunnel) instead of listening on {addr}"
+        ));
+    }
+    Ok(())
+}
+
+fn bind(addr: &str) -> Result<TcpListener, String> {
+    let start = Instant::now();
+    loop {
+        match TcpListener::bind(addr) {
+            Ok(listener) => return Ok(listener),
+            Err(e) if e.kind() == io::ErrorKind::AddrInUse && start.elapsed() < MAX_BIND_WAIT => {
+                thread::sleep(Duration::from_millis(100));
+            }
+            Err(e) => return Err(format!("failed to listen on {addr}: {e}")),
+        }
+    }
+}
+
+/// Counts a connection waiting to authenticate for as long as it lives
+struct Pending(Arc<AtomicUsize>);
+
+impl Pending {
+    /// Counts another connection, unless there are already `MAX_PENDING` of them
+    fn new(pending: &Arc<AtomicUsize>) -> Option<Self> {
+        pending
+            .fetch_update(Ordering::AcqRel, Ordering::Acquire, |count| {
+                (count < MAX_PENDING).then_some(count + 1)
+            })
+            .ok()
+            .map(|_| Self(Arc::clone(pending)))
+    }
+}
+
+impl Drop for Pending {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::AcqRel);
+    }
+}
+
+fn accept(listener: &TcpListener, token: &str, pending: &Arc<AtomicUsize>) {
+    loop {
+        let (stream, peer) = match listener.accept() {
+            Ok(accepted) => accepted,
+            Err(e) if e.kind() == io::ErrorKind::WouldBlock => return,
+            Err(e) => {
+                error!("Failed to accept remote connection: {e}");
+                return;
+            }
+        };
+        // the connection may inherit the listener's nonblocking mode on some systems
+        if let Err(e) = stream.set_nonblocking(false) {
+            error!("Failed to set blocking mode for remote connection: {e}");
+            continue;
+        }
+        let Some(pending) = Pending::new(pending) else {
+            warn!("Closed connection from {peer}: too many clients are waiting to authenticate");
+            continue;
+        };
+        let token = token.to_string();
+        if let Err(e) = thread::Builder::new()
+            .name("remote".to_string())
+            .spawn(move || handle(stream, &token, pending))
+        {
+            error!("failed to spawn thread for remote connection: {e}");
+        }
+    }
+}
+
+fn handle(stream: TcpStream, token: &str, pending: Pending) {
+    let peer = match stream.peer_addr() {
+        Ok(peer) => peer.to_string(),
+        Err(_) => "unknown address".to_string(),
+    };
+    let set_timeout = |timeout| {
+        let set = stream.set_read_timeout(Some(timeout));
+        if let Err(e) = &set {
+            error!("Failed to set read timeout for {peer}: {e}");
+        }
+        set.is_ok()
+    };
+    if !set_timeout(TOKEN_TIMEOUT) {
+        return;
+    }
+    let mut reader = BufReader::new(&stream);
+    let authenticated = authenticate(&mut reader, token);
+    drop(pending);
+    if authenticated.is_ok() && !set_timeout(REQUEST_TIMEOUT) {
+        return;
+    }
+    let answer = match authenticated {
+        Ok(()) => match Request::receive(&mut reader) {
+            Ok(request) if !allowed(&request) => {
+                warn!("Refused {} from {peer}", crate::describe_request(&request));
+                Answer::Err(Error::Unsupported(format!(
+                    "{} can't be sent to swww-daemon over the network",
+                    crate::describe_request(&request)
+                )))
+            }
+            Ok(request) => {
+                debug!(
+                    "Forwarding {} from {peer}",
+                    crate::describe_request(&request)
+                );
+                swww_client::send(request).unwrap_or_else(Answer::Err)
+            }
+            Err(e) => Answer::Err(e),
+        },
+        Err(e) => {
+            warn!("Rejected remote client at {peer}: {e}");
+            Answer::Err(e)
+        }
+    };
+    if let Err(e) = answer.send(&stream) {
+        error!("error sending answer to remote client at {peer}: {e}");
+    }
+}
+
+/// Whether `request` may come from the network. Anything that runs commands (external generators),
+/// reads or writes files (slideshows and recordings), forgets the cache, stops or restarts the daemon, or tells what other
+/// clients asked for (traces) is only accepted on our socket
+fn allowed(request: &Request) -> bool {
+    match request {
+        Request::Generate(generate) => !matches!(generate.generator, Generator::External { .. }),
+        Request::Queue(command) => matches!(command, QueueCommand::List),
+        Request::Slideshow(command) => matches!(command, SlideshowCommand::Stop(_)),
+        Request::Animation(_)
+        | Request::Adjust(_)
+        | Request::Busy(_)
+        | Request::Clear(_)
+        | Request::Colors(_)
+        | Request::Disable(_)
+        | Request::Enable(_)
+        | Request::Follow(_)
+        | Request::Group(_)
+        | Request::Img(_)
+        | Request::Manual(_)
+        | Request::Patch(_)
+        | Request::Pin(_)
+        | Request::Playback
+        | Request::Query
+        | Request::Region(_)
+        | Request::SafeArea(_)
+        | Request::Transaction(_)
+        | Request::Version => true,
+        Request::Init
+        | Request::Kill
+        | Request::Record(_)
+        | Request::Refresh(_)
+        | Request::Trace(_) => false,
+    }
+}
+
+/// Reads the token line, and checks it against ours
+fn authenticate(reader: &mut impl BufRead, token: &str) -> Result<(), Error> {
+    let mut line = Vec::new();
+    if let Err(e) = reader
+        .by_ref()
+        .take(MAX_TOKEN_LEN)
+        .read_until(b'\n', &mut line)
+    {
+        return Err(Error::Ipc(format!("failed to read token: {e}")));
+    }
+    if line.pop() != Some(b'\n') {
+        return Err(Error::Ipc("expected a token on its own line".to_string()));
+    }
+    if tokens_match(&line, token.as_bytes()) {
+        Ok(())
+    } else {
+        Err(Error::Ipc("invalid token".to_string()))
+    }
+}
+
+/// Compares every byte, no matter where the first difference is, so that how long we take doesn't
+/// tell how much of a guess was right
+fn tokens_match(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |diff, (a, b)| diff | (a ^ b)) == 0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn should_check_the_token_line() {
+        let check = |input: &[u8]| authenticate(&mut BufReader::new(input), "secret");
+        assert!(check(b"secret\n").is_ok());
+        assert!(check(b"secret\nrest of the request").is_ok());
+        assert!(check(b"secreT\n").is_err());
+        assert!(check(b"secret").is_err());
+        assert!(check(b"secret2\n").is_err());
+        assert!(check(b"\n").is_err());
+    }
+
+    #[test]
+    fn should_only_listen_on_loopback_addresses() {
+        assert!(check_local("127.0.0.1:9500").is_ok());
+        assert!(check_local("[::1]:9500").is_ok());
+        assert!(check_local("0.0.0.0:9500").is_err());
+        assert!(check_local("10.0.0.5:9500").is_err());
+        assert!(check_local("[::]:9500").is_err());
+    }
+
+    #[test]
+    fn should_limit_the_clients_waiting_to_authenticate() {
+        let count = Arc::new(AtomicUsize::new(0));
+        let mut pending: Vec<Pending> = (0..MAX_PENDING)
+            .map(|_| Pending::new(&count).unwrap())
+            .collect();
+        assert!(Pending::new(&count).is_none());
+        pending.pop();
+        assert!(Pending::new(&count).is_some());
+        pending.clear();
+        assert_eq!(count.load(Ordering::Acquire), 0);
+    }
+
+    #[test]
+    fn should_refuse_requests_that_can_take_over_the_machine() {
+        let generate = |generator| {
+            Request::Generate(swww_protocol::Generate {
+                generator,
+                outputs: Vec::new(),
+            })
+        };
+        assert!(allowed(&Request::Query));
+        assert!(allowed(&Request::Queue(QueueCommand::List)));
+        assert!(allowed(&Request::Slideshow(SlideshowCommand::Stop(
+            Vec::new()
+        ))));
+        assert!(!allowed(&Request::Kill));
+        assert!(!allowed(&Request::Queue(QueueCommand::Clear)));
+        assert!(!allowed(&Request::Refresh(swww_protocol::Refresh {
+            outputs: Vec::new()
+        })));
+        assert!(!allowed(&Request::Record(swww_protocol::Record {
+            output: String::new(),
+            duration: Duration::from_secs(1),
+            fps: 30,
+            path: "/tmp/out.mp4".into(),
+        })));
+        assert!(!allowed(&generate(Generator::External {
+            command: vec!["true".to_string()],
+            fps: 30,
+        })));
+    }
+}
@@ -0,0 +1,120 @@
+//! Waits for the session to be ready before setting the first wallpaper, as asked with `--delay`
+//! and `--wait-for`. Compositors are often still setting up the outputs when they start us, so
+//! until then we only clear the outputs, and leave the cached images and most requests for later
+use log::{debug, error, warn};
+use smithay_client_toolkit::reexports::calloop::{
+    timer::{TimeoutAction, Timer},
+    LoopHandle,
+};
+
+use std::{
+    process::{Child, Command, Stdio},
+    time::{Duration, Instant},
+};
+
+use utils::communication::MAX_SESSION_WAIT;
+
+/// How often we check whether `--wait-for`'s command exited
+const POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+pub struct SessionWait {
+    /// How long to wait, at least
+    pub delay: Option<Duration>,
+    /// Command (run with `sh -c`) that exits once the session is ready
+    pub command: Option<String>,
+}
+
+/// `--wait-for`'s command, once it is running
+struct Waiting {
+    command: String,
+    child: Child,
+    start: Instant,
+}
+
+impl SessionWait {
+    /// Whether there is anything to wait for at all
+    pub fn is_needed(&self) -> bool {
+        self.delay.is_some() || self.command.is_some()
+    }
+
+    /// Waits with a timer in the event loop of `handle`, calling `ready` once the session is ready.
+    /// The timer first fires after the delay, starting the command, and then keeps firing until the
+    /// command exits
+    pub fn register<'l, D>(
+        self,
+        handle: &LoopHandle<'l, D>,
+        mut ready: impl FnMut(&mut D) + 'l,
+    ) -> Result<(), String> {
+        let mut command = self.command;
+        let mut waiting: Option<Waiting> = None;
+        let timer = Timer::from_duration(self.delay.unwrap_or_default());
+        let result = handle.insert_source(timer, move |_, _, data| {
+            if let Some(command) = command.take() {
+                waiting = start(command);
+            }
+            if waiting
+                .as_mut()
+                .is_some_and(|waiting| !waiting.has_exited())
+            {
+                return TimeoutAction::ToDuration(POLL_INTERVAL);
+            }
+            ready(data);
+            TimeoutAction::Drop
+        });
+        match result {
+            Ok(_) => Ok(()),
+            Err(e) => Err(format!("failed to register session timer: {e}")),
+        }
+    }
+}
+
+/// Starts `command`, which we then wait for at most `MAX_SESSION_WAIT`
+fn start(command: String) -> Option<Waiting> {
+    debug!("Waiting for the session with {command:?}");
+    match Command::new("sh")
+        .arg("-c")
+        .arg(&command)
+        .stdin(Stdio::null())
+        .spawn()
+    {
+        Ok(child) => Some(Waiting {
+            command,
+            child,
+            start: Instant::now(),
+        }),
+        Err(e) => {
+            error!("failed to run {command:?}: {e}. Not waiting for the session");
+            None
+        }
+    }
+}
+
+impl Waiting {
+    /// Whether we are done waiting for the command, because it exited, or took too long
+    fn has_exited(&mut self) -> bool {
+        let command = &self.command;
+        match self.child.try_wait() {
+            Ok(Some(status)) if !status.success() => {
+                warn!("{command:?} failed ({status}), assuming the session is ready anyway");
+                true
+            }
+            Ok(Some(_)) => true,
+            Ok(None) if self.start.elapsed() >= MAX_SESSION_WAIT => {
+                warn!(
+                    "{command:?} didn't exit in {}s, assuming the session is ready",
+                    MAX_SESSION_WAIT.as_secs()
+                );
+                if let Err(e) = self.child.kill() {
+                    error!("failed to kill {command:?}: {e}");
+                }
+                let _ = self.child.wait();
+                true
+            }
+            Ok(None) => false,
+            Err(e) => {
+                error!("failed to wait for {command:?}: {e}");
+                true
+            }
+        }
+    }
+}
@@ -0,0 +1,411 @@
+//! Slideshows, started with `swww img <DIRECTORY>`: the images in a directory, displayed one after
+//! the other on some outputs. Each slideshow has a timer in the event loop, ticking once per
+//! interval, and a thread decoding its images. The timer ticks `--lead-time` before each image is
+//! due, so that the thread, running at the lowest priority, has the image ready by then: the switch
+//! is instant, and preparing it doesn't compete with what the user is doing. Like the fifo's
+//! commands, the images are sent to our own socket, so they wait in the queue like any other
+//! request, and decoding them never holds up the event loop. The directory is read again before every round, so images added to it (or
+//! removed from it) are picked up without starting over. The filters (`--newer-than` and
+//! `--min-resolution`) are evaluated then too, so that folders like screenshots don't surface
+//! ancient or tiny images
+use log::{debug, error};
+use rand::{rngs::StdRng, seq::SliceRandom, Rng, SeedableRng};
+use smithay_client_toolkit::reexports::calloop::{
+    timer::{TimeoutAction, Timer},
+    LoopHandle, RegistrationToken,
+};
+
+use std::{
+    collections::VecDeque,
+    path::{Path, PathBuf},
+    sync::{
+        mpsc::{self, Receiver, TrySendError},
+        Arc, Mutex,
+    },
+    thread,
+    time::{Instant, SystemTime},
+};
+
+use swww_protocol::{
+    error::Error, Answer, BgInfo, Request, Slideshow, SlideshowCommand, SlideshowSort,
+};
+use utils::variant;
+
+#[derive(Default)]
+pub struct Slideshows {
+    shows: Vec<Show>,
+}
+
+struct Show {
+    /// Shared with the slideshow's thread, which only displays its images on these. It is emptied
+    /// when the slideshow stops, in case the thread was in the middle of preparing an image
+    outputs: Arc<Mutex<Vec<String>>>,
+    timer: RegistrationToken,
+}
+
+impl Slideshows {
+    pub fn handle<'l, D>(
+        &mut self,
+        handle: &LoopHandle<'l, D>,
+        infos: &[&BgInfo],
+        command: SlideshowCommand,
+    ) -> Answer {
+        match command {
+            SlideshowCommand::Start(slideshow) => self.start(handle, infos, slideshow),
+            SlideshowCommand::Stop(outputs) => {
+                self.stop(handle, &outputs);
+                Answer::Ok
+            }
+        }
+    }
+
+    fn start<'l, D>(
+        &mut self,
+        handle: &LoopHandle<'l, D>,
+        infos: &[&BgInfo],
+        slideshow: Slideshow,
+    ) -> Answer {
+        if let Some(output) = slideshow
+            .outputs
+            .iter()
+            .find(|output| !infos.iter().any(|info| info.name == **output))
+        {
+            return Answer::Err(Error::Output(format!("Output {output} doesn't exist")));
+        }
+        let outputs: Vec<String> = infos
+            .iter()
+            .filter(|info| info.is_requested(&slideshow.outputs))
+            .map(|info| info.name.clone())
+            .collect();
+        if outputs.is_empty() {
+            return Answer::Err(Error::Output(
+                "none of the requested outputs are valid".to_string(),
+            ));
+        }
+        if let Err(e) = images(&slideshow) {
+            return Answer::Err(Error::InvalidRequest(e));
+        }
+
+        // a tick waiting for the thread is enough: if it is still busy with the last one by the
+        // next, it is already late, and catching up would only flash the images it missed
+        let (ticks, receiver) = mpsc::sync_channel(1);
+        let interval = slideshow.interval;
+        let lead = slideshow.lead.min(interval);
+        // the first image is displayed right away
+        let mut due = Instant::now();
+        let timer = handle.insert_source(Timer::immediate(), move |_, _, _| {
+            match ticks.try_send(due) {
+                Ok(()) => (),
+                Err(TrySendError::Full(_)) => debug!("slideshow is late, skipping an image"),
+                Err(TrySendError::Disconnected(_)) => return TimeoutAction::Drop,
+            }
+            // when we are late (eg.: after a suspend), the next image is due an interval after
+            // this one is displayed, instead of catching up
+            due = due.max(Instant::now()) + interval;
+            TimeoutAction::ToInstant(due - lead)
+        });
+        let timer = match timer {
+            Ok(timer) => timer,
+            Err(e) => {
+                return Answer::Err(Error::Other(format!(
+                    "failed to set up the slideshow's timer: {e}"
+                )))
+            }
+        };
+
+        self.stop(handle, &outputs);
+        let shared = Arc::new(Mutex::new(outputs));
+        let thread_outputs = Arc::clone(&shared);
+        if let Err(e) = thread::Builder::new()
+            .name("slideshow".to_string()) //Name our threads  for better log messages
+            .spawn(move || run(slideshow, &thread_outputs, receiver))
+        {
+            handle.remove(timer);
+            return Answer::Err(Error::Other(format!(
+                "failed to spawn 'slideshow' thread: {e}"
+            )));
+        }
+        self.shows.push(Show {
+            outputs: shared,
+            timer,
+        });
+        Answer::Ok
+    }
+
+    /// Takes `outputs` (or every output, if empty) out of their slideshows, stopping the ones left
+    /// without any
+    fn stop<'l, D>(&mut self, handle: &LoopHandle<'l, D>, outputs: &[String]) {
+        self.shows.retain(|show| {
+            let mut shown = show.outputs.lock().unwrap_or_else(|e| e.into_inner());
+            shown.retain(|output| !outputs.is_empty() && !outputs.contains(output));
+            if shown.is_empty() {
+                // dropping the timer's callback hangs up on the thread, which then exits
+                handle.remove(show.timer);
+            }
+            !shown.is_empty()
+        });
+    }
+}
+
+/// Displays an image when each tick's time is due
+fn run(slideshow: Slideshow, outputs: &Mutex<Vec<String>>, ticks: Receiver<Instant>) {
+    // only this thread, on linux
+    if unsafe { libc::setpriority(libc::PRIO_PROCESS, 0, 19) } != 0 {
+        debug!(
+            "failed to lower the slideshow's priority: {}",
+            std::io::Error::last_os_error()
+        );
+    }
+    let dir = &slideshow.dir;
+    let mut rng = match slideshow.seed {
+        Some(seed) => StdRng::seed_from_u64(seed),
+        None => StdRng::from_entropy(),
+    };
+    let mut round = VecDeque::new();
+    let mut last = None;
+    for due in ticks {
+        if round.is_empty() {
+            match images(&slideshow) {
+                Ok(mut images) => {
+                    if slideshow.sort == SlideshowSort::Random {
+                        shuffle(&mut images, last.as_deref(), &mut rng);
+                    }
+                    round = images.into();
+                }
+                Err(e) => {
+                    error!("slideshow stopped showing new images: {e}");
+                    continue;
+                }
+            }
+        }
+        let Some(path) = round.pop_front() else {
+            continue;
+        };
+        let requested = outputs.lock().unwrap_or_else(|e| e.into_inner()).clone();
+        if let Err(e) = display(&path, &requested, due, &slideshow, outputs) {
+            error!("slideshow of {dir:?} failed to display {path:?}: {e}");
+        }
+        last = Some(path);
+    }
+    debug!("slideshow of {dir:?} stopped");
+}
+
+/// Prepares the image at `path` for `requested`, and sends it to the ones still in the slideshow
+/// once it is `due`
+fn display(
+    path: &Path,
+    requested: &[String],
+    due: Instant,
+    slideshow: &Slideshow,
+    outputs: &Mutex<Vec<String>>,
+) -> Result<(), Error> {
+    let mut request = swww_client::image_request(path, requested, slideshow.transition.clone())?;
+    thread::sleep(due.saturating_duration_since(Instant::now()));
+    if let Request::Img((_, imgs)) = &mut request {
+        let outputs = outputs.lock().unwrap_or_else(|e| e.into_inner());
+        for (_, names) in imgs.iter_mut() {
+            names.retain(|name| outputs.contains(name));
+        }
+        imgs.retain(|(_, names)| !names.is_empty());
+        if imgs.is_empty() {
+            return Ok(());
+        }
+    }
+    swww_client::send(request).map(|_| ())
+}
+
+/// The images in the slideshow's directory that pass its filters, in the order of its sort (by
+/// name, for `Random`). Hidden files, files whose extension isn't one of an image format, and the
+/// variants of other images (which come with them), are left out
+fn images(slideshow: &Slideshow) -> Result<Vec<PathBuf>, String> {
+    let dir = &slideshow.dir;
+    let entries = match std::fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(e) => return Err(format!("failed to read directory {dir:?}: {e}")),
+    };
+    let paths: Vec<PathBuf> = entries
+        .filter_map(Result::ok)
+        .map(|entry| entry.path())
+        .filter(|path| is_image(path))
+        .collect();
+    let now = SystemTime::now();
+    let mut images: Vec<(PathBuf, SystemTime)> = paths
+        .iter()
+        .filter(|path| variant::base(path).is_none_or(|base| !paths.contains(&base)))
+        .filter_map(|path| {
+            let modified = std::fs::metadata(path).and_then(|m| m.modified()).ok()?;
+            Some((path.clone(), modified))
+        })
+        .filter(|(_, modified)| {
+            slideshow.newer_than.is_none_or(|newer_than| {
+                now.duration_since(*modified).unwrap_or_default() <= newer_than
+            })
+        })
+        .filter(|(path, _)| {
+            slideshow
+                .min_resolution
+                .is_none_or(|min| is_large(path, min))
+        })
+        .collect();
+    if images.is_empty() {
+        return Err(
+            if slideshow.newer_than.is_some() || slideshow.min_resolution.is_some() {
+                format!("directory {dir:?} has no images passing the filters")
+            } else {
+                format!("directory {dir:?} has no images")
+            },
+        );
+    }
+    match slideshow.sort {
+        SlideshowSort::Mtime => images.sort_by(|a, b| (a.1, &a.0).cmp(&(b.1, &b.0))),
+        SlideshowSort::Name | SlideshowSort::Random => images.sort(),
+    }
+    Ok(images.into_iter().map(|(path, _)| path).collect())
+}
+
+/// Whether the image at `path` is at least `min` in both dimensions. Only its header is read
+fn is_large(path: &Path, min: (u32, u32)) -> bool {
+    match image::image_dimensions(path) {
+        Ok((width, height)) => width >= min.0 && height >= min.1,
+        Err(_) => false,
+    }
+}
+
+fn is_image(path: &Path) -> bool {
+    let hidden = path
+        .file_name()
+        .is_some_and(|name| name.to_string_lossy().starts_with('.'));
+    !hidden && path.is_file() && image::ImageFormat::from_path(path).is_ok()
+}
+
+/// Shuffles `images`, without starting with `last`, so that rounds don't show it twice in a row
+fn shuffle(images: &mut [PathBuf], last: Option<&Path>, rng: &mut impl Rng) {
+    images.shuffle(rng);
+    if images.len() > 1 && images.first().map(PathBuf::as_path) == last {
+        let other = rng.gen_range(1..images.len());
+        images.swap(0, other);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+    use swww_protocol::{Coord, Position, Transition, TransitionSync, TransitionType};
+
+    fn slideshow(dir: &Path) -> Slideshow {
+        Slideshow {
+            dir: dir.to_path_buf(),
+            outputs: Vec::new(),
+            interval: Duration::from_secs(300),
+            lead: Duration::from_secs(10),
+            sort: SlideshowSort::Name,
+            newer_than: None,
+            min_resolution: None,
+            seed: None,
+            transition: Transition {
+                transition_type: TransitionType::Simple,
+                duration: 3.0,
+                step: 2,
+                fps: 30,
+                angle: 45.0,
+                pos: Position::new(Coord::Percent(0.5), Coord::Percent(0.5)),
+                bezier: (0.54, 0.0, 0.34, 0.99),
+                wave: (20.0, 20.0),
+                sync: TransitionSync::None,
+                skip_threshold: 0.0,
+            },
+        }
+    }
+
+    #[test]
+    fn should_list_the_images_by_name() {
+        let dir = std::env::temp_dir().join(format!("swww-slideshow-{}", std::process::id()));
+        std::fs::create_dir_all(dir.join("nested.png")).unwrap();
+        let names = [
+            "b.png",
+            "b.1920x1080.png",
+            "c.1920x1080.png",
+            "a.JPG",
+            ".hidden.png",
+            "x.txt",
+        ];
+        for name in names {
+            std::fs::write(dir.join(name), []).unwrap();
+        }
+        let found = images(&slideshow(&dir));
+        let empty = images(&slideshow(&dir.join("nested.png")));
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(
+            found.unwrap(),
+            [
+                dir.join("a.JPG"),
+                dir.join("b.png"),
+                dir.join("c.1920x1080.png")
+            ]
+        );
+        assert!(empty.unwrap_err().ends_with("has no images"));
+    }
+
+    #[test]
+    fn should_filter_and_sort_the_images() {
+        let dir = std::env::temp_dir().join(format!("swww-slideshow-f-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let month_ago = SystemTime::now() - Duration::from_secs(30 * 24 * 60 * 60);
+        for (name, size, modified) in [
+            ("a.png", 16, SystemTime::now()),
+            ("b.png", 4, SystemTime::now()),
+            ("c.png", 16, month_ago),
+        ] {
+            let path = dir.join(name);
+            image::RgbaImage::new(size, size).save(&path).unwrap();
+            let file = std::fs::File::options().write(true).open(&path).unwrap();
+            file.set_modified(modified).unwrap();
+        }
+        let names = |change: fn(&mut Slideshow)| -> Vec<String> {
+            let mut slideshow = slideshow(&dir);
+            change(&mut slideshow);
+            match images(&slideshow) {
+                Ok(images) => images
+                    .iter()
+                    .map(|path| path.file_name().unwrap().to_string_lossy().into_owned())
+                    .collect(),
+                Err(e) => vec![e],
+            }
+        };
+        let by_mtime = names(|s| s.sort = SlideshowSort::Mtime);
+        let large = names(|s| s.min_resolution = Some((8, 8)));
+        let recent = names(|s| s.newer_than = Some(Duration::from_secs(24 * 60 * 60)));
+        let none = names(|s| s.min_resolution = Some((100, 100)));
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(by_mtime[0], "c.png");
+        assert_eq!(large, ["a.png", "c.png"]);
+        assert_eq!(recent, ["a.png", "b.png"]);
+        assert!(none[0].ends_with("has no images passing the filters"));
+    }
+
+    #[test]
+    fn shuffled_rounds_should_not_start_with_the_last_image() {
+        let mut rng = StdRng::seed_from_u64(0);
+        let all: Vec<PathBuf> = ["a", "b", "c"].iter().map(PathBuf::from).collect();
+        for _ in 0..50 {
+            let mut round = all.clone();
+            shuffle(&mut round, Some(Path::new("a")), &mut rng);
+            assert_ne!(round[0], Path::new("a"));
+            round.sort();
+            assert_eq!(round, all);
+        }
+    }
+
+    #[test]
+    fn seeded_rounds_should_repeat() {
+        let all: Vec<PathBuf> = (0..10).map(|i| PathBuf::from(i.to_string())).collect();
+        let round = |seed| {
+            let mut round = all.clone();
+            shuffle(&mut round, None, &mut StdRng::seed_from_u64(seed));
+            round
+        };
+        assert_eq!(round(7), round(7));
+        assert_ne!(round(7), round(8));
+    }
+}
@@ -0,0 +1,84 @@
+//! `--replace`: taking over from the daemon that is already running, so that upgrading it doesn't
+//! make the wallpaper flash. Until our own surfaces are drawn over the old daemon's, we listen on
+//! a temporary socket. Then we tell the old daemon to exit, and move our socket into its place
+use log::{debug, info, warn};
+
+use std::{
+    fs, io,
+    os::unix::net::UnixStream,
+    path::PathBuf,
+    thread,
+    time::{Duration, Instant},
+};
+
+use swww_protocol::{Answer, Request};
+use utils::communication::get_socket_path;
+
+use crate::remote::Listen;
+
+/// How long we wait for our outputs to be drawn before taking over anyway
+const MAX_DRAW_WAIT: Duration = Duration::from_secs(3);
+/// How long the old daemon has to exit once it is told to
+const MAX_EXIT_WAIT: Duration = Duration::from_secs(5);
+
+pub struct Takeover {
+    since: Instant,
+    /// Whether to make the fifo once we are done, which the old daemon deletes when it exits
+    pub fifo: bool,
+    /// Where to listen for remote clients once we are done, which the old daemon is still doing
+    pub listen: Option<Listen>,
+}
+
+impl Takeover {
+    pub fn new(fifo: bool, listen: Option<Listen>) -> Self {
+        Self {
+            since: Instant::now(),
+            fifo,
+            listen,
+        }
+    }
+
+    /// Where we listen until we take over
+    pub fn socket_path() -> PathBuf {
+        get_socket_path().with_file_name("swww-takeover.socket")
+    }
+
+    /// Whether it is time to take over, now that the outputs are `drawn` or not
+    pub fn is_due(&self, drawn: bool) -> bool {
+        drawn || self.since.elapsed() >= MAX_DRAW_WAIT
+    }
+
+    /// Makes the old daemon exit, and moves our socket into its place
+    pub fn run(&self) -> Result<(), String> {
+        let socket_path = get_socket_path();
+        match UnixStream::connect(&socket_path) {
+            Ok(stream) => {
+                info!("Telling the previous daemon to exit");
+                if let Err(e) = Request::Kill
+                    .send(&stream)
+                    .and_then(|()| Answer::receive(stream))
+                {
+                    return Err(format!("failed to tell the previous daemon to exit: {e}"));
+                }
+                // it deletes its socket on its way out, which would delete ours if we were quicker
+                let start = Instant::now();
+                while socket_path.exists() {
+                    if start.elapsed() >= MAX_EXIT_WAIT {
+                        warn!("The previous daemon didn't exit in time, taking over anyway");
+                        break;
+                    }
+                    thread::sleep(Duration::from_millis(20));
+                }
+            }
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                debug!("There was no daemon to take over from");
+            }
+            Err(e) => warn!("Failed to connect to the previous daemon, taking over anyway: {e}"),
+        }
+        if let Err(e) = fs::rename(Self::socket_path(), &socket_path) {
+            return Err(format!("failed to move socket to {socket_path:?}: {e}"));
+        }
+        info!("Took over {socket_path:?}");
+        Ok(())
+    }
+}
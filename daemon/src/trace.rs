@@ -0,0 +1,348 @@
+//! Per-request traces, for `swww trace`. Every request gets a `request` span when it is read from
+//! the socket, which the threads working on it (transitions, animations, the outputs' workers)
+//! enter, so that the events they record end up in the same trace. Instead of logging them, we
+//! keep the events of the last few requests in memory, until someone asks for them
+use std::{
+    cell::RefCell,
+    collections::{HashMap, VecDeque},
+    fmt::{self, Write},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+use tracing::{
+    field::{Field, Visit},
+    span, Event, Metadata, Span, Subscriber,
+};
+use tracing_core::span::Current;
+
+/// How many requests we keep the traces of
+const MAX_TRACES: usize = 64;
+/// How many events we keep per request. Animations send frames forever, so we must stop somewhere
+const MAX_EVENTS: usize = 512;
+
+static NEXT_REQUEST: AtomicU64 = AtomicU64::new(1);
+
+thread_local! {
+    /// The spans this thread entered, innermost last
+    static ENTERED: RefCell<Vec<span::Id>> = const { RefCell::new(Vec::new()) };
+}
+
+/// Makes the span of a new request, described by `what`. Returns its id along with it
+pub fn request(what: &str) -> (u64, Span) {
+    let id = NEXT_REQUEST.fetch_add(1, Ordering::Relaxed);
+    (id, tracing::info_span!("request", id, what))
+}
+
+/// Everything recorded about a request
+struct Trace {
+    id: u64,
+    what: String,
+    start: Instant,
+    events: Vec<(Duration, String)>,
+    /// Events that didn't fit in `events`
+    dropped: usize,
+    /// How long it took, once everything working on the request is done with it
+    took: Option<Duration>,
+}
+
+impl Trace {
+    fn push(&mut self, event: String) {
+        if self.events.len() < MAX_EVENTS {
+            let thread = thread::current();
+            let thread = thread.name().unwrap_or("unnamed");
+            self.events
+                .push((self.start.elapsed(), format!("[{thread}] {event}")));
+        } else {
+            self.dropped += 1;
+        }
+    }
+
+    fn summary(&self) -> String {
+        match self.took {
+            Some(took) => format!(
+                "#{} {}: took {:.3}s",
+                self.id,
+                self.what,
+                took.as_secs_f32()
+            ),
+            None => format!(
+                "#{} {}: running for {:.3}s",
+                self.id,
+                self.what,
+                self.start.elapsed().as_secs_f32()
+            ),
+        }
+    }
+}
+
+/// The traces of the last requests, oldest first
+#[derive(Clone, Default)]
+pub struct Traces(Arc<Mutex<VecDeque<Trace>>>);
+
+impl Traces {
+    /// One line for each request we have the trace of
+    pub fn list(&self) -> Vec<String> {
+        self.0.lock().unwrap().iter().map(Trace::summary).collect()
+    }
+
+    /// The events of request `id`, one per line, or `None` if it is too old (or never existed)
+    pub fn get(&self, id: u64) -> Option<Vec<String>> {
+        let traces = self.0.lock().unwrap();
+        let trace = traces.iter().find(|trace| trace.id == id)?;
+        let mut lines = vec![trace.summary()];
+        for (at, event) in &trace.events {
+            lines.push(format!("  +{:.3}s {event}", at.as_secs_f32()));
+        }
+        if trace.dropped > 0 {
+            lines.push(format!("  ({} more events were dropped)", trace.dropped));
+        }
+        Some(lines)
+    }
+
+    fn with_trace(&self, request: u64, f: impl FnOnce(&mut Trace)) {
+        let mut traces = self.0.lock().unwrap();
+        if let Some(trace) = traces.iter_mut().rev().find(|trace| trace.id == request) {
+            f(trace);
+        }
+    }
+}
+
+struct SpanData {
+    metadata: &'static Metadata<'static>,
+    /// The request the span is part of, if any
+    request: Option<u64>,
+    start: Instant,
+    refs: usize,
+}
+
+/// Records the events of every request into its trace
+pub struct Recorder {
+    spans: Mutex<HashMap<u64, SpanData>>,
+    next_span: AtomicU64,
+    traces: Traces,
+}
+
+impl Recorder {
+    pub fn new(traces: Traces) -> Self {
+        Self {
+            spans: Mutex::new(HashMap::new()),
+            next_span: AtomicU64::new(1),
+            traces,
+        }
+    }
+
+    /// The request of `parent`, or of the innermost span this thread entered
+    fn request_of(&self, parent: Option<&span::Id>, contextual: bool) -> Option<u64> {
+        let parent = match parent {
+            Some(parent) => Some(parent.clone()),
+            None if contextual => ENTERED.with(|entered| entered.borrow().last().cloned()),
+            None => None,
+        }?;
+        self.spans.lock().unwrap().get(&parent.into_u64())?.request
+    }
+}
+
+/// Turns the fields of spans and events into text, remembering the ones making up a request
+#[derive(Default)]
+struct Fields {
+    message: String,
+    rest: String,
+    id: Option<u64>,
+    what: Option<String>,
+}
+
+impl Fields {
+    fn text(self) -> String {
+        match (self.message.is_empty(), self.rest.is_empty()) {
+            (_, true) => self.message,
+            (true, false) => self.rest.trim_start().to_string(),
+            (false, false) => format!("{}{}", self.message, self.rest),
+        }
+    }
+}
+
+impl Visit for Fields {
+    fn record_u64(&mut self, field: &Field, value: u64) {
+        if field.name() == "id" {
+            self.id = Some(value);
+        }
+        self.record_debug(field, &value);
+    }
+
+    fn record_str(&mut self, field: &Field, value: &str) {
+        if field.name() == "what" {
+            self.what = Some(value.to_string());
+        } else {
+            self.record_debug(field, &value);
+        }
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+        if field.name() == "message" {
+            let _ = write!(self.message, "{value:?}");
+        } else {
+            let _ = write!(self.rest, " {}={value:?}", field.name());
+        }
+    }
+}
+
+impl Subscriber for Recorder {
+    fn enabled(&self, _: &Metadata<'_>) -> bool {
+        true
+    }
+
+    fn new_span(&self, attrs: &span::Attributes<'_>) -> span::Id {
+        let id = self.next_span.fetch_add(1, Ordering::Relaxed);
+        let mut fields = Fields::default();
+        attrs.record(&mut fields);
+        let name = attrs.metadata().name();
+        let request = match (name, fields.id) {
+            ("request", Some(request)) => {
+                let mut traces = self.traces.0.lock().unwrap();
+                if traces.len() == MAX_TRACES {
+                    traces.pop_front();
+                }
+                traces.push_back(Trace {
+                    id: request,
+                    what: fields.what.take().unwrap_or_default(),
+                    start: Instant::now(),
+                    events: Vec::new(),
+                    dropped: 0,
+                    took: None,
+                });
+                Some(request)
+            }
+            _ => {
+                let request = self.request_of(attrs.parent(), attrs.is_contextual());
+                if let Some(request) = request {
+                    let text = fields.text();
+                    self.traces.with_trace(request, |trace| {
+                        trace.push(format!("{name} started {text}").trim_end().to_string())
+                    });
+                }
+                request
+            }
+        };
+        self.spans.lock().unwrap().insert(
+            id,
+            SpanData {
+                metadata: attrs.metadata(),
+                request,
+                start: Instant::now(),
+                refs: 1,
+            },
+        );
+        span::Id::from_u64(id)
+    }
+
+    fn record(&self, _: &span::Id, _: &span::Record<'_>) {}
+
+    fn record_follows_from(&self, _: &span::Id, _: &span::Id) {}
+
+    fn event(&self, event: &Event<'_>) {
+        let Some(request) = self.request_of(event.parent(), event.is_contextual()) else {
+            return;
+        };
+        let mut fields = Fields::default();
+        event.record(&mut fields);
+        self.traces
+            .with_trace(request, |trace| trace.push(fields.text()));
+    }
+
+    fn enter(&self, span: &span::Id) {
+        ENTERED.with(|entered| entered.borrow_mut().push(span.clone()));
+    }
+
+    fn exit(&self, span: &span::Id) {
+        ENTERED.with(|entered| {
+            let mut entered = entered.borrow_mut();
+            if let Some(i) = entered.iter().rposition(|id| id == span) {
+                entered.remove(i);
+            }
+        });
+    }
+
+    fn current_span(&self) -> Current {
+        let Some(current) = ENTERED.with(|entered| entered.borrow().last().cloned()) else {
+            return Current::none();
+        };
+        match self.spans.lock().unwrap().get(&current.into_u64()) {
+            Some(data) => Current::new(current, data.metadata),
+            None => Current::none(),
+        }
+    }
+
+    fn clone_span(&self, span: &span::Id) -> span::Id {
+        if let Some(data) = self.spans.lock().unwrap().get_mut(&span.into_u64()) {
+            data.refs += 1;
+        }
+        span.clone()
+    }
+
+    fn try_close(&self, span: span::Id) -> bool {
+        let mut spans = self.spans.lock().unwrap();
+        let Some(data) = spans.get_mut(&span.into_u64()) else {
+            return false;
+        };
+        data.refs -= 1;
+        if data.refs > 0 {
+            return false;
+        }
+        let data = spans.remove(&span.into_u64()).unwrap();
+        drop(spans);
+        if let Some(request) = data.request {
+            let took = data.start.elapsed();
+            self.traces.with_trace(request, |trace| {
+                if data.metadata.name() == "request" {
+                    trace.took = Some(took);
+                } else {
+                    let name = data.metadata.name();
+                    trace.push(format!("{name} finished after {:.3}s", took.as_secs_f32()));
+                }
+            });
+        }
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use tracing::{dispatcher, Dispatch};
+
+    #[test]
+    fn should_follow_requests_across_threads() {
+        let traces = Traces::default();
+        let dispatch = Dispatch::new(Recorder::new(traces.clone()));
+        dispatcher::with_default(&dispatch, || {
+            let (id, span) = request("img on DP-1");
+            let entered = span.enter();
+            tracing::info!("queued");
+            let current = Span::current();
+            let dispatch = dispatch.clone();
+            thread::spawn(move || {
+                dispatcher::with_default(&dispatch, || {
+                    let _entered = current.enter();
+                    tracing::info!(output = "DP-1", "frame drawn");
+                })
+            })
+            .join()
+            .unwrap();
+            drop(entered);
+
+            let lines = traces.get(id).unwrap();
+            assert!(lines[0].starts_with(&format!("#{id} img on DP-1: running for")));
+            assert!(lines[1].ends_with("] queued"));
+            assert!(lines[2].ends_with("] frame drawn output=\"DP-1\""));
+            drop(span);
+            assert!(traces.list()[0].starts_with(&format!("#{id} img on DP-1: took")));
+            assert_eq!(traces.get(id + 1), None);
+        });
+    }
+}
@@ -4,11 +4,12 @@ use smithay_client_toolkit::{
     output::{OutputHandler, XdgOutputHandler},
     reexports::{
         client::{
-            protocol::{wl_compositor, wl_output, wl_shm},
+            protocol::{wl_compositor, wl_output, wl_shm, wl_subcompositor},
             Display, EventQueue,
         },
         protocols::{
             unstable::xdg_output::v1::client::zxdg_output_manager_v1,
+            viewporter::client::wp_viewporter,
             wlr::unstable::layer_shell::v1::client::zwlr_layer_shell_v1,
         },
     },
@@ -17,16 +18,20 @@ use smithay_client_toolkit::{
 
 pub struct Env {
     compositor: SimpleGlobal<wl_compositor::WlCompositor>,
+    subcompositor: SimpleGlobal<wl_subcompositor::WlSubcompositor>,
     shm: ShmHandler,
     outputs: OutputHandler,
     xdg_out: XdgOutputHandler,
     layer_shell: SimpleGlobal<zwlr_layer_shell_v1::ZwlrLayerShellV1>,
+    viewporter: SimpleGlobal<wp_viewporter::WpViewporter>,
 }
 
 smithay_client_toolkit::environment!(Env,
 singles = [
     wl_compositor::WlCompositor => compositor,
+    wl_subcompositor::WlSubcompositor => subcompositor,
     zwlr_layer_shell_v1::ZwlrLayerShellV1 => layer_shell,
+    wp_viewporter::WpViewporter => viewporter,
     wl_shm::WlShm => shm,
     zxdg_output_manager_v1::ZxdgOutputManagerV1 => xdg_out
 ],
@@ -59,8 +64,10 @@ pub fn make_wayland_environment() -> (Environment<Env>, Display, EventQueue) {
         &mut event_queue,
         Env {
             compositor: SimpleGlobal::new(),
+            subcompositor: SimpleGlobal::new(),
             shm: ShmHandler::new(),
             layer_shell: SimpleGlobal::new(),
+            viewporter: SimpleGlobal::new(),
             xdg_out,
             outputs,
         },
@@ -0,0 +1,426 @@
+//! Just enough of the X11 protocol to draw the root window's background: we speak it ourselves
+//! over the display's unix socket, instead of pulling in xlib or xcb for a handful of requests
+use std::{
+    io::{Read, Write},
+    ops::Range,
+    os::unix::net::UnixStream,
+    path::PathBuf,
+};
+
+const CREATE_PIXMAP: u8 = 53;
+const CREATE_GC: u8 = 55;
+const PUT_IMAGE: u8 = 72;
+const INTERN_ATOM: u8 = 16;
+const CHANGE_PROPERTY: u8 = 18;
+const CHANGE_WINDOW_ATTRIBUTES: u8 = 2;
+const CLEAR_AREA: u8 = 61;
+const GET_INPUT_FOCUS: u8 = 43;
+
+/// The predefined atom of the PIXMAP type
+const PIXMAP_ATOM: u32 = 20;
+const CW_BACK_PIXMAP: u32 = 1;
+const Z_PIXMAP: u8 = 2;
+
+/// Where the display is, from `$DISPLAY`
+#[derive(Debug, PartialEq)]
+pub struct Display {
+    pub number: u32,
+    pub screen: usize,
+}
+
+impl Display {
+    /// Only local displays are supported, eg.: ':0', ':1.0' or 'unix:0'
+    pub fn parse(raw: &str) -> Result<Self, String> {
+        let Some(("" | "unix", rest)) = raw.split_once(':') else {
+            return Err(format!(
+                "only local X11 displays are supported, found: {raw}"
+            ));
+        };
+        let (number, screen) = rest.split_once('.').unwrap_or((rest, "0"));
+        match (number.parse(), screen.parse()) {
+            (Ok(number), Ok(screen)) => Ok(Self { number, screen }),
+            _ => Err(format!("invalid X11 display: {raw}")),
+        }
+    }
+}
+
+/// What we need to know about the screen we draw to
+#[derive(Debug, PartialEq)]
+struct Setup {
+    resource_base: u32,
+    resource_mask: u32,
+    /// In units of 4 bytes, like the length of every request
+    max_request_length: u16,
+    root: u32,
+    width: u16,
+    height: u16,
+    depth: u8,
+}
+
+pub struct Connection {
+    stream: UnixStream,
+    root: u32,
+    pub dim: (u32, u32),
+    depth: u8,
+    pixmap: u32,
+    gc: u32,
+    /// `_XROOTPMAP_ID` and `ESETROOT_PMAP_ID`, which tell compositors and other programs where the
+    /// background is, to draw pseudo transparent windows
+    root_atoms: [u32; 2],
+    max_request_length: usize,
+    /// The sequence number of the last request, which replies and errors refer to
+    sequence: u16,
+}
+
+impl Connection {
+    pub fn connect(display: &Display) -> Result<Self, String> {
+        let path = format!("/tmp/.X11-unix/X{}", display.number);
+        let mut stream = match UnixStream::connect(&path) {
+            Ok(stream) => stream,
+            Err(e) => return Err(format!("failed to connect to the X server at {path}: {e}")),
+        };
+        let (name, data) = cookie(display.number).unwrap_or_default();
+        let mut hello = vec![b'l', 0];
+        hello.extend(11u16.to_le_bytes());
+        hello.extend(0u16.to_le_bytes());
+        hello.extend((name.len() as u16).to_le_bytes());
+        hello.extend((data.len() as u16).to_le_bytes());
+        hello.extend([0, 0]);
+        hello.extend(padded(&name));
+        hello.extend(padded(&data));
+        write(&mut stream, &hello)?;
+
+        let mut header = [0u8; 8];
+        read(&mut stream, &mut header)?;
+        let mut body = vec![0u8; u16::from_le_bytes([header[6], header[7]]) as usize * 4];
+        read(&mut stream, &mut body)?;
+        if header[0] != 1 {
+            let reason = match header[0] {
+                0 => &body[..(header[1] as usize).min(body.len())],
+                _ => b"the server wants more authentication than a cookie",
+            };
+            return Err(format!(
+                "the X server refused the connection: {}",
+                String::from_utf8_lossy(reason).trim_end()
+            ));
+        }
+        let setup = parse_setup(&body, display.screen)?;
+
+        let shift = setup.resource_mask.trailing_zeros();
+        let mut conn = Self {
+            stream,
+            root: setup.root,
+            dim: (setup.width as u32, setup.height as u32),
+            depth: setup.depth,
+            pixmap: setup.resource_base | (1 << shift),
+            gc: setup.resource_base | (2 << shift),
+            root_atoms: [0; 2],
+            max_request_length: setup.max_request_length as usize * 4,
+            sequence: 0,
+        };
+        let mut body = Vec::new();
+        push_u32s(&mut body, &[conn.pixmap, conn.root]);
+        push_u16s(&mut body, &[setup.width, setup.height]);
+        conn.send(CREATE_PIXMAP, setup.depth, &body)?;
+        let mut body = Vec::new();
+        push_u32s(&mut body, &[conn.gc, conn.root, 0]);
+        conn.send(CREATE_GC, 0, &body)?;
+        conn.root_atoms = [
+            conn.intern_atom("_XROOTPMAP_ID")?,
+            conn.intern_atom("ESETROOT_PMAP_ID")?,
+        ];
+        Ok(conn)
+    }
+
+    /// Draws the `rows` of `canvas`, which has the size of the screen, to the root window
+    pub fn draw(&mut self, canvas: &[u8], rows: Range<u32>) -> Result<(), String> {
+        let stride = self.dim.0 as usize * 4;
+        // PutImage's header takes 24 bytes
+        let rows_per_request = ((self.max_request_length - 24) / stride).max(1) as u32;
+        let mut y = rows.start;
+        while y < rows.end {
+            let height = rows_per_request.min(rows.end - y);
+            let mut body = Vec::new();
+            push_u32s(&mut body, &[self.pixmap, self.gc]);
+            push_u16s(&mut body, &[self.dim.0 as u16, height as u16, 0, y as u16]);
+            body.extend([0, self.depth, 0, 0]);
+            let start = y as usize * stride;
+            body.extend(&canvas[start..start + height as usize * stride]);
+            self.send(PUT_IMAGE, Z_PIXMAP, &body)?;
+            y += height;
+        }
+        let mut body = Vec::new();
+        push_u32s(&mut body, &[self.root, CW_BACK_PIXMAP, self.pixmap]);
+        self.send(CHANGE_WINDOW_ATTRIBUTES, 0, &body)?;
+        let mut body = Vec::new();
+        push_u32s(&mut body, &[self.root]);
+        push_u16s(&mut body, &[0, 0, 0, 0]);
+        self.send(CLEAR_AREA, 0, &body)?;
+        for atom in self.root_atoms {
+            let mut body = Vec::new();
+            push_u32s(&mut body, &[self.root, atom, PIXMAP_ATOM]);
+            body.extend([32, 0, 0, 0]);
+            push_u32s(&mut body, &[1, self.pixmap]);
+            self.send(CHANGE_PROPERTY, 0, &body)?;
+        }
+        // waiting for the reply of a request means the server is done with the ones before it
+        self.send(GET_INPUT_FOCUS, 0, &[])?;
+        self.reply().map(|_| ())
+    }
+
+    fn intern_atom(&mut self, name: &str) -> Result<u32, String> {
+        let mut body = Vec::new();
+        push_u16s(&mut body, &[name.len() as u16, 0]);
+        body.extend(padded(name.as_bytes()));
+        self.send(INTERN_ATOM, 0, &body)?;
+        let reply = self.reply()?;
+        Ok(u32::from_le_bytes(reply[8..12].try_into().unwrap()))
+    }
+
+    /// `body` must already be padded to a multiple of 4 bytes
+    fn send(&mut self, opcode: u8, data: u8, body: &[u8]) -> Result<(), String> {
+        let mut request = vec![opcode, data];
+        request.extend((((4 + body.len()) / 4) as u16).to_le_bytes());
+        request.extend(body);
+        self.sequence = self.sequence.wrapping_add(1);
+        write(&mut self.stream, &request)
+    }
+
+    /// Reads the reply to the last request, skipping events. Errors of the requests before it are
+    /// reported instead
+    fn reply(&mut self) -> Result<Vec<u8>, String> {
+        let mut error = None;
+        loop {
+            let mut reply = vec![0u8; 32];
+            read(&mut self.stream, &mut reply)?;
+            match reply[0] {
+                0 => {
+                    error = error.or(Some(format!(
+                        "the X server failed request {} with error {}",
+                        reply[10], reply[1]
+                    )));
+                    // requests failing don't get a reply
+                    if u16::from_le_bytes([reply[2], reply[3]]) == self.sequence {
+                        return Err(error.unwrap());
+                    }
+                }
+                1 => {
+                    let extra = u32::from_le_bytes(reply[4..8].try_into().unwrap()) as usize * 4;
+                    let mut rest = vec![0u8; extra];
+                    read(&mut self.stream, &mut rest)?;
+                    reply.extend(rest);
+                    return match error {
+                        Some(error) => Err(error),
+                        None => Ok(reply),
+                    };
+                }
+                _ => continue,
+            }
+        }
+    }
+}
+
+fn parse_setup(body: &[u8], screen: usize) -> Result<Setup, String> {
+    let too_short = || "the X server's setup was cut short".to_string();
+    let u16_at = |i: usize| -> Result<u16, String> {
+        let bytes = body.get(i..i + 2).ok_or_else(too_short)?;
+        Ok(u16::from_le_bytes(bytes.try_into().unwrap()))
+    };
+    let u32_at = |i: usize| -> Result<u32, String> {
+        let bytes = body.get(i..i + 4).ok_or_else(too_short)?;
+        Ok(u32::from_le_bytes(bytes.try_into().unwrap()))
+    };
+    let u8_at = |i: usize| body.get(i).copied().ok_or_else(too_short);
+
+    let vendor_len = u16_at(16)? as usize;
+    let screens = u8_at(20)? as usize;
+    let formats = u8_at(21)? as usize;
+    if u8_at(22)? != 0 {
+        return Err("only X servers with a little endian image byte order are supported".into());
+    }
+    if screen >= screens {
+        return Err(format!("the X server has no screen {screen}"));
+    }
+    let formats_start = 32 + vendor_len.div_ceil(4) * 4;
+    let mut i = formats_start + formats * 8;
+    for _ in 0..screen {
+        let depths = u8_at(i + 39)? as usize;
+        i += 40;
+        for _ in 0..depths {
+            i += 8 + u16_at(i + 2)? as usize * 24;
+        }
+    }
+    let depth = u8_at(i + 38)?;
+    let bpp = (0..formats)
+        .map(|f| formats_start + f * 8)
+        .find(|f| body.get(*f) == Some(&depth))
+        .map(|f| u8_at(f + 1))
+        .transpose()?;
+    let visual = u32_at(i + 32)?;
+    let mut masks = None;
+    let mut d = i + 40;
+    for _ in 0..u8_at(i + 39)? {
+        let visuals = u16_at(d + 2)? as usize;
+        for v in 0..visuals {
+            let v = d + 8 + v * 24;
+            if u32_at(v)? == visual {
+                masks = Some((u32_at(v + 8)?, u32_at(v + 12)?, u32_at(v + 16)?));
+            }
+        }
+        d += 8 + visuals * 24;
+    }
+    if !matches!(depth, 24 | 32) || bpp != Some(32) || masks != Some((0xff0000, 0xff00, 0xff)) {
+        return Err(format!(
+            "only 24 bit true color X screens are supported, found a depth of {depth}"
+        ));
+    }
+    Ok(Setup {
+        resource_base: u32_at(4)?,
+        resource_mask: u32_at(8)?,
+        max_request_length: u16_at(18)?,
+        root: u32_at(i)?,
+        width: u16_at(i + 20)?,
+        height: u16_at(i + 22)?,
+        depth,
+    })
+}
+
+/// The MIT-MAGIC-COOKIE-1 for `display` in the Xauthority file, if there is one. Without it, we
+/// try connecting anyway, which works when the server lets local users in
+fn cookie(display: u32) -> Option<(Vec<u8>, Vec<u8>)> {
+    let path = match std::env::var_os("XAUTHORITY") {
+        Some(path) => PathBuf::from(path),
+        None => PathBuf::from(std::env::var_os("HOME")?).join(".Xauthority"),
+    };
+    find_cookie(&std::fs::read(path).ok()?, display)
+}
+
+fn find_cookie(mut file: &[u8], display: u32) -> Option<(Vec<u8>, Vec<u8>)> {
+    let field = |file: &mut &[u8]| -> Option<Vec<u8>> {
+        let len = u16::from_be_bytes(file.get(0..2)?.try_into().unwrap()) as usize;
+        let field = file.get(2..2 + len)?.to_vec();
+        *file = &file[2 + len..];
+        Some(field)
+    };
+    while file.len() >= 2 {
+        let family = u16::from_be_bytes(file[0..2].try_into().unwrap());
+        file = &file[2..];
+        let _address = field(&mut file)?;
+        let number = field(&mut file)?;
+        let name = field(&mut file)?;
+        let data = field(&mut file)?;
+        // local, or any address
+        let local = family == 256 || family == 65535;
+        let number_matches = number.is_empty() || number == display.to_string().as_bytes();
+        if local && number_matches && name == b"MIT-MAGIC-COOKIE-1" {
+            return Some((name, data));
+        }
+    }
+    None
+}
+
+fn padded(bytes: &[u8]) -> Vec<u8> {
+    let mut padded = bytes.to_vec();
+    padded.resize(bytes.len().div_ceil(4) * 4, 0);
+    padded
+}
+
+fn push_u32s(body: &mut Vec<u8>, values: &[u32]) {
+    values.iter().for_each(|v| body.extend(v.to_le_bytes()));
+}
+
+fn push_u16s(body: &mut Vec<u8>, values: &[u16]) {
+    values.iter().for_each(|v| body.extend(v.to_le_bytes()));
+}
+
+fn write(stream: &mut UnixStream, bytes: &[u8]) -> Result<(), String> {
+    match stream.write_all(bytes) {
+        Ok(()) => Ok(()),
+        Err(e) => Err(format!("failed to write to the X server: {e}")),
+    }
+}
+
+fn read(stream: &mut UnixStream, buf: &mut [u8]) -> Result<(), String> {
+    match stream.read_exact(buf) {
+        Ok(()) => Ok(()),
+        Err(e) => Err(format!("failed to read from the X server: {e}")),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn should_parse_local_displays() {
+        let display = |number, screen| Ok(Display { number, screen });
+        assert_eq!(Display::parse(":0"), display(0, 0));
+        assert_eq!(Display::parse(":1.2"), display(1, 2));
+        assert_eq!(Display::parse("unix:3"), display(3, 0));
+        assert!(Display::parse("remote:0").is_err());
+        assert!(Display::parse(":x").is_err());
+    }
+
+    #[test]
+    fn should_find_the_display_cookie() {
+        let mut file = Vec::new();
+        let mut entry = |family: u16, number: &str, data: &[u8]| {
+            file.extend(family.to_be_bytes());
+            for field in [&b"host"[..], number.as_bytes(), b"MIT-MAGIC-COOKIE-1", data] {
+                file.extend((field.len() as u16).to_be_bytes());
+                file.extend(field);
+            }
+        };
+        entry(256, "0", b"zero");
+        entry(256, "1", b"one");
+        let (name, data) = find_cookie(&file, 1).unwrap();
+        assert_eq!(
+            (name.as_slice(), data.as_slice()),
+            (&b"MIT-MAGIC-COOKIE-1"[..], &b"one"[..])
+        );
+        assert_eq!(find_cookie(&file, 2), None);
+    }
+
+    #[test]
+    fn should_parse_the_setup() {
+        let mut body = vec![0u8; 32];
+        body[4..8].copy_from_slice(&0x0400_0000u32.to_le_bytes());
+        body[8..12].copy_from_slice(&0x001f_ffffu32.to_le_bytes());
+        body[16..18].copy_from_slice(&3u16.to_le_bytes());
+        body[18..20].copy_from_slice(&0xffffu16.to_le_bytes());
+        body[20] = 1;
+        body[21] = 1;
+        body.extend(padded(b"Foo"));
+        body.extend([24, 32, 32, 0, 0, 0, 0, 0]);
+        let mut screen = vec![0u8; 40];
+        screen[0..4].copy_from_slice(&0x1234u32.to_le_bytes());
+        screen[20..22].copy_from_slice(&1920u16.to_le_bytes());
+        screen[22..24].copy_from_slice(&1080u16.to_le_bytes());
+        screen[32..36].copy_from_slice(&0x21u32.to_le_bytes());
+        screen[38] = 24;
+        screen[39] = 1;
+        screen.extend([24, 0, 1, 0, 0, 0, 0, 0]);
+        let mut visual = vec![0u8; 24];
+        visual[0..4].copy_from_slice(&0x21u32.to_le_bytes());
+        visual[8..12].copy_from_slice(&0xff0000u32.to_le_bytes());
+        visual[12..16].copy_from_slice(&0xff00u32.to_le_bytes());
+        visual[16..20].copy_from_slice(&0xffu32.to_le_bytes());
+        screen.extend(visual);
+        body.extend(screen);
+
+        let setup = parse_setup(&body, 0).unwrap();
+        assert_eq!(
+            setup,
+            Setup {
+                resource_base: 0x0400_0000,
+                resource_mask: 0x001f_ffff,
+                max_request_length: 0xffff,
+                root: 0x1234,
+                width: 1920,
+                height: 1080,
+                depth: 24,
+            }
+        );
+        assert!(parse_setup(&body, 1).is_err());
+    }
+}
@@ -0,0 +1,323 @@
+//! A minimal backend for X sessions, behind the `x11` feature, for machines that occasionally boot
+//! into one. Like `feh --bg-fill`, we draw the wallpaper to the root window's background, so that
+//! `swww img`, `swww clear` and animated images keep working, transitions included. The whole
+//! screen is a single output, named `X11`, and the requests that only make sense with a surface
+//! per output (like `swww pin` or `swww disable`) are rejected
+use log::{debug, error, info};
+
+use smithay_client_toolkit::reexports::calloop::{
+    self,
+    channel::{self, Channel},
+    signals::{self, Signal},
+    LoopSignal,
+};
+
+use std::{
+    io,
+    os::unix::net::{UnixListener, UnixStream},
+    path::PathBuf,
+};
+
+use swww_protocol::{
+    error::Error, Answer, BgImg, BgInfo, Clear, Img, QueueCommand, Request, SafeArea,
+};
+use utils::{
+    cache::Cached,
+    communication::{get_fifo_path, get_socket_path},
+};
+
+use crate::{
+    processor::{evict_cache, Presentation, Processor},
+    Args,
+};
+
+mod conn;
+
+use conn::{Connection, Display};
+
+/// The name of the only output we have
+const OUTPUT: &str = "X11";
+
+/// Whether we are running in an X session, rather than a Wayland one
+pub fn is_session() -> bool {
+    std::env::var_os("WAYLAND_DISPLAY").is_none() && std::env::var_os("DISPLAY").is_some()
+}
+
+struct Screen {
+    conn: Connection,
+    info: BgInfo,
+    /// What the root window is displaying, in the same format as the Wayland outputs' buffers
+    canvas: Vec<u8>,
+}
+
+impl Screen {
+    fn draw(&mut self, frame: &utils::comp_decomp::ReadiedPack) {
+        if !frame.unpack(&mut self.canvas) {
+            error!("buf_len different from expected_buf_size");
+            return;
+        }
+        let rows = match frame.window() {
+            Some(window) => window.offset.1..window.offset.1 + window.dim.1,
+            None => 0..self.info.dim.1,
+        };
+        if let Err(e) = self.conn.draw(&self.canvas, rows) {
+            error!("Failed to draw to the root window: {e}");
+        }
+    }
+}
+
+struct X11Daemon {
+    screen: Screen,
+    processor: Processor,
+    signal: LoopSignal,
+}
+
+/// Runs the daemon until it is killed. Most of the command line applies like it does on Wayland,
+/// except for the layer-shell options, which we ignore, and `--replace`
+pub fn main(args: Args) -> Result<(), String> {
+    if args.replace {
+        return Err("--replace isn't supported on X11".to_string());
+    }
+    let raw = std::env::var("DISPLAY").unwrap_or_default();
+    let conn = Connection::connect(&Display::parse(&raw)?)?;
+    info!("Connected to X display {raw}, drawing to its root window");
+
+    let listener = crate::make_socket(get_socket_path())?;
+    if args.fifo {
+        crate::fifo::spawn(get_fifo_path())?;
+    }
+    evict_cache(args.cache_size, vec![OUTPUT.to_string()]);
+
+    let (presenter, frame_receiver) = channel::channel();
+    let mut event_loop = match calloop::EventLoop::<X11Daemon>::try_new() {
+        Ok(el) => el,
+        Err(e) => return Err(e.to_string()),
+    };
+    let handle = event_loop.handle();
+    register_sources(&handle, frame_receiver, listener)?;
+    if let Some(listen) = args.listen {
+        crate::remote::register(&handle, listen)?;
+    }
+
+    let dim = conn.dim;
+    let mut daemon = X11Daemon {
+        screen: Screen {
+            conn,
+            info: BgInfo {
+                name: OUTPUT.to_string(),
+                dim,
+                scale_factor: 1,
+                img: BgImg::Color([0, 0, 0]),
+                following: None,
+                pinned: false,
+                safe_area: SafeArea::default(),
+                buffer_dim: None,
+                hash: 0,
+            },
+            canvas: vec![0; dim.0 as usize * dim.1 as usize * 4],
+        },
+        processor: Processor::new(presenter, args.hook, args.reduce_motion),
+        signal: event_loop.get_signal(),
+    };
+    daemon.processor.add_output(OUTPUT);
+    daemon.processor.set_output_count(1);
+    let screen = &mut daemon.screen;
+    if let Err(e) = screen.conn.draw(&screen.canvas, 0..dim.1) {
+        return Err(format!("failed to clear the root window: {e}"));
+    }
+    match utils::cache::load(OUTPUT) {
+        Ok(Some(Cached::Img(img, animation))) => {
+            let cached = (img, animation);
+            let info = screen.info.clone();
+            if let Some(path) = daemon
+                .processor
+                .import_cached_img(info, &mut screen.canvas, cached)
+            {
+                screen.info.img = BgImg::Img(path);
+            }
+        }
+        Ok(Some(Cached::Color(color))) => {
+            screen.info.img = BgImg::Color(color);
+            for pixel in screen.canvas.chunks_exact_mut(4) {
+                pixel[..3].copy_from_slice(&[color[2], color[1], color[0]]);
+            }
+            if let Err(e) = screen.conn.draw(&screen.canvas, 0..dim.1) {
+                error!("Failed to draw to the root window: {e}");
+            }
+        }
+        // we have no generators to restore them with
+        Ok(Some(Cached::Generated(_)) | None) => (),
+        Err(e) => error!("failed to load cached image: {e}"),
+    }
+
+    info!("Initialization succeeded! Starting main loop...");
+    if let Err(e) = event_loop.run(None, &mut daemon, |_| ()) {
+        return Err(format!("Event loop closed unexpectedly: {e}"));
+    }
+    info!("Finished running event loop.");
+    crate::remove_socket(true, args.fifo)
+}
+
+fn register_sources(
+    handle: &calloop::LoopHandle<X11Daemon>,
+    frames: Channel<Presentation>,
+    listener: UnixListener,
+) -> Result<(), String> {
+    let signals = match signals::Signals::new(&[Signal::SIGINT, Signal::SIGQUIT, Signal::SIGTERM]) {
+        Ok(signals) => signals,
+        Err(e) => return Err(format!("failed to register signals to stop program: {e}")),
+    };
+    if let Err(e) = handle.insert_source(signals, |_, _, daemon| daemon.signal.stop()) {
+        return Err(format!("failed to insert signals source: {e}"));
+    }
+    if let Err(e) = handle.insert_source(frames, |evt, _, daemon| match evt {
+        channel::Event::Msg((_, frame, done)) => {
+            daemon.screen.draw(&frame);
+            // the worker may have exited in the meantime, in which case nobody is waiting for this
+            let _ = done.send(());
+        }
+        channel::Event::Closed => daemon.signal.stop(),
+    }) {
+        return Err(format!("failed to register channel: {e}"));
+    }
+    if let Err(e) = listener.set_nonblocking(true) {
+        return Err(format!("failed to set nonblocking mode for socket: {e}"));
+    };
+    if let Err(e) = handle.insert_source(
+        calloop::generic::Generic::new(listener, calloop::Interest::READ, calloop::Mode::Level),
+        |_, listener, daemon| {
+            loop {
+                match listener.accept() {
+                    Ok((stream, _)) => daemon.answer(stream),
+                    Err(e) if e.kind() == io::ErrorKind::WouldBlock => break,
+                    Err(e) => {
+                        error!("Failed to accept connection: {}", e);
+                        break;
+                    }
+                }
+            }
+            Ok(calloop::PostAction::Continue)
+        },
+    ) {
+        return Err(format!("failed to register socket: {e}"));
+    }
+    Ok(())
+}
+
+impl X11Daemon {
+    /// Requests are handled as soon as they arrive, since there is only one output to draw to
+    fn answer(&mut self, stream: UnixStream) {
+        let answer = match Request::receive(&stream) {
+            Ok(request) => {
+                debug!("Handling {}", crate::describe_request(&request));
+                self.handle(request)
+            }
+            Err(e) => Answer::Err(e),
+        };
+        if let Err(e) = answer.send(&stream) {
+            error!("error sending answer to client: {e}");
+        }
+    }
+
+    fn handle(&mut self, request: Request) -> Answer {
+        let screen = &mut self.screen;
+        let proc = &mut self.processor;
+        let size = screen.canvas.len();
+        match request {
+            Request::Init => Answer::Ok,
+            Request::Kill => {
+                self.signal.stop();
+                Answer::Ok
+            }
+            Request::Query => Answer::Info(vec![BgInfo {
+                hash: crc32fast::hash(&screen.canvas),
+                ..screen.info.clone()
+            }]),
+            Request::Playback => Answer::Playback(proc.playback()),
+            Request::Version => Answer::Version(crate::check::version()),
+            Request::Colors(colors) => {
+                if let Err(e) = check_outputs(std::iter::once(&colors.outputs)) {
+                    return Answer::Err(e);
+                }
+                let dim = screen.info.real_dim();
+                match crate::ambient::colors(OUTPUT, &screen.canvas, dim, colors.grid) {
+                    Ok(output) => Answer::Colors(vec![output]),
+                    Err(e) => Answer::Err(e),
+                }
+            }
+            Request::Queue(QueueCommand::List) => Answer::Queue(Vec::new()),
+            Request::Queue(QueueCommand::Clear) => Answer::Ok,
+            Request::SafeArea(set) if set.output == OUTPUT => {
+                screen.info.safe_area = set.area;
+                Answer::Ok
+            }
+            Request::Img((transition, imgs)) => {
+                if let Err(e) = check_outputs(imgs.iter().map(|img| &img.1)) {
+                    return Answer::Err(e);
+                }
+                if let Some((img, _)) = imgs.last() {
+                    screen.info.img = BgImg::Img(img.path.clone());
+                }
+                let old_imgs = imgs
+                    .iter()
+                    .map(|_| (screen.canvas.clone().into(), screen.info.dim))
+                    .collect();
+                proc.transition(&transition, imgs, old_imgs, Vec::new())
+            }
+            Request::Clear(Clear {
+                color,
+                outputs,
+                transition,
+            }) => {
+                if let Err(e) = check_outputs(std::iter::once(&outputs)) {
+                    return Answer::Err(e);
+                }
+                screen.info.img = BgImg::Color(color);
+                if let Err(e) = utils::cache::store_color(color, &[OUTPUT.to_string()]) {
+                    error!("failed to cache the color: {e}");
+                }
+                let img = Img {
+                    path: PathBuf::new(),
+                    img: [color[2], color[1], color[0], 255].repeat(size / 4),
+                };
+                let old_img = (screen.canvas.clone().into(), screen.info.dim);
+                proc.transition(
+                    &transition,
+                    vec![(img, vec![OUTPUT.to_string()])],
+                    vec![old_img],
+                    Vec::new(),
+                )
+            }
+            Request::Animation(animations) => {
+                if let Err(e) = check_outputs(animations.iter().map(|a| &a.1)) {
+                    return Answer::Err(e);
+                }
+                let path = match &screen.info.img {
+                    BgImg::Img(path) => Some(path.clone()),
+                    _ => None,
+                };
+                let mut answer = Answer::Ok;
+                for (animation, outputs) in animations {
+                    if let Answer::Err(e) = proc.animate(animation, outputs, size, path.clone()) {
+                        answer = Answer::Err(e);
+                    }
+                }
+                answer
+            }
+            request => Answer::Err(Error::Unsupported(format!(
+                "{} isn't supported on X11",
+                crate::describe_request(&request)
+            ))),
+        }
+    }
+}
+
+/// Every output must be ours. An empty list means every output, which is just ours too
+fn check_outputs<'a>(mut requested: impl Iterator<Item = &'a Vec<String>>) -> Result<(), Error> {
+    match requested.find_map(|outputs| outputs.iter().find(|output| *output != OUTPUT)) {
+        Some(output) => Err(Error::Output(format!(
+            "Output {output} doesn't exist, on X11 there is only {OUTPUT}"
+        ))),
+        None => Ok(()),
+    }
+}
@@ -1,7 +1,7 @@
 #!/bin/bash
 
 # This script will randomly go through the files of a directory, setting it
-# up as the wallpaper at regular intervals
+# up as the wallpaper of each output at regular intervals
 #
 # NOTE: this script is in bash (not posix shell), because the RANDOM variable
 # we use is not defined in posix
@@ -19,14 +19,51 @@ export SWWW_TRANSITION_STEP=2
 # This controls (in seconds) when to switch to the next image
 INTERVAL=300
 
-while true; do
-	find "$1" \
+# How long (in seconds) before each switch to start decoding and resizing the
+# next images. They are prepared at low priority in the meantime, so that the
+# switch itself is instant and doesn't spike the cpu (eg.: while gaming). Must be
+# smaller than INTERVAL, and 0 prepares them at the time of the switch
+PREWARM=10
+
+# How many of the last images shown in each output won't be repeated. The
+# history is kept in the files bellow, so that restarting the script (or the
+# computer) doesn't immediately show the same images again
+HISTORY=10
+HISTORY_DIR="${XDG_CACHE_HOME:-$HOME/.cache}/swww/randomize"
+mkdir -p "$HISTORY_DIR"
+
+# Picks a random image that isn't in the last $HISTORY lines of the file $1. If
+# there are too few images for that, we only avoid as many as we can
+pick_img() {
+	local all recent
+	all=$(find "$DIR" -type f)
+	recent=$(( $(echo "$all" | wc -l) - 1 ))
+	if [[ $recent -gt $HISTORY ]]; then
+		recent=$HISTORY
+	fi
+	echo "$all" | grep -vxFf <(tail -n "$recent" "$1" 2>/dev/null) \
 		| while read -r img; do
 			echo "$((RANDOM % 1000)):$img"
 		done \
-		| sort -n | cut -d':' -f2- \
-		| while read -r img; do
-			swww img "$img"
-			sleep $INTERVAL
-		done
+		| sort -n | cut -d':' -f2- | head -n 1
+}
+
+DIR=$1
+# the first images are displayed right away
+defer=0
+while true; do
+	for output in $(swww query | cut -d':' -f1); do
+		history="$HISTORY_DIR/$output"
+		img=$(pick_img "$history")
+		if [[ -n $img ]]; then
+			# every output waits for the same moment, so they are prepared together
+			nice -n 19 swww img --defer "${defer}s" -o "$output" "$img" &
+			echo "$img" >> "$history"
+			# keep only what we need, so the file doesn't grow forever
+			tail -n "$HISTORY" "$history" > "$history.tmp" && mv "$history.tmp" "$history"
+		fi
+	done
+	wait
+	sleep $((INTERVAL - PREWARM))
+	defer=$PREWARM
 done
@@ -0,0 +1,74 @@
+//! Color adjustments, which is what `swww adjust` does to images and animation frames. They work
+//! on every color channel alike, so they are a lookup table of 256 bytes, cheap enough to apply to
+//! every frame of an animation while it plays
+use serde::{Deserialize, Serialize};
+
+/// Changes to the colors of an image
+#[derive(Clone, Copy, PartialEq, Debug, Serialize, Deserialize)]
+pub struct Adjustment {
+    /// Scales the colors by this much percent, from -100 (black) to 100 (twice as bright)
+    pub brightness: i8,
+    /// Spreads the colors away from (or, when negative, towards) the middle gray by this much
+    /// percent, from -100 (flat gray) to 100 (twice as far)
+    pub contrast: i8,
+    /// Raises the colors, between 0 and 1, to `1 / gamma`. Above 1 brightens the midtones, under 1
+    /// darkens them
+    pub gamma: f32,
+}
+
+impl Default for Adjustment {
+    fn default() -> Self {
+        Self {
+            brightness: 0,
+            contrast: 0,
+            gamma: 1.0,
+        }
+    }
+}
+
+impl Adjustment {
+    /// Whether this leaves every color as it is
+    #[must_use]
+    pub fn is_identity(&self) -> bool {
+        *self == Self::default()
+    }
+
+    /// What every value of a color channel becomes. Gamma goes first, then contrast, then
+    /// brightness
+    #[must_use]
+    pub fn lut(&self) -> [u8; 256] {
+        let gamma = 1.0 / self.gamma.max(f32::EPSILON);
+        let contrast = 1.0 + f32::from(self.contrast) / 100.0;
+        let brightness = 1.0 + f32::from(self.brightness) / 100.0;
+        let mut lut = [0; 256];
+        for (i, value) in lut.iter_mut().enumerate() {
+            let mut v = i as f32;
+            if gamma != 1.0 {
+                v = (v / 255.0).powf(gamma) * 255.0;
+            }
+            v = (v - 127.5) * contrast + 127.5;
+            *value = (v * brightness).round().clamp(0.0, 255.0) as u8;
+        }
+        lut
+    }
+
+    /// Adjusts the color channels of `img`, in bgra
+    pub fn apply(&self, img: &mut [u8]) {
+        let lut = self.lut();
+        for pixel in img.chunks_exact_mut(4) {
+            for channel in &mut pixel[0..3] {
+                *channel = lut[*channel as usize];
+            }
+        }
+    }
+}
+
+impl std::fmt::Display for Adjustment {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "brightness {}%, contrast {}%, gamma {}",
+            self.brightness, self.contrast, self.gamma
+        )
+    }
+}
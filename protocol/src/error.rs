@@ -0,0 +1,138 @@
+//! Errors shared by `swww` and `swww-daemon`.
+//!
+//! The daemon answers failed requests with one of these, so what kind of error it was survives the
+//! trip through the socket. `swww` then exits with the error's code, letting scripts decide
+//! whether trying again makes sense.
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+#[derive(Debug, Clone, PartialEq, Eq, Error, Serialize, Deserialize)]
+pub enum Error {
+    /// Anything that doesn't fit the other kinds
+    #[error("{0}")]
+    Other(String),
+
+    /// Talking through the socket failed midway
+    #[error("{0}")]
+    Ipc(String),
+
+    /// The daemon isn't running (or, for `swww init`, is already running)
+    #[error("{0}")]
+    Daemon(String),
+
+    /// The request itself makes no sense, like an image whose size doesn't match its dimensions
+    #[error("{0}")]
+    InvalidRequest(String),
+
+    /// Some requested output doesn't exist, or can't be drawn to
+    #[error("{0}")]
+    Output(String),
+
+    /// The request clashes with the daemon's current state, like beginning a transaction while
+    /// another one is in progress
+    #[error("{0}")]
+    Conflict(String),
+
+    /// The request was cancelled with `swww queue clear`
+    #[error("{0}")]
+    Cancelled(String),
+
+    /// Opening or decoding an image failed
+    #[error("{0}")]
+    Image(String),
+
+    /// The daemon was built without the feature the request needs
+    #[error("{0}")]
+    Unsupported(String),
+}
+
+impl Error {
+    /// The code `swww` exits with. 2 is left for clap's usage errors
+    pub fn code(&self) -> u8 {
+        match self {
+            Error::Other(_) => 1,
+            Error::Ipc(_) => 3,
+            Error::Daemon(_) => 4,
+            Error::InvalidRequest(_) => 5,
+            Error::Output(_) => 6,
+            Error::Conflict(_) => 7,
+            Error::Cancelled(_) => 8,
+            Error::Image(_) => 9,
+            Error::Unsupported(_) => 10,
+        }
+    }
+
+    /// Whether the same request may succeed if it is simply sent again later
+    pub fn is_transient(&self) -> bool {
+        matches!(
+            self,
+            Error::Ipc(_) | Error::Conflict(_) | Error::Cancelled(_)
+        )
+    }
+
+    pub fn message(&self) -> &str {
+        match self {
+            Error::Other(msg)
+            | Error::Ipc(msg)
+            | Error::Daemon(msg)
+            | Error::InvalidRequest(msg)
+            | Error::Output(msg)
+            | Error::Conflict(msg)
+            | Error::Cancelled(msg)
+            | Error::Image(msg)
+            | Error::Unsupported(msg) => msg,
+        }
+    }
+
+    /// Prefixes the message with what we were doing, keeping the kind of error
+    pub fn context(mut self, what: &str) -> Self {
+        let msg = match &mut self {
+            Error::Other(msg)
+            | Error::Ipc(msg)
+            | Error::Daemon(msg)
+            | Error::InvalidRequest(msg)
+            | Error::Output(msg)
+            | Error::Conflict(msg)
+            | Error::Cancelled(msg)
+            | Error::Image(msg)
+            | Error::Unsupported(msg) => msg,
+        };
+        *msg = format!("{what}: {msg}");
+        self
+    }
+}
+
+/// Most of our internals still describe their errors with plain strings
+impl From<String> for Error {
+    fn from(msg: String) -> Self {
+        Error::Other(msg)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn context_should_keep_the_kind() {
+        let e = Error::Output("DP-1 doesn't exist".to_string()).context("failed to clear");
+        assert_eq!(
+            e,
+            Error::Output("failed to clear: DP-1 doesn't exist".to_string())
+        );
+        assert_eq!(e.code(), 6);
+    }
+
+    #[test]
+    fn codes_should_survive_the_socket() {
+        for e in [
+            Error::Other(String::new()),
+            Error::Conflict("busy".to_string()),
+            Error::Unsupported(String::new()),
+        ] {
+            let decoded: Error = bincode::deserialize(&bincode::serialize(&e).unwrap()).unwrap();
+            assert_eq!(decoded.code(), e.code());
+            assert_eq!(decoded, e);
+        }
+    }
+}
@@ -0,0 +1,967 @@
+//! The messages `swww` and `swww-daemon` exchange through the daemon's socket.
+//!
+//! Clients connect to the socket, send a single [`Request`], serialized with `bincode`, and read
+//! back a single [`Answer`]. Everything a request carries is defined here, so that other programs
+//! can talk to the daemon without depending on the rest of `swww`. See `doc/protocol.md` for the
+//! format of every message.
+use serde::{Deserialize, Serialize};
+use std::{
+    fmt,
+    io::{self, BufReader, BufWriter, Write},
+    os::unix::net::UnixStream,
+    path::PathBuf,
+    time::Duration,
+};
+
+pub mod adjust;
+pub mod error;
+pub mod transition;
+
+pub use adjust::Adjustment;
+pub use transition::{Coord, Position, Transition, TransitionSync, TransitionType};
+
+use error::Error;
+
+#[derive(PartialEq, Eq, Clone, Serialize, Deserialize)]
+pub enum BgImg {
+    Color([u8; 3]),
+    Img(PathBuf),
+    Generated(String),
+}
+
+impl fmt::Display for BgImg {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BgImg::Color(color) => write!(f, "color: {}{}{}", color[0], color[1], color[2]),
+            BgImg::Img(p) => write!(
+                f,
+                "image: {:#?}",
+                p.file_name().unwrap_or_else(|| std::ffi::OsStr::new("?"))
+            ),
+            BgImg::Generated(name) => write!(f, "generated: {name}"),
+        }
+    }
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct BgInfo {
+    pub name: String,
+    pub dim: (u32, u32),
+    pub scale_factor: i32,
+    pub img: BgImg,
+    /// Output whose wallpaper this one mirrors
+    pub following: Option<String>,
+    /// Pinned outputs are skipped by requests that don't name their outputs
+    pub pinned: bool,
+    /// Part of the output that images' important content should stay clear of
+    pub safe_area: SafeArea,
+    /// The resolution we draw the output at, when `--max-resolution` made it smaller than its
+    /// real one. The compositor scales it up
+    pub buffer_dim: Option<(u32, u32)>,
+    /// Crc32 of what the output is displaying, exactly as drawn. Tools deriving things from the
+    /// wallpaper can compare it to know whether it really changed. Only set in answers to queries
+    pub hash: u32,
+}
+
+impl BgInfo {
+    /// The resolution images for this output must have
+    #[must_use]
+    pub fn real_dim(&self) -> (u32, u32) {
+        self.buffer_dim.unwrap_or((
+            self.dim.0 * self.scale_factor as u32,
+            self.dim.1 * self.scale_factor as u32,
+        ))
+    }
+
+    /// Whether a request for `outputs` should be applied to this output. An empty list means every
+    /// output that isn't pinned
+    #[must_use]
+    pub fn is_requested(&self, outputs: &[String]) -> bool {
+        if outputs.is_empty() {
+            !self.pinned
+        } else {
+            outputs.contains(&self.name)
+        }
+    }
+}
+
+impl fmt::Display for BgInfo {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}: {}x{}, scale: {}, currently displaying: {}",
+            self.name, self.dim.0, self.dim.1, self.scale_factor, self.img
+        )?;
+        if let Some(source) = &self.following {
+            write!(f, ", following: {source}")?;
+        }
+        if self.pinned {
+            write!(f, ", pinned")?;
+        }
+        if !self.safe_area.is_empty() {
+            write!(f, ", safe area: {}", self.safe_area)?;
+        }
+        if let Some((width, height)) = self.buffer_dim {
+            write!(f, ", drawn at: {width}x{height}")?;
+        }
+        write!(f, ", hash: {:08x}", self.hash)
+    }
+}
+
+/// Named lists of outputs, that can be used instead of listing the outputs one by one
+pub type Groups = Vec<(String, Vec<String>)>;
+
+/// Replaces every group name in `outputs` with the outputs in that group
+pub fn expand_groups(outputs: &mut Vec<String>, groups: &Groups) {
+    let mut expanded: Vec<String> = Vec::with_capacity(outputs.len());
+    for output in outputs.drain(..) {
+        let members = match groups.iter().find(|(name, _)| *name == output) {
+            Some((_, members)) => members.clone(),
+            None => vec![output],
+        };
+        for member in members {
+            if !expanded.contains(&member) {
+                expanded.push(member);
+            }
+        }
+    }
+    *outputs = expanded;
+}
+
+/// Adds to `outputs` every output that follows (directly or not) one of them
+pub fn add_followers<'a, I>(outputs: &mut Vec<String>, infos: I)
+where
+    I: Iterator<Item = &'a BgInfo> + Clone,
+{
+    let mut i = 0;
+    while i < outputs.len() {
+        for info in infos.clone() {
+            if info.following.as_ref() == Some(&outputs[i]) && !outputs.contains(&info.name) {
+                outputs.push(info.name.clone());
+            }
+        }
+        i += 1;
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct Follow {
+    pub output: String,
+    /// `None` stops following
+    pub source: Option<String>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct Pin {
+    pub output: String,
+    pub pinned: bool,
+}
+
+/// How many pixels of each side of an output are hidden, by a notch, a camera cutout or a panel
+/// that is always visible. Images are cropped so that their center is in the middle of the rest
+#[derive(Clone, Copy, Default, PartialEq, Eq, Debug, Serialize, Deserialize)]
+pub struct SafeArea {
+    pub top: u32,
+    pub bottom: u32,
+    pub left: u32,
+    pub right: u32,
+}
+
+impl SafeArea {
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        *self == Self::default()
+    }
+}
+
+impl fmt::Display for SafeArea {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let sides = [
+            ("top", self.top),
+            ("bottom", self.bottom),
+            ("left", self.left),
+            ("right", self.right),
+        ];
+        let sides: Vec<String> = sides
+            .iter()
+            .filter(|(_, pixels)| *pixels > 0)
+            .map(|(side, pixels)| format!("{side}:{pixels}"))
+            .collect();
+        if sides.is_empty() {
+            write!(f, "none")
+        } else {
+            write!(f, "{}", sides.join(","))
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct SetSafeArea {
+    pub output: String,
+    pub area: SafeArea,
+}
+
+/// Asks for the average colors of what outputs are displaying, for ambient lighting
+#[derive(Serialize, Deserialize)]
+pub struct Colors {
+    pub outputs: Vec<String>,
+    /// How many columns and rows to split every output into, each with its own average
+    pub grid: (u32, u32),
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct Record {
+    pub output: String,
+    pub duration: Duration,
+    pub fps: u8,
+    /// Where to write the video. Since the daemon writes it, this must be absolute
+    pub path: PathBuf,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct Clear {
+    pub color: [u8; 3],
+    pub outputs: Vec<String>,
+    pub transition: Transition,
+}
+
+/// Adjusts the colors of what the outputs display. Still images transition into their adjusted
+/// selves, while animations are adjusted as they play. Adjustments are relative to what was set,
+/// so they don't add up
+#[derive(Serialize, Deserialize)]
+pub struct Adjust {
+    pub adjustment: Adjustment,
+    pub source: AdjustSource,
+    /// Whether to also even out the brightness of the still images the outputs display
+    pub match_brightness: bool,
+    /// The name of an effect plugin to apply to the still images, after the adjustment
+    pub effect: Option<String>,
+    pub outputs: Vec<String>,
+    pub transition: Transition,
+}
+
+/// What an `Adjust` applies to
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+pub enum AdjustSource {
+    /// What was set with `Img` or `Clear`, animated or not
+    Base,
+    /// The animations playing in regions of the outputs
+    Regions,
+}
+
+/// Things the daemon can draw by itself, without needing an image
+#[derive(Serialize, Deserialize, Clone)]
+pub enum Generator {
+    Gradient {
+        colors: Vec<[u8; 3]>,
+        angle: f64,
+        /// How long it takes to cycle through all the colors. `None` means a static gradient
+        period: Option<Duration>,
+        fps: u8,
+    },
+    Plasma {
+        colors: Vec<[u8; 3]>,
+        scale: f32,
+        speed: f32,
+        fps: u8,
+    },
+    Clock {
+        format: String,
+        /// Contents of the font file
+        font: Vec<u8>,
+        size: f32,
+        color: [u8; 3],
+        pos: Position,
+    },
+    Life {
+        /// Size of each cell, in pixels
+        cell_size: u32,
+        alive: [u8; 3],
+        dead: [u8; 3],
+        fps: u8,
+    },
+    /// Frames are produced by an external program. See `swww-generate(1)` for the protocol
+    External {
+        command: Vec<String>,
+        fps: u8,
+    },
+    TestPattern {
+        pattern: TestPattern,
+    },
+}
+
+/// Patterns for calibrating monitors, drawn at the output's exact resolution
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TestPattern {
+    /// SMPTE color bars
+    Bars,
+    /// Smooth and stepped ramps of gray, red, green and blue
+    Ramps,
+    /// A grid with a circle and a crosshair, for checking alignment and scaling
+    Grid,
+}
+
+impl Generator {
+    #[must_use]
+    pub fn name(&self) -> &'static str {
+        match self {
+            Generator::Gradient { .. } => "gradient",
+            Generator::Plasma { .. } => "plasma",
+            Generator::Clock { .. } => "clock",
+            Generator::Life { .. } => "life",
+            Generator::External { .. } => "external",
+            Generator::TestPattern { .. } => "testpattern",
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct Generate {
+    pub generator: Generator,
+    pub outputs: Vec<String>,
+}
+
+/// The images of a directory, displayed one after the other by the daemon
+#[derive(Serialize, Deserialize)]
+pub struct Slideshow {
+    /// Since the daemon reads it, this must be absolute
+    pub dir: PathBuf,
+    pub outputs: Vec<String>,
+    /// How long each image is displayed
+    pub interval: Duration,
+    /// How long before each image is due to start preparing it
+    pub lead: Duration,
+    pub sort: SlideshowSort,
+    /// Only the images modified in this long are displayed
+    pub newer_than: Option<Duration>,
+    /// Only the images at least this large are displayed
+    pub min_resolution: Option<(u32, u32)>,
+    /// Seeds the order of `SlideshowSort::Random`, which is different every time without one
+    pub seed: Option<u64>,
+    pub transition: Transition,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SlideshowSort {
+    /// By file name
+    Name,
+    /// From the oldest image to the newest one
+    Mtime,
+    /// A different order every round, which never starts with the image the last one ended with
+    Random,
+}
+
+/// How far along the client is in processing an animation for `outputs`, from 0.0 to 1.0. `None`
+/// removes the busy indicator
+#[derive(Serialize, Deserialize)]
+pub struct Busy {
+    pub outputs: Vec<String>,
+    pub progress: Option<f32>,
+}
+
+/// Makes the daemon forget what it cached for `outputs`, so that their images can be read and
+/// resized again. Answered with the outputs' `BgInfo`, to know what to send them
+#[derive(Serialize, Deserialize)]
+pub struct Refresh {
+    pub outputs: Vec<String>,
+}
+
+/// A small image to draw over what is currently being displayed
+#[derive(Serialize, Deserialize)]
+pub struct Patch {
+    /// In bgra, so that we can blend it with what's beneath
+    pub img: Vec<u8>,
+    pub dim: (u32, u32),
+    /// Where the center of the patch goes
+    pub pos: Position,
+    pub outputs: Vec<String>,
+}
+
+/// An animation played in a rectangle of the outputs, over what they are displaying, with its own
+/// timeline
+#[derive(Serialize, Deserialize)]
+pub struct Region {
+    /// Packed at `dim`. Its last frame must have a keyframe, which is drawn first
+    pub animation: Animation,
+    pub dim: (u32, u32),
+    /// Where the center of the region goes
+    pub pos: Position,
+    pub outputs: Vec<String>,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Img {
+    pub path: PathBuf,
+    pub img: Vec<u8>,
+}
+
+/// A frame of animation, as its difference from the previous one, compressed with lz4. See
+/// `swww_render::comp_decomp` for how frames are packed and unpacked
+#[derive(Clone, PartialEq, Serialize, Deserialize)]
+pub struct BitPack {
+    inner: Box<[u8]>,
+}
+
+impl BitPack {
+    /// Wraps an already packed and compressed frame
+    #[must_use]
+    pub fn from_compressed(inner: Box<[u8]>) -> Self {
+        Self { inner }
+    }
+
+    #[must_use]
+    pub fn compressed(&self) -> &[u8] {
+        &self.inner
+    }
+}
+
+#[derive(Clone, PartialEq, Serialize, Deserialize)]
+pub struct Animation {
+    pub animation: Box<[(BitPack, Duration)]>,
+    /// Full packs of some of the frames, by their index in `animation`, sorted. They can be drawn
+    /// instead of the frame's diff to resynchronize, without replaying the frames before them
+    pub keyframes: Box<[(usize, BitPack)]>,
+    pub sync: bool,
+}
+
+impl Animation {
+    pub fn keyframe(&self, frame: usize) -> Option<&BitPack> {
+        match self.keyframes.binary_search_by_key(&frame, |(i, _)| *i) {
+            Ok(i) => Some(&self.keyframes[i].1),
+            Err(_) => None,
+        }
+    }
+}
+
+pub type AnimationRequest = Vec<(Animation, Vec<String>)>;
+pub type ImageRequest = (Transition, Vec<(Img, Vec<String>)>);
+
+#[derive(Serialize, Deserialize)]
+pub enum Request {
+    Animation(AnimationRequest),
+    Busy(Busy),
+    Clear(Clear),
+    Disable(String),
+    Enable(String),
+    Follow(Follow),
+    Group(GroupCommand),
+    Init,
+    Kill,
+    Patch(Patch),
+    Pin(Pin),
+    Query,
+    Queue(QueueCommand),
+    Record(Record),
+    Img(ImageRequest),
+    Transaction(TransactionCommand),
+    Adjust(Adjust),
+    SafeArea(SetSafeArea),
+    Region(Region),
+    Refresh(Refresh),
+    /// The trace of a request, by its id, or a summary of the last requests
+    Trace(Option<u64>),
+    /// Where the animations playing on every output are
+    Playback,
+    Manual(ManualCommand),
+    /// The daemon's version, and the optional features it was built with
+    Version,
+    Colors(Colors),
+    Generate(Generate),
+    Slideshow(SlideshowCommand),
+}
+
+/// Transactions stage the requests changing what is displayed until they are committed, and then
+/// apply them all at once
+#[derive(Serialize, Deserialize)]
+pub enum TransactionCommand {
+    Begin,
+    Commit,
+    Rollback,
+}
+
+/// Transitions whose progress is set by the client, like a slider, instead of by time. Only one
+/// can be in progress at a time
+#[derive(Serialize, Deserialize)]
+pub enum ManualCommand {
+    /// Starts a transition into the images, which stays at the old ones until it is moved
+    Start(ImageRequest),
+    /// Moves the transition to this point, from 0 (the old images) to 1 (the new ones)
+    Set(f32),
+    /// Ends the transition at whichever images it is closest to
+    End,
+}
+
+#[derive(Serialize, Deserialize)]
+pub enum SlideshowCommand {
+    /// Starts the slideshow on its outputs, replacing the slideshows they were part of
+    Start(Slideshow),
+    /// Stops the slideshows of these outputs, which keep displaying their current image
+    Stop(Vec<String>),
+}
+
+#[derive(Serialize, Deserialize)]
+pub enum GroupCommand {
+    Create { name: String, outputs: Vec<String> },
+    Delete(String),
+    List,
+}
+
+#[derive(Serialize, Deserialize)]
+pub enum QueueCommand {
+    List,
+    Clear,
+}
+
+/// How urgently the daemon should handle a request. When many requests arrive at once, the more
+/// urgent ones are handled first
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Clone, Copy)]
+pub enum Priority {
+    /// Long running work, that can wait a little
+    Background,
+    /// Things the user is probably waiting to see
+    Interactive,
+}
+
+impl Request {
+    pub fn priority(&self) -> Priority {
+        match self {
+            Request::Animation(_) | Request::Generate(_) => Priority::Background,
+            _ => Priority::Interactive,
+        }
+    }
+
+    pub fn send(&self, stream: impl io::Write) -> Result<(), Error> {
+        let mut writer = BufWriter::new(stream);
+        let sent = write_header(&mut writer)
+            .map_err(bincode::Error::from)
+            .and_then(|()| bincode::serialize_into(&mut writer, self))
+            .and_then(|()| writer.flush().map_err(bincode::Error::from));
+        match sent {
+            Ok(()) => Ok(()),
+            Err(e) => Err(Error::Ipc(format!("Failed to serialize request: {e}"))),
+        }
+    }
+
+    /// Fails with `Error::Unsupported` for requests sent with another version of the protocol,
+    /// without reading the rest of them
+    pub fn receive(stream: impl io::Read) -> Result<Self, Error> {
+        let mut reader = BufReader::new(stream);
+        read_header(
+            &mut reader,
+            ("the client", "swww-daemon"),
+            "Failed to deserialize request",
+        )?;
+        match bincode::deserialize_from(reader) {
+            Ok(i) => Ok(i),
+            Err(e) => Err(Error::Ipc(format!("Failed to deserialize request: {e}"))),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+pub enum Answer {
+    Ok,
+    Err(Error),
+    Info(Vec<BgInfo>),
+    Groups(Groups),
+    /// Descriptions of the requests waiting to be handled, in the order they will be handled
+    Queue(Vec<String>),
+    /// The request started a transition, which is expected to send this many frames, taking about
+    /// this long. When there are many transitions, this is the longest one
+    Transition {
+        frames: u32,
+        duration: Duration,
+    },
+    /// The request was dropped, because a newer one replaced everything it would have changed
+    /// before it was applied. See `swww-daemon --min-interval`
+    Coalesced,
+    /// Answers `Trace`, one line per request or event
+    Trace(Vec<String>),
+    /// Answers `Playback`, for the outputs playing an animation
+    Playback(Vec<Playback>),
+    /// Answers `Version`
+    Version(DaemonVersion),
+    /// Answers `Colors`, for every requested output
+    Colors(Vec<OutputColors>),
+}
+
+/// The average colors of what an output is displaying
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct OutputColors {
+    pub output: String,
+    /// In rgb, for every cell of the grid, row by row
+    pub colors: Vec<[u8; 3]>,
+}
+
+impl fmt::Display for OutputColors {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:", self.output)?;
+        for [r, g, b] in &self.colors {
+            write!(f, " {r:02x}{g:02x}{b:02x}")?;
+        }
+        Ok(())
+    }
+}
+
+/// The version of a running daemon, which may not be the one installed anymore
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct DaemonVersion {
+    pub version: String,
+    /// The optional features it was built with, like `ffmpeg`
+    pub features: Vec<String>,
+}
+
+impl fmt::Display for DaemonVersion {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "swww-daemon {}", self.version)?;
+        if !self.features.is_empty() {
+            write!(f, " (features: {})", self.features.join(", "))?;
+        }
+        Ok(())
+    }
+}
+
+/// Where the animation playing on an output is
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Playback {
+    pub output: String,
+    /// The index of the frame being displayed
+    pub frame: usize,
+    pub frames: usize,
+    /// How many times the animation went back to its first frame
+    pub loops: u64,
+    /// How long the animation has been playing on the output
+    pub elapsed: Duration,
+}
+
+impl fmt::Display for Playback {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}: frame {}/{}, loop {}, {:.3}s elapsed",
+            self.output,
+            self.frame,
+            self.frames,
+            self.loops,
+            self.elapsed.as_secs_f32()
+        )
+    }
+}
+
+impl Answer {
+    pub fn send(&self, stream: impl io::Write) -> Result<(), Error> {
+        let mut writer = BufWriter::new(stream);
+        let sent = write_header(&mut writer)
+            .map_err(bincode::Error::from)
+            .and_then(|()| bincode::serialize_into(&mut writer, self))
+            .and_then(|()| writer.flush().map_err(bincode::Error::from));
+        match sent {
+            Ok(()) => Ok(()),
+            Err(e) => Err(Error::Ipc(format!("Failed to send answer: {e}"))),
+        }
+    }
+
+    pub fn receive(stream: UnixStream) -> Result<Self, Error> {
+        #[cfg(debug_assertions)]
+        let timeout = Duration::from_secs(30); //Some operations take a while to respond in debug mode
+        #[cfg(not(debug_assertions))]
+        let timeout = Duration::from_secs(5);
+        Self::receive_within(stream, timeout)
+    }
+
+    /// Like `receive`, for answers we know may take longer than usual, like `Init`'s while the
+    /// daemon waits for the session. Answers from a daemon speaking another version of the
+    /// protocol fail with `Error::Unsupported`
+    pub fn receive_within(stream: UnixStream, timeout: Duration) -> Result<Self, Error> {
+        if let Err(e) = stream.set_read_timeout(Some(timeout)) {
+            return Err(Error::Ipc(format!("Failed to set read timeout: {e}")));
+        };
+
+        let mut reader = BufReader::new(stream);
+        read_header(
+            &mut reader,
+            ("swww-daemon", "this client"),
+            "Failed to receive answer",
+        )?;
+        match bincode::deserialize_from(reader) {
+            Ok(i) => Ok(i),
+            Err(e) => Err(Error::Ipc(format!("Failed to receive answer: {e}"))),
+        }
+    }
+}
+
+/// The version of the protocol. It changes whenever a message does, in a way the other side can't
+/// read anymore
+pub const PROTOCOL_VERSION: u32 = 1;
+
+/// What every message starts with, followed by `PROTOCOL_VERSION`. Messages from before the
+/// protocol had a version start with their tag instead, which we call version 0
+const MAGIC: [u8; 4] = *b"swww";
+
+fn write_header(mut writer: impl io::Write) -> io::Result<()> {
+    writer.write_all(&MAGIC)?;
+    writer.write_all(&PROTOCOL_VERSION.to_le_bytes())
+}
+
+/// Checks that the message starts with our header. `sender` and `receiver` say who is on each side
+/// of the socket, and `failed` what we were doing, for when reading fails
+fn read_header(
+    mut reader: impl io::Read,
+    (sender, receiver): (&str, &str),
+    failed: &str,
+) -> Result<(), Error> {
+    let mut read = |buf: &mut [u8; 4]| match reader.read_exact(buf) {
+        Ok(()) => Ok(*buf),
+        Err(e) => Err(Error::Ipc(format!("{failed}: {e}"))),
+    };
+    // unversioned messages may be just their tag, so we can't wait for more before telling them
+    // apart: the other side would be waiting for us to answer
+    let version = if read(&mut [0; 4])? == MAGIC {
+        u32::from_le_bytes(read(&mut [0; 4])?)
+    } else {
+        0
+    };
+    if version != PROTOCOL_VERSION {
+        let version = match version {
+            0 => "0 (from before it had versions)".to_string(),
+            version => version.to_string(),
+        };
+        return Err(Error::Unsupported(format!(
+            "{sender} speaks version {version} of swww's protocol, but {receiver} speaks version \
+            {PROTOCOL_VERSION}. Make sure swww and swww-daemon are the same version, and restart \
+            swww-daemon after upgrading"
+        )));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn info(name: &str, following: Option<&str>) -> BgInfo {
+        BgInfo {
+            name: name.to_string(),
+            dim: (1920, 1080),
+            scale_factor: 1,
+            img: BgImg::Color([0, 0, 0]),
+            following: following.map(str::to_string),
+            pinned: false,
+            safe_area: SafeArea::default(),
+            buffer_dim: None,
+            hash: 0,
+        }
+    }
+
+    #[test]
+    fn broadcasts_should_skip_pinned_outputs() {
+        let mut pinned = info("DP-1", None);
+        pinned.pinned = true;
+        assert!(!pinned.is_requested(&[]));
+        assert!(pinned.is_requested(&["DP-1".to_string()]));
+        assert!(info("DP-2", None).is_requested(&[]));
+        assert!(!info("DP-2", None).is_requested(&["DP-1".to_string()]));
+    }
+
+    /// Third-party clients rely on the tags documented in `doc/protocol.md`, so reordering the
+    /// variants breaks them
+    #[test]
+    fn tags_should_match_the_protocol_docs() {
+        let tag = |bytes: Vec<u8>| u32::from_le_bytes(bytes[0..4].try_into().unwrap());
+        let requests = [
+            (Request::Disable(String::new()), 3),
+            (Request::Init, 7),
+            (Request::Kill, 8),
+            (Request::Query, 11),
+            (Request::Queue(QueueCommand::List), 12),
+            (Request::Img((transition(), Vec::new())), 14),
+            (Request::Transaction(TransactionCommand::Commit), 15),
+            (
+                Request::Adjust(Adjust {
+                    adjustment: Adjustment::default(),
+                    source: AdjustSource::Base,
+                    match_brightness: false,
+                    effect: None,
+                    outputs: Vec::new(),
+                    transition: transition(),
+                }),
+                16,
+            ),
+            (
+                Request::SafeArea(SetSafeArea {
+                    output: String::new(),
+                    area: SafeArea::default(),
+                }),
+                17,
+            ),
+            (Request::Trace(None), 20),
+            (Request::Playback, 21),
+            (Request::Manual(ManualCommand::End), 22),
+            (Request::Version, 23),
+            (
+                Request::Colors(Colors {
+                    outputs: Vec::new(),
+                    grid: (1, 1),
+                }),
+                24,
+            ),
+            (
+                Request::Generate(Generate {
+                    generator: Generator::TestPattern {
+                        pattern: TestPattern::Grid,
+                    },
+                    outputs: Vec::new(),
+                }),
+                25,
+            ),
+            (Request::Slideshow(SlideshowCommand::Stop(Vec::new())), 26),
+        ];
+        for (request, expected) in requests {
+            assert_eq!(tag(bincode::serialize(&request).unwrap()), expected);
+        }
+        assert_eq!(tag(bincode::serialize(&Answer::Ok).unwrap()), 0);
+        let answer = Answer::Transition {
+            frames: 0,
+            duration: Duration::ZERO,
+        };
+        assert_eq!(tag(bincode::serialize(&answer).unwrap()), 5);
+        assert_eq!(
+            tag(bincode::serialize(&Answer::Trace(Vec::new())).unwrap()),
+            7
+        );
+        assert_eq!(
+            tag(bincode::serialize(&Answer::Playback(Vec::new())).unwrap()),
+            8
+        );
+        let version = DaemonVersion {
+            version: String::new(),
+            features: Vec::new(),
+        };
+        assert_eq!(
+            tag(bincode::serialize(&Answer::Version(version)).unwrap()),
+            9
+        );
+        assert_eq!(
+            tag(bincode::serialize(&Answer::Colors(Vec::new())).unwrap()),
+            10
+        );
+    }
+
+    #[test]
+    fn paths_should_survive_the_socket_whatever_they_contain() {
+        // strings are sent with their length, so nothing in them can end a field early
+        let path = PathBuf::from("/walls/two\nlines, and \0 spaces.png");
+        let img = Img {
+            path: path.clone(),
+            img: vec![0; 4],
+        };
+        let outputs = vec!["DP-1".to_string()];
+        let mut bytes = Vec::new();
+        Request::Img((transition(), vec![(img, outputs)]))
+            .send(&mut bytes)
+            .unwrap();
+        match Request::receive(&bytes[..]).unwrap() {
+            Request::Img((_, imgs)) => {
+                assert_eq!(imgs[0].0.path, path);
+                assert_eq!(imgs[0].1, ["DP-1"]);
+            }
+            _ => panic!("expected an Img request"),
+        }
+    }
+
+    #[test]
+    fn should_refuse_messages_of_other_protocol_versions() {
+        let mut bytes = Vec::new();
+        Request::Query.send(&mut bytes).unwrap();
+        assert_eq!(&bytes[..4], b"swww");
+        assert!(matches!(Request::receive(&bytes[..]), Ok(Request::Query)));
+
+        bytes[4..8].copy_from_slice(&(PROTOCOL_VERSION + 1).to_le_bytes());
+        match Request::receive(&bytes[..]) {
+            Err(Error::Unsupported(e)) => assert!(e.contains(&format!(
+                "the client speaks version {} of swww's protocol, but swww-daemon speaks version {}",
+                PROTOCOL_VERSION + 1,
+                PROTOCOL_VERSION
+            ))),
+            _ => panic!("expected the request to be refused"),
+        }
+
+        // what clients sent before the protocol had versions
+        let unversioned = bincode::serialize(&Request::Query).unwrap();
+        assert!(matches!(
+            Request::receive(&unversioned[..]),
+            Err(Error::Unsupported(_))
+        ));
+
+        let (mut daemon, client) = UnixStream::pair().unwrap();
+        let mut answer = Vec::new();
+        Answer::Ok.send(&mut answer).unwrap();
+        answer[4..8].copy_from_slice(&(PROTOCOL_VERSION + 1).to_le_bytes());
+        io::Write::write_all(&mut daemon, &answer).unwrap();
+        match Answer::receive(client) {
+            Err(Error::Unsupported(e)) => assert!(e.contains("swww-daemon speaks version")),
+            _ => panic!("expected the answer to be refused"),
+        }
+    }
+
+    fn transition() -> Transition {
+        Transition {
+            transition_type: TransitionType::Simple,
+            duration: 0.0,
+            step: 255,
+            fps: 30,
+            angle: 0.0,
+            pos: Position::new(Coord::Percent(0.5), Coord::Percent(0.5)),
+            bezier: (0.0, 0.0, 1.0, 1.0),
+            wave: (20.0, 20.0),
+            sync: TransitionSync::None,
+            skip_threshold: 0.0,
+        }
+    }
+
+    #[test]
+    fn should_expand_groups_without_repeating_outputs() {
+        let groups = vec![
+            (
+                "sides".to_string(),
+                vec!["DP-1".to_string(), "DP-3".to_string()],
+            ),
+            ("left".to_string(), vec!["DP-1".to_string()]),
+        ];
+        let mut outputs = vec!["sides".to_string(), "DP-2".to_string(), "left".to_string()];
+        expand_groups(&mut outputs, &groups);
+        assert_eq!(outputs, ["DP-1", "DP-3", "DP-2"]);
+    }
+
+    #[test]
+    fn should_add_followers_of_followers() {
+        let infos = [
+            info("DP-1", None),
+            info("DP-2", Some("HDMI-A-1")),
+            info("HDMI-A-1", Some("DP-1")),
+            info("eDP-1", None),
+        ];
+        let mut outputs = vec!["DP-1".to_string()];
+        add_followers(&mut outputs, infos.iter());
+        assert_eq!(outputs, ["DP-1", "HDMI-A-1", "DP-2"]);
+    }
+
+    #[test]
+    fn interactive_requests_come_first() {
+        let generate = Request::Generate(Generate {
+            generator: Generator::Plasma {
+                colors: vec![[0, 0, 0]],
+                scale: 1.0,
+                speed: 1.0,
+                fps: 30,
+            },
+            outputs: Vec::new(),
+        });
+        let mut requests = [Request::Query, generate, Request::Init];
+        // the daemon relies on this being a stable sort
+        requests.sort_by_key(|r| std::cmp::Reverse(r.priority()));
+        assert!(matches!(requests[0], Request::Query));
+        assert!(matches!(requests[1], Request::Init));
+        assert!(matches!(requests[2], Request::Generate(_)));
+    }
+}
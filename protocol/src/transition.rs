@@ -0,0 +1,86 @@
+//! The transitions users choose, which `swww` sends through the socket. `swww_render::transition`
+//! plays them
+use serde::{Deserialize, Serialize};
+
+#[derive(PartialEq, Clone, Serialize, Deserialize, Debug)]
+pub enum Coord {
+    Pixel(f32),
+    Percent(f32),
+}
+
+#[derive(PartialEq, Clone, Serialize, Deserialize, Debug)]
+pub struct Position {
+    pub x: Coord,
+    pub y: Coord,
+}
+
+impl Position {
+    pub fn new(x: Coord, y: Coord) -> Self {
+        Self { x, y }
+    }
+
+    pub fn to_pixel(&self, dim: (u32, u32)) -> (f32, f32) {
+        let x = match self.x {
+            Coord::Pixel(x) => x,
+            Coord::Percent(x) => x * dim.0 as f32,
+        };
+
+        let y = match self.y {
+            Coord::Pixel(y) => y,
+            Coord::Percent(y) => y * dim.1 as f32,
+        };
+
+        (x, y)
+    }
+
+    pub fn to_percent(&self, dim: (u32, u32)) -> (f32, f32) {
+        let x = match self.x {
+            Coord::Pixel(x) => x / dim.0 as f32,
+            Coord::Percent(x) => x,
+        };
+
+        let y = match self.y {
+            Coord::Pixel(y) => y / dim.1 as f32,
+            Coord::Percent(y) => y,
+        };
+
+        (x, y)
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub enum TransitionType {
+    Simple,
+    Outer,
+    Wipe,
+    Grow,
+    Wave,
+    /// Fades into the color, in rgb, and then into the new image
+    Flash([u8; 3]),
+    /// Moves the new image in over the old one, in the direction of the angle
+    Slide,
+    /// Drawn by a plugin, by its name. See `swww-daemon(1)`
+    Plugin(String),
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub enum TransitionSync {
+    None,
+    Layout,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct Transition {
+    pub transition_type: TransitionType,
+    pub duration: f32,
+    pub step: u8,
+    pub fps: u8,
+    pub angle: f64,
+    pub pos: Position,
+    pub bezier: (f32, f32, f32, f32),
+    pub wave: (f32, f32),
+    pub sync: TransitionSync,
+    /// Images differing by less than this, in percent, are swapped right away instead. 0 always
+    /// transitions
+    pub skip_threshold: f32,
+}
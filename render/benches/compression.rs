@@ -1,5 +1,5 @@
 use criterion::{black_box, criterion_group, criterion_main, BatchSize, Criterion};
-use utils::comp_decomp::{BitPack, ReadiedPack};
+use swww_render::comp_decomp::{BitPack, Pack, ReadiedPack};
 
 fn generate_data() -> (Box<[u8]>, Box<[u8]>) {
     let v1 = vec![120; 1920 * 1080 * 4];
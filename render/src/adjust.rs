@@ -0,0 +1,106 @@
+//! Color adjustments, which is what `swww adjust` does to images and animation frames. They work
+//! on every color channel alike, so they are a lookup table of 256 bytes, cheap enough to apply to
+//! every frame of an animation while it plays
+pub use swww_protocol::adjust::Adjustment;
+
+/// The average luminance of `img`, in bgra, from 0 to 255, weighing the channels like Rec. 709
+#[must_use]
+pub fn luminance(img: &[u8]) -> f32 {
+    let pixels = img.len() / 4;
+    if pixels == 0 {
+        return 0.0;
+    }
+    let sum: f64 = img
+        .chunks_exact(4)
+        .map(|pix| {
+            0.0722 * f64::from(pix[0]) + 0.7152 * f64::from(pix[1]) + 0.2126 * f64::from(pix[2])
+        })
+        .sum();
+    (sum / pixels as f64) as f32
+}
+
+/// The brightness that takes images of each of `luminances` towards their average, which is what
+/// `swww adjust --match-brightness` does. Black images stay black, and brightening can't go past
+/// twice as bright
+#[must_use]
+pub fn match_brightness(luminances: &[f32]) -> Vec<Adjustment> {
+    let target = luminances.iter().sum::<f32>() / luminances.len().max(1) as f32;
+    luminances
+        .iter()
+        .map(|luminance| {
+            let brightness = if *luminance > 0.0 {
+                ((target / luminance - 1.0) * 100.0)
+                    .round()
+                    .clamp(-100.0, 100.0) as i8
+            } else {
+                0
+            };
+            Adjustment {
+                brightness,
+                ..Default::default()
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn brighten(img: &mut [u8], brightness: i8) {
+        Adjustment {
+            brightness,
+            ..Default::default()
+        }
+        .apply(img);
+    }
+
+    #[test]
+    fn brightness_should_scale_colors_but_not_alpha() {
+        let mut img = [100, 200, 0, 128];
+        brighten(&mut img, -20);
+        assert_eq!(img, [80, 160, 0, 128]);
+        brighten(&mut img, 100);
+        assert_eq!(img, [160, 255, 0, 128]);
+        brighten(&mut img, -100);
+        assert_eq!(img, [0, 0, 0, 128]);
+    }
+
+    #[test]
+    fn contrast_and_gamma_should_keep_the_extremes() {
+        let lut = Adjustment::default().lut();
+        assert!(lut.iter().enumerate().all(|(i, v)| i == *v as usize));
+
+        let flat = Adjustment {
+            contrast: -100,
+            ..Default::default()
+        };
+        assert!(flat.lut().iter().all(|v| *v == 128));
+
+        let gamma = Adjustment {
+            gamma: 2.2,
+            ..Default::default()
+        }
+        .lut();
+        assert_eq!((gamma[0], gamma[255]), (0, 255));
+        assert!(gamma[128] > 128);
+    }
+
+    #[test]
+    fn brightness_should_be_matched_to_the_average() {
+        assert_eq!(luminance(&[255, 255, 255, 0, 0, 0, 0, 255]), 127.5);
+        // green weighs the most
+        assert!(luminance(&[0, 200, 0, 255]) > luminance(&[0, 0, 200, 255]));
+
+        let brightness = |luminances: &[f32]| -> Vec<i8> {
+            match_brightness(luminances)
+                .iter()
+                .map(|adjustment| adjustment.brightness)
+                .collect()
+        };
+        assert_eq!(brightness(&[50.0, 150.0]), [100, -33]);
+        assert_eq!(brightness(&[20.0, 200.0]), [100, -45]);
+        assert_eq!(brightness(&[0.0, 100.0]), [0, -50]);
+        assert_eq!(brightness(&[80.0]), [0]);
+    }
+}
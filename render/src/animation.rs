@@ -0,0 +1,309 @@
+//! Compressing animations, so that playing them only costs applying the difference between frames.
+//! Animated gifs, pngs (apng) and webps are supported
+use std::{
+    io::{Cursor, Read},
+    time::Duration,
+};
+
+use image::{
+    codecs::{
+        gif::GifDecoder,
+        png::{ApngDecoder, PngDecoder},
+        webp::WebPDecoder,
+    },
+    AnimationDecoder, Frames, ImageDecoder, ImageFormat, RgbaImage,
+};
+
+use crate::comp_decomp::{BitPack, Pack};
+
+/// Whether `bytes`, an encoded image in `format`, is an animation. Gifs always are, even with a
+/// single frame, while pngs and webps have to say so in their headers
+pub fn is_animated(bytes: &[u8], format: ImageFormat) -> bool {
+    match format {
+        ImageFormat::Gif => true,
+        ImageFormat::Png => PngDecoder::new(Cursor::new(bytes)).is_ok_and(|png| png.is_apng()),
+        // the animation flag of the extended header, which WebPDecoder would decode the whole
+        // image to tell us about
+        ImageFormat::WebP => {
+            bytes.get(12..16) == Some(b"VP8X")
+                && bytes.get(20).is_some_and(|flags| flags & 0x02 != 0)
+        }
+        _ => false,
+    }
+}
+
+/// Counts the frames of `bytes`, an animated png or webp, from their headers, without decoding
+/// them. Returns `None` for other formats, or if it isn't animated after all
+pub fn count_frames(bytes: &[u8], format: ImageFormat) -> Option<usize> {
+    let frames = match format {
+        // the acTL chunk, which comes before the image data
+        ImageFormat::Png => {
+            let (_, actl) = chunks(bytes.get(8..)?, false).find(|(name, _)| name == b"acTL")?;
+            u32::from_be_bytes(actl.get(0..4)?.try_into().ok()?) as usize
+        }
+        // one ANMF chunk per frame
+        ImageFormat::WebP => chunks(bytes.get(12..)?, true)
+            .filter(|(name, _)| name == b"ANMF")
+            .count(),
+        _ => return None,
+    };
+    (frames > 0).then_some(frames)
+}
+
+/// The chunks of a png (big endian, with a crc after the data) or of a webp (little endian,
+/// padded to an even length), as their names and their data
+fn chunks(mut bytes: &[u8], webp: bool) -> impl Iterator<Item = ([u8; 4], &[u8])> {
+    std::iter::from_fn(move || {
+        let (len, name) = if webp {
+            let len = u32::from_le_bytes(bytes.get(4..8)?.try_into().ok()?);
+            (len, bytes.get(0..4)?)
+        } else {
+            let len = u32::from_be_bytes(bytes.get(0..4)?.try_into().ok()?);
+            (len, bytes.get(4..8)?)
+        };
+        let len = len as usize;
+        let data = bytes.get(8..8 + len)?;
+        let trailer = if webp { len % 2 } else { 4 };
+        let name = name.try_into().ok()?;
+        bytes = bytes.get(8 + len + trailer..).unwrap_or_default();
+        Some((name, data))
+    })
+}
+
+/// Decodes the frames of an animated gif, png or webp
+pub struct AnimatedDecoder<'a> {
+    dimensions: (u32, u32),
+    frames: Frames<'a>,
+}
+
+impl<'a> AnimatedDecoder<'a> {
+    pub fn new(reader: impl Read + 'a, format: ImageFormat) -> Result<Self, String> {
+        let decoding = |e: image::ImageError| format!("failed to decode animation: {e}");
+        let (dimensions, frames) = match format {
+            ImageFormat::Gif => {
+                let gif = GifDecoder::new(reader).map_err(decoding)?;
+                (gif.dimensions(), gif.into_frames())
+            }
+            ImageFormat::Png => {
+                let png = PngDecoder::new(reader).map_err(decoding)?;
+                (png.dimensions(), ApngDecoder::into_frames(png.apng()))
+            }
+            ImageFormat::WebP => {
+                let webp = WebPDecoder::new(reader).map_err(decoding)?;
+                (webp.dimensions(), webp.into_frames())
+            }
+            format => return Err(format!("{format:?} images can't be animated")),
+        };
+        Ok(Self { dimensions, frames })
+    }
+
+    /// The dimensions of the frames
+    pub fn dimensions(&self) -> (u32, u32) {
+        self.dimensions
+    }
+}
+
+impl<'a> AnimationDecoder<'a> for AnimatedDecoder<'a> {
+    fn into_frames(self) -> Frames<'a> {
+        self.frames
+    }
+}
+
+pub struct CompressedFrames {
+    /// Every frame, as the difference from the one before it, with how long it is displayed for.
+    /// The last one goes back to the first frame, so that the animation can loop
+    pub frames: Box<[(BitPack, Duration)]>,
+    /// Whole frames, at every `keyframe_interval` frames, so that players can skip ahead
+    pub keyframes: Box<[(usize, BitPack)]>,
+}
+
+/// Crossfading between frames that stay on screen for long, instead of swapping them at once
+#[derive(Clone, Copy, Debug)]
+pub struct Blend {
+    /// How long the crossfade takes. It is capped at half the time the frame is displayed for
+    pub duration: Duration,
+    /// Frames displayed for less than this are swapped at once, like without blending
+    pub min_delay: Duration,
+}
+
+impl Blend {
+    /// How many frames the crossfade takes per second
+    const FPS: f32 = 30.0;
+
+    /// The frames crossfading from `from` to `to`, which is displayed after `delay`, each with
+    /// how long to wait before displaying it. The last one is `to` itself, and the waits add up
+    /// to `delay`, so the animation takes as long as it would without blending
+    fn frames(&self, from: &[u8], to: &[u8], delay: Duration) -> Vec<(Vec<u8>, Duration)> {
+        if delay <= self.min_delay {
+            return vec![(to.to_vec(), delay)];
+        }
+        let duration = self.duration.min(delay / 2);
+        let steps = ((duration.as_secs_f32() * Self::FPS).round() as u32).max(2);
+        let step = duration / steps;
+        (1..=steps)
+            .map(|k| {
+                let frame = from
+                    .iter()
+                    .zip(to)
+                    .map(|(&a, &b)| {
+                        let (a, b) = (a as u32, b as u32);
+                        ((a * (steps - k) + b * k + steps / 2) / steps) as u8
+                    })
+                    .collect();
+                let wait = if k == 1 {
+                    delay - duration + step
+                } else {
+                    step
+                };
+                (frame, wait)
+            })
+            .collect()
+    }
+}
+
+/// Loops whose last and first frames differ by less than this, on average per byte, already look
+/// seamless, so `smooth_loop` leaves them alone
+const SEAMLESS_LOOP_DIFF: u64 = 2;
+
+/// Whether going from `last` back to `first` can't be told apart from any other frame change
+fn is_seamless(last: &[u8], first: &[u8]) -> bool {
+    let diff: u64 = last
+        .iter()
+        .zip(first)
+        .map(|(&a, &b)| a.abs_diff(b) as u64)
+        .sum();
+    diff <= SEAMLESS_LOOP_DIFF * first.len() as u64
+}
+
+/// Compresses all frames of `decoder`, after turning each of them into what will be displayed
+/// (in bgra) with `prepare`. Changes smaller than `diff_threshold` are ignored, and a
+/// `keyframe_interval` of 0 means no keyframes. With `blend`, the frames that stay on screen for
+/// long crossfade into the next one. With `smooth_loop`, the last frame crossfades back into the
+/// first one, taking that long, unless the animation already loops seamlessly. `report` is called
+/// after each frame is done
+pub fn compress_frames<'a>(
+    decoder: impl AnimationDecoder<'a>,
+    mut prepare: impl FnMut(RgbaImage) -> Result<Vec<u8>, String>,
+    diff_threshold: u8,
+    keyframe_interval: u32,
+    blend: Option<Blend>,
+    smooth_loop: Option<Duration>,
+    report: &mut dyn FnMut(),
+) -> Result<CompressedFrames, String> {
+    let mut compressed_frames = Vec::new();
+    let mut keyframes = Vec::new();
+    let blended = |from: &[u8], to: Vec<u8>, delay: Duration| match &blend {
+        Some(blend) => blend.frames(from, &to, delay),
+        None => vec![(to, delay)],
+    };
+    let mut frames = decoder.into_frames();
+
+    let first = match frames.next() {
+        Some(Ok(first)) => first,
+        Some(Err(e)) => return Err(format!("failed to decode first frame: {e}")),
+        None => return Err("animation has no frames".to_string()),
+    };
+    let first_duration = first.delay().numer_denom_ms();
+    let first_duration = Duration::from_millis((first_duration.0 / first_duration.1).into());
+    let first_img = prepare(first.into_buffer())?;
+
+    let mut canvas = first_img.clone();
+    while let Some(Ok(frame)) = frames.next() {
+        let (dur_num, dur_div) = frame.delay().numer_denom_ms();
+        let duration = Duration::from_millis((dur_num / dur_div).into());
+
+        let img = prepare(frame.into_buffer())?;
+        report();
+        for (img, duration) in blended(&canvas, img, duration) {
+            compressed_frames.push((
+                BitPack::pack_with_threshold(&mut canvas, &img, diff_threshold)?,
+                duration,
+            ));
+            // the keyframe must match the canvas, rather than the image, since the following diffs
+            // were made against it
+            let i = compressed_frames.len() - 1;
+            if keyframe_interval > 0 && i > 0 && i % keyframe_interval as usize == 0 {
+                keyframes.push((i, BitPack::keyframe(&canvas)?));
+            }
+        }
+    }
+    //Add the first frame we got earlier. We pack it without the threshold, so that whatever we
+    //ignored doesn't carry over to the next loop:
+    let mut back = match smooth_loop {
+        Some(duration) if !is_seamless(&canvas, &first_img) => Blend {
+            duration,
+            min_delay: Duration::ZERO,
+        }
+        .frames(&canvas, &first_img, first_duration),
+        _ => blended(&canvas, first_img, first_duration),
+    };
+    let (first_img, first_duration) = back.pop().expect("blending keeps the last frame");
+    for (img, duration) in back {
+        compressed_frames.push((
+            BitPack::pack_with_threshold(&mut canvas, &img, diff_threshold)?,
+            duration,
+        ));
+    }
+    compressed_frames.push((BitPack::pack(&mut canvas, &first_img)?, first_duration));
+
+    Ok(CompressedFrames {
+        frames: compressed_frames.into_boxed_slice(),
+        keyframes: keyframes.into_boxed_slice(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A png chunk, with a made up crc
+    fn png_chunk(name: &[u8; 4], data: &[u8]) -> Vec<u8> {
+        let mut chunk = (data.len() as u32).to_be_bytes().to_vec();
+        chunk.extend(name);
+        chunk.extend(data);
+        chunk.extend([0; 4]);
+        chunk
+    }
+
+    /// A webp chunk, padded to an even length
+    fn webp_chunk(name: &[u8; 4], data: &[u8]) -> Vec<u8> {
+        let mut chunk = name.to_vec();
+        chunk.extend((data.len() as u32).to_le_bytes());
+        chunk.extend(data);
+        if data.len() % 2 == 1 {
+            chunk.push(0);
+        }
+        chunk
+    }
+
+    #[test]
+    fn frames_should_be_counted_from_the_headers() {
+        let mut png = b"\x89PNG\r\n\x1a\n".to_vec();
+        png.extend(png_chunk(b"IHDR", &[0; 13]));
+        png.extend(png_chunk(b"acTL", &[0, 0, 0, 12, 0, 0, 0, 0]));
+        png.extend(png_chunk(b"IDAT", &[]));
+        assert_eq!(count_frames(&png, ImageFormat::Png), Some(12));
+
+        let mut webp = b"RIFF\0\0\0\0WEBP".to_vec();
+        webp.extend(webp_chunk(b"VP8X", &[0x02, 0, 0, 0, 0, 0, 0, 0, 0, 0]));
+        webp.extend(webp_chunk(b"ANIM", &[0; 6]));
+        for _ in 0..3 {
+            webp.extend(webp_chunk(b"ANMF", &[0; 17]));
+        }
+        assert!(is_animated(&webp, ImageFormat::WebP));
+        assert_eq!(count_frames(&webp, ImageFormat::WebP), Some(3));
+
+        // still images
+        let mut webp = b"RIFF\0\0\0\0WEBP".to_vec();
+        webp.extend(webp_chunk(b"VP8X", &[0; 10]));
+        assert!(!is_animated(&webp, ImageFormat::WebP));
+        assert_eq!(count_frames(&webp, ImageFormat::WebP), None);
+        let mut png = Vec::new();
+        RgbaImage::new(1, 1)
+            .write_to(&mut Cursor::new(&mut png), ImageFormat::Png)
+            .unwrap();
+        assert!(!is_animated(&png, ImageFormat::Png));
+        assert_eq!(count_frames(&png, ImageFormat::Png), None);
+        assert!(!is_animated(&png, ImageFormat::Jpeg));
+    }
+}
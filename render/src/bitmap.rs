@@ -0,0 +1,162 @@
+//! X11 bitmaps (xbm): monochrome images written as C source, which is what classic X patterns
+//! (like those `xsetroot -bitmap` tiles over the root window) come in. They have no colors of
+//! their own, so we draw them with whatever colors we are given
+use image::RgbaImage;
+
+/// The colors to draw a bitmap with
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Colors {
+    /// The color of set bits, in rgb
+    pub fg: [u8; 3],
+    /// The color of unset bits, in rgb
+    pub bg: [u8; 3],
+}
+
+impl Default for Colors {
+    /// Black on white, like X does
+    fn default() -> Self {
+        Self {
+            fg: [0, 0, 0],
+            bg: [255, 255, 255],
+        }
+    }
+}
+
+/// Whether `bytes` look like an xbm. They have no magic number, but always start with the
+/// `#define` of their width
+pub fn is_xbm(bytes: &[u8]) -> bool {
+    let start = bytes.iter().position(|b| !b.is_ascii_whitespace());
+    start.is_some_and(|start| bytes[start..].starts_with(b"#define"))
+}
+
+/// Decodes the xbm in `bytes`, drawing it with `colors`. Both the X11 format, with a byte per 8
+/// pixels, and the older X10 one, with a `short` per 16 pixels, are supported
+pub fn decode(bytes: &[u8], colors: Colors) -> Result<RgbaImage, String> {
+    let text = match std::str::from_utf8(bytes) {
+        Ok(text) => text,
+        Err(e) => return Err(format!("xbm isn't valid text: {e}")),
+    };
+
+    let (mut width, mut height) = (None, None);
+    for line in text.lines() {
+        let mut words = line.split_whitespace();
+        if words.next() != Some("#define") {
+            continue;
+        }
+        let (Some(name), Some(value)) = (words.next(), words.next()) else {
+            continue;
+        };
+        let value = || {
+            value
+                .parse::<u32>()
+                .map_err(|e| format!("bad value in xbm's {name:?}: {e}"))
+        };
+        if name.ends_with("_width") {
+            width = Some(value()?);
+        } else if name.ends_with("_height") {
+            height = Some(value()?);
+        }
+    }
+    let (Some(width), Some(height)) = (width, height) else {
+        return Err("xbm doesn't define its width and height".to_string());
+    };
+    if width == 0 || height == 0 {
+        return Err("xbm has no pixels".to_string());
+    }
+
+    let (Some(open), Some(close)) = (text.find('{'), text.rfind('}')) else {
+        return Err("xbm has no bits".to_string());
+    };
+    if close < open {
+        return Err("xbm has no bits".to_string());
+    }
+    let bits_per_value = if text[..open].contains("short") {
+        16
+    } else {
+        8
+    };
+    let values = text[open + 1..close]
+        .split(',')
+        .map(str::trim)
+        .filter(|value| !value.is_empty())
+        .map(parse_value)
+        .collect::<Result<Vec<u16>, String>>()?;
+
+    // rows are padded to whole values
+    let row_values = width.div_ceil(bits_per_value) as usize;
+    if values.len() < row_values * height as usize {
+        return Err(format!(
+            "xbm has {} values, but a {width}x{height} bitmap needs {}",
+            values.len(),
+            row_values * height as usize
+        ));
+    }
+
+    let [fg, bg] = [colors.fg, colors.bg].map(|[r, g, b]| image::Rgba([r, g, b, 255]));
+    Ok(RgbaImage::from_fn(width, height, |x, y| {
+        let value = values[y as usize * row_values + (x / bits_per_value) as usize];
+        // the first pixel is the least significant bit
+        if value >> (x % bits_per_value) & 1 == 1 {
+            fg
+        } else {
+            bg
+        }
+    }))
+}
+
+fn parse_value(value: &str) -> Result<u16, String> {
+    let parsed = match value
+        .strip_prefix("0x")
+        .or_else(|| value.strip_prefix("0X"))
+    {
+        Some(hex) => u16::from_str_radix(hex, 16),
+        None => value.parse(),
+    };
+    parsed.map_err(|e| format!("bad value in xbm's bits ({value:?}): {e}"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const XBM: &str = "#define pattern_width 10
+#define pattern_height 2
+static unsigned char pattern_bits[] = {
+   0x01, 0x02, 0xff, 0x03 };
+";
+
+    #[test]
+    fn should_decode_bits_lsb_first() {
+        assert!(is_xbm(XBM.as_bytes()));
+        let colors = Colors {
+            fg: [255, 0, 0],
+            bg: [0, 0, 255],
+        };
+        let img = decode(XBM.as_bytes(), colors).unwrap();
+        assert_eq!(img.dimensions(), (10, 2));
+        let fg = |x, y| img.get_pixel(x, y).0 == [255, 0, 0, 255];
+        let first_row: Vec<bool> = (0..10).map(|x| fg(x, 0)).collect();
+        assert_eq!(
+            first_row,
+            [true, false, false, false, false, false, false, false, false, true]
+        );
+        assert!((0..10).all(|x| fg(x, 1)));
+    }
+
+    #[test]
+    fn should_decode_x10_bitmaps() {
+        let xbm = "#define x10_width 17\n#define x10_height 1\nstatic unsigned short x10_bits[] = {\n0x8001, 0x0001};";
+        let img = decode(xbm.as_bytes(), Colors::default()).unwrap();
+        let black: Vec<u32> = (0..17)
+            .filter(|x| img.get_pixel(*x, 0).0 == [0, 0, 0, 255])
+            .collect();
+        assert_eq!(black, [0, 15, 16]);
+    }
+
+    #[test]
+    fn should_reject_truncated_bitmaps() {
+        assert!(!is_xbm(b"\x89PNG"));
+        let truncated = XBM.replace(", 0x03", "");
+        assert!(decode(truncated.as_bytes(), Colors::default()).is_err());
+    }
+}
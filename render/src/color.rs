@@ -0,0 +1,57 @@
+//! Color profiles. We display everything as sRGB, so images with an embedded ICC profile (like
+//! photos taken in Display P3) must be converted first, or they look washed out or oversaturated
+use std::io::Cursor;
+
+use image::{
+    codecs::{jpeg::JpegDecoder, png::PngDecoder},
+    ImageDecoder, ImageFormat, RgbaImage,
+};
+use qcms::{DataType, Intent, Profile, Transform};
+
+/// Returns the ICC profile embedded in `bytes`, an encoded image in `format`, if any. Only pngs
+/// and jpegs are supported
+pub fn icc_profile(bytes: &[u8], format: ImageFormat) -> Option<Vec<u8>> {
+    match format {
+        ImageFormat::Png => PngDecoder::new(Cursor::new(bytes)).ok()?.icc_profile(),
+        ImageFormat::Jpeg => JpegDecoder::new(Cursor::new(bytes)).ok()?.icc_profile(),
+        _ => None,
+    }
+}
+
+/// Converts `img` from the color space described by the ICC profile `icc` to sRGB
+pub fn to_srgb(img: &mut RgbaImage, icc: &[u8]) -> Result<(), String> {
+    let input = match Profile::new_from_slice(icc, false) {
+        Some(profile) => profile,
+        None => return Err("failed to parse the image's color profile".to_string()),
+    };
+    let mut output = Profile::new_sRGB();
+    output.precache_output_transform();
+    match Transform::new(&input, &output, DataType::RGBA8, Intent::Perceptual) {
+        Some(transform) => {
+            transform.apply(img);
+            Ok(())
+        }
+        None => Err("can't convert the image's color profile to sRGB".to_string()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn invalid_profiles_should_be_rejected() {
+        let mut img = RgbaImage::new(1, 1);
+        assert!(to_srgb(&mut img, b"not a profile").is_err());
+    }
+
+    #[test]
+    fn images_without_profiles_should_have_none() {
+        let mut png = Vec::new();
+        RgbaImage::new(1, 1)
+            .write_to(&mut Cursor::new(&mut png), ImageFormat::Png)
+            .unwrap();
+        assert!(icc_profile(&png, ImageFormat::Png).is_none());
+        assert!(icc_profile(&png, ImageFormat::Gif).is_none());
+    }
+}
@@ -0,0 +1,613 @@
+//! # Compression Strategy
+//!
+//! For every pixel, we drop the alpha part; I don't think anyone will use transparency for a
+//! background (nor if it even makes sense)
+//!
+//! For what's left, we store only the difference from the last frame to this one. We do that as
+//! follows:
+//! * First, we count how many pixels didn't change. We store that value as a u8.
+//!   Every time the u8 hits the max (i.e. 255, or 0xFF), we push in onto the vector
+//!   and restart the counting.
+//! * Once we find a pixel that has changed, we count, starting from that one, how many changed,
+//!   the same way we counted above (i.e. store as u8, every time it hits the max push and restart
+//!   the counting)
+//! * Then, we store all the new bytes.
+//! * Start from the top until we are done with the image
+//!
+//! Animation frames may also be packed with a threshold, under which changes are ignored. This is
+//! lossy, but for noisy sources (like videos or grainy gifs) almost every pixel changes every
+//! frame, and the diffs would otherwise be as big as the frames themselves.
+//!
+
+use lzzzz::lz4f;
+use std::iter::zip;
+
+lazy_static::lazy_static! {
+    static ref COMPRESSION_PREFERENCES: lz4f::Preferences = lz4f::PreferencesBuilder::new()
+            .block_size(lz4f::BlockSize::Max256KB)
+            .compression_level(9)
+            .build();
+}
+
+/// This calculates the difference between the current(cur) frame and the next(goal).
+/// The closure you pass is run at every difference. It dictates the update logic of the current
+/// frame. With that, you can control whether all different pixels changed are updated, or only the
+/// ones at a certain position. It is meant to be used primarily when writing transitions
+///
+/// Pixels whose colors all changed by no more than `threshold` are considered equal
+fn pack_bytes<F>(cur: &mut [u8], goal: &[u8], threshold: u8, mut f: F) -> Box<[u8]>
+where
+    F: FnMut(&mut [u8; 4], &[u8; 4], usize),
+{
+    let mut v = Vec::with_capacity((goal.len() * 5) / 8);
+
+    let mut iter = zip_eq(pixels_mut(cur), pixels(goal)).enumerate();
+    let mut to_add = Vec::with_capacity(333); // 100 pixels
+    while let Some((mut i, (mut cur, mut goal))) = iter.next() {
+        let mut equals = 0;
+        while !differs(cur, goal, threshold) {
+            equals += 1;
+            match iter.next() {
+                None => return v.into_boxed_slice(),
+                Some((j, (c, g))) => {
+                    i = j;
+                    cur = c;
+                    goal = g;
+                }
+            }
+        }
+
+        let mut diffs = 0;
+        while differs(cur, goal, threshold) {
+            f(cur, goal, i);
+            to_add.extend_from_slice(&cur[0..3]);
+            diffs += 1;
+            match iter.next() {
+                None => break,
+                Some((j, (c, g))) => {
+                    i = j;
+                    cur = c;
+                    goal = g;
+                }
+            }
+        }
+        let j = v.len() + equals / 255;
+        v.resize(1 + v.len() + equals / 255 + diffs / 255, 255);
+        v[j] = (equals % 255) as u8;
+        v.push((diffs % 255) as u8);
+        v.append(&mut to_add);
+    }
+    v.push(0);
+    v.into_boxed_slice()
+}
+
+#[inline]
+fn differs(cur: &[u8; 4], goal: &[u8; 4], threshold: u8) -> bool {
+    if threshold == 0 {
+        cur != goal
+    } else {
+        zip(&cur[0..3], &goal[0..3]).any(|(c, g)| c.abs_diff(*g) > threshold)
+    }
+}
+
+fn unpack_bytes(buf: &mut [u8], diff: &[u8]) {
+    let buf_chunks = pixels_mut(buf);
+    let mut diff_idx = 0;
+    let mut pix_idx = 0;
+    while diff_idx < diff.len() - 1 {
+        while diff[diff_idx] == u8::MAX {
+            pix_idx += u8::MAX as usize;
+            diff_idx += 1;
+        }
+        pix_idx += diff[diff_idx] as usize;
+        diff_idx += 1;
+
+        let mut to_cpy = 0;
+        while diff[diff_idx] == u8::MAX {
+            to_cpy += u8::MAX as usize;
+            diff_idx += 1;
+        }
+        to_cpy += diff[diff_idx] as usize;
+        diff_idx += 1;
+
+        for _ in 0..to_cpy {
+            // copying 4 bytes at a time is faster, but when the frame ends with unchanged pixels,
+            // the last diff is also the end of the pack, so there is no 4th byte to copy
+            unsafe {
+                if diff_idx + 4 <= diff.len() {
+                    buf_chunks
+                        .get_unchecked_mut(pix_idx)
+                        .clone_from_slice(diff.get_unchecked(diff_idx..diff_idx + 4));
+                } else {
+                    buf_chunks
+                        .get_unchecked_mut(pix_idx)
+                        .get_unchecked_mut(0..3)
+                        .clone_from_slice(diff.get_unchecked(diff_idx..diff_idx + 3));
+                }
+            }
+            diff_idx += 3;
+            pix_idx += 1;
+        }
+        pix_idx += 1;
+    }
+}
+
+/// Like `unpack_bytes`, but for a diff of an image of `window.dim`, drawn at `window.offset` of
+/// `buf`. Pixels falling outside of `buf` are dropped, so this doesn't need any unsafe
+fn unpack_bytes_windowed(buf: &mut [u8], diff: &[u8], window: &Window) {
+    let buf_chunks = pixels_mut(buf);
+    let width = window.dim.0 as usize;
+    let (left, top) = (window.offset.0 as usize, window.offset.1 as usize);
+    let stride = window.buf_width as usize;
+    let mut diff_idx = 0;
+    let mut pix_idx = 0;
+    while diff_idx < diff.len() - 1 {
+        while diff[diff_idx] == u8::MAX {
+            pix_idx += u8::MAX as usize;
+            diff_idx += 1;
+        }
+        pix_idx += diff[diff_idx] as usize;
+        diff_idx += 1;
+
+        let mut to_cpy = 0;
+        while diff[diff_idx] == u8::MAX {
+            to_cpy += u8::MAX as usize;
+            diff_idx += 1;
+        }
+        to_cpy += diff[diff_idx] as usize;
+        diff_idx += 1;
+
+        for _ in 0..to_cpy {
+            let buf_idx = (top + pix_idx / width) * stride + left + pix_idx % width;
+            if let (Some(pixel), Some(new)) = (
+                buf_chunks.get_mut(buf_idx),
+                diff.get(diff_idx..diff_idx + 3),
+            ) {
+                pixel[0..3].copy_from_slice(new);
+            }
+            diff_idx += 3;
+            pix_idx += 1;
+        }
+        pix_idx += 1;
+    }
+}
+
+/// Where, in a bigger buffer, to unpack a pack made for a smaller image
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Window {
+    /// Where the window's top left corner is, in pixels
+    pub offset: (u32, u32),
+    /// The dimensions of the image the pack was made for
+    pub dim: (u32, u32),
+    /// How many pixels wide the buffer is
+    pub buf_width: u32,
+}
+
+impl Window {
+    /// Whether the window is entirely inside of a buffer of `buf_dim`
+    #[must_use]
+    pub fn fits(&self, buf_dim: (u32, u32)) -> bool {
+        self.buf_width == buf_dim.0
+            && u64::from(self.offset.0) + u64::from(self.dim.0) <= u64::from(buf_dim.0)
+            && u64::from(self.offset.1) + u64::from(self.dim.1) <= u64::from(buf_dim.1)
+    }
+}
+
+pub use swww_protocol::BitPack;
+
+/// Packing and unpacking [`BitPack`]s, the cached difference between the previous frame and the
+/// next
+pub trait Pack: Sized {
+    /// Compresses a frame of animation by getting the difference between the previous and the
+    /// current frame.
+    /// IMPORTANT: this will change `prev` into `cur`, that's why it needs to be 'mut'
+    fn pack(prev: &mut [u8], cur: &[u8]) -> Result<Self, String> {
+        Self::pack_with_threshold(prev, cur, 0)
+    }
+
+    /// Like `pack`, but ignores the pixels whose colors all changed by no more than `threshold`.
+    /// Since the ignored changes are also left out of `prev`, they never accumulate
+    fn pack_with_threshold(prev: &mut [u8], cur: &[u8], threshold: u8) -> Result<Self, String>;
+
+    /// Packs every pixel of `frame`, so that it can be unpacked over anything. These are the
+    /// keyframes animations use to resynchronize without replaying every diff before them
+    fn keyframe(frame: &[u8]) -> Result<Self, String> {
+        // every pixel of this canvas differs from the frame's, so all of them end up in the pack
+        let mut canvas: Vec<u8> = frame.iter().map(|byte| !byte).collect();
+        Self::pack(&mut canvas, frame)
+    }
+
+    /// Produces a `ReadiedPack`, which can be sent through a channel to be unpacked later
+    #[must_use]
+    fn ready(&self, expected_buf_size: usize) -> ReadiedPack;
+}
+
+impl Pack for BitPack {
+    fn pack_with_threshold(prev: &mut [u8], cur: &[u8], threshold: u8) -> Result<Self, String> {
+        let bit_pack = pack_bytes(prev, cur, threshold, |old, new, _| *old = *new);
+        let mut v = Vec::with_capacity(bit_pack.len() / 2);
+        match lzzzz::lz4f::compress_to_vec(&bit_pack, &mut v, &COMPRESSION_PREFERENCES) {
+            Ok(_) => Ok(BitPack::from_compressed(v.into_boxed_slice())),
+            Err(e) => Err(e.to_string()),
+        }
+    }
+
+    fn ready(&self, expected_buf_size: usize) -> ReadiedPack {
+        let mut v = Vec::with_capacity(self.compressed().len() * 3);
+        // Note: panics will never happen because BitPacked is *always* only produced with
+        // correct lz4 compression
+        lz4f::decompress_to_vec(self.compressed(), &mut v).unwrap();
+        ReadiedPack {
+            inner: v.into_boxed_slice(),
+            expected_buf_size,
+            window: None,
+        }
+    }
+}
+
+/// This is what we send through the channel to be drawn
+pub struct ReadiedPack {
+    inner: Box<[u8]>,
+    /// This field will ensure we won't ever try to unpack the images on a buffer of the wrong size,
+    /// which ultimately is what allows us to use unsafe in the unpack_bytes function
+    expected_buf_size: usize,
+    window: Option<Window>,
+}
+
+impl ReadiedPack {
+    /// This should only be used in the transitions. For caching the animation frames, use the
+    /// Bitpack struct
+    ///
+    /// The `f` runs at every different pixel found, iterating through the three colors BGR. Its
+    /// parameters are:
+    ///
+    /// * First -> old img byte, that has to change to the new one according to the transition logic
+    /// * Second -> new img byte. This stays constant
+    /// * Third -> the pixel's position in the image. This can be used to make more complex
+    ///   transition logic
+    #[must_use]
+    pub fn new<F>(cur: &mut [u8], goal: &[u8], f: F) -> Self
+    where
+        F: FnMut(&mut [u8; 4], &[u8; 4], usize),
+    {
+        let bit_pack = pack_bytes(cur, goal, 0, f);
+        ReadiedPack {
+            inner: bit_pack,
+            expected_buf_size: cur.len(),
+            window: None,
+        }
+    }
+
+    /// Like `BitPack::keyframe`, without the compression: a pack of every pixel of `frame`, which
+    /// unpacks over anything
+    #[must_use]
+    pub fn keyframe(frame: &[u8]) -> Self {
+        let mut canvas: Vec<u8> = frame.iter().map(|byte| !byte).collect();
+        Self::new(&mut canvas, frame, |old, new, _| *old = *new)
+    }
+
+    /// Passes every color this pack writes through `lut`, which is how animations are adjusted
+    /// without touching their cached frames
+    pub fn map_colors(&mut self, lut: &[u8; 256]) {
+        let diff = &mut self.inner;
+        let mut diff_idx = 0;
+        while diff_idx + 1 < diff.len() {
+            while diff[diff_idx] == u8::MAX {
+                diff_idx += 1;
+            }
+            diff_idx += 1;
+
+            let mut to_map = 0;
+            while diff[diff_idx] == u8::MAX {
+                to_map += u8::MAX as usize;
+                diff_idx += 1;
+            }
+            to_map += diff[diff_idx] as usize;
+            diff_idx += 1;
+
+            let end = (diff_idx + to_map * 3).min(diff.len());
+            for byte in &mut diff[diff_idx..end] {
+                *byte = lut[*byte as usize];
+            }
+            diff_idx = end;
+        }
+    }
+
+    /// Makes this pack, readied for an image of `window.dim`, unpack into `window` of a bigger
+    /// buffer instead. Unpacking fails if the window doesn't fit the buffer's width
+    #[must_use]
+    pub fn in_window(self, window: Window) -> Self {
+        let expected_buf_size =
+            if window.offset.0 as usize + window.dim.0 as usize <= window.buf_width as usize {
+                (window.offset.1 as usize + window.dim.1 as usize) * window.buf_width as usize * 4
+            } else {
+                usize::MAX
+            };
+        ReadiedPack {
+            inner: self.inner,
+            expected_buf_size,
+            window: Some(window),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.inner.is_empty()
+    }
+
+    /// Where this pack unpacks, if it only covers part of the buffer
+    pub fn window(&self) -> Option<&Window> {
+        self.window.as_ref()
+    }
+
+    ///return whether unpacking was successful. Note it can only fail if `buf.len() !=
+    ///expected_buf_size`
+    pub fn unpack(&self, buf: &mut [u8]) -> bool {
+        if buf.len() >= self.expected_buf_size {
+            if !self.inner.is_empty() {
+                match &self.window {
+                    Some(window) => unpack_bytes_windowed(buf, &self.inner, window),
+                    None => unpack_bytes(buf, &self.inner),
+                }
+            }
+            true
+        } else {
+            false
+        }
+    }
+}
+
+// Utility functions. Largely copied from the Itertools and Bytemuck crates
+
+/// An iterator which iterates two other iterators simultaneously
+/// Copy pasted from the Iterator crate, and adapted for our purposes
+#[must_use = "iterator adaptors are lazy and do nothing unless consumed"]
+struct ZipEq<'a, I> {
+    a: std::slice::IterMut<'a, I>,
+    b: std::slice::Iter<'a, I>,
+}
+
+fn zip_eq<'a, I>(i: &'a mut [I], j: &'a [I]) -> ZipEq<'a, I> {
+    if i.len() != j.len() {
+        unreachable!(
+            "Iterators of zip_eq have different sizes: {}, {}",
+            i.len(),
+            j.len()
+        );
+    }
+    ZipEq {
+        a: i.iter_mut(),
+        b: j.iter(),
+    }
+}
+
+impl<'a, I> Iterator for ZipEq<'a, I> {
+    type Item = (&'a mut I, &'a I);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match (self.a.next(), self.b.next()) {
+            (None, None) => None,
+            (Some(a), Some(b)) => Some((a, b)),
+            _ => unsafe { std::hint::unreachable_unchecked() },
+        }
+    }
+}
+
+// The functions below were copy pasted and adapted from the bytemuck crate:
+
+#[inline]
+fn pixels(img: &[u8]) -> &[[u8; 4]] {
+    if !img.len().is_multiple_of(4) {
+        unreachable!("Calling pixels with a wrongly formatted image");
+    }
+    unsafe { core::slice::from_raw_parts(img.as_ptr().cast::<[u8; 4]>(), img.len() / 4) }
+}
+
+#[inline]
+fn pixels_mut(img: &mut [u8]) -> &mut [[u8; 4]] {
+    if !img.len().is_multiple_of(4) {
+        unreachable!("Calling pixels_mut with a wrongly formatted image");
+    }
+    unsafe { core::slice::from_raw_parts_mut(img.as_ptr() as *mut [u8; 4], img.len() / 4) }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{pack_bytes, BitPack, Pack, Window};
+    use rand::prelude::random;
+
+    #[test]
+    fn windowed_packs_should_only_change_their_window() {
+        // a 2x2 image, drawn at (1, 1) of a 4x3 buffer
+        let frame1 = [0; 16];
+        let frame2: Vec<u8> = (1..=16).collect();
+        let compressed = BitPack::pack(&mut frame1.clone(), &frame2).unwrap();
+        let window = Window {
+            offset: (1, 1),
+            dim: (2, 2),
+            buf_width: 4,
+        };
+        assert!(window.fits((4, 3)));
+        assert!(!window.fits((4, 2)));
+
+        let mut buf = vec![0; 48];
+        assert!(compressed.ready(16).in_window(window).unpack(&mut buf));
+        for (i, pixel) in buf.chunks_exact(4).enumerate() {
+            let expected = match i {
+                5 => [1, 2, 3],
+                6 => [5, 6, 7],
+                9 => [9, 10, 11],
+                10 => [13, 14, 15],
+                _ => [0, 0, 0],
+            };
+            assert_eq!(pixel[0..3], expected, "pixel {i}");
+        }
+        assert!(!compressed.ready(16).in_window(window).unpack(&mut [0; 32]));
+    }
+
+    #[test]
+    fn mapped_packs_should_only_change_the_colors() {
+        let mut prev = vec![0u8; 4 * 600];
+        let mut cur = prev.clone();
+        for (i, pixel) in cur.chunks_exact_mut(4).enumerate() {
+            if i % 300 < 280 {
+                pixel[0..3].copy_from_slice(&[10, 20, (i % 250) as u8]);
+            }
+        }
+        let mut lut = [0u8; 256];
+        for (i, v) in lut.iter_mut().enumerate() {
+            *v = (i as u8).wrapping_add(1);
+        }
+        let pack = BitPack::pack(&mut prev, &cur).unwrap();
+        let mut readied = pack.ready(cur.len());
+        readied.map_colors(&lut);
+        let mut buf = vec![0u8; cur.len()];
+        assert!(readied.unpack(&mut buf));
+        for (got, want) in buf.chunks_exact(4).zip(cur.chunks_exact(4)) {
+            if want[0..3] == [0, 0, 0] {
+                assert_eq!(got[0..3], [0, 0, 0]);
+            } else {
+                assert_eq!(got[0..3], [want[0] + 1, want[1] + 1, want[2] + 1]);
+            }
+        }
+    }
+
+    #[test]
+    fn keyframes_should_unpack_over_anything() {
+        let frame: Vec<u8> = (0..4000).map(|_| random::<u8>()).collect();
+        let keyframe = BitPack::keyframe(&frame).unwrap();
+        for base in [frame.clone(), vec![0; 4000], vec![255; 4000]] {
+            let mut buf = base;
+            keyframe.ready(4000).unpack(&mut buf);
+            for i in 0..1000 {
+                assert_eq!(buf[i * 4..i * 4 + 3], frame[i * 4..i * 4 + 3]);
+            }
+        }
+    }
+
+    #[test]
+    fn threshold_should_ignore_small_changes() {
+        let frame1 = [10, 10, 10, 0, 10, 10, 10, 0, 10, 10, 10, 0];
+        let frame2 = [12, 8, 10, 0, 10, 30, 10, 0, 13, 10, 10, 0];
+        let mut prev = frame1;
+        let compressed = BitPack::pack_with_threshold(&mut prev, &frame2, 2).unwrap();
+
+        let mut buf = frame1;
+        compressed.ready(12).unpack(&mut buf);
+        assert_eq!(
+            buf[0..3],
+            frame1[0..3],
+            "change within the threshold was applied"
+        );
+        assert_eq!(buf[4..7], frame2[4..7]);
+        assert_eq!(buf[8..11], frame2[8..11]);
+        // prev must always reflect what is actually displayed
+        for i in 0..3 {
+            assert_eq!(buf[i * 4..i * 4 + 3], prev[i * 4..i * 4 + 3]);
+        }
+    }
+
+    #[test]
+    //Use this when annoying problems show up
+    fn should_compress_and_decompress_to_same_info_small() {
+        let frame1 = [1, 2, 3, 4, 5, 6, 7, 8];
+        let frame2 = [1, 2, 3, 4, 8, 7, 6, 5];
+        let compressed = BitPack::pack(&mut frame1.clone(), &frame2).unwrap();
+
+        let mut buf = frame1;
+        let readied = compressed.ready(8);
+        readied.unpack(&mut buf);
+        for i in 0..2 {
+            for j in 0..3 {
+                assert_eq!(
+                    frame2[i * 4 + j],
+                    buf[i * 4 + j],
+                    "\nframe2: {frame2:?}, buf: {buf:?}\n"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn packs_ending_with_unchanged_pixels_should_not_be_padded() {
+        let frame1 = [1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12];
+        let frame2 = [8, 7, 6, 4, 5, 6, 7, 8, 9, 10, 11, 12];
+        let pack = pack_bytes(&mut frame1.clone(), &frame2, 0, |old, new, _| *old = *new);
+        assert_eq!(*pack, [0, 1, 8, 7, 6]);
+
+        let compressed = BitPack::pack(&mut frame1.clone(), &frame2).unwrap();
+        let mut buf = frame1;
+        assert!(compressed.ready(12).unpack(&mut buf));
+        assert_eq!(buf, frame2);
+    }
+
+    #[test]
+    fn should_compress_and_decompress_to_same_info() {
+        for _ in 0..10 {
+            let mut original = Vec::with_capacity(20);
+            for _ in 0..20 {
+                let mut v = Vec::with_capacity(4000);
+                for _ in 0..4000 {
+                    v.push(random::<u8>());
+                }
+                original.push(v);
+            }
+
+            let mut compressed = Vec::with_capacity(20);
+            compressed
+                .push(BitPack::pack(&mut original.last().unwrap().clone(), &original[0]).unwrap());
+            for i in 1..20 {
+                compressed.push(BitPack::pack(&mut original[i - 1].clone(), &original[i]).unwrap());
+            }
+
+            let mut buf = original.last().unwrap().clone();
+            for i in 0..20 {
+                let readied = compressed[i].ready(4000);
+                readied.unpack(&mut buf);
+                let mut j = 0;
+                while j < 4000 {
+                    for k in 0..3 {
+                        assert_eq!(buf[j + k], original[i][j + k], "Failed at index: {}", j + k);
+                    }
+                    j += 4;
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn should_compress_and_decompress_to_same_info_with_equal_data() {
+        for _ in 0..10 {
+            let mut original = Vec::with_capacity(20);
+            for _ in 0..20 {
+                let mut v = Vec::with_capacity(4000);
+                for _ in 0..3000 {
+                    v.push(random::<u8>());
+                }
+                for i in 0..1000 {
+                    v.push((i % 255) as u8);
+                }
+                original.push(v);
+            }
+
+            let mut compressed = Vec::with_capacity(20);
+            compressed
+                .push(BitPack::pack(&mut original.last().unwrap().clone(), &original[0]).unwrap());
+            for i in 1..20 {
+                compressed.push(BitPack::pack(&mut original[i - 1].clone(), &original[i]).unwrap());
+            }
+
+            let mut buf = original.last().unwrap().clone();
+            for i in 0..20 {
+                let readied = compressed[i].ready(4000);
+                readied.unpack(&mut buf);
+                let mut j = 0;
+                while j < 4000 {
+                    for k in 0..3 {
+                        assert_eq!(buf[j + k], original[i][j + k], "Failed at index: {}", j + k);
+                    }
+                    j += 4;
+                }
+            }
+        }
+    }
+}
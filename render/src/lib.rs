@@ -0,0 +1,50 @@
+//! The rendering pipeline of `swww`, without anything tied to wayland or to `swww`'s daemon, so
+//! that other programs (like lockscreens or greeters) can draw exactly what `swww` would:
+//!
+//! * [`bitmap`] decodes X11 bitmaps (xbm), drawing them with the given colors;
+//! * [`color`] converts images with an embedded color profile to sRGB;
+//! * [`resize`] fits images to an output, in bgra;
+//! * [`animation`] compresses animated images into [`comp_decomp::BitPack`]s, the difference
+//!   between consecutive frames;
+//! * [`transition`] plays the transition from one image into another, frame by frame;
+//! * [`adjust`] is what `swww adjust` does to the images and animation frames.
+//!
+//! All images are buffers of 4 bytes per pixel, in bgra, and rows without padding. For example,
+//! to make the frames of a `wipe` transition into an image:
+//!
+//! ```no_run
+//! use swww_render::{resize, transition};
+//!
+//! let img = image::open("new.png").unwrap().into_rgba8();
+//! let new = resize::resize(img, (1920, 1080), resize::FilterType::Lanczos3).unwrap();
+//! let old = vec![0; new.len()].into_boxed_slice();
+//! let settings = transition::Transition {
+//!     transition_type: transition::TransitionType::Wipe,
+//!     duration: 1.0,
+//!     step: 90,
+//!     fps: 30,
+//!     angle: 45.0,
+//!     pos: transition::Position::new(
+//!         transition::Coord::Percent(0.5),
+//!         transition::Coord::Percent(0.5),
+//!     ),
+//!     bezier: (0.54, 0.0, 0.34, 0.99),
+//!     wave: (20.0, 20.0),
+//!     sync: transition::TransitionSync::None,
+//!     skip_threshold: 0.0,
+//! };
+//! let mut buffer = old.to_vec();
+//! transition::Runner::new(old, (1920, 1080), settings, None).execute(&new, &mut |frame, due| {
+//!     frame.unpack(&mut buffer);
+//!     // draw `buffer` after `due`, and return whether to stop
+//!     std::thread::sleep(due);
+//!     false
+//! });
+//! ```
+pub mod adjust;
+pub mod animation;
+pub mod bitmap;
+pub mod color;
+pub mod comp_decomp;
+pub mod resize;
+pub mod transition;
@@ -0,0 +1,492 @@
+//! Fitting images to outputs. Everything returned here is in bgra, which is what `wl_shm`'s
+//! `Xrgb8888` and `Argb8888` formats are in little endian machines
+use std::num::NonZeroU32;
+
+use fast_image_resize::{PixelType, Resizer};
+
+pub use fast_image_resize::FilterType;
+
+/// Shrinking images by this much before scaling them back up blurs them about as much as we want
+const BLUR_FACTOR: u32 = 32;
+
+/// What to fill the space around an image that doesn't cover the whole output with
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Fill {
+    /// A solid color, in rgb
+    Color([u8; 3]),
+    /// A blurred copy of the image, covering the whole output
+    Blur,
+    /// The image's edges, stretched until the output's edges
+    Edge,
+    /// The image's most common color
+    Dominant,
+}
+
+/// Centers `img` in a canvas of `dimensions` filled with `fill`, cropping whatever doesn't fit,
+/// and converts it to bgra
+pub fn pad(
+    mut img: image::RgbaImage,
+    dimensions: (u32, u32),
+    fill: Fill,
+) -> Result<Vec<u8>, String> {
+    let img = image::imageops::crop(&mut img, 0, 0, dimensions.0, dimensions.1).to_image();
+    let img_dim = img.dimensions();
+    let mut bgra = img.into_raw();
+    for pixel in bgra.chunks_exact_mut(4) {
+        pixel.swap(0, 2);
+    }
+    letterbox(&bgra, img_dim, dimensions, fill)
+}
+
+/// Resizes `img` to fit inside `dimensions`, keeping its aspect ratio, fills the space around it
+/// with `fill`, and converts it to bgra
+pub fn fit(
+    img: image::RgbaImage,
+    dimensions: (u32, u32),
+    filter: FilterType,
+    fill: Fill,
+) -> Result<Vec<u8>, String> {
+    let (img_w, img_h) = img.dimensions();
+    if img_w == 0 || img_h == 0 {
+        return Err("can't resize images without dimensions".to_string());
+    }
+    let scale = f64::min(
+        dimensions.0 as f64 / img_w as f64,
+        dimensions.1 as f64 / img_h as f64,
+    );
+    let fitted = (
+        ((img_w as f64 * scale).round() as u32).clamp(1, dimensions.0.max(1)),
+        ((img_h as f64 * scale).round() as u32).clamp(1, dimensions.1.max(1)),
+    );
+    let resized = resize(img, fitted, filter)?;
+    letterbox(&resized, fitted, dimensions, fill)
+}
+
+/// Resizes `img` to fill `dimensions`, cropping whatever doesn't fit around its center, and
+/// converts it to bgra
+pub fn resize(
+    img: image::RgbaImage,
+    dimensions: (u32, u32),
+    filter: FilterType,
+) -> Result<Vec<u8>, String> {
+    resize_to_bgra(img, dimensions, filter, true)
+}
+
+/// Resizes `img` to exactly `dimensions`, without keeping its aspect ratio, and converts it to
+/// bgra
+pub fn stretch(
+    img: image::RgbaImage,
+    dimensions: (u32, u32),
+    filter: FilterType,
+) -> Result<Vec<u8>, String> {
+    resize_to_bgra(img, dimensions, filter, false)
+}
+
+fn resize_to_bgra(
+    img: image::RgbaImage,
+    dimensions: (u32, u32),
+    filter: FilterType,
+    crop: bool,
+) -> Result<Vec<u8>, String> {
+    let img_dim = img.dimensions();
+    let mut resized_img = if img_dim != dimensions {
+        resize_raw(img.into_raw(), img_dim, dimensions, filter, crop)?
+    } else {
+        img.into_vec()
+    };
+
+    // The ARGB is 'little endian', so here we must  put the order
+    // of bytes 'in reverse', so it needs to be BGRA.
+    for pixel in resized_img.chunks_exact_mut(4) {
+        pixel.swap(0, 2);
+    }
+
+    Ok(resized_img)
+}
+
+/// Resizes `img`, with 4 bytes per pixel and the alpha last, from `img_dim` to `dimensions`,
+/// without touching the order of its channels. With `crop`, it keeps its aspect ratio, cropping
+/// whatever doesn't fit around its center
+fn resize_raw(
+    img: Vec<u8>,
+    img_dim: (u32, u32),
+    dimensions: (u32, u32),
+    filter: FilterType,
+    crop: bool,
+) -> Result<Vec<u8>, String> {
+    let (Some(img_w), Some(img_h), Some(new_w), Some(new_h)) = (
+        NonZeroU32::new(img_dim.0),
+        NonZeroU32::new(img_dim.1),
+        NonZeroU32::new(dimensions.0),
+        NonZeroU32::new(dimensions.1),
+    ) else {
+        return Err("can't resize images without dimensions".to_string());
+    };
+    let mut src = match fast_image_resize::Image::from_vec_u8(img_w, img_h, img, PixelType::U8x4) {
+        Ok(i) => i,
+        Err(e) => return Err(e.to_string()),
+    };
+
+    let alpha_mul_div = fast_image_resize::MulDiv::default();
+    if let Err(e) = alpha_mul_div.multiply_alpha_inplace(&mut src.view_mut()) {
+        return Err(e.to_string());
+    }
+
+    let mut src_view = src.view();
+    if crop {
+        src_view.set_crop_box_to_fit_dst_size(new_w, new_h, Some((0.5, 0.5)));
+    }
+
+    let mut dst = fast_image_resize::Image::new(new_w, new_h, PixelType::U8x4);
+    let mut dst_view = dst.view_mut();
+
+    let mut resizer = Resizer::new(fast_image_resize::ResizeAlg::Convolution(filter));
+    if let Err(e) = resizer.resize(&src_view, &mut dst_view) {
+        return Err(e.to_string());
+    }
+
+    if let Err(e) = alpha_mul_div.divide_alpha_inplace(&mut dst_view) {
+        return Err(e.to_string());
+    }
+
+    Ok(dst.into_vec())
+}
+
+/// Centers `img` (in bgra, with dimensions `img_dim`, no larger than `dimensions`) in a canvas of
+/// `dimensions` filled with `fill`
+fn letterbox(
+    img: &[u8],
+    img_dim: (u32, u32),
+    dimensions: (u32, u32),
+    fill: Fill,
+) -> Result<Vec<u8>, String> {
+    let (width, height) = (dimensions.0 as usize, dimensions.1 as usize);
+    let (img_w, img_h) = (img_dim.0 as usize, img_dim.1 as usize);
+    if img_w == 0 || img_h == 0 {
+        return Err("can't fill around images without dimensions".to_string());
+    }
+    let (left, top) = ((width - img_w) / 2, (height - img_h) / 2);
+
+    let Some(mut canvas) = backdrop(img, img_dim, dimensions, fill)? else {
+        // every pixel takes the closest one in the image, which also draws the image itself
+        let mut canvas = Vec::with_capacity(width * height * 4);
+        for y in 0..height {
+            let row = y.saturating_sub(top).min(img_h - 1);
+            for x in 0..width {
+                let i = (row * img_w + x.saturating_sub(left).min(img_w - 1)) * 4;
+                canvas.extend_from_slice(&img[i..i + 4]);
+            }
+        }
+        return Ok(canvas);
+    };
+
+    for (y, row) in img.chunks_exact(img_w * 4).enumerate() {
+        let start = ((top + y) * width + left) * 4;
+        canvas[start..start + img_w * 4].copy_from_slice(row);
+    }
+    Ok(canvas)
+}
+
+/// A canvas of `dimensions` filled with `fill`, made from `img` (in bgra, with dimensions
+/// `img_dim`). Edge fills depend on where the image goes, so they are left to the caller
+fn backdrop(
+    img: &[u8],
+    img_dim: (u32, u32),
+    dimensions: (u32, u32),
+    fill: Fill,
+) -> Result<Option<Vec<u8>>, String> {
+    Ok(Some(match fill {
+        Fill::Color(color) => solid(dimensions, [color[2], color[1], color[0], 255]),
+        Fill::Dominant => solid(dimensions, dominant_color(img)),
+        Fill::Blur => {
+            let small = (
+                (dimensions.0 / BLUR_FACTOR).max(1),
+                (dimensions.1 / BLUR_FACTOR).max(1),
+            );
+            let small_img = resize_raw(img.to_vec(), img_dim, small, FilterType::Box, true)?;
+            resize_raw(small_img, small, dimensions, FilterType::Bilinear, true)?
+        }
+        Fill::Edge => return Ok(None),
+    }))
+}
+
+fn solid(dimensions: (u32, u32), pixel: [u8; 4]) -> Vec<u8> {
+    pixel.repeat(dimensions.0 as usize * dimensions.1 as usize)
+}
+
+/// The average color of the most common group of similar colors in `img`, in bgra
+fn dominant_color(img: &[u8]) -> [u8; 4] {
+    // we group colors by their 4 most significant bits of each channel
+    let mut groups = vec![(0u64, [0u64; 3]); 1 << 12];
+    for pixel in img.chunks_exact(4).filter(|pixel| pixel[3] > 0) {
+        let key =
+            (pixel[0] as usize >> 4) << 8 | (pixel[1] as usize >> 4) << 4 | pixel[2] as usize >> 4;
+        let (count, sums) = &mut groups[key];
+        *count += 1;
+        for (sum, channel) in sums.iter_mut().zip(pixel) {
+            *sum += *channel as u64;
+        }
+    }
+    match groups.iter().max_by_key(|(count, _)| *count) {
+        Some((count, sums)) if *count > 0 => [
+            (sums[0] / count) as u8,
+            (sums[1] / count) as u8,
+            (sums[2] / count) as u8,
+            255,
+        ],
+        _ => [0, 0, 0, 255],
+    }
+}
+
+/// Centers `img` (in bgra, with dimensions `img_dim`) in a canvas of `dimensions` filled with
+/// `color`, rounding its corners with `radius`
+pub fn frame(
+    img: &[u8],
+    img_dim: (u32, u32),
+    dimensions: (u32, u32),
+    radius: u32,
+    color: &[u8; 3],
+) -> Vec<u8> {
+    let (width, height) = (dimensions.0 as usize, dimensions.1 as usize);
+    let (img_w, img_h) = (img_dim.0 as usize, img_dim.1 as usize);
+    let (left, top) = ((width - img_w) / 2, (height - img_h) / 2);
+    let radius = radius.min(img_dim.0 / 2).min(img_dim.1 / 2) as f32;
+
+    let mut framed = Vec::with_capacity(width * height * 4);
+    for _ in 0..width * height {
+        framed.extend_from_slice(&[color[2], color[1], color[0], 255]);
+    }
+
+    for (y, row) in img.chunks_exact(img_w * 4).enumerate() {
+        let start = ((top + y) * width + left) * 4;
+        let framed_row = &mut framed[start..start + img_w * 4];
+        for (x, (dst, src)) in framed_row
+            .chunks_exact_mut(4)
+            .zip(row.chunks_exact(4))
+            .enumerate()
+        {
+            // distance from the center of the pixel to the center of the closest corner's circle
+            let dx = (radius - (x.min(img_w - 1 - x) as f32 + 0.5)).max(0.0);
+            let dy = (radius - (y.min(img_h - 1 - y) as f32 + 0.5)).max(0.0);
+            let coverage = if dx > 0.0 && dy > 0.0 {
+                (radius - (dx * dx + dy * dy).sqrt() + 0.5).clamp(0.0, 1.0)
+            } else {
+                1.0
+            };
+            for (d, s) in dst[0..3].iter_mut().zip(&src[0..3]) {
+                *d = (*d as f32 * (1.0 - coverage) + *s as f32 * coverage).round() as u8;
+            }
+        }
+    }
+    framed
+}
+
+/// Rotates `img` (in bgra, with dimensions `dim`) clockwise by `degrees` around its center,
+/// keeping its dimensions. Whatever the rotation uncovers is filled with `fill`
+///
+/// Multiples of 180 degrees just move the pixels around. Every other angle is resampled with a
+/// bicubic filter, and the image's edges are antialiased against the fill
+pub fn rotate(img: &[u8], dim: (u32, u32), degrees: f32, fill: Fill) -> Result<Vec<u8>, String> {
+    let degrees = degrees.rem_euclid(360.0);
+    if degrees == 0.0 {
+        return Ok(img.to_vec());
+    }
+    if degrees == 180.0 {
+        return Ok(img.chunks_exact(4).rev().flatten().copied().collect());
+    }
+
+    let (width, height) = (dim.0 as usize, dim.1 as usize);
+    if width == 0 || height == 0 {
+        return Err("can't rotate images without dimensions".to_string());
+    }
+    let backdrop = backdrop(img, dim, dim, fill)?;
+    let (sin, cos) = degrees.to_radians().sin_cos();
+    let (center_x, center_y) = (dim.0 as f32 / 2.0, dim.1 as f32 / 2.0);
+    let mut rotated = Vec::with_capacity(width * height * 4);
+    for y in 0..height {
+        for x in 0..width {
+            // where this pixel's center comes from, in the unrotated image
+            let (dx, dy) = (x as f32 + 0.5 - center_x, y as f32 + 0.5 - center_y);
+            let src_x = cos * dx + sin * dy + center_x;
+            let src_y = cos * dy - sin * dx + center_y;
+            let coverage = match &backdrop {
+                Some(_) => edge_coverage(src_x, dim.0) * edge_coverage(src_y, dim.1),
+                // edge fills stretch the image's edges, which sampling outside of it already does
+                None => 1.0,
+            };
+            let mut pixel = [0; 4];
+            if coverage > 0.0 {
+                pixel = bicubic(img, dim, src_x - 0.5, src_y - 0.5);
+            }
+            if let Some(backdrop) = &backdrop {
+                let i = (y * width + x) * 4;
+                for (p, b) in pixel.iter_mut().zip(&backdrop[i..i + 4]) {
+                    *p = (*p as f32 * coverage + *b as f32 * (1.0 - coverage)).round() as u8;
+                }
+            }
+            rotated.extend_from_slice(&pixel);
+        }
+    }
+    Ok(rotated)
+}
+
+/// How much of a pixel centered at `pos` falls inside of `0..len`
+fn edge_coverage(pos: f32, len: u32) -> f32 {
+    (pos.min(len as f32 - pos) + 0.5).clamp(0.0, 1.0)
+}
+
+/// Samples `img` (in bgra, with dimensions `dim`) at `(x, y)` with a Catmull-Rom filter, clamping
+/// the coordinates to the image
+fn bicubic(img: &[u8], dim: (u32, u32), x: f32, y: f32) -> [u8; 4] {
+    fn weights(t: f32) -> [f32; 4] {
+        let (t2, t3) = (t * t, t * t * t);
+        [
+            (-t3 + 2.0 * t2 - t) / 2.0,
+            (3.0 * t3 - 5.0 * t2 + 2.0) / 2.0,
+            (-3.0 * t3 + 4.0 * t2 + t) / 2.0,
+            (t3 - t2) / 2.0,
+        ]
+    }
+    let (x0, y0) = (x.floor(), y.floor());
+    let (wx, wy) = (weights(x - x0), weights(y - y0));
+    let clamp = |v: f32, len: u32| (v as i64).clamp(0, len as i64 - 1) as usize;
+    let mut sum = [0.0f32; 4];
+    for (j, wy) in wy.iter().enumerate() {
+        let row = clamp(y0 + j as f32 - 1.0, dim.1) * dim.0 as usize;
+        for (i, wx) in wx.iter().enumerate() {
+            let idx = (row + clamp(x0 + i as f32 - 1.0, dim.0)) * 4;
+            for (s, channel) in sum.iter_mut().zip(&img[idx..idx + 4]) {
+                *s += *channel as f32 * wx * wy;
+            }
+        }
+    }
+    sum.map(|s| s.round().clamp(0.0, 255.0) as u8)
+}
+
+/// Copies the `dim` window starting at `offset` out of `img` (in bgra, with dimensions `img_dim`)
+pub fn crop(img: &[u8], img_dim: (u32, u32), offset: (u32, u32), dim: (u32, u32)) -> Vec<u8> {
+    let stride = img_dim.0 as usize * 4;
+    let (x, y) = (offset.0 as usize * 4, offset.1 as usize);
+    let row_len = dim.0 as usize * 4;
+    img.chunks_exact(stride)
+        .skip(y)
+        .take(dim.1 as usize)
+        .flat_map(|row| &row[x..x + row_len])
+        .copied()
+        .collect()
+}
+
+/// Repeats `img` from the top left corner until it covers `dimensions`, and converts it to bgra.
+/// This is how small patterns are drawn at the output's own resolution
+pub fn tile(img: image::RgbaImage, dimensions: (u32, u32)) -> Result<Vec<u8>, String> {
+    let (img_w, img_h) = img.dimensions();
+    if img_w == 0 || img_h == 0 {
+        return Err("can't tile images without dimensions".to_string());
+    }
+    let mut bgra = img.into_raw();
+    for pixel in bgra.chunks_exact_mut(4) {
+        pixel.swap(0, 2);
+    }
+
+    let row_len = dimensions.0 as usize * 4;
+    let mut tiled = Vec::with_capacity(row_len * dimensions.1 as usize);
+    for row in bgra
+        .chunks_exact(img_w as usize * 4)
+        .cycle()
+        .take(dimensions.1 as usize)
+    {
+        tiled.extend(row.iter().cycle().take(row_len));
+    }
+    Ok(tiled)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn padding_should_center_the_image() {
+        let img = image::RgbaImage::from_pixel(1, 1, image::Rgba([10, 20, 30, 255]));
+        let padded = pad(img, (4, 3), Fill::Color([1, 2, 3])).unwrap();
+        let pixels: Vec<&[u8]> = padded.chunks_exact(4).collect();
+        assert_eq!(pixels.len(), 12);
+        assert_eq!(pixels[5], [30, 20, 10, 255]);
+        assert!(pixels
+            .iter()
+            .enumerate()
+            .all(|(i, pixel)| i == 5 || *pixel == [3, 2, 1, 255]));
+    }
+
+    #[test]
+    fn edge_fill_should_stretch_the_closest_pixels() {
+        // a 1x2 image, whose top pixel is red and bottom one is blue
+        let img = [0, 0, 255, 255, 255, 0, 0, 255];
+        let filled = letterbox(&img, (1, 2), (3, 4), Fill::Edge).unwrap();
+        for (i, pixel) in filled.chunks_exact(4).enumerate() {
+            let expected = if i / 3 < 2 { &img[0..4] } else { &img[4..8] };
+            assert_eq!(pixel, expected, "pixel {i}");
+        }
+    }
+
+    #[test]
+    fn rotation_should_turn_the_image_around_its_center() {
+        // a 3x2 image, whose pixels are numbered in their first channel
+        let img: Vec<u8> = (0..6).flat_map(|i| [i, 0, 0, 255]).collect();
+        let upside_down = rotate(&img, (3, 2), 180.0, Fill::Color([0, 0, 0])).unwrap();
+        let firsts: Vec<u8> = upside_down.chunks_exact(4).map(|p| p[0]).collect();
+        assert_eq!(firsts, [5, 4, 3, 2, 1, 0]);
+        assert_eq!(rotate(&img, (3, 2), -360.0, Fill::Edge).unwrap(), img);
+
+        // a quarter turn of a square moves the top left corner to the top right
+        let img: Vec<u8> = (0..9).flat_map(|i| [i * 10, 0, 0, 255]).collect();
+        let turned = rotate(&img, (3, 3), 90.0, Fill::Color([0, 0, 0])).unwrap();
+        let firsts: Vec<u8> = turned.chunks_exact(4).map(|p| p[0]).collect();
+        assert_eq!(firsts, [60, 30, 0, 70, 40, 10, 80, 50, 20]);
+    }
+
+    #[test]
+    fn rotation_should_fill_what_it_uncovers() {
+        let img = [200, 200, 200, 255].repeat(100 * 50);
+        let rotated = rotate(&img, (100, 50), 45.0, Fill::Color([1, 2, 3])).unwrap();
+        let pixel = |x: usize, y: usize| &rotated[(y * 100 + x) * 4..(y * 100 + x) * 4 + 4];
+        assert_eq!(pixel(0, 0), [3, 2, 1, 255]);
+        assert_eq!(pixel(50, 25), [200, 200, 200, 255]);
+    }
+
+    #[test]
+    fn tiling_should_repeat_the_image_from_the_top_left() {
+        // a 2x1 image, whose pixels are numbered in their red channel
+        let img = image::RgbaImage::from_fn(2, 1, |x, _| image::Rgba([x as u8, 0, 0, 255]));
+        let tiled = tile(img, (3, 2)).unwrap();
+        let reds: Vec<u8> = tiled.chunks_exact(4).map(|p| p[2]).collect();
+        assert_eq!(reds, [0, 1, 0, 0, 1, 0]);
+    }
+
+    #[test]
+    fn stretching_should_not_keep_the_aspect_ratio() {
+        // a 2x1 image, with a red pixel on the left and a blue one on the right
+        let img = image::RgbaImage::from_fn(2, 1, |x, _| {
+            image::Rgba(if x == 0 {
+                [255, 0, 0, 255]
+            } else {
+                [0, 0, 255, 255]
+            })
+        });
+        let stretched = stretch(img.clone(), (2, 2), FilterType::Box).unwrap();
+        for row in stretched.chunks_exact(8) {
+            assert_eq!(row, [0, 0, 255, 255, 255, 0, 0, 255]);
+        }
+        // cropping keeps only the middle of the image instead
+        let cropped = resize(img, (2, 2), FilterType::Box).unwrap();
+        assert_ne!(cropped, stretched);
+    }
+
+    #[test]
+    fn dominant_color_should_be_the_most_common_one() {
+        let mut img = [10, 200, 30, 255].repeat(5);
+        img.extend([250, 0, 0, 255].repeat(3));
+        img.extend([0, 0, 0, 0].repeat(10));
+        assert_eq!(dominant_color(&img), [10, 200, 30, 255]);
+        assert_eq!(dominant_color(&[]), [0, 0, 0, 255]);
+    }
+}
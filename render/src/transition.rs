@@ -0,0 +1,866 @@
+//! Transitions between two images of the same size.
+//!
+//! [`Transition`] is what users choose (and what `swww` sends through its socket), and [`Runner`]
+//! plays it, handing every intermediate frame to a `present` callback, as a [`ReadiedPack`] with
+//! the difference from the previous one. How the frames reach the screen is up to the caller.
+use std::time::{Duration, Instant};
+
+use log::debug;
+
+use crate::comp_decomp::ReadiedPack;
+pub use swww_protocol::transition::{Coord, Position, Transition, TransitionSync, TransitionType};
+
+use keyframe::{
+    functions::BezierCurve, keyframes, mint::Vector2, num_traits::Pow, AnimationSequence,
+};
+
+/// Receives every frame of a transition, along with how long until it is due. Returns whether the
+/// transition should stop
+pub type Present<'a> = dyn FnMut(ReadiedPack, Duration) -> bool + 'a;
+
+/// Draws the frames of [`TransitionType::Plugin`]: given the old and the new images (in bgra),
+/// and how far along the transition is (from 0 to 1, after the bezier curve), fills the last
+/// argument with the frame
+pub type PluginFrame = dyn Fn(&[u8], &[u8], f32, &mut [u8]) + Send + Sync;
+
+macro_rules! send_transition_frame {
+    ($img:ident, $now:ident, $fps:ident, $present:ident) => {
+        if $img.is_empty() {
+            debug!("Transition has finished.");
+            return;
+        }
+        let timeout = $fps.saturating_sub($now.elapsed());
+        if $present($img, timeout) {
+            debug!("Transition was interrupted!");
+            return;
+        }
+    };
+}
+
+/// Where an output sits in the area covered by all the outputs taking part in a transition. Both
+/// `offset` and `canvas` are in logical coordinates, so that every output draws the same shapes no
+/// matter its scale, and `offset` has its y axis growing upwards, like the pixel coordinates we use
+/// in the transitions below
+#[derive(Clone, Copy)]
+pub struct Layout {
+    pub offset: (u32, u32),
+    pub canvas: (u32, u32),
+    /// How many of the output's pixels there are in a logical one, in each axis
+    pub scale: (f64, f64),
+}
+
+/// Plays a [`Transition`] from `old_img` into a new image
+pub struct Runner {
+    old_img: Box<[u8]>,
+    dimensions: (u32, u32),
+    transition_type: TransitionType,
+    duration: f32,
+    step: u8,
+    fps: Duration,
+    angle: f64,
+    pos: Position,
+    bezier: BezierCurve,
+    wave: (f32, f32),
+    skip_threshold: f32,
+    layout: Option<Layout>,
+    plugin: Option<Box<PluginFrame>>,
+}
+
+impl Runner {
+    /// `old_img` is in bgra, with `dimensions`. `layout` is only needed for
+    /// [`TransitionSync::Layout`]
+    pub fn new(
+        old_img: Box<[u8]>,
+        dimensions: (u32, u32),
+        transition: Transition,
+        layout: Option<Layout>,
+    ) -> Self {
+        Runner {
+            old_img,
+            dimensions,
+            transition_type: transition.transition_type,
+            duration: transition.duration,
+            // zeros would never finish, or divide by zero
+            step: transition.step.max(1),
+            fps: Duration::from_nanos(1_000_000_000 / transition.fps.max(1) as u64),
+            angle: transition.angle,
+            pos: transition.pos,
+            bezier: BezierCurve::from(
+                Vector2 {
+                    x: transition.bezier.0,
+                    y: transition.bezier.1,
+                },
+                Vector2 {
+                    x: transition.bezier.2,
+                    y: transition.bezier.3,
+                },
+            ),
+            wave: transition.wave,
+            skip_threshold: transition.skip_threshold,
+            layout,
+            plugin: None,
+        }
+    }
+
+    /// Draws [`TransitionType::Plugin`] transitions with `plugin`. Without one, they are drawn as a
+    /// fade
+    pub fn with_plugin(mut self, plugin: Box<PluginFrame>) -> Self {
+        self.plugin = Some(plugin);
+        self
+    }
+
+    /// Returns the dimensions of the area the transition is happening in
+    fn canvas(&self) -> (u32, u32) {
+        match self.layout {
+            Some(Layout { canvas, .. }) => canvas,
+            None => self.dimensions,
+        }
+    }
+
+    /// Where the `i`th pixel of the output is in the canvas, with the y axis growing upwards
+    fn to_canvas(&self) -> impl Fn(usize) -> (f64, f64) {
+        let (width, height) = (self.dimensions.0 as usize, self.dimensions.1 as usize);
+        let ((offset_x, offset_y), (scale_x, scale_y)) = match self.layout {
+            Some(Layout { offset, scale, .. }) => ((offset.0 as f64, offset.1 as f64), scale),
+            None => ((0.0, 0.0), (1.0, 1.0)),
+        };
+        move |i| {
+            (
+                (i % width) as f64 / scale_x + offset_x,
+                (height - i / width) as f64 / scale_y + offset_y,
+            )
+        }
+    }
+
+    /// Estimates how many frames the transition will send, and how long it will take to send them
+    pub fn estimate(&self, new_img: &[u8]) -> (u32, Duration) {
+        if self.is_imperceptible(new_img) {
+            return (1, self.fps);
+        }
+        let frames = match self.transition_type {
+            TransitionType::Simple => self.fade_frames(&self.old_img, new_img),
+            TransitionType::Flash(color) => {
+                let flash_img = flash_img(color, new_img);
+                self.fade_frames(&self.old_img, &flash_img) + self.fade_frames(&flash_img, new_img)
+            }
+            // these run for `duration`, and then send a last frame with whatever is left
+            _ => (self.duration * self.fps_value() as f32).ceil() as u32 + 1,
+        };
+        (frames, self.fps * frames)
+    }
+
+    /// Every frame of a fade moves the colors by `step`, so the largest difference dominates
+    fn fade_frames(&self, from: &[u8], to: &[u8]) -> u32 {
+        let max_diff = from
+            .iter()
+            .zip(to)
+            .map(|(from, to)| from.abs_diff(*to))
+            .max()
+            .unwrap_or(0);
+        (max_diff as u32 + self.step as u32 - 1) / self.step.max(1) as u32
+    }
+
+    fn fps_value(&self) -> u32 {
+        (1_000_000_000 / self.fps.as_nanos().max(1)) as u32
+    }
+
+    /// Plays the transition into `new_img`, blocking until it is done or `present` stops it.
+    /// Applying every frame given to `present` to `old_img` ends with `new_img` (ignoring alpha)
+    pub fn execute(mut self, new_img: &[u8], present: &mut Present) {
+        if self.is_imperceptible(new_img) {
+            debug!("Images are too close to transition between, swapping them");
+            self.step = 255;
+            return self.simple(new_img, present);
+        }
+        debug!("Starting transition");
+        match self.transition_type {
+            TransitionType::Simple => self.simple(new_img, present),
+            TransitionType::Wipe => self.wipe(new_img, present),
+            TransitionType::Grow => self.grow(new_img, present),
+            TransitionType::Outer => self.outer(new_img, present),
+            TransitionType::Wave => self.wave(new_img, present),
+            TransitionType::Flash(color) => self.flash(color, new_img, present),
+            TransitionType::Slide => self.slide(new_img, present),
+            TransitionType::Plugin(_) if self.plugin.is_some() => self.plugin(new_img, present),
+            TransitionType::Plugin(ref name) => {
+                debug!("No plugin was given for {name}, fading instead");
+                self.simple(new_img, present)
+            }
+        }
+    }
+
+    /// Whether the average difference between the colors of `old_img` and `new_img` is below
+    /// `skip_threshold`, in percent of the whole range. This way, a few pixels changing a lot (like
+    /// the digits of a clock) count as little as the whole image changing a tiny bit, which are
+    /// both not worth a transition
+    fn is_imperceptible(&self, new_img: &[u8]) -> bool {
+        if self.skip_threshold <= 0.0 || new_img.is_empty() {
+            return false;
+        }
+        let total: u64 = self
+            .old_img
+            .chunks_exact(4)
+            .zip(new_img.chunks_exact(4))
+            .map(|(old, new)| (0..3).map(|i| old[i].abs_diff(new[i]) as u64).sum::<u64>())
+            .sum();
+        let colors = new_img.len() / 4 * 3;
+        let average = total as f64 / colors as f64 / 255.0 * 100.0;
+        average < self.skip_threshold as f64
+    }
+
+    /// The frame `progress` (from 0 to 1) of the way through the transition into `new_img`, for
+    /// transitions driven by something other than time, like `swww transition set`. Fades blend
+    /// the images, and the other transitions show each pixel of either one, so the step and the
+    /// bezier curve don't apply
+    pub fn frame_at(&self, new_img: &[u8], progress: f32) -> Vec<u8> {
+        // the shapes below may leave a pixel or two on the edges of the canvas at the ends
+        if progress <= 0.0 {
+            return self.old_img.to_vec();
+        } else if progress >= 1.0 {
+            return new_img.to_vec();
+        }
+        match &self.transition_type {
+            TransitionType::Simple => blend(&self.old_img, new_img, progress),
+            TransitionType::Plugin(_) => match &self.plugin {
+                Some(plugin) => {
+                    let mut frame = vec![0; new_img.len()];
+                    plugin(&self.old_img, new_img, progress, &mut frame);
+                    frame
+                }
+                None => blend(&self.old_img, new_img, progress),
+            },
+            TransitionType::Flash(color) => {
+                let flash_img = flash_img(*color, new_img);
+                if progress < 0.5 {
+                    blend(&self.old_img, &flash_img, progress * 2.0)
+                } else {
+                    blend(&flash_img, new_img, progress * 2.0 - 1.0)
+                }
+            }
+            TransitionType::Slide => {
+                let source = self.slide_source(1.0 - progress as f64);
+                let mut frame = self.old_img.to_vec();
+                for (i, pix) in frame.chunks_exact_mut(4).enumerate() {
+                    if let Some(j) = source(i) {
+                        pix.copy_from_slice(&new_img[j..j + 4]);
+                    }
+                }
+                frame
+            }
+            _ => {
+                let covered = self.covered(progress as f64);
+                let to_canvas = self.to_canvas();
+                let mut frame = self.old_img.to_vec();
+                for (i, (pix, new_pix)) in frame
+                    .chunks_exact_mut(4)
+                    .zip(new_img.chunks_exact(4))
+                    .enumerate()
+                {
+                    let (x, y) = to_canvas(i);
+                    if covered(x, y) {
+                        pix.copy_from_slice(new_pix);
+                    }
+                }
+                frame
+            }
+        }
+    }
+
+    /// Whether the pixel at (x, y) of the canvas shows the new image, `progress` of the way through
+    /// the wipe, wave, grow or outer transitions. These are the same lines and circles the
+    /// transitions below sweep across the canvas
+    fn covered(&self, progress: f64) -> Box<dyn Fn(f64, f64) -> bool> {
+        let (canvas_w, canvas_h) = self.canvas();
+        let center = ((canvas_w / 2) as f64, (canvas_h / 2) as f64);
+        let radius = ((canvas_w.pow(2) + canvas_h.pow(2)) as f64).sqrt() / 2.0;
+        let angle = self.angle.to_radians();
+        match self.transition_type {
+            TransitionType::Wave => {
+                let (scale_x, scale_y) = (self.wave.0 as f64, self.wave.1 as f64);
+                let is_low = move |x: f64, y: f64, offset: f64| {
+                    let (x, y) = (x - center.0, y - center.1);
+                    let lhs = y * angle.cos() - x * angle.sin();
+                    let wave = ((x * angle.cos() + y * angle.sin()) / scale_x).sin() * scale_y;
+                    lhs >= wave + radius - offset
+                };
+                let mut start = 0.0;
+                for x in 0..canvas_w {
+                    if (0..canvas_h).any(|y| is_low(x as f64, y as f64, start)) {
+                        start += 1.0;
+                    }
+                }
+                let offset = start + (2.0 * radius - 2.0 * start) * progress;
+                Box::new(move |x, y| is_low(x, y, offset))
+            }
+            TransitionType::Grow | TransitionType::Outer => {
+                let (center_x, center_y) = self.pos.to_pixel((canvas_w, canvas_h));
+                let (center_x, center_y) = (center_x as f64, center_y as f64);
+                let far_x = center_x.max(canvas_w as f64 - 1.0 - center_x);
+                let far_y = center_y.max(canvas_h as f64 - 1.0 - center_y);
+                let far = (far_x.powi(2) + far_y.powi(2)).sqrt();
+                let grow = matches!(self.transition_type, TransitionType::Grow);
+                let dist = if grow {
+                    far * progress
+                } else {
+                    far * (1.0 - progress)
+                };
+                Box::new(move |x, y| {
+                    let pix_dist = ((x - center_x).powi(2) + (y - center_y).powi(2)).sqrt();
+                    if grow {
+                        pix_dist <= dist
+                    } else {
+                        pix_dist >= dist
+                    }
+                })
+            }
+            _ => {
+                let offset = radius.powi(2) * 2.0 * progress;
+                let (a, b) = (radius * angle.cos(), radius * angle.sin());
+                Box::new(move |x, y| {
+                    (x - center.0) * a + (y - center.1) * b + offset >= radius.powi(2)
+                })
+            }
+        }
+    }
+
+    /// Where in the new image each pixel of the output comes from, when the slide is `left` (from
+    /// 1 to 0) of the way from its end. `None` for the pixels still showing the old image
+    fn slide_source(&self, left: f64) -> impl Fn(usize) -> Option<usize> {
+        let (width, height) = (self.dimensions.0 as f64, self.dimensions.1 as f64);
+        // same as wipe's angle: '0' moves from right to left, and '90' from top to bottom, in
+        // coordinates where y grows downwards
+        let angle = self.angle.to_radians();
+        let (dir_x, dir_y) = (-angle.cos(), angle.sin());
+        // how far the new image has to travel to cover the whole output
+        let distance = dir_x.abs() * width + dir_y.abs() * height;
+        let (shift_x, shift_y) = (dir_x * distance * left, dir_y * distance * left);
+        move |i| {
+            let x = (i as f64 % width) + shift_x;
+            let y = (i as f64 / width).floor() + shift_y;
+            (x >= 0.0 && y >= 0.0 && x < width && y < height)
+                .then(|| (y as usize * width as usize + x as usize) * 4)
+        }
+    }
+
+    fn bezier_seq(&self, start: f32, end: f32) -> (AnimationSequence<f32>, Instant) {
+        (
+            keyframes![(start, 0.0, self.bezier), (end, self.duration, self.bezier)],
+            Instant::now(),
+        )
+    }
+
+    fn simple(mut self, new_img: &[u8], present: &mut Present) {
+        let fps = self.fps;
+        let mut now = Instant::now();
+        loop {
+            let transition_img =
+                ReadiedPack::new(&mut self.old_img, new_img, |old_pix, new_pix, _| {
+                    change_cols(self.step, old_pix, *new_pix);
+                });
+            send_transition_frame!(transition_img, now, fps, present);
+            now = Instant::now();
+        }
+    }
+
+    fn flash(mut self, color: [u8; 3], new_img: &[u8], present: &mut Present) {
+        let fps = self.fps;
+        let flash_img = flash_img(color, new_img);
+        let mut now = Instant::now();
+        loop {
+            let transition_img =
+                ReadiedPack::new(&mut self.old_img, &flash_img, |old_pix, new_pix, _| {
+                    change_cols(self.step, old_pix, *new_pix);
+                });
+            // we reached the color, so we carry on into the new image
+            if transition_img.is_empty() {
+                break;
+            }
+            if present(transition_img, fps.saturating_sub(now.elapsed())) {
+                debug!("Transition was interrupted!");
+                return;
+            }
+            now = Instant::now();
+        }
+        self.simple(new_img, present)
+    }
+
+    fn wave(mut self, new_img: &[u8], present: &mut Present) {
+        let fps = self.fps;
+        let (canvas_w, canvas_h) = self.canvas();
+        let to_canvas = self.to_canvas();
+        let mut now = Instant::now();
+        let center = (canvas_w / 2, canvas_h / 2);
+        let screen_diag = ((canvas_w.pow(2) + canvas_h.pow(2)) as f64).sqrt();
+
+        let angle = self.angle.to_radians();
+        let (scale_x, scale_y) = (self.wave.0 as f64, self.wave.1 as f64);
+
+        let circle_radius = screen_diag / 2.0;
+
+        let f = |x: f64| (x / scale_x).sin() * scale_y;
+
+        // graph: https://www.desmos.com/calculator/wunde042es
+        //
+        // checks if a pixel is to the left or right of the line
+        let is_low = |x: f64, y: f64, offset: f64| {
+            let x = x - center.0 as f64;
+            let y = y - center.1 as f64;
+
+            let lhs = y * angle.cos() - x * angle.sin();
+            let rhs = f(x * angle.cos() + y * angle.sin()) + circle_radius - offset;
+            lhs >= rhs
+        };
+
+        // find the offset to start the transition at
+        let mut offset = {
+            let mut offset = 0.0;
+            for x in 0..canvas_w {
+                for y in 0..canvas_h {
+                    if is_low(x as f64, y as f64, offset) {
+                        offset += 1.0;
+                        break;
+                    }
+                }
+            }
+            offset
+        };
+        let max_offset = 2.0 * circle_radius - offset;
+
+        let (mut seq, start) = self.bezier_seq(offset as f32, max_offset as f32);
+
+        let step = self.step;
+
+        loop {
+            let transition_img =
+                ReadiedPack::new(&mut self.old_img, new_img, |old_pix, new_pix, i| {
+                    let (x, y) = to_canvas(i);
+                    if is_low(x, y, offset) {
+                        change_cols(step, old_pix, *new_pix);
+                    }
+                });
+            send_transition_frame!(transition_img, now, fps, present);
+            now = Instant::now();
+
+            offset = seq.now() as f64;
+            seq.advance_to(start.elapsed().as_secs_f64());
+            if start.elapsed().as_secs_f64() >= seq.duration() {
+                break;
+            }
+        }
+        self.step = 255;
+        self.simple(new_img, present)
+    }
+
+    fn wipe(mut self, new_img: &[u8], present: &mut Present) {
+        let fps = self.fps;
+        let (canvas_w, canvas_h) = self.canvas();
+        let to_canvas = self.to_canvas();
+        let mut now = Instant::now();
+        let center = (canvas_w / 2, canvas_h / 2);
+        let screen_diag = ((canvas_w.pow(2) + canvas_h.pow(2)) as f64).sqrt();
+
+        let circle_radius = screen_diag / 2.0;
+        let max_offset = circle_radius.pow(2) * 2.0;
+
+        let angle = self.angle.to_radians();
+
+        let mut offset = {
+            let (x, y) = angle.sin_cos();
+            (x.abs() * canvas_w as f64 / 2.0 + y.abs() * canvas_h as f64 / 2.0).abs()
+        };
+
+        // line formula: (x-h)*a + (y-k)*b + C = r^2
+        // https://www.desmos.com/calculator/vpvzk12yar
+        //
+        // checks if a pixel is to the left or right of the line
+        let is_low = |pix_x: f64, pix_y: f64, offset: f64, radius: f64| {
+            let a = radius * angle.cos();
+            let b = radius * angle.sin();
+            let x = pix_x - center.0 as f64;
+            let y = pix_y - center.1 as f64;
+            let res = x * a + y * b + offset;
+            res >= radius.pow(2)
+        };
+
+        let (mut seq, start) = self.bezier_seq(0.0, max_offset as f32);
+
+        let step = self.step;
+
+        loop {
+            let transition_img =
+                ReadiedPack::new(&mut self.old_img, new_img, |old_pix, new_pix, i| {
+                    let (x, y) = to_canvas(i);
+                    if is_low(x, y, offset, circle_radius) {
+                        change_cols(step, old_pix, *new_pix);
+                    }
+                });
+            send_transition_frame!(transition_img, now, fps, present);
+            now = Instant::now();
+
+            offset = seq.now() as f64;
+            seq.advance_to(start.elapsed().as_secs_f64());
+            if start.elapsed().as_secs_f64() >= seq.duration() {
+                break;
+            }
+        }
+        self.step = 255;
+        self.simple(new_img, present)
+    }
+
+    /// Unlike the other transitions, this moves the new image's pixels, so outputs in a layout
+    /// slide on their own: we don't have their neighbours' images to slide in from
+    fn slide(mut self, new_img: &[u8], present: &mut Present) {
+        let fps = self.fps;
+        let mut now = Instant::now();
+        let (mut seq, start) = self.bezier_seq(1.0, 0.0);
+        let mut left = 1.0;
+        loop {
+            let source = self.slide_source(left);
+            let transition_img = ReadiedPack::new(&mut self.old_img, new_img, |old_pix, _, i| {
+                // the rest of the output still shows the old image
+                if let Some(j) = source(i) {
+                    old_pix.copy_from_slice(&new_img[j..j + 4]);
+                }
+            });
+            send_transition_frame!(transition_img, now, fps, present);
+            now = Instant::now();
+
+            left = seq.now() as f64;
+            seq.advance_to(start.elapsed().as_secs_f64());
+            if start.elapsed().as_secs_f64() >= seq.duration() {
+                break;
+            }
+        }
+        self.step = 255;
+        self.simple(new_img, present)
+    }
+
+    /// Has the plugin draw every frame, from the images we started with, and moves the colors of
+    /// whatever it left behind into the new image at the end
+    fn plugin(mut self, new_img: &[u8], present: &mut Present) {
+        let fps = self.fps;
+        let mut now = Instant::now();
+        let (mut seq, start) = self.bezier_seq(0.0, 1.0);
+        let old_img = self.old_img.clone();
+        let mut frame = vec![0; new_img.len()];
+        loop {
+            if let Some(plugin) = &self.plugin {
+                plugin(&old_img, new_img, seq.now(), &mut frame);
+            }
+            let transition_img =
+                ReadiedPack::new(&mut self.old_img, &frame, |old_pix, new_pix, _| {
+                    *old_pix = *new_pix;
+                });
+            let timeout = fps.saturating_sub(now.elapsed());
+            // the plugin may hold still for a while, which doesn't mean it is done
+            if transition_img.is_empty() {
+                std::thread::sleep(timeout);
+            } else if present(transition_img, timeout) {
+                debug!("Transition was interrupted!");
+                return;
+            }
+            now = Instant::now();
+
+            seq.advance_to(start.elapsed().as_secs_f64());
+            if start.elapsed().as_secs_f64() >= seq.duration() {
+                break;
+            }
+        }
+        self.step = 255;
+        self.simple(new_img, present)
+    }
+
+    fn grow(mut self, new_img: &[u8], present: &mut Present) {
+        let fps = self.fps;
+        let canvas = self.canvas();
+        let to_canvas = self.to_canvas();
+        let (width, height) = (canvas.0 as f32, canvas.1 as f32);
+        let (center_x, center_y) = self.pos.to_pixel(canvas);
+        let mut dist_center: f32 = 0.0;
+        let dist_end: f32 = {
+            let mut x = center_x;
+            let mut y = center_y;
+            if x < width / 2.0 {
+                x = width - 1.0 - x;
+            }
+            if y < height / 2.0 {
+                y = height - 1.0 - y;
+            }
+            f32::sqrt(x.pow(2) + y.pow(2))
+        };
+        let mut now = Instant::now();
+
+        let (mut seq, start) = self.bezier_seq(0.0, dist_end);
+
+        loop {
+            let transition_img =
+                ReadiedPack::new(&mut self.old_img, new_img, |old_pix, new_pix, i| {
+                    let (x, y) = to_canvas(i);
+                    let diff_x = (x as f32 - center_x).abs();
+                    let diff_y = (y as f32 - center_y).abs();
+                    let pix_center_dist = f32::sqrt(diff_x.pow(2) + diff_y.pow(2));
+                    if pix_center_dist <= dist_center {
+                        let step = self
+                            .step
+                            .saturating_add((dist_center - pix_center_dist).log2() as u8);
+                        change_cols(step, old_pix, *new_pix);
+                    }
+                });
+            send_transition_frame!(transition_img, now, fps, present);
+            now = Instant::now();
+            dist_center = seq.now();
+            seq.advance_to(start.elapsed().as_secs_f64());
+            if start.elapsed().as_secs_f64() >= seq.duration() {
+                break;
+            }
+        }
+        self.step = 255;
+        self.simple(new_img, present)
+    }
+
+    fn outer(mut self, new_img: &[u8], present: &mut Present) {
+        let fps = self.fps;
+        let canvas = self.canvas();
+        let to_canvas = self.to_canvas();
+        let (width, height) = (canvas.0 as f32, canvas.1 as f32);
+        let (center_x, center_y) = self.pos.to_pixel(canvas);
+        let mut dist_center = {
+            let mut x = center_x;
+            let mut y = center_y;
+            if x < width / 2.0 {
+                x = width - 1.0 - x;
+            }
+            if y < height / 2.0 {
+                y = height - 1.0 - y;
+            }
+            f32::sqrt(x.pow(2) + y.pow(2))
+        };
+        let mut now = Instant::now();
+
+        let (mut seq, start) = self.bezier_seq(dist_center, 0.0);
+
+        loop {
+            let transition_img =
+                ReadiedPack::new(&mut self.old_img, new_img, |old_pix, new_pix, i| {
+                    let (x, y) = to_canvas(i);
+                    let diff_x = (x as f32 - center_x).abs();
+                    let diff_y = (y as f32 - center_y).abs();
+                    let pix_center_dist = f32::sqrt(diff_x.pow(2) + diff_y.pow(2));
+                    if pix_center_dist >= dist_center {
+                        let step = self
+                            .step
+                            .saturating_add((pix_center_dist - dist_center).log2() as u8);
+                        change_cols(step, old_pix, *new_pix);
+                    }
+                });
+            send_transition_frame!(transition_img, now, fps, present);
+            now = Instant::now();
+
+            dist_center = seq.now();
+            seq.advance_to(start.elapsed().as_secs_f64());
+
+            if start.elapsed().as_secs_f64() >= seq.duration() {
+                break;
+            }
+        }
+        self.step = 255;
+        self.simple(new_img, present)
+    }
+}
+
+/// `from` moved `amount` (from 0 to 1) of the way to `to`
+pub fn blend(from: &[u8], to: &[u8], amount: f32) -> Vec<u8> {
+    from.iter()
+        .zip(to)
+        .map(|(from, to)| (*from as f32 + (*to as f32 - *from as f32) * amount).round() as u8)
+        .collect()
+}
+
+/// `color`, in bgra, with the alpha of `new_img`, so that fading out of it only changes the colors
+fn flash_img(color: [u8; 3], new_img: &[u8]) -> Vec<u8> {
+    let [r, g, b] = color;
+    new_img
+        .chunks_exact(4)
+        .flat_map(|pix| [b, g, r, pix[3]])
+        .collect()
+}
+
+fn change_cols(step: u8, old: &mut [u8; 4], new: [u8; 4]) {
+    for (old_col, new_col) in old.iter_mut().zip(new) {
+        if old_col.abs_diff(new_col) < step {
+            *old_col = new_col;
+        } else if *old_col > new_col {
+            *old_col -= step;
+        } else {
+            *old_col += step;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn make_test_boxes() -> (Box<[u8]>, Box<[u8]>) {
+        let mut vec1 = Vec::with_capacity(4000);
+        let mut vec2 = Vec::with_capacity(4000);
+
+        for _ in 0..4000 {
+            vec1.push(rand::random());
+            vec2.push(rand::random());
+        }
+
+        (vec1.into_boxed_slice(), vec2.into_boxed_slice())
+    }
+
+    fn test_transition(old_img: Box<[u8]>, transition_type: TransitionType) -> Runner {
+        Runner {
+            old_img,
+            transition_type,
+            dimensions: (100, 10),
+            duration: 2.0,
+            step: 100,
+            fps: Duration::from_nanos(1),
+            angle: 0.0,
+            pos: Position::new(Coord::Percent(0.0), Coord::Percent(0.0)),
+            bezier: BezierCurve::from(Vector2 { x: 1.0, y: 0.0 }, Vector2 { x: 0.0, y: 1.0 }),
+            wave: (20.0, 20.0),
+            skip_threshold: 0.0,
+            layout: None,
+            plugin: None,
+        }
+    }
+
+    #[test]
+    fn should_estimate_transitions() {
+        let old_img = vec![0; 400].into_boxed_slice();
+        let new_img = vec![250; 400];
+
+        let t = test_transition(old_img.clone(), TransitionType::Simple);
+        assert_eq!(t.estimate(&new_img).0, 3);
+        assert_eq!(t.estimate(&old_img).0, 0);
+
+        // up to white, and then back down
+        let t = test_transition(old_img.clone(), TransitionType::Flash([255, 255, 255]));
+        assert_eq!(t.estimate(&old_img).0, 6);
+
+        let mut t = test_transition(old_img, TransitionType::Wipe);
+        t.fps = Duration::from_nanos(1_000_000_000 / 30);
+        let (frames, duration) = t.estimate(&new_img);
+        assert_eq!(frames, 61);
+        assert!(duration.as_secs_f32() > 2.0 && duration.as_secs_f32() < 2.1);
+    }
+
+    #[test]
+    fn should_swap_imperceptibly_different_images() {
+        let old_img = vec![0; 400].into_boxed_slice();
+        // a tenth of the pixels change completely, which is 10% on average
+        let mut new_img = vec![0; 400];
+        new_img[..40].fill(255);
+
+        let mut t = test_transition(old_img, TransitionType::Wipe);
+        assert!(!t.is_imperceptible(&new_img));
+        t.skip_threshold = 10.5;
+        assert!(t.is_imperceptible(&new_img));
+        assert_eq!(t.estimate(&new_img).0, 1);
+        let mut frames = 0;
+        t.execute(&new_img, &mut |_, _| {
+            frames += 1;
+            false
+        });
+        assert_eq!(frames, 1);
+    }
+
+    #[test]
+    fn transitions_should_end_with_equal_vectors() {
+        use TransitionType as TT;
+        let transitions = [
+            TT::Simple,
+            TT::Wipe,
+            TT::Outer,
+            TT::Grow,
+            TT::Wave,
+            TT::Flash([255, 255, 255]),
+            TT::Slide,
+            TT::Plugin("invert".to_string()),
+        ];
+        // the second one pretends the output is in the middle of a 3x3 grid of identical outputs
+        let layouts = [
+            None,
+            Some(Layout {
+                offset: (100, 10),
+                canvas: (300, 30),
+                scale: (1.0, 1.0),
+            }),
+            // and this one, at twice the scale of its neighbours
+            Some(Layout {
+                offset: (50, 5),
+                canvas: (150, 15),
+                scale: (2.0, 2.0),
+            }),
+        ];
+        for (transition, layout) in transitions
+            .into_iter()
+            .flat_map(|t| layouts.into_iter().map(move |l| (t.clone(), l)))
+        {
+            let (old_img, new_img) = make_test_boxes();
+            let mut transition_img = old_img.clone();
+            let mut t = test_transition(old_img, transition.clone());
+            t.layout = layout;
+            if let TT::Plugin(_) = transition {
+                // whatever the plugin leaves on screen, we still end up in the new image
+                t = t.with_plugin(Box::new(|old, _, _, frame| {
+                    for (pix, old) in frame.iter_mut().zip(old) {
+                        *pix = !old;
+                    }
+                }));
+            }
+
+            t.execute(&new_img, &mut |frame, _| {
+                frame.unpack(&mut transition_img);
+                false
+            });
+
+            for (tpix, npix) in transition_img.chunks_exact(4).zip(new_img.chunks_exact(4)) {
+                assert_eq!(
+                    tpix[0..3],
+                    npix[0..3],
+                    "Transition {transition:?} did not end with correct new_img (layout: {})",
+                    layout.is_some()
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn frames_at_the_ends_should_be_the_images() {
+        use TransitionType as TT;
+        let (old_img, new_img) = make_test_boxes();
+        for transition in [
+            TT::Simple,
+            TT::Wipe,
+            TT::Outer,
+            TT::Grow,
+            TT::Wave,
+            TT::Flash([255, 255, 255]),
+            TT::Slide,
+        ] {
+            let t = test_transition(old_img.clone(), transition.clone());
+            assert_eq!(t.frame_at(&new_img, 0.0), *old_img, "{transition:?} at 0");
+            assert_eq!(t.frame_at(&new_img, 1.0), *new_img, "{transition:?} at 1");
+        }
+    }
+
+    #[test]
+    fn plugins_should_draw_the_frames() {
+        let (old_img, new_img) = make_test_boxes();
+        let t = test_transition(old_img.clone(), TransitionType::Plugin("gray".to_string()));
+        // without the plugin, it fades
+        assert_eq!(t.frame_at(&new_img, 0.5), blend(&old_img, &new_img, 0.5));
+
+        let t = t.with_plugin(Box::new(|_, _, progress, frame| {
+            frame.fill((progress * 100.0) as u8);
+        }));
+        assert_eq!(t.frame_at(&new_img, 0.5), vec![50; new_img.len()]);
+    }
+}
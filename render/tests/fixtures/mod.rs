@@ -0,0 +1,158 @@
+//! Inputs for the golden-image tests, generated in memory so that they are the same everywhere,
+//! and the helpers to compare outputs against the fingerprints checked into `tests/golden`
+use std::path::PathBuf;
+
+use image::{Delay, Frame, RgbaImage};
+
+/// Set this to regenerate the fingerprints, after a change that is meant to change how things look
+const BLESS_VAR: &str = "SWWW_BLESS";
+
+/// How many blocks each side of an image is split into when fingerprinting it
+const GRID: u32 = 8;
+
+/// How much each value of a fingerprint may differ from the checked-in one. This absorbs rounding
+/// differences (e.g. between SIMD and scalar code), but not actual changes in what is drawn
+const TOLERANCE: u8 = 2;
+
+/// A diagonal gradient, with every channel going a different way
+pub fn gradient(dim: (u32, u32)) -> RgbaImage {
+    RgbaImage::from_fn(dim.0, dim.1, |x, y| {
+        let (fx, fy) = (x as f32 / dim.0 as f32, y as f32 / dim.1 as f32);
+        image::Rgba([
+            (fx * 255.0) as u8,
+            (fy * 255.0) as u8,
+            ((1.0 - (fx + fy) / 2.0) * 255.0) as u8,
+            255,
+        ])
+    })
+}
+
+/// Alternating black and white squares of `cell` pixels, with a red one in the top left corner,
+/// so that flips and rotations show up
+pub fn checkerboard(dim: (u32, u32), cell: u32) -> RgbaImage {
+    RgbaImage::from_fn(dim.0, dim.1, |x, y| {
+        let (cx, cy) = (x / cell, y / cell);
+        if cx == 0 && cy == 0 {
+            image::Rgba([255, 0, 0, 255])
+        } else if (cx + cy) % 2 == 0 {
+            image::Rgba([255, 255, 255, 255])
+        } else {
+            image::Rgba([0, 0, 0, 255])
+        }
+    })
+}
+
+/// Converts `img` to bgra, which is what the pipelines that don't take an `RgbaImage` work with
+pub fn bgra(img: RgbaImage) -> Vec<u8> {
+    let mut bgra = img.into_raw();
+    for pixel in bgra.chunks_exact_mut(4) {
+        pixel.swap(0, 2);
+    }
+    bgra
+}
+
+/// The color channels of `img`, in bgra. Alpha is ignored when drawing, so neither transitions nor
+/// animations keep it
+pub fn colors(img: &[u8]) -> Vec<u8> {
+    img.chunks_exact(4)
+        .flat_map(|pixel| &pixel[0..3])
+        .copied()
+        .collect()
+}
+
+/// A gif of `count` frames of a square moving across a gradient, 100ms each
+pub fn gif(dim: (u32, u32), count: u32) -> Vec<u8> {
+    let side = dim.0.min(dim.1) / 4;
+    let frames = (0..count).map(|i| {
+        let mut img = gradient(dim);
+        let x = (dim.0 - side) * i / count.max(2).saturating_sub(1);
+        for y in (dim.1 - side) / 2..(dim.1 + side) / 2 {
+            for x in x..x + side {
+                img.put_pixel(x, y, image::Rgba([255, 255, 0, 255]));
+            }
+        }
+        Frame::from_parts(img, 0, 0, Delay::from_numer_denom_ms(100, 1))
+    });
+
+    let mut gif = Vec::new();
+    image::codecs::gif::GifEncoder::new(&mut gif)
+        .encode_frames(frames)
+        .expect("failed to encode the fixture gif");
+    gif
+}
+
+/// The average of every color channel (in bgra order) in each block of a `GRID` x `GRID` grid
+/// over `img`. Unlike a hash of the bytes, this only changes a little when the image does
+pub fn fingerprint(img: &[u8], dim: (u32, u32)) -> Vec<u8> {
+    assert_eq!(
+        img.len(),
+        dim.0 as usize * dim.1 as usize * 4,
+        "image doesn't match its size"
+    );
+    let (grid_w, grid_h) = (GRID.min(dim.0), GRID.min(dim.1));
+    let mut fingerprint = Vec::with_capacity((grid_w * grid_h * 3) as usize);
+    for by in 0..grid_h {
+        for bx in 0..grid_w {
+            let (x0, x1) = (bx * dim.0 / grid_w, (bx + 1) * dim.0 / grid_w);
+            let (y0, y1) = (by * dim.1 / grid_h, (by + 1) * dim.1 / grid_h);
+            let mut sums = [0u64; 3];
+            for y in y0..y1 {
+                for x in x0..x1 {
+                    let i = (y as usize * dim.0 as usize + x as usize) * 4;
+                    for (sum, channel) in sums.iter_mut().zip(&img[i..i + 3]) {
+                        *sum += *channel as u64;
+                    }
+                }
+            }
+            let count = ((x1 - x0) * (y1 - y0)) as u64;
+            fingerprint.extend(sums.iter().map(|sum| ((sum + count / 2) / count) as u8));
+        }
+    }
+    fingerprint
+}
+
+/// Compares `img` against the fingerprint in `tests/golden/{name}.txt`, or writes it there if
+/// `SWWW_BLESS` is set
+#[track_caller]
+pub fn assert_golden(name: &str, img: &[u8], dim: (u32, u32)) {
+    let path = PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+        .join("tests/golden")
+        .join(format!("{name}.txt"));
+    let actual = format!("{}x{} {}\n", dim.0, dim.1, to_hex(&fingerprint(img, dim)));
+
+    if std::env::var_os(BLESS_VAR).is_some() {
+        std::fs::write(&path, actual).expect("failed to write the golden fingerprint");
+        return;
+    }
+    let expected = std::fs::read_to_string(&path).unwrap_or_else(|e| {
+        panic!(
+            "failed to read {}: {e}. Run the tests with {BLESS_VAR}=1 to create it",
+            path.display()
+        )
+    });
+
+    let (expected_dim, expected_hex) = expected.trim().split_once(' ').expect("bad golden file");
+    let (actual_dim, actual_hex) = actual.trim().split_once(' ').unwrap();
+    assert_eq!(actual_dim, expected_dim, "{name}: the dimensions changed");
+    let worst = from_hex(expected_hex)
+        .iter()
+        .zip(from_hex(actual_hex))
+        .map(|(e, a)| e.abs_diff(a))
+        .max()
+        .unwrap_or(0);
+    assert!(
+        worst <= TOLERANCE,
+        "{name}: the image changed (by up to {worst} in some block), expected\n{expected_hex}\ngot\n{actual_hex}\nIf this is intended, run the tests with {BLESS_VAR}=1"
+    );
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{b:02x}")).collect()
+}
+
+fn from_hex(hex: &str) -> Vec<u8> {
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&hex[i..i + 2], 16).expect("bad golden file"))
+        .collect()
+}
@@ -0,0 +1,296 @@
+//! Golden-image tests: known inputs go through the pixel pipelines, and what comes out is compared
+//! against the fingerprints in `tests/golden`, within a small tolerance. They are here so that
+//! refactoring those pipelines (SIMD, new codecs...) can't change what gets drawn unnoticed.
+//!
+//! When a change is meant to alter the output, regenerate the fingerprints with
+//! `SWWW_BLESS=1 cargo test -p swww-render --test golden`, and check the new images by hand
+mod fixtures;
+
+use std::{io::Cursor, time::Duration};
+
+use fixtures::{assert_golden, bgra, checkerboard, colors, gif, gradient};
+use swww_render::{
+    adjust::Adjustment,
+    animation::{compress_frames, AnimatedDecoder, Blend},
+    comp_decomp::Pack,
+    resize::{self, Fill},
+    transition::{Coord, Position, Runner, Transition, TransitionSync, TransitionType},
+};
+
+const DIM: (u32, u32) = (64, 48);
+
+#[test]
+fn pad() {
+    let padded = resize::pad(gradient((40, 30)), DIM, Fill::Color([10, 20, 30])).unwrap();
+    assert_golden("pad_color", &padded, DIM);
+    let padded = resize::pad(gradient((40, 30)), DIM, Fill::Edge).unwrap();
+    assert_golden("pad_edge", &padded, DIM);
+    let padded = resize::pad(checkerboard((40, 30), 4), DIM, Fill::Dominant).unwrap();
+    assert_golden("pad_dominant", &padded, DIM);
+    // bigger images are cropped instead
+    let cropped = resize::pad(gradient((100, 100)), DIM, Fill::Edge).unwrap();
+    assert_golden("pad_crop", &cropped, DIM);
+}
+
+#[test]
+fn tile() {
+    let tiled = resize::tile(checkerboard((20, 12), 4), DIM).unwrap();
+    assert_golden("tile", &tiled, DIM);
+}
+
+#[test]
+fn frame() {
+    let img = bgra(gradient((40, 30)));
+    let framed = resize::frame(&img, (40, 30), DIM, 8, &[200, 100, 50]);
+    assert_golden("frame", &framed, DIM);
+}
+
+#[test]
+fn rotate() {
+    let img = bgra(checkerboard(DIM, 8));
+    for degrees in [90.0, 180.0, 30.0] {
+        let rotated = resize::rotate(&img, DIM, degrees, Fill::Color([0, 0, 255])).unwrap();
+        assert_golden(&format!("rotate_{degrees}"), &rotated, DIM);
+    }
+}
+
+#[test]
+fn crop() {
+    let img = bgra(gradient(DIM));
+    let cropped = resize::crop(&img, DIM, (16, 8), (32, 24));
+    assert_golden("crop", &cropped, (32, 24));
+}
+
+#[test]
+fn adjust() {
+    let mut img = bgra(gradient(DIM));
+    Adjustment {
+        brightness: 10,
+        contrast: 30,
+        gamma: 1.8,
+    }
+    .apply(&mut img);
+    assert_golden("adjust", &img, DIM);
+}
+
+// fast_image_resize trips debug assertions in its unsafe code, so this only runs in release builds
+#[test]
+#[cfg_attr(debug_assertions, ignore = "run with --release")]
+fn resize() {
+    let resized = resize::resize(gradient((120, 80)), DIM, resize::FilterType::Lanczos3).unwrap();
+    assert_golden("resize_lanczos3", &resized, DIM);
+    let resized = resize::resize(checkerboard((32, 24), 4), DIM, resize::FilterType::Box).unwrap();
+    assert_golden("resize_box", &resized, DIM);
+    let fitted = resize::fit(
+        gradient((120, 40)),
+        DIM,
+        resize::FilterType::CatmullRom,
+        Fill::Blur,
+    )
+    .unwrap();
+    assert_golden("fit_blur", &fitted, DIM);
+}
+
+fn transition(transition_type: TransitionType) -> Transition {
+    Transition {
+        transition_type,
+        duration: 0.1,
+        step: 64,
+        fps: 30,
+        angle: 45.0,
+        pos: Position::new(Coord::Percent(0.5), Coord::Percent(0.5)),
+        bezier: (0.54, 0.0, 0.34, 0.99),
+        wave: (20.0, 20.0),
+        sync: TransitionSync::None,
+        skip_threshold: 0.0,
+    }
+}
+
+/// Plays `transition` from a gradient into a checkerboard, returning every frame
+fn play(transition: Transition) -> Vec<Vec<u8>> {
+    let old = bgra(gradient(DIM));
+    let new = bgra(checkerboard(DIM, 8));
+    let mut canvas = old.clone();
+    let mut frames = Vec::new();
+    Runner::new(old.into_boxed_slice(), DIM, transition, None).execute(
+        &new,
+        &mut |pack, _: Duration| {
+            assert!(pack.unpack(&mut canvas));
+            frames.push(canvas.clone());
+            false
+        },
+    );
+    assert_eq!(
+        frames.last().map(|last| colors(last)),
+        Some(colors(&new)),
+        "the transition didn't end in the new image"
+    );
+    frames
+}
+
+#[test]
+fn simple_transition() {
+    // this is the only transition that doesn't depend on how long frames take, so we can check
+    // every one of them
+    let frames = play(transition(TransitionType::Simple));
+    assert_eq!(frames.len(), 4);
+    for (i, frame) in frames.iter().enumerate() {
+        assert_golden(&format!("transition_simple_{i}"), frame, DIM);
+    }
+}
+
+#[test]
+fn flash_transition() {
+    let frames = play(transition(TransitionType::Flash([255, 255, 255])));
+    // 64 per frame, up to white and back down to the checkerboard's black squares
+    assert_eq!(frames.len(), 8);
+    assert!(colors(&frames[3]).iter().all(|c| *c == 255));
+}
+
+#[test]
+fn timed_transitions() {
+    for transition_type in [
+        TransitionType::Wipe,
+        TransitionType::Grow,
+        TransitionType::Outer,
+        TransitionType::Wave,
+        TransitionType::Slide,
+    ] {
+        play(transition(transition_type));
+    }
+}
+
+#[test]
+fn gif_animation() {
+    let decoder = AnimatedDecoder::new(Cursor::new(gif(DIM, 4)), image::ImageFormat::Gif).unwrap();
+    assert_eq!(decoder.dimensions(), DIM);
+    let mut prepared = Vec::new();
+    let compressed = compress_frames(
+        decoder,
+        |img| {
+            let img = resize::pad(img, DIM, Fill::Color([0, 0, 0]))?;
+            prepared.push(img.clone());
+            Ok(img)
+        },
+        0,
+        0,
+        None,
+        None,
+        &mut || (),
+    )
+    .unwrap();
+    assert_eq!(compressed.frames.len(), 4);
+
+    // the last frame goes back to the first one
+    let mut canvas = prepared[0].clone();
+    assert_golden("gif_0", &canvas, DIM);
+    for (i, (frame, duration)) in compressed.frames.iter().enumerate() {
+        assert_eq!(*duration, Duration::from_millis(100));
+        assert!(frame.ready(canvas.len()).unpack(&mut canvas));
+        let i = (i + 1) % prepared.len();
+        assert_eq!(
+            colors(&canvas),
+            colors(&prepared[i]),
+            "frame {i} wasn't decompressed as it was compressed"
+        );
+        assert_golden(&format!("gif_{i}"), &canvas, DIM);
+    }
+}
+
+#[test]
+fn gif_blending() {
+    let decoder = image::codecs::gif::GifDecoder::new(Cursor::new(gif(DIM, 4))).unwrap();
+    let mut prepared = Vec::new();
+    let blend = Blend {
+        duration: Duration::from_millis(40),
+        min_delay: Duration::from_millis(50),
+    };
+    let compressed = compress_frames(
+        decoder,
+        |img| {
+            let img = resize::pad(img, DIM, Fill::Color([0, 0, 0]))?;
+            prepared.push(img.clone());
+            Ok(img)
+        },
+        0,
+        0,
+        Some(blend),
+        None,
+        &mut || (),
+    )
+    .unwrap();
+    // every frame is reached through one in-between frame, without changing how long it takes
+    assert_eq!(compressed.frames.len(), 8);
+    let total: Duration = compressed
+        .frames
+        .iter()
+        .map(|(_, duration)| *duration)
+        .sum();
+    assert_eq!(total, Duration::from_millis(400));
+
+    let mut canvas = prepared[0].clone();
+    for (i, pair) in compressed.frames.chunks(2).enumerate() {
+        let before = canvas.clone();
+        assert_eq!(pair[0].1, Duration::from_millis(80));
+        assert!(pair[0].0.ready(canvas.len()).unpack(&mut canvas));
+        let i = (i + 1) % prepared.len();
+        // halfway between the frames
+        let (mid, a, b) = (colors(&canvas), colors(&before), colors(&prepared[i]));
+        for ((&mid, &a), &b) in mid.iter().zip(&a).zip(&b) {
+            assert!(mid >= a.min(b) && mid <= a.max(b));
+        }
+        assert_ne!(canvas, before);
+        assert_eq!(pair[1].1, Duration::from_millis(20));
+        assert!(pair[1].0.ready(canvas.len()).unpack(&mut canvas));
+        assert_eq!(colors(&canvas), colors(&prepared[i]));
+    }
+
+    // frames that are displayed for less than `min_delay` are left alone
+    let decoder = image::codecs::gif::GifDecoder::new(Cursor::new(gif(DIM, 4))).unwrap();
+    let blend = Blend {
+        min_delay: Duration::from_millis(100),
+        ..blend
+    };
+    let compressed = compress_frames(
+        decoder,
+        |img| resize::pad(img, DIM, Fill::Color([0, 0, 0])),
+        0,
+        0,
+        Some(blend),
+        None,
+        &mut || (),
+    )
+    .unwrap();
+    assert_eq!(compressed.frames.len(), 4);
+}
+
+#[test]
+fn gif_smooth_loop() {
+    let smooth = |frames| {
+        let decoder = image::codecs::gif::GifDecoder::new(Cursor::new(gif(DIM, frames))).unwrap();
+        compress_frames(
+            decoder,
+            |img| resize::pad(img, DIM, Fill::Color([0, 0, 0])),
+            0,
+            0,
+            None,
+            Some(Duration::from_millis(40)),
+            &mut || (),
+        )
+        .unwrap()
+    };
+    // only going back to the first frame gets an in-between frame
+    let compressed = smooth(4);
+    assert_eq!(compressed.frames.len(), 5);
+    let total: Duration = compressed
+        .frames
+        .iter()
+        .map(|(_, duration)| *duration)
+        .sum();
+    assert_eq!(total, Duration::from_millis(400));
+    assert_eq!(compressed.frames[3].1, Duration::from_millis(80));
+    assert_eq!(compressed.frames[4].1, Duration::from_millis(20));
+
+    // a single frame already loops seamlessly
+    assert_eq!(smooth(1).frames.len(), 1);
+}
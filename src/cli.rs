@@ -1,7 +1,7 @@
 /// Note: this file only has basic declarations and some definitions in order to be possible to
 /// import it in the build script, to automate shell completion
-use clap::Parser;
-use std::path::PathBuf;
+use clap::{builder::PossibleValue, Args, Parser, Subcommand, ValueEnum};
+use std::{path::PathBuf, time::Duration};
 
 fn from_hex(hex: &str) -> Result<[u8; 3], String> {
     let chars = hex
@@ -9,19 +9,16 @@ fn from_hex(hex: &str) -> Result<[u8; 3], String> {
         .filter(|&c| c.is_ascii_alphanumeric())
         .map(|c| c.to_ascii_uppercase() as u8);
 
-    if chars.clone().count() != 6 {
-        return Err(format!(
-            "expected 6 characters, found {}",
-            chars.clone().count()
-        ));
+    let count = chars.clone().count();
+    if count != 6 && count != 3 {
+        return Err(format!("expected 3 or 6 characters, found {count}"));
     }
 
-    let mut color = [0, 0, 0];
-
-    for (i, c) in chars.enumerate() {
+    let mut nibbles = Vec::with_capacity(6);
+    for c in chars {
         match c {
-            b'A'..=b'F' => color[i / 2] += c - b'A' + 10,
-            b'0'..=b'9' => color[i / 2] += c - b'0',
+            b'A'..=b'F' => nibbles.push(c - b'A' + 10),
+            b'0'..=b'9' => nibbles.push(c - b'0'),
             _ => {
                 return Err(format!(
                     "expected [0-9], [a-f], or [A-F], found '{}'",
@@ -29,13 +26,46 @@ fn from_hex(hex: &str) -> Result<[u8; 3], String> {
                 ))
             }
         }
-        if i % 2 == 0 {
-            color[i / 2] *= 16;
-        }
+    }
+
+    let mut color = [0, 0, 0];
+    for (i, c) in color.iter_mut().enumerate() {
+        *c = if count == 3 {
+            // shorthand notation: 'f' means 'ff'
+            nibbles[i] * 17
+        } else {
+            nibbles[i * 2] * 16 + nibbles[i * 2 + 1]
+        };
     }
     Ok(color)
 }
 
+/// Parses durations such as '500ms', '30s', '5m' and '1h'. Plain numbers are read as seconds
+pub fn parse_duration(raw: &str) -> Result<Duration, String> {
+    let raw = raw.trim();
+    let split = raw
+        .find(|c: char| !c.is_ascii_digit() && c != '.')
+        .unwrap_or(raw.len());
+    let (number, unit) = raw.split_at(split);
+    let number = match number.parse::<f64>() {
+        Ok(n) if n.is_finite() && n >= 0.0 => n,
+        _ => return Err(format!("invalid duration: {raw}")),
+    };
+    let secs = match unit.trim() {
+        "ms" => number / 1000.0,
+        "" | "s" => number,
+        "m" => number * 60.0,
+        "h" => number * 60.0 * 60.0,
+        "d" => number * 60.0 * 60.0 * 24.0,
+        unit => {
+            return Err(format!(
+                "unrecognized duration unit '{unit}'. Valid units are: ms | s | m | h | d"
+            ))
+        }
+    };
+    Ok(Duration::from_secs_f64(secs))
+}
+
 #[derive(Clone)]
 pub enum Filter {
     Nearest,
@@ -45,20 +75,25 @@ pub enum Filter {
     Lanczos3,
 }
 
-impl std::str::FromStr for Filter {
-    type Err = &'static str;
+impl ValueEnum for Filter {
+    fn value_variants<'a>() -> &'a [Self] {
+        &[
+            Self::Nearest,
+            Self::Bilinear,
+            Self::CatmullRom,
+            Self::Mitchell,
+            Self::Lanczos3,
+        ]
+    }
 
-    fn from_str(s: &str) -> Result<Self, Self::Err> {
-        match s {
-            "Nearest" => Ok(Self::Nearest),
-            "Bilinear" => Ok(Self::Bilinear),
-            "CatmullRom" => Ok(Self::CatmullRom),
-            "Mitchell" => Ok(Self::Mitchell),
-            "Lanczos3" => Ok(Self::Lanczos3),
-            _ => Err("unrecognized filter. Valid filters are:\
-                     Nearest | Bilinear | CatmullRom | Mitchell | Lanczos3\
-                     see swww img --help for more details"),
-        }
+    fn to_possible_value(&self) -> Option<PossibleValue> {
+        Some(match self {
+            Self::Nearest => PossibleValue::new("Nearest"),
+            Self::Bilinear => PossibleValue::new("Bilinear"),
+            Self::CatmullRom => PossibleValue::new("CatmullRom"),
+            Self::Mitchell => PossibleValue::new("Mitchell"),
+            Self::Lanczos3 => PossibleValue::new("Lanczos3"),
+        })
     }
 }
 
@@ -76,133 +111,1337 @@ pub enum TransitionType {
     Wipe,
     Wave,
     Grow,
+    Flash,
+    Slide,
+    /// `plugin:NAME`, drawn by the daemon's plugin called NAME
+    Plugin(String),
+}
+
+impl ValueEnum for TransitionType {
+    fn value_variants<'a>() -> &'a [Self] {
+        // plugins aren't listed, since their names can be anything
+        const VARIANTS: &[TransitionType] = &[
+            TransitionType::Simple,
+            TransitionType::Left,
+            TransitionType::Right,
+            TransitionType::Top,
+            TransitionType::Bottom,
+            TransitionType::Wipe,
+            TransitionType::Grow,
+            TransitionType::Center,
+            TransitionType::Outer,
+            TransitionType::Any,
+            TransitionType::Wave,
+            TransitionType::Flash,
+            TransitionType::Slide,
+            TransitionType::Random,
+        ];
+        VARIANTS
+    }
+
+    fn to_possible_value(&self) -> Option<PossibleValue> {
+        Some(match self {
+            Self::Simple => PossibleValue::new("simple"),
+            Self::Left => PossibleValue::new("left"),
+            Self::Right => PossibleValue::new("right"),
+            Self::Top => PossibleValue::new("top"),
+            Self::Bottom => PossibleValue::new("bottom"),
+            Self::Wipe => PossibleValue::new("wipe"),
+            Self::Grow => PossibleValue::new("grow"),
+            Self::Center => PossibleValue::new("center"),
+            Self::Outer => PossibleValue::new("outer"),
+            Self::Any => PossibleValue::new("any"),
+            Self::Wave => PossibleValue::new("wave"),
+            Self::Flash => PossibleValue::new("flash"),
+            Self::Slide => PossibleValue::new("slide"),
+            Self::Random => PossibleValue::new("random"),
+            Self::Plugin(_) => return None,
+        })
+    }
+}
+
+/// Parses `plugin:NAME` into [`TransitionType::Plugin`], and everything else like the other
+/// [`ValueEnum`]s
+#[derive(Clone)]
+struct TransitionTypeParser;
+
+impl clap::builder::TypedValueParser for TransitionTypeParser {
+    type Value = TransitionType;
+
+    fn parse_ref(
+        &self,
+        cmd: &clap::Command,
+        arg: Option<&clap::Arg>,
+        value: &std::ffi::OsStr,
+    ) -> Result<Self::Value, clap::Error> {
+        match value
+            .to_str()
+            .and_then(|value| value.strip_prefix("plugin:"))
+        {
+            Some("") => Err(clap::Error::raw(
+                clap::error::ErrorKind::InvalidValue,
+                "the plugin's name is missing, expected plugin:NAME\n",
+            )
+            .with_cmd(cmd)),
+            Some(name) => Ok(TransitionType::Plugin(name.to_string())),
+            None => {
+                clap::builder::EnumValueParser::<TransitionType>::new().parse_ref(cmd, arg, value)
+            }
+        }
+    }
+
+    fn possible_values(&self) -> Option<Box<dyn Iterator<Item = PossibleValue> + '_>> {
+        Some(Box::new(
+            TransitionType::value_variants()
+                .iter()
+                .filter_map(ValueEnum::to_possible_value),
+        ))
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum SlideshowSort {
+    Name,
+    Mtime,
+    Random,
+}
+
+impl ValueEnum for SlideshowSort {
+    fn value_variants<'a>() -> &'a [Self] {
+        &[Self::Name, Self::Mtime, Self::Random]
+    }
+
+    fn to_possible_value(&self) -> Option<PossibleValue> {
+        Some(match self {
+            Self::Name => PossibleValue::new("name"),
+            Self::Mtime => PossibleValue::new("mtime"),
+            Self::Random => PossibleValue::new("random"),
+        })
+    }
+}
+
+#[derive(Clone)]
+pub enum TransitionSync {
+    None,
+    Layout,
+}
+
+impl ValueEnum for TransitionSync {
+    fn value_variants<'a>() -> &'a [Self] {
+        &[Self::None, Self::Layout]
+    }
+
+    fn to_possible_value(&self) -> Option<PossibleValue> {
+        Some(match self {
+            Self::None => PossibleValue::new("none"),
+            Self::Layout => PossibleValue::new("layout"),
+        })
+    }
+}
+
+#[derive(Clone)]
+pub enum TestPattern {
+    Bars,
+    Ramps,
+    Grid,
+}
+
+impl ValueEnum for TestPattern {
+    fn value_variants<'a>() -> &'a [Self] {
+        &[Self::Bars, Self::Ramps, Self::Grid]
+    }
+
+    fn to_possible_value(&self) -> Option<PossibleValue> {
+        Some(match self {
+            Self::Bars => PossibleValue::new("bars"),
+            Self::Ramps => PossibleValue::new("ramps"),
+            Self::Grid => PossibleValue::new("grid"),
+        })
+    }
+}
+
+#[derive(Clone)]
+pub enum ImgFormat {
+    Bmp,
+    Farbfeld,
+    Gif,
+    Jpeg,
+    Png,
+    Pnm,
+    Tga,
+    Tiff,
+    Webp,
+    Xbm,
+}
+
+impl ValueEnum for ImgFormat {
+    fn value_variants<'a>() -> &'a [Self] {
+        &[
+            Self::Bmp,
+            Self::Farbfeld,
+            Self::Gif,
+            Self::Jpeg,
+            Self::Png,
+            Self::Pnm,
+            Self::Tga,
+            Self::Tiff,
+            Self::Webp,
+            Self::Xbm,
+        ]
+    }
+
+    fn to_possible_value(&self) -> Option<PossibleValue> {
+        Some(match self {
+            Self::Bmp => PossibleValue::new("bmp"),
+            Self::Farbfeld => PossibleValue::new("farbfeld").alias("ff"),
+            Self::Gif => PossibleValue::new("gif"),
+            Self::Jpeg => PossibleValue::new("jpeg").alias("jpg"),
+            Self::Png => PossibleValue::new("png"),
+            Self::Pnm => PossibleValue::new("pnm").aliases(["pbm", "pgm", "ppm", "pam"]),
+            Self::Tga => PossibleValue::new("tga"),
+            Self::Tiff => PossibleValue::new("tiff").alias("tif"),
+            Self::Webp => PossibleValue::new("webp"),
+            Self::Xbm => PossibleValue::new("xbm"),
+        })
+    }
+}
+
+#[derive(Clone, PartialEq, Debug)]
+pub enum ResizeStrategy {
+    Crop,
+    Fit,
+    No,
+    Stretch,
+    Tile,
+}
+
+impl ValueEnum for ResizeStrategy {
+    fn value_variants<'a>() -> &'a [Self] {
+        &[Self::Crop, Self::Fit, Self::No, Self::Stretch, Self::Tile]
+    }
+
+    fn to_possible_value(&self) -> Option<PossibleValue> {
+        Some(match self {
+            Self::Crop => PossibleValue::new("crop"),
+            Self::Fit => PossibleValue::new("fit"),
+            Self::No => PossibleValue::new("no").aliases(["center"]),
+            Self::Stretch => PossibleValue::new("stretch"),
+            Self::Tile => PossibleValue::new("tile"),
+        })
+    }
+}
+
+#[derive(Clone, PartialEq, Debug)]
+pub enum PreviewProtocol {
+    Auto,
+    Kitty,
+    Sixel,
+}
+
+impl ValueEnum for PreviewProtocol {
+    fn value_variants<'a>() -> &'a [Self] {
+        &[Self::Auto, Self::Kitty, Self::Sixel]
+    }
+
+    fn to_possible_value(&self) -> Option<PossibleValue> {
+        Some(match self {
+            Self::Auto => PossibleValue::new("auto"),
+            Self::Kitty => PossibleValue::new("kitty"),
+            Self::Sixel => PossibleValue::new("sixel"),
+        })
+    }
+}
+
+#[derive(Clone, PartialEq, Debug)]
+pub enum QueryFormat {
+    Text,
+    Json,
+}
+
+impl ValueEnum for QueryFormat {
+    fn value_variants<'a>() -> &'a [Self] {
+        &[Self::Text, Self::Json]
+    }
+
+    fn to_possible_value(&self) -> Option<PossibleValue> {
+        Some(match self {
+            Self::Text => PossibleValue::new("text"),
+            Self::Json => PossibleValue::new("json"),
+        })
+    }
+}
+
+#[derive(Clone, PartialEq, Debug)]
+pub enum ReduceMotion {
+    Auto,
+    On,
+    Off,
+}
+
+impl ValueEnum for ReduceMotion {
+    fn value_variants<'a>() -> &'a [Self] {
+        &[Self::Auto, Self::On, Self::Off]
+    }
+
+    fn to_possible_value(&self) -> Option<PossibleValue> {
+        Some(match self {
+            Self::Auto => PossibleValue::new("auto"),
+            Self::On => PossibleValue::new("on").aliases(["1", "true", "yes"]),
+            Self::Off => PossibleValue::new("off").aliases(["0", "false", "no"]),
+        })
+    }
+}
+
+#[derive(Clone)]
+pub enum FillMode {
+    Color,
+    Blur,
+    Edge,
+    Dominant,
+}
+
+impl ValueEnum for FillMode {
+    fn value_variants<'a>() -> &'a [Self] {
+        &[Self::Color, Self::Blur, Self::Edge, Self::Dominant]
+    }
+
+    fn to_possible_value(&self) -> Option<PossibleValue> {
+        Some(match self {
+            Self::Color => PossibleValue::new("color"),
+            Self::Blur => PossibleValue::new("blur"),
+            Self::Edge => PossibleValue::new("edge"),
+            Self::Dominant => PossibleValue::new("dominant"),
+        })
+    }
+}
+
+#[derive(Clone)]
+pub enum AdjustSource {
+    Base,
+    Regions,
+}
+
+impl ValueEnum for AdjustSource {
+    fn value_variants<'a>() -> &'a [Self] {
+        &[Self::Base, Self::Regions]
+    }
+
+    fn to_possible_value(&self) -> Option<PossibleValue> {
+        Some(match self {
+            Self::Base => PossibleValue::new("base"),
+            Self::Regions => PossibleValue::new("regions"),
+        })
+    }
+}
+
+#[derive(Clone)]
+pub enum CliCoord {
+    Percent(f32),
+    Pixel(f32),
+}
+
+#[derive(Clone)]
+pub struct CliPosition {
+    pub x: CliCoord,
+    pub y: CliCoord,
+    //Unknown(f32, f32),
+}
+
+impl CliPosition {
+    pub fn new(x: CliCoord, y: CliCoord) -> Self {
+        Self { x, y }
+    }
+}
+
+#[derive(Clone)]
+pub struct CliLayer {
+    pub path: PathBuf,
+    pub opacity: f32,
+    pub pos: CliPosition,
+}
+
+#[derive(Parser)]
+#[command(version, name = "swww")]
+///A Solution to your Wayland Wallpaper Woes
+///
+///Change what your monitors display as a background by controlling the swww daemon at runtime.
+///Supports animated gifs and putting different stuff in different monitors. I also did my best to
+///make it as resource efficient as possible.
+///
+///Note `swww` will only work in a compositor that implements the layer-shell protocol. Typically,
+///wlr-roots based compositors.
+pub enum Swww {
+    ///Adjusts the brightness, contrast and gamma of what is being displayed, or applies an effect
+    ///plugin to it
+    ///
+    ///Still images transition into their adjusted selves, while animations (including the ones
+    ///played with `swww region`) are adjusted as they play, without restarting. The daemon works
+    ///from what was set, so adjustments don't add up: running this without any adjustments brings
+    ///back the original.
+    Adjust(Adjust),
+
+    ///Inspects or cleans the cache of the images being displayed
+    ///
+    ///The cache is used to restore the outputs' images when the daemon starts. It doesn't need
+    ///the daemon to be running.
+    #[command(subcommand)]
+    Cache(Cache),
+
+    ///Fills the specified outputs with the given color.
+    ///
+    ///Defaults to filling all outputs with black.
+    Clear(Clear),
+
+    ///Stops drawing the background of an output, so that something else can own it
+    ///
+    ///The rest of the outputs keep working normally. Use `swww enable` to take the output back.
+    Disable {
+        ///The output to let go of
+        output: String,
+    },
+
+    ///Runs swww-daemon with these arguments, starting it again whenever it crashes. This is what
+    ///`swww init --supervise` runs in the background
+    #[command(hide = true)]
+    Supervise {
+        #[arg(trailing_var_arg = true, allow_hyphen_values = true)]
+        args: Vec<String>,
+    },
+
+    ///Checks that swww can work in this session, printing how to fix what can't
+    ///
+    ///This checks the runtime directory, the swww-daemon executable, whether the compositor
+    ///supports the layer-shell protocol (GNOME's doesn't), and, if the daemon is running, whether
+    ///it answers, is the same version as this swww, and has found any outputs. It exits with an
+    ///error if it found a problem.
+    Doctor,
+
+    ///Prints a JSON description of every subcommand, option and accepted value of swww
+    ///
+    ///This is meant for programs wrapping swww, like graphical interfaces or completions for
+    ///shells we don't generate them for, so that they can keep up with new options
+    ///automatically. It doesn't need the daemon to be running. See the swww-dump-cli-spec(1) man
+    ///page for the format.
+    DumpCliSpec,
+
+    ///Starts drawing the background of an output previously disabled with `swww disable` again
+    Enable {
+        ///The output to take back
+        output: String,
+    },
+
+    ///Makes an output mirror whatever is displayed in another one.
+    ///
+    ///From then on, everything sent to the source output is also sent to the follower, resized
+    ///to its own resolution. This is useful for projectors, for example.
+    Follow(Follow),
+
+    ///Makes the daemon procedurally generate what to display.
+    ///
+    ///The images are rendered at each output's exact resolution, so no files are needed.
+    #[command(subcommand_required = true)]
+    Generate(Generate),
+
+    ///Manages named groups of outputs
+    ///
+    ///Groups can be used anywhere `--outputs` is accepted, so that common combinations of
+    ///monitors don't need to be retyped in every script. They are stored in the daemon.
+    #[command(subcommand)]
+    Group(Group),
+
+    /// Sends an image (or animation) for the daemon to display.
+    ///
+    /// Use `-` to read from stdin
+    Img(Img),
+
+    /// Initializes the daemon.
+    ///
+    /// Exits if there is already a daemon running. We check that by seeing if
+    /// $XDG_RUNTIME_DIR/swww.socket exists.
+    Init {
+        ///Don't fork the daemon. This will keep it running in the current terminal.
+        ///
+        ///The only advantage of this would be seeing the logging real time. Note that for release
+        ///builds we only log info, warnings and errors, so you won't be seeing much (ideally).
+        #[clap(long)]
+        no_daemon: bool,
+
+        ///Maximum size of the cache, like '500M' or '2G'
+        ///
+        ///Once the cache grows past this, the daemon deletes the files of the outputs that were
+        ///used the longest time ago. Cache files of images that no longer exist are always deleted.
+        #[clap(long)]
+        cache_size: Option<String>,
+
+        ///Command to run (with `sh -c`) whenever a transition starts or finishes
+        ///
+        ///The event is in the SWWW_EVENT environment variable (`transition-start` or
+        ///`transition-end`), and the outputs it happened on in SWWW_OUTPUTS, separated by commas.
+        ///eg: --hook '[ "$SWWW_EVENT" = transition-start ] && paplay whoosh.ogg'
+        #[clap(long)]
+        hook: Option<String>,
+
+        ///Minimum time between changes to an output's wallpaper, like '2s' or '500ms'
+        ///
+        ///Requests changing an output sooner wait until this has passed, and the ones replaced by
+        ///newer requests while waiting are dropped. This keeps misbehaving scripts from hogging
+        ///the cpu. By default, every request is applied right away.
+        #[clap(long, value_parser = parse_duration)]
+        min_interval: Option<Duration>,
+
+        ///Wait this long before setting the first wallpaper, like '2s' or '500ms'
+        ///
+        ///Until then, the outputs are only cleared. Use this (or `--wait-for`) when the compositor
+        ///is still setting up the outputs when swww starts.
+        #[clap(long, value_parser = parse_duration)]
+        delay: Option<Duration>,
+
+        ///Command to run (with `sh -c`) before setting the first wallpaper, which exits once the
+        ///session is ready
+        ///
+        ///Until it exits (or for 60 seconds, at most), the outputs are only cleared, and `swww init`
+        ///doesn't return. eg: --wait-for 'gdbus wait --session org.freedesktop.portal.Desktop'
+        #[clap(long)]
+        wait_for: Option<String>,
+
+        ///Also read simple line commands from $XDG_RUNTIME_DIR/swww.fifo
+        ///
+        ///This is for environments that can write to a pipe, but can't connect to a socket, like
+        ///some sandboxes. eg: echo 'img /path/to/img.png' > $XDG_RUNTIME_DIR/swww.fifo. See
+        ///swww-init(1) for the commands.
+        #[clap(long)]
+        fifo: bool,
+
+        ///Also accept requests over TCP, at a loopback address like 'tcp://127.0.0.1:9500'
+        ///
+        ///Clients must authenticate with the token in the SWWW_TOKEN environment variable, which
+        ///must be set here too. Nothing is encrypted, so other machines must reach the port
+        ///through a tunnel (like ssh's port forwarding), and then set SWWW_REMOTE to their end of
+        ///it, and SWWW_TOKEN to the token, to control this daemon with swww. Only requests that
+        ///change or query what is displayed are accepted: `swww kill`, recordings, external
+        ///generators and the like are refused.
+        #[clap(long)]
+        listen: Option<String>,
+
+        ///Layer-shell layer to put the wallpaper in: 'background' (the default) or 'bottom'
+        ///
+        ///'bottom' is above other background clients (like a terminal used as a wallpaper), but
+        ///still below normal windows. Outputs can be given their own layer, eg.:
+        ///'background,DP-1=bottom'
+        #[clap(long)]
+        layer: Option<String>,
+
+        ///Exclusive zone of the wallpaper's surfaces: -1 (the default) or 0
+        ///
+        ///-1 covers the whole output, even under panels, while 0 keeps clear of the areas other
+        ///clients (like bars) reserved. Outputs can be given their own zone, eg.: '-1,DP-1=0'
+        #[clap(long, allow_hyphen_values = true)]
+        exclusive_zone: Option<String>,
+
+        ///The largest resolution to draw outputs at, like '3840x2160'
+        ///
+        ///Outputs of higher resolutions are drawn at the largest one that fits, with the same
+        ///aspect ratio, and the compositor scales them up. This trades sharpness for much less
+        ///memory and CPU on huge displays. Outputs can be given their own limit, or 'none', eg.:
+        ///'3840x2160,eDP-1=none'
+        #[clap(long)]
+        max_resolution: Option<String>,
+
+        ///Swap images without transitions, and only display the first frame of animations
+        ///
+        ///For people sensitive to motion. 'auto' follows the desktop's reduced motion (or 'enable
+        ///animations') setting, read through xdg-desktop-portal, when swww was built with the
+        ///'portal' feature, and is the same as 'off' otherwise. The setting is read when the
+        ///daemon starts, so run `swww init --replace` after changing it.
+        #[clap(long, env = "SWWW_REDUCE_MOTION", default_value = "auto")]
+        reduce_motion: ReduceMotion,
+
+        ///Take over from the daemon that is already running, instead of exiting
+        ///
+        ///The new daemon draws the cached wallpapers over the old one's before telling it to exit,
+        ///so that upgrading swww doesn't make the wallpaper flash. Groups, pins, followers, safe
+        ///areas and colors are carried over too.
+        #[clap(long)]
+        replace: bool,
+
+        ///Start the daemon again whenever it crashes
+        ///
+        ///A small swww process stays in the background, watching the daemon. When it crashes, the
+        ///new daemon brings back the cached wallpapers, and the groups, pins, followers, safe areas
+        ///and colors of a few seconds before the crash. It gives up after 5 crashes in a minute.
+        ///`swww kill` stops both.
+        #[clap(long)]
+        supervise: bool,
+    },
+
+    ///Kills the daemon
+    Kill,
+
+    ///Draws a small image over what is currently being displayed
+    ///
+    ///Unlike `swww img`, this doesn't replace the whole background, so it can be used to display
+    ///things rendered by other programs, like widgets.
+    Patch(Patch),
+
+    ///Plays a gif in a rectangle of the outputs, over what they are displaying
+    ///
+    ///Every region has its own timeline, so many of them can play at once on the same output,
+    ///like a cinemagraph in one corner of a static image. A new region replaces the ones it
+    ///overlaps, and setting a new image removes them all.
+    Region(Region),
+
+    ///Pins an output, so that requests that don't set `--outputs` leave it alone
+    ///
+    ///This is useful to keep a fixed background in one monitor while scripts change the others.
+    ///Requests that name the output explicitly still work.
+    Pin(Pin),
+
+    ///Goes through a playlist of images, each displayed for its own time, until it is killed
+    ///
+    ///Items can be limited to some days and hours, like '09:00-17:00' on weekdays, and be given
+    ///their own `swww img` options, like the transition to be displayed with. This is enough for
+    ///simple digital signage. See the swww-playlist(1) man page for the file format.
+    Playlist(Playlist),
+
+    ///Sets which parts of an output are hidden, by a notch or a panel that is always visible
+    ///
+    ///When resizing images for the output, swww crops them so that their center is in the middle
+    ///of the rest of the output, instead of the middle of the whole output.
+    ///
+    ///eg: swww safe-area eDP-1 top:40
+    SafeArea(SafeArea),
+
+    ///Shows how an image would look on an output, in the terminal, without changing anything
+    ///
+    ///The image is prepared exactly like `swww img` would, with the same options, for the first of
+    ///the requested outputs (or for `--size`), and then drawn in the terminal. This is useful over
+    ///ssh, to make sure you picked the right file before changing a remote machine's wallpaper.
+    Preview(Preview),
+
+    ///Inspects or cancels the requests the daemon hasn't handled yet
+    ///
+    ///The daemon handles one request at a time, so if many arrive at once the rest wait in a
+    ///queue. Interactive requests (like `img` and `clear`) skip ahead of background work (like
+    ///`generate`).
+    #[command(subcommand)]
+    Queue(Queue),
+
+    ///Records what the daemon displays on an output into a video file
+    ///
+    ///The daemon encodes the frames it commits to the output with ffmpeg, so this needs a
+    ///swww-daemon built with the `ffmpeg` feature, and ffmpeg installed. The recording happens in
+    ///the background, so this returns right away.
+    Record(Record),
+
+    ///Makes the daemon forget its cached images, and displays the outputs' images again
+    ///
+    ///The images are read from disk again, resized for the outputs' current geometry, and brought
+    ///in with a quick fade. Use this when something changed behind swww's back, like an image file
+    ///being overwritten, and what is displayed looks stale. The images are displayed with the
+    ///default `swww img` options, while colors and generated wallpapers, which the daemon always
+    ///draws at the outputs' resolution, are left alone.
+    Refresh(Refresh),
+
+    ///Prints the average colors of what the outputs display, over and over, until it is killed
+    ///
+    ///Every line is an output's name followed by its colors, as hex, like 'DP-1: 1a804a'. This is
+    ///meant for ambient lighting (like LED strips behind the monitor), which can then follow
+    ///animated wallpapers without capturing the screen. See the swww-subscribe(1) man page.
+    Subscribe(Subscribe),
+
+    ///Serves xdg-desktop-portal's wallpaper interface, so that programs setting the wallpaper
+    ///through it (mostly flatpaks) drive swww instead
+    ///
+    ///This keeps running until it is killed. See the swww-portal(1) man page for how to make
+    ///xdg-desktop-portal use it.
+    #[cfg(feature = "portal")]
+    Portal {
+        ///Also stand in for plasmashell's wallpaper methods, so that KDE Plasma's settings and
+        ///`plasma-apply-wallpaperimage` drive swww. Only works when plasmashell isn't running
+        #[arg(long)]
+        kde: bool,
+
+        ///Also follow the wallpapers COSMIC's settings write for cosmic-bg. Don't run cosmic-bg
+        ///along with this, or it will draw over swww
+        #[arg(long)]
+        cosmic: bool,
+    },
+
+    ///Opens an interactive terminal interface for choosing what each output displays
+    ///
+    ///It lists the outputs, what they are displaying and the daemon's queue, and lets you browse
+    ///for images and pick the transition to send them with. See the swww-tui(1) man page for the
+    ///keys.
+    #[cfg(feature = "tui")]
+    Tui,
+
+    ///Stages several changes, so that they are all displayed at once
+    ///
+    ///After `swww transaction begin`, requests that change what is displayed (like `img`, `clear`
+    ///and `generate`) are kept by the daemon instead of being applied. `swww transaction commit`
+    ///then applies all of them together, in the same frame.
+    #[command(subcommand)]
+    Transaction(Transaction),
+
+    ///Lets another program drive a transition, like a slider or a touchpad gesture
+    ///
+    ///`swww transition manual` starts a transition into an image, taking the same options as
+    ///`swww img`, but leaves the old image on screen. Every `swww transition set` then moves the
+    ///transition to that point, until `swww transition end` finishes it.
+    #[command(subcommand)]
+    Transition(ManualTransition),
+
+    ///Shows where the time went while the daemon handled a request
+    ///
+    ///Without an id, lists the last requests the daemon received, along with their ids and how
+    ///long they took. With one, prints everything that happened to that request, from when it was
+    ///read from the socket until its last frame was drawn, with timestamps.
+    Trace {
+        ///The request to show, as listed by `swww trace` or `swww queue list` (without the '#')
+        id: Option<u64>,
+    },
+
+    ///Asks the daemon to print output information (names and dimensions).
+    ///
+    ///You may use this to find out valid values for the <swww-img --outputs> option. If you want
+    ///more detailed information about your outputs, I would recommend trying wlr-randr.
+    Query {
+        ///Instead, print where the animation playing on each output is
+        ///
+        ///For every output playing an animation, prints the frame it is displaying (counting from
+        ///0), how many frames the animation has, how many times it looped, and for how long it has
+        ///been playing there. Outputs that aren't playing one are left out.
+        #[arg(long)]
+        anim: bool,
+
+        ///How to print the outputs: 'text' (the default) or 'json'
+        ///
+        ///'json' prints an array with an object per output, meant for scripts and status bars. See
+        ///the swww-query(1) man page for its fields.
+        #[arg(long, default_value = "text")]
+        format: QueryFormat,
+    },
+}
+
+#[derive(Subcommand)]
+pub enum Cache {
+    ///Deletes every cached image
+    Clean,
+
+    ///Prints the size and contents of every cache file, checking whether they are corrupted
+    Stats,
+}
+
+#[derive(Subcommand)]
+pub enum Group {
+    ///Creates a group, or replaces the outputs of an existing one
+    ///
+    ///eg: swww group create sides DP-1 DP-3
+    Create {
+        ///Name of the group. It can't be the name of an output
+        name: String,
+
+        ///The outputs in the group
+        #[arg(required = true)]
+        outputs: Vec<String>,
+    },
+
+    ///Deletes a group
+    Delete {
+        ///Name of the group to delete
+        name: String,
+    },
+
+    ///Prints every group, along with its outputs
+    List,
 }
 
-impl std::str::FromStr for TransitionType {
-    type Err = &'static str;
-
-    fn from_str(s: &str) -> Result<Self, Self::Err> {
-        match s {
-            "simple" => Ok(Self::Simple),
-            "left" => Ok(Self::Left),
-            "right" => Ok(Self::Right),
-            "top" => Ok(Self::Top),
-            "bottom" => Ok(Self::Bottom),
-            "wipe" => Ok(Self::Wipe),
-            "grow" => Ok(Self::Grow),
-            "center" => Ok(Self::Center),
-            "outer" => Ok(Self::Outer),
-            "any" => Ok(Self::Any),
-            "wave" => Ok(Self::Wave),
-            "random" => Ok(Self::Random),
-            _ => Err("unrecognized transition type.\nValid transitions are:\n\
-                     \tsimple | left | right | top | bottom | wipe | grow | center | outer | random | wave\n\
-                     see swww img --help for more details"),
-        }
-    }
-}
+#[derive(Subcommand)]
+pub enum Queue {
+    ///Prints the requests waiting to be handled, in the order they will be handled
+    List,
+
+    ///Cancels every request waiting to be handled
+    Clear,
+}
+
+#[derive(Subcommand)]
+pub enum Transaction {
+    ///Starts staging the requests that change what is displayed
+    Begin,
+
+    ///Applies every staged request at once, ending the transaction
+    Commit,
+
+    ///Discards every staged request, ending the transaction
+    Rollback,
+}
+
+#[derive(Subcommand)]
+pub enum ManualTransition {
+    ///Starts a transition into an image, which stays at the old image until it is moved
+    ///
+    ///Animated images only display their first frame. The transition's duration, step, fps and
+    ///bezier curve don't apply, since it moves only when told to.
+    Manual(Box<Img>),
+
+    ///Moves the transition in progress to a point, from 0 (the old image) to 1 (the new one)
+    Set {
+        #[arg(value_parser = parse_progress)]
+        progress: f32,
+    },
+
+    ///Ends the transition in progress, at the new image if it was moved at least halfway there,
+    ///and back at the old one otherwise
+    End,
+}
+
+#[derive(Parser)]
+pub struct Clear {
+    /// Color to fill the screen with.
+    ///
+    /// Must be given in rrggbb or rgb format (a leading '#' is fine).
+    #[arg(value_parser = from_hex, default_value = "000000")]
+    pub color: [u8; 3],
+
+    /// Comma separated list of outputs to display the image at.
+    /// Names of groups created with `swww group` can be used as well.
+    ///
+    /// If it isn't set, the image is displayed on all outputs.
+    #[clap(short, long, default_value = "")]
+    pub outputs: String,
+
+    #[command(flatten)]
+    pub transition: Transition,
+}
+
+#[derive(Parser)]
+pub struct Adjust {
+    /// Brightness, in percent, from -100 (black) to 100 (twice as bright)
+    #[arg(long, default_value = "0", allow_negative_numbers = true,
+          value_parser = clap::value_parser!(i8).range(-100..=100))]
+    pub brightness: i8,
+
+    /// Contrast, in percent, from -100 (flat gray) to 100 (twice as much)
+    #[arg(long, default_value = "0", allow_negative_numbers = true,
+          value_parser = clap::value_parser!(i8).range(-100..=100))]
+    pub contrast: i8,
+
+    /// Gamma, from 0.1 to 10. Values above 1 brighten the midtones, and below 1 darken them
+    #[arg(long, default_value = "1", value_parser = parse_gamma)]
+    pub gamma: f32,
+
+    /// What to adjust
+    ///
+    /// base | regions
+    ///
+    /// 'base' is what was set with `swww img` or `swww clear`, and 'regions' the animations
+    /// played with `swww region`, so that an animation and what is beneath it can be adjusted
+    /// differently.
+    #[arg(long, default_value = "base", hide_possible_values = true)]
+    pub source: AdjustSource,
+
+    /// Even out the brightness of the outputs
+    ///
+    /// Each output's image is brightened or darkened towards the average luminance of all of the
+    /// adjusted outputs' images, on top of the other adjustments, so that a dark wallpaper next to
+    /// a bright one doesn't stand out. Animations are left out of the average, and aren't
+    /// matched. Only works with '--source base'.
+    #[arg(long)]
+    pub match_brightness: bool,
+
+    /// Apply an effect plugin, given as 'plugin:NAME'
+    ///
+    /// The effect is the WebAssembly module ~/.config/swww/effects/NAME.wasm, which the daemon
+    /// runs sandboxed, so that effects written by others are safe to use. It is applied on top of
+    /// the other adjustments, and only to still images. Needs a daemon built with the 'effects'
+    /// feature.
+    #[arg(long, value_name = "plugin:NAME", value_parser = parse_effect)]
+    pub effect: Option<String>,
+
+    /// Comma separated list of outputs to adjust.
+    /// Names of groups created with `swww group` can be used as well.
+    ///
+    /// If it isn't set, all outputs are adjusted.
+    #[clap(short, long, default_value = "")]
+    pub outputs: String,
+
+    #[command(flatten)]
+    pub transition: Transition,
+}
+
+#[derive(Parser)]
+pub struct Follow {
+    /// The output that will mirror the other one
+    pub output: String,
+
+    /// The output to mirror
+    #[arg(long, required_unless_present = "stop")]
+    pub from: Option<String>,
+
+    /// Stop following whatever output this one is currently following
+    #[arg(long, conflicts_with = "from")]
+    pub stop: bool,
+}
+
+#[derive(Parser)]
+pub struct Pin {
+    /// The output to pin
+    pub output: String,
+
+    /// Unpin the output, so that it receives every request again
+    #[arg(long)]
+    pub unpin: bool,
+}
+
+#[derive(Parser)]
+pub struct Playlist {
+    /// Path to the playlist file
+    pub file: PathBuf,
+
+    /// Comma separated list of outputs to display the playlist on. Names of groups created with
+    /// `swww group` can be used as well.
+    ///
+    /// If it isn't set, the images are displayed on all outputs that aren't pinned.
+    #[arg(short, long, default_value = "")]
+    pub outputs: String,
+}
+
+#[derive(Parser)]
+pub struct Refresh {
+    /// Comma separated list of outputs to refresh. Names of groups created with `swww group` can
+    /// be used as well.
+    ///
+    /// If it isn't set, every output that isn't pinned is refreshed.
+    #[arg(short, long, default_value = "")]
+    pub outputs: String,
+}
+
+#[derive(Parser)]
+pub struct SafeArea {
+    /// The output whose safe area to set
+    pub output: String,
+
+    /// Comma separated list of hidden sides, with how many pixels of them are hidden, like
+    /// `top:40,left:20`. Use `none` to remove the safe area
+    #[arg(value_parser = parse_safe_area)]
+    pub area: CliSafeArea,
+}
+
+#[derive(Clone, Default, PartialEq, Debug)]
+pub struct CliSafeArea {
+    pub top: u32,
+    pub bottom: u32,
+    pub left: u32,
+    pub right: u32,
+}
+
+#[derive(Parser)]
+pub struct Record {
+    /// The output to record
+    pub output: String,
+
+    /// How long to record for. Can be given in ms, s, m, h or d, eg: '10s'
+    #[arg(long, default_value = "10s", value_parser = parse_duration)]
+    pub duration: Duration,
+
+    /// Frame rate of the video
+    #[arg(long, default_value = "30", value_parser = clap::value_parser!(u8).range(1..))]
+    pub fps: u8,
+
+    /// Where to write the video. ffmpeg picks the format from the extension, eg: 'out.mp4'
+    pub path: PathBuf,
+}
+
+#[derive(Parser)]
+pub struct Subscribe {
+    /// Print the average colors of the outputs. This is the only thing that can be subscribed to
+    /// for now
+    #[arg(long, required = true)]
+    pub avg_color: bool,
+
+    /// Split each output into <columns>x<rows> cells, printing the average color of each of them
+    ///
+    /// The colors are printed row by row, starting at the top left.
+    #[arg(long, default_value = "1x1", value_parser = parse_size)]
+    pub grid: (u32, u32),
+
+    /// How many times per second to print the colors
+    #[arg(long, default_value = "10", value_parser = clap::value_parser!(u8).range(1..))]
+    pub rate: u8,
+
+    /// Comma separated list of outputs to print the colors of.
+    /// Names of groups created with `swww group` can be used as well.
+    ///
+    /// If it isn't set, the colors of all outputs are printed.
+    #[arg(short, long, default_value = "")]
+    pub outputs: String,
+}
+
+#[derive(Parser)]
+pub struct Patch {
+    /// Path to the image to draw. Use `-` to read from stdin
+    pub path: PathBuf,
+
+    /// Where to put the center of the image. Accepts the same values as
+    /// `swww img --transition-pos`
+    #[arg(long, default_value = "center", value_parser = parse_coords)]
+    pub pos: CliPosition,
+
+    /// Resize the image to <width>x<height> pixels before drawing it
+    #[arg(long, value_parser = parse_size)]
+    pub size: Option<(u32, u32)>,
+
+    /// Comma separated list of outputs to draw the image at.
+    /// Names of groups created with `swww group` can be used as well.
+    ///
+    /// If it isn't set, the image is drawn on all outputs.
+    #[arg(short, long, default_value = "")]
+    pub outputs: String,
+}
+
+#[derive(Parser)]
+pub struct Preview {
+    /// Draw the preview as an image, with the kitty or sixel graphics protocol
+    ///
+    /// This needs swww to be built with the `inline-preview` feature, and a terminal supporting
+    /// one of those protocols. Otherwise, the preview is drawn with colored half blocks, which only
+    /// needs a terminal with true color support.
+    #[arg(long)]
+    pub inline: bool,
+
+    /// Graphics protocol to draw `--inline` previews with
+    ///
+    /// `auto` uses kitty's in terminals known to support it (like kitty, WezTerm and ghostty), and
+    /// sixel in the others.
+    #[arg(long, default_value = "auto")]
+    pub protocol: PreviewProtocol,
+
+    /// How many columns of the terminal the preview takes
+    #[arg(long, default_value = "60", value_parser = clap::value_parser!(u32).range(1..))]
+    pub columns: u32,
+
+    /// Preview the image for an output of <width>x<height> pixels, instead of asking the daemon
+    /// for the size of the output
+    #[arg(long, value_parser = parse_size)]
+    pub size: Option<(u32, u32)>,
+
+    #[command(flatten)]
+    pub img: Img,
+}
+
+#[derive(Parser)]
+pub struct Region {
+    /// Path to the gif to play. Animated pngs and webps work too
+    pub path: PathBuf,
+
+    /// Where to put the center of the region. Accepts the same values as
+    /// `swww img --transition-pos`
+    #[arg(long, default_value = "center", value_parser = parse_coords)]
+    pub pos: CliPosition,
+
+    /// Resize the gif to <width>x<height> pixels before playing it
+    #[arg(long, value_parser = parse_size)]
+    pub size: Option<(u32, u32)>,
+
+    /// Comma separated list of outputs to play the gif at.
+    /// Names of groups created with `swww group` can be used as well.
+    ///
+    /// If it isn't set, the gif is played on all outputs. It must fit entirely inside of them.
+    #[arg(short, long, default_value = "")]
+    pub outputs: String,
+}
+
+#[derive(Parser)]
+pub struct Generate {
+    /// Comma separated list of outputs to display the generated image at.
+    /// Names of groups created with `swww group` can be used as well.
+    ///
+    /// If it isn't set, the image is displayed on all outputs.
+    #[arg(short, long, default_value = "", global = true)]
+    pub outputs: String,
+
+    #[command(subcommand)]
+    pub generator: Generator,
+}
+
+#[derive(Subcommand)]
+pub enum Generator {
+    ///A gradient going through multiple colors
+    Gradient {
+        ///Comma separated list of colors, in rrggbb or rgb format
+        ///
+        ///eg: '#123,#456,#789'
+        #[arg(short, long, value_parser = from_hex, value_delimiter = ',', required = true)]
+        colors: Vec<[u8; 3]>,
+
+        ///Angle of the gradient, in degrees. '0' goes from left to right and '90' from bottom to top
+        #[arg(long, default_value = "0")]
+        angle: f64,
+
+        ///Slowly shift the gradient's colors, completing a full cycle in the given duration
+        ///
+        ///The duration can be given in ms, s, m, h or d, eg: '60s'. Note that, when animated,
+        ///the gradient wraps around back to its first color.
+        #[arg(long, value_parser = parse_duration)]
+        animate: Option<Duration>,
+
+        ///Frame rate of the animation. Since it is meant to be slow, you can usually keep this low
+        #[arg(long, default_value = "10", value_parser = clap::value_parser!(u8).range(1..))]
+        fps: u8,
+    },
+
+    ///An animated plasma, made out of Perlin noise
+    Plasma {
+        ///Comma separated list of colors, in rrggbb or rgb format, used as the plasma's palette
+        #[arg(
+            short,
+            long,
+            value_parser = from_hex,
+            value_delimiter = ',',
+            default_value = "1a1a2e,16213e,0f3460,e94560"
+        )]
+        colors: Vec<[u8; 3]>,
 
-#[derive(Clone)]
-pub enum CliCoord {
-    Percent(f32),
-    Pixel(f32),
-}
+        ///Size of the plasma's features, in pixels. Larger values make for smoother blobs
+        #[arg(long, default_value = "400")]
+        scale: f32,
 
-#[derive(Clone)]
-pub struct CliPosition {
-    pub x: CliCoord,
-    pub y: CliCoord,
-    //Unknown(f32, f32),
-}
+        ///How fast the plasma changes. '0' makes it static
+        #[arg(long, default_value = "1.0")]
+        speed: f32,
 
-impl CliPosition {
-    pub fn new(x: CliCoord, y: CliCoord) -> Self {
-        Self { x, y }
-    }
-}
+        ///Frame rate of the animation
+        #[arg(long, default_value = "10", value_parser = clap::value_parser!(u8).range(1..))]
+        fps: u8,
+    },
 
-#[derive(Parser)]
-#[command(version, name = "swww")]
-///A Solution to your Wayland Wallpaper Woes
-///
-///Change what your monitors display as a background by controlling the swww daemon at runtime.
-///Supports animated gifs and putting different stuff in different monitors. I also did my best to
-///make it as resource efficient as possible.
-///
-///Note `swww` will only work in a compositor that implements the layer-shell protocol. Typically,
-///wlr-roots based compositors.
-pub enum Swww {
-    ///Fills the specified outputs with the given color.
+    ///The current time, drawn over whatever is currently being displayed
     ///
-    ///Defaults to filling all outputs with black.
-    Clear(Clear),
+    ///The clock is only redrawn when its text changes (so, with the default format, once a
+    ///minute).
+    Clock {
+        ///Format of the clock, in strftime format
+        ///
+        ///eg: '%H:%M' for hours and minutes, or '%a %d %b' for something like 'Mon 01 Jan'
+        #[arg(long, default_value = "%H:%M")]
+        format: String,
+
+        ///Path to the font to draw the text with (any ttf or otf file)
+        #[arg(long)]
+        font: PathBuf,
+
+        ///Font size, in pixels
+        #[arg(long, default_value = "120")]
+        size: f32,
+
+        ///Color of the text, in rrggbb or rgb format
+        #[arg(long, value_parser = from_hex, default_value = "ffffff")]
+        color: [u8; 3],
+
+        ///Where to put the center of the text. Accepts the same values as
+        ///`swww img --transition-pos`
+        #[arg(long, default_value = "center", value_parser = parse_coords)]
+        pos: CliPosition,
+    },
 
-    /// Sends an image (or animated gif) for the daemon to display.
+    ///Conway's Game of Life, seeded from whatever is currently being displayed
     ///
-    /// Use `-` to read from stdin
-    Img(Img),
+    ///Brighter parts of the current image start with more living cells. The animation stops once
+    ///the cells settle into a still pattern.
+    Life {
+        ///Size of each cell, in pixels
+        #[arg(long, default_value = "8", value_parser = clap::value_parser!(u32).range(1..))]
+        cell_size: u32,
 
-    /// Initializes the daemon.
+        ///Color of the living cells, in rrggbb or rgb format
+        #[arg(long, value_parser = from_hex, default_value = "ffffff")]
+        alive: [u8; 3],
+
+        ///Color of the dead cells, in rrggbb or rgb format
+        #[arg(long, value_parser = from_hex, default_value = "000000")]
+        dead: [u8; 3],
+
+        ///How many generations to step through per second
+        #[arg(long, default_value = "5", value_parser = clap::value_parser!(u8).range(1..))]
+        fps: u8,
+    },
+
+    ///Frames drawn by an external program
     ///
-    /// Exits if there is already a daemon running. We check that by seeing if
-    /// $XDG_RUNTIME_DIR/swww.socket exists.
-    Init {
-        ///Don't fork the daemon. This will keep it running in the current terminal.
+    ///The daemon starts the program and, for every frame, writes a line with
+    ///'<frame> <width> <height> <deadline>' to its stdin, where the deadline is in microseconds
+    ///since the unix epoch. The program must answer every line by writing width * height * 4 bytes
+    ///of rgba to its stdout. Frames arriving after their deadline are dropped, and no new frame is
+    ///requested until the late one arrives.
+    ///
+    ///eg: swww generate external --fps 30 -- my-shader-renderer --some-flag
+    External {
+        ///Frame rate we will ask frames at
+        #[arg(long, default_value = "30", value_parser = clap::value_parser!(u8).range(1..))]
+        fps: u8,
+
+        ///The program to run, followed by its arguments
+        #[arg(trailing_var_arg = true, allow_hyphen_values = true, required = true)]
+        command: Vec<String>,
+    },
+
+    ///A test pattern drawn at each output's exact resolution, for calibrating monitors
+    ///
+    ///Since the pattern is drawn by the daemon pixel by pixel, it is also a good way of checking
+    ///whether the compositor scales or alters the colors of what we display.
+    #[command(name = "testpattern")]
+    TestPattern {
+        ///Which pattern to draw
         ///
-        ///The only advantage of this would be seeing the logging real time. Note that for release
-        ///builds we only log info, warnings and errors, so you won't be seeing much (ideally).
-        #[clap(long)]
-        no_daemon: bool,
+        ///Possible patterns:
+        ///
+        ///bars: SMPTE color bars, with a PLUGE for setting the black level
+        ///
+        ///ramps: smooth and stepped ramps of gray, red, green and blue, for spotting banding
+        ///
+        ///grid: an alignment grid with a circle, a crosshair and 1 pixel checkerboards at the
+        ///corners, which look gray unless something is scaling the image
+        #[arg(long, default_value = "bars", hide_possible_values = true)]
+        pattern: TestPattern,
     },
+}
 
-    ///Kills the daemon
-    Kill,
+#[derive(Parser, Clone)]
+pub struct Img {
+    /// Path to the image to display. Use `-` (or `--stdin`) to read it from stdin
+    ///
+    /// With a directory, the daemon displays its images one after the other, each for
+    /// `--interval`, until the slideshow is stopped with `--stop-slideshow`.
+    #[arg(required_unless_present_any = ["stdin", "stop_slideshow"])]
+    pub path: Option<PathBuf>,
 
-    ///Asks the daemon to print output information (names and dimensions).
+    /// Stop the slideshows of the outputs (of every output, without `--outputs`), leaving them at
+    /// their current image
+    #[arg(long, conflicts_with_all = ["path", "stdin"])]
+    pub stop_slideshow: bool,
+
+    /// How long each image of a directory is displayed, like 30s, 5m or 1h
+    #[arg(long, default_value = "5m", value_parser = parse_duration)]
+    pub interval: Duration,
+
+    /// How long before each image of a directory is due to start preparing it, like 10s
     ///
-    ///You may use this to find out valid values for the <swww-img --outputs> option. If you want
-    ///more detailed information about your outputs, I would recommend trying wlr-randr.
-    Query,
-}
+    /// The daemon decodes and resizes it at low priority in the meantime, so that the switch is
+    /// instant and doesn't spike the cpu (eg.: while gaming). At most `--interval`.
+    #[arg(long, default_value = "10s", value_parser = parse_duration)]
+    pub lead_time: Duration,
 
-#[derive(Parser)]
-pub struct Clear {
-    /// Color to fill the screen with.
+    /// The order to display the images of a directory in
     ///
-    /// Must be given in rrggbb format (note there is no prepended '#').
-    #[arg(value_parser = from_hex, default_value = "000000")]
-    pub color: [u8; 3],
+    /// Available options are:
+    ///
+    /// name | mtime | random
+    ///
+    /// 'name' goes by file name, 'mtime' from the oldest image to the newest one, and 'random'
+    /// changes the order with every round through the directory (the same orders every time,
+    /// with `--seed`).
+    #[arg(long, default_value = "name", hide_possible_values = true)]
+    pub sort: SlideshowSort,
 
-    /// Comma separated list of outputs to display the image at.
+    /// Only display the images of a directory modified in this long, like 30d or 12h
+    #[arg(long, value_parser = parse_duration)]
+    pub newer_than: Option<Duration>,
+
+    /// Only display the images of a directory at least this large, like 1920x1080
+    #[arg(long, value_parser = parse_size)]
+    pub min_resolution: Option<(u32, u32)>,
+
+    /// Read the image's encoded bytes from stdin, eg.: `curl <url> | swww img --stdin`
+    #[arg(long, conflicts_with = "path")]
+    pub stdin: bool,
+
+    /// Format of the image, instead of guessing it from its contents
     ///
-    /// If it isn't set, the image is displayed on all outputs.
-    #[clap(short, long, default_value = "")]
-    pub outputs: String,
-}
+    /// Some formats (like tga) can't be told apart by their contents alone, so this is mostly
+    /// useful with `--stdin`, where there is no file extension to go by either.
+    ///
+    /// Available options are:
+    ///
+    /// bmp | farbfeld | gif | jpeg | png | pnm | tga | tiff | webp | xbm
+    #[arg(long, ignore_case = true, hide_possible_values = true)]
+    pub format: Option<ImgFormat>,
 
-#[derive(Parser)]
-pub struct Img {
-    /// Path to the image to display
-    pub path: PathBuf,
+    /// Color to draw the set bits of X11 bitmaps (xbm) with
+    ///
+    /// Bitmaps are monochrome, and have no colors of their own. They are usually small patterns,
+    /// meant to be repeated with `--resize tile`.
+    #[arg(long, value_parser = from_hex, default_value = "000000")]
+    pub fg: [u8; 3],
+
+    /// Color to draw the unset bits of X11 bitmaps (xbm) with
+    #[arg(long, value_parser = from_hex, default_value = "ffffff")]
+    pub bg: [u8; 3],
 
     /// Comma separated list of outputs to display the image at.
+    /// Names of groups created with `swww group` can be used as well.
     ///
     /// If it isn't set, the image is displayed on all outputs.
     #[arg(short, long, default_value = "")]
     pub outputs: String,
 
+    /// How to resize the image to the screen's size
+    ///
+    /// Available options are:
+    ///
+    /// crop | fit | no | stretch | tile
+    ///
+    /// 'crop' resizes the image to cover the whole screen, cropping whatever doesn't fit. 'fit'
+    /// resizes it to fit inside the screen, keeping all of it visible, and 'no' (or 'center')
+    /// centers it without resizing. With 'fit' and 'no', the space around the image is filled
+    /// according to `--fill-mode`. 'stretch' resizes it to exactly the screen's size, distorting
+    /// it if their aspect ratios differ. 'tile' repeats the image, at its original size, from the
+    /// screen's top left corner until it covers the whole screen.
+    #[arg(long, default_value = "crop", hide_possible_values = true)]
+    pub resize: ResizeStrategy,
+
     /// Do not resize the image
     ///
-    /// If this is set, the image won't be resized, and will be centralized in the middle of the
-    /// screen instead. If it is smaller than the screen's size, it will be padded with the value
-    /// of `fill_color`, below.
-    #[arg(long)]
+    /// The same as `--resize no`.
+    #[arg(long, conflicts_with = "resize")]
     pub no_resize: bool,
 
-    /// Which color to fill the padding with when not resizing
+    /// Which color to fill the padding with when not resizing, or when using `--margin` or
+    /// `--corner-radius`
     #[arg(value_parser = from_hex, long, default_value = "000000")]
     pub fill_color: [u8; 3],
 
+    /// How to fill the space around the image with `--resize fit` or `--resize no`, and what
+    /// `--rotate-deg` uncovers
+    ///
+    /// Available options are:
+    ///
+    /// color | blur | edge | dominant
+    ///
+    /// 'color' uses `--fill-color`, 'blur' a blurred copy of the image covering the whole screen,
+    /// 'edge' stretches the image's edges, and 'dominant' uses the image's most common color.
+    #[arg(long, default_value = "color", hide_possible_values = true)]
+    pub fill_mode: FillMode,
+
+    /// Draw the image inset by this many pixels from every edge of the screen
+    ///
+    /// The space around it is filled with `fill_color`.
+    #[arg(long, default_value = "0")]
+    pub margin: u32,
+
+    /// Round the image's corners with this radius, in pixels
+    ///
+    /// This is mostly useful together with `--margin`.
+    #[arg(long, default_value = "0")]
+    pub corner_radius: u32,
+
+    /// Rotate the image clockwise by this many degrees, around the screen's center
+    ///
+    /// This is meant for screens that are physically tilted. Anything other than a multiple of
+    /// 180 degrees uncovers parts of the screen, which are filled according to `--fill-mode`.
+    #[arg(long, default_value = "0", allow_negative_numbers = true, value_parser = parse_degrees)]
+    pub rotate_deg: f32,
+
     ///Filter to use when scaling images (run swww img --help to see options).
     ///
     ///Available options are:
@@ -217,7 +1456,7 @@ pub struct Img {
     ///For non pixel art stuff, I would usually recommend one of the last three, though some
     ///experimentation will be necessary to see which one you like best. Also note they are
     ///all slower than Nearest.
-    #[arg(short, long, default_value = "Lanczos3")]
+    #[arg(short, long, default_value = "Lanczos3", hide_possible_values = true)]
     pub filter: Filter,
 
     ///Sync the animations' frames between the monitors
@@ -230,15 +1469,106 @@ pub struct Img {
     ///Furthermore, if you only set `--sync` to one monitor and not the others, that monitor will
     ///not animate anything, while the others will animate out-of-sync.
     ///
-    ///Finally, note this only applies to `gif` animations, not the transitions.
+    ///Finally, note this only applies to animations, not the transitions.
     #[arg(long, default_value = "false")]
     pub sync: bool,
 
+    ///Ignore changes between the frames of animations no bigger than this, in every color
+    ///
+    ///Noisy animations (like videos or grainy gifs) change almost every pixel every frame, which
+    ///makes them take a lot of memory. Small values, like 4, drastically shrink them, but note this
+    ///does lower their quality: large values will leave visible artifacts behind.
+    #[arg(long, default_value = "0")]
+    pub diff_threshold: u8,
+
+    ///Store a full copy of every this many frames of animations. 0 disables it
+    ///
+    ///Animation frames are stored as the difference from the previous one, so an animation that
+    ///falls behind (because the compositor stalled, for example) has to replay every frame to
+    ///catch up. With keyframes, it can jump straight to the latest one instead. Keyframes take
+    ///much more memory than regular frames, so only long animations have them by default.
+    #[arg(long, default_value = "60")]
+    pub keyframe_interval: u32,
+
+    ///Crossfade between the frames of animations that stay on screen for longer than 500ms,
+    ///taking this long (250ms if not given)
+    ///
+    ///Slideshow-like gifs, with frames that last for seconds, otherwise snap from one frame to the
+    ///next. The crossfade is capped at half of the frame's duration, and doesn't change how long
+    ///the animation takes. Note the in-between frames make the animation take more memory.
+    #[arg(long, num_args = 0..=1, default_missing_value = "250ms", value_parser = parse_duration)]
+    pub anim_blend: Option<Duration>,
+
+    ///Crossfade from the last frame of animations back into the first one, taking this long
+    ///(250ms if not given)
+    ///
+    ///Many gifs jump abruptly from their last frame to their first one when looping. The
+    ///crossfade is capped at half of the first frame's duration, and animations whose last frame
+    ///already looks like their first one are left alone.
+    #[arg(long, num_args = 0..=1, default_missing_value = "250ms", value_parser = parse_duration)]
+    pub anim_smooth_loop: Option<Duration>,
+
+    ///While processing animations, have the daemon draw a thin progress bar at the bottom of the
+    ///outputs
+    ///
+    ///The animation's first frame is displayed right away, and processing big gifs can take a
+    ///while, during which it would otherwise look frozen.
+    #[arg(long)]
+    pub busy_indicator: bool,
+
+    ///Decode and resize the image right away, but only display it after this long, like '10s'
+    ///
+    ///Scripts that know when the next image is due can start this a little before, so that the
+    ///image is ready by then and the switch is instant (slideshows of directories do this on their
+    ///own, see `--lead-time`). Run it with `nice`, so that preparing the image doesn't compete with
+    ///what you are doing, eg.: nice -n 19 swww img next.png --defer 10s
+    #[arg(long, value_parser = parse_duration)]
+    pub defer: Option<Duration>,
+
+    ///Only send the image to the outputs that don't have a wallpaper yet
+    ///
+    ///That is, the ones still displaying a plain color, like right after `swww init` (without a
+    ///cached image to restore) or `swww clear`. This lets login scripts set a default without
+    ///replacing whatever was already chosen. If every output has a wallpaper, nothing is sent.
+    #[arg(long)]
+    pub if_unset: bool,
+
+    #[command(flatten)]
+    pub transition: Transition,
+
+    ///Image to draw over the main one. Can be given multiple times, to draw several layers
+    ///
+    ///Layers are drawn in the order they are given, at their original size, and can be followed by
+    ///':'-separated options:
+    ///
+    ///  opacity=<0.0-1.0>: how opaque the layer is (default is 1.0)
+    ///
+    ///  pos=<x,y>: where to put the center of the layer. Accepts the same values as
+    ///  `--transition-pos` (default is 'center')
+    ///
+    ///eg: swww img photo.png --layer logo.png:opacity=0.4:pos=bottom-right
+    #[arg(long, value_parser = parse_layer)]
+    pub layer: Vec<CliLayer>,
+
+    ///Quantize the image (and every frame of animations) to a fixed palette, with dithering
+    ///
+    ///This can be the path to a GIMP palette (.gpl), 'auto:<n>', to pick the n colors that best
+    ///represent the image, or one of the builtin palettes:
+    ///
+    ///gameboy | cga | pico8 | mono
+    #[arg(long)]
+    pub palette: Option<String>,
+}
+
+///Options controlling the transition into a new image or color
+#[derive(Args, Clone)]
+pub struct Transition {
     ///Sets the type of transition. Default is 'simple', that fades into the new image
     ///
     ///Possible transitions are:
     ///
-    ///simple | left | right | top | bottom | wipe | wave | grow | center | any | outer | random
+    ///simple | left | right | top | bottom | wipe | wave | grow | center | any | outer | flash |
+    ///slide | random
     ///
     ///The 'left', 'right', 'top' and 'bottom' options make the transition happen from that
     ///position to its opposite in the screen.
@@ -256,8 +1586,22 @@ pub struct Img {
     ///
     ///'outer' is the same as grow but the circle shrinks instead of growing.
     ///
-    ///Finally, 'random' will select a transition effect at random
-    #[arg(short, long, env = "SWWW_TRANSITION", default_value = "simple")]
+    ///'flash' fades into the `--flash-color`, and then into the new image.
+    ///
+    ///'slide' moves the new image in over the old one, in the direction of `--transition-angle`.
+    ///
+    ///'random' will select a transition effect at random.
+    ///
+    ///Finally, 'plugin:NAME' has the daemon's plugin called NAME draw the transition, see
+    ///swww-daemon(1). Daemons built without plugins refuse it
+    #[arg(
+        short,
+        long,
+        env = "SWWW_TRANSITION",
+        default_value = "simple",
+        hide_possible_values = true,
+        value_parser = TransitionTypeParser
+    )]
     pub transition_type: TransitionType,
 
     ///How fast the transition approaches the new image.
@@ -266,20 +1610,22 @@ pub struct Img {
     ///old image transforms in the new one. This controls by how much we add or subtract.
     ///
     ///Larger values will make the transition faster, but more abrupt. A value of 255 will always
-    ///switch to the new image immediately.
+    ///switch to the new image immediately, while 1 is the slowest it can go.
     ///
-    /// This defaults to 2 when transition-type is 'simple', and 90 otherwise
+    /// This defaults to 2 when transition-type is 'simple', 20 when it is 'flash', and 90 otherwise
     #[arg(
         long,
         env = "SWWW_TRANSITION_STEP",
+        value_parser = clap::value_parser!(u8).range(1..),
         default_value = "90",
-        default_value_if("transition_type", "simple", "2")
+        default_value_if("transition_type", "simple", "2"),
+        default_value_if("transition_type", "flash", "20")
     )]
     pub transition_step: u8,
 
     ///How long the transition takes to complete in seconds.
     ///
-    ///Note that this doesn't work with the 'simple' transition
+    ///Note that this doesn't work with the 'simple' and 'flash' transitions
     #[arg(long, env = "SWWW_TRANSITION_DURATION", default_value = "3")]
     pub transition_duration: f32,
 
@@ -289,16 +1635,23 @@ pub struct Img {
     ///
     ///Also note this is **different** from the transition-step. That one controls by how much we
     ///approach the new image every frame.
-    #[arg(long, env = "SWWW_TRANSITION_FPS", default_value = "30")]
+    #[arg(
+        long,
+        env = "SWWW_TRANSITION_FPS",
+        default_value = "30",
+        value_parser = clap::value_parser!(u8).range(1..)
+    )]
     pub transition_fps: u8,
 
-    ///This is used for the 'wipe' and 'wave' transitions. It controls the angle of the wipe
+    ///This is used for the 'wipe', 'wave' and 'slide' transitions, and given to 'plugin:NAME' ones.
+    ///It controls the angle of the wipe
     ///
     ///Note that the angle is in degrees, where '0' is right to left and '90' is top to bottom, and '270' bottom to top
     #[arg(long, env = "SWWW_TRANSITION_ANGLE", default_value = "45")]
     pub transition_angle: f64,
 
-    ///This is only used for the 'grow','outer' transitions. It controls the center of circle (default is 'center').
+    ///This is only used for the 'grow','outer' transitions, and given to 'plugin:NAME' ones. It
+    ///controls the center of circle (default is 'center').
     ///
     ///Position values can be given in both percentage values and pixel values:
     ///  float values are interpreted as percentages and integer values as pixel values
@@ -320,6 +1673,184 @@ pub struct Img {
     ///currently only used for 'wave' transition to control the width and height of each wave
     #[arg(long, env = "SWWW_TRANSITION_WAVE", default_value = "20,20", value_parser = parse_wave)]
     pub transition_wave: (f32, f32),
+
+    ///Only used for the 'flash' transition. The color the screen fades through, in rrggbb or rgb
+    ///format (a leading '#' is fine)
+    #[arg(long, env = "SWWW_TRANSITION_FLASH_COLOR", default_value = "ffffff", value_parser = from_hex)]
+    pub flash_color: [u8; 3],
+
+    ///Swap the images right away, without a transition, when they differ by less than this
+    ///percentage on average
+    ///
+    ///Every color of every pixel counts, so a few pixels changing completely (like the digits of a
+    ///clock) weigh as little as the whole image getting slightly brighter. Useful when sending
+    ///images that are regenerated all the time, and often barely change, like '0.5'. Default is
+    ///0, which always transitions.
+    #[arg(long, env = "SWWW_TRANSITION_SKIP_THRESHOLD", default_value = "0", value_parser = parse_percent)]
+    pub transition_skip_threshold: f32,
+
+    ///How the transition should be coordinated between the outputs. Default is 'none'
+    ///
+    ///Possible values are:
+    ///
+    ///none | layout
+    ///
+    ///'none' makes every output run its own, independent, transition: an output that is slow to
+    ///take frames doesn't hold back the others, and a new image sent to one of them only
+    ///interrupts its own transition.
+    ///
+    ///'layout' makes the 'wipe', 'wave', 'grow' and 'outer' transitions use each output's position
+    ///in the global layout, so that the effect looks like a single transition traveling across all
+    ///of them. Note that, with this, `--transition-pos` becomes relative to the area covered by all
+    ///the outputs, both it and `--transition-wave` are in logical pixels, and outputs with the same
+    ///dimensions can no longer share the same resized image, so it will take a little longer to
+    ///process multiple outputs.
+    #[arg(
+        long,
+        env = "SWWW_TRANSITION_SYNC",
+        default_value = "none",
+        hide_possible_values = true
+    )]
+    pub transition_sync: TransitionSync,
+
+    ///Seed for the choices made by the 'any' and 'random' transitions, and by `--sort random`
+    ///
+    ///With the same seed (and the same version of swww), they always make the same choices,
+    ///which is useful to reproduce a setup exactly, like when recording a demo or debugging an
+    ///artifact that only shows up with certain parameters.
+    #[arg(long, env = "SWWW_SEED")]
+    pub seed: Option<u64>,
+
+    ///Print how many frames the transition is expected to take, and how long it will take, in
+    ///seconds, separated by a space
+    ///
+    ///This is an estimate made by the daemon as soon as the transition starts, meant for scripts
+    ///that want to do something right when the transition ends.
+    #[arg(long)]
+    pub print_duration: bool,
+
+    ///Print, to stderr, the options that were applied and where each came from
+    ///
+    ///That is, whether they were given in the command line, read from the environment or left
+    ///to their defaults, along with the transition picked for 'random' and the like, and the
+    ///size the image was prepared at for each output. Useful to find out why a setting isn't
+    ///being applied.
+    #[arg(long)]
+    pub verbose: bool,
+}
+
+/// Parses layers in the format '<path>[:opacity=<opacity>][:pos=<position>]'
+fn parse_layer(raw: &str) -> Result<CliLayer, String> {
+    let mut path = raw;
+    let mut opacity = 1.0;
+    let mut pos = CliPosition::new(CliCoord::Percent(0.5), CliCoord::Percent(0.5));
+    // We go through the options from the end, so that paths containing ':' still work
+    while let Some((rest, option)) = path.rsplit_once(':') {
+        match option.split_once('=') {
+            Some(("opacity", value)) => {
+                opacity = match value.parse::<f32>() {
+                    Ok(o) if (0.0..=1.0).contains(&o) => o,
+                    _ => return Err(format!("invalid opacity (must be in [0,1]): {value}")),
+                }
+            }
+            Some(("pos", value)) => pos = parse_coords(value)?,
+            _ => break,
+        }
+        path = rest;
+    }
+
+    if path.is_empty() {
+        return Err(format!("missing path in layer: {raw}"));
+    }
+    Ok(CliLayer {
+        path: PathBuf::from(path),
+        opacity,
+        pos,
+    })
+}
+
+/// Parses sizes in the format '<width>x<height>'
+fn parse_degrees(raw: &str) -> Result<f32, String> {
+    match raw.trim().parse::<f32>() {
+        Ok(degrees) if degrees.is_finite() => Ok(degrees),
+        _ => Err(format!("invalid angle: {raw}")),
+    }
+}
+
+fn parse_gamma(raw: &str) -> Result<f32, String> {
+    match raw.trim().parse::<f32>() {
+        Ok(gamma) if (0.1..=10.0).contains(&gamma) => Ok(gamma),
+        _ => Err(format!("gamma must be a number from 0.1 to 10, got {raw}")),
+    }
+}
+
+/// Effects can only come from plugins for now, but the prefix leaves room for built-in ones
+fn parse_effect(raw: &str) -> Result<String, String> {
+    match raw.strip_prefix("plugin:") {
+        Some("") => Err("the plugin's name is missing, expected plugin:NAME".to_string()),
+        Some(name) => Ok(name.to_string()),
+        None => Err(format!("expected plugin:NAME, got {raw}")),
+    }
+}
+
+fn parse_percent(raw: &str) -> Result<f32, String> {
+    match raw.trim().trim_end_matches('%').parse::<f32>() {
+        Ok(percent) if (0.0..=100.0).contains(&percent) => Ok(percent),
+        _ => Err(format!("expected a percentage from 0 to 100, got {raw}")),
+    }
+}
+
+fn parse_progress(raw: &str) -> Result<f32, String> {
+    match raw.trim().parse::<f32>() {
+        Ok(progress) if (0.0..=1.0).contains(&progress) => Ok(progress),
+        _ => Err(format!("progress must be a number from 0 to 1, got {raw}")),
+    }
+}
+
+fn parse_size(raw: &str) -> Result<(u32, u32), String> {
+    let parse = |s: &str| match s.trim().parse::<u32>() {
+        Ok(n) if n > 0 => Ok(n),
+        _ => Err(format!("invalid size: {raw}")),
+    };
+    match raw.split_once('x') {
+        Some((width, height)) => Ok((parse(width)?, parse(height)?)),
+        None => Err(format!(
+            "size must be in the format <width>x<height>, found: {raw}"
+        )),
+    }
+}
+
+/// Parses safe areas in the format '<side>:<pixels>,...', or 'none'
+fn parse_safe_area(raw: &str) -> Result<CliSafeArea, String> {
+    let mut area = CliSafeArea::default();
+    if raw == "none" {
+        return Ok(area);
+    }
+    for side in raw.split(',') {
+        let (name, pixels) = match side.split_once(':') {
+            Some((name, pixels)) => match pixels.trim().parse::<u32>() {
+                Ok(pixels) => (name.trim(), pixels),
+                Err(e) => return Err(format!("invalid number of pixels in {side}: {e}")),
+            },
+            None => {
+                return Err(format!(
+                    "sides must be in the format <side>:<pixels>, found: {side}"
+                ))
+            }
+        };
+        match name {
+            "top" => area.top = pixels,
+            "bottom" => area.bottom = pixels,
+            "left" => area.left = pixels,
+            "right" => area.right = pixels,
+            _ => {
+                return Err(format!(
+                    "unrecognized side: {name}. Valid sides are top, bottom, left and right"
+                ))
+            }
+        }
+    }
+    Ok(area)
 }
 
 fn parse_wave(raw: &str) -> Result<(f32, f32), String> {
@@ -454,6 +1985,174 @@ mod tests {
         );
     }
 
+    #[test]
+    fn should_convert_short_colors_from_hex() {
+        assert_eq!(from_hex("#123").unwrap(), [0x11, 0x22, 0x33]);
+        assert_eq!(from_hex("fff").unwrap(), [255, 255, 255]);
+    }
+
+    #[test]
+    fn should_parse_durations() {
+        assert_eq!(parse_duration("60s").unwrap(), Duration::from_secs(60));
+        assert_eq!(parse_duration("60").unwrap(), Duration::from_secs(60));
+        assert_eq!(parse_duration("1.5m").unwrap(), Duration::from_secs(90));
+        assert_eq!(parse_duration("250ms").unwrap(), Duration::from_millis(250));
+        assert_eq!(parse_duration("2h").unwrap(), Duration::from_secs(7200));
+        assert!(parse_duration("10 parsecs").is_err());
+        assert!(parse_duration("s").is_err());
+        assert!(parse_duration("-1s").is_err());
+    }
+
+    #[test]
+    fn should_parse_layers() {
+        let layer = parse_layer("logo.png").unwrap();
+        assert_eq!(layer.path, PathBuf::from("logo.png"));
+        assert_eq!(layer.opacity, 1.0);
+
+        let layer = parse_layer("a:b.png:pos=10,20:opacity=0.4").unwrap();
+        assert_eq!(layer.path, PathBuf::from("a:b.png"));
+        assert_eq!(layer.opacity, 0.4);
+        assert!(matches!(layer.pos.x, CliCoord::Pixel(x) if x == 10.0));
+        assert!(matches!(layer.pos.y, CliCoord::Pixel(y) if y == 20.0));
+
+        assert!(parse_layer("logo.png:opacity=2").is_err());
+        assert!(parse_layer(":opacity=0.5").is_err());
+    }
+
+    #[test]
+    fn img_should_need_either_a_path_or_stdin() {
+        let img = Img::try_parse_from(["img", "--stdin", "--format", "PNG"]).unwrap();
+        assert!(img.path.is_none());
+        assert!(matches!(img.format, Some(ImgFormat::Png)));
+        assert!(Img::try_parse_from(["img", "a.png"]).is_ok());
+        assert!(Img::try_parse_from(["img"]).is_err());
+        assert!(Img::try_parse_from(["img", "--stdin", "a.png"]).is_err());
+        assert!(Img::try_parse_from(["img", "--stdin", "--format", "svg"]).is_err());
+    }
+
+    #[test]
+    fn should_parse_transition_plugins() {
+        let img = Img::try_parse_from(["img", "a.png", "-t", "plugin:ripple"]).unwrap();
+        assert!(matches!(
+            img.transition.transition_type,
+            TransitionType::Plugin(name) if name == "ripple"
+        ));
+        let img = Img::try_parse_from(["img", "a.png", "-t", "wave"]).unwrap();
+        assert!(matches!(
+            img.transition.transition_type,
+            TransitionType::Wave
+        ));
+        assert!(Img::try_parse_from(["img", "a.png", "-t", "plugin:"]).is_err());
+        assert!(Img::try_parse_from(["img", "a.png", "-t", "ripple"]).is_err());
+    }
+
+    #[test]
+    fn no_resize_should_conflict_with_resize() {
+        let img = Img::try_parse_from(["img", "a.png", "--no-resize"]).unwrap();
+        assert_eq!(img.resize, ResizeStrategy::Crop);
+        assert!(img.no_resize);
+        let img = Img::try_parse_from(["img", "a.png", "--resize", "fit"]).unwrap();
+        assert_eq!(img.resize, ResizeStrategy::Fit);
+        assert!(Img::try_parse_from(["img", "a.png", "--resize", "fit", "--no-resize"]).is_err());
+        let img = Img::try_parse_from(["img", "a.png", "--resize", "center"]).unwrap();
+        assert_eq!(img.resize, ResizeStrategy::No);
+        let img = Img::try_parse_from(["img", "a.png", "--rotate-deg", "-2.5"]).unwrap();
+        assert_eq!(img.rotate_deg, -2.5);
+        assert!(Img::try_parse_from(["img", "a.png", "--rotate-deg", "inf"]).is_err());
+    }
+
+    #[test]
+    fn defer_should_take_a_duration() {
+        let img = Img::try_parse_from(["img", "a.png", "--defer", "1.5m"]).unwrap();
+        assert_eq!(img.defer, Some(Duration::from_secs(90)));
+        assert!(Img::try_parse_from(["img", "a.png"])
+            .unwrap()
+            .defer
+            .is_none());
+        assert!(Img::try_parse_from(["img", "a.png", "--defer", "soon"]).is_err());
+    }
+
+    #[test]
+    fn bitmaps_should_take_short_hex_colors() {
+        let img = Img::try_parse_from([
+            "img", "a.xbm", "--fg", "#888", "--bg", "#222", "--resize", "tile",
+        ])
+        .unwrap();
+        assert_eq!((img.fg, img.bg), ([0x88; 3], [0x22; 3]));
+        assert_eq!(img.resize, ResizeStrategy::Tile);
+    }
+
+    #[test]
+    fn flash_should_default_to_a_slower_step() {
+        let img = Img::try_parse_from(["img", "a.png", "-t", "flash", "--flash-color", "#f00"]);
+        let img = img.unwrap();
+        assert_eq!(img.transition.transition_step, 20);
+        assert_eq!(img.transition.flash_color, [255, 0, 0]);
+    }
+
+    #[test]
+    fn transition_step_and_fps_should_not_be_zero() {
+        let img = Img::try_parse_from(["img", "a.png", "--transition-step", "255"]).unwrap();
+        assert_eq!(img.transition.transition_step, 255);
+        assert!(Img::try_parse_from(["img", "a.png", "--transition-step", "0"]).is_err());
+        assert!(Img::try_parse_from(["img", "a.png", "--transition-fps", "0"]).is_err());
+        assert!(Img::try_parse_from(["img", "a.png", "--transition-fps", "256"]).is_err());
+    }
+
+    #[test]
+    fn adjust_should_accept_negative_brightness() {
+        let adjust = Adjust::try_parse_from(["adjust", "--brightness", "-20"]).unwrap();
+        assert_eq!(adjust.brightness, -20);
+        assert!(Adjust::try_parse_from(["adjust", "--brightness", "-101"]).is_err());
+        let adjust = Adjust::try_parse_from(["adjust", "--contrast", "-50", "--gamma", "2.2"]);
+        let adjust = adjust.unwrap();
+        assert_eq!((adjust.contrast, adjust.gamma), (-50, 2.2));
+        assert!(Adjust::try_parse_from(["adjust", "--gamma", "0"]).is_err());
+    }
+
+    #[test]
+    fn adjust_should_only_accept_effect_plugins() {
+        let adjust = Adjust::try_parse_from(["adjust", "--effect", "plugin:sepia"]).unwrap();
+        assert_eq!(adjust.effect.unwrap(), "sepia");
+        assert!(Adjust::try_parse_from(["adjust", "--effect", "plugin:"]).is_err());
+        assert!(Adjust::try_parse_from(["adjust", "--effect", "sepia"]).is_err());
+    }
+
+    #[test]
+    fn subscribe_should_need_something_to_subscribe_to() {
+        assert!(Subscribe::try_parse_from(["subscribe"]).is_err());
+        let subscribe = Subscribe::try_parse_from(["subscribe", "--avg-color"]).unwrap();
+        assert_eq!((subscribe.grid, subscribe.rate), ((1, 1), 10));
+        let subscribe = Subscribe::try_parse_from(["subscribe", "--avg-color", "--grid", "8x1"]);
+        assert_eq!(subscribe.unwrap().grid, (8, 1));
+        assert!(Subscribe::try_parse_from(["subscribe", "--avg-color", "--rate", "0"]).is_err());
+    }
+
+    #[test]
+    fn should_parse_safe_areas() {
+        let area = parse_safe_area("top:40, left:20").unwrap();
+        assert_eq!(
+            area,
+            CliSafeArea {
+                top: 40,
+                left: 20,
+                ..CliSafeArea::default()
+            }
+        );
+        assert_eq!(parse_safe_area("none").unwrap(), CliSafeArea::default());
+        assert!(parse_safe_area("top").is_err());
+        assert!(parse_safe_area("middle:10").is_err());
+        assert!(parse_safe_area("top:-1").is_err());
+    }
+
+    #[test]
+    fn should_parse_sizes() {
+        assert_eq!(parse_size("200x100").unwrap(), (200, 100));
+        assert!(parse_size("200").is_err());
+        assert!(parse_size("0x100").is_err());
+        assert!(parse_size("ax100").is_err());
+    }
+
     #[test]
     fn should_convert_colors_from_hex() {
         let color = from_hex("101010").unwrap();
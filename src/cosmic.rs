@@ -0,0 +1,117 @@
+//! Stand-in for cosmic-bg, COSMIC's wallpaper daemon. COSMIC's settings don't talk to cosmic-bg
+//! directly: they write its configuration into `$XDG_CONFIG_HOME/cosmic`, one file per key, and
+//! cosmic-bg follows it. We do the same, setting the images of the `all` key on every output, and
+//! those of the `output.<name>` keys on their outputs. Colors and gradients aren't supported
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime},
+};
+
+const CONFIG_DIR: &str = "cosmic/com.system76.CosmicBackground/v1";
+
+/// How often we look for changes. Settings are changed by hand, so this doesn't need to be fast
+const POLL: Duration = Duration::from_secs(1);
+
+pub fn run() -> Result<(), String> {
+    let dir = match std::env::var_os("XDG_CONFIG_HOME") {
+        Some(dir) => PathBuf::from(dir),
+        None => match std::env::var_os("HOME") {
+            Some(home) => PathBuf::from(home).join(".config"),
+            None => return Err("failed to read both XDG_CONFIG_HOME and HOME env vars".to_string()),
+        },
+    }
+    .join(CONFIG_DIR);
+
+    // only what changes after we start is applied, we don't want to undo whatever swww displays
+    let mut seen = modified(&dir);
+    loop {
+        std::thread::sleep(POLL);
+        let now = modified(&dir);
+        for (key, time) in &now {
+            if seen.get(key) == Some(time) {
+                continue;
+            }
+            let outputs = match key.as_str() {
+                "all" => "",
+                key => match key.strip_prefix("output.") {
+                    Some(output) => output,
+                    None => continue,
+                },
+            };
+            let result = std::fs::read_to_string(dir.join(key))
+                .map_err(|e| format!("failed to read {key}: {e}"))
+                .and_then(|entry| source(&entry))
+                .and_then(|path| crate::set_img(&path, outputs, &[]).map_err(|e| e.to_string()));
+            if let Err(e) = result {
+                eprintln!("failed to follow cosmic-bg's {key}: {e}");
+            }
+        }
+        seen = now;
+    }
+}
+
+/// When each key in `dir` was last written
+fn modified(dir: &Path) -> HashMap<String, SystemTime> {
+    let Ok(entries) = std::fs::read_dir(dir) else {
+        return HashMap::new();
+    };
+    entries
+        .filter_map(Result::ok)
+        .filter_map(|entry| {
+            let modified = entry.metadata().and_then(|m| m.modified()).ok()?;
+            Some((entry.file_name().into_string().ok()?, modified))
+        })
+        .collect()
+}
+
+/// The image of a cosmic-bg entry, which is written in RON, like:
+/// `(output: "all", source: Path("/a.png"), filter_by_theme: true, ...)`
+fn source(entry: &str) -> Result<PathBuf, String> {
+    let Some(start) = entry.find("source:") else {
+        return Err("entry has no source".to_string());
+    };
+    let source = entry[start + "source:".len()..].trim_start();
+    let Some(path) = source.strip_prefix("Path(").map(str::trim_start) else {
+        return Err("only images are supported, not colors or gradients".to_string());
+    };
+    let mut chars = path.strip_prefix('"').ok_or("invalid source")?.chars();
+    let mut literal = String::new();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => match chars.next().ok_or("invalid source")? {
+                'n' => literal.push('\n'),
+                't' => literal.push('\t'),
+                escaped => literal.push(escaped),
+            },
+            '"' => return Ok(PathBuf::from(literal)),
+            c => literal.push(c),
+        }
+    }
+    Err("invalid source".to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn should_read_the_image_of_entries() {
+        let entry = r#"(
+    output: "all",
+    source: Path("/home/me/My \"Pictures\"/a.png"),
+    filter_by_theme: true,
+    rotation_frequency: 300,
+    filter_method: Lanczos,
+    scaling_mode: Zoom,
+    sampling_method: Alphanumeric,
+)"#;
+        assert_eq!(
+            source(entry).unwrap(),
+            PathBuf::from(r#"/home/me/My "Pictures"/a.png"#)
+        );
+        assert!(source(r#"(source: Color(Single((0.1, 0.2, 0.3))))"#).is_err());
+        assert!(source(r#"(source: Path("/a.png)"#).is_err());
+        assert!(source("(output: \"all\")").is_err());
+    }
+}
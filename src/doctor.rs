@@ -0,0 +1,317 @@
+//! `swww doctor`: checks the things swww needs that users can't easily see, like whether the
+//! compositor supports layer-shell, and prints how to fix the ones that are wrong. What the
+//! compositor supports comes from `swww-daemon --check`, since only the daemon speaks Wayland
+use std::{
+    collections::HashMap, ffi::OsString, fs, io, os::unix::fs::PermissionsExt, process::Command,
+    time::Duration,
+};
+
+use swww_protocol::{error::Error, Answer, Request};
+use utils::communication;
+
+const VERSION: &str = env!("CARGO_PKG_VERSION");
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Status {
+    Ok,
+    Warning,
+    Problem,
+}
+
+#[derive(Debug)]
+struct Check {
+    status: Status,
+    what: String,
+    /// How to fix it, for warnings and problems
+    fix: Option<String>,
+}
+
+fn ok(what: impl Into<String>) -> Check {
+    Check {
+        status: Status::Ok,
+        what: what.into(),
+        fix: None,
+    }
+}
+
+fn warning(what: impl Into<String>, fix: Option<&str>) -> Check {
+    Check {
+        status: Status::Warning,
+        what: what.into(),
+        fix: fix.map(str::to_string),
+    }
+}
+
+fn problem(what: impl Into<String>, fix: impl Into<String>) -> Check {
+    Check {
+        status: Status::Problem,
+        what: what.into(),
+        fix: Some(fix.into()),
+    }
+}
+
+pub fn run() -> Result<(), Error> {
+    let mut checks = Vec::new();
+    match communication::get_remote() {
+        // the local session doesn't matter to a remote daemon
+        Some(remote) => checks.push(ok(format!("checking the remote daemon at {remote}"))),
+        None => {
+            checks.push(runtime_dir(std::env::var_os("XDG_RUNTIME_DIR")));
+            checks.extend(daemon_executable());
+        }
+    }
+    checks.extend(running_daemon());
+
+    for check in &checks {
+        let status = match check.status {
+            Status::Ok => "ok",
+            Status::Warning => "warning",
+            Status::Problem => "problem",
+        };
+        println!("{status:<8} {}", check.what);
+        if let Some(fix) = &check.fix {
+            println!("{:<8} fix: {fix}", "");
+        }
+    }
+    let problems = checks
+        .iter()
+        .filter(|check| check.status == Status::Problem)
+        .count();
+    match problems {
+        0 => Ok(()),
+        1 => Err(Error::Other("found a problem".to_string())),
+        n => Err(Error::Other(format!("found {n} problems"))),
+    }
+}
+
+/// Where our socket goes. It should only be accessible by the user
+fn runtime_dir(dir: Option<OsString>) -> Check {
+    let Some(dir) = dir else {
+        return warning(
+            "XDG_RUNTIME_DIR isn't set, so the socket goes in /tmp/swww",
+            Some("log in through a session manager that sets it, like systemd-logind or elogind"),
+        );
+    };
+    let shown = dir.to_string_lossy();
+    match fs::metadata(&dir) {
+        Err(e) => problem(
+            format!("XDG_RUNTIME_DIR ({shown}) can't be used: {e}"),
+            "log in again, so that the session manager creates it",
+        ),
+        Ok(metadata) if !metadata.is_dir() => problem(
+            format!("XDG_RUNTIME_DIR ({shown}) isn't a directory"),
+            "point it to the directory the session manager created for you",
+        ),
+        Ok(metadata) if metadata.permissions().mode() & 0o077 != 0 => warning(
+            format!("XDG_RUNTIME_DIR ({shown}) can be accessed by other users"),
+            Some(&format!("chmod 700 {shown}")),
+        ),
+        Ok(_) => ok(format!("runtime directory: {shown}")),
+    }
+}
+
+/// Runs `swww-daemon --check`, which is the same daemon `swww init` would start
+fn daemon_executable() -> Vec<Check> {
+    let output = match Command::new("swww-daemon").arg("--check").output() {
+        Ok(output) => output,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => {
+            return vec![problem(
+                "swww-daemon isn't in PATH",
+                "install it along with swww, or add the directory it is in to PATH",
+            )]
+        }
+        Err(e) => {
+            return vec![problem(
+                format!("failed to run swww-daemon: {e}"),
+                "make sure swww-daemon can be executed",
+            )]
+        }
+    };
+    if !output.status.success() {
+        // daemons older than `--check` reject it
+        return vec![problem(
+            format!(
+                "swww-daemon --check failed: {}",
+                String::from_utf8_lossy(&output.stderr).trim()
+            ),
+            "swww-daemon is probably older than swww, reinstall them together",
+        )];
+    }
+    let wayland_display = std::env::var("WAYLAND_DISPLAY").ok();
+    diagnose(
+        &String::from_utf8_lossy(&output.stdout),
+        wayland_display.as_deref(),
+    )
+}
+
+/// Checks the `key: value` lines printed by `swww-daemon --check`
+fn diagnose(report: &str, wayland_display: Option<&str>) -> Vec<Check> {
+    let fields: HashMap<&str, &str> = report
+        .lines()
+        .filter_map(|line| line.split_once(':'))
+        .map(|(key, value)| (key.trim(), value.trim()))
+        .collect();
+    let field = |key: &str| fields.get(key).copied().unwrap_or_default();
+
+    let mut checks = Vec::new();
+    let version = field("version");
+    checks.push(if version == VERSION {
+        ok(format!("swww-daemon {version}"))
+    } else {
+        problem(
+            format!("swww-daemon is version {version}, but swww is {VERSION}"),
+            "reinstall them together, from the same release",
+        )
+    });
+    checks.push(ok(match field("features") {
+        "" => "swww-daemon was built without optional features".to_string(),
+        features => format!("swww-daemon was built with: {features}"),
+    }));
+
+    match field("backend") {
+        "x11" => checks.push(ok("X11 session, the daemon will draw to the root window")),
+        "wayland" => {
+            checks.push(match field("layer-shell") {
+                "yes" => ok("the compositor supports layer-shell"),
+                _ => problem(
+                    "the compositor doesn't support the layer-shell protocol, which swww draws \
+                    wallpapers with",
+                    "use a compositor implementing wlr-layer-shell, like sway, Hyprland, river, \
+                    Wayfire or KDE Plasma. GNOME doesn't implement it",
+                ),
+            });
+            if field("viewporter") != "yes" {
+                checks.push(warning(
+                    "the compositor doesn't support the viewporter protocol, so `swww init \
+                    --max-resolution` has no effect",
+                    None,
+                ));
+            }
+            checks.push(match field("outputs") {
+                "0" => warning(
+                    "the compositor has no outputs",
+                    Some("turn a display on, or check the compositor's output configuration"),
+                ),
+                outputs => ok(format!("the compositor has {outputs} outputs")),
+            });
+        }
+        _ => checks.push(problem(
+            format!(
+                "swww-daemon can't connect to the compositor: {}",
+                field("error")
+            ),
+            match wayland_display {
+                Some(display) => format!(
+                    "make sure WAYLAND_DISPLAY ({display}) is the socket of the compositor \
+                    you are running"
+                ),
+                None => {
+                    "WAYLAND_DISPLAY isn't set, run swww from inside a Wayland session".to_string()
+                }
+            },
+        )),
+    }
+    checks
+}
+
+fn running_daemon() -> Vec<Check> {
+    let restart = "restart it with `swww init --replace`";
+    let version = match ask(Request::Version) {
+        Ok(Answer::Version(version)) => version,
+        Ok(_) => {
+            return vec![problem(
+                "the daemon answered the version request with something else",
+                restart,
+            )]
+        }
+        // this is what `swww_client::connect_retrying` returns when there is no socket to connect to
+        Err(Error::Daemon(e)) => {
+            return vec![warning(
+                format!("the daemon isn't running: {e}"),
+                Some("start it with `swww init`"),
+            )]
+        }
+        Err(e) => {
+            return vec![problem(
+                format!("the daemon didn't answer the version request: {e}"),
+                format!("it is probably older than swww, {restart}"),
+            )]
+        }
+    };
+    let mut checks = vec![if version.version == VERSION {
+        ok(format!("the daemon is running: {version}"))
+    } else {
+        problem(
+            format!(
+                "the running daemon is version {}, but swww is {VERSION}",
+                version.version
+            ),
+            restart,
+        )
+    }];
+    checks.push(match ask(Request::Query) {
+        Ok(Answer::Info(infos)) if infos.is_empty() => warning(
+            "the daemon has no outputs",
+            Some("check that the compositor has outputs, and `swww enable` the ones you disabled"),
+        ),
+        Ok(Answer::Info(infos)) => {
+            let names: Vec<_> = infos.iter().map(|info| info.name.as_str()).collect();
+            ok(format!("the daemon is drawing on: {}", names.join(", ")))
+        }
+        Ok(_) => problem("the daemon answered the query with something else", restart),
+        Err(e) => problem(format!("the daemon didn't answer the query: {e}"), restart),
+    });
+    checks
+}
+
+fn ask(request: Request) -> Result<Answer, Error> {
+    let socket = swww_client::connect_retrying(1, Duration::ZERO)?;
+    request.send(&socket)?;
+    match Answer::receive(socket)? {
+        Answer::Err(e) => Err(e),
+        answer => Ok(answer),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn statuses(checks: &[Check]) -> Vec<Status> {
+        checks.iter().map(|check| check.status).collect()
+    }
+
+    #[test]
+    fn should_diagnose_the_daemons_report() {
+        let report = format!(
+            "version: {VERSION}\nfeatures: \nbackend: wayland\nlayer-shell: yes\n\
+             viewporter: yes\noutputs: 2\n"
+        );
+        let checks = diagnose(&report, Some("wayland-1"));
+        assert!(checks.iter().all(|check| check.status == Status::Ok));
+        assert_eq!(
+            checks[1].what,
+            "swww-daemon was built without optional features"
+        );
+
+        let report = "version: 0.1.0\nfeatures: ffmpeg\nbackend: wayland\nlayer-shell: no\n\
+                      viewporter: no\noutputs: 0\n";
+        assert_eq!(
+            statuses(&diagnose(report, Some("wayland-1"))),
+            [
+                Status::Problem,
+                Status::Ok,
+                Status::Problem,
+                Status::Warning,
+                Status::Warning
+            ]
+        );
+
+        let report =
+            format!("version: {VERSION}\nfeatures: \nbackend: none\nerror: no compositor\n");
+        let checks = diagnose(&report, None);
+        assert_eq!(checks[2].status, Status::Problem);
+        assert!(checks[2].what.ends_with("no compositor"));
+        assert!(checks[2].fix.as_ref().unwrap().contains("isn't set"));
+    }
+}
@@ -1,27 +1,168 @@
-use clap::Parser;
-use fast_image_resize::{FilterType, PixelType, Resizer};
-use image::{codecs::gif::GifDecoder, AnimationDecoder, RgbaImage};
+use clap::{CommandFactory, FromArgMatches, Parser};
+use image::RgbaImage;
+use rand::{rngs::StdRng, Rng, SeedableRng};
 use std::{
     fs::File,
-    io::{stdin, BufReader, Read},
-    num::NonZeroU32,
-    os::unix::net::UnixStream,
+    io::{stdin, BufReader, Cursor, Read},
     path::{Path, PathBuf},
-    process::Stdio,
-    time::Duration,
+    process::{ExitCode, Stdio},
+    sync::mpsc,
+    time::{Duration, Instant},
 };
 
+use swww_protocol::{
+    error::Error, AnimationRequest, Answer, Coord, Position, Request, SlideshowCommand,
+};
+use swww_render::{
+    animation::{self, AnimatedDecoder},
+    bitmap, color,
+    comp_decomp::{BitPack, Pack},
+    resize::{self, FilterType},
+};
 use utils::{
-    communication::{self, get_socket_path, AnimationRequest, Answer, Coord, Position, Request},
-    comp_decomp::BitPack,
+    communication::{self, get_socket_path, MAX_SESSION_WAIT},
+    variant,
 };
 
 mod cli;
+mod doctor;
+mod options;
+mod palette;
+mod playlist;
+mod preview;
+mod query;
+mod replace;
+mod spec;
+mod subscribe;
+mod supervise;
 use cli::Swww;
 
-fn main() -> Result<(), String> {
-    let swww = Swww::parse();
-    if let Swww::Init { no_daemon } = &swww {
+#[cfg(feature = "portal")]
+mod cosmic;
+#[cfg(feature = "portal")]
+mod plasma;
+#[cfg(feature = "portal")]
+mod portal;
+#[cfg(feature = "tui")]
+mod tui;
+
+/// How often we update the busy indicator, at most
+const PROGRESS_INTERVAL: Duration = Duration::from_millis(100);
+
+/// `--diff-threshold`s above this tend to visibly degrade animations
+const MAX_SAFE_DIFF_THRESHOLD: u8 = 16;
+
+/// `--anim-blend` only crossfades frames displayed for longer than this
+const ANIM_BLEND_MIN_DELAY: Duration = Duration::from_millis(500);
+
+/// `swww refresh` brings the images back with a fade of a few frames
+const REFRESH_TRANSITION: &[&str] = &["--transition-type", "simple", "--transition-step", "90"];
+
+/// An animated image, of which only the first frame was decoded yet
+struct Animated {
+    format: image::ImageFormat,
+    /// The encoded image, if it was read from stdin, since stdin can't be read again
+    stdin: Option<Vec<u8>>,
+}
+
+impl Animated {
+    /// Reads the encoded animation from the beginning
+    fn open<'a>(&'a self, path: &Path) -> Result<Box<dyn Read + 'a>, String> {
+        match &self.stdin {
+            Some(bytes) => Ok(Box::new(Cursor::new(bytes.as_slice()))),
+            None => match File::open(path) {
+                Ok(file) => Ok(Box::new(BufReader::new(file))),
+                Err(e) => Err(format!("error opening image during animation: {e}")),
+            },
+        }
+    }
+}
+
+/// An image drawn over the main one
+struct Layer {
+    img: RgbaImage,
+    opacity: f32,
+    pos: Position,
+}
+
+fn main() -> ExitCode {
+    let matches = Swww::command().get_matches();
+    let swww = Swww::from_arg_matches(&matches).unwrap_or_else(|e| e.exit());
+    if transition_args(&swww).is_some_and(|transition| transition.verbose) {
+        options::effective(&matches)
+            .into_iter()
+            .for_each(|line| eprintln!("{line}"));
+    }
+    match run(swww) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("Error: {e}");
+            ExitCode::from(e.code())
+        }
+    }
+}
+
+fn run(mut swww: Swww) -> Result<(), Error> {
+    if let Swww::Cache(cache) = &swww {
+        return Ok(manage_cache(cache)?);
+    }
+    if let Swww::Doctor = &swww {
+        return doctor::run();
+    }
+    if let Swww::DumpCliSpec = &swww {
+        println!("{}", spec::dump());
+        return Ok(());
+    }
+    if let Swww::Subscribe(subscribe) = &swww {
+        return subscribe::run(subscribe);
+    }
+    if let Swww::Supervise { args } = &swww {
+        return supervise::run(args);
+    }
+    let mut previous = None;
+    if let Swww::Init {
+        no_daemon,
+        cache_size,
+        hook,
+        min_interval,
+        delay,
+        wait_for,
+        fifo,
+        listen,
+        layer,
+        exclusive_zone,
+        max_resolution,
+        reduce_motion,
+        replace,
+        supervise,
+    } = &swww
+    {
+        if communication::get_remote().is_some() {
+            return Err(Error::InvalidRequest(
+                "swww init starts a daemon on this machine, unset SWWW_REMOTE first".to_string(),
+            ));
+        }
+        // we check these here, so that a typo doesn't go unnoticed in the daemon's logs
+        let cache_size = match cache_size {
+            Some(size) => Some(utils::cache::parse_size(size)?),
+            None => None,
+        };
+        utils::surface::Placement::parse(
+            layer.as_deref(),
+            exclusive_zone.as_deref(),
+            max_resolution.as_deref(),
+        )?;
+        if let Some(listen) = listen {
+            communication::parse_tcp_addr(listen).map_err(Error::InvalidRequest)?;
+            if communication::get_token().is_none() {
+                return Err(Error::InvalidRequest(
+                    "--listen requires a token in SWWW_TOKEN".to_string(),
+                ));
+            }
+        }
+        if *replace {
+            previous = replace::previous_state()?;
+        }
         match is_daemon_running() {
             Ok(false) => {
                 let socket_path = get_socket_path();
@@ -31,38 +172,147 @@ fn main() -> Result<(), String> {
                         socket_path.to_string_lossy()
                     );
                     if let Err(e) = std::fs::remove_file(socket_path) {
-                        return Err(format!("failed to delete previous socket: {e}"));
+                        return Err(Error::Daemon(format!(
+                            "failed to delete previous socket: {e}"
+                        )));
                     }
                 }
             }
+            Ok(true) if previous.is_some() => (),
             Ok(true) => {
-                return Err("There seems to already be another instance running...".to_string())
+                return Err(Error::Daemon(
+                    "There seems to already be another instance running...".to_string(),
+                ))
             }
             Err(e) => {
                 eprintln!("WARNING: failed to read '/proc' directory to determine whether the daemon is running: {e}
                           Falling back to trying to checking if the socket file exists...");
                 let socket_path = get_socket_path();
-                if socket_path.exists() {
-                    return Err(format!(
+                if socket_path.exists() && previous.is_none() {
+                    return Err(Error::Daemon(format!(
                         "Found socket at {}. There seems to be an instance already running...",
                         socket_path.to_string_lossy()
-                    ));
+                    )));
                 }
             }
         }
-        spawn_daemon(*no_daemon)?;
+        spawn_daemon(
+            (*no_daemon, previous.is_some(), *supervise),
+            cache_size,
+            hook.as_deref(),
+            (*min_interval, prefers_reduced_motion(reduce_motion)),
+            (*delay, wait_for.as_deref()),
+            (*fifo, listen.as_deref()),
+            (
+                layer.as_deref(),
+                exclusive_zone.as_deref(),
+                max_resolution.as_deref(),
+            ),
+        )?;
         if *no_daemon {
             return Ok(());
         }
+        if let Some(previous) = &previous {
+            replace::wait_for_takeover(previous)?;
+        }
+    }
+
+    if let Swww::Preview(preview) = &swww {
+        return preview::run(preview);
+    }
+
+    if let Swww::Playlist(playlist) = &swww {
+        return playlist::run(playlist);
+    }
+
+    if let Swww::Query {
+        anim,
+        format: cli::QueryFormat::Json,
+    } = swww
+    {
+        return query::print_json(anim);
+    }
+
+    #[cfg(feature = "portal")]
+    if let Swww::Portal { kde, cosmic } = swww {
+        return Ok(portal::run(kde, cosmic)?);
+    }
+
+    #[cfg(feature = "tui")]
+    if let Swww::Tui = swww {
+        return tui::run();
+    }
+
+    if let Swww::Img(img) = &mut swww {
+        if img.if_unset {
+            let unset = unset_outputs(&img.outputs)?;
+            if unset.is_empty() {
+                println!("skipped: every output already has a wallpaper");
+                return Ok(());
+            }
+            img.outputs = unset.join(",");
+        }
     }
 
     let request = make_request(&swww)?;
-    let socket = connect_to_socket(5, 100)?;
-    request.send(&socket)?;
-    match Answer::receive(socket)? {
+    let socket = swww_client::connect()?;
+    communication::send(&request, &socket)?;
+    let answer = match &swww {
+        // the daemon only answers once the session is ready
+        Swww::Init {
+            delay, wait_for, ..
+        } if delay.is_some() || wait_for.is_some() => {
+            let wait = delay.unwrap_or_default()
+                + wait_for
+                    .as_ref()
+                    .map_or(Duration::ZERO, |_| MAX_SESSION_WAIT);
+            Answer::receive_within(socket, wait + Duration::from_secs(5))?
+        }
+        // the daemon answers once its surfaces and socket are gone
+        Swww::Kill => match Answer::receive(socket) {
+            Err(Error::Ipc(e)) => {
+                return Err(Error::Daemon(format!(
+                    "the daemon didn't confirm it exited: {e}"
+                )))
+            }
+            answer => answer?,
+        },
+        _ => Answer::receive(socket)?,
+    };
+    match answer {
         Answer::Err(msg) => return Err(msg),
+        Answer::Info(infos) if matches!(swww, Swww::Refresh(_)) => return redraw_outputs(infos),
         Answer::Info(info) => info.into_iter().for_each(|i| println!("{i}")),
+        Answer::Groups(groups) => {
+            for (name, outputs) in groups {
+                println!("{name}: {}", outputs.join(", "));
+            }
+        }
+        Answer::Queue(requests) => requests.into_iter().for_each(|r| println!("{r}")),
+        Answer::Trace(lines) => lines.into_iter().for_each(|l| println!("{l}")),
+        Answer::Playback(playback) => playback.into_iter().for_each(|p| println!("{p}")),
+        Answer::Colors(colors) => colors.into_iter().for_each(|c| println!("{c}")),
+        Answer::Version(version) => println!("{version}"),
+        // a newer request replaced ours, which is what the user wanted to see anyway
+        Answer::Coalesced => (),
+        Answer::Transition { frames, duration } => {
+            if let Some(transition) = transition_args(&swww) {
+                print_estimate(transition, frames, duration)
+            }
+        }
         Answer::Ok => {
+            if let Swww::Follow(cli::Follow {
+                output,
+                from: Some(source),
+                ..
+            }) = &swww
+            {
+                return mirror_output(source, output);
+            }
+            if let Some(previous) = previous {
+                return replace::restore(previous);
+            }
+            // older daemons answer right away, and remove their socket on their way out
             if let Swww::Kill = swww {
                 #[cfg(debug_assertions)]
                 let tries = 20;
@@ -75,50 +325,469 @@ fn main() -> Result<(), String> {
                     }
                     std::thread::sleep(Duration::from_millis(100));
                 }
-                return Err(format!(
+                return Err(Error::Daemon(format!(
                     "Could not confirm socket deletion at: {socket_path:?}"
-                ));
+                )));
             }
         }
     }
     Ok(())
 }
 
-fn make_request(args: &Swww) -> Result<Request, String> {
+/// The transition options of the commands that have them
+fn transition_args(swww: &Swww) -> Option<&cli::Transition> {
+    match swww {
+        Swww::Adjust(cli::Adjust { transition, .. })
+        | Swww::Clear(cli::Clear { transition, .. })
+        | Swww::Img(cli::Img { transition, .. }) => Some(transition),
+        _ => None,
+    }
+}
+
+fn print_estimate(transition: &cli::Transition, frames: u32, duration: Duration) {
+    if transition.print_duration {
+        println!("{frames} {:.3}", duration.as_secs_f64());
+    }
+}
+
+fn make_request(args: &Swww) -> Result<Request, Error> {
     match args {
-        Swww::Clear(c) => Ok(Request::Clear(communication::Clear {
+        Swww::Adjust(a) => Ok(Request::Adjust(swww_protocol::Adjust {
+            adjustment: swww_protocol::Adjustment {
+                brightness: a.brightness,
+                contrast: a.contrast,
+                gamma: a.gamma,
+            },
+            source: match a.source {
+                cli::AdjustSource::Base => swww_protocol::AdjustSource::Base,
+                cli::AdjustSource::Regions => swww_protocol::AdjustSource::Regions,
+            },
+            match_brightness: a.match_brightness,
+            effect: a.effect.clone(),
+            outputs: get_outputs(&a.outputs)?,
+            transition: make_transition(&a.transition),
+        })),
+        Swww::Clear(c) => Ok(Request::Clear(swww_protocol::Clear {
             color: c.color,
-            outputs: split_cmdline_outputs(&c.outputs),
+            outputs: get_outputs(&c.outputs)?,
+            transition: make_transition(&c.transition),
+        })),
+        Swww::Disable { output } => Ok(Request::Disable(output.clone())),
+        Swww::Enable { output } => Ok(Request::Enable(output.clone())),
+        Swww::Follow(f) => Ok(Request::Follow(swww_protocol::Follow {
+            output: f.output.clone(),
+            source: f.from.clone(),
+        })),
+        Swww::Pin(p) => Ok(Request::Pin(swww_protocol::Pin {
+            output: p.output.clone(),
+            pinned: !p.unpin,
         })),
+        Swww::SafeArea(s) => Ok(Request::SafeArea(swww_protocol::SetSafeArea {
+            output: s.output.clone(),
+            area: swww_protocol::SafeArea {
+                top: s.area.top,
+                bottom: s.area.bottom,
+                left: s.area.left,
+                right: s.area.right,
+            },
+        })),
+        Swww::Group(group) => Ok(Request::Group(match group {
+            cli::Group::Create { name, outputs } => swww_protocol::GroupCommand::Create {
+                name: name.clone(),
+                outputs: outputs.clone(),
+            },
+            cli::Group::Delete { name } => swww_protocol::GroupCommand::Delete(name.clone()),
+            cli::Group::List => swww_protocol::GroupCommand::List,
+        })),
+        Swww::Generate(g) => Ok(Request::Generate(swww_protocol::Generate {
+            generator: make_generator(&g.generator)?,
+            outputs: get_outputs(&g.outputs)?,
+        })),
+        Swww::Img(img) if img.stop_slideshow => Ok(Request::Slideshow(SlideshowCommand::Stop(
+            get_outputs(&img.outputs)?,
+        ))),
+        Swww::Img(img) if img_path(img).is_dir() => make_slideshow(img),
         Swww::Img(img) => {
-            let requested_outputs = split_cmdline_outputs(&img.outputs);
-            let (dims, outputs) = get_dimensions_and_outputs(requested_outputs)?;
-            let (img_raw, is_gif) = read_img(&img.path)?;
-            if is_gif {
+            let deadline = img.defer.map(|defer| Instant::now() + defer);
+            let requested_outputs = get_outputs(&img.outputs)?;
+            let merge_outputs =
+                !matches!(img.transition.transition_sync, cli::TransitionSync::Layout);
+            let (dims, areas, outputs) =
+                get_dimensions_and_outputs(requested_outputs, merge_outputs)?;
+            if img.transition.verbose {
+                for (dim, outputs) in dims.iter().zip(&outputs) {
+                    eprintln!("size for {} = {}x{}", outputs.join(", "), dim.0, dim.1);
+                }
+            }
+            let colors = bitmap::Colors {
+                fg: img.fg,
+                bg: img.bg,
+            };
+            let (img_raw, animation) = read_img(img_path(img), img.format.as_ref(), colors)?;
+            let layers = read_layers(&img.layer, colors)?;
+            if img.diff_threshold > MAX_SAFE_DIFF_THRESHOLD {
+                eprintln!(
+                    "WARNING: diff thresholds above {MAX_SAFE_DIFF_THRESHOLD} usually leave visible artifacts in animations"
+                );
+            }
+            let palette = match &img.palette {
+                Some(spec) => Some(palette::load(spec, &img_raw)?),
+                None => None,
+            };
+            let palette = palette.as_deref();
+            if let Some(animated) = animation {
                 match std::thread::scope(|s| {
-                    let animations = s.spawn(|| make_animation_request(img, &dims, &outputs));
-                    let img_request = make_img_request(img, img_raw, &dims, &outputs)?;
-                    let animations = match animations.join() {
-                        Ok(a) => a,
-                        Err(e) => Err(format!("{e:?}")),
-                    };
-                    let socket = connect_to_socket(5, 100)?;
-                    Request::Img(img_request).send(&socket)?;
-                    Answer::receive(socket)?;
-                    animations
+                    let (progress, progress_recv) = mpsc::channel();
+                    let progress = img.busy_indicator.then_some(progress);
+                    let animations = s.spawn(|| {
+                        make_animation_request(
+                            (img, &animated),
+                            &dims,
+                            &areas,
+                            &outputs,
+                            &layers,
+                            palette,
+                            progress,
+                        )
+                    });
+                    let img_request =
+                        make_img_request(img, img_raw, &dims, &areas, &outputs, &layers, palette)
+                            .map_err(Error::Other)?;
+                    // we display the first frame right away, so that the screen isn't stuck on the
+                    // old image while the animation is processed
+                    wait_for_deadline(deadline);
+                    let socket = swww_client::connect()?;
+                    communication::send(&Request::Img(img_request), &socket)?;
+                    match Answer::receive(socket)? {
+                        Answer::Transition { frames, duration } => {
+                            print_estimate(&img.transition, frames, duration)
+                        }
+                        // a newer image replaced ours, so its animation would only draw over it
+                        Answer::Coalesced => return Ok(Vec::new()),
+                        _ => (),
+                    }
+                    if img.busy_indicator {
+                        report_progress(progress_recv, outputs.concat());
+                    }
+                    match animations.join() {
+                        Ok(a) => a.map_err(Error::Other),
+                        Err(e) => Err(Error::Other(format!("{e:?}"))),
+                    }
                 }) {
                     Ok(animations) => Ok(Request::Animation(animations)),
-                    Err(e) => Err(format!("failed to create animated request: {e}")),
+                    Err(e) => Err(e.context("failed to create animated request")),
                 }
             } else {
-                Ok(Request::Img(make_img_request(
-                    img, img_raw, &dims, &outputs,
-                )?))
+                let img_request =
+                    make_img_request(img, img_raw, &dims, &areas, &outputs, &layers, palette)?;
+                wait_for_deadline(deadline);
+                Ok(Request::Img(img_request))
             }
         }
         Swww::Init { .. } => Ok(Request::Init),
         Swww::Kill => Ok(Request::Kill),
-        Swww::Query => Ok(Request::Query),
+        Swww::Patch(patch) => {
+            let (img, _) = read_img(&patch.path, None, bitmap::Colors::default())?;
+            let dim = patch.size.unwrap_or_else(|| img.dimensions());
+            Ok(Request::Patch(swww_protocol::Patch {
+                // this won't resize anything if the dimensions are already right
+                img: resize::resize(img, dim, FilterType::Lanczos3)?,
+                dim,
+                pos: make_position(&patch.pos),
+                outputs: get_outputs(&patch.outputs)?,
+            }))
+        }
+        Swww::Region(region) => Ok(Request::Region(make_region(region)?)),
+        Swww::Refresh(refresh) => Ok(Request::Refresh(swww_protocol::Refresh {
+            outputs: get_outputs(&refresh.outputs)?,
+        })),
+        Swww::Cache(_) => Err(Error::InvalidRequest(
+            "cache commands are handled by the client".to_string(),
+        )),
+        Swww::Doctor => Err(Error::InvalidRequest(
+            "the checks are run by the client".to_string(),
+        )),
+        Swww::DumpCliSpec => Err(Error::InvalidRequest(
+            "the cli spec is printed by the client".to_string(),
+        )),
+        Swww::Preview(_) => Err(Error::InvalidRequest(
+            "previews are drawn by the client".to_string(),
+        )),
+        Swww::Subscribe(_) => Err(Error::InvalidRequest(
+            "subscriptions are handled by the client".to_string(),
+        )),
+        Swww::Playlist(_) => Err(Error::InvalidRequest(
+            "playlists are played by the client".to_string(),
+        )),
+        Swww::Supervise { .. } => Err(Error::InvalidRequest(
+            "the supervisor does not send requests by itself".to_string(),
+        )),
+        Swww::Query { anim: false, .. } => Ok(Request::Query),
+        Swww::Query { anim: true, .. } => Ok(Request::Playback),
+        #[cfg(feature = "portal")]
+        Swww::Portal { .. } => Err(Error::InvalidRequest(
+            "the portal does not send requests by itself".to_string(),
+        )),
+        #[cfg(feature = "tui")]
+        Swww::Tui => Err(Error::InvalidRequest(
+            "the tui does not send requests by itself".to_string(),
+        )),
+        Swww::Queue(queue) => Ok(Request::Queue(match queue {
+            cli::Queue::List => swww_protocol::QueueCommand::List,
+            cli::Queue::Clear => swww_protocol::QueueCommand::Clear,
+        })),
+        Swww::Trace { id } => Ok(Request::Trace(*id)),
+        Swww::Transaction(transaction) => Ok(Request::Transaction(match transaction {
+            cli::Transaction::Begin => swww_protocol::TransactionCommand::Begin,
+            cli::Transaction::Commit => swww_protocol::TransactionCommand::Commit,
+            cli::Transaction::Rollback => swww_protocol::TransactionCommand::Rollback,
+        })),
+        Swww::Transition(cli::ManualTransition::Manual(img)) => Ok(Request::Manual(
+            swww_protocol::ManualCommand::Start(make_manual_request(img)?),
+        )),
+        Swww::Transition(cli::ManualTransition::Set { progress }) => Ok(Request::Manual(
+            swww_protocol::ManualCommand::Set(*progress),
+        )),
+        Swww::Transition(cli::ManualTransition::End) => {
+            Ok(Request::Manual(swww_protocol::ManualCommand::End))
+        }
+        Swww::Record(r) => {
+            // the daemon may be running in another directory
+            let path = match std::env::current_dir() {
+                Ok(dir) => dir.join(&r.path),
+                Err(e) => {
+                    return Err(Error::Other(format!(
+                        "failed to get the current directory: {e}"
+                    )))
+                }
+            };
+            Ok(Request::Record(swww_protocol::Record {
+                output: r.output.clone(),
+                duration: r.duration,
+                fps: r.fps,
+                path,
+            }))
+        }
+    }
+}
+
+/// Like the request of `swww img`, but with only the first frame of animated images, since
+/// manual transitions can't be followed by the animation
+/// The daemon runs slideshows, so that they keep going after we exit
+fn make_slideshow(img: &cli::Img) -> Result<Request, Error> {
+    let dir = img_path(img);
+    let dir = match dir.canonicalize() {
+        Ok(dir) => dir,
+        Err(e) => {
+            return Err(Error::InvalidRequest(format!(
+                "failed to canonicalize {dir:?}: {e}"
+            )))
+        }
+    };
+    Ok(Request::Slideshow(SlideshowCommand::Start(
+        swww_protocol::Slideshow {
+            dir,
+            outputs: get_outputs(&img.outputs)?,
+            interval: img.interval,
+            lead: img.lead_time,
+            sort: match img.sort {
+                cli::SlideshowSort::Name => swww_protocol::SlideshowSort::Name,
+                cli::SlideshowSort::Mtime => swww_protocol::SlideshowSort::Mtime,
+                cli::SlideshowSort::Random => swww_protocol::SlideshowSort::Random,
+            },
+            newer_than: img.newer_than,
+            min_resolution: img.min_resolution,
+            seed: img.transition.seed,
+            transition: make_transition(&img.transition),
+        },
+    )))
+}
+
+fn make_manual_request(img: &cli::Img) -> Result<swww_protocol::ImageRequest, Error> {
+    let merge_outputs = !matches!(img.transition.transition_sync, cli::TransitionSync::Layout);
+    let (dims, areas, outputs) =
+        get_dimensions_and_outputs(get_outputs(&img.outputs)?, merge_outputs)?;
+    let colors = bitmap::Colors {
+        fg: img.fg,
+        bg: img.bg,
+    };
+    let (img_raw, _) = read_img(img_path(img), img.format.as_ref(), colors)?;
+    let layers = read_layers(&img.layer, colors)?;
+    let palette = match &img.palette {
+        Some(spec) => Some(palette::load(spec, &img_raw)?),
+        None => None,
+    };
+    Ok(make_img_request(
+        img,
+        img_raw,
+        &dims,
+        &areas,
+        &outputs,
+        &layers,
+        palette.as_deref(),
+    )?)
+}
+
+/// Sends whatever image `source` is displaying to `follower`, so that it doesn't have to wait for
+/// the next one to start mirroring it
+fn mirror_output(source: &str, follower: &str) -> Result<(), Error> {
+    let socket = swww_client::connect()?;
+    Request::Query.send(&socket)?;
+    let path = match Answer::receive(socket)? {
+        Answer::Info(infos) => match infos.into_iter().find(|info| info.name == source) {
+            Some(swww_protocol::BgInfo {
+                img: swww_protocol::BgImg::Img(path),
+                ..
+            }) if path.is_file() => path,
+            _ => return Ok(()),
+        },
+        Answer::Err(e) => return Err(e.context("failed to query swww-daemon")),
+        _ => unreachable!(),
+    };
+
+    set_img(&path, follower, &[])
+        .map_err(|e| e.context(&format!("failed to mirror {source}'s image")))
+}
+
+/// Sleeps until `deadline`, if there is one. Images are prepared ahead of it with `--defer`, and
+/// only sent once it passes
+fn wait_for_deadline(deadline: Option<Instant>) {
+    let Some(deadline) = deadline else {
+        return;
+    };
+    match deadline.checked_duration_since(Instant::now()) {
+        Some(left) => std::thread::sleep(left),
+        None => eprintln!("WARNING: preparing the image took longer than --defer"),
+    }
+}
+
+/// Sends the images of the outputs `swww refresh` refreshed again, read from disk and resized anew
+fn redraw_outputs(infos: Vec<swww_protocol::BgInfo>) -> Result<(), Error> {
+    // outputs displaying the same image are sent together, so that it is only read once
+    let mut imgs: Vec<(PathBuf, Vec<String>)> = Vec::new();
+    for info in infos {
+        // followers get whatever their source is sent
+        if info.following.is_some() {
+            continue;
+        }
+        let swww_protocol::BgImg::Img(path) = info.img else {
+            continue;
+        };
+        if !path.is_file() {
+            eprintln!(
+                "WARNING: can't refresh {}, since its image ({path:?}) no longer exists",
+                info.name
+            );
+            continue;
+        }
+        match imgs.iter_mut().find(|(p, _)| *p == path) {
+            Some((_, outputs)) => outputs.push(info.name),
+            None => imgs.push((path, vec![info.name])),
+        }
+    }
+    for (path, outputs) in imgs {
+        set_img(&path, &outputs.join(","), REFRESH_TRANSITION)
+            .map_err(|e| e.context(&format!("failed to refresh {}", outputs.join(", "))))?;
+    }
+    Ok(())
+}
+
+/// Sends `path` to `outputs` just like `swww img` would, with the default options (or whatever
+/// is set in the environment), plus the options in `args`
+fn set_img(path: &Path, outputs: &str, args: &[&str]) -> Result<(), Error> {
+    let mut full_args: Vec<&std::ffi::OsStr> = vec![
+        "img".as_ref(),
+        path.as_os_str(),
+        "--outputs".as_ref(),
+        outputs.as_ref(),
+    ];
+    full_args.extend(args.iter().map(std::ffi::OsStr::new));
+    let img = match cli::Img::try_parse_from(full_args) {
+        Ok(img) => img,
+        Err(e) => return Err(Error::InvalidRequest(e.to_string())),
+    };
+    let request = make_request(&Swww::Img(img))?;
+    let socket = swww_client::connect()?;
+    communication::send(&request, &socket)?;
+    match Answer::receive(socket)? {
+        Answer::Err(e) => Err(e),
+        _ => Ok(()),
+    }
+}
+
+fn manage_cache(cache: &cli::Cache) -> Result<(), String> {
+    match cache {
+        cli::Cache::Clean => {
+            let freed = utils::cache::clean()?;
+            println!("freed {}", human_size(freed));
+        }
+        cli::Cache::Stats => {
+            let stats = utils::cache::stats()?;
+            for file in &stats {
+                let contents = match &file.entries {
+                    Ok(entries) => entries
+                        .iter()
+                        .map(|kind| format!("{kind:?}").to_lowercase())
+                        .collect::<Vec<_>>()
+                        .join(" + "),
+                    Err(e) => format!("corrupted: {e}"),
+                };
+                let contents = if file.outdated {
+                    format!("{contents}, from an older version")
+                } else {
+                    contents
+                };
+                println!("{}: {} ({contents})", file.output, human_size(file.size));
+            }
+            let total = stats.iter().map(|file| file.size).sum();
+            println!("total: {} in {} files", human_size(total), stats.len());
+        }
+    }
+    Ok(())
+}
+
+fn human_size(bytes: u64) -> String {
+    let mut size = bytes as f64;
+    for unit in ["B", "KiB", "MiB"] {
+        if size < 1024.0 {
+            return format!("{size:.1}{unit}");
+        }
+        size /= 1024.0;
+    }
+    format!("{size:.1}GiB")
+}
+
+/// Splits the outputs given in the command line, replacing the names of groups with their outputs
+fn get_outputs(outputs: &str) -> Result<Vec<String>, Error> {
+    let mut outputs = split_cmdline_outputs(outputs);
+    if outputs.is_empty() {
+        return Ok(outputs);
+    }
+    let socket = swww_client::connect()?;
+    Request::Group(swww_protocol::GroupCommand::List).send(&socket)?;
+    match Answer::receive(socket)? {
+        Answer::Groups(groups) => swww_protocol::expand_groups(&mut outputs, &groups),
+        Answer::Err(e) => return Err(e.context("failed to get the groups of outputs")),
+        _ => unreachable!(),
+    }
+    Ok(outputs)
+}
+
+/// Which of `outputs` (or of all outputs, if empty) are displaying a plain color, rather than a
+/// wallpaper
+fn unset_outputs(outputs: &str) -> Result<Vec<String>, Error> {
+    let requested = get_outputs(outputs)?;
+    let socket = swww_client::connect()?;
+    Request::Query.send(&socket)?;
+    match Answer::receive(socket)? {
+        Answer::Info(infos) => Ok(infos
+            .into_iter()
+            .filter(|info| info.is_requested(&requested))
+            .filter(|info| matches!(info.img, swww_protocol::BgImg::Color(_)))
+            .map(|info| info.name)
+            .collect()),
+        Answer::Err(e) => Err(e.context("failed to query the outputs")),
+        _ => Err(Error::Ipc("unexpected answer to query".to_string())),
     }
 }
 
@@ -130,34 +799,136 @@ fn split_cmdline_outputs(outputs: &str) -> Vec<String> {
         .collect()
 }
 
-fn read_img(path: &Path) -> Result<(RgbaImage, bool), String> {
-    if let Some("-") = path.to_str() {
+/// Where to read the image from, with `-` meaning stdin
+fn img_path(img: &cli::Img) -> &Path {
+    match &img.path {
+        Some(path) => path,
+        None => Path::new("-"),
+    }
+}
+
+/// Returns `None` for the formats we decode ourselves, rather than with the `image` crate
+fn make_format(format: &cli::ImgFormat) -> Option<image::ImageFormat> {
+    Some(match format {
+        cli::ImgFormat::Bmp => image::ImageFormat::Bmp,
+        cli::ImgFormat::Farbfeld => image::ImageFormat::Farbfeld,
+        cli::ImgFormat::Gif => image::ImageFormat::Gif,
+        cli::ImgFormat::Jpeg => image::ImageFormat::Jpeg,
+        cli::ImgFormat::Png => image::ImageFormat::Png,
+        cli::ImgFormat::Pnm => image::ImageFormat::Pnm,
+        cli::ImgFormat::Tga => image::ImageFormat::Tga,
+        cli::ImgFormat::Tiff => image::ImageFormat::Tiff,
+        cli::ImgFormat::Webp => image::ImageFormat::WebP,
+        cli::ImgFormat::Xbm => return None,
+    })
+}
+
+/// Reads the image at `path` (or stdin, if it is `-`), guessing its format unless it is given.
+/// X11 bitmaps are drawn with `colors`. If the image is an animation, only its first frame is
+/// decoded, and the rest of it is returned to decode the other frames from
+fn read_img(
+    path: &Path,
+    format: Option<&cli::ImgFormat>,
+    colors: bitmap::Colors,
+) -> Result<(RgbaImage, Option<Animated>), Error> {
+    // we read the whole image at once, since we need its bytes to look for a color profile anyway
+    let is_stdin = path.to_str() == Some("-");
+    let (buffer, from_path) = if is_stdin {
         let mut reader = BufReader::new(stdin());
         let mut buffer = Vec::new();
         if let Err(e) = reader.read_to_end(&mut buffer) {
-            return Err(format!("failed to read stdin: {e}"));
+            return Err(Error::Image(format!("failed to read stdin: {e}")));
+        }
+        (buffer, None)
+    } else {
+        match std::fs::read(path) {
+            Ok(buffer) => (buffer, image::ImageFormat::from_path(path).ok()),
+            Err(e) => return Err(Error::Image(format!("failed to open image: {e}"))),
         }
+    };
 
-        return match image::load_from_memory(&buffer) {
-            Ok(img) => Ok((img.into_rgba8(), false)),
-            Err(e) => return Err(format!("failed load image from memory: {e}")),
+    let is_xbm = match format {
+        Some(format) => matches!(format, cli::ImgFormat::Xbm),
+        None => {
+            bitmap::is_xbm(&buffer)
+                || path
+                    .extension()
+                    .is_some_and(|ext| ext.eq_ignore_ascii_case("xbm"))
+        }
+    };
+    if is_xbm {
+        return match bitmap::decode(&buffer, colors) {
+            Ok(img) => Ok((img, None)),
+            Err(e) => Err(Error::Image(format!("failed to decode bitmap: {e}"))),
         };
     }
 
-    let imgbuf = match image::io::Reader::open(path) {
-        Ok(img) => img,
-        Err(e) => return Err(format!("failed to open image: {e}")),
+    let format = match format.and_then(make_format) {
+        Some(format) => format,
+        None => match image::guess_format(&buffer).ok().or(from_path) {
+            Some(format) => format,
+            None => {
+                return Err(Error::Image(
+                    "failed to detect the image's format".to_string(),
+                ))
+            }
+        },
     };
 
-    let imgbuf = match imgbuf.with_guessed_format() {
-        Ok(img) => img,
-        Err(e) => return Err(format!("failed to detect the image's format: {e}")),
+    let mut img = match image::load_from_memory_with_format(&buffer, format) {
+        Ok(img) => img.into_rgba8(),
+        Err(e) => return Err(Error::Image(format!("failed to decode image: {e}"))),
     };
+    if let Some(icc) = color::icc_profile(&buffer, format) {
+        // the colors will be off, but that is better than not displaying anything
+        if let Err(e) = color::to_srgb(&mut img, &icc) {
+            eprintln!("WARNING: {e}, displaying the image as if it was in sRGB");
+        }
+    }
+    if !animation::is_animated(&buffer, format) {
+        return Ok((img, None));
+    }
+    let animated = Animated {
+        format,
+        // we can't read stdin again to decode the other frames, so we keep what we read
+        stdin: is_stdin.then_some(buffer),
+    };
+    Ok((img, Some(animated)))
+}
+
+fn read_layers(layers: &[cli::CliLayer], colors: bitmap::Colors) -> Result<Vec<Layer>, Error> {
+    let mut v = Vec::with_capacity(layers.len());
+    for layer in layers {
+        let (img, _) = read_img(&layer.path, None, colors)
+            .map_err(|e| e.context(&format!("failed to open layer {:?}", layer.path)))?;
+        v.push(Layer {
+            img,
+            opacity: layer.opacity,
+            pos: make_position(&layer.pos),
+        });
+    }
+    Ok(v)
+}
 
-    let is_gif = imgbuf.format() == Some(image::ImageFormat::Gif);
-    match imgbuf.decode() {
-        Ok(img) => Ok((img.into_rgba8(), is_gif)),
-        Err(e) => Err(format!("failed to decode image: {e}")),
+/// Draws the `layers` over `canvas`, which must be in bgra
+fn composite_layers(canvas: &mut [u8], dimensions: (u32, u32), layers: &[Layer]) {
+    let (width, height) = (dimensions.0 as i64, dimensions.1 as i64);
+    for layer in layers {
+        let (center_x, center_y) = layer.pos.to_pixel(dimensions);
+        let left = (center_x - layer.img.width() as f32 / 2.0).round() as i64;
+        // our positions have the y axis growing upwards
+        let top = (height as f32 - center_y - layer.img.height() as f32 / 2.0).round() as i64;
+        for (x, y, pixel) in layer.img.enumerate_pixels() {
+            let (x, y) = (left + x as i64, top + y as i64);
+            if !(0..width).contains(&x) || !(0..height).contains(&y) {
+                continue;
+            }
+            let alpha = pixel[3] as f32 / 255.0 * layer.opacity;
+            let i = ((y * width + x) * 4) as usize;
+            for (byte, c) in canvas[i..i + 3].iter_mut().zip(pixel.0[0..3].iter().rev()) {
+                *byte = (*byte as f32 * (1.0 - alpha) + *c as f32 * alpha).round() as u8;
+            }
+        }
     }
 }
 
@@ -165,22 +936,43 @@ fn make_img_request(
     img: &cli::Img,
     img_raw: image::RgbaImage,
     dims: &[(u32, u32)],
+    areas: &[swww_protocol::SafeArea],
     outputs: &[Vec<String>],
-) -> Result<communication::ImageRequest, String> {
-    let transition = make_transition(img);
-    let mut unique_requests = Vec::with_capacity(dims.len());
-    for (dim, outputs) in dims.iter().zip(outputs) {
+    layers: &[Layer],
+    palette: Option<&[[u8; 3]]>,
+) -> Result<swww_protocol::ImageRequest, String> {
+    let transition = make_transition(&img.transition);
+    let mut unique_requests: Vec<(swww_protocol::Img, Vec<String>)> =
+        Vec::with_capacity(dims.len());
+    for (i, ((dim, area), outputs)) in dims.iter().zip(areas).zip(outputs).enumerate() {
+        if let Some(same) = same_geometry(dims, areas, i) {
+            let prepared = unique_requests[same].0.clone();
+            unique_requests.push((prepared, outputs.to_owned()));
+            continue;
+        }
+        let img_raw = match variant::find(img_path(img), *dim) {
+            Some(variant) => {
+                if img.transition.verbose {
+                    eprintln!("using {variant:?} for {}", outputs.join(", "));
+                }
+                let colors = bitmap::Colors {
+                    fg: img.fg,
+                    bg: img.bg,
+                };
+                match read_img(&variant, None, colors) {
+                    Ok((variant, _)) => variant,
+                    Err(e) => return Err(format!("failed to read variant {variant:?}: {e}")),
+                }
+            }
+            None => img_raw.clone(),
+        };
         unique_requests.push((
-            communication::Img {
-                img: if img.no_resize {
-                    img_pad(img_raw.clone(), *dim, &img.fill_color)?
-                } else {
-                    img_resize(img_raw.clone(), *dim, make_filter(&img.filter))?
-                },
-                path: match img.path.canonicalize() {
+            swww_protocol::Img {
+                img: prepare_img(img_raw, *dim, area, img, layers, palette)?,
+                path: match img_path(img).canonicalize() {
                     Ok(p) => p,
                     Err(e) => {
-                        if let Some("-") = img.path.to_str() {
+                        if let Some("-") = img_path(img).to_str() {
                             PathBuf::from("STDIN")
                         } else {
                             return Err(format!("failed no canonicalize image path: {e}"));
@@ -195,243 +987,350 @@ fn make_img_request(
     Ok((transition, unique_requests))
 }
 
+/// The first group before `i` with the same dimensions and safe area as it, if any. Groups that
+/// weren't merged only because their outputs display different things get the same image, so we
+/// reuse the one we already prepared instead of resizing (or compressing) it all over again
+fn same_geometry(
+    dims: &[(u32, u32)],
+    areas: &[swww_protocol::SafeArea],
+    i: usize,
+) -> Option<usize> {
+    (0..i).find(|&j| dims[j] == dims[i] && areas[j] == areas[i])
+}
+
+/// If `merge_outputs` is true, outputs with the same dimensions, safe area and displaying the same
+/// image will share the same entry, so that we only have to process the image once for all of them
 #[allow(clippy::type_complexity)]
 fn get_dimensions_and_outputs(
-    requested_outputs: Vec<String>,
-) -> Result<(Vec<(u32, u32)>, Vec<Vec<String>>), String> {
+    mut requested_outputs: Vec<String>,
+    merge_outputs: bool,
+) -> Result<
+    (
+        Vec<(u32, u32)>,
+        Vec<swww_protocol::SafeArea>,
+        Vec<Vec<String>>,
+    ),
+    Error,
+> {
     let mut outputs: Vec<Vec<String>> = Vec::new();
     let mut dims: Vec<(u32, u32)> = Vec::new();
-    let mut imgs: Vec<communication::BgImg> = Vec::new();
+    let mut areas: Vec<swww_protocol::SafeArea> = Vec::new();
+    let mut imgs: Vec<swww_protocol::BgImg> = Vec::new();
 
-    let socket = connect_to_socket(5, 100)?;
+    let socket = swww_client::connect()?;
     Request::Query.send(&socket)?;
     let answer = Answer::receive(socket)?;
     match answer {
         Answer::Info(infos) => {
+            swww_protocol::add_followers(&mut requested_outputs, infos.iter());
             for info in infos {
-                if !requested_outputs.is_empty() && !requested_outputs.contains(&info.name) {
+                if !info.is_requested(&requested_outputs) {
                     continue;
                 }
                 let mut should_add = true;
-                let real_dim = (
-                    info.dim.0 * info.scale_factor as u32,
-                    info.dim.1 * info.scale_factor as u32,
-                );
-                for (i, (dim, img)) in dims.iter().zip(&imgs).enumerate() {
-                    if real_dim == *dim && info.img == *img {
-                        outputs[i].push(info.name.clone());
-                        should_add = false;
-                        break;
+                let real_dim = info.real_dim();
+                if merge_outputs {
+                    for (i, ((dim, area), img)) in dims.iter().zip(&areas).zip(&imgs).enumerate() {
+                        if real_dim == *dim && info.safe_area == *area && info.img == *img {
+                            outputs[i].push(info.name.clone());
+                            should_add = false;
+                            break;
+                        }
                     }
                 }
 
                 if should_add {
                     outputs.push(vec![info.name]);
                     dims.push(real_dim);
+                    areas.push(info.safe_area);
                     imgs.push(info.img);
                 }
             }
             if outputs.is_empty() {
-                Err("none of the requested outputs are valid".to_owned())
+                Err(Error::Output(
+                    "none of the requested outputs are valid".to_owned(),
+                ))
             } else {
-                Ok((dims, outputs))
+                Ok((dims, areas, outputs))
             }
         }
-        Answer::Err(e) => Err(format!("failed to query swww-daemon: {e}")),
+        Answer::Err(e) => Err(e.context("failed to query swww-daemon")),
         _ => unreachable!(),
     }
 }
 
+/// Has the daemon draw a progress bar over `outputs` for every value received from `progress`,
+/// and removes it once `progress` is closed. Updates are sent at most every `PROGRESS_INTERVAL`
+fn report_progress(progress: mpsc::Receiver<f32>, outputs: Vec<String>) {
+    let mut last_report: Option<Instant> = None;
+    for value in progress.into_iter().map(Some).chain([None]) {
+        if value.is_some() && last_report.is_some_and(|last| last.elapsed() < PROGRESS_INTERVAL) {
+            continue;
+        }
+        last_report = Some(Instant::now());
+        let request = Request::Busy(swww_protocol::Busy {
+            outputs: outputs.clone(),
+            progress: value,
+        });
+        // this is just cosmetic, so we don't give up on the animation if it fails
+        let sent = swww_client::connect_retrying(1, Duration::ZERO).and_then(|socket| {
+            request.send(&socket)?;
+            Answer::receive(socket)
+        });
+        if let Err(e) = sent {
+            eprintln!("WARNING: failed to update the busy indicator: {e}");
+            return;
+        }
+    }
+}
+
+/// Compresses `img`, the `animated` image, for every output. If `progress` is given, the
+/// fraction of the frames already processed is sent through it after each frame
 fn make_animation_request(
-    img: &cli::Img,
+    (img, animated): (&cli::Img, &Animated),
     dims: &[(u32, u32)],
+    areas: &[swww_protocol::SafeArea],
     outputs: &[Vec<String>],
+    layers: &[Layer],
+    palette: Option<&[[u8; 3]]>,
+    progress: Option<mpsc::Sender<f32>>,
 ) -> Result<AnimationRequest, String> {
-    let filter = make_filter(&img.filter);
-    let mut animations = Vec::with_capacity(dims.len());
-    for (dim, outputs) in dims.iter().zip(outputs) {
-        let imgbuf = match image::io::Reader::open(&img.path) {
-            Ok(img) => img.into_inner(),
-            Err(e) => return Err(format!("error opening image during animation: {e}")),
-        };
-        let gif = match GifDecoder::new(imgbuf) {
-            Ok(gif) => gif,
-            Err(e) => return Err(format!("failed to decode gif during animation: {e}")),
-        };
+    let unique = (0..dims.len())
+        .filter(|&i| same_geometry(dims, areas, i).is_none())
+        .count();
+    let total_frames = match progress {
+        Some(_) => count_frames(animated.open(img_path(img))?, animated.format)? * unique,
+        None => 0,
+    };
+    let mut done_frames = 0;
+    let mut report = || {
+        done_frames += 1;
+        if let Some(progress) = &progress {
+            // the receiver may have given up, which doesn't matter for us
+            let _ = progress.send(done_frames as f32 / total_frames.max(1) as f32);
+        }
+    };
+    let mut animations: AnimationRequest = Vec::with_capacity(dims.len());
+    // where the animation of every group is, since groups of the same geometry share one
+    let mut indices: Vec<usize> = Vec::with_capacity(dims.len());
+    for (i, ((dim, area), outputs)) in dims.iter().zip(areas).zip(outputs).enumerate() {
+        if let Some(same) = same_geometry(dims, areas, i) {
+            // unlike transitions, animations don't care about what the outputs displayed before,
+            // so the daemon can play a single one for all of them
+            let same = indices[same];
+            animations[same].1.extend_from_slice(outputs);
+            indices.push(same);
+            continue;
+        }
+        indices.push(animations.len());
+        let decoder = AnimatedDecoder::new(animated.open(img_path(img))?, animated.format)?;
         animations.push((
-            communication::Animation {
-                animation: compress_frames(gif, *dim, filter, img.no_resize, &img.fill_color)?
-                    .into_boxed_slice(),
-                sync: img.sync,
-            },
+            compress_frames(decoder, *dim, area, img, layers, palette, &mut report)?,
             outputs.to_owned(),
         ));
     }
     Ok(animations)
 }
 
-fn compress_frames(
-    gif: GifDecoder<BufReader<File>>,
-    dim: (u32, u32),
-    filter: FilterType,
-    no_resize: bool,
-    color: &[u8; 3],
-) -> Result<Vec<(BitPack, Duration)>, String> {
-    let mut compressed_frames = Vec::new();
-    let mut frames = gif.into_frames();
-
-    // The first frame should always exist
-    let first = frames.next().unwrap().unwrap();
-    let first_duration = first.delay().numer_denom_ms();
-    let first_duration = Duration::from_millis((first_duration.0 / first_duration.1).into());
-    let first_img = if no_resize {
-        img_pad(first.into_buffer(), dim, color)?
-    } else {
-        img_resize(first.into_buffer(), dim, filter)?
+fn make_region(region: &cli::Region) -> Result<swww_protocol::Region, Error> {
+    let reader = match image::io::Reader::open(&region.path).and_then(|r| r.with_guessed_format()) {
+        Ok(reader) => reader,
+        Err(e) => return Err(Error::Image(format!("failed to open animation: {e}"))),
     };
-
-    let mut canvas = first_img.clone();
-    while let Some(Ok(frame)) = frames.next() {
-        let (dur_num, dur_div) = frame.delay().numer_denom_ms();
-        let duration = Duration::from_millis((dur_num / dur_div).into());
-
-        let img = if no_resize {
-            img_pad(frame.into_buffer(), dim, color)?
-        } else {
-            img_resize(frame.into_buffer(), dim, filter)?
-        };
-
-        compressed_frames.push((BitPack::pack(&mut canvas, &img)?, duration));
+    let Some(format) = reader.format() else {
+        return Err(Error::Image(
+            "failed to detect the animation's format".to_string(),
+        ));
+    };
+    let decoder = AnimatedDecoder::new(reader.into_inner(), format).map_err(Error::Image)?;
+    let dim = region.size.unwrap_or_else(|| decoder.dimensions());
+    let mut first = None;
+    let compressed = animation::compress_frames(
+        decoder,
+        |img| {
+            // this won't resize anything if the dimensions are already right
+            let img = resize::resize(img, dim, FilterType::Lanczos3)?;
+            if first.is_none() {
+                first = Some(img.clone());
+            }
+            Ok(img)
+        },
+        0,
+        0,
+        None,
+        None,
+        &mut || (),
+    )
+    .map_err(Error::Image)?;
+    if compressed.frames.len() <= 1 {
+        return Err(Error::Image(format!(
+            "{:?} isn't animated, use `swww patch` for still images",
+            region.path
+        )));
     }
-    //Add the first frame we got earlier:
-    compressed_frames.push((BitPack::pack(&mut canvas, &first_img)?, first_duration));
 
-    Ok(compressed_frames)
-}
-
-fn make_filter(filter: &cli::Filter) -> fast_image_resize::FilterType {
-    match filter {
-        cli::Filter::Nearest => fast_image_resize::FilterType::Box,
-        cli::Filter::Bilinear => fast_image_resize::FilterType::Bilinear,
-        cli::Filter::CatmullRom => fast_image_resize::FilterType::CatmullRom,
-        cli::Filter::Mitchell => fast_image_resize::FilterType::Mitchell,
-        cli::Filter::Lanczos3 => fast_image_resize::FilterType::Lanczos3,
+    // the daemon starts from the last frame's keyframe, which goes back to the first image
+    let mut keyframes = compressed.keyframes.into_vec();
+    if let Some(first) = first {
+        keyframes.push((compressed.frames.len() - 1, BitPack::keyframe(&first)?));
     }
+    Ok(swww_protocol::Region {
+        animation: swww_protocol::Animation {
+            animation: compressed.frames,
+            keyframes: keyframes.into_boxed_slice(),
+            sync: false,
+        },
+        dim,
+        pos: make_position(&region.pos),
+        outputs: get_outputs(&region.outputs)?,
+    })
 }
 
-fn img_pad(
-    mut img: image::RgbaImage,
-    dimensions: (u32, u32),
-    color: &[u8; 3],
-) -> Result<Vec<u8>, String> {
-    let (padded_w, padded_h) = dimensions;
-    let (padded_w, padded_h) = (padded_w as usize, padded_h as usize);
-    let mut padded = Vec::with_capacity(padded_w * padded_w * 4);
-
-    let img = image::imageops::crop(&mut img, 0, 0, dimensions.0, dimensions.1).to_image();
-    let (img_w, img_h) = img.dimensions();
-    let (img_w, img_h) = (img_w as usize, img_h as usize);
-    let raw_img = img.into_vec();
-
-    for _ in 0..(((padded_h - img_h) / 2) * padded_w) {
-        padded.push(color[2]);
-        padded.push(color[1]);
-        padded.push(color[0]);
-        padded.push(255);
+/// Counts the frames of an animation in `format`, without decoding them into images
+fn count_frames(mut reader: impl Read, format: image::ImageFormat) -> Result<usize, String> {
+    if format != image::ImageFormat::Gif {
+        let mut bytes = Vec::new();
+        return match reader.read_to_end(&mut bytes) {
+            Ok(_) => animation::count_frames(&bytes, format)
+                .ok_or_else(|| "failed to count the animation's frames".to_string()),
+            Err(e) => Err(format!("error reading image to count its frames: {e}")),
+        };
     }
-
-    for row in 0..img_h {
-        for _ in 0..(padded_w - img_w) / 2 {
-            padded.push(color[2]);
-            padded.push(color[1]);
-            padded.push(color[0]);
-            padded.push(255);
-        }
-
-        for pixel in raw_img[(row * img_w * 4)..((row + 1) * img_w * 4)].chunks_exact(4) {
-            padded.push(pixel[2]);
-            padded.push(pixel[1]);
-            padded.push(pixel[0]);
-            padded.push(pixel[3]);
-        }
-        for _ in 0..(padded_w - img_w) / 2 {
-            padded.push(color[2]);
-            padded.push(color[1]);
-            padded.push(color[0]);
-            padded.push(255);
+    let mut decoder = match gif::DecodeOptions::new().read_info(reader) {
+        Ok(decoder) => decoder,
+        Err(e) => return Err(format!("failed to decode gif to count its frames: {e}")),
+    };
+    let mut frames = 0;
+    loop {
+        match decoder.next_frame_info() {
+            Ok(Some(_)) => frames += 1,
+            Ok(None) => return Ok(frames),
+            Err(e) => return Err(format!("failed to read gif frame: {e}")),
         }
     }
+}
 
-    while padded.len() < (padded_h * padded_w * 4) {
-        padded.push(color[2]);
-        padded.push(color[1]);
-        padded.push(color[0]);
-        padded.push(255);
-    }
-
-    Ok(padded)
+fn compress_frames(
+    decoder: AnimatedDecoder,
+    dim: (u32, u32),
+    area: &swww_protocol::SafeArea,
+    args: &cli::Img,
+    layers: &[Layer],
+    palette: Option<&[[u8; 3]]>,
+    report: &mut dyn FnMut(),
+) -> Result<swww_protocol::Animation, String> {
+    let compressed = animation::compress_frames(
+        decoder,
+        |img| prepare_img(img, dim, area, args, layers, palette),
+        args.diff_threshold,
+        args.keyframe_interval,
+        args.anim_blend.map(|duration| animation::Blend {
+            duration,
+            min_delay: ANIM_BLEND_MIN_DELAY,
+        }),
+        args.anim_smooth_loop,
+        report,
+    )?;
+    Ok(swww_protocol::Animation {
+        animation: compressed.frames,
+        keyframes: compressed.keyframes,
+        sync: args.sync,
+    })
 }
 
-fn img_resize(
-    img: image::RgbaImage,
-    dimensions: (u32, u32),
-    filter: FilterType,
+/// Turns `img` into what will be displayed in an output with dimensions `dim`, applying all the
+/// options given in `args`. Resized images are centered in the part of the output outside of `area`
+fn prepare_img(
+    img: RgbaImage,
+    dim: (u32, u32),
+    area: &swww_protocol::SafeArea,
+    args: &cli::Img,
+    layers: &[Layer],
+    palette: Option<&[[u8; 3]]>,
 ) -> Result<Vec<u8>, String> {
-    let (width, height) = dimensions;
-    let (img_w, img_h) = img.dimensions();
-    let mut resized_img = if (img_w, img_h) != (width, height) {
-        let mut src = match fast_image_resize::Image::from_vec_u8(
-            // We unwrap below because we know the images's dimensions should never be 0
-            NonZeroU32::new(img_w).unwrap(),
-            NonZeroU32::new(img_h).unwrap(),
-            img.into_raw(),
-            PixelType::U8x4,
-        ) {
-            Ok(i) => i,
-            Err(e) => return Err(e.to_string()),
-        };
-
-        let alpha_mul_div = fast_image_resize::MulDiv::default();
-        if let Err(e) = alpha_mul_div.multiply_alpha_inplace(&mut src.view_mut()) {
-            return Err(e.to_string());
-        }
-
-        // We unwrap below because we know the outputs's dimensions should never be 0
-        let new_w = NonZeroU32::new(width).unwrap();
-        let new_h = NonZeroU32::new(height).unwrap();
-        let mut src_view = src.view();
-        src_view.set_crop_box_to_fit_dst_size(new_w, new_h, Some((0.5, 0.5)));
-
-        let mut dst = fast_image_resize::Image::new(new_w, new_h, PixelType::U8x4);
-        let mut dst_view = dst.view_mut();
-
-        let mut resizer = Resizer::new(fast_image_resize::ResizeAlg::Convolution(filter));
-        if let Err(e) = resizer.resize(&src_view, &mut dst_view) {
-            return Err(e.to_string());
-        }
-
-        if let Err(e) = alpha_mul_div.divide_alpha_inplace(&mut dst_view) {
-            return Err(e.to_string());
-        }
-
-        dst.into_vec()
+    let inner_dim = (
+        dim.0.saturating_sub(2 * args.margin).max(1),
+        dim.1.saturating_sub(2 * args.margin).max(1),
+    );
+    let fill = match args.fill_mode {
+        cli::FillMode::Color => resize::Fill::Color(args.fill_color),
+        cli::FillMode::Blur => resize::Fill::Blur,
+        cli::FillMode::Edge => resize::Fill::Edge,
+        cli::FillMode::Dominant => resize::Fill::Dominant,
+    };
+    let strategy = if args.no_resize {
+        &cli::ResizeStrategy::No
     } else {
-        img.into_vec()
+        &args.resize
     };
-
-    // The ARGB is 'little endian', so here we must  put the order
-    // of bytes 'in reverse', so it needs to be BGRA.
-    for pixel in resized_img.chunks_exact_mut(4) {
-        pixel.swap(0, 2);
+    let mut prepared = match strategy {
+        cli::ResizeStrategy::No => resize::pad(img, inner_dim, fill)?,
+        cli::ResizeStrategy::Tile => resize::tile(img, inner_dim)?,
+        cli::ResizeStrategy::Stretch => resize::stretch(img, inner_dim, make_filter(&args.filter))?,
+        cli::ResizeStrategy::Fit => resize::fit(img, inner_dim, make_filter(&args.filter), fill)?,
+        cli::ResizeStrategy::Crop => {
+            // the margin already keeps part of the image clear of the output's edges
+            let area = swww_protocol::SafeArea {
+                top: area.top.saturating_sub(args.margin),
+                bottom: area.bottom.saturating_sub(args.margin),
+                left: area.left.saturating_sub(args.margin),
+                right: area.right.saturating_sub(args.margin),
+            };
+            let (virtual_dim, offset) = safe_area_window(inner_dim, &area);
+            let resized = resize::resize(img, virtual_dim, make_filter(&args.filter))?;
+            if virtual_dim == inner_dim {
+                resized
+            } else {
+                resize::crop(&resized, virtual_dim, offset, inner_dim)
+            }
+        }
+    };
+    if inner_dim != dim || args.corner_radius > 0 {
+        prepared = resize::frame(
+            &prepared,
+            inner_dim,
+            dim,
+            args.corner_radius,
+            &args.fill_color,
+        );
+    }
+    composite_layers(&mut prepared, dim, layers);
+    if args.rotate_deg != 0.0 {
+        prepared = resize::rotate(&prepared, dim, args.rotate_deg, fill)?;
+    }
+    if let Some(palette) = palette {
+        palette::quantize(&mut prepared, dim, palette);
     }
+    Ok(prepared)
+}
 
-    Ok(resized_img)
+/// To center an image in the part of `dim` outside of `area`, we resize it to a larger, virtual
+/// output, whose center is the center of that part. Returns the virtual output's dimensions and
+/// where `dim` starts within it
+fn safe_area_window(dim: (u32, u32), area: &swww_protocol::SafeArea) -> ((u32, u32), (u32, u32)) {
+    let virtual_dim = (
+        dim.0 + area.left.abs_diff(area.right),
+        dim.1 + area.top.abs_diff(area.bottom),
+    );
+    let offset = (
+        area.right.saturating_sub(area.left),
+        area.bottom.saturating_sub(area.top),
+    );
+    (virtual_dim, offset)
 }
 
-fn make_transition(img: &cli::Img) -> communication::Transition {
-    let mut angle = img.transition_angle;
+fn make_filter(filter: &cli::Filter) -> FilterType {
+    match filter {
+        cli::Filter::Nearest => FilterType::Box,
+        cli::Filter::Bilinear => FilterType::Bilinear,
+        cli::Filter::CatmullRom => FilterType::CatmullRom,
+        cli::Filter::Mitchell => FilterType::Mitchell,
+        cli::Filter::Lanczos3 => FilterType::Lanczos3,
+    }
+}
 
-    let x = match img.transition_pos.x {
+fn make_position(pos: &cli::CliPosition) -> Position {
+    let x = match pos.x {
         cli::CliCoord::Percent(x) => {
             if !(0.0..=1.0).contains(&x) {
                 println!(
@@ -443,7 +1342,7 @@ fn make_transition(img: &cli::Img) -> communication::Transition {
         cli::CliCoord::Pixel(x) => Coord::Pixel(x),
     };
 
-    let y = match img.transition_pos.y {
+    let y = match pos.y {
         cli::CliCoord::Percent(y) => {
             if !(0.0..=1.0).contains(&y) {
                 println!(
@@ -455,121 +1354,235 @@ fn make_transition(img: &cli::Img) -> communication::Transition {
         cli::CliCoord::Pixel(y) => Coord::Pixel(y),
     };
 
-    let mut pos = Position::new(x, y);
+    Position::new(x, y)
+}
+
+fn make_transition(transition: &cli::Transition) -> swww_protocol::Transition {
+    let mut rng = match transition.seed {
+        Some(seed) => StdRng::seed_from_u64(seed),
+        None => StdRng::from_entropy(),
+    };
+    let mut angle = transition.transition_angle;
 
-    let transition_type = match img.transition_type {
-        cli::TransitionType::Simple => communication::TransitionType::Simple,
-        cli::TransitionType::Wipe => communication::TransitionType::Wipe,
-        cli::TransitionType::Outer => communication::TransitionType::Outer,
-        cli::TransitionType::Grow => communication::TransitionType::Grow,
-        cli::TransitionType::Wave => communication::TransitionType::Wave,
+    let mut pos = make_position(&transition.transition_pos);
+
+    let transition_type = match &transition.transition_type {
+        cli::TransitionType::Simple => swww_protocol::TransitionType::Simple,
+        cli::TransitionType::Wipe => swww_protocol::TransitionType::Wipe,
+        cli::TransitionType::Outer => swww_protocol::TransitionType::Outer,
+        cli::TransitionType::Grow => swww_protocol::TransitionType::Grow,
+        cli::TransitionType::Wave => swww_protocol::TransitionType::Wave,
+        cli::TransitionType::Flash => swww_protocol::TransitionType::Flash(transition.flash_color),
+        cli::TransitionType::Slide => swww_protocol::TransitionType::Slide,
+        cli::TransitionType::Plugin(name) => swww_protocol::TransitionType::Plugin(name.clone()),
         cli::TransitionType::Right => {
             angle = 0.0;
-            communication::TransitionType::Wipe
+            swww_protocol::TransitionType::Wipe
         }
         cli::TransitionType::Top => {
             angle = 90.0;
-            communication::TransitionType::Wipe
+            swww_protocol::TransitionType::Wipe
         }
         cli::TransitionType::Left => {
             angle = 180.0;
-            communication::TransitionType::Wipe
+            swww_protocol::TransitionType::Wipe
         }
         cli::TransitionType::Bottom => {
             angle = 270.0;
-            communication::TransitionType::Wipe
+            swww_protocol::TransitionType::Wipe
         }
         cli::TransitionType::Center => {
             pos = Position::new(Coord::Percent(0.5), Coord::Percent(0.5));
-            communication::TransitionType::Grow
+            swww_protocol::TransitionType::Grow
         }
         cli::TransitionType::Any => {
             pos = Position::new(
-                Coord::Percent(rand::random::<f32>()),
-                Coord::Percent(rand::random::<f32>()),
+                Coord::Percent(rng.gen::<f32>()),
+                Coord::Percent(rng.gen::<f32>()),
             );
-            if rand::random::<u8>() % 2 == 0 {
-                communication::TransitionType::Grow
+            if rng.gen::<bool>() {
+                swww_protocol::TransitionType::Grow
             } else {
-                communication::TransitionType::Outer
+                swww_protocol::TransitionType::Outer
             }
         }
         cli::TransitionType::Random => {
             pos = Position::new(
-                Coord::Percent(rand::random::<f32>()),
-                Coord::Percent(rand::random::<f32>()),
+                Coord::Percent(rng.gen::<f32>()),
+                Coord::Percent(rng.gen::<f32>()),
             );
-            angle = rand::random();
-            match rand::random::<u8>() % 4 {
-                0 => communication::TransitionType::Simple,
-                1 => communication::TransitionType::Wipe,
-                2 => communication::TransitionType::Outer,
-                3 => communication::TransitionType::Grow,
+            angle = rng.gen();
+            match rng.gen::<u8>() % 4 {
+                0 => swww_protocol::TransitionType::Simple,
+                1 => swww_protocol::TransitionType::Wipe,
+                2 => swww_protocol::TransitionType::Outer,
+                3 => swww_protocol::TransitionType::Grow,
                 _ => unreachable!(),
             }
         }
     };
 
-    communication::Transition {
-        duration: img.transition_duration,
-        step: img.transition_step,
-        fps: img.transition_fps,
-        bezier: img.transition_bezier,
+    if transition.verbose {
+        eprintln!("transition = {transition_type:?}, angle {angle}, pos {pos:?}");
+    }
+
+    swww_protocol::Transition {
+        duration: transition.transition_duration,
+        step: transition.transition_step,
+        fps: transition.transition_fps,
+        bezier: transition.transition_bezier,
         angle,
         pos,
         transition_type,
-        wave: img.transition_wave,
+        wave: transition.transition_wave,
+        sync: match transition.transition_sync {
+            cli::TransitionSync::None => swww_protocol::TransitionSync::None,
+            cli::TransitionSync::Layout => swww_protocol::TransitionSync::Layout,
+        },
+        skip_threshold: transition.transition_skip_threshold,
+    }
+}
+
+fn make_generator(generator: &cli::Generator) -> Result<swww_protocol::Generator, String> {
+    match generator {
+        cli::Generator::Gradient {
+            colors,
+            angle,
+            animate,
+            fps,
+        } => Ok(swww_protocol::Generator::Gradient {
+            colors: colors.clone(),
+            angle: *angle,
+            period: *animate,
+            fps: *fps,
+        }),
+        cli::Generator::Plasma {
+            colors,
+            scale,
+            speed,
+            fps,
+        } => Ok(swww_protocol::Generator::Plasma {
+            colors: colors.clone(),
+            scale: *scale,
+            speed: *speed,
+            fps: *fps,
+        }),
+        cli::Generator::Clock {
+            format,
+            font,
+            size,
+            color,
+            pos,
+        } => Ok(swww_protocol::Generator::Clock {
+            format: format.clone(),
+            font: match std::fs::read(font) {
+                Ok(font) => font,
+                Err(e) => return Err(format!("failed to read font file {font:?}: {e}")),
+            },
+            size: *size,
+            color: *color,
+            pos: make_position(pos),
+        }),
+        cli::Generator::Life {
+            cell_size,
+            alive,
+            dead,
+            fps,
+        } => Ok(swww_protocol::Generator::Life {
+            cell_size: *cell_size,
+            alive: *alive,
+            dead: *dead,
+            fps: *fps,
+        }),
+        cli::Generator::External { fps, command } => Ok(swww_protocol::Generator::External {
+            command: command.clone(),
+            fps: *fps,
+        }),
+        cli::Generator::TestPattern { pattern } => Ok(swww_protocol::Generator::TestPattern {
+            pattern: match pattern {
+                cli::TestPattern::Bars => swww_protocol::TestPattern::Bars,
+                cli::TestPattern::Ramps => swww_protocol::TestPattern::Ramps,
+                cli::TestPattern::Grid => swww_protocol::TestPattern::Grid,
+            },
+        }),
+    }
+}
+
+/// Whether the daemon should reduce motion, asking the desktop when the user didn't say
+fn prefers_reduced_motion(reduce_motion: &cli::ReduceMotion) -> bool {
+    match reduce_motion {
+        cli::ReduceMotion::On => true,
+        cli::ReduceMotion::Off => false,
+        #[cfg(feature = "portal")]
+        cli::ReduceMotion::Auto => portal::prefers_reduced_motion(),
+        #[cfg(not(feature = "portal"))]
+        cli::ReduceMotion::Auto => false,
     }
 }
 
-fn spawn_daemon(no_daemon: bool) -> Result<(), String> {
-    let cmd = "swww-daemon";
+fn spawn_daemon(
+    (no_daemon, replace, supervise): (bool, bool, bool),
+    cache_size: Option<u64>,
+    hook: Option<&str>,
+    (min_interval, reduce_motion): (Option<Duration>, bool),
+    (delay, wait_for): (Option<Duration>, Option<&str>),
+    (fifo, listen): (bool, Option<&str>),
+    (layer, exclusive_zone, max_resolution): (Option<&str>, Option<&str>, Option<&str>),
+) -> Result<(), Error> {
+    let mut cmd = std::process::Command::new("swww-daemon");
+    if let Some(size) = cache_size {
+        cmd.arg("--cache-size").arg(size.to_string());
+    }
+    if let Some(hook) = hook {
+        cmd.arg("--hook").arg(hook);
+    }
+    if let Some(interval) = min_interval {
+        cmd.arg("--min-interval")
+            .arg(format!("{}ms", interval.as_millis()));
+    }
+    if let Some(delay) = delay {
+        cmd.arg("--delay").arg(format!("{}ms", delay.as_millis()));
+    }
+    if let Some(command) = wait_for {
+        cmd.arg("--wait-for").arg(command);
+    }
+    if fifo {
+        cmd.arg("--fifo");
+    }
+    if let Some(listen) = listen {
+        cmd.arg("--listen").arg(listen);
+    }
+    if let Some(layer) = layer {
+        cmd.arg("--layer").arg(layer);
+    }
+    if let Some(zone) = exclusive_zone {
+        cmd.arg("--exclusive-zone").arg(zone);
+    }
+    if let Some(max) = max_resolution {
+        cmd.arg("--max-resolution").arg(max);
+    }
+    if reduce_motion {
+        cmd.arg("--reduce-motion");
+    }
+    if replace {
+        cmd.arg("--replace");
+    }
+    if supervise {
+        cmd = supervise::command(&cmd)?;
+    }
     if no_daemon {
-        match std::process::Command::new(cmd).status() {
+        match cmd.status() {
             Ok(_) => Ok(()),
-            Err(e) => Err(format!("error spawning swww-daemon: {e}")),
+            Err(e) => Err(Error::Daemon(format!("error spawning swww-daemon: {e}"))),
         }
     } else {
-        match std::process::Command::new(cmd)
-            .stdout(Stdio::null())
-            .stderr(Stdio::null())
-            .spawn()
-        {
+        match cmd.stdout(Stdio::null()).stderr(Stdio::null()).spawn() {
             Ok(_) => Ok(()),
-            Err(e) => Err(format!("error spawning swww-daemon: {e}")),
+            Err(e) => Err(Error::Daemon(format!("error spawning swww-daemon: {e}"))),
         }
     }
 }
 
-/// We make sure the Stream is always set to blocking mode
-///
-/// * `tries` -  how make times to attempt the connection
-/// * `interval` - how long to wait between attempts, in milliseconds
-fn connect_to_socket(tries: u8, interval: u64) -> Result<UnixStream, String> {
-    //Make sure we try at least once
-    let tries = if tries == 0 { 1 } else { tries };
-    let path = get_socket_path();
-    let mut error = None;
-    for _ in 0..tries {
-        match UnixStream::connect(&path) {
-            Ok(socket) => {
-                if let Err(e) = socket.set_nonblocking(false) {
-                    return Err(format!("Failed to set blocking connection: {e}"));
-                }
-                return Ok(socket);
-            }
-            Err(e) => error = Some(e),
-        }
-        std::thread::sleep(Duration::from_millis(interval));
-    }
-    let error = error.unwrap();
-    if error.kind() == std::io::ErrorKind::NotFound {
-        return Err("Socket file not found. Are you sure swww-daemon is running?".to_string());
-    }
-
-    Err(format!("Failed to connect to socket: {error}"))
-}
-
 fn is_daemon_running() -> Result<bool, String> {
     let proc = PathBuf::from("/proc");
 
@@ -0,0 +1,57 @@
+//! `--verbose`: lists the options a command ended up with, and which layer of configuration each
+//! one came from (the command line, the environment or swww's defaults), so that users can tell
+//! why a setting they made isn't being applied
+use clap::{parser::ValueSource, ArgMatches, CommandFactory};
+
+use crate::cli::Swww;
+
+/// One line per option of the subcommand in `matches`, in the order they appear in `--help`
+pub fn effective(matches: &ArgMatches) -> Vec<String> {
+    let Some((name, matches)) = matches.subcommand() else {
+        return Vec::new();
+    };
+    let cmd = Swww::command();
+    let Some(cmd) = cmd.find_subcommand(name) else {
+        return Vec::new();
+    };
+
+    let mut lines = Vec::new();
+    for arg in cmd.get_arguments() {
+        let id = arg.get_id().as_str();
+        let Ok(Some(values)) = matches.try_get_raw(id) else {
+            continue;
+        };
+        let values: Vec<_> = values.map(|value| value.to_string_lossy()).collect();
+        let source = match matches.value_source(id) {
+            Some(ValueSource::CommandLine) => "command line".to_string(),
+            Some(ValueSource::EnvVariable) => match arg.get_env() {
+                Some(env) => format!("environment, {}", env.to_string_lossy()),
+                None => "environment".to_string(),
+            },
+            Some(ValueSource::DefaultValue) => "default".to_string(),
+            _ => "unknown".to_string(),
+        };
+        lines.push(format!(
+            "{} = {} ({source})",
+            arg.get_long().unwrap_or(id),
+            values.join(",")
+        ));
+    }
+    lines
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn should_say_where_options_come_from() {
+        let matches = Swww::command()
+            .try_get_matches_from(["swww", "clear", "--verbose", "--transition-fps", "60"])
+            .unwrap();
+        let lines = effective(&matches);
+        assert!(lines.contains(&"color = 000000 (default)".to_string()));
+        assert!(lines.contains(&"transition-fps = 60 (command line)".to_string()));
+        assert!(lines.contains(&"verbose = true (command line)".to_string()));
+    }
+}
@@ -0,0 +1,241 @@
+//! Quantizes images to a fixed palette, with Floyd-Steinberg dithering, for retro looks
+use image::RgbaImage;
+
+use std::path::Path;
+
+/// Palettes that can be given by name, instead of with a file
+const BUILTINS: &[(&str, &[[u8; 3]])] = &[
+    (
+        "gameboy",
+        &[
+            [0x0f, 0x38, 0x0f],
+            [0x30, 0x62, 0x30],
+            [0x8b, 0xac, 0x0f],
+            [0x9b, 0xbc, 0x0f],
+        ],
+    ),
+    (
+        "cga",
+        &[
+            [0x00, 0x00, 0x00],
+            [0x55, 0xff, 0xff],
+            [0xff, 0x55, 0xff],
+            [0xff, 0xff, 0xff],
+        ],
+    ),
+    (
+        "pico8",
+        &[
+            [0x00, 0x00, 0x00],
+            [0x1d, 0x2b, 0x53],
+            [0x7e, 0x25, 0x53],
+            [0x00, 0x87, 0x51],
+            [0xab, 0x52, 0x36],
+            [0x5f, 0x57, 0x4f],
+            [0xc2, 0xc3, 0xc7],
+            [0xff, 0xf1, 0xe8],
+            [0xff, 0x00, 0x4d],
+            [0xff, 0xa3, 0x00],
+            [0xff, 0xec, 0x27],
+            [0x00, 0xe4, 0x36],
+            [0x29, 0xad, 0xff],
+            [0x83, 0x76, 0x9c],
+            [0xff, 0x77, 0xa8],
+            [0xff, 0xcc, 0xaa],
+        ],
+    ),
+    ("mono", &[[0x00, 0x00, 0x00], [0xff, 0xff, 0xff]]),
+];
+
+/// How many pixels we look at, at most, when picking a palette out of an image
+const MAX_SAMPLES: usize = 1 << 16;
+
+/// Loads the palette described by `spec`, which is either the name of a builtin palette,
+/// 'auto:<n>', to pick `n` colors out of `img` with median cut, or the path to a GIMP palette
+pub fn load(spec: &str, img: &RgbaImage) -> Result<Vec<[u8; 3]>, String> {
+    if let Some((_, colors)) = BUILTINS.iter().find(|(name, _)| *name == spec) {
+        return Ok(colors.to_vec());
+    }
+    if let Some(n) = spec.strip_prefix("auto:") {
+        return match n.parse::<usize>() {
+            Ok(n) if n > 0 => Ok(median_cut(img, n)),
+            _ => Err(format!("invalid number of colors: {n}")),
+        };
+    }
+    let path = Path::new(spec);
+    if path.extension().is_some_and(|ext| ext == "gpl") {
+        return match std::fs::read_to_string(path) {
+            Ok(contents) => parse_gpl(&contents),
+            Err(e) => Err(format!("failed to read palette {spec}: {e}")),
+        };
+    }
+    let names: Vec<_> = BUILTINS.iter().map(|(name, _)| *name).collect();
+    Err(format!(
+        "unknown palette: {spec}. Use a .gpl file, 'auto:<n>' or one of: {}",
+        names.join(", ")
+    ))
+}
+
+/// Parses a palette in GIMP's format: a 'GIMP Palette' header, followed by one 'R G B [name]' line
+/// per color
+fn parse_gpl(contents: &str) -> Result<Vec<[u8; 3]>, String> {
+    let mut lines = contents.lines().map(str::trim);
+    if lines.next() != Some("GIMP Palette") {
+        return Err("palette is missing its 'GIMP Palette' header".to_string());
+    }
+    let mut colors = Vec::new();
+    for line in lines {
+        if line.is_empty()
+            || line.starts_with('#')
+            || line.starts_with("Name:")
+            || line.starts_with("Columns:")
+        {
+            continue;
+        }
+        let mut components = line.split_whitespace().map(str::parse::<u8>);
+        match (components.next(), components.next(), components.next()) {
+            (Some(Ok(r)), Some(Ok(g)), Some(Ok(b))) => colors.push([r, g, b]),
+            _ => return Err(format!("invalid palette line: {line}")),
+        }
+    }
+    if colors.is_empty() {
+        return Err("palette has no colors".to_string());
+    }
+    Ok(colors)
+}
+
+/// Picks `n` colors that represent `img` well, by repeatedly splitting the box of colors with the
+/// widest range in half, at its median
+pub fn median_cut(img: &RgbaImage, n: usize) -> Vec<[u8; 3]> {
+    let step = (img.width() as usize * img.height() as usize / MAX_SAMPLES).max(1);
+    let samples: Vec<[u8; 3]> = img
+        .pixels()
+        .step_by(step)
+        .map(|p| [p[0], p[1], p[2]])
+        .collect();
+
+    let mut boxes = vec![samples];
+    while boxes.len() < n {
+        // the channel with the widest range of every box, and how wide it is
+        let widest = boxes
+            .iter()
+            .enumerate()
+            .filter(|(_, colors)| colors.len() > 1)
+            .flat_map(|(i, colors)| {
+                (0..3).map(move |c| {
+                    let min = colors.iter().map(|color| color[c]).min().unwrap_or(0);
+                    let max = colors.iter().map(|color| color[c]).max().unwrap_or(0);
+                    (i, c, max - min)
+                })
+            })
+            .max_by_key(|(_, _, range)| *range);
+        let (i, channel) = match widest {
+            Some((i, channel, range)) if range > 0 => (i, channel),
+            // every box has a single color, so there is nothing left to split
+            _ => break,
+        };
+        let mut colors = boxes.swap_remove(i);
+        colors.sort_unstable_by_key(|color| color[channel]);
+        let upper = colors.split_off(colors.len() / 2);
+        boxes.push(colors);
+        boxes.push(upper);
+    }
+
+    boxes
+        .iter()
+        .filter(|colors| !colors.is_empty())
+        .map(|colors| {
+            let mut sum = [0usize; 3];
+            for color in colors {
+                for c in 0..3 {
+                    sum[c] += color[c] as usize;
+                }
+            }
+            sum.map(|s| (s / colors.len()) as u8)
+        })
+        .collect()
+}
+
+/// Maps every pixel of `canvas` (in bgra) to the closest color in `palette`, diffusing the error
+/// to its neighbors
+pub fn quantize(canvas: &mut [u8], dim: (u32, u32), palette: &[[u8; 3]]) {
+    let width = dim.0 as usize;
+    // the error diffused into the current and the next row, with a pixel of padding on each side
+    let mut current = vec![[0f32; 3]; width + 2];
+    let mut next = vec![[0f32; 3]; width + 2];
+    for row in canvas.chunks_exact_mut(width * 4) {
+        for (x, pixel) in row.chunks_exact_mut(4).enumerate() {
+            let error = current[x + 1];
+            let wanted = [
+                (pixel[2] as f32 + error[0]).clamp(0.0, 255.0),
+                (pixel[1] as f32 + error[1]).clamp(0.0, 255.0),
+                (pixel[0] as f32 + error[2]).clamp(0.0, 255.0),
+            ];
+            let color = nearest(palette, wanted);
+            pixel[0] = color[2];
+            pixel[1] = color[1];
+            pixel[2] = color[0];
+            for c in 0..3 {
+                let error = wanted[c] - color[c] as f32;
+                current[x + 2][c] += error * 7.0 / 16.0;
+                next[x][c] += error * 3.0 / 16.0;
+                next[x + 1][c] += error * 5.0 / 16.0;
+                next[x + 2][c] += error / 16.0;
+            }
+        }
+        std::mem::swap(&mut current, &mut next);
+        next.fill([0.0; 3]);
+    }
+}
+
+fn nearest(palette: &[[u8; 3]], color: [f32; 3]) -> [u8; 3] {
+    let distance = |candidate: &[u8; 3]| {
+        (0..3)
+            .map(|c| (candidate[c] as f32 - color[c]).powi(2))
+            .sum::<f32>()
+    };
+    palette
+        .iter()
+        .min_by(|a, b| distance(a).total_cmp(&distance(b)))
+        .copied()
+        .unwrap_or([0, 0, 0])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn should_parse_gimp_palettes() {
+        let gpl = "GIMP Palette\nName: test\nColumns: 2\n#\n  0   0   0\tBlack\n255 128 1 Orange\n";
+        assert_eq!(parse_gpl(gpl).unwrap(), vec![[0, 0, 0], [255, 128, 1]]);
+        assert!(parse_gpl("0 0 0\n").is_err());
+        assert!(parse_gpl("GIMP Palette\n0 0\n").is_err());
+        assert!(parse_gpl("GIMP Palette\n").is_err());
+    }
+
+    #[test]
+    fn median_cut_should_find_the_image_colors() {
+        let img = RgbaImage::from_fn(4, 4, |x, _| {
+            if x < 2 {
+                image::Rgba([255, 0, 0, 255])
+            } else {
+                image::Rgba([0, 0, 255, 255])
+            }
+        });
+        let mut palette = median_cut(&img, 4);
+        palette.sort();
+        // there are only two colors, so we can't split them any further
+        assert_eq!(palette, vec![[0, 0, 255], [255, 0, 0]]);
+    }
+
+    #[test]
+    fn quantized_pixels_should_be_in_the_palette() {
+        let palette = BUILTINS[0].1;
+        let mut canvas: Vec<u8> = (0..16 * 16 * 4).map(|i| (i * 7 % 256) as u8).collect();
+        quantize(&mut canvas, (16, 16), palette);
+        for pixel in canvas.chunks_exact(4) {
+            assert!(palette.contains(&[pixel[2], pixel[1], pixel[0]]));
+        }
+    }
+}
@@ -0,0 +1,133 @@
+//! Stand-in for the wallpaper methods of plasmashell's `org.kde.PlasmaShell` interface, for
+//! sessions running KWin with swww instead of plasmashell. Plasma's settings panel and tools like
+//! `plasma-apply-wallpaperimage` set the wallpaper through either `setWallpaper`, or a script
+//! given to `evaluateScript` that writes the `Image` key of the `org.kde.image` plugin. We only
+//! understand those, so scripts doing anything else are refused
+use std::{collections::HashMap, path::PathBuf};
+
+use zbus::{fdo, interface, zvariant::Value};
+
+pub const BUS_NAME: &str = "org.kde.PlasmaShell";
+pub const OBJECT_PATH: &str = "/PlasmaShell";
+
+/// The only wallpaper plugin we can stand in for
+const IMAGE_PLUGIN: &str = "org.kde.image";
+
+pub struct PlasmaShell;
+
+#[interface(name = "org.kde.PlasmaShell")]
+impl PlasmaShell {
+    /// Plasma numbers screens in an order swww doesn't know about, so `_screen` is ignored and the
+    /// image is set on every output
+    #[zbus(name = "setWallpaper")]
+    fn set_wallpaper(
+        &self,
+        plugin: &str,
+        parameters: HashMap<&str, Value<'_>>,
+        _screen: u32,
+    ) -> fdo::Result<()> {
+        if plugin != IMAGE_PLUGIN {
+            return Err(fdo::Error::NotSupported(format!(
+                "swww can only stand in for the {IMAGE_PLUGIN} wallpaper plugin, not {plugin}"
+            )));
+        }
+        match parameters.get("Image") {
+            Some(Value::Str(image)) => set(image.as_str()),
+            _ => Err(fdo::Error::InvalidArgs(
+                "setWallpaper needs an 'Image' parameter".to_string(),
+            )),
+        }
+    }
+
+    /// Returns what the script would have printed, which is nothing for the scripts we understand
+    #[zbus(name = "evaluateScript")]
+    fn evaluate_script(&self, script: &str) -> fdo::Result<String> {
+        match script_image(script) {
+            Some(image) => set(&image).map(|()| String::new()),
+            None => Err(fdo::Error::NotSupported(
+                "swww only understands scripts that write the wallpaper's 'Image'".to_string(),
+            )),
+        }
+    }
+}
+
+fn set(image: &str) -> fdo::Result<()> {
+    let path = if image.starts_with("file://") {
+        crate::portal::uri_to_path(image).map_err(fdo::Error::InvalidArgs)?
+    } else {
+        PathBuf::from(image)
+    };
+    crate::set_img(&path, "", &[]).map_err(|e| fdo::Error::Failed(e.to_string()))
+}
+
+/// The last image a script writes with `writeConfig("Image", "<image>")`
+fn script_image(script: &str) -> Option<String> {
+    let mut image = None;
+    let mut rest = script;
+    while let Some(start) = rest.find("writeConfig") {
+        rest = rest[start + "writeConfig".len()..].trim_start();
+        let Some(args) = rest.strip_prefix('(') else {
+            continue;
+        };
+        let Some((key, args)) = string_literal(args) else {
+            continue;
+        };
+        let Some(args) = args.trim_start().strip_prefix(',') else {
+            continue;
+        };
+        if let Some((value, _)) = string_literal(args).filter(|_| key == "Image") {
+            image = Some(value);
+        }
+    }
+    image
+}
+
+/// Reads a javascript string literal at the start of `s` (ignoring whitespace), returning it and
+/// whatever comes after it
+fn string_literal(s: &str) -> Option<(String, &str)> {
+    let s = s.trim_start();
+    let quote = s.chars().next().filter(|c| *c == '"' || *c == '\'')?;
+    let mut literal = String::new();
+    let mut chars = s.char_indices().skip(1);
+    while let Some((i, c)) = chars.next() {
+        match c {
+            '\\' => match chars.next()?.1 {
+                'n' => literal.push('\n'),
+                't' => literal.push('\t'),
+                escaped => literal.push(escaped),
+            },
+            c if c == quote => return Some((literal, &s[i + 1..])),
+            c => literal.push(c),
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn should_find_the_image_scripts_write() {
+        // what plasma-apply-wallpaperimage sends
+        let script = r#"
+            var allDesktops = desktops();
+            for (var i = 0; i < allDesktops.length; i++) {
+                var d = allDesktops[i];
+                d.wallpaperPlugin = "org.kde.image";
+                d.currentConfigGroup = Array("Wallpaper", "org.kde.image", "General");
+                d.writeConfig("Image", "file:///home/me/My Pictures/a.png");
+            }"#;
+        assert_eq!(
+            script_image(script).unwrap(),
+            "file:///home/me/My Pictures/a.png"
+        );
+        assert_eq!(
+            script_image(r"d.writeConfig ( 'Image' , 'it\'s.png' )").unwrap(),
+            "it's.png"
+        );
+        assert!(script_image(r##"d.writeConfig("Color", "#000000")"##).is_none());
+        assert!(script_image(r#"d.writeConfig("Image", "unterminated)"#).is_none());
+        assert!(script_image("print(screenGeometry(0))").is_none());
+    }
+}
@@ -0,0 +1,274 @@
+//! `swww playlist`: goes through a list of images, displaying each for its own time, and skipping
+//! the ones outside of their schedule. The file is made of blocks like
+//!
+//! ```text
+//! img /srv/signage/menu.png
+//! dwell 1m
+//! days mon-fri
+//! hours 09:00-17:00
+//! options --transition-type wipe --transition-angle 30
+//! ```
+//!
+//! where every `img` line starts a new item. The lines before the first one set the defaults of
+//! every item
+use chrono::{Datelike, Local, NaiveTime, Timelike, Weekday};
+
+use std::{
+    path::PathBuf,
+    thread,
+    time::{Duration, Instant},
+};
+
+use swww_protocol::error::Error;
+
+use crate::cli;
+
+/// How long items are displayed, unless the playlist says otherwise
+const DEFAULT_DWELL: Duration = Duration::from_secs(30);
+
+#[derive(Clone, Debug, PartialEq)]
+struct Item {
+    path: PathBuf,
+    dwell: Duration,
+    schedule: Schedule,
+    /// `swww img` options to display the image with
+    options: Vec<String>,
+}
+
+/// When an item may be displayed. Schedules are checked against the local time
+#[derive(Clone, Debug, Default, PartialEq)]
+struct Schedule {
+    /// Indexed from monday. `None` means every day
+    days: Option<[bool; 7]>,
+    /// From the first time until the second one, which may be on the next day. `None` means all
+    /// day long
+    hours: Option<(NaiveTime, NaiveTime)>,
+}
+
+impl Schedule {
+    fn contains(&self, day: Weekday, time: NaiveTime) -> bool {
+        let day_matches = self
+            .days
+            .is_none_or(|days| days[day.num_days_from_monday() as usize]);
+        let time_matches = self.hours.is_none_or(|(start, end)| {
+            if start < end {
+                start <= time && time < end
+            } else {
+                start <= time || time < end
+            }
+        });
+        day_matches && time_matches
+    }
+}
+
+pub fn run(playlist: &cli::Playlist) -> Result<(), Error> {
+    let raw = match std::fs::read_to_string(&playlist.file) {
+        Ok(raw) => raw,
+        Err(e) => {
+            return Err(Error::InvalidRequest(format!(
+                "failed to read playlist {:?}: {e}",
+                playlist.file
+            )))
+        }
+    };
+    let items = parse(&raw).map_err(Error::InvalidRequest)?;
+    let mut current = None;
+    loop {
+        let now = Local::now();
+        let Some(i) = next(&items, current, now.weekday(), now.time()) else {
+            // whatever was displayed last stays up until an item is due again
+            thread::sleep(until_next_minute(now.second()));
+            continue;
+        };
+        let item = &items[i];
+        // a single item in its schedule would otherwise be displayed again after every dwell
+        if current != Some(i) {
+            let options: Vec<&str> = item.options.iter().map(String::as_str).collect();
+            if let Err(e) = crate::set_img(&item.path, &playlist.outputs, &options) {
+                eprintln!("WARNING: failed to display {:?}: {e}", item.path);
+            }
+        }
+        current = Some(i);
+        dwell(item);
+    }
+}
+
+/// The first item after `current` that may be displayed at `time`, going back to the start of the
+/// playlist after its end
+fn next(items: &[Item], current: Option<usize>, day: Weekday, time: NaiveTime) -> Option<usize> {
+    let start = current.map_or(0, |i| i + 1);
+    (0..items.len())
+        .map(|offset| (start + offset) % items.len())
+        .find(|i| items[*i].schedule.contains(day, time))
+}
+
+/// Waits for `item`'s time to be over, or for it to leave its schedule, if that happens first
+fn dwell(item: &Item) {
+    let end = Instant::now() + item.dwell;
+    loop {
+        let left = end.saturating_duration_since(Instant::now());
+        let now = Local::now();
+        if left.is_zero() || !item.schedule.contains(now.weekday(), now.time()) {
+            return;
+        }
+        thread::sleep(left.min(until_next_minute(now.second())));
+    }
+}
+
+/// Schedules only change on the minute
+fn until_next_minute(second: u32) -> Duration {
+    Duration::from_secs(60 - u64::from(second.min(59)))
+}
+
+fn parse(raw: &str) -> Result<Vec<Item>, String> {
+    let mut defaults = Item {
+        path: PathBuf::new(),
+        dwell: DEFAULT_DWELL,
+        schedule: Schedule::default(),
+        options: Vec::new(),
+    };
+    let mut items: Vec<Item> = Vec::new();
+    for (n, line) in raw.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let (key, value) = match line.split_once(char::is_whitespace) {
+            Some((key, value)) => (key, value.trim_start()),
+            None => (line, ""),
+        };
+        if key == "img" {
+            if value.is_empty() {
+                return Err(format!("line {}: img requires the path to an image", n + 1));
+            }
+            items.push(Item {
+                path: PathBuf::from(value),
+                ..defaults.clone()
+            });
+            continue;
+        }
+        let item = items.last_mut().unwrap_or(&mut defaults);
+        let parsed = match key {
+            "dwell" => cli::parse_duration(value).map(|dwell| item.dwell = dwell),
+            "days" => parse_days(value).map(|days| item.schedule.days = Some(days)),
+            "hours" => parse_hours(value).map(|hours| item.schedule.hours = Some(hours)),
+            "options" => {
+                item.options = value.split_whitespace().map(str::to_string).collect();
+                Ok(())
+            }
+            _ => Err(format!(
+                "unknown key {key:?}, expected img, dwell, days, hours or options"
+            )),
+        };
+        if let Err(e) = parsed {
+            return Err(format!("line {}: {e}", n + 1));
+        }
+    }
+    if items.is_empty() {
+        return Err("the playlist has no images".to_string());
+    }
+    Ok(items)
+}
+
+/// Parses comma separated days and ranges of days, like `mon-fri,sun`
+fn parse_days(raw: &str) -> Result<[bool; 7], String> {
+    let day = |name: &str| match name.trim().parse::<Weekday>() {
+        Ok(day) => Ok(day.num_days_from_monday() as usize),
+        Err(_) => Err(format!(
+            "invalid day {name:?}, expected one like mon or monday"
+        )),
+    };
+    let mut days = [false; 7];
+    for part in raw.split(',') {
+        let (first, last) = match part.split_once('-') {
+            Some((first, last)) => (day(first)?, day(last)?),
+            None => (day(part)?, day(part)?),
+        };
+        // ranges like fri-mon go through the weekend
+        let len = (last + 7 - first) % 7;
+        for offset in 0..=len {
+            days[(first + offset) % 7] = true;
+        }
+    }
+    Ok(days)
+}
+
+/// Parses ranges like `09:00-17:00`, or `22:00-06:00` for ones going through midnight
+fn parse_hours(raw: &str) -> Result<(NaiveTime, NaiveTime), String> {
+    let time = |raw: &str| match NaiveTime::parse_from_str(raw.trim(), "%H:%M") {
+        Ok(time) => Ok(time),
+        Err(_) => Err(format!("invalid time {raw:?}, expected one like 09:30")),
+    };
+    match raw.split_once('-') {
+        Some((start, end)) => match (time(start)?, time(end)?) {
+            (start, end) if start == end => Err(format!("{raw:?} is an empty range of hours")),
+            hours => Ok(hours),
+        },
+        None => Err(format!(
+            "invalid hours {raw:?}, expected a range like 09:00-17:00"
+        )),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(hour: u32, minute: u32) -> NaiveTime {
+        NaiveTime::from_hms_opt(hour, minute, 0).unwrap()
+    }
+
+    #[test]
+    fn should_parse_items_with_defaults() {
+        let items = parse(
+            "# signage\n\
+             dwell 10s\n\
+             options --transition-type fade\n\
+             img /a b.png\n\
+             img /c.png\n\
+             dwell 1m\n\
+             days mon-fri\n\
+             hours 09:00-17:00\n",
+        )
+        .unwrap();
+        assert_eq!(items.len(), 2);
+        assert_eq!(items[0].path, PathBuf::from("/a b.png"));
+        assert_eq!(items[0].dwell, Duration::from_secs(10));
+        assert_eq!(items[0].options, ["--transition-type", "fade"]);
+        assert_eq!(items[0].schedule, Schedule::default());
+        assert_eq!(items[1].dwell, Duration::from_secs(60));
+        assert_eq!(
+            items[1].schedule.days,
+            Some([true, true, true, true, true, false, false])
+        );
+        assert!(parse("dwell 10s\n").is_err());
+        assert!(parse("img /a.png\nspeed 2\n")
+            .unwrap_err()
+            .starts_with("line 2:"));
+    }
+
+    #[test]
+    fn schedules_should_wrap_around() {
+        assert_eq!(
+            parse_days("fri-mon").unwrap(),
+            [true, false, false, false, true, true, true]
+        );
+        let night = Schedule {
+            days: None,
+            hours: Some(parse_hours("22:00-06:00").unwrap()),
+        };
+        assert!(night.contains(Weekday::Tue, at(23, 0)));
+        assert!(night.contains(Weekday::Tue, at(5, 59)));
+        assert!(!night.contains(Weekday::Tue, at(6, 0)));
+        assert!(parse_hours("09:00-09:00").is_err());
+    }
+
+    #[test]
+    fn should_skip_items_out_of_their_schedule() {
+        let items = parse("img /a.png\nimg /b.png\nhours 09:00-17:00\nimg /c.png\n").unwrap();
+        assert_eq!(next(&items, None, Weekday::Mon, at(12, 0)), Some(0));
+        assert_eq!(next(&items, Some(0), Weekday::Mon, at(12, 0)), Some(1));
+        assert_eq!(next(&items, Some(0), Weekday::Mon, at(20, 0)), Some(2));
+        assert_eq!(next(&items, Some(2), Weekday::Mon, at(20, 0)), Some(0));
+    }
+}
@@ -0,0 +1,144 @@
+//! Backend for xdg-desktop-portal's wallpaper interface. Programs that set the wallpaper through
+//! the portal, which are mostly sandboxed ones like flatpaks, end up calling us. Desktop settings
+//! panels don't use the portal: see the `plasma` and `cosmic` modules for the ones we stand in for.
+//! We also read the desktop's reduced motion setting through the portal, for
+//! `swww init --reduce-motion`
+use std::{collections::HashMap, ffi::OsString, os::unix::ffi::OsStringExt, path::PathBuf};
+
+use swww_protocol::error::Error;
+use zbus::{
+    blocking::{connection, Connection},
+    interface,
+    zvariant::{ObjectPath, OwnedValue, Value},
+};
+
+const BUS_NAME: &str = "org.freedesktop.impl.portal.desktop.swww";
+const OBJECT_PATH: &str = "/org/freedesktop/portal/desktop";
+
+/// Responses defined by the portal's `Request` interface
+const RESPONSE_SUCCESS: u32 = 0;
+const RESPONSE_OTHER: u32 = 2;
+
+struct Wallpaper;
+
+#[interface(name = "org.freedesktop.impl.portal.Wallpaper")]
+impl Wallpaper {
+    #[zbus(name = "SetWallpaperURI")]
+    fn set_wallpaper_uri(
+        &self,
+        _handle: ObjectPath<'_>,
+        app_id: &str,
+        _parent_window: &str,
+        uri: &str,
+        options: HashMap<&str, Value<'_>>,
+    ) -> u32 {
+        // we only draw backgrounds, there is nothing we can do about lock screens
+        if let Some(Value::Str(set_on)) = options.get("set-on") {
+            if set_on.as_str() == "lockscreen" {
+                eprintln!("{app_id} asked to set the lock screen, which swww can't do");
+                return RESPONSE_OTHER;
+            }
+        }
+        match uri_to_path(uri)
+            .map_err(Error::InvalidRequest)
+            .and_then(|path| crate::set_img(&path, "", &[]))
+        {
+            Ok(()) => RESPONSE_SUCCESS,
+            Err(e) => {
+                eprintln!("failed to set wallpaper requested by {app_id}: {e}");
+                RESPONSE_OTHER
+            }
+        }
+    }
+}
+
+/// Whether the desktop asks for less motion. The portal's own `reduced-motion` setting is fairly
+/// new, so we fall back to GNOME's `enable-animations`. Desktops that say neither get animations
+pub fn prefers_reduced_motion() -> bool {
+    let Ok(connection) = Connection::session() else {
+        return false;
+    };
+    let read = |namespace: &str, key: &str| -> Option<OwnedValue> {
+        let reply = connection
+            .call_method(
+                Some("org.freedesktop.portal.Desktop"),
+                OBJECT_PATH,
+                Some("org.freedesktop.portal.Settings"),
+                "ReadOne",
+                &(namespace, key),
+            )
+            .ok()?;
+        reply.body().deserialize().ok()
+    };
+    if let Some(value) = read("org.freedesktop.appearance", "reduced-motion") {
+        // 0 means no preference, and 1 reduced motion
+        return u32::try_from(value).is_ok_and(|value| value == 1);
+    }
+    read("org.gnome.desktop.interface", "enable-animations")
+        .and_then(|value| bool::try_from(value).ok())
+        .is_some_and(|enabled| !enabled)
+}
+
+/// Serves the portal, and, with `kde`, plasmashell's wallpaper methods. With `cosmic`, this then
+/// follows cosmic-bg's configuration instead of just waiting
+pub fn run(kde: bool, cosmic: bool) -> Result<(), String> {
+    let mut builder = connection::Builder::session()
+        .and_then(|builder| builder.name(BUS_NAME))
+        .and_then(|builder| builder.serve_at(OBJECT_PATH, Wallpaper))
+        .map_err(|e| format!("failed to register portal backend: {e}"))?;
+    if kde {
+        builder = builder
+            .name(crate::plasma::BUS_NAME)
+            .and_then(|builder| {
+                builder.serve_at(crate::plasma::OBJECT_PATH, crate::plasma::PlasmaShell)
+            })
+            .map_err(|e| format!("failed to stand in for plasmashell: {e}"))?;
+    }
+    // this is where the names are requested, so it fails if plasmashell is running
+    let _connection = builder
+        .build()
+        .map_err(|e| format!("failed to register portal backend: {e}"))?;
+    if cosmic {
+        return crate::cosmic::run();
+    }
+    loop {
+        std::thread::park();
+    }
+}
+
+/// Only local files make sense for us. Their paths may be percent encoded
+pub fn uri_to_path(uri: &str) -> Result<PathBuf, String> {
+    let encoded = match uri.strip_prefix("file://") {
+        Some(path) => path.as_bytes(),
+        None => return Err(format!("only file:// uris are supported, got: {uri}")),
+    };
+    let mut path = Vec::with_capacity(encoded.len());
+    let mut bytes = encoded.iter();
+    while let Some(&byte) = bytes.next() {
+        if byte != b'%' {
+            path.push(byte);
+            continue;
+        }
+        let hex = [bytes.next(), bytes.next()];
+        match hex.map(|c| c.and_then(|c| (*c as char).to_digit(16))) {
+            [Some(high), Some(low)] => path.push((high * 16 + low) as u8),
+            _ => return Err(format!("invalid percent encoding in uri: {uri}")),
+        }
+    }
+    Ok(PathBuf::from(OsString::from_vec(path)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn should_convert_uris_to_paths() {
+        assert_eq!(
+            uri_to_path("file:///home/me/My%20Pictures/a.png").unwrap(),
+            PathBuf::from("/home/me/My Pictures/a.png")
+        );
+        assert!(uri_to_path("https://example.com/a.png").is_err());
+        assert!(uri_to_path("file:///a%2").is_err());
+    }
+}
@@ -0,0 +1,285 @@
+//! `swww preview`: draws an image in the terminal, prepared exactly like `swww img` would for an
+//! output. By default, we draw it with colored half blocks, which any terminal with true color
+//! support can show. With the `inline-preview` feature, `--inline` sends it as an actual image,
+//! with kitty's graphics protocol or sixel
+use image::{imageops, RgbaImage};
+
+use std::io::Write;
+
+use swww_protocol::{error::Error, SafeArea};
+use swww_render::bitmap;
+use utils::variant;
+
+use crate::cli;
+
+/// How many pixels wide we assume a terminal cell is, for sixel, which can't be told to take a
+/// number of columns like kitty's protocol can
+#[cfg(feature = "inline-preview")]
+const SIXEL_CELL_WIDTH: u32 = 8;
+
+/// Kitty's protocol splits images in chunks of at most this many bytes of base64
+#[cfg(feature = "inline-preview")]
+const KITTY_CHUNK: usize = 4096;
+
+pub fn run(preview: &cli::Preview) -> Result<(), Error> {
+    let args = &preview.img;
+    let (name, dim, area) = match preview.size {
+        Some(size) => (None, size, SafeArea::default()),
+        None => {
+            let requested = crate::get_outputs(&args.outputs)?;
+            let (dims, areas, outputs) = crate::get_dimensions_and_outputs(requested, false)?;
+            (Some(outputs[0][0].clone()), dims[0], areas[0])
+        }
+    };
+    let colors = bitmap::Colors {
+        fg: args.fg,
+        bg: args.bg,
+    };
+    let (img, _) = crate::read_img(crate::img_path(args), args.format.as_ref(), colors)?;
+    let img = match variant::find(crate::img_path(args), dim) {
+        Some(variant) => crate::read_img(&variant, None, colors)?.0,
+        None => img,
+    };
+    let layers = crate::read_layers(&args.layer, colors)?;
+    let palette = match &args.palette {
+        Some(spec) => Some(crate::palette::load(spec, &img)?),
+        None => None,
+    };
+    let prepared = crate::prepare_img(img, dim, &area, args, &layers, palette.as_deref())?;
+    let prepared = to_rgba(prepared, dim);
+
+    let out = if preview.inline {
+        inline(&prepared, preview)?
+    } else {
+        half_blocks(&prepared, preview.columns)
+    };
+    let mut stdout = std::io::stdout().lock();
+    let path = crate::img_path(args).display();
+    let header = match name {
+        Some(name) => format!("{path} on {name} ({}x{}):", dim.0, dim.1),
+        None => format!("{path} at {}x{}:", dim.0, dim.1),
+    };
+    let written = writeln!(stdout, "{header}")
+        .and_then(|()| stdout.write_all(out.as_bytes()))
+        .and_then(|()| stdout.flush());
+    match written {
+        Ok(()) => Ok(()),
+        Err(e) => Err(Error::Other(format!("failed to write preview: {e}"))),
+    }
+}
+
+/// Turns the bgra canvas `swww img` would send into an opaque rgba image
+fn to_rgba(mut canvas: Vec<u8>, dim: (u32, u32)) -> RgbaImage {
+    for pixel in canvas.chunks_exact_mut(4) {
+        pixel.swap(0, 2);
+        pixel[3] = 255;
+    }
+    // prepare_img always makes a canvas of exactly `dim`
+    RgbaImage::from_raw(dim.0, dim.1, canvas).unwrap()
+}
+
+/// Scales `img` down to `width` pixels wide, keeping its aspect ratio, and making its height a
+/// multiple of `multiple`
+fn downscale(img: &RgbaImage, width: u32, multiple: u32) -> RgbaImage {
+    let width = width.min(img.width());
+    let height = (img.height() as f32 * width as f32 / img.width() as f32 / multiple as f32)
+        .round()
+        .max(1.0) as u32
+        * multiple;
+    imageops::resize(img, width, height, imageops::FilterType::Triangle)
+}
+
+/// Draws `img` with '▀', one pixel with its foreground and another with its background, since
+/// terminal cells are about twice as tall as they are wide
+fn half_blocks(img: &RgbaImage, columns: u32) -> String {
+    let img = downscale(img, columns, 2);
+    let mut out = String::new();
+    for y in (0..img.height()).step_by(2) {
+        for x in 0..img.width() {
+            let top = img.get_pixel(x, y);
+            let bottom = img.get_pixel(x, y + 1);
+            out.push_str(&format!(
+                "\x1b[38;2;{};{};{}m\x1b[48;2;{};{};{}m▀",
+                top[0], top[1], top[2], bottom[0], bottom[1], bottom[2]
+            ));
+        }
+        out.push_str("\x1b[0m\n");
+    }
+    out
+}
+
+#[cfg(not(feature = "inline-preview"))]
+fn inline(_: &RgbaImage, _: &cli::Preview) -> Result<String, Error> {
+    Err(Error::Unsupported(
+        "swww was built without the `inline-preview` feature, drop --inline to draw the preview \
+         with text instead"
+            .to_string(),
+    ))
+}
+
+#[cfg(feature = "inline-preview")]
+fn inline(img: &RgbaImage, preview: &cli::Preview) -> Result<String, Error> {
+    let kitty = match preview.protocol {
+        cli::PreviewProtocol::Kitty => true,
+        cli::PreviewProtocol::Sixel => false,
+        cli::PreviewProtocol::Auto => supports_kitty(),
+    };
+    if kitty {
+        kitty_image(img, preview.columns)
+    } else {
+        let img = downscale(img, preview.columns * SIXEL_CELL_WIDTH, 6);
+        let palette = crate::palette::median_cut(&img, 256);
+        Ok(sixel(&img, &palette))
+    }
+}
+
+/// Whether the terminal we are running in is known to support kitty's graphics protocol
+#[cfg(feature = "inline-preview")]
+fn supports_kitty() -> bool {
+    let var = |name| std::env::var(name).unwrap_or_default();
+    std::env::var_os("KITTY_WINDOW_ID").is_some()
+        || var("TERM").contains("kitty")
+        || var("TERM").contains("ghostty")
+        || matches!(var("TERM_PROGRAM").as_str(), "WezTerm" | "ghostty")
+}
+
+/// Sends `img` as a png, which the terminal scales to `columns`
+#[cfg(feature = "inline-preview")]
+fn kitty_image(img: &RgbaImage, columns: u32) -> Result<String, Error> {
+    // there is no point in sending more pixels than the terminal can show
+    let img = downscale(img, columns * 20, 1);
+    let mut png = std::io::Cursor::new(Vec::new());
+    if let Err(e) = img.write_to(&mut png, image::ImageOutputFormat::Png) {
+        return Err(Error::Other(format!("failed to encode preview: {e}")));
+    }
+    let data = base64(png.get_ref());
+    let chunks: Vec<&[u8]> = data.as_bytes().chunks(KITTY_CHUNK).collect();
+    let mut out = String::new();
+    for (i, chunk) in chunks.iter().enumerate() {
+        let more = u8::from(i + 1 < chunks.len());
+        let chunk = std::str::from_utf8(chunk).unwrap_or_default();
+        if i == 0 {
+            out.push_str(&format!(
+                "\x1b_Ga=T,f=100,c={columns},m={more};{chunk}\x1b\\"
+            ));
+        } else {
+            out.push_str(&format!("\x1b_Gm={more};{chunk}\x1b\\"));
+        }
+    }
+    out.push('\n');
+    Ok(out)
+}
+
+#[cfg(feature = "inline-preview")]
+fn base64(bytes: &[u8]) -> String {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut out = String::with_capacity(bytes.len().div_ceil(3) * 4);
+    for chunk in bytes.chunks(3) {
+        let n = chunk
+            .iter()
+            .enumerate()
+            .fold(0u32, |n, (i, byte)| n | ((*byte as u32) << (16 - 8 * i)));
+        for i in 0..4 {
+            if i <= chunk.len() {
+                out.push(ALPHABET[((n >> (18 - 6 * i)) & 0x3f) as usize] as char);
+            } else {
+                out.push('=');
+            }
+        }
+    }
+    out
+}
+
+/// Encodes `img`, whose height must be a multiple of 6, as sixels, dithered to `palette`
+#[cfg(feature = "inline-preview")]
+fn sixel(img: &RgbaImage, palette: &[[u8; 3]]) -> String {
+    let (width, height) = img.dimensions();
+    // quantize works on bgra, like everything we send to the daemon
+    let mut canvas: Vec<u8> = img.pixels().flat_map(|p| [p[2], p[1], p[0], 255]).collect();
+    crate::palette::quantize(&mut canvas, (width, height), palette);
+    let index = |x: u32, y: u32| {
+        let i = (y * width + x) as usize * 4;
+        let color = [canvas[i + 2], canvas[i + 1], canvas[i]];
+        palette.iter().position(|c| *c == color).unwrap_or(0)
+    };
+
+    let mut out = format!("\x1bPq\"1;1;{width};{height}");
+    for (i, color) in palette.iter().enumerate() {
+        let percent = color.map(|c| (c as u32 * 100 + 127) / 255);
+        out.push_str(&format!(
+            "#{i};2;{};{};{}",
+            percent[0], percent[1], percent[2]
+        ));
+    }
+    for band in (0..height).step_by(6) {
+        // every color used in the band is drawn over the whole band, going back with '$' between
+        let mut bits = vec![vec![0u8; width as usize]; palette.len()];
+        for x in 0..width {
+            for row in 0..6 {
+                bits[index(x, band + row)][x as usize] |= 1 << row;
+            }
+        }
+        let mut first = true;
+        for (i, bits) in bits.iter().enumerate() {
+            if bits.iter().all(|b| *b == 0) {
+                continue;
+            }
+            if !first {
+                out.push('$');
+            }
+            first = false;
+            out.push_str(&format!("#{i}"));
+            push_runs(&mut out, bits);
+        }
+        out.push('-');
+    }
+    out.push_str("\x1b\\\n");
+    out
+}
+
+/// Appends the sixels of `bits`, with repeated ones run length encoded
+#[cfg(feature = "inline-preview")]
+fn push_runs(out: &mut String, bits: &[u8]) {
+    let mut i = 0;
+    while i < bits.len() {
+        let run = bits[i..].iter().take_while(|b| **b == bits[i]).count();
+        let c = (bits[i] + 63) as char;
+        if run > 3 {
+            out.push_str(&format!("!{run}{c}"));
+        } else {
+            (0..run).for_each(|_| out.push(c));
+        }
+        i += run;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn half_blocks_should_pair_rows() {
+        let mut img = RgbaImage::new(2, 2);
+        img.put_pixel(0, 0, image::Rgba([255, 0, 0, 255]));
+        img.put_pixel(0, 1, image::Rgba([0, 0, 255, 255]));
+        let out = half_blocks(&img, 2);
+        assert_eq!(out.lines().count(), 1);
+        assert!(out.starts_with("\x1b[38;2;255;0;0m\x1b[48;2;0;0;255m▀"));
+    }
+
+    #[cfg(feature = "inline-preview")]
+    #[test]
+    fn should_encode_base64() {
+        assert_eq!(base64(b"swww"), "c3d3dw==");
+        assert_eq!(base64(b"sww"), "c3d3");
+        assert_eq!(base64(b""), "");
+    }
+
+    #[cfg(feature = "inline-preview")]
+    #[test]
+    fn sixels_should_be_run_length_encoded() {
+        let img = RgbaImage::from_pixel(8, 6, image::Rgba([255, 255, 255, 255]));
+        let out = sixel(&img, &[[255, 255, 255]]);
+        assert!(out.ends_with("#0;2;100;100;100#0!8~-\x1b\\\n"));
+    }
+}
@@ -0,0 +1,111 @@
+//! `swww query --format json`: the same information as `swww query`, for scripts and status bars
+//! that would rather not parse our text
+use serde_json::{json, Value};
+
+use swww_protocol::{error::Error, Answer, BgImg, BgInfo, Playback, Request};
+
+/// Prints every output, or with `anim`, every animation playing, as a json array
+pub fn print_json(anim: bool) -> Result<(), Error> {
+    let playback = match swww_client::send(Request::Playback)? {
+        Answer::Playback(playback) => playback,
+        _ => {
+            return Err(Error::Ipc(
+                "unexpected answer to playback query".to_string(),
+            ))
+        }
+    };
+    let values: Vec<Value> = if anim {
+        playback.iter().map(playback_json).collect()
+    } else {
+        let infos = match swww_client::send(Request::Query)? {
+            Answer::Info(infos) => infos,
+            _ => return Err(Error::Ipc("unexpected answer to query".to_string())),
+        };
+        infos
+            .iter()
+            .map(|info| {
+                let animated = playback.iter().any(|p| p.output == info.name);
+                info_json(info, animated)
+            })
+            .collect()
+    };
+    println!("{}", Value::Array(values));
+    Ok(())
+}
+
+fn info_json(info: &BgInfo, animated: bool) -> Value {
+    let displaying = match &info.img {
+        BgImg::Img(path) => json!({ "type": "image", "path": path }),
+        BgImg::Color([r, g, b]) => {
+            json!({ "type": "color", "color": format!("{r:02x}{g:02x}{b:02x}") })
+        }
+        BgImg::Generated(name) => json!({ "type": "generated", "generator": name }),
+    };
+    let area = &info.safe_area;
+    json!({
+        "name": info.name,
+        "width": info.dim.0,
+        "height": info.dim.1,
+        "scale_factor": info.scale_factor,
+        "drawn_at": info.buffer_dim.map(|(width, height)| json!({ "width": width, "height": height })),
+        "displaying": displaying,
+        "animated": animated,
+        "following": info.following,
+        "pinned": info.pinned,
+        "safe_area": {
+            "top": area.top,
+            "bottom": area.bottom,
+            "left": area.left,
+            "right": area.right,
+        },
+        "hash": format!("{:08x}", info.hash),
+    })
+}
+
+fn playback_json(playback: &Playback) -> Value {
+    json!({
+        "output": playback.output,
+        "frame": playback.frame,
+        "frames": playback.frames,
+        "loops": playback.loops,
+        "elapsed": playback.elapsed.as_secs_f64(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use swww_protocol::SafeArea;
+
+    #[test]
+    fn should_describe_outputs() {
+        let info = BgInfo {
+            name: "DP-1".to_string(),
+            dim: (1920, 1080),
+            scale_factor: 2,
+            img: BgImg::Color([26, 128, 74]),
+            following: None,
+            pinned: true,
+            safe_area: SafeArea::default(),
+            buffer_dim: None,
+            hash: 0xbeef,
+        };
+        let value = info_json(&info, false);
+        assert_eq!(value["width"], 1920);
+        assert_eq!(
+            value["displaying"],
+            json!({ "type": "color", "color": "1a804a" })
+        );
+        assert_eq!(value["following"], Value::Null);
+        assert_eq!(value["pinned"], true);
+        assert_eq!(value["hash"], "0000beef");
+
+        let info = BgInfo {
+            img: BgImg::Img("/wallpapers/a.gif".into()),
+            ..info
+        };
+        let value = info_json(&info, true);
+        assert_eq!(value["displaying"]["path"], "/wallpapers/a.gif");
+        assert_eq!(value["animated"], true);
+    }
+}
@@ -0,0 +1,146 @@
+//! `swww init --replace`: starts a new daemon in place of the one that is running, carrying over
+//! what it was displaying. The new daemon restores the images from the cache and takes the socket
+//! over by itself (see `swww-daemon --replace`), and we bring back what the cache doesn't have
+use std::{
+    os::unix::{fs::MetadataExt, net::UnixStream},
+    time::Duration,
+};
+
+use swww_protocol::{
+    error::Error, Answer, BgImg, BgInfo, Clear, Coord, Follow, GroupCommand, Groups, Pin, Position,
+    Request, SafeArea, SetSafeArea, Transition, TransitionSync, TransitionType,
+};
+use utils::communication::get_socket_path;
+
+/// How long the new daemon has to take over. It waits for at most 3 seconds for its outputs to
+/// be drawn, and 5 for the old daemon to exit
+const MAX_TAKEOVER_WAIT: Duration = Duration::from_secs(10);
+
+/// What the previous daemon was displaying, and how it was set up
+pub struct State {
+    /// Identifies the previous daemon's socket, so that we can tell when the new one replaced it
+    socket: u64,
+    infos: Vec<BgInfo>,
+    groups: Groups,
+}
+
+/// Asks the running daemon for its state. Returns `None` if there is no daemon to replace
+pub fn previous_state() -> Result<Option<State>, Error> {
+    let socket_path = get_socket_path();
+    let Ok(metadata) = std::fs::metadata(&socket_path) else {
+        return Ok(None);
+    };
+    if UnixStream::connect(&socket_path).is_err() {
+        return Ok(None);
+    }
+    let infos = match swww_client::send(Request::Query)? {
+        Answer::Info(infos) => infos,
+        _ => return Err(Error::Ipc("unexpected answer to query".to_string())),
+    };
+    let groups = match swww_client::send(Request::Group(GroupCommand::List))? {
+        Answer::Groups(groups) => groups,
+        _ => return Err(Error::Ipc("unexpected answer to group list".to_string())),
+    };
+    Ok(Some(State {
+        socket: metadata.ino(),
+        infos,
+        groups,
+    }))
+}
+
+/// Waits for the new daemon to take over the socket from the previous one
+pub fn wait_for_takeover(previous: &State) -> Result<(), Error> {
+    let socket_path = get_socket_path();
+    let interval = Duration::from_millis(50);
+    for _ in 0..MAX_TAKEOVER_WAIT.as_millis() / interval.as_millis() {
+        match std::fs::metadata(&socket_path) {
+            Ok(metadata) if metadata.ino() != previous.socket => return Ok(()),
+            _ => std::thread::sleep(interval),
+        }
+    }
+    Err(Error::Daemon(format!(
+        "the new daemon didn't take over in {}s, see its logs",
+        MAX_TAKEOVER_WAIT.as_secs()
+    )))
+}
+
+/// Brings back what the cache doesn't keep: groups, pins, followers, safe areas, colors, and the
+/// images whose cache was evicted. Generated wallpapers can't be, since the daemon only tells
+/// their name
+pub fn restore(previous: State) -> Result<(), Error> {
+    for (name, outputs) in previous.groups {
+        swww_client::send(Request::Group(GroupCommand::Create { name, outputs }))?;
+    }
+    let current = match swww_client::send(Request::Query)? {
+        Answer::Info(infos) => infos,
+        _ => return Err(Error::Ipc("unexpected answer to query".to_string())),
+    };
+    for info in previous.infos {
+        // outputs may have gone away in the meantime
+        let Some(now) = current.iter().find(|now| now.name == info.name) else {
+            continue;
+        };
+        if info.pinned {
+            swww_client::send(Request::Pin(Pin {
+                output: info.name.clone(),
+                pinned: true,
+            }))?;
+        }
+        if info.safe_area != SafeArea::default() {
+            swww_client::send(Request::SafeArea(SetSafeArea {
+                output: info.name.clone(),
+                area: info.safe_area,
+            }))?;
+        }
+        if let Some(source) = info.following {
+            swww_client::send(Request::Follow(Follow {
+                output: info.name.clone(),
+                source: Some(source),
+            }))?;
+        }
+        if now.img == info.img {
+            continue;
+        }
+        match info.img {
+            BgImg::Color(color) => {
+                swww_client::send(Request::Clear(Clear {
+                    color,
+                    outputs: vec![info.name],
+                    transition: instant_transition(),
+                }))?;
+            }
+            BgImg::Img(path) if path.is_file() => {
+                crate::set_img(&path, &info.name, crate::REFRESH_TRANSITION)?;
+            }
+            BgImg::Img(path) => {
+                eprintln!(
+                    "WARNING: {path:?}, displayed on {}, no longer exists",
+                    info.name
+                );
+            }
+            BgImg::Generated(name) => {
+                eprintln!(
+                    "WARNING: {}'s generated wallpaper ({name}) can't be carried over",
+                    info.name
+                );
+            }
+        }
+    }
+    Ok(())
+}
+
+/// The outputs were already displaying these colors, so there is nothing to transition from
+fn instant_transition() -> Transition {
+    Transition {
+        transition_type: TransitionType::Simple,
+        duration: 0.0,
+        step: 255,
+        fps: 30,
+        angle: 0.0,
+        pos: Position::new(Coord::Percent(0.5), Coord::Percent(0.5)),
+        bezier: (0.0, 0.0, 1.0, 1.0),
+        wave: (20.0, 20.0),
+        sync: TransitionSync::None,
+        skip_threshold: 0.0,
+    }
+}
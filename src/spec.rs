@@ -0,0 +1,117 @@
+//! `swww dump-cli-spec`: a description of the whole cli, generated from its clap definitions, so
+//! that programs wrapping swww don't have to scrape `--help`
+//!
+//! The format is documented in `doc/swww-dump-cli-spec.1.scd`. Adding fields is fine, but renaming
+//! or removing them breaks those programs, and must bump `SPEC_VERSION`
+use clap::{builder::PossibleValue, Arg, ArgAction, Command, CommandFactory};
+use serde_json::{json, Value};
+
+use crate::cli::Swww;
+
+const SPEC_VERSION: u32 = 1;
+
+pub fn dump() -> String {
+    let spec = json!({
+        "spec_version": SPEC_VERSION,
+        "version": env!("CARGO_PKG_VERSION"),
+        "command": command(&Swww::command()),
+    });
+    // serializing a `Value` can't fail
+    serde_json::to_string_pretty(&spec).unwrap()
+}
+
+fn command(cmd: &Command) -> Value {
+    json!({
+        "name": cmd.get_name(),
+        "about": cmd.get_about().map(ToString::to_string),
+        "long_about": cmd.get_long_about().map(ToString::to_string),
+        "aliases": cmd.get_all_aliases().collect::<Vec<_>>(),
+        "subcommand_required": cmd.is_subcommand_required_set(),
+        "args": cmd
+            .get_arguments()
+            .filter(|arg| !arg.is_hide_set())
+            .map(arg)
+            .collect::<Vec<_>>(),
+        "subcommands": cmd
+            .get_subcommands()
+            .filter(|sub| !sub.is_hide_set())
+            .map(command)
+            .collect::<Vec<_>>(),
+    })
+}
+
+fn arg(arg: &Arg) -> Value {
+    let takes_value = matches!(arg.get_action(), ArgAction::Set | ArgAction::Append);
+    json!({
+        "id": arg.get_id().as_str(),
+        "long": arg.get_long(),
+        "short": arg.get_short().map(String::from),
+        "aliases": arg.get_all_aliases().unwrap_or_default(),
+        "positional": arg.is_positional(),
+        "required": arg.is_required_set(),
+        "takes_value": takes_value,
+        "multiple": matches!(arg.get_action(), ArgAction::Append | ArgAction::Count),
+        "value_names": arg
+            .get_value_names()
+            .map(|names| names.iter().map(ToString::to_string).collect::<Vec<_>>())
+            .filter(|_| takes_value),
+        "default_values": arg
+            .get_default_values()
+            .iter()
+            .map(|value| value.to_string_lossy())
+            .collect::<Vec<_>>(),
+        "env": arg.get_env().map(|env| env.to_string_lossy()),
+        "possible_values": arg
+            .get_possible_values()
+            .iter()
+            .filter(|value| !value.is_hide_set())
+            .map(possible_value)
+            .collect::<Vec<_>>(),
+        "help": arg.get_help().map(ToString::to_string),
+        "long_help": arg.get_long_help().map(ToString::to_string),
+    })
+}
+
+fn possible_value(value: &PossibleValue) -> Value {
+    json!({
+        "name": value.get_name(),
+        "aliases": value.get_name_and_aliases().skip(1).collect::<Vec<_>>(),
+        "help": value.get_help().map(ToString::to_string),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn find<'a>(cmd: &'a Value, name: &str) -> &'a Value {
+        cmd["subcommands"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .find(|sub| sub["name"] == name)
+            .unwrap_or_else(|| panic!("no subcommand {name}"))
+    }
+
+    #[test]
+    fn spec_should_describe_subcommands_options_and_values() {
+        let spec: Value = serde_json::from_str(&dump()).unwrap();
+        assert_eq!(spec["spec_version"], SPEC_VERSION);
+        let img = find(&spec["command"], "img");
+        let transition = img["args"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .find(|arg| arg["long"] == "transition-type")
+            .unwrap();
+        assert_eq!(transition["short"], "t");
+        assert_eq!(transition["env"], "SWWW_TRANSITION");
+        assert_eq!(transition["default_values"], json!(["simple"]));
+        let values = transition["possible_values"].as_array().unwrap();
+        assert!(values.iter().any(|value| value["name"] == "wipe"));
+
+        let generate = find(&spec["command"], "generate");
+        assert_eq!(generate["subcommand_required"], true);
+        find(generate, "gradient");
+    }
+}
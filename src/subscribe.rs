@@ -0,0 +1,33 @@
+//! `swww subscribe --avg-color`: prints the average colors of what the outputs display, a few
+//! times per second, for ambient lighting that follows the wallpaper. Every round is one line per
+//! output, like `DP-1: 1a804a 203040`
+use std::{
+    io::{self, Write},
+    thread,
+    time::{Duration, Instant},
+};
+
+use swww_protocol::error::Error;
+
+use crate::cli;
+
+pub fn run(subscribe: &cli::Subscribe) -> Result<(), Error> {
+    let outputs = crate::get_outputs(&subscribe.outputs)?;
+    let interval = Duration::from_secs(1) / subscribe.rate as u32;
+    let mut stdout = io::stdout().lock();
+    loop {
+        let start = Instant::now();
+        let colors = swww_client::colors(&outputs, subscribe.grid)?;
+        let written = colors
+            .iter()
+            .try_for_each(|colors| writeln!(stdout, "{colors}"))
+            .and_then(|()| stdout.flush());
+        match written {
+            Ok(()) => (),
+            // whatever we were piped into is gone, so nobody is listening anymore
+            Err(e) if e.kind() == io::ErrorKind::BrokenPipe => return Ok(()),
+            Err(e) => return Err(Error::Other(format!("failed to print the colors: {e}"))),
+        }
+        thread::sleep(interval.saturating_sub(start.elapsed()));
+    }
+}
@@ -0,0 +1,124 @@
+//! `swww init --supervise`: instead of the daemon, we start a small `swww supervise` process, which
+//! runs the daemon and starts it again whenever it crashes. Since a crashed daemon can't tell us
+//! what it was displaying, we take a snapshot of its state every few seconds, and bring it back
+//! with `swww init --replace`'s restore once the new daemon is up
+use std::{
+    collections::VecDeque,
+    io,
+    process::{Child, Command, ExitStatus},
+    thread,
+    time::{Duration, Instant},
+};
+
+use swww_protocol::{error::Error, Answer, Request};
+use utils::communication::{get_socket_path, MAX_SESSION_WAIT};
+
+use crate::replace::{self, State};
+
+/// How often we ask the daemon for its state
+const SNAPSHOT_INTERVAL: Duration = Duration::from_secs(5);
+/// How often we check whether the daemon exited
+const POLL_INTERVAL: Duration = Duration::from_millis(200);
+/// If the daemon crashes this many times in `CRASH_WINDOW`, something is very wrong, and starting
+/// it again won't help
+const MAX_CRASHES: usize = 5;
+const CRASH_WINDOW: Duration = Duration::from_secs(60);
+
+/// Turns `daemon`, the command starting swww-daemon, into one starting the supervisor instead
+pub fn command(daemon: &Command) -> Result<Command, Error> {
+    let exe = match std::env::current_exe() {
+        Ok(exe) => exe,
+        Err(e) => return Err(Error::Other(format!("failed to find the swww binary: {e}"))),
+    };
+    let mut cmd = Command::new(exe);
+    cmd.arg("supervise").arg("--").args(daemon.get_args());
+    Ok(cmd)
+}
+
+/// Runs swww-daemon with `args` until it exits by itself (with `swww kill`, for example)
+pub fn run(args: &[String]) -> Result<(), Error> {
+    let mut crashes = VecDeque::new();
+    let mut state = None;
+    let mut daemon = spawn(args.iter().map(String::as_str))?;
+    loop {
+        let status = watch(&mut daemon, &mut state)?;
+        if status.success() {
+            return Ok(());
+        }
+        let now = Instant::now();
+        crashes.retain(|crash| now.duration_since(*crash) < CRASH_WINDOW);
+        crashes.push_back(now);
+        if crashes.len() >= MAX_CRASHES {
+            return Err(Error::Daemon(format!(
+                "swww-daemon crashed {MAX_CRASHES} times in {}s, giving up",
+                CRASH_WINDOW.as_secs()
+            )));
+        }
+        eprintln!("WARNING: swww-daemon crashed ({status}), starting it again");
+        // it didn't get to delete its socket, and there is no one left to take over from
+        remove_stale_socket()?;
+        daemon = spawn(
+            args.iter()
+                .map(String::as_str)
+                .filter(|a| *a != "--replace"),
+        )?;
+        if let Err(e) = restore(state.take()) {
+            eprintln!("WARNING: failed to restore the daemon's state: {e}");
+        }
+    }
+}
+
+fn spawn<'a>(args: impl Iterator<Item = &'a str>) -> Result<Child, Error> {
+    match Command::new("swww-daemon").args(args).spawn() {
+        Ok(child) => Ok(child),
+        Err(e) => Err(Error::Daemon(format!("error spawning swww-daemon: {e}"))),
+    }
+}
+
+/// Waits for `daemon` to exit, keeping `state` up to date in the meantime
+fn watch(daemon: &mut Child, state: &mut Option<State>) -> Result<ExitStatus, Error> {
+    let mut last_snapshot: Option<Instant> = None;
+    loop {
+        match daemon.try_wait() {
+            Ok(Some(status)) => return Ok(status),
+            Ok(None) => (),
+            Err(e) => {
+                return Err(Error::Daemon(format!(
+                    "failed to wait for swww-daemon: {e}"
+                )))
+            }
+        }
+        if last_snapshot.is_none_or(|last| last.elapsed() >= SNAPSHOT_INTERVAL) {
+            // the daemon may not be listening yet, or be too busy to answer; we keep what we had
+            if let Ok(Some(snapshot)) = replace::previous_state() {
+                *state = Some(snapshot);
+            }
+            last_snapshot = Some(Instant::now());
+        }
+        thread::sleep(POLL_INTERVAL);
+    }
+}
+
+fn remove_stale_socket() -> Result<(), Error> {
+    let socket_path = get_socket_path();
+    match std::fs::remove_file(&socket_path) {
+        Ok(()) => Ok(()),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(()),
+        Err(e) => Err(Error::Daemon(format!(
+            "failed to delete the crashed daemon's socket at {socket_path:?}: {e}"
+        ))),
+    }
+}
+
+/// Waits for the new daemon to be ready, like `swww init` does, and brings back `state` in it
+fn restore(state: Option<State>) -> Result<(), Error> {
+    let socket = swww_client::connect_retrying(20, Duration::from_millis(100))?;
+    Request::Init.send(&socket)?;
+    match Answer::receive_within(socket, MAX_SESSION_WAIT + Duration::from_secs(5))? {
+        Answer::Err(e) => Err(e),
+        _ => match state {
+            Some(state) => replace::restore(state),
+            None => Ok(()),
+        },
+    }
+}
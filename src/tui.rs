@@ -0,0 +1,297 @@
+//! Interactive terminal interface, for those who would rather browse for an image than remember
+//! `swww img`'s options. It only talks to the daemon through the same requests `swww` sends
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use ratatui::{
+    crossterm::event::{self, Event, KeyCode, KeyEventKind},
+    layout::{Constraint, Layout},
+    style::{Modifier, Style},
+    text::Line,
+    widgets::{Block, List, ListItem, ListState, Paragraph},
+    DefaultTerminal, Frame,
+};
+use swww_protocol::{error::Error, Answer, BgInfo, QueueCommand, Request};
+
+/// Everything `--transition-type` accepts, in the order `t` cycles through them
+const TRANSITIONS: [&str; 14] = [
+    "simple", "left", "right", "top", "bottom", "wipe", "wave", "grow", "center", "any", "outer",
+    "flash", "slide", "random",
+];
+
+#[derive(Clone, Copy, PartialEq)]
+enum Focus {
+    Outputs,
+    Files,
+}
+
+struct App {
+    outputs: Vec<BgInfo>,
+    /// Outputs marked with space. When none are, requests go to the highlighted one
+    marked: Vec<String>,
+    output_state: ListState,
+    queue: Vec<String>,
+    dir: PathBuf,
+    entries: Vec<PathBuf>,
+    file_state: ListState,
+    transition: usize,
+    focus: Focus,
+    status: String,
+}
+
+impl App {
+    fn new() -> Result<Self, Error> {
+        let dir = std::env::current_dir()
+            .map_err(|e| Error::Other(format!("failed to read current directory: {e}")))?;
+        let mut app = Self {
+            outputs: Vec::new(),
+            marked: Vec::new(),
+            output_state: ListState::default().with_selected(Some(0)),
+            queue: Vec::new(),
+            entries: Vec::new(),
+            dir,
+            file_state: ListState::default(),
+            transition: 0,
+            focus: Focus::Files,
+            status: "press ? for help".to_string(),
+        };
+        app.refresh()?;
+        app.open_dir(app.dir.clone());
+        Ok(app)
+    }
+
+    fn refresh(&mut self) -> Result<(), Error> {
+        if let Answer::Info(infos) = swww_client::send(Request::Query)? {
+            self.outputs = infos;
+        }
+        if let Answer::Queue(queue) = swww_client::send(Request::Queue(QueueCommand::List))? {
+            self.queue = queue;
+        }
+        self.marked
+            .retain(|name| self.outputs.iter().any(|info| info.name == *name));
+        Ok(())
+    }
+
+    fn open_dir(&mut self, dir: PathBuf) {
+        match list_dir(&dir) {
+            Ok(entries) => {
+                self.entries = entries;
+                self.dir = dir;
+                self.file_state.select(Some(0));
+            }
+            Err(e) => self.status = e,
+        }
+    }
+
+    /// The outputs requests go to, joined the way `--outputs` expects them
+    fn target_outputs(&self) -> String {
+        if !self.marked.is_empty() {
+            return self.marked.join(",");
+        }
+        self.output_state
+            .selected()
+            .and_then(|i| self.outputs.get(i))
+            .map(|info| info.name.clone())
+            .unwrap_or_default()
+    }
+
+    /// Returns whether we should quit
+    fn handle_key(&mut self, key: KeyCode) -> bool {
+        let state = match self.focus {
+            Focus::Outputs => &mut self.output_state,
+            Focus::Files => &mut self.file_state,
+        };
+        match key {
+            KeyCode::Char('q') | KeyCode::Esc => return true,
+            KeyCode::Tab => {
+                self.focus = match self.focus {
+                    Focus::Outputs => Focus::Files,
+                    Focus::Files => Focus::Outputs,
+                }
+            }
+            KeyCode::Down | KeyCode::Char('j') => state.select_next(),
+            KeyCode::Up | KeyCode::Char('k') => state.select_previous(),
+            KeyCode::Char('t') => self.transition = (self.transition + 1) % TRANSITIONS.len(),
+            KeyCode::Char('T') => {
+                self.transition = (self.transition + TRANSITIONS.len() - 1) % TRANSITIONS.len()
+            }
+            KeyCode::Char('r') => {
+                self.status = match self.refresh() {
+                    Ok(()) => "refreshed".to_string(),
+                    Err(e) => e.to_string(),
+                }
+            }
+            KeyCode::Char(' ') if self.focus == Focus::Outputs => {
+                if let Some(info) = self
+                    .output_state
+                    .selected()
+                    .and_then(|i| self.outputs.get(i))
+                {
+                    match self.marked.iter().position(|name| *name == info.name) {
+                        Some(i) => drop(self.marked.remove(i)),
+                        None => self.marked.push(info.name.clone()),
+                    }
+                }
+            }
+            KeyCode::Backspace | KeyCode::Char('h') if self.focus == Focus::Files => {
+                if let Some(parent) = self.dir.parent() {
+                    self.open_dir(parent.to_path_buf());
+                }
+            }
+            KeyCode::Enter | KeyCode::Char('l') if self.focus == Focus::Files => {
+                if let Some(entry) = self.file_state.selected().and_then(|i| self.entries.get(i)) {
+                    if entry.is_dir() {
+                        self.open_dir(entry.clone());
+                    } else {
+                        let entry = entry.clone();
+                        self.set_img(&entry);
+                    }
+                }
+            }
+            KeyCode::Char('?') => {
+                self.status = "tab: switch lists, space: mark output, enter: open/send, \
+                    backspace: parent dir, t/T: transition, r: refresh, q: quit"
+                    .to_string()
+            }
+            _ => (),
+        }
+        false
+    }
+
+    fn set_img(&mut self, path: &Path) {
+        let outputs = self.target_outputs();
+        let args = ["--transition-type", TRANSITIONS[self.transition]];
+        self.status = match crate::set_img(path, &outputs, &args).and_then(|()| self.refresh()) {
+            Ok(()) => format!("sent {} to {outputs}", path.display()),
+            Err(e) => format!("Error: {e}"),
+        };
+    }
+
+    fn draw(&mut self, frame: &mut Frame) {
+        let [main, status] =
+            Layout::vertical([Constraint::Min(0), Constraint::Length(1)]).areas(frame.area());
+        let [left, files] =
+            Layout::horizontal([Constraint::Percentage(50), Constraint::Percentage(50)])
+                .areas(main);
+        let [outputs, queue] =
+            Layout::vertical([Constraint::Min(0), Constraint::Length(6)]).areas(left);
+
+        let highlight = Style::default().add_modifier(Modifier::REVERSED);
+        let title = |name: &str, focus: Focus| {
+            let block = Block::bordered().title(name.to_string());
+            if self.focus == focus {
+                block.border_style(Style::default().add_modifier(Modifier::BOLD))
+            } else {
+                block
+            }
+        };
+
+        let output_items: Vec<ListItem> = self
+            .outputs
+            .iter()
+            .map(|info| {
+                let mark = if self.marked.contains(&info.name) {
+                    "* "
+                } else {
+                    "  "
+                };
+                ListItem::new(vec![
+                    Line::from(format!("{mark}{}", info.name)),
+                    Line::from(format!("    {}", info.img)),
+                ])
+            })
+            .collect();
+        let output_list = List::new(output_items)
+            .block(title("Outputs", Focus::Outputs))
+            .highlight_style(highlight);
+        frame.render_stateful_widget(output_list, outputs, &mut self.output_state);
+
+        let queue_list = List::new(self.queue.iter().map(String::as_str))
+            .block(Block::bordered().title("Queue"));
+        frame.render_widget(queue_list, queue);
+
+        let file_items = self.entries.iter().map(|entry| {
+            let name = entry.file_name().unwrap_or_default().to_string_lossy();
+            if entry.is_dir() {
+                format!("{name}/")
+            } else {
+                name.into_owned()
+            }
+        });
+        let file_list = List::new(file_items)
+            .block(title(&self.dir.display().to_string(), Focus::Files))
+            .highlight_style(highlight);
+        frame.render_stateful_widget(file_list, files, &mut self.file_state);
+
+        let status_line = format!(
+            "[transition: {}] [outputs: {}] {}",
+            TRANSITIONS[self.transition],
+            self.target_outputs(),
+            self.status
+        );
+        frame.render_widget(Paragraph::new(status_line), status);
+    }
+}
+
+/// Lists the entries of `dir`, with directories first, skipping hidden ones
+fn list_dir(dir: &Path) -> Result<Vec<PathBuf>, String> {
+    let read = match fs::read_dir(dir) {
+        Ok(read) => read,
+        Err(e) => return Err(format!("failed to read {}: {e}", dir.display())),
+    };
+    let mut entries: Vec<PathBuf> = read
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.path())
+        .filter(|path| {
+            !path
+                .file_name()
+                .is_some_and(|name| name.to_string_lossy().starts_with('.'))
+        })
+        .collect();
+    entries.sort_by_key(|path| (!path.is_dir(), path.file_name().map(|n| n.to_os_string())));
+    Ok(entries)
+}
+
+fn event_loop(terminal: &mut DefaultTerminal, app: &mut App) -> Result<(), String> {
+    loop {
+        if let Err(e) = terminal.draw(|frame| app.draw(frame)) {
+            return Err(format!("failed to draw: {e}"));
+        }
+        match event::read() {
+            Ok(Event::Key(key)) if key.kind == KeyEventKind::Press => {
+                if app.handle_key(key.code) {
+                    return Ok(());
+                }
+            }
+            Ok(_) => (),
+            Err(e) => return Err(format!("failed to read terminal event: {e}")),
+        }
+    }
+}
+
+pub fn run() -> Result<(), Error> {
+    // fail before taking over the terminal if the daemon isn't there
+    let mut app = App::new()?;
+    let mut terminal = ratatui::init();
+    let result = event_loop(&mut terminal, &mut app);
+    ratatui::restore();
+    Ok(result?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn every_transition_should_be_accepted_by_swww_img() {
+        for transition in TRANSITIONS {
+            assert!(
+                <crate::cli::TransitionType as clap::ValueEnum>::from_str(transition, false)
+                    .is_ok(),
+                "{transition}"
+            );
+        }
+    }
+}
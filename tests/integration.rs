@@ -67,7 +67,9 @@ fn general_commands() {
     sending_img_to_individual_monitors(&output);
     sending_img_to_monitor_that_does_not_exist();
     sending_img_with_custom_transition();
+    patching_outputs();
     clear_outputs();
+    sending_img_if_unset();
     killing_daemon();
     cmd().arg("query").assert().failure(); //daemon is dead, so this should fail
 }
@@ -167,10 +169,37 @@ fn sending_img_with_custom_transition() {
         .success();
 }
 
+fn patching_outputs() {
+    cmd()
+        .arg("patch")
+        .arg(TEST_IMGS[0])
+        .arg("--pos")
+        .arg("top-left")
+        .arg("--size")
+        .arg("100x100")
+        .assert()
+        .success();
+}
+
 fn clear_outputs() {
     cmd().arg("clear").assert().success();
 }
 
+/// Must come right after `clear_outputs`, so that the first image is sent and the second isn't
+fn sending_img_if_unset() {
+    for skipped in [false, true] {
+        let output = cmd()
+            .arg("img")
+            .arg(TEST_IMGS[0])
+            .arg("--if-unset")
+            .output()
+            .expect("failed to send image");
+        assert!(output.status.success());
+        let stdout = String::from_utf8(output.stdout).unwrap();
+        assert_eq!(stdout.starts_with("skipped"), skipped);
+    }
+}
+
 fn killing_daemon() {
     cmd().arg("kill").assert().success();
 }
@@ -14,12 +14,15 @@ fn spell_check_code_and_man_pages() {
             "--enable-colors",
             "--ignore-words-list",
             "crate",
-            "src",        // client
-            "daemon/src", // daemon
-            "utils/src",  // common code
-            "doc",        // man pages
+            "src",          // client
+            "daemon/src",   // daemon
+            "utils/src",    // common code
+            "protocol/src", // the socket's messages
+            "render/src",   // image processing
+            "client/src",   // client library
+            "doc",          // man pages
             "CHANGELOG.md",
-            "README.md"
+            "README.md",
         ])
         .output()
     {
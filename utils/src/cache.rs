@@ -0,0 +1,609 @@
+//! Every output has its own cache file, named after it, in the directory returned by
+//! `get_cache_path`. The file is a sequence of entries: the first one is what is being displayed,
+//! either an image, which may be followed by an animation, a color set with `swww clear`, or a
+//! generator started with `swww generate`. Each entry is:
+//!
+//! | field    | size      | contents                                                     |
+//! |----------|-----------|--------------------------------------------------------------|
+//! | magic    | 4 bytes   | `SWWC`                                                       |
+//! | version  | 1 byte    | `3`                                                          |
+//! | kind     | 1 byte    | `0` for images, `1` for animations, `2` for colors, and `3`  |
+//! |          |           | for generators                                               |
+//! | length   | 8 bytes   | length of the payload, little endian                         |
+//! | checksum | 4 bytes   | crc32 of the payload, little endian                          |
+//! | payload  | `length`  | zstd compressed, bincode serialized `Img`/`Animation`/`[u8;  |
+//! |          |           | 3]`/`Generator`                                              |
+//!
+//! The version is bumped whenever the payloads change. Entries of older versions are migrated
+//! when loaded, and their file rewritten in the current version, so upgrading swww doesn't lose
+//! the cache:
+//!
+//! | version | changes                                                         |
+//! |---------|-----------------------------------------------------------------|
+//! | `1`     | the first one                                                   |
+//! | `2`     | animations have keyframes. Those of `1` are loaded without them |
+//! | `3`     | files may start with a color or a generator instead of an image |
+//!
+//! Files written by newer versions are left alone, so that going back to an older swww doesn't
+//! lose them either. Any other file that doesn't follow this format is considered corrupted, and
+//! deleted when read.
+//!
+//! A file's modification time is the last time it was used, which `evict` relies on.
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use std::{
+    fs::{self, File},
+    io::{BufRead, BufReader, BufWriter, Read, Write},
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime},
+};
+
+use swww_protocol::{Animation, BitPack, Generator, Img};
+
+use crate::communication::get_cache_path;
+
+const MAGIC: &[u8; 4] = b"SWWC";
+const VERSION: u8 = 3;
+/// The oldest version we can migrate from
+const OLDEST_VERSION: u8 = 1;
+const HEADER_LEN: usize = 18;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EntryKind {
+    Image = 0,
+    Animation = 1,
+    Color = 2,
+    Generator = 3,
+}
+
+/// What an output was displaying, as it was cached
+pub enum Cached {
+    Img(Img, Option<Animation>),
+    /// In rgb
+    Color([u8; 3]),
+    Generated(Generator),
+}
+
+/// What we know about an output's cache file
+pub struct FileStats {
+    pub output: String,
+    pub size: u64,
+    /// The kinds of the entries in the file, or why it is corrupted
+    pub entries: Result<Vec<EntryKind>, String>,
+    /// Whether it was written by an older version, and will be migrated when loaded
+    pub outdated: bool,
+}
+
+/// Animations as version 1 stored them, before keyframes
+#[derive(Serialize, Deserialize)]
+struct AnimationV1 {
+    animation: Box<[(BitPack, Duration)]>,
+    sync: bool,
+}
+
+impl From<AnimationV1> for Animation {
+    fn from(old: AnimationV1) -> Self {
+        Animation {
+            animation: old.animation,
+            keyframes: Box::new([]),
+            sync: old.sync,
+        }
+    }
+}
+
+/// Caches `img` for every output in `outputs`, replacing whatever was there
+pub fn store_img(img: &Img, outputs: &[String]) -> Result<(), String> {
+    let entry = make_entry(EntryKind::Image, img)?;
+    for output in outputs {
+        let path = output_path(output)?;
+        if let Err(e) = fs::write(&path, &entry) {
+            return Err(format!("failed to write cache file {path:?}: {e}"));
+        }
+    }
+    Ok(())
+}
+
+/// Caches `animation` for every output in `outputs`. Must be called after `store_img`
+pub fn store_animation(animation: &Animation, outputs: &[String]) -> Result<(), String> {
+    let entry = make_entry(EntryKind::Animation, animation)?;
+    for output in outputs {
+        let path = output_path(output)?;
+        let result = File::options()
+            .append(true)
+            .open(&path)
+            .and_then(|file| BufWriter::new(file).write_all(&entry));
+        if let Err(e) = result {
+            return Err(format!("failed to append to cache file {path:?}: {e}"));
+        }
+    }
+    Ok(())
+}
+
+/// Caches `color`, which `swww clear` set, for every output in `outputs`, replacing whatever was
+/// there
+pub fn store_color(color: [u8; 3], outputs: &[String]) -> Result<(), String> {
+    store(&make_entry(EntryKind::Color, &color)?, outputs)
+}
+
+/// Caches `generator`, which `swww generate` started, for every output in `outputs`, replacing
+/// whatever was there
+pub fn store_generator(generator: &Generator, outputs: &[String]) -> Result<(), String> {
+    store(&make_entry(EntryKind::Generator, generator)?, outputs)
+}
+
+fn store(entry: &[u8], outputs: &[String]) -> Result<(), String> {
+    for output in outputs {
+        let path = output_path(output)?;
+        if let Err(e) = fs::write(&path, entry) {
+            return Err(format!("failed to write cache file {path:?}: {e}"));
+        }
+    }
+    Ok(())
+}
+
+/// Loads what was cached for `output`, if anything. Corrupted files are deleted
+pub fn load(output: &str) -> Result<Option<Cached>, String> {
+    let path = output_path(output)?;
+    let file = match File::open(&path) {
+        Ok(file) => file,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(format!("failed to open cache file {path:?}: {e}")),
+    };
+
+    // this marks the file as recently used
+    let _ = file.set_modified(SystemTime::now());
+    let size = file.metadata().map_err(|e| e.to_string())?.len();
+    let mut reader = BufReader::new(file);
+    if let Ok(header) = reader.fill_buf() {
+        if header.starts_with(MAGIC) && header.get(4).is_some_and(|v| *v > VERSION) {
+            return Err(format!(
+                "cache file {path:?} was written by a newer version of swww, ignoring it"
+            ));
+        }
+    }
+
+    match read_file(&mut reader, size) {
+        Ok((cached, outdated)) => {
+            if outdated {
+                // so that it is only migrated once. If this fails, it is just migrated again
+                if let Ok(entries) = make_entries(&cached) {
+                    let _ = fs::write(&path, entries);
+                }
+            }
+            Ok(Some(cached))
+        }
+        Err(e) => {
+            // there is no point in keeping it around
+            let _ = fs::remove_file(&path);
+            Err(format!(
+                "cache file {path:?} is corrupted ({e}), deleted it"
+            ))
+        }
+    }
+}
+
+/// Checks every file in the cache, without decompressing them
+pub fn stats() -> Result<Vec<FileStats>, String> {
+    let mut stats = Vec::new();
+    for path in cache_files()? {
+        let output = path.file_name().unwrap_or_default().to_string_lossy();
+        let size = fs::metadata(&path).map(|m| m.len()).unwrap_or(0);
+        let entries = File::open(&path)
+            .map_err(|e| e.to_string())
+            .and_then(|file| {
+                let mut reader = BufReader::new(file);
+                let mut entries = Vec::new();
+                while let Some(entry) = read_entry(&mut reader, size)? {
+                    entries.push((entry.kind, entry.version));
+                }
+                Ok(entries)
+            });
+        stats.push(FileStats {
+            output: output.into_owned(),
+            size,
+            outdated: entries
+                .as_ref()
+                .is_ok_and(|entries| entries.iter().any(|(_, version)| *version < VERSION)),
+            entries: entries.map(|entries| entries.into_iter().map(|(kind, _)| kind).collect()),
+        });
+    }
+    stats.sort_by(|a, b| a.output.cmp(&b.output));
+    Ok(stats)
+}
+
+/// Deletes every file in the cache, returning how many bytes were freed
+pub fn clean() -> Result<u64, String> {
+    let mut freed = 0;
+    for path in cache_files()? {
+        let size = fs::metadata(&path).map(|m| m.len()).unwrap_or(0);
+        match fs::remove_file(&path) {
+            Ok(()) => freed += size,
+            Err(e) => return Err(format!("failed to delete cache file {path:?}: {e}")),
+        }
+    }
+    Ok(freed)
+}
+
+/// Deletes the cache files of `outputs`, if they have any
+pub fn forget(outputs: &[String]) -> Result<(), String> {
+    for output in outputs {
+        let path = output_path(output)?;
+        match fs::remove_file(&path) {
+            Ok(()) => (),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => (),
+            Err(e) => return Err(format!("failed to delete cache file {path:?}: {e}")),
+        }
+    }
+    Ok(())
+}
+
+/// Deletes the files whose images no longer exist, and then the least recently used ones, until
+/// the cache is no larger than `limit` bytes. The files of the outputs in `keep` are only deleted
+/// if their images no longer exist. Returns how many bytes were freed
+pub fn evict(limit: Option<u64>, keep: &[String]) -> Result<u64, String> {
+    let mut files = Vec::new();
+    let mut freed = 0;
+    for path in cache_files()? {
+        let metadata = match fs::metadata(&path) {
+            Ok(metadata) => metadata,
+            Err(_) => continue,
+        };
+        // images read from stdin have no path we could check
+        let missing = source_path(&path).is_some_and(|src| src.is_absolute() && !src.exists());
+        if missing {
+            if fs::remove_file(&path).is_ok() {
+                freed += metadata.len();
+            }
+        } else {
+            let used = metadata.modified().unwrap_or(SystemTime::UNIX_EPOCH);
+            files.push((used, metadata.len(), path));
+        }
+    }
+
+    if let Some(limit) = limit {
+        let mut total: u64 = files.iter().map(|(_, size, _)| size).sum();
+        files.sort_by_key(|(used, _, _)| *used);
+        for (_, size, path) in files {
+            if total <= limit {
+                break;
+            }
+            let output = path.file_name().unwrap_or_default().to_string_lossy();
+            if keep.iter().any(|k| *k == output) {
+                continue;
+            }
+            match fs::remove_file(&path) {
+                Ok(()) => {
+                    total -= size;
+                    freed += size;
+                }
+                Err(e) => return Err(format!("failed to delete cache file {path:?}: {e}")),
+            }
+        }
+    }
+    Ok(freed)
+}
+
+/// Parses sizes like '500M', '2GiB' or '4096' (in bytes)
+pub fn parse_size(raw: &str) -> Result<u64, String> {
+    let trimmed = raw.trim();
+    let trimmed = trimmed
+        .strip_suffix("iB")
+        .or_else(|| trimmed.strip_suffix('B'))
+        .unwrap_or(trimmed);
+    let (number, multiplier) = match trimmed.char_indices().last() {
+        Some((i, 'K' | 'k')) => (&trimmed[..i], 1 << 10),
+        Some((i, 'M' | 'm')) => (&trimmed[..i], 1 << 20),
+        Some((i, 'G' | 'g')) => (&trimmed[..i], 1 << 30),
+        _ => (trimmed, 1),
+    };
+    match number.trim().parse::<u64>() {
+        Ok(n) => Ok(n * multiplier),
+        Err(_) => Err(format!("invalid size: {raw}")),
+    }
+}
+
+/// Reads only the path of the image cached in `file`, without decompressing the rest of it
+fn source_path(file: &Path) -> Option<PathBuf> {
+    let file = File::open(file).ok()?;
+    let size = file.metadata().ok()?.len();
+    match read_entry(&mut BufReader::new(file), size) {
+        // `Img` starts with its path, so we can stop deserializing right after it
+        Ok(Some(Entry {
+            kind: EntryKind::Image,
+            payload,
+            ..
+        })) => decode(&payload).ok(),
+        _ => None,
+    }
+}
+
+fn output_path(output: &str) -> Result<PathBuf, String> {
+    let mut path = get_cache_path()?;
+    path.push(output);
+    Ok(path)
+}
+
+fn cache_files() -> Result<Vec<PathBuf>, String> {
+    let cache_path = get_cache_path()?;
+    match fs::read_dir(&cache_path) {
+        Ok(dir) => Ok(dir
+            .filter_map(|entry| entry.ok().map(|e| e.path()))
+            .filter(|path| path.is_file())
+            .collect()),
+        Err(e) => Err(format!(
+            "failed to read cache directory {cache_path:?}: {e}"
+        )),
+    }
+}
+
+/// The entries of a whole file holding `cached`
+fn make_entries(cached: &Cached) -> Result<Vec<u8>, String> {
+    match cached {
+        Cached::Img(img, animation) => {
+            let mut entries = make_entry(EntryKind::Image, img)?;
+            if let Some(animation) = animation {
+                entries.extend(make_entry(EntryKind::Animation, animation)?);
+            }
+            Ok(entries)
+        }
+        Cached::Color(color) => make_entry(EntryKind::Color, color),
+        Cached::Generated(generator) => make_entry(EntryKind::Generator, generator),
+    }
+}
+
+fn make_entry<T: Serialize>(kind: EntryKind, value: &T) -> Result<Vec<u8>, String> {
+    make_entry_with_version(VERSION, kind, value)
+}
+
+fn make_entry_with_version<T: Serialize>(
+    version: u8,
+    kind: EntryKind,
+    value: &T,
+) -> Result<Vec<u8>, String> {
+    let mut encoder = match zstd::stream::Encoder::new(Vec::new(), 0) {
+        Ok(encoder) => encoder,
+        Err(e) => return Err(format!("failed to create zstd encoder: {e}")),
+    };
+    if let Err(e) = bincode::serialize_into(&mut encoder, value) {
+        return Err(format!("failed to serialize cache entry: {e}"));
+    }
+    let payload = match encoder.finish() {
+        Ok(payload) => payload,
+        Err(e) => return Err(format!("failed to compress cache entry: {e}")),
+    };
+
+    let mut entry = Vec::with_capacity(HEADER_LEN + payload.len());
+    entry.extend_from_slice(MAGIC);
+    entry.push(version);
+    entry.push(kind as u8);
+    entry.extend_from_slice(&(payload.len() as u64).to_le_bytes());
+    entry.extend_from_slice(&crc32fast::hash(&payload).to_le_bytes());
+    entry.extend_from_slice(&payload);
+    Ok(entry)
+}
+
+struct Entry {
+    version: u8,
+    kind: EntryKind,
+    payload: Vec<u8>,
+}
+
+/// Reads a whole cache file: what it holds, and whether any of its entries had to be migrated from
+/// an older version
+fn read_file(reader: &mut impl Read, size: u64) -> Result<(Cached, bool), String> {
+    let (img, mut outdated) = match read_entry(reader, size)? {
+        Some(Entry {
+            kind: EntryKind::Image,
+            version,
+            payload,
+        }) => (decode(&payload)?, version < VERSION),
+        Some(Entry {
+            kind: kind @ (EntryKind::Color | EntryKind::Generator),
+            version,
+            payload,
+        }) => {
+            let cached = match kind {
+                EntryKind::Color => Cached::Color(decode(&payload)?),
+                _ => Cached::Generated(decode(&payload)?),
+            };
+            if read_entry(reader, size)?.is_some() {
+                return Err("found entries after a color or generator".to_string());
+            }
+            return Ok((cached, version < VERSION));
+        }
+        _ => return Err("file doesn't start with an image, a color or a generator".to_string()),
+    };
+    let animation = match read_entry(reader, size)? {
+        Some(Entry {
+            kind: EntryKind::Animation,
+            version,
+            payload,
+        }) => {
+            outdated |= version < VERSION;
+            Some(match version {
+                1 => decode::<AnimationV1>(&payload)?.into(),
+                _ => decode(&payload)?,
+            })
+        }
+        Some(_) => return Err("found two images".to_string()),
+        None => None,
+    };
+    Ok((Cached::Img(img, animation), outdated))
+}
+
+/// Reads the next entry and verifies its checksum. Returns `None` at the end of the file.
+/// `file_size` is used to reject absurd lengths before allocating anything
+fn read_entry(reader: &mut impl Read, file_size: u64) -> Result<Option<Entry>, String> {
+    let mut header = [0; HEADER_LEN];
+    match reader.read(&mut header[..1]) {
+        Ok(0) => return Ok(None),
+        Ok(_) => (),
+        Err(e) => return Err(e.to_string()),
+    }
+    if let Err(e) = reader.read_exact(&mut header[1..]) {
+        return Err(format!("truncated header: {e}"));
+    }
+    if &header[0..4] != MAGIC {
+        return Err("bad magic number".to_string());
+    }
+    let version = header[4];
+    if !(OLDEST_VERSION..=VERSION).contains(&version) {
+        return Err(format!("unknown version {version}"));
+    }
+    let kind = match header[5] {
+        0 => EntryKind::Image,
+        1 => EntryKind::Animation,
+        2 => EntryKind::Color,
+        3 => EntryKind::Generator,
+        other => return Err(format!("unknown entry kind {other}")),
+    };
+    let len = u64::from_le_bytes(header[6..14].try_into().unwrap());
+    let checksum = u32::from_le_bytes(header[14..18].try_into().unwrap());
+    if len > file_size {
+        return Err("entry is longer than the file".to_string());
+    }
+
+    let mut payload = vec![0; len as usize];
+    if let Err(e) = reader.read_exact(&mut payload) {
+        return Err(format!("truncated payload: {e}"));
+    }
+    if crc32fast::hash(&payload) != checksum {
+        return Err("checksum mismatch".to_string());
+    }
+    Ok(Some(Entry {
+        version,
+        kind,
+        payload,
+    }))
+}
+
+fn decode<T: DeserializeOwned>(payload: &[u8]) -> Result<T, String> {
+    let decoder = match zstd::stream::Decoder::new(payload) {
+        Ok(decoder) => decoder,
+        Err(e) => return Err(format!("failed to create zstd decoder: {e}")),
+    };
+    bincode::deserialize_from(decoder).map_err(|e| format!("failed to deserialize entry: {e}"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entries(bytes: &[u8]) -> Result<Vec<EntryKind>, String> {
+        let mut reader = bytes;
+        let mut entries = Vec::new();
+        while let Some(entry) = read_entry(&mut reader, bytes.len() as u64)? {
+            if entry.kind == EntryKind::Image {
+                let img: Img = decode(&entry.payload)?;
+                assert_eq!(img.img, vec![1, 2, 3, 4]);
+            }
+            entries.push(entry.kind);
+        }
+        Ok(entries)
+    }
+
+    #[test]
+    fn entries_should_round_trip() {
+        let img = Img {
+            path: PathBuf::from("/a.png"),
+            img: vec![1, 2, 3, 4],
+        };
+        let animation = Animation {
+            animation: Box::new([]),
+            keyframes: Box::new([]),
+            sync: false,
+        };
+        let mut file = make_entry(EntryKind::Image, &img).unwrap();
+        file.extend(make_entry(EntryKind::Animation, &animation).unwrap());
+        assert_eq!(
+            entries(&file).unwrap(),
+            vec![EntryKind::Image, EntryKind::Animation]
+        );
+    }
+
+    #[test]
+    fn older_versions_should_be_migrated() {
+        let img = Img {
+            path: PathBuf::from("/a.gif"),
+            img: vec![1, 2, 3, 4],
+        };
+        let animation = AnimationV1 {
+            animation: Box::new([]),
+            sync: true,
+        };
+        let mut file = make_entry_with_version(1, EntryKind::Image, &img).unwrap();
+        file.extend(make_entry_with_version(1, EntryKind::Animation, &animation).unwrap());
+        let (Cached::Img(img, animation), outdated) =
+            read_file(&mut file.as_slice(), file.len() as u64).unwrap()
+        else {
+            panic!("the image was read as something else");
+        };
+        assert_eq!(img.path, PathBuf::from("/a.gif"));
+        let animation = animation.unwrap();
+        assert!(animation.sync);
+        assert!(animation.keyframes.is_empty());
+        assert!(outdated);
+
+        let file = make_entry(EntryKind::Image, &img).unwrap();
+        let (cached, outdated) = read_file(&mut file.as_slice(), file.len() as u64).unwrap();
+        assert!(matches!(cached, Cached::Img(_, None)));
+        assert!(!outdated);
+
+        let newer = make_entry_with_version(VERSION + 1, EntryKind::Image, &img).unwrap();
+        assert!(entries(&newer).is_err());
+    }
+
+    #[test]
+    fn colors_and_generators_should_round_trip() {
+        let read = |file: Vec<u8>| read_file(&mut file.as_slice(), file.len() as u64);
+
+        let color = make_entry(EntryKind::Color, &[1u8, 2, 3]).unwrap();
+        assert!(matches!(
+            read(color.clone()),
+            Ok((Cached::Color([1, 2, 3]), false))
+        ));
+
+        let generator = Generator::Gradient {
+            colors: vec![[0, 0, 0], [255, 255, 255]],
+            angle: 45.0,
+            period: None,
+            fps: 30,
+        };
+        let file = make_entries(&Cached::Generated(generator)).unwrap();
+        match read(file) {
+            Ok((Cached::Generated(Generator::Gradient { colors, angle, .. }), false)) => {
+                assert_eq!((colors.len(), angle), (2, 45.0));
+            }
+            _ => panic!("the generator was read as something else"),
+        }
+
+        // nothing follows colors and generators
+        let mut file = color;
+        file.extend(make_entry(EntryKind::Color, &[1u8, 2, 3]).unwrap());
+        assert!(read(file).is_err());
+    }
+
+    #[test]
+    fn should_parse_sizes() {
+        assert_eq!(parse_size("500M").unwrap(), 500 << 20);
+        assert_eq!(parse_size("2GiB").unwrap(), 2 << 30);
+        assert_eq!(parse_size("10kB").unwrap(), 10 << 10);
+        assert_eq!(parse_size("4096").unwrap(), 4096);
+        assert!(parse_size("M").is_err());
+        assert!(parse_size("lots").is_err());
+    }
+
+    #[test]
+    fn corruption_should_be_detected() {
+        let img = Img {
+            path: PathBuf::new(),
+            img: vec![1, 2, 3, 4],
+        };
+        let entry = make_entry(EntryKind::Image, &img).unwrap();
+
+        let mut flipped = entry.clone();
+        *flipped.last_mut().unwrap() ^= 1;
+        assert!(entries(&flipped).is_err());
+        assert!(entries(&entry[..entry.len() - 1]).is_err());
+        assert!(entries(b"not a cache file at all").is_err());
+    }
+}
@@ -1,2 +1,7 @@
+pub mod cache;
 pub mod communication;
-pub mod comp_decomp;
+pub mod surface;
+pub mod variant;
+
+/// Lives in `swww-render`, along with the rest of the rendering pipeline
+pub use swww_render::comp_decomp;
@@ -0,0 +1,209 @@
+//! Where the daemon's surfaces go among those of other layer-shell clients, and how big their
+//! buffers can be, as set with `swww init --layer`, `--exclusive-zone` and `--max-resolution`.
+//! `swww` parses them too, so that typos are caught before the daemon is spawned
+use std::fmt;
+
+/// The layer-shell layer our surfaces are in
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Layer {
+    /// Below everything, which is where backgrounds usually go
+    Background,
+    /// Above the background layer, but still below normal windows
+    Bottom,
+}
+
+impl fmt::Display for Layer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Background => write!(f, "background"),
+            Self::Bottom => write!(f, "bottom"),
+        }
+    }
+}
+
+fn parse_layer(raw: &str) -> Result<Layer, String> {
+    match raw {
+        "background" => Ok(Layer::Background),
+        "bottom" => Ok(Layer::Bottom),
+        _ => Err(format!(
+            "invalid layer {raw:?}, expected 'background' or 'bottom'"
+        )),
+    }
+}
+
+fn parse_exclusive_zone(raw: &str) -> Result<i32, String> {
+    match raw.parse() {
+        Ok(zone) => Ok(zone),
+        Err(e) => Err(format!("invalid exclusive zone {raw:?}: {e}")),
+    }
+}
+
+/// Buffers are tiled past this size, and tiles can't be scaled up along with the rest of the
+/// surface, so `--max-resolution` can't go over it
+const MAX_CLAMP_SIDE: u32 = 8192;
+
+/// Parses resolutions like `3840x2160`, or `none` to not clamp an output at all
+fn parse_max_resolution(raw: &str) -> Result<Option<(u32, u32)>, String> {
+    if raw == "none" {
+        return Ok(None);
+    }
+    let side = |s: &str| match s.parse::<u32>() {
+        Ok(side) if (1..=MAX_CLAMP_SIDE).contains(&side) => Ok(side),
+        _ => Err(format!(
+            "invalid resolution {raw:?}, sides go from 1 to {MAX_CLAMP_SIDE}"
+        )),
+    };
+    match raw.split_once('x') {
+        Some((width, height)) => Ok(Some((side(width)?, side(height)?))),
+        None => Err(format!(
+            "invalid resolution {raw:?}, expected one like 3840x2160, or 'none'"
+        )),
+    }
+}
+
+/// The resolution to draw an output of `real` resolution at, if it's bigger than `max`. The
+/// aspect ratio is kept, so both sides are shrunk until they fit
+#[must_use]
+pub fn clamp_resolution(real: (u32, u32), max: (u32, u32)) -> Option<(u32, u32)> {
+    if real.0 <= max.0 && real.1 <= max.1 {
+        return None;
+    }
+    let factor = (max.0 as f64 / real.0 as f64).min(max.1 as f64 / real.1 as f64);
+    let shrink = |side: u32| ((side as f64 * factor).round() as u32).max(1);
+    Some((shrink(real.0), shrink(real.1)))
+}
+
+/// A value for every output, along with overrides for some of them
+#[derive(Clone, PartialEq, Debug)]
+pub struct PerOutput<T> {
+    default: T,
+    overrides: Vec<(String, T)>,
+}
+
+impl<T: Copy> PerOutput<T> {
+    /// Parses comma separated values, like `background,DP-1=bottom`. A value without an output
+    /// replaces `default`
+    fn parse(raw: &str, default: T, parse: fn(&str) -> Result<T, String>) -> Result<Self, String> {
+        let mut values = Self {
+            default,
+            overrides: Vec::new(),
+        };
+        for entry in raw.split(',').map(str::trim).filter(|e| !e.is_empty()) {
+            match entry.split_once('=') {
+                Some((output, value)) => {
+                    let value = parse(value.trim())?;
+                    values.overrides.push((output.trim().to_string(), value));
+                }
+                None => values.default = parse(entry)?,
+            }
+        }
+        Ok(values)
+    }
+
+    pub fn get(&self, output: &str) -> T {
+        self.overrides
+            .iter()
+            .rev()
+            .find(|(name, _)| name == output)
+            .map_or(self.default, |(_, value)| *value)
+    }
+}
+
+/// How each output's surface is placed
+#[derive(Clone, PartialEq, Debug)]
+pub struct Placement {
+    pub layer: PerOutput<Layer>,
+    /// -1 covers the whole output, even under panels, and 0 keeps clear of the areas other clients
+    /// reserved with their own exclusive zones
+    pub exclusive_zone: PerOutput<i32>,
+    /// The largest buffer to draw each output at. Outputs of higher resolutions are drawn at a
+    /// smaller one, which the compositor scales up
+    pub max_resolution: PerOutput<Option<(u32, u32)>>,
+}
+
+impl Default for Placement {
+    fn default() -> Self {
+        Self {
+            layer: PerOutput {
+                default: Layer::Background,
+                overrides: Vec::new(),
+            },
+            exclusive_zone: PerOutput {
+                default: -1,
+                overrides: Vec::new(),
+            },
+            max_resolution: PerOutput {
+                default: None,
+                overrides: Vec::new(),
+            },
+        }
+    }
+}
+
+impl Placement {
+    /// Parses `--layer`, `--exclusive-zone` and `--max-resolution`, any of which may be missing
+    pub fn parse(
+        layer: Option<&str>,
+        exclusive_zone: Option<&str>,
+        max_resolution: Option<&str>,
+    ) -> Result<Self, String> {
+        let mut placement = Self::default();
+        if let Some(layer) = layer {
+            placement.layer = PerOutput::parse(layer, Layer::Background, parse_layer)?;
+        }
+        if let Some(zone) = exclusive_zone {
+            placement.exclusive_zone = PerOutput::parse(zone, -1, parse_exclusive_zone)?;
+        }
+        if let Some(max) = max_resolution {
+            placement.max_resolution = PerOutput::parse(max, None, parse_max_resolution)?;
+        }
+        Ok(placement)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn should_override_outputs() {
+        let placement = Placement::parse(
+            Some("DP-1=bottom"),
+            Some("0, HDMI-A-1=-1"),
+            Some("3840x2160,eDP-1=none"),
+        )
+        .unwrap();
+        assert_eq!(placement.layer.get("DP-1"), Layer::Bottom);
+        assert_eq!(placement.layer.get("DP-2"), Layer::Background);
+        assert_eq!(placement.exclusive_zone.get("DP-1"), 0);
+        assert_eq!(placement.exclusive_zone.get("HDMI-A-1"), -1);
+        assert_eq!(placement.max_resolution.get("DP-1"), Some((3840, 2160)));
+        assert_eq!(placement.max_resolution.get("eDP-1"), None);
+    }
+
+    #[test]
+    fn should_reject_typos() {
+        assert!(Placement::parse(Some("botom"), None, None).is_err());
+        assert!(Placement::parse(Some("DP-1=top"), None, None).is_err());
+        assert!(Placement::parse(None, Some("DP-1=a"), None).is_err());
+        assert!(Placement::parse(None, None, Some("3840")).is_err());
+        assert!(Placement::parse(None, None, Some("16384x8192")).is_err());
+        assert_eq!(
+            Placement::parse(None, None, None).unwrap(),
+            Placement::default()
+        );
+    }
+
+    #[test]
+    fn should_keep_the_aspect_ratio_when_clamping() {
+        assert_eq!(
+            clamp_resolution((7680, 4320), (3840, 2160)),
+            Some((3840, 2160))
+        );
+        assert_eq!(
+            clamp_resolution((5120, 1440), (3840, 2160)),
+            Some((3840, 1080))
+        );
+        assert_eq!(clamp_resolution((1920, 1080), (3840, 2160)), None);
+    }
+}
@@ -0,0 +1,76 @@
+//! Pre-rendered variants of an image, for outputs of a given size. They sit next to the image, with
+//! the size before the extension: `wall.png`'s variant for 1920x1080 outputs is
+//! `wall.1920x1080.png`. We display them instead of resizing the image, so that carefully sharpened
+//! exports reach the screen pixel for pixel
+use std::path::{Path, PathBuf};
+
+/// The variant of the image at `path` for outputs of `dim`, if there is one. `path` may be a
+/// variant itself, in which case we look for its siblings
+pub fn find(path: &Path, dim: (u32, u32)) -> Option<PathBuf> {
+    let file_name = path.file_name()?.to_str()?;
+    let variant = path.with_file_name(variant_name(file_name, dim)?);
+    (variant != path && variant.is_file()).then_some(variant)
+}
+
+/// The name of `file_name`'s variant for outputs of `dim`. Animations can't have variants, since
+/// their other frames are read from the image itself
+fn variant_name(file_name: &str, dim: (u32, u32)) -> Option<String> {
+    let (stem, ext) = file_name.rsplit_once('.')?;
+    if stem.is_empty() || ext.eq_ignore_ascii_case("gif") {
+        return None;
+    }
+    let base = match stem.rsplit_once('.') {
+        Some((base, size)) if !base.is_empty() && is_size(size) => base,
+        _ => stem,
+    };
+    Some(format!("{base}.{}x{}.{ext}", dim.0, dim.1))
+}
+
+/// The image `path` is a variant of, if it is one. Whether that image exists isn't checked
+pub fn base(path: &Path) -> Option<PathBuf> {
+    let (stem, ext) = path.file_name()?.to_str()?.rsplit_once('.')?;
+    match stem.rsplit_once('.') {
+        Some((base, size)) if !base.is_empty() && is_size(size) => {
+            Some(path.with_file_name(format!("{base}.{ext}")))
+        }
+        _ => None,
+    }
+}
+
+fn is_size(s: &str) -> bool {
+    s.split_once('x').is_some_and(|(w, h)| {
+        [w, h]
+            .iter()
+            .all(|n| !n.is_empty() && n.bytes().all(|b| b.is_ascii_digit()))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn should_name_variants_after_the_image() {
+        let dim = (1920, 1080);
+        let expected = Some("wall.1920x1080.png".to_string());
+        assert_eq!(variant_name("wall.png", dim), expected);
+        assert_eq!(variant_name("wall.3840x2160.png", dim), expected);
+        assert_eq!(
+            variant_name("my.wall.jpg", dim),
+            Some("my.wall.1920x1080.jpg".to_string())
+        );
+        assert_eq!(variant_name("wall.gif", dim), None);
+        assert_eq!(variant_name("wall", dim), None);
+        assert_eq!(variant_name(".png", dim), None);
+    }
+
+    #[test]
+    fn variants_should_know_their_image() {
+        assert_eq!(
+            base(Path::new("/w/wall.1920x1080.png")),
+            Some(PathBuf::from("/w/wall.png"))
+        );
+        assert_eq!(base(Path::new("/w/my.wall.png")), None);
+        assert_eq!(base(Path::new("/w/wall.png")), None);
+    }
+}